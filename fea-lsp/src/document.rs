@@ -61,7 +61,10 @@ impl Document {
             result.push(Diagnostic {
                 range,
                 severity: Some(DiagnosticSeverity::ERROR),
-                message: err.text().to_owned(),
+                message: match err.help() {
+                    Some(help) => format!("{}\nhelp: {}", err.text(), help),
+                    None => err.text().to_owned(),
+                },
                 ..Default::default()
             })
         }
@@ -165,7 +168,10 @@ fn compute_offsets(text: &str) -> Vec<usize> {
 
 fn parse(text: &str) -> (Vec<(Kind, Range<usize>)>, Vec<fea_rs::Diagnostic>) {
     let (root, errors) = fea_rs::parse::parse_string(text);
-    let result = root.iter_tokens().map(|t| (t.kind, t.range())).collect();
+    let result = root
+        .iter_tokens_with_ranges()
+        .map(|(range, t)| (t.kind, range))
+        .collect();
     (result, errors)
 }
 
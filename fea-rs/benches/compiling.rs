@@ -0,0 +1,37 @@
+//! Benchmarks for validation and compilation
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fea_rs::compile::Opts;
+
+mod corpus;
+
+fn compile_corpus(corpus: &corpus::Corpus) -> Vec<u8> {
+    let fea = corpus.fea.clone();
+    fea_rs::Compiler::new("corpus.fea", &corpus.glyph_map)
+        .with_resolver(move |_: &std::ffi::OsStr| Ok(fea.clone()))
+        .compile_binary()
+        .unwrap()
+}
+
+fn validation(c: &mut Criterion) {
+    for (name, corpus) in [("arabic", corpus::arabic()), ("kern", corpus::kern(10_000))] {
+        let tree = corpus.parse();
+        c.bench_function(&format!("validate generated {name}"), |b| {
+            b.iter(|| fea_rs::validate(black_box(&tree), &corpus.glyph_map, &Opts::new()).unwrap())
+        });
+    }
+}
+
+fn compilation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compile");
+    group.sample_size(10);
+    for (name, corpus) in [("arabic", corpus::arabic()), ("kern", corpus::kern(10_000))] {
+        group.bench_function(format!("compile generated {name}"), |b| {
+            b.iter(|| compile_corpus(black_box(&corpus)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, validation, compilation);
+criterion_main!(benches);
@@ -0,0 +1,350 @@
+//! Generated sources for benchmarking.
+//!
+//! Each corpus is generated along with a glyph map containing every glyph it
+//! references, so that it can be validated and compiled as well as parsed.
+
+// each benchmark only uses some of these
+#![allow(dead_code)]
+
+use std::{fmt::Write, sync::Arc};
+
+use fea_rs::{GlyphMap, GlyphName, ParseTree};
+
+/// A generated source, and the glyphs it uses.
+pub struct Corpus {
+    pub fea: Arc<str>,
+    pub glyph_map: GlyphMap,
+}
+
+impl Corpus {
+    /// Parse this source, using the glyph map to resolve ambiguous names.
+    pub fn parse(&self) -> ParseTree {
+        let fea = self.fea.clone();
+        let (tree, errs) = fea_rs::parse_root(
+            "corpus.fea".into(),
+            Some(&self.glyph_map),
+            move |_: &std::ffi::OsStr| Ok(fea.clone()),
+        )
+        .unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+        tree
+    }
+}
+
+fn make_glyph_map(names: impl IntoIterator<Item = String>) -> GlyphMap {
+    std::iter::once(".notdef".to_string())
+        .chain(names)
+        .map(GlyphName::new)
+        .collect()
+}
+
+/// Generate a large kerning feature with only glyph pairs.
+pub fn kern_pairs(n_pairs: usize) -> String {
+    let mut out = String::from("languagesystem DFLT dflt;\n\nfeature kern {\n");
+    for i in 0..n_pairs {
+        writeln!(
+            out,
+            "    pos glyph{} glyph{} {};",
+            i % 701,
+            i % 443,
+            -(i as i32 % 97)
+        )
+        .unwrap();
+    }
+    out.push_str("} kern;\n");
+    out
+}
+
+/// A single glyph class definition with `n_glyphs` glyphs, one per line.
+///
+/// If `bad_every` is non-zero, a bad token is inserted after every
+/// `bad_every` glyphs, to measure error recovery inside the class.
+pub fn long_class(n_glyphs: usize, bad_every: usize) -> String {
+    let mut out = String::from("@long = [\n");
+    for i in 0..n_glyphs {
+        writeln!(out, "    glyph{i}").unwrap();
+        if bad_every != 0 && i % bad_every == bad_every - 1 {
+            out.push_str("    12\n");
+        }
+    }
+    out.push_str("];\n");
+    out
+}
+
+/// A large kerning feature, similar to those produced by font editors.
+///
+/// This has kerning classes covering every glyph, glyph pairs (which are
+/// exceptions to the class kerning), and some `enum` rules.
+pub fn kern(n_pairs: usize) -> Corpus {
+    const N_GLYPHS: usize = 701;
+    const N_LEFT: usize = 50;
+    const N_RIGHT: usize = 40;
+
+    let mut out = String::from("languagesystem DFLT dflt;\nlanguagesystem latn dflt;\n\n");
+    for class in 0..N_LEFT {
+        write!(out, "@kern1.{class} = [").unwrap();
+        for glyph in (0..N_GLYPHS).filter(|glyph| glyph % N_LEFT == class) {
+            write!(out, "glyph{glyph} ").unwrap();
+        }
+        out.push_str("];\n");
+    }
+    for class in 0..N_RIGHT {
+        write!(out, "@kern2.{class} = [").unwrap();
+        for glyph in (0..N_GLYPHS).filter(|glyph| glyph % N_RIGHT == class) {
+            write!(out, "glyph{glyph} ").unwrap();
+        }
+        out.push_str("];\n");
+    }
+
+    out.push_str("\nfeature kern {\n    lookupflag IgnoreMarks;\n");
+    // 701 and 443 are coprime, so each pair is unique
+    for i in 0..n_pairs {
+        writeln!(
+            out,
+            "    pos glyph{} glyph{} {};",
+            i % N_GLYPHS,
+            i % 443,
+            -(i as i32 % 97)
+        )
+        .unwrap();
+    }
+    for i in 0..N_LEFT {
+        writeln!(out, "    enum pos @kern1.{i} glyph{} -5;", 443 + i).unwrap();
+    }
+    for left in 0..N_LEFT {
+        for right in 0..N_RIGHT {
+            let value = ((left * 7 + right * 13) % 120) as i32 - 60;
+            if value != 0 {
+                writeln!(out, "    pos @kern1.{left} @kern2.{right} {value};").unwrap();
+            }
+        }
+    }
+    out.push_str("} kern;\n");
+
+    Corpus {
+        fea: out.into(),
+        glyph_map: make_glyph_map((0..N_GLYPHS).map(|i| format!("glyph{i}"))),
+    }
+}
+
+const DUAL_JOINING: &[&str] = &[
+    "beh", "teh", "theh", "jeem", "hah", "khah", "seen", "sheen", "sad", "dad", "tah", "zah",
+    "ain", "ghain", "feh", "qaf", "kaf", "gaf", "lam", "meem", "noon", "heh", "yeh", "peh",
+];
+const RIGHT_JOINING: &[&str] = &["alef", "dal", "thal", "reh", "zain", "waw", "tehMarbuta"];
+const MARKS: &[&str] = &["fatha", "damma", "kasra", "shadda", "sukun", "fathatan"];
+
+/// An Arabic source with a lot of contextual rules.
+///
+/// This has joining forms selected with single substitution, ligatures,
+/// chained contextual alternates for every pair of joining letters, cursive
+/// attachment, mark positioning and contextual kerning.
+pub fn arabic() -> Corpus {
+    let dual = |suffix: &str| {
+        DUAL_JOINING
+            .iter()
+            .map(|name| format!("{name}-ar{suffix}"))
+            .collect::<Vec<_>>()
+    };
+    let right = |suffix: &str| {
+        RIGHT_JOINING
+            .iter()
+            .map(|name| format!("{name}-ar{suffix}"))
+            .collect::<Vec<_>>()
+    };
+    let isol = dual("").into_iter().chain(right("")).collect::<Vec<_>>();
+    let init = dual(".init");
+    let medi = dual(".medi");
+    let fina = dual(".fina")
+        .into_iter()
+        .chain(right(".fina"))
+        .collect::<Vec<_>>();
+    let init_alt = dual(".init.alt");
+    let medi_alt = dual(".medi.alt");
+    let marks = MARKS
+        .iter()
+        .map(|name| format!("{name}-ar"))
+        .collect::<Vec<_>>();
+    let ligatures = ["lam_alef-ar", "lam_alef-ar.fina", "lam_meem-ar.init"].map(String::from);
+
+    let mut out = String::from("languagesystem DFLT dflt;\nlanguagesystem arab dflt;\n\n");
+    for (name, glyphs) in [
+        ("isol", &isol),
+        ("init", &init),
+        ("medi", &medi),
+        ("fina", &fina),
+        ("marks", &marks),
+    ] {
+        writeln!(out, "@{name} = [{}];", glyphs.join(" ")).unwrap();
+    }
+    writeln!(out, "@dual = [{}];", dual("").join(" ")).unwrap();
+    writeln!(out, "@dual.fina = [{}];", dual(".fina").join(" ")).unwrap();
+    out.push('\n');
+    writeln!(
+        out,
+        "table GDEF {{\n    GlyphClassDef [{} {} {} {}], [{}], @marks, ;\n}} GDEF;\n",
+        isol.join(" "),
+        init.join(" "),
+        medi.join(" "),
+        fina.join(" "),
+        ligatures.join(" ")
+    )
+    .unwrap();
+
+    // joining forms
+    for (feature, target) in [("init", ".init"), ("medi", ".medi"), ("fina", ".fina")] {
+        writeln!(out, "feature {feature} {{\n    lookupflag IgnoreMarks;").unwrap();
+        for name in DUAL_JOINING {
+            writeln!(out, "    sub {name}-ar by {name}-ar{target};").unwrap();
+        }
+        if target == ".fina" {
+            for name in RIGHT_JOINING {
+                writeln!(out, "    sub {name}-ar by {name}-ar{target};").unwrap();
+            }
+        }
+        writeln!(out, "}} {feature};\n").unwrap();
+    }
+
+    out.push_str(
+        "feature rlig {
+    lookupflag IgnoreMarks;
+    sub lam-ar.init alef-ar.fina by lam_alef-ar;
+    sub lam-ar.medi alef-ar.fina by lam_alef-ar.fina;
+    sub lam-ar.init meem-ar.medi by lam_meem-ar.init;
+} rlig;\n\n",
+    );
+
+    // contextual alternates, depending on the following letter
+    out.push_str("lookup INIT_ALT {\n");
+    for (from, to) in init.iter().zip(&init_alt) {
+        writeln!(out, "    sub {from} by {to};").unwrap();
+    }
+    out.push_str("} INIT_ALT;\n\nlookup MEDI_ALT {\n");
+    for (from, to) in medi.iter().zip(&medi_alt) {
+        writeln!(out, "    sub {from} by {to};").unwrap();
+    }
+    out.push_str("} MEDI_ALT;\n\nfeature calt {\n    lookupflag IgnoreMarks;\n");
+    for (i, first) in init.iter().enumerate() {
+        writeln!(out, "    ignore sub {first}' @medi @medi @dual.fina;").unwrap();
+        for (j, second) in medi.iter().enumerate() {
+            if (i + j) % 3 == 0 {
+                writeln!(out, "    sub {first}' lookup INIT_ALT {second} @fina;").unwrap();
+            } else if (i + j) % 3 == 1 {
+                writeln!(out, "    sub @init {}' lookup MEDI_ALT {second};", medi[i]).unwrap();
+            }
+        }
+    }
+    out.push_str("} calt;\n\n");
+
+    // cursive attachment
+    out.push_str("feature curs {\n    lookupflag RightToLeft IgnoreMarks;\n");
+    for (i, glyph) in init.iter().chain(&init_alt).enumerate() {
+        writeln!(
+            out,
+            "    pos cursive {glyph} <anchor NULL> <anchor 0 {}>;",
+            i % 20
+        )
+        .unwrap();
+    }
+    for (i, glyph) in medi.iter().chain(&medi_alt).enumerate() {
+        let width = 300 + (i % 7) * 50;
+        writeln!(
+            out,
+            "    pos cursive {glyph} <anchor {width} {}> <anchor 0 {}>;",
+            i % 20,
+            (i + 3) % 20
+        )
+        .unwrap();
+    }
+    for (i, glyph) in fina.iter().enumerate() {
+        let width = 400 + (i % 5) * 60;
+        writeln!(
+            out,
+            "    pos cursive {glyph} <anchor {width} {}> <anchor NULL>;",
+            i % 20
+        )
+        .unwrap();
+    }
+    out.push_str("} curs;\n\n");
+
+    // mark positioning
+    for (i, mark) in marks.iter().enumerate() {
+        let (class, y) = if i % 3 == 2 {
+            ("BOTTOM", -100)
+        } else {
+            ("TOP", 600)
+        };
+        writeln!(
+            out,
+            "markClass {mark} <anchor {} {y}> @MC_{class};",
+            100 + i * 10
+        )
+        .unwrap();
+    }
+    out.push_str("\nfeature mark {\n");
+    let bases = isol
+        .iter()
+        .chain(&init)
+        .chain(&medi)
+        .chain(&fina)
+        .chain(&init_alt)
+        .chain(&medi_alt);
+    for (i, base) in bases.enumerate() {
+        let x = 150 + (i % 9) * 25;
+        writeln!(
+            out,
+            "    pos base {base} <anchor {x} {}> mark @MC_TOP <anchor {x} -{}> mark @MC_BOTTOM;",
+            500 + i % 200,
+            80 + i % 40
+        )
+        .unwrap();
+    }
+    for (i, lig) in ligatures.iter().enumerate() {
+        writeln!(
+            out,
+            "    pos ligature {lig} <anchor {} 600> mark @MC_TOP <anchor 100 -80> mark @MC_BOTTOM
+        ligComponent <anchor 450 {}> mark @MC_TOP <anchor 400 -80> mark @MC_BOTTOM;",
+            500 + i * 10,
+            650 + i * 10
+        )
+        .unwrap();
+    }
+    out.push_str("} mark;\n\nfeature mkmk {\n");
+    for (i, mark) in marks.iter().enumerate() {
+        writeln!(
+            out,
+            "    pos mark {mark} <anchor 100 {}> mark @MC_TOP;",
+            800 + i * 20
+        )
+        .unwrap();
+    }
+    out.push_str("} mkmk;\n\n");
+
+    // contextual kerning
+    out.push_str("lookup KERN_TIGHT {\n    pos @fina -30;\n} KERN_TIGHT;\n\n");
+    out.push_str("feature kern {\n    lookupflag RightToLeft IgnoreMarks;\n");
+    for (i, first) in fina.iter().enumerate() {
+        for (j, second) in isol.iter().enumerate() {
+            if (i * 5 + j) % 4 == 0 {
+                let value = ((i + j) % 60) as i32 - 30;
+                writeln!(out, "    pos {second} {first} {value};").unwrap();
+            }
+        }
+        writeln!(out, "    pos {first}' lookup KERN_TIGHT @isol @medi;").unwrap();
+    }
+    out.push_str("} kern;\n");
+
+    let glyphs = isol
+        .into_iter()
+        .chain(init)
+        .chain(medi)
+        .chain(fina)
+        .chain(init_alt)
+        .chain(medi_alt)
+        .chain(marks)
+        .chain(ligatures);
+    Corpus {
+        fea: out.into(),
+        glyph_map: make_glyph_map(glyphs),
+    }
+}
@@ -0,0 +1,98 @@
+//! Benchmarks for the lexer
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+mod corpus;
+
+fn lexing(c: &mut Criterion) {
+    let arabic = corpus::arabic();
+    let kern = corpus::kern(10_000);
+    c.bench_function("lex generated arabic", |b| {
+        b.iter(|| fea_rs::parse::count_tokens(black_box(&arabic.fea)))
+    });
+    c.bench_function("lex generated kern", |b| {
+        b.iter(|| fea_rs::parse::count_tokens(black_box(&kern.fea)))
+    });
+}
+
+// candidate implementations of the lexer's `is_special` check; the lexer uses
+// `is_special_ranges`.
+
+fn is_special_match(byte: u8) -> bool {
+    matches!(
+        byte,
+        b'\''
+            | b'('
+            | b')'
+            | b'*'
+            | b'+'
+            | b','
+            | b'-'
+            | b';'
+            | b'<'
+            | b'='
+            | b'>'
+            | b'?'
+            | b'@'
+            | b'['
+            | b'\\'
+            | b']'
+            | b'{'
+            | b'}'
+    )
+}
+
+fn is_special_ranges(byte: u8) -> bool {
+    (39..=45).contains(&byte)
+        || (59..=64).contains(&byte)
+        || (91..=93).contains(&byte)
+        || byte == 123
+        || byte == 125
+}
+
+const SPECIAL: [u8; 18] = [
+    39, 40, 41, 42, 43, 44, 45, 59, 60, 61, 62, 63, 64, 91, 92, 93, 123, 125,
+];
+
+fn is_special_bsearch(byte: u8) -> bool {
+    SPECIAL.binary_search(&byte).is_ok()
+}
+
+fn is_special_linear_scan(byte: u8) -> bool {
+    SPECIAL.contains(&byte)
+}
+
+type IsSpecial = fn(u8) -> bool;
+
+fn is_special(c: &mut Criterion) {
+    for byte in 0..=u8::MAX {
+        let expected = is_special_ranges(byte);
+        assert_eq!(is_special_match(byte), expected);
+        assert_eq!(is_special_bsearch(byte), expected);
+        assert_eq!(is_special_linear_scan(byte), expected);
+    }
+
+    let arabic = corpus::arabic();
+    let bytes = arabic.fea.as_bytes();
+    let mut group = c.benchmark_group("is_special");
+    let candidates: [(&str, IsSpecial); 4] = [
+        ("match", is_special_match),
+        ("ranges", is_special_ranges),
+        ("bsearch", is_special_bsearch),
+        ("linear scan", is_special_linear_scan),
+    ];
+    for (name, is_special) in candidates {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                black_box(bytes)
+                    .iter()
+                    .filter(|byte| is_special(**byte))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lexing, is_special);
+criterion_main!(benches);
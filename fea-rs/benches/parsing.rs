@@ -4,6 +4,8 @@ use std::sync::Arc;
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
+mod corpus;
+
 const DEVA: &str = include_str!("../test-data/real-files/plex_devanagari.fea");
 const LATN: &str = include_str!("../test-data/real-files/roboto-regular.fea");
 const ARAB: &str = include_str!("../test-data/real-files/tajawal-regular.fea");
@@ -25,7 +27,45 @@ fn parsing(c: &mut Criterion) {
     c.bench_function("parse tajawal-regular", |b| {
         b.iter(|| parse_source(black_box(arab.clone())))
     });
+
+    let arabic = corpus::arabic();
+    c.bench_function("parse generated arabic", |b| {
+        b.iter(|| black_box(&arabic).parse())
+    });
+}
+
+fn parsing_large(c: &mut Criterion) {
+    let kern: Arc<str> = corpus::kern_pairs(100_000).into();
+    let mut group = c.benchmark_group("large");
+    group.sample_size(10);
+    group.bench_function("parse generated kern (in memory)", |b| {
+        b.iter(|| parse_source(black_box(kern.clone())))
+    });
+
+    let class: Arc<str> = corpus::long_class(100_000, 0).into();
+    group.bench_function("parse long glyph class", |b| {
+        b.iter(|| parse_source(black_box(class.clone())))
+    });
+    let bad_class: Arc<str> = corpus::long_class(100_000, 1_000).into();
+    group.bench_function("parse long glyph class with errors", |b| {
+        b.iter(|| parse_source(black_box(bad_class.clone())))
+    });
+
+    #[cfg(feature = "mmap")]
+    {
+        use fea_rs::parse::FileSystemResolver;
+        let dir = std::env::temp_dir();
+        let path = dir.join("fea-rs-bench-kern.fea");
+        std::fs::write(&path, kern.as_bytes()).unwrap();
+        group.bench_function("parse generated kern (mmap)", |b| {
+            b.iter(|| {
+                let resolver = FileSystemResolver::new(dir.clone()).use_mmap(true);
+                fea_rs::parse::parse_root(path.clone().into_os_string(), None, resolver).unwrap()
+            })
+        });
+    }
+    group.finish();
 }
 
-criterion_group!(benches, parsing);
+criterion_group!(benches, parsing, parsing_large);
 criterion_main!(benches);
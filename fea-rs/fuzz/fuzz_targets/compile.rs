@@ -0,0 +1,38 @@
+//! Compile arbitrary input to a font binary.
+//!
+//! The glyph map contains every word in the input, so that the input
+//! can get past glyph name validation.
+
+#![no_main]
+
+use std::{ffi::OsStr, sync::Arc};
+
+use fea_rs::{Compiler, GlyphMap, GlyphName};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_GLYPHS: usize = 1000;
+
+fn glyph_map_for(text: &str) -> GlyphMap {
+    let mut names = vec![GlyphName::from(".notdef")];
+    let words = text.split(|c: char| !(c.is_ascii_alphanumeric() || "._-".contains(c)));
+    for word in words.filter(|word| !word.is_empty()) {
+        if names.len() == MAX_GLYPHS {
+            break;
+        }
+        if !names.iter().any(|name| name.as_str() == word) {
+            names.push(word.into());
+        }
+    }
+    names.into_iter().collect()
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let glyph_map = glyph_map_for(text);
+    let text: Arc<str> = text.into();
+    let _ = Compiler::new("fuzz.fea", &glyph_map)
+        .with_resolver(move |_: &OsStr| Ok(text.clone()))
+        .compile_binary();
+});
@@ -0,0 +1,19 @@
+//! Tokenize arbitrary input.
+//!
+//! Every byte of the input must end up in exactly one token, in order.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let (node, _errs) = fea_rs::parse::parse_string(text);
+    let tokens = node
+        .iter_tokens()
+        .map(|token| token.text.as_str())
+        .collect::<String>();
+    assert_eq!(tokens, text);
+});
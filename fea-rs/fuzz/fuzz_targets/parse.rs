@@ -0,0 +1,18 @@
+//! Parse arbitrary input, resolving includes.
+//!
+//! Every include statement resolves to the input itself, so this also
+//! exercises include cycle detection.
+
+#![no_main]
+
+use std::{ffi::OsStr, sync::Arc};
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let text: Arc<str> = text.into();
+    let _ = fea_rs::parse::parse_root("fuzz.fea".into(), None, move |_: &OsStr| Ok(text.clone()));
+});
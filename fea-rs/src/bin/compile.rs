@@ -7,10 +7,12 @@ use fea_rs::{
     compile::{
         self,
         error::{FontGlyphOrderError, GlyphOrderError, UfoGlyphOrderError},
-        Compiler, Opts,
+        Compiler, Kerning, MarkAnchors, MissingGlyphs, Opts, PairPosConflicts, VariationAxis,
     },
-    GlyphMap,
+    parse::{ParseTree, SourceLoadError},
+    Diagnostic, GlyphMap, TreeFormat,
 };
+use write_fonts::types::Tag;
 
 /// Attempt to compile features into a font file.
 ///
@@ -20,31 +22,188 @@ use fea_rs::{
 fn main() -> Result<(), Error> {
     env_logger::init();
     let args = Args::parse();
-    let (fea, glyph_names) = args.get_inputs()?;
+    match &args.command {
+        Some(Command::Parse(parse_args)) => return parse(parse_args),
+        Some(Command::Explain(explain_args)) => return explain(explain_args),
+        None => (),
+    }
+    let (fea, glyph_names, kerning, anchors) = args.get_inputs()?;
     if !fea.exists() {
         return Err(Error::EmptyFeatureFile);
     }
-    let compiled = Compiler::new(fea, &glyph_names)
-        .with_opts(Opts::new().make_post_table(args.post))
-        .compile()?;
+    let mut opts = Opts::new()
+        .make_post_table(args.post)
+        .warn_unused(args.warn_unused)
+        .pedantic(args.pedantic)
+        .missing_glyphs(args.missing_glyphs.into())
+        .pair_pos_conflicts(args.pair_pos_conflicts.into())
+        .implicit_default_lang_system(args.implicit_default_lang_system)
+        .implicit_script_lang_systems(args.implicit_script_lang_systems)
+        .forward_class_references(args.forward_class_references)
+        .compact_value_formats(args.compact_value_formats)
+        .rtl_pair_pos_placement(args.rtl_pair_pos_placement)
+        .variation_axes(args.axis.iter().copied());
+    for (from, to) in &args.rename_feature {
+        opts = opts.rename_feature(*from, *to);
+    }
+    for (from, to) in &args.copy_feature {
+        opts = opts.copy_feature(*from, *to);
+    }
+    if !args.script.is_empty() || !args.language_system.is_empty() {
+        opts = opts
+            .script_subset(args.script.iter().copied())
+            .language_system_subset(args.language_system.iter().copied());
+    }
+    let mut compiler = Compiler::new(fea, &glyph_names)
+        .verbose(args.verbose)
+        .max_include_depth(args.max_include_depth)
+        .restrict_includes_to_project_root(args.restrict_includes)
+        .with_opts(opts);
+    if let Some(kerning) = kerning {
+        compiler = compiler.with_kerning(kerning);
+    }
+    if let Some(anchors) = anchors {
+        compiler = compiler.with_mark_anchors(anchors);
+    }
+    let compiled = compiler.compile();
+    #[cfg(feature = "snippets")]
+    let compiled = compiled.map_err(render_compile_error);
+    let compiled = compiled?;
+
+    // the same error would stop the font from being written
+    let report_err =
+        |err: compile::error::CompileError| compile::error::CompilerError::WriteFail(err.into());
+    if args.report_rules {
+        let report = compile::report::Report::new(&compiled).map_err(report_err)?;
+        print!("{report:#}");
+    } else if args.report {
+        let report = compile::report::Report::new(&compiled).map_err(report_err)?;
+        print!("{report}");
+    }
+    if args.feature_coverage {
+        let coverage = compile::report::FeatureCoverage::new(&compiled).map_err(report_err)?;
+        print!("{coverage}");
+    }
+    if args.mark_coverage {
+        // without a cmap, every base glyph may be reachable
+        let coverage = compile::report::MarkCoverage::new(&compiled, &glyph_names, |_| true);
+        print!("{coverage}");
+    }
+
+    #[cfg(feature = "shape-check")]
+    if let Some(tests) = args.shape_tests.as_deref() {
+        check_shaping(&compiled, &glyph_names, tests)?;
+    }
 
     let path = args.out_path();
     let opts = Opts::new().make_post_table(args.post);
     let raw_font = compiled
         .assemble(&glyph_names, opts)
-        .expect("ttf compile failed")
+        .map_err(compile::error::CompilerError::from)?
         .build();
 
     log::info!("writing {} bytes to {}", raw_font.len(), path.display());
     std::fs::write(path, raw_font).map_err(Into::into)
 }
 
+/// Parse a FEA file, printing the tree and any diagnostics.
+///
+/// The tree is printed even if there are errors.
+fn parse(args: &ParseArgs) -> Result<(), Error> {
+    let (tree, diagnostics) = fea_rs::parse::parse_root_file(&args.path, None, None)?;
+    print!("{}", tree.root().format(args.format.into()));
+    for diagnostic in &diagnostics {
+        eprintln!("{}", format_diagnostic(&tree, diagnostic));
+    }
+    match diagnostics.iter().filter(|diag| diag.is_error()).count() {
+        0 => Ok(()),
+        n => Err(Error::ParseFail(n)),
+    }
+}
+
+/// Print how the rule at an offset in a FEA file is compiled.
+fn explain(args: &ExplainArgs) -> Result<(), Error> {
+    let (path, offset) = &args.location;
+    let glyph_names = load_glyph_order(args.glyph_order.as_deref(), args.font.as_deref())?;
+    let (tree, diagnostics) = fea_rs::parse::parse_root_file(path, Some(&glyph_names), None)?;
+    let errors = diagnostics.iter().filter(|diag| diag.is_error()).count();
+    if errors > 0 {
+        for diagnostic in &diagnostics {
+            eprintln!("{}", format_diagnostic(&tree, diagnostic));
+        }
+        return Err(Error::ParseFail(errors));
+    }
+    let compiled = fea_rs::compile(&tree, &glyph_names, Opts::new())
+        .map_err(compile::error::CompilerError::CompilationFail)?;
+    let explanation = compile::report::Explanation::new(&tree, &compiled, *offset)
+        .map_err(|err| compile::error::CompilerError::WriteFail(err.into()))?
+        .ok_or(Error::NoRuleAtOffset(*offset))?;
+    print!("{explanation}");
+    Ok(())
+}
+
+#[cfg(feature = "shape-check")]
+fn check_shaping(
+    compiled: &compile::Compilation,
+    glyph_names: &GlyphMap,
+    path: &Path,
+) -> Result<(), Error> {
+    use compile::shape_check;
+
+    let tests = shape_check::parse_shape_tests(&std::fs::read_to_string(path)?)?;
+    let mismatches = shape_check::check_shaping(compiled, glyph_names, &tests)?;
+    for mismatch in &mismatches {
+        eprintln!("{mismatch}");
+    }
+    if mismatches.is_empty() {
+        log::info!("{} shaping tests passed", tests.len());
+        Ok(())
+    } else {
+        Err(Error::ShapeCheckFailed(mismatches.len()))
+    }
+}
+
+/// Format a diagnostic for printing.
+///
+/// With the `snippets` feature this is an annotated snippet of the source,
+/// which is only coloured when printing to a terminal.
+fn format_diagnostic(tree: &ParseTree, diagnostic: &Diagnostic) -> String {
+    #[cfg(feature = "snippets")]
+    {
+        use std::io::IsTerminal;
+        tree.render_diagnostic(diagnostic, std::io::stderr().is_terminal())
+    }
+    #[cfg(not(feature = "snippets"))]
+    tree.format_diagnostic(diagnostic)
+}
+
+/// Print the diagnostics of a failed compilation as annotated snippets.
+#[cfg(feature = "snippets")]
+fn render_compile_error(err: compile::error::CompilerError) -> Error {
+    use compile::error::CompilerError;
+    use std::io::IsTerminal;
+    match err {
+        CompilerError::ParseFail(diagnostics)
+        | CompilerError::ValidationFail(diagnostics)
+        | CompilerError::CompilationFail(diagnostics)
+        | CompilerError::TransformFail(diagnostics) => {
+            eprintln!("{}", diagnostics.render(std::io::stderr().is_terminal()));
+            Error::DiagnosticsFail(diagnostics.messages().len())
+        }
+        other => other.into(),
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("io error: '{0}'")]
     File(#[from] std::io::Error),
+    #[cfg(feature = "ufo")]
     #[error("Couldn't read UFO: '{0}'")]
     Ufo(Box<norad::error::FontLoadError>),
+    #[cfg(not(feature = "ufo"))]
+    #[error("UFO inputs require the 'ufo' feature")]
+    UfoUnsupported,
     #[error("invalid glyph map: '{0}'")]
     InvalidGlyphMap(#[from] GlyphOrderError),
     #[error("Couldn't get glyph order from UFO: '{0}'")]
@@ -56,13 +215,35 @@ enum Error {
     #[error("No glyph order provided")]
     MissingGlyphOrder,
     #[error("{0}")]
+    SourceLoad(#[from] SourceLoadError),
+    #[error("parsing failed with {0} errors")]
+    ParseFail(usize),
+    #[error("no substitution or positioning rule at offset {0}")]
+    NoRuleAtOffset(usize),
+    #[error("{0}")]
     CompileFail(#[from] compile::error::CompilerError),
+    #[cfg(feature = "snippets")]
+    #[error("compilation failed with {0} errors")]
+    DiagnosticsFail(usize),
+    #[cfg(feature = "shape-check")]
+    #[error("Invalid shaping tests: {0}")]
+    ShapeTestParse(#[from] compile::error::ShapeTestParseError),
+    #[cfg(feature = "shape-check")]
+    #[error("Couldn't run shaping tests: {0}")]
+    ShapeCheck(#[from] compile::error::ShapeCheckError),
+    #[cfg(feature = "shape-check")]
+    #[error("{0} shaping tests failed")]
+    ShapeCheckFailed(usize),
 }
 
 /// Compile FEA files
 #[derive(Parser, Debug)]
 #[command(author, version, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Display more information about failures
     ///
     /// This includes errors encountered, as well as the generated diffs when
@@ -73,7 +254,8 @@ struct Args {
     ///
     /// If a FEA file, you will also need to provide a glyph order.
     /// If a UFO file, the public.glyphOrder key must be present.
-    input: PathBuf,
+    #[arg(required = true)]
+    input: Option<PathBuf>,
     /// Path to a file containing the glyph order.
     ///
     /// This should be a utf-8 encoded file with one name per line,
@@ -92,30 +274,324 @@ struct Args {
     /// Optionally write a post table to the generated font
     #[arg(short, long)]
     post: bool,
+
+    /// Compile the groups and kerning of a UFO input into the 'kern' feature
+    ///
+    /// The kerning is added after any 'kern' lookups in the feature file, and
+    /// skips the pairs that they already kern. Pairs with right-to-left glyphs
+    /// are compiled into a separate lookup.
+    #[arg(long)]
+    ufo_kerning: bool,
+
+    /// Compile the glyph anchors of a UFO input into the 'mark' and 'mkmk'
+    /// features
+    ///
+    /// The generated lookups are added after any lookups in the feature file,
+    /// and skip the marks and bases that the feature file already attaches.
+    #[arg(long)]
+    ufo_anchors: bool,
+
+    /// Warn about classes, anchors and lookups that are defined but never used
+    ///
+    /// Warnings are only printed with '--verbose'.
+    #[arg(long)]
+    warn_unused: bool,
+
+    /// Warn when the input triggers behaviour that matches feaLib but differs
+    /// from the FEA spec
+    ///
+    /// Warnings are only printed with '--verbose'.
+    #[arg(long)]
+    pedantic: bool,
+
+    /// The maximum depth of nested include statements
+    #[arg(long, value_name = "DEPTH", default_value_t = 5)]
+    max_include_depth: usize,
+
+    /// Refuse to include files outside the directory of the input
+    ///
+    /// This is useful when compiling untrusted sources.
+    #[arg(long)]
+    restrict_includes: bool,
+
+    /// How to handle glyphs that are not in the font
+    #[arg(long, value_enum, default_value_t = MissingGlyphsArg::Error)]
+    missing_glyphs: MissingGlyphsArg,
+
+    /// How to handle a glyph pair that a class pair rule in the same
+    /// subtable gives a different value
+    #[arg(long, value_enum, default_value_t = PairPosConflictsArg::SpecificWins)]
+    pair_pos_conflicts: PairPosConflictsArg,
+
+    /// Register 'languagesystem DFLT dflt' if other language systems are
+    /// declared without it
+    #[arg(long)]
+    implicit_default_lang_system: bool,
+
+    /// Register 'languagesystem SCRIPT dflt' for each script used in a
+    /// 'script' statement that has no language system
+    #[arg(long)]
+    implicit_script_lang_systems: bool,
+
+    /// Allow glyph classes to be referenced before they are defined
+    #[arg(long)]
+    forward_class_references: bool,
+
+    /// Drop components of pair positioning value formats that are zero in
+    /// every record of a subtable, instead of keeping explicit zero values
+    ///
+    /// This is off by default so that the output matches feaLib's.
+    #[arg(long)]
+    compact_value_formats: bool,
+
+    /// Apply single-number kerning values in 'RightToLeft' lookups to the x
+    /// placement as well as the x advance
+    #[arg(long)]
+    rtl_pair_pos_placement: bool,
+
+    /// Compile the lookups of one feature under another tag, as 'FROM:TO'
+    ///
+    /// For instance, 'ss01:calt' applies the rules of 'ss01' by default. This
+    /// can be repeated.
+    #[arg(long, value_name = "FROM:TO", value_parser = parse_tag_pair)]
+    rename_feature: Vec<(Tag, Tag)>,
+
+    /// Compile the lookups of one feature under another tag as well, as
+    /// 'FROM:TO'
+    ///
+    /// This can be repeated.
+    #[arg(long, value_name = "FROM:TO", value_parser = parse_tag_pair)]
+    copy_feature: Vec<(Tag, Tag)>,
+
+    /// Only compile the features registered for this script
+    ///
+    /// This can be repeated, and combined with '--language-system'.
+    #[arg(long, value_name = "TAG", value_parser = parse_tag)]
+    script: Vec<Tag>,
+
+    /// Only compile the features registered for this language system, as
+    /// 'SCRIPT:LANGUAGE'
+    ///
+    /// This can be repeated, and combined with '--script'.
+    #[arg(long, value_name = "SCRIPT:LANGUAGE", value_parser = parse_tag_pair)]
+    language_system: Vec<(Tag, Tag)>,
+
+    /// A variation axis of the font, as 'TAG:MIN:DEFAULT:MAX'
+    ///
+    /// This is required to compile variable anchors, and can be repeated.
+    #[arg(long, value_name = "TAG:MIN:DEFAULT:MAX", value_parser = parse_axis)]
+    axis: Vec<VariationAxis>,
+
+    /// Print the size, coverage and classes of each lookup and subtable
+    #[arg(long)]
+    report: bool,
+
+    /// With --report, also print the rules of each lookup, with glyph ids in
+    /// place of glyph names
+    #[arg(long, requires = "report")]
+    report_rules: bool,
+
+    /// Print which features apply in each language system, including those
+    /// that only apply by falling back to another language system
+    #[arg(long)]
+    feature_coverage: bool,
+
+    /// Print the base glyphs that are missing anchors for some mark classes,
+    /// for each script
+    #[arg(long)]
+    mark_coverage: bool,
+
+    /// Path to a file of shaping tests to run against the compiled tables.
+    ///
+    /// Each line is of the form 'a b -> c d', optionally followed by
+    /// '; features=-liga,+smcp', '; script=latn' or '; language=TRK'.
+    #[cfg(feature = "shape-check")]
+    #[arg(long)]
+    shape_tests: Option<PathBuf>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Parse a FEA file and print the lossless tree
+    Parse(ParseArgs),
+    /// Print the lookups, lookupflags and features of the rule at an offset
+    /// in a FEA file
+    Explain(ExplainArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ParseArgs {
+    /// The FEA file to parse
+    path: PathBuf,
+
+    /// How to print the tree
+    #[arg(long, value_enum, default_value_t = TreeFormatArg::Tree)]
+    format: TreeFormatArg,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExplainArgs {
+    /// The rule to explain, as 'PATH:OFFSET'
+    ///
+    /// The offset is in bytes, from the start of the file.
+    #[arg(value_name = "PATH:OFFSET", value_parser = parse_location)]
+    location: (PathBuf, usize),
+
+    /// Path to a file containing the glyph order.
+    #[arg(short, long, group = "glyph_source")]
+    glyph_order: Option<PathBuf>,
+
+    /// Path to a font file to be used to calculate glyph order.
+    #[arg(short, long, group = "glyph_source")]
+    font: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum TreeFormatArg {
+    /// An indented tree of nodes and tokens
+    Tree,
+    /// Each token on its own line
+    Tokens,
+    /// A JSON object for each node and token
+    Json,
+    /// An s-expression for each node and token
+    SExpr,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum MissingGlyphsArg {
+    /// Fail to compile
+    Error,
+    /// Warn, and ignore any rule that uses the glyph
+    Drop,
+    /// Warn, and use '.notdef' in place of the glyph
+    Placeholder,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum PairPosConflictsArg {
+    /// Use the glyph pair, as feaLib does
+    SpecificWins,
+    /// Use the rule that comes first, and warn about ignored glyph pairs
+    FirstWins,
+    /// Fail to compile
+    Error,
+}
+
+fn parse_tag_pair(arg: &str) -> Result<(Tag, Tag), String> {
+    let (from, to) = arg
+        .split_once(':')
+        .ok_or_else(|| format!("expected 'FROM:TO', found '{arg}'"))?;
+    let parse = |tag: &str| {
+        tag.parse::<Tag>()
+            .map_err(|_| format!("invalid tag '{tag}'"))
+    };
+    Ok((parse(from)?, parse(to)?))
+}
+
+fn parse_tag(arg: &str) -> Result<Tag, String> {
+    arg.parse().map_err(|_| format!("invalid tag '{arg}'"))
+}
+
+fn parse_location(arg: &str) -> Result<(PathBuf, usize), String> {
+    let (path, offset) = arg
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected 'PATH:OFFSET', found '{arg}'"))?;
+    let offset = offset
+        .parse()
+        .map_err(|_| format!("invalid offset '{offset}'"))?;
+    Ok((path.into(), offset))
+}
+
+fn parse_axis(arg: &str) -> Result<VariationAxis, String> {
+    let expected = || format!("expected 'TAG:MIN:DEFAULT:MAX', found '{arg}'");
+    let mut parts = arg.split(':');
+    let tag = parse_tag(parts.next().ok_or_else(expected)?)?;
+    let mut values = parts.map(|value| value.parse::<f32>().map_err(|_| expected()));
+    let mut next = || values.next().unwrap_or_else(|| Err(expected()));
+    let (min, default, max) = (next()?, next()?, next()?);
+    if values.next().is_some() {
+        return Err(expected());
+    }
+    VariationAxis::new(tag, min, default, max).map_err(|err| err.to_string())
+}
+
+impl From<MissingGlyphsArg> for MissingGlyphs {
+    fn from(src: MissingGlyphsArg) -> MissingGlyphs {
+        match src {
+            MissingGlyphsArg::Error => MissingGlyphs::Error,
+            MissingGlyphsArg::Drop => MissingGlyphs::Drop,
+            MissingGlyphsArg::Placeholder => MissingGlyphs::Placeholder,
+        }
+    }
+}
+
+impl From<TreeFormatArg> for TreeFormat {
+    fn from(src: TreeFormatArg) -> TreeFormat {
+        match src {
+            TreeFormatArg::Tree => TreeFormat::Tree,
+            TreeFormatArg::Tokens => TreeFormat::Tokens,
+            TreeFormatArg::Json => TreeFormat::Json,
+            TreeFormatArg::SExpr => TreeFormat::SExpr,
+        }
+    }
+}
+
+impl From<PairPosConflictsArg> for PairPosConflicts {
+    fn from(src: PairPosConflictsArg) -> PairPosConflicts {
+        match src {
+            PairPosConflictsArg::SpecificWins => PairPosConflicts::SpecificWins,
+            PairPosConflictsArg::FirstWins => PairPosConflicts::FirstWins,
+            PairPosConflictsArg::Error => PairPosConflicts::Error,
+        }
+    }
 }
 
 impl Args {
-    pub fn get_inputs(&self) -> Result<(PathBuf, GlyphMap), Error> {
-        if self.input.extension() == Some("ufo".as_ref()) {
-            let request = norad::DataRequest::none().lib(true);
-            let font = norad::Font::load_requested_data(&self.input, request)?;
-            let glyph_order = compile::get_ufo_glyph_order(&font)?;
-            let fea_path = self.input.join("features.fea");
-            Ok((fea_path, glyph_order))
+    pub fn get_inputs(
+        &self,
+    ) -> Result<(PathBuf, GlyphMap, Option<Kerning>, Option<MarkAnchors>), Error> {
+        let input = self.input();
+        if input.extension() == Some("ufo".as_ref()) {
+            self.get_ufo_inputs(input)
         } else {
-            let order = if let Some(path) = self.glyph_order() {
-                let contents = std::fs::read_to_string(path)?;
-                compile::parse_glyph_order(&contents)?
-            } else if let Some(path) = self.font.as_deref() {
-                let bytes = std::fs::read(path)?;
-                compile::get_post_glyph_order(&bytes)?
-            } else {
-                return Err(Error::MissingGlyphOrder);
-            };
-            Ok((self.input.clone(), order))
+            let order = load_glyph_order(self.glyph_order(), self.font.as_deref())?;
+            Ok((input.to_owned(), order, None, None))
         }
     }
 
+    #[cfg(feature = "ufo")]
+    fn get_ufo_inputs(
+        &self,
+        input: &Path,
+    ) -> Result<(PathBuf, GlyphMap, Option<Kerning>, Option<MarkAnchors>), Error> {
+        let request = norad::DataRequest::none()
+            .lib(true)
+            .groups(self.ufo_kerning)
+            .kerning(self.ufo_kerning)
+            .layers(self.ufo_kerning || self.ufo_anchors);
+        let font = norad::Font::load_requested_data(input, request)?;
+        let glyph_order = compile::get_ufo_glyph_order(&font)?;
+        let fea_path = input.join("features.fea");
+        let kerning = self.ufo_kerning.then(|| Kerning::from_ufo(&font));
+        let anchors = self.ufo_anchors.then(|| MarkAnchors::from_ufo(&font));
+        Ok((fea_path, glyph_order, kerning, anchors))
+    }
+
+    #[cfg(not(feature = "ufo"))]
+    fn get_ufo_inputs(
+        &self,
+        _input: &Path,
+    ) -> Result<(PathBuf, GlyphMap, Option<Kerning>, Option<MarkAnchors>), Error> {
+        Err(Error::UfoUnsupported)
+    }
+
+    fn input(&self) -> &Path {
+        self.input
+            .as_deref()
+            .expect("input is required without a subcommand")
+    }
+
     fn glyph_order(&self) -> Option<&Path> {
         self.glyph_order.as_deref()
     }
@@ -127,6 +603,20 @@ impl Args {
     }
 }
 
+/// Load the glyph order from a glyph order file or a font.
+fn load_glyph_order(glyph_order: Option<&Path>, font: Option<&Path>) -> Result<GlyphMap, Error> {
+    if let Some(path) = glyph_order {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(compile::parse_glyph_order(&contents)?)
+    } else if let Some(path) = font {
+        let bytes = std::fs::read(path)?;
+        Ok(compile::get_post_glyph_order(&bytes)?)
+    } else {
+        Err(Error::MissingGlyphOrder)
+    }
+}
+
+#[cfg(feature = "ufo")]
 impl From<norad::error::FontLoadError> for Error {
     fn from(src: norad::error::FontLoadError) -> Error {
         Error::Ufo(Box::new(src))
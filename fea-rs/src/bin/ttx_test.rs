@@ -1,5 +1,18 @@
 //! Run the compiler against a bunch of inputs, comparing them with
 //! the results of fonttools.
+//!
+//! The inputs are the feaLib test suite, in the `test-data` submodule. Each
+//! input is compared against the ttx recorded from feaLib and, if one exists,
+//! the binary recorded from feaLib; if the layout tables of that binary are
+//! identical to ours, the ttx comparison is skipped. Running with
+//! `FEA_WRITE_TEST_OUTPUT` set records the feaLib binaries, using the
+//! `fonttools` executable.
+//!
+//! A test can be marked as an expected failure by adding a file with the
+//! `.xfail` extension next to it, containing the reason it fails.
+//!
+//! With `--builtin-ttx`, only the layout tables are compared, and they are
+//! rendered without calling the `ttx` executable.
 
 use std::path::{Path, PathBuf};
 
@@ -12,7 +25,12 @@ static WIP_DIFF_DIR: &str = "./wip";
 fn main() {
     let args = Args::parse();
 
-    let results = ttx::run_all_tests(TEST_DATA, args.test_filter.as_ref());
+    let source = if args.builtin_ttx {
+        ttx::TtxSource::Builtin
+    } else {
+        ttx::TtxSource::Executable
+    };
+    let results = ttx::run_all_tests(TEST_DATA, args.test_filter.as_ref(), source);
 
     if let Some(to_compare) = args
         .compare
@@ -81,4 +99,8 @@ struct Args {
     /// Compare results against those previously saved
     #[arg(short, long)]
     compare: Option<PathBuf>,
+    /// Compare only the GDEF, GPOS and GSUB tables, rendered without the
+    /// `ttx` executable.
+    #[arg(long)]
+    builtin_ttx: bool,
 }
@@ -3,13 +3,15 @@
 use std::fmt::{Display, Formatter};
 
 use smol_str::SmolStr;
+use write_fonts::tables::layout::{CoverageTable, CoverageTableBuilder};
 pub use write_fonts::types::GlyphId;
 
 mod glyph_class;
 mod glyph_map;
 
 pub use glyph_class::GlyphClass;
-pub use glyph_map::GlyphMap;
+pub(crate) use glyph_class::GlyphClassBuilder;
+pub use glyph_map::{AliasError, GlyphMap, GlyphMapUpdate};
 
 /// A glyph name
 pub type GlyphName = SmolStr;
@@ -51,6 +53,16 @@ impl Display for GlyphIdent {
     }
 }
 
+impl Display for GlyphOrClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            GlyphOrClass::Glyph(gid) => write!(f, "{}", gid),
+            GlyphOrClass::Class(class) => write!(f, "{}", class),
+            GlyphOrClass::Null => write!(f, "NULL"),
+        }
+    }
+}
+
 impl GlyphOrClass {
     pub(crate) fn len(&self) -> usize {
         match self {
@@ -82,6 +94,13 @@ impl GlyphOrClass {
         }
     }
 
+    /// Build a coverage table for this glyph or class.
+    ///
+    /// See [`GlyphClass::to_coverage`].
+    pub(crate) fn to_coverage(&self) -> CoverageTable {
+        self.iter().collect::<CoverageTableBuilder>().build()
+    }
+
     pub(crate) fn iter(&self) -> impl Iterator<Item = GlyphId> + '_ {
         let mut idx = 0;
         std::iter::from_fn(move || {
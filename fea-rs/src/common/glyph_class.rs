@@ -1,6 +1,13 @@
-use std::rc::Rc;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::Arc,
+};
 
-use write_fonts::types::GlyphId;
+use write_fonts::{
+    tables::layout::{CoverageTable, CoverageTableBuilder},
+    types::GlyphId,
+};
 
 use super::GlyphOrClass;
 
@@ -10,7 +17,7 @@ use super::GlyphOrClass;
 /// that a glyph class is sorted and deduplicated, and in other places it expects
 /// a glyph class to be an arbitrary sequence of glyphs.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct GlyphClass(Rc<[GlyphId]>);
+pub struct GlyphClass(Arc<[GlyphId]>);
 
 impl std::iter::FromIterator<GlyphId> for GlyphClass {
     fn from_iter<T: IntoIterator<Item = GlyphId>>(iter: T) -> Self {
@@ -34,7 +41,7 @@ impl GlyphClass {
     }
 
     pub fn empty() -> Self {
-        Self(Rc::new([]))
+        Self(Arc::new([]))
     }
 
     pub fn sort_and_dedupe(&self) -> GlyphClass {
@@ -45,6 +52,14 @@ impl GlyphClass {
         GlyphClass(vec.into())
     }
 
+    /// Build a coverage table containing the glyphs in this class.
+    ///
+    /// Coverage tables must be sorted and cannot contain duplicates; the
+    /// order of the class is not preserved.
+    pub(crate) fn to_coverage(&self) -> CoverageTable {
+        self.iter().collect::<CoverageTableBuilder>().build()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = GlyphId> + '_ {
         self.items().iter().copied()
     }
@@ -52,6 +67,84 @@ impl GlyphClass {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Formats the class as in FEA, with glyph ids in place of names.
+impl std::fmt::Display for GlyphClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i, gid) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{gid}")?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Incrementally builds a [`GlyphClass`].
+///
+/// This keeps track of the glyphs already added, so that duplicates can be
+/// reported as they are inserted. It can optionally also remember the source
+/// span that each glyph was first added from, so that diagnostics about the
+/// content of a class can point at a specific glyph in its definition.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GlyphClassBuilder {
+    glyphs: Vec<GlyphId>,
+    seen: HashSet<GlyphId>,
+    spans: Option<HashMap<GlyphId, Range<usize>>>,
+}
+
+impl GlyphClassBuilder {
+    /// A builder that remembers where each glyph came from.
+    pub(crate) fn with_spans() -> Self {
+        Self {
+            spans: Some(Default::default()),
+            ..Default::default()
+        }
+    }
+
+    /// Add a glyph, returning `false` if it was already in the class.
+    ///
+    /// The glyph is added either way; the order and length of a class matter
+    /// in some rules, such as `sub [a b c] by [d e f];`.
+    pub(crate) fn insert(&mut self, glyph: GlyphId, span: Range<usize>) -> bool {
+        self.glyphs.push(glyph);
+        if let Some(spans) = self.spans.as_mut() {
+            spans.entry(glyph).or_insert(span);
+        }
+        self.seen.insert(glyph)
+    }
+
+    /// Add a number of glyphs that share a span, such as a range or a named
+    /// class, returning any that were already in the class.
+    pub(crate) fn extend(
+        &mut self,
+        glyphs: impl IntoIterator<Item = GlyphId>,
+        span: Range<usize>,
+    ) -> Vec<GlyphId> {
+        glyphs
+            .into_iter()
+            .filter(|gid| !self.insert(*gid, span.clone()))
+            .collect()
+    }
+
+    /// The span each glyph was first added from, if spans are being tracked.
+    pub(crate) fn spans(&self) -> impl Iterator<Item = (GlyphId, Range<usize>)> + '_ {
+        self.spans
+            .iter()
+            .flatten()
+            .map(|(gid, span)| (*gid, span.clone()))
+    }
+
+    pub(crate) fn build(self) -> GlyphClass {
+        self.glyphs.into()
+    }
 }
 
 impl From<Vec<GlyphId>> for GlyphClass {
@@ -14,11 +14,45 @@ use std::{
 /// GlyphIds that will be used in the final font.
 ///
 /// Currently, the only way to construct this type is by calling `collect()`
-/// on an iterator of cids or names.
+/// on an iterator of cids or names. Glyphs can be added to the end of an
+/// existing map with [`append`][Self::append].
+///
+/// A map may also have aliases: alternate names for its glyphs, such as the
+/// working names used in a source when the map contains the production names
+/// of the final font. See [`add_aliases`][Self::add_aliases].
 #[derive(Clone, Debug, Default)]
 pub struct GlyphMap {
     names: HashMap<GlyphName, GlyphId>,
     cids: HashMap<u16, GlyphId>,
+    aliases: HashMap<GlyphName, GlyphId>,
+    // aliases that could not be added, so we can explain them in diagnostics
+    alias_errors: HashMap<GlyphName, AliasError>,
+}
+
+/// The result of adding glyphs to a [`GlyphMap`] with [`GlyphMap::append`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GlyphMapUpdate {
+    added: usize,
+    requires_reparse: bool,
+}
+
+/// An error that occurs when adding aliases to a [`GlyphMap`].
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum AliasError {
+    /// The alias refers to a glyph that is not in the map
+    #[error("alias '{alias}' refers to unknown glyph '{name}'")]
+    UnknownGlyph { alias: GlyphName, name: GlyphName },
+    /// The alias is already the name of a different glyph
+    #[error("alias '{alias}' for '{name}' is also the name of another glyph")]
+    ShadowsGlyph { alias: GlyphName, name: GlyphName },
+    /// The same alias was given for two different glyphs
+    #[error("alias '{alias}' is given for both '{first}' and '{second}'")]
+    Ambiguous {
+        alias: GlyphName,
+        first: GlyphName,
+        second: GlyphName,
+    },
 }
 
 impl GlyphMap {
@@ -32,6 +66,11 @@ impl GlyphMap {
         self.names.is_empty() && self.cids.is_empty()
     }
 
+    /// Iterate over all the glyph names in this map, in arbitrary order
+    pub(crate) fn names(&self) -> impl Iterator<Item = &GlyphName> {
+        self.names.keys()
+    }
+
     /// Generates a reverse map of ids -> raw identifers (names or CIDs)
     //  maybe just for testing?
     pub fn reverse_map(&self) -> BTreeMap<GlyphId, GlyphIdent> {
@@ -46,10 +85,126 @@ impl GlyphMap {
             .collect()
     }
 
+    /// Add glyphs to the end of this map.
+    ///
+    /// New glyphs are given the ids following the last glyph, in order, so the
+    /// ids of existing glyphs do not change. Glyphs that are already in the
+    /// map are skipped. A new glyph name takes precedence over an alias with
+    /// the same name, and the alias is removed.
+    ///
+    /// This is intended for editors, where the glyph set changes often. A
+    /// [`ParseTree`] parsed with this map generally remains valid, and only
+    /// needs to be validated and compiled again; however if the names of the
+    /// new glyphs could change how a hyphenated name in the source is parsed
+    /// (as a glyph or as a range) the returned update will
+    /// [require a reparse][GlyphMapUpdate::requires_reparse].
+    ///
+    /// [`ParseTree`]: crate::ParseTree
+    pub fn append(&mut self, glyphs: impl IntoIterator<Item = GlyphIdent>) -> GlyphMapUpdate {
+        let mut update = GlyphMapUpdate::default();
+        for glyph in glyphs {
+            if self.contains_glyph(&glyph) {
+                continue;
+            }
+            let gid = GlyphId::new(self.len().try_into().unwrap());
+            match glyph {
+                GlyphIdent::Cid(cid) => {
+                    self.cids.insert(cid, gid);
+                }
+                GlyphIdent::Name(name) => {
+                    // the parser only consults the glyph map for names with hyphens
+                    update.requires_reparse |= name.contains('-');
+                    if let Some(target) = self.aliases.remove(&name) {
+                        let target = self
+                            .names
+                            .iter()
+                            .find_map(|(name, gid)| (*gid == target).then(|| name.clone()))
+                            .unwrap();
+                        let error = AliasError::ShadowsGlyph {
+                            alias: name.clone(),
+                            name: target,
+                        };
+                        self.alias_errors.insert(name.clone(), error);
+                    }
+                    self.names.insert(name, gid);
+                }
+            }
+            update.added += 1;
+        }
+        update
+    }
+
+    // like `contains`, but ignoring aliases
+    fn contains_glyph(&self, glyph: &GlyphIdent) -> bool {
+        match glyph {
+            GlyphIdent::Name(name) => self.names.contains_key(name),
+            GlyphIdent::Cid(cid) => self.cids.contains_key(cid),
+        }
+    }
+
+    /// Add aliases for glyphs in this map.
+    ///
+    /// Each item is a pair of `(alias, name)`, where `name` is a glyph in the
+    /// map; for instance a working name and the corresponding production name.
+    /// After this, looking up an alias returns the id of the named glyph.
+    ///
+    /// An alias is skipped if its glyph is not in the map, if it is already
+    /// the name of a different glyph, or if it is given for more than one glyph.
+    /// These are returned as errors; all other aliases are added.
+    pub fn add_aliases(
+        &mut self,
+        aliases: impl IntoIterator<Item = (GlyphName, GlyphName)>,
+    ) -> Vec<AliasError> {
+        let mut targets = HashMap::<GlyphName, GlyphName>::new();
+        let mut errors = Vec::new();
+        for (alias, name) in aliases {
+            // an alias for the glyph's own name is harmless
+            if alias == name {
+                continue;
+            }
+            let error = match (self.names.get(&alias), self.names.get(&name)) {
+                (_, None) => AliasError::UnknownGlyph { alias, name },
+                (Some(_), Some(_)) => AliasError::ShadowsGlyph { alias, name },
+                (None, Some(gid)) => match targets.get(&alias) {
+                    Some(prev) if *prev != name => AliasError::Ambiguous {
+                        first: prev.clone(),
+                        second: name,
+                        alias,
+                    },
+                    _ => {
+                        self.aliases.insert(alias.clone(), *gid);
+                        targets.insert(alias, name);
+                        continue;
+                    }
+                },
+            };
+            errors.push(error);
+        }
+        for error in &errors {
+            let alias = match error {
+                AliasError::UnknownGlyph { alias, .. } | AliasError::ShadowsGlyph { alias, .. } => {
+                    alias
+                }
+                // we don't know which glyph was meant, so we don't use either
+                AliasError::Ambiguous { alias, .. } => {
+                    self.aliases.remove(alias);
+                    alias
+                }
+            };
+            self.alias_errors.insert(alias.clone(), error.clone());
+        }
+        errors
+    }
+
+    /// If `name` is an alias that could not be added, return the reason.
+    pub(crate) fn alias_error(&self, name: &str) -> Option<&AliasError> {
+        self.alias_errors.get(name)
+    }
+
     /// Return `true` if the map contains the provided `GlyphIdent`.
     pub fn contains<Q: ?Sized + sealed::AsGlyphIdent>(&self, key: &Q) -> bool {
         if let Some(name) = key.named() {
-            self.names.contains_key(name)
+            self.names.contains_key(name) || self.aliases.contains_key(name)
         } else if let Some(cid) = key.cid() {
             self.cids.contains_key(cid)
         } else {
@@ -60,7 +215,10 @@ impl GlyphMap {
     /// Return the `GlyphId` for the provided `GlyphIdent`
     pub fn get<Q: ?Sized + sealed::AsGlyphIdent>(&self, key: &Q) -> Option<GlyphId> {
         if let Some(name) = key.named() {
-            self.names.get(name).copied()
+            self.names
+                .get(name)
+                .or_else(|| self.aliases.get(name))
+                .copied()
         } else if let Some(cid) = key.cid() {
             self.cids.get(cid).copied()
         } else {
@@ -83,6 +241,23 @@ impl GlyphMap {
     }
 }
 
+impl GlyphMapUpdate {
+    /// The number of glyphs that were added to the map.
+    pub fn added(&self) -> usize {
+        self.added
+    }
+
+    /// `true` if parse trees created with the previous map should be parsed again.
+    ///
+    /// This is the case if any new glyph name contains a hyphen, since the
+    /// parser uses the glyph map to decide whether a hyphenated name is a
+    /// glyph or a range of glyphs. Otherwise an existing tree only needs to
+    /// be validated again, which will report glyphs that are still missing.
+    pub fn requires_reparse(&self) -> bool {
+        self.requires_reparse
+    }
+}
+
 impl FromIterator<u16> for GlyphMap {
     fn from_iter<T: IntoIterator<Item = u16>>(iter: T) -> Self {
         GlyphMap {
@@ -92,6 +267,7 @@ impl FromIterator<u16> for GlyphMap {
                 .enumerate()
                 .map(|(i, cid)| (cid, GlyphId::new(i.try_into().unwrap())))
                 .collect(),
+            ..Default::default()
         }
     }
 }
@@ -104,7 +280,7 @@ impl FromIterator<GlyphName> for GlyphMap {
                 .enumerate()
                 .map(|(i, cid)| (cid, GlyphId::new(i.try_into().unwrap())))
                 .collect(),
-            cids: HashMap::new(),
+            ..Default::default()
         }
     }
 }
@@ -121,7 +297,11 @@ impl FromIterator<GlyphIdent> for GlyphMap {
                 GlyphIdent::Name(name) => names.insert(name, idx),
             };
         }
-        GlyphMap { names, cids }
+        GlyphMap {
+            names,
+            cids,
+            ..Default::default()
+        }
     }
 }
 
@@ -1,45 +1,150 @@
 //! Compiling OpenType Layout tables
 
+use std::time::Instant;
+
 use write_fonts::types::GlyphId;
 
 use crate::{parse::ParseTree, Diagnostic, GlyphMap, GlyphName};
 
 use self::{
     compile_ctx::CompilationCtx,
-    error::{FontGlyphOrderError, GlyphOrderError, UfoGlyphOrderError},
+    error::{FontGlyphOrderError, GlyphOrderError},
 };
 
+#[cfg(feature = "ufo")]
+use self::error::UfoGlyphOrderError;
+
+pub use anon::AnonBlockHandler;
+pub use cache::CompilationCache;
 pub use compiler::Compiler;
-pub use opts::Opts;
+pub use feature_params::FeatureParamsHandler;
+pub use fingerprint::Fingerprint;
+pub use kerning::Kerning;
+pub use lookups::FeatureKey;
+pub use marks::MarkAnchors;
+pub use opts::{FeatureRecordOrder, MissingGlyphs, Opts, PairPosConflicts};
 pub use output::Compilation;
+pub use project::{FeaProject, GlyphSource, ProjectOutput, ProjectTarget};
+pub use summary::{FeatureInfo, LookupInfo, LookupKind};
+pub use transform::TreeTransform;
+pub use variations::VariationAxis;
 
+mod anon;
+mod cache;
 mod compile_ctx;
 mod compiler;
 pub mod error;
+mod feature_params;
 mod features;
+mod fingerprint;
 mod glyph_range;
+mod kerning;
 mod language_system;
+mod lint;
 mod lookups;
+mod marks;
 mod opts;
 mod output;
+mod project;
+pub mod report;
+#[cfg(feature = "shape-check")]
+pub mod shape_check;
+mod summary;
 mod tables;
 mod tags;
+mod transform;
 mod validate;
 mod valuerecordext;
+mod variations;
+
+/// Validate a parse tree, without compiling it.
+///
+/// This is the stage of compilation between parsing (see
+/// [`parse_root`][crate::parse::parse_root]) and compilation; the tree should
+/// be free of parse errors. It checks that names are defined before use,
+/// that glyphs exist in the `glyph_map`, and that statements are legal
+/// where they appear, among other things.
+///
+/// On success, any warnings are returned. Otherwise the errors (but not the
+/// warnings) are returned in a [`DiagnosticSet`], which can be displayed with
+/// the appropriate source context.
+///
+/// [`DiagnosticSet`]: error::DiagnosticSet
+pub fn validate(
+    tree: &ParseTree,
+    glyph_map: &GlyphMap,
+    opts: &Opts,
+) -> Result<Vec<Diagnostic>, error::DiagnosticSet> {
+    error::DiagnosticSet::split(validation_diagnostics(tree, glyph_map, opts), tree)
+}
 
-/// Run the validation pass, returning any diagnostics.
-pub(crate) fn validate(node: &ParseTree, glyph_map: &GlyphMap) -> Vec<Diagnostic> {
-    let mut ctx = validate::ValidationCtx::new(glyph_map, node.source_map());
+/// Validate and compile a parse tree.
+///
+/// This is equivalent to [`Compiler::compile`], for a source that has already
+/// been parsed; this is useful when the same tree is compiled more than once,
+/// or when the parse tree is also used for other purposes. The tree should be
+/// free of parse errors.
+///
+/// Warnings from both validation and compilation are available in
+/// [`Compilation::warnings`]. To reuse lookups from an earlier compilation of
+/// a similar source, see [`Compilation::assemble_with_cache`].
+pub fn compile(
+    tree: &ParseTree,
+    glyph_map: &GlyphMap,
+    opts: Opts,
+) -> Result<Compilation, error::DiagnosticSet> {
+    let start = Instant::now();
+    let mut warnings = validate(tree, glyph_map, &opts)?;
+    log::debug!("validated in {:?}", start.elapsed());
+    let start = Instant::now();
+    let mut ctx = CompilationCtx::new(glyph_map, tree.source_map(), opts);
+    ctx.compile(&tree.typed_root());
+    log::debug!("compiled in {:?}", start.elapsed());
+    warnings.extend(error::DiagnosticSet::split(
+        std::mem::take(&mut ctx.errors),
+        tree,
+    )?);
+    let mut compilation = ctx.build().unwrap(); // we've taken the errors, so this can't fail
+    compilation.warnings = warnings;
+    compilation.sources = tree.sources.sorted_paths();
+    Ok(compilation)
+}
+
+/// Run the validation pass, returning all diagnostics.
+///
+/// This includes the unused definition lint, if it is enabled.
+pub(crate) fn validation_diagnostics(
+    node: &ParseTree,
+    glyph_map: &GlyphMap,
+    opts: &Opts,
+) -> Vec<Diagnostic> {
+    let mut ctx = validate::ValidationCtx::new(
+        glyph_map,
+        node.source_map(),
+        opts.missing_glyphs,
+        opts.implicit_default_lang_system,
+        opts.implicit_script_lang_systems,
+        opts.forward_class_references,
+        opts.unicode_name_cmap.as_ref(),
+    );
     ctx.validate_root(&node.typed_root());
-    ctx.errors
+    let mut diagnostics = ctx.errors;
+    if opts.warn_unused {
+        diagnostics.extend(lint::unused_definitions(node));
+    }
+    diagnostics
 }
 
+#[cfg(feature = "ufo")]
 static GLYPH_ORDER_KEY: &str = "public.glyphOrder";
+#[cfg(feature = "ufo")]
+static POSTSCRIPT_NAMES_KEY: &str = "public.postscriptNames";
 
 /// A helper function for extracting the glyph order from a UFO
 ///
 /// If the public.glyphOrder key is missing, or the glyphOrder is malformed,
 /// this will return `None`.
+#[cfg(feature = "ufo")]
 pub fn get_ufo_glyph_order(font: &norad::Font) -> Result<GlyphMap, UfoGlyphOrderError> {
     font.lib
         .get(GLYPH_ORDER_KEY)
@@ -54,6 +159,28 @@ pub fn get_ufo_glyph_order(font: &norad::Font) -> Result<GlyphMap, UfoGlyphOrder
         .ok_or(UfoGlyphOrderError::Malformed)
 }
 
+/// A helper function for extracting the production names of glyphs in a UFO.
+///
+/// This returns pairs of `(working name, production name)`, suitable for
+/// passing to [`GlyphMap::add_aliases`] on the glyph map of a font that uses
+/// production names. Glyphs without an entry have the same name in both.
+///
+/// If the public.postscriptNames key is missing or malformed, this will
+/// return `None`.
+#[cfg(feature = "ufo")]
+pub fn get_ufo_postscript_names(font: &norad::Font) -> Option<Vec<(GlyphName, GlyphName)>> {
+    font.lib
+        .get(POSTSCRIPT_NAMES_KEY)?
+        .as_dictionary()?
+        .iter()
+        .map(|(working, production)| {
+            production
+                .as_string()
+                .map(|production| (GlyphName::new(working), GlyphName::new(production)))
+        })
+        .collect()
+}
+
 /// A helper function for extracting glyph order from a font with a 'post' table
 ///
 /// If 'post' is missing or malformed, this will return `None`.
@@ -0,0 +1,54 @@
+//! Support for `anonymous` blocks.
+//!
+//! The feature file syntax reserves `anon` (or `anonymous`) blocks for data
+//! that is opaque to the compiler. The contents are passed verbatim to a
+//! handler registered for the block's tag, which may produce additional
+//! tables for the final font.
+//!
+//! See [the spec][] for more information.
+//!
+//! [the spec]: http://adobe-type-tools.github.io/afdko/OpenTypeFeatureFileSpecification.html#10-anonymous-data-blocks
+
+use write_fonts::types::Tag;
+
+/// A trait for handling the contents of `anonymous` blocks.
+///
+/// A handler is registered for a specific block tag with
+/// [`Compiler::with_anon_handler`][crate::Compiler::with_anon_handler].
+///
+/// This trait is implemented for closures with the signature of [`handle`],
+/// so in most cases you will not need to implement it yourself.
+///
+/// [`handle`]: AnonBlockHandler::handle
+pub trait AnonBlockHandler {
+    /// Process the raw text of a block.
+    ///
+    /// The text is everything between the opening and closing braces,
+    /// including whitespace and comments. On success, this returns any tables
+    /// that should be added to the compiled font; a table produced here will
+    /// replace any table with the same tag generated by the compiler.
+    ///
+    /// On failure, the returned message is reported as an error at the block.
+    fn handle(&self, contents: &str) -> Result<Vec<(Tag, Vec<u8>)>, String>;
+
+    // a little helper used in our debug impl
+    #[doc(hidden)]
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+impl<F> AnonBlockHandler for F
+where
+    F: Fn(&str) -> Result<Vec<(Tag, Vec<u8>)>, String>,
+{
+    fn handle(&self, contents: &str) -> Result<Vec<(Tag, Vec<u8>)>, String> {
+        (self)(contents)
+    }
+}
+
+impl std::fmt::Debug for dyn AnonBlockHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.type_name().fmt(f)
+    }
+}
@@ -0,0 +1,231 @@
+//! Reusing built lookups between compilations
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use write_fonts::tables::{gpos as write_gpos, gsub as write_gsub};
+
+use crate::{Kind, Node, NodeOrToken};
+
+use super::Opts;
+
+/// A cache of built lookups, for recompiling a source that has changed in part.
+///
+/// Each top-level feature or lookup block is identified by a hash of its
+/// tokens, along with the top-level statements that are not blocks (such as
+/// class definitions and language systems), the glyph map, the compilation
+/// options and the final ids of the lookups generated so far. When a
+/// compilation is assembled with [`Compilation::assemble_with_cache`], the
+/// lookups generated by a block that is unchanged since the previous assembly
+/// are reused instead of being built again.
+///
+/// Rules are still resolved from the source each time; what is skipped is
+/// building and splitting the subtables of each lookup, which dominates the
+/// cost of compiling large kerning or contextual features. The cache only
+/// lives in memory, and only holds the blocks of the most recent assembly.
+///
+/// A [`FeaProject`] shares one cache between its targets, so that a block
+/// that compiles to the same lookups in several targets (such as one in a
+/// shared include) is only built once. In this case the cache holds the blocks
+/// of every target from the most recent compilation of the project.
+///
+/// [`Compilation::assemble_with_cache`]: super::Compilation::assemble_with_cache
+/// [`FeaProject`]: super::FeaProject
+#[derive(Clone, Debug, Default)]
+pub struct CompilationCache {
+    blocks: HashMap<u64, CachedBlock>,
+    // while compiling a project, the blocks of the targets compiled so far
+    batch: Option<HashMap<u64, CachedBlock>>,
+    reused: usize,
+}
+
+#[derive(Clone, Debug)]
+struct CachedBlock {
+    gpos: Vec<write_gpos::PositionLookup>,
+    gsub: Vec<write_gsub::SubstitutionLookup>,
+}
+
+/// The lookups generated by a top-level block, by their final index.
+#[derive(Clone, Debug)]
+pub(crate) struct BlockLookups {
+    pub(crate) key: u64,
+    pub(crate) gpos: Vec<usize>,
+    pub(crate) gsub: Vec<usize>,
+}
+
+/// Lookups from a previous compilation, by their final index.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PrebuiltLookups {
+    pub(crate) gpos: HashMap<usize, write_gpos::PositionLookup>,
+    pub(crate) gsub: HashMap<usize, write_gsub::SubstitutionLookup>,
+}
+
+impl CompilationCache {
+    /// Create a new, empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of lookups that were reused by the most recent assembly.
+    pub fn reused_lookups(&self) -> usize {
+        self.reused
+    }
+
+    /// Remove all cached lookups.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.batch = None;
+        self.reused = 0;
+    }
+
+    /// Keep the blocks of every assembly until [`finish_batch`] is called.
+    ///
+    /// [`finish_batch`]: Self::finish_batch
+    pub(crate) fn start_batch(&mut self) {
+        self.batch = Some(HashMap::new());
+    }
+
+    /// Replace the contents of the cache with the blocks of every assembly
+    /// since [`start_batch`] was called.
+    ///
+    /// [`start_batch`]: Self::start_batch
+    pub(crate) fn finish_batch(&mut self) {
+        if let Some(batch) = self.batch.take() {
+            self.blocks = batch;
+        }
+    }
+
+    /// The cached lookups for any of these blocks.
+    pub(crate) fn prebuilt(&mut self, blocks: &[BlockLookups]) -> PrebuiltLookups {
+        let mut result = PrebuiltLookups::default();
+        for block in blocks {
+            let Some(cached) = self.blocks.get(&block.key) else {
+                continue;
+            };
+            // the ids are part of the key, so the lengths always match
+            result
+                .gpos
+                .extend(block.gpos.iter().copied().zip(cached.gpos.iter().cloned()));
+            result
+                .gsub
+                .extend(block.gsub.iter().copied().zip(cached.gsub.iter().cloned()));
+        }
+        self.reused = result.gpos.len() + result.gsub.len();
+        result
+    }
+
+    /// Replace the contents of the cache with the lookups from these blocks.
+    ///
+    /// During a batch, the lookups are added to the cache instead.
+    pub(crate) fn update(
+        &mut self,
+        blocks: &[BlockLookups],
+        gsub: Option<&write_gsub::Gsub>,
+        gpos: Option<&write_gpos::Gpos>,
+    ) {
+        let gpos_lookups = gpos.map(|gpos| gpos.lookup_list.lookups.as_slice());
+        let gsub_lookups = gsub.map(|gsub| gsub.lookup_list.lookups.as_slice());
+        let blocks = blocks
+            .iter()
+            .map(|block| {
+                let cached = CachedBlock {
+                    gpos: block
+                        .gpos
+                        .iter()
+                        .map(|idx| (*gpos_lookups.unwrap()[*idx]).clone())
+                        .collect(),
+                    gsub: block
+                        .gsub
+                        .iter()
+                        .map(|idx| (*gsub_lookups.unwrap()[*idx]).clone())
+                        .collect(),
+                };
+                (block.key, cached)
+            })
+            .collect::<HashMap<_, _>>();
+        match self.batch.as_mut() {
+            Some(batch) => {
+                batch.extend(blocks.iter().map(|(key, block)| (*key, block.clone())));
+                self.blocks.extend(blocks);
+            }
+            None => self.blocks = blocks,
+        }
+    }
+}
+
+pub(crate) fn new_hasher() -> DefaultHasher {
+    DefaultHasher::new()
+}
+
+/// Hash the tokens of this item, ignoring whitespace and comments.
+pub(crate) fn hash_tokens(item: &NodeOrToken, state: &mut impl Hasher) {
+    match item {
+        NodeOrToken::Token(token) if token.kind.is_trivia() => (),
+        NodeOrToken::Token(token) => token.hash_structure(state),
+        NodeOrToken::Node(node) => node.hash_structure(state),
+    }
+}
+
+/// Hash any definitions in this block that are visible outside of it.
+pub(crate) fn hash_definitions(node: &Node, state: &mut impl Hasher) {
+    for child in node.iter_children() {
+        match child.kind() {
+            Kind::GlyphClassDefNode
+            | Kind::MarkClassNode
+            | Kind::AnchorDefNode
+            | Kind::ValueRecordDefNode => hash_tokens(child, state),
+            Kind::LookupBlockNode => hash_definitions(child.as_node().unwrap(), state),
+            _ => (),
+        }
+    }
+}
+
+/// Hash every option that can change the lookups we generate.
+pub(crate) fn hash_opts(opts: &Opts, state: &mut impl Hasher) {
+    let Opts {
+        make_post_table: _,
+        force_chain_pos,
+        pair_pos_class_0,
+        compact_value_formats,
+        rtl_pair_pos_placement,
+        pair_pos_conflicts,
+        // this only changes the order of feature records
+        feature_record_order: _,
+        infer_glyph_classes,
+        warn_unused: _,
+        pedantic: _,
+        missing_glyphs,
+        implicit_default_lang_system,
+        implicit_script_lang_systems,
+        forward_class_references,
+        // this only adds warnings
+        unicode_name_cmap: _,
+        glyph_subset,
+        feature_remap: _,
+        language_system_subset,
+        variation_axes,
+    } = opts;
+    force_chain_pos.hash(state);
+    pair_pos_class_0.hash(state);
+    compact_value_formats.hash(state);
+    rtl_pair_pos_placement.hash(state);
+    pair_pos_conflicts.hash(state);
+    infer_glyph_classes.hash(state);
+    missing_glyphs.hash(state);
+    implicit_default_lang_system.hash(state);
+    implicit_script_lang_systems.hash(state);
+    forward_class_references.hash(state);
+    let mut subset = glyph_subset.iter().flatten().copied().collect::<Vec<_>>();
+    subset.sort_unstable();
+    (glyph_subset.is_some(), subset).hash(state);
+    // removing lookups changes the ids of the lookups that follow them
+    language_system_subset.hash(state);
+    for axis in variation_axes {
+        axis.tag().hash(state);
+        [axis.min(), axis.default(), axis.max()]
+            .map(f32::to_bits)
+            .hash(state);
+    }
+}
@@ -1,7 +1,9 @@
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
     convert::TryInto,
+    hash::{Hash, Hasher},
     ops::Range,
+    time::Instant,
 };
 
 use smol_str::SmolStr;
@@ -10,23 +12,24 @@ use write_fonts::{
         self,
         gdef::CaretValue,
         gpos::{AnchorTable, ValueRecord},
-        layout::LookupFlag,
+        layout::{Device, FeatureParams, LookupFlag},
     },
     types::{NameId, Tag},
 };
 
 use crate::{
-    common::{GlyphClass, GlyphId, GlyphOrClass},
-    parse::SourceMap,
+    common::{GlyphClass, GlyphClassBuilder, GlyphId, GlyphOrClass},
+    parse::{Location, SourceMap},
     token_tree::{
         typed::{self, AstNode},
         Token,
     },
     typed::ContextualRuleNode,
-    Diagnostic, GlyphIdent, GlyphMap, Kind, NodeOrToken,
+    Diagnostic, GlyphIdent, GlyphMap, Kind, Level, NodeOrToken,
 };
 
 use super::{
+    cache::{self, BlockLookups},
     features::{AaltFeature, ActiveFeature, SizeFeature, SpecialVerticalFeatureState},
     glyph_range,
     language_system::{DefaultLanguageSystems, LanguageSystem},
@@ -38,6 +41,9 @@ use super::{
     tables::{ClassId, CvParams, ScriptRecord, Tables},
     tags,
     valuerecordext::ValueRecordExt,
+    variations::{self, VariationStoreBuilder},
+    AnonBlockHandler, FeatureParamsHandler, Kerning, MarkAnchors, MissingGlyphs, Opts,
+    PairPosConflicts,
 };
 
 pub struct CompilationCtx<'a> {
@@ -54,22 +60,76 @@ pub struct CompilationCtx<'a> {
     vertical_feature: SpecialVerticalFeatureState,
     script: Option<Tag>,
     glyph_class_defs: HashMap<SmolStr, GlyphClass>,
+    // with forward class references, the definition of every glyph class,
+    // so that a class can be resolved before its definition is compiled
+    forward_class_defs: HashMap<SmolStr, typed::GlyphClassDef>,
     mark_classes: HashMap<SmolStr, MarkClass>,
     anchor_defs: HashMap<SmolStr, (AnchorTable, usize)>,
+    // the deltas of variable metrics
+    variations: VariationStoreBuilder,
     mark_attach_class_id: HashMap<GlyphClass, u16>,
     mark_filter_sets: HashMap<GlyphClass, FilterSetId>,
     size: Option<SizeFeature>,
+    // FeatureParams built by a handler, for features other than 'size'
+    feature_params: HashMap<Tag, FeatureParams>,
     aalt: Option<AaltFeature>,
     required_features: HashSet<FeatureKey>,
+    opts: Opts,
+    // scripts named in 'script' statements, if they should be registered
+    // as default language systems
+    implicit_scripts: Vec<Tag>,
+    // mark attachment rules that imply a GDEF class for their base glyphs,
+    // with the number of ligature components; used to explain inferred classes
+    class_inference_rules: HashMap<GlyphId, Vec<(ClassId, usize, Range<usize>)>>,
+    // lookupflag statements that skip glyphs by their GDEF class, and their flags
+    gdef_class_flags: Vec<(Range<usize>, LookupFlag)>,
+    // glyphs with ligature carets, the number of carets, and the statement range
+    ligature_carets: Vec<(GlyphOrClass, usize, Range<usize>)>,
+    // the first statement to substitute each ligature component sequence
+    ligature_rules: HashMap<LigatureRuleKey, Range<usize>>,
+    // the statements of the glyph and class pair rules in each pair
+    // positioning subtable, if conflicts between them are reported
+    pair_rules: HashMap<PairSubtableKey, PairRuleSources>,
+    // the first statement in each cursive lookup
+    cursive_rules: BTreeMap<LookupId, Range<usize>>,
+    // the statement that gave each glyph its anchors in a cursive lookup
+    cursive_glyph_rules: HashMap<(LookupId, GlyphId), Range<usize>>,
+    // the hash of each top-level block and its context, with the ranges of
+    // gpos and gsub lookups it generated
+    block_lookup_counts: Vec<(u64, Range<usize>, Range<usize>)>,
+    // the final lookups of each top-level block, used to reuse built lookups
+    blocks: Vec<BlockLookups>,
+    // the statements that added rules to each lookup
+    lookup_sources: HashMap<LookupId, Vec<Range<usize>>>,
+    // the same, once lookups have their final ids
+    final_lookup_sources: HashMap<LookupId, Vec<Location>>,
+    anon_handlers: Option<&'a HashMap<SmolStr, Box<dyn AnonBlockHandler>>>,
+    feature_params_handlers: Option<&'a HashMap<Tag, Box<dyn FeatureParamsHandler>>>,
+    kerning: Option<&'a Kerning>,
+    mark_anchors: Option<&'a MarkAnchors>,
+}
+
+// the lookup that contains a ligature component sequence, and the sequence
+type LigatureRuleKey = (LookupId, Vec<GlyphId>);
+
+// a pair positioning lookup, and the index of the subtable in the lookup
+type PairSubtableKey = (LookupId, usize);
+
+#[derive(Clone, Debug, Default)]
+struct PairRuleSources {
+    pairs: HashMap<(GlyphId, GlyphId), Range<usize>>,
+    classes: HashMap<(GlyphClass, GlyphClass), Range<usize>>,
 }
 
 #[derive(Clone, Debug, Default)]
 struct MarkClass {
     members: Vec<(GlyphClass, Option<AnchorTable>)>,
+    // where each glyph was first added to this class
+    glyph_spans: HashMap<GlyphId, Range<usize>>,
 }
 
 impl<'a> CompilationCtx<'a> {
-    pub(crate) fn new(glyph_map: &'a GlyphMap, source_map: &'a SourceMap) -> Self {
+    pub(crate) fn new(glyph_map: &'a GlyphMap, source_map: &'a SourceMap, opts: Opts) -> Self {
         CompilationCtx {
             glyph_map,
             reverse_glyph_map: glyph_map.reverse_map(),
@@ -78,10 +138,12 @@ impl<'a> CompilationCtx<'a> {
             tables: Tables::default(),
             default_lang_systems: Default::default(),
             glyph_class_defs: Default::default(),
-            lookups: Default::default(),
+            forward_class_defs: Default::default(),
+            lookups: AllLookups::new(&opts),
             features: Default::default(),
             mark_classes: Default::default(),
             anchor_defs: Default::default(),
+            variations: VariationStoreBuilder::new(opts.variation_axes.clone()),
             lookup_flags: Default::default(),
             active_feature: None,
             vertical_feature: Default::default(),
@@ -89,45 +151,565 @@ impl<'a> CompilationCtx<'a> {
             mark_attach_class_id: Default::default(),
             mark_filter_sets: Default::default(),
             size: None,
+            feature_params: Default::default(),
             required_features: Default::default(),
             aalt: Default::default(),
+            opts,
+            implicit_scripts: Vec::new(),
+            class_inference_rules: Default::default(),
+            gdef_class_flags: Default::default(),
+            ligature_carets: Default::default(),
+            ligature_rules: Default::default(),
+            pair_rules: Default::default(),
+            cursive_rules: Default::default(),
+            cursive_glyph_rules: Default::default(),
+            block_lookup_counts: Default::default(),
+            blocks: Default::default(),
+            lookup_sources: Default::default(),
+            final_lookup_sources: Default::default(),
+            anon_handlers: None,
+            feature_params_handlers: None,
+            kerning: None,
+            mark_anchors: None,
         }
     }
 
+    pub(crate) fn with_anon_handlers(
+        mut self,
+        handlers: &'a HashMap<SmolStr, Box<dyn AnonBlockHandler>>,
+    ) -> Self {
+        self.anon_handlers = Some(handlers);
+        self
+    }
+
+    pub(crate) fn with_feature_params_handlers(
+        mut self,
+        handlers: &'a HashMap<Tag, Box<dyn FeatureParamsHandler>>,
+    ) -> Self {
+        self.feature_params_handlers = Some(handlers);
+        self
+    }
+
+    pub(crate) fn with_kerning(mut self, kerning: Option<&'a Kerning>) -> Self {
+        self.kerning = kerning;
+        self
+    }
+
+    pub(crate) fn with_mark_anchors(mut self, anchors: Option<&'a MarkAnchors>) -> Self {
+        self.mark_anchors = anchors;
+        self
+    }
+
     pub(crate) fn compile(&mut self, node: &typed::Root) {
+        // everything outside of a block that can affect the lookups it generates
+        let mut context = cache::new_hasher();
+        cache::hash_opts(&self.opts, &mut context);
+        self.reverse_glyph_map.hash(&mut context);
+        for item in node.statements().filter(|item| !is_block(item)) {
+            cache::hash_tokens(item, &mut context);
+        }
+        if self.opts.forward_class_references {
+            // any block can use a class defined in a later block
+            for item in node.statements().filter(|item| is_block(item)) {
+                cache::hash_definitions(item.as_node().unwrap(), &mut context);
+            }
+            collect_class_defs(node.statements(), &mut self.forward_class_defs);
+        }
+        if self.opts.implicit_script_lang_systems {
+            self.implicit_scripts = node
+                .statements()
+                .filter_map(typed::Feature::cast)
+                .flat_map(|feature| {
+                    feature
+                        .statements()
+                        .filter_map(typed::Script::cast)
+                        .map(|script| script.tag().to_raw())
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+        }
+        let compile_start = Instant::now();
         for item in node.statements() {
-            if let Some(language_system) = typed::LanguageSystem::cast(item) {
-                self.add_language_system(language_system);
-            } else if let Some(class_def) = typed::GlyphClassDef::cast(item) {
-                self.define_glyph_class(class_def);
-            } else if let Some(mark_def) = typed::MarkClassDef::cast(item) {
-                self.define_mark_class(mark_def);
-            } else if let Some(anchor_def) = typed::AnchorDef::cast(item) {
-                self.define_named_anchor(anchor_def);
-            } else if let Some(feature) = typed::Feature::cast(item) {
-                self.add_feature(feature);
-            } else if let Some(lookup) = typed::LookupBlock::cast(item) {
-                self.resolve_lookup_block(lookup);
-            } else if item.kind() == Kind::AnonBlockNode {
-                // noop
-            } else if let Some(table) = typed::Table::cast(item) {
-                self.resolve_table(table);
-            } else if !item.kind().is_trivia() {
-                let span = match item {
-                    NodeOrToken::Token(t) => t.range(),
-                    NodeOrToken::Node(node) => {
-                        let range = node.range();
-                        let end = range.end.min(range.start + 16);
-                        range.start..end
-                    }
-                };
-                self.error(span, format!("unhandled top-level item: '{}'", item.kind()));
+            if is_block(item) {
+                let block_start = Instant::now();
+                let start = self.lookups.lookup_counts();
+                let mut hasher = context.clone();
+                cache::hash_tokens(item, &mut hasher);
+                // the indices of any variation deltas depend on earlier blocks
+                self.variations.digest().hash(&mut hasher);
+                // definitions in a block are visible in the blocks that follow it
+                cache::hash_definitions(item.as_node().unwrap(), &mut context);
+                self.compile_statement(item);
+                let end = self.lookups.lookup_counts();
+                self.block_lookup_counts
+                    .push((hasher.finish(), start.0..end.0, start.1..end.1));
+                if log::log_enabled!(log::Level::Trace) {
+                    log::trace!(
+                        "compiled {} in {:?}: {} gpos and {} gsub lookups",
+                        describe_block(item),
+                        block_start.elapsed(),
+                        end.0 - start.0,
+                        end.1 - start.1,
+                    );
+                }
+            } else {
+                self.compile_statement(item);
             }
         }
 
+        log::debug!("compiled statements in {:?}", compile_start.elapsed());
+
+        if let Some(kerning) = self.kerning {
+            let kerning_start = Instant::now();
+            self.add_kerning(kerning);
+            log::debug!("compiled kerning in {:?}", kerning_start.elapsed());
+        }
+
+        if let Some(anchors) = self.mark_anchors {
+            let anchors_start = Instant::now();
+            self.add_mark_anchors(anchors);
+            log::debug!("compiled mark anchors in {:?}", anchors_start.elapsed());
+        }
+
+        let finalize_start = Instant::now();
+        self.check_cursive_lookups();
+        if self.opts.pedantic {
+            self.check_forced_chain_pos();
+        }
         self.finalize_gdef_table();
+        if let Some(subset) = self.opts.glyph_subset.clone() {
+            self.retain_glyph_subset(&subset);
+        }
+        if self.opts.language_system_subset.is_some() {
+            self.retain_language_systems();
+        }
         self.finalize_aalt();
+        self.finalize_lookup_ids();
+        self.remap_features();
         self.sort_and_dedupe_lookups();
+        log::debug!("finalized lookups in {:?}", finalize_start.elapsed());
+    }
+
+    fn compile_statement(&mut self, item: &NodeOrToken) {
+        if let Some(language_system) = typed::LanguageSystem::cast(item) {
+            self.add_language_system(language_system);
+        } else if let Some(class_def) = typed::GlyphClassDef::cast(item) {
+            self.define_glyph_class(class_def);
+        } else if let Some(mark_def) = typed::MarkClassDef::cast(item) {
+            self.define_mark_class(mark_def);
+        } else if let Some(anchor_def) = typed::AnchorDef::cast(item) {
+            self.define_named_anchor(anchor_def);
+        } else if let Some(feature) = typed::Feature::cast(item) {
+            self.add_feature(feature);
+        } else if let Some(lookup) = typed::LookupBlock::cast(item) {
+            self.resolve_lookup_block(lookup);
+        } else if let Some(block) = typed::AnonBlock::cast(item) {
+            self.resolve_anon_block(&block);
+        } else if let Some(table) = typed::Table::cast(item) {
+            self.resolve_table(table);
+        } else if !item.kind().is_trivia() {
+            let span = match item {
+                NodeOrToken::Token(t) => t.range(),
+                NodeOrToken::Node(node) => {
+                    let range = node.range();
+                    let end = range.end.min(range.start + 16);
+                    range.start..end
+                }
+            };
+            self.error(span, format!("unhandled top-level item: '{}'", item.kind()));
+        }
+    }
+
+    /// Compile kerning from outside the feature file into its own lookups,
+    /// and add them to the 'kern' feature.
+    fn add_kerning(&mut self, kerning: &Kerning) {
+        // pairs that the feature file already kerns are skipped
+        let user_lookups = self
+            .features
+            .iter()
+            .filter(|(key, _)| key.feature == tags::KERN)
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect::<Vec<_>>();
+        let covered = self.lookups.pair_pos_pairs(&user_lookups);
+        let mut ltr_lookup = None;
+        let mut rtl_lookup = None;
+        for resolved in kerning.resolve(self.glyph_map, &covered) {
+            let id = self.add_kerning_lookup(
+                resolved.glyph_pairs,
+                resolved.class_subtables,
+                resolved.rtl,
+            );
+            if resolved.rtl {
+                rtl_lookup = Some(id);
+            } else {
+                ltr_lookup = Some(id);
+            }
+        }
+        if ltr_lookup.is_none() && rtl_lookup.is_none() {
+            return;
+        }
+
+        // the kerning is added after any lookups from the feature file; the
+        // DFLT script gets both directions, and right-to-left scripts fall
+        // back to the other pairs if there are no right-to-left ones.
+        for key in self.generated_feature_keys(tags::KERN) {
+            let ids = if key.script == tags::SCRIPT_DFLT {
+                vec![ltr_lookup, rtl_lookup]
+            } else if tags::is_rtl_script(key.script) {
+                vec![rtl_lookup.or(ltr_lookup)]
+            } else {
+                vec![ltr_lookup]
+            };
+            self.features
+                .entry(key)
+                .or_default()
+                .extend(ids.into_iter().flatten());
+        }
+    }
+
+    fn add_kerning_lookup(
+        &mut self,
+        glyph_pairs: BTreeMap<(GlyphId, GlyphId), i16>,
+        class_subtables: Vec<Vec<(GlyphClass, GlyphClass, i16)>>,
+        rtl: bool,
+    ) -> LookupId {
+        let finished = self
+            .lookups
+            .start_lookup(Kind::GposType2, LookupFlagInfo::default());
+        debug_assert!(
+            finished.is_none(),
+            "no lookup is active after the last block"
+        );
+        let lookup = self.lookups.current_mut().expect("we just created it");
+        // these match the values of 'pos a b <n>;', or 'pos a b <n 0 n 0>;'
+        // for right-to-left pairs
+        let values = |value: i16| {
+            let first = ValueRecord {
+                x_placement: rtl.then_some(value),
+                x_advance: Some(value),
+                ..Default::default()
+            };
+            (
+                first.for_pair_pos(false),
+                ValueRecord::default().for_pair_pos(false),
+            )
+        };
+        for ((first, second), value) in glyph_pairs {
+            let (val_one, val_two) = values(value);
+            lookup.add_gpos_type_2_pair(first, second, val_one, val_two);
+        }
+        for (i, subtable) in class_subtables.into_iter().enumerate() {
+            if i > 0 {
+                self.lookups.add_subtable_break();
+            }
+            let lookup = self.lookups.current_mut().expect("we just created it");
+            for (first, second, value) in subtable {
+                let (val_one, val_two) = values(value);
+                lookup.add_gpos_type_2_class(first, second, val_one, val_two);
+            }
+        }
+        self.lookups.finish_current().expect("we just created it").0
+    }
+
+    /// Compile mark attachment from glyph anchors, and add it to the 'mark'
+    /// and 'mkmk' features.
+    fn add_mark_anchors(&mut self, anchors: &MarkAnchors) {
+        // marks and bases from the feature file are left alone
+        let user_marks = self
+            .mark_classes
+            .values()
+            .flat_map(|class| class.members.iter().flat_map(|(glyphs, _)| glyphs.iter()))
+            .collect::<HashSet<_>>();
+        let (user_bases, user_base_marks) = self.lookups.mark_attachment_bases();
+        let attachments = anchors.resolve(self.glyph_map, |gid| user_marks.contains(&gid));
+
+        let mut mark_lookups = Vec::new();
+        for item in &attachments {
+            let bases = item
+                .bases
+                .iter()
+                .filter(|(gid, _)| !user_bases.contains(gid))
+                .collect::<Vec<_>>();
+            if bases.is_empty() {
+                continue;
+            }
+            let class_name = SmolStr::from(format!("MC_{}", item.name));
+            self.lookups
+                .start_lookup(Kind::GposType4, LookupFlagInfo::default());
+            let lookup = self.lookups.current_mut().expect("we just created it");
+            lookup.with_gpos_type_4(|subtable| {
+                for (gid, (x, y)) in &item.marks {
+                    let anchor = AnchorTable::format_1(*x, *y);
+                    let _ = subtable.insert_mark(*gid, class_name.clone(), anchor);
+                }
+                for (gid, (x, y)) in bases {
+                    subtable.insert_base(*gid, &class_name, AnchorTable::format_1(*x, *y));
+                }
+            });
+            mark_lookups.push(self.lookups.finish_current().expect("we just created it").0);
+        }
+
+        let mut mkmk_lookups = Vec::new();
+        for item in &attachments {
+            let bases = item
+                .base_marks
+                .iter()
+                .filter(|(gid, _)| !user_base_marks.contains(gid))
+                .collect::<Vec<_>>();
+            if bases.is_empty() {
+                continue;
+            }
+            let class_name = SmolStr::from(format!("MC_{}", item.name));
+            let filter_set = item
+                .marks
+                .keys()
+                .chain(bases.iter().map(|(gid, _)| *gid))
+                .copied()
+                .collect::<GlyphClass>();
+            let mut flags = LookupFlagInfo::default();
+            if let Some(id) = self.add_mark_filter_set(filter_set) {
+                flags.flags.set_use_mark_filtering_set(true);
+                flags.mark_filter_set = Some(id);
+            }
+            self.lookups.start_lookup(Kind::GposType6, flags);
+            let lookup = self.lookups.current_mut().expect("we just created it");
+            lookup.with_gpos_type_6(|subtable| {
+                for (gid, (x, y)) in &item.marks {
+                    let anchor = AnchorTable::format_1(*x, *y);
+                    let _ = subtable.insert_mark(*gid, class_name.clone(), anchor);
+                }
+                for (gid, (x, y)) in bases {
+                    subtable.insert_base(*gid, &class_name, AnchorTable::format_1(*x, *y));
+                }
+            });
+            mkmk_lookups.push(self.lookups.finish_current().expect("we just created it").0);
+        }
+
+        // like kerning, these come after any lookups from the feature file
+        self.add_generated_lookups(tags::MARK, &mark_lookups);
+        self.add_generated_lookups(tags::MKMK, &mkmk_lookups);
+    }
+
+    /// Add lookups that were not defined in the feature file to a feature.
+    fn add_generated_lookups(&mut self, feature: Tag, ids: &[LookupId]) {
+        if ids.is_empty() {
+            return;
+        }
+        for key in self.generated_feature_keys(feature) {
+            self.features.entry(key).or_default().extend(ids);
+        }
+    }
+
+    /// The keys that lookups generated for this feature are added to: every
+    /// default language system, and any other that has this feature.
+    fn generated_feature_keys(&self, feature: Tag) -> HashSet<FeatureKey> {
+        let mut keys = self
+            .default_lang_systems
+            .iter()
+            .map(|sys| sys.to_feature_key(feature))
+            .collect::<HashSet<_>>();
+        keys.extend(
+            self.features
+                .keys()
+                .filter(|key| key.feature == feature)
+                .copied(),
+        );
+        keys
+    }
+
+    /// Warn about cursive lookups that can never attach anything, because no
+    /// glyph has an entry anchor, or no glyph has an exit anchor.
+    fn check_cursive_lookups(&mut self) {
+        for (id, range) in std::mem::take(&mut self.cursive_rules) {
+            let missing = match self.lookups.cursive_entry_and_exit(id) {
+                Some((false, true)) => "entry",
+                Some((true, false)) => "exit",
+                _ => continue,
+            };
+            self.warning(
+                range,
+                format!(
+                    "no glyph in this cursive lookup has an {missing} anchor, \
+                     so no glyphs will be attached"
+                ),
+            );
+        }
+    }
+
+    /// Warn about contextual positioning lookups that we compile as chained
+    /// contextual lookups, to match feaLib.
+    fn check_forced_chain_pos(&mut self) {
+        for id in self.lookups.forced_chain_pos().to_owned() {
+            let Some(range) = self.first_lookup_source(id) else {
+                continue;
+            };
+            self.warning(
+                range,
+                "contextual positioning lookup is compiled as chained contextual \
+                 positioning (GPOS type 8) instead of GPOS type 7, to match feaLib",
+            );
+        }
+    }
+
+    /// Warn about ligature glyphs that feaLib leaves out of inferred glyph
+    /// classes, although the spec would give them the ligature class.
+    fn check_uninferred_ligatures(&mut self, classes: &HashMap<GlyphId, ClassId>) {
+        for (id, glyphs) in self.lookups.ligature_glyphs() {
+            let names = glyphs
+                .iter()
+                .filter(|gid| !classes.contains_key(gid))
+                .map(|gid| format!("'{}'", self.reverse_glyph_map[gid]))
+                .collect::<Vec<_>>();
+            if names.is_empty() {
+                continue;
+            }
+            let Some(range) = self.first_lookup_source(id) else {
+                continue;
+            };
+            self.warning(
+                range,
+                format!(
+                    "ligature glyphs are not given the ligature class in the inferred \
+                     GDEF glyph classes, to match feaLib: {}",
+                    names.join(", ")
+                ),
+            );
+        }
+    }
+
+    /// The first statement that added rules to this lookup.
+    fn first_lookup_source(&self, id: LookupId) -> Option<Range<usize>> {
+        self.lookup_sources.get(&id)?.first().cloned()
+    }
+
+    /// Remove everything that references glyphs outside of the subset.
+    ///
+    /// This runs before aalt is compiled, so that it only collects the
+    /// alternates that remain.
+    fn retain_glyph_subset(&mut self, subset: &HashSet<GlyphId>) {
+        let keep = |gid: GlyphId| gid == GlyphId::NOTDEF || subset.contains(&gid);
+        let removed = self.lookups.retain_glyphs(&keep);
+        self.features.retain(|_, lookups| {
+            // features with no lookups to begin with (such as 'size') are kept
+            let had_lookups = !lookups.is_empty();
+            lookups.retain(|id| !removed.contains(id));
+            !had_lookups || !lookups.is_empty()
+        });
+
+        if let Some(gdef) = self.tables.gdef.as_mut() {
+            gdef.glyph_classes.retain(|gid, _| keep(*gid));
+            gdef.attach.retain(|gid, _| keep(*gid));
+            gdef.ligature_pos.retain(|gid, _| keep(*gid));
+            gdef.mark_attach_class.retain(|gid, _| keep(*gid));
+            // these are referenced by index, so we keep them even if empty
+            for set in gdef.mark_glyph_sets.iter_mut() {
+                *set = set.iter().filter(|gid| keep(*gid)).collect();
+            }
+            if gdef.is_empty() {
+                self.tables.gdef = None;
+            }
+        }
+    }
+
+    /// Remove the features of language systems that are not being compiled,
+    /// and any lookups that are only used by those features.
+    fn retain_language_systems(&mut self) {
+        let (mut kept, mut dropped) = (Vec::new(), Vec::new());
+        for (key, lookups) in &self.features {
+            if self.opts.keeps_language_system(key.script, key.language) {
+                kept.extend(lookups.iter().copied());
+            } else {
+                dropped.extend(lookups.iter().copied());
+            }
+        }
+        self.lookups.remove_unreachable(&kept, &dropped);
+        let opts = &self.opts;
+        self.features
+            .retain(|key, _| opts.keeps_language_system(key.script, key.language));
+        self.required_features
+            .retain(|key| opts.keeps_language_system(key.script, key.language));
+    }
+
+    /// Assign lookups their final ids, and update the features to match.
+    fn finalize_lookup_ids(&mut self) {
+        let id_map = self.lookups.finalize_ids();
+        for lookups in self.features.values_mut() {
+            id_map.remap_ids(lookups);
+        }
+        for (id, ranges) in self.lookup_sources.drain() {
+            let id = id_map.get(id);
+            if id != LookupId::Empty {
+                let locations = ranges.into_iter().map(|range| {
+                    let (file, range) = self.source_map.resolve_range(range);
+                    Location { file, range }
+                });
+                self.final_lookup_sources.insert(id, locations.collect());
+            }
+        }
+
+        // the ids of mark filtering sets and attachment classes are stored in
+        // the lookups, but are assigned in the order they are first used.
+        let mut classes = cache::new_hasher();
+        let mut filter_sets = self.mark_filter_sets.iter().collect::<Vec<_>>();
+        filter_sets.sort_unstable_by_key(|(_, id)| **id);
+        let mut attach_classes = self.mark_attach_class_id.iter().collect::<Vec<_>>();
+        attach_classes.sort_unstable_by_key(|(_, id)| **id);
+        (filter_sets, attach_classes).hash(&mut classes);
+        let classes = classes.finish();
+
+        // lookups can refer to lookups in earlier blocks, so a block's lookups
+        // can only be reused if every earlier block has the same lookup ids
+        let mut layout = cache::new_hasher();
+        for (content, gpos, gsub) in self.block_lookup_counts.drain(..) {
+            let final_ids = |ids: Vec<LookupId>| -> Vec<usize> {
+                ids.into_iter()
+                    .filter_map(|id| match id_map.get(id) {
+                        LookupId::Gpos(idx) | LookupId::Gsub(idx) => Some(idx),
+                        LookupId::Empty => None,
+                    })
+                    .collect()
+            };
+            let gpos = final_ids(gpos.map(LookupId::Gpos).collect());
+            let gsub = final_ids(gsub.map(LookupId::Gsub).collect());
+            (&gpos, &gsub).hash(&mut layout);
+            let mut key = cache::new_hasher();
+            (content, classes, layout.finish()).hash(&mut key);
+            self.blocks.push(BlockLookups {
+                key: key.finish(),
+                gpos,
+                gsub,
+            });
+        }
+    }
+
+    /// Rename or copy features, as requested in the options.
+    fn remap_features(&mut self) {
+        let mut remapped = Vec::new();
+        let mut renamed = HashMap::new();
+        for (key, lookups) in &self.features {
+            for (from, to, keep) in &self.opts.feature_remap {
+                if key.feature != *from {
+                    continue;
+                }
+                let new_key = FeatureKey {
+                    feature: *to,
+                    ..*key
+                };
+                if !keep {
+                    renamed.entry(*key).or_insert(new_key);
+                }
+                remapped.push((new_key, lookups.clone()));
+            }
+        }
+        for (key, new_key) in renamed {
+            self.features.remove(&key);
+            if self.required_features.remove(&key) {
+                self.required_features.insert(new_key);
+            }
+        }
+        for (key, lookups) in remapped {
+            self.features.entry(key).or_default().extend(lookups);
+        }
     }
 
     fn sort_and_dedupe_lookups(&mut self) {
@@ -169,21 +751,17 @@ impl<'a> CompilationCtx<'a> {
         }
 
         // now we have all of our referenced lookups, and so we want to use that
-        // to construct the aalt lookups:
+        // to construct the aalt lookups. These will be moved to the front of
+        // the lookup list when we finalize the lookup ids.
         let aalt_lookup_indices = self
             .lookups
             .insert_aalt_lookups(std::mem::take(&mut aalt.all_alts));
 
-        // now adjust our previously set lookupids, which are now invalid,
-        // since we're going to insert the aalt lookups in front of the lookup
-        // list:
-        self.features
-            .values_mut()
-            .flat_map(|x| x.iter_mut())
-            .for_each(|id| id.adjust_if_gsub(aalt_lookup_indices.len()));
-
         // finally add the aalt feature to all the default language systems
         for sys in self.default_lang_systems.iter() {
+            if !self.opts.keeps_language_system(sys.script, sys.language) {
+                continue;
+            }
             self.features
                 .insert(sys.to_feature_key(tags::AALT), aalt_lookup_indices.clone());
         }
@@ -202,7 +780,11 @@ impl<'a> CompilationCtx<'a> {
             features: self.features.clone(),
             tables: self.tables.clone(),
             size: self.size.clone(),
+            feature_params: self.feature_params.clone(),
             required_features: self.required_features.clone(),
+            blocks: self.blocks.clone(),
+            lookup_sources: self.final_lookup_sources.clone(),
+            sources: Vec::new(),
         })
     }
 
@@ -219,8 +801,9 @@ impl<'a> CompilationCtx<'a> {
     fn finalize_gdef_table(&mut self) {
         // if the FEA included a GDEF block, use that, otherwise create an empty table
         let mut gdef = self.tables.gdef.take().unwrap_or_default();
+        let explicit_classes = !gdef.glyph_classes.is_empty();
         // infer glyph classes, if they were not declared explicitly
-        if gdef.glyph_classes.is_empty() {
+        if gdef.glyph_classes.is_empty() && self.opts.infer_glyph_classes {
             self.lookups.infer_glyph_classes(|glyph, class_id| {
                 gdef.glyph_classes.insert(glyph, class_id);
             });
@@ -232,7 +815,12 @@ impl<'a> CompilationCtx<'a> {
             {
                 gdef.glyph_classes.insert(glyph, ClassId::Mark);
             }
+            if self.opts.pedantic {
+                self.check_uninferred_ligatures(&gdef.glyph_classes);
+            }
         }
+        self.check_gdef_class_flags(&gdef.glyph_classes);
+        self.check_ligature_carets(&gdef.glyph_classes, explicit_classes);
 
         if !self.mark_attach_class_id.is_empty() {
             gdef.mark_attach_class.extend(
@@ -252,11 +840,103 @@ impl<'a> CompilationCtx<'a> {
             gdef.mark_glyph_sets = sorted.into_iter().map(|(_, cls)| cls).collect();
         }
 
+        gdef.var_store = self.variations.build();
+
         if !gdef.is_empty() {
             self.tables.gdef = Some(gdef);
         }
     }
 
+    /// Warn about lookup flags that depend on GDEF glyph classes, if there are none.
+    ///
+    /// Shapers find bases, ligatures and marks by their GDEF class, so without
+    /// any classes these flags are silently ignored.
+    fn check_gdef_class_flags(&mut self, glyph_classes: &HashMap<GlyphId, ClassId>) {
+        if !glyph_classes.is_empty() {
+            return;
+        }
+        for (range, flags) in std::mem::take(&mut self.gdef_class_flags) {
+            let names = [
+                (flags.ignore_base_glyphs(), "IgnoreBaseGlyphs"),
+                (flags.ignore_ligatures(), "IgnoreLigatures"),
+                (flags.ignore_marks(), "IgnoreMarks"),
+            ]
+            .into_iter()
+            .filter_map(|(set, name)| set.then_some(name))
+            .collect::<Vec<_>>();
+            let verb = if names.len() == 1 { "has" } else { "have" };
+            self.warning(
+                range,
+                format!(
+                    "{} {verb} no effect, because the font has no GDEF glyph classes",
+                    names.join(" and ")
+                ),
+            );
+        }
+    }
+
+    /// Warn about ligature carets that disagree with the GDEF glyph classes.
+    ///
+    /// Carets are only meaningful for ligature glyphs; if the classes were
+    /// inferred, we also point at the mark attachment rule responsible.
+    fn check_ligature_carets(
+        &mut self,
+        glyph_classes: &HashMap<GlyphId, ClassId>,
+        explicit_classes: bool,
+    ) {
+        if glyph_classes.is_empty() {
+            return;
+        }
+        for (glyphs, n_carets, range) in std::mem::take(&mut self.ligature_carets) {
+            for glyph in glyphs.iter() {
+                let name = self.reverse_glyph_map.get(&glyph).unwrap().clone();
+                let class = glyph_classes.get(&glyph).copied();
+                // the last rule to assign the final class is the one that won
+                let rule = class.and_then(|class| {
+                    self.class_inference_rules
+                        .get(&glyph)
+                        .and_then(|rules| rules.iter().rev().find(|rule| rule.0 == class))
+                        .cloned()
+                });
+                match (class, rule) {
+                    (Some(ClassId::Ligature), Some((_, n_components, rule_range))) => {
+                        if n_components != n_carets + 1 {
+                            self.warning(
+                                range.clone(),
+                                format!(
+                                    "'{name}' has {n_carets} ligature carets, but {n_components} components in a mark-to-ligature rule"
+                                ),
+                            );
+                            self.info(rule_range, format!("'{name}' has {n_components} components here"));
+                        }
+                    }
+                    (Some(ClassId::Ligature), None) => (),
+                    (Some(class), _) if explicit_classes => self.warning(
+                        range.clone(),
+                        format!("'{name}' has ligature carets, but is in the GDEF {class} class"),
+                    ),
+                    (None, _) if explicit_classes => self.warning(
+                        range.clone(),
+                        format!("'{name}' has ligature carets, but is not in the GDEF Ligature class"),
+                    ),
+                    (Some(class), rule) => {
+                        self.warning(
+                            range.clone(),
+                            format!("'{name}' has ligature carets, but is inferred to be a {class} glyph"),
+                        );
+                        if let Some((_, _, rule_range)) = rule {
+                            self.info(rule_range, format!("'{name}' is inferred to be a {class} glyph here"));
+                        }
+                    }
+                    (None, _) => self.warning(
+                        range.clone(),
+                        format!("'{name}' has ligature carets, but is not used as a ligature in any mark-to-ligature rule"),
+                    ),
+                }
+            }
+        }
+    }
+
     fn error(&mut self, range: Range<usize>, message: impl Into<String>) {
         let (file, range) = self.source_map.resolve_range(range);
         self.errors.push(Diagnostic::error(file, range, message));
@@ -267,6 +947,48 @@ impl<'a> CompilationCtx<'a> {
         self.errors.push(Diagnostic::warning(file, range, message));
     }
 
+    fn info(&mut self, range: Range<usize>, message: impl Into<String>) {
+        let (file, range) = self.source_map.resolve_range(range);
+        self.errors
+            .push(Diagnostic::new(Level::Info, file, range, message));
+    }
+
+    fn resolve_anon_block(&mut self, block: &typed::AnonBlock) {
+        let tag = block.tag();
+        let Some(handler) = self.anon_handlers.and_then(|h| h.get(tag.as_str())) else {
+            self.warning(
+                tag.range(),
+                format!(
+                    "no handler for anonymous block '{}', contents will be ignored",
+                    tag.as_str()
+                ),
+            );
+            return;
+        };
+        match handler.handle(&block.contents()) {
+            Ok(tables) => {
+                for (table_tag, data) in tables {
+                    if self
+                        .tables
+                        .custom
+                        .iter()
+                        .any(|(prev, _)| *prev == table_tag)
+                    {
+                        self.error(
+                            tag.range(),
+                            format!(
+                                "table '{table_tag}' was already produced by an anonymous block"
+                            ),
+                        );
+                    } else {
+                        self.tables.custom.push((table_tag, data));
+                    }
+                }
+            }
+            Err(message) => self.error(tag.range(), message),
+        }
+    }
+
     fn add_language_system(&mut self, language_system: typed::LanguageSystem) {
         let script = language_system.script().to_raw();
         let language = language_system.language().to_raw();
@@ -280,11 +1002,28 @@ impl<'a> CompilationCtx<'a> {
             "no lookup should be active at start of feature"
         );
         let raw_tag = feature_name.to_raw();
+        if self.opts.implicit_default_lang_system
+            && !self
+                .default_lang_systems
+                .contains(&LanguageSystem::default())
+        {
+            self.default_lang_systems.insert_implicit_default();
+        }
+        for script in &self.implicit_scripts {
+            let system = LanguageSystem {
+                script: *script,
+                language: tags::LANG_DFLT,
+            };
+            if !self.default_lang_systems.contains(&system) {
+                self.default_lang_systems.insert_implicit(system);
+            }
+        }
         self.active_feature = Some(ActiveFeature::new(
             raw_tag,
             self.default_lang_systems.clone(),
         ));
         self.vertical_feature.begin_feature(raw_tag);
+        self.script = None;
         self.lookup_flags.clear();
     }
 
@@ -338,20 +1077,18 @@ impl<'a> CompilationCtx<'a> {
             script,
             language,
             stmt.exclude_dflt().is_some(),
-            stmt.required().is_some(),
+            stmt.required().map(Token::range),
         );
     }
 
     fn set_script(&mut self, stmt: typed::Script) {
+        // like feaLib, a script statement always resets the language and the
+        // lookupflag, even if the script has not changed
         let script = stmt.tag().to_raw();
-        if Some(script) == self.script {
-            return;
-        }
-
         self.script = Some(script);
         self.lookup_flags.clear();
 
-        self.set_script_language(script, tags::LANG_DFLT, false, false);
+        self.set_script_language(script, tags::LANG_DFLT, false, None);
     }
 
     fn set_script_language(
@@ -359,7 +1096,8 @@ impl<'a> CompilationCtx<'a> {
         script: Tag,
         language: Tag,
         exclude_dflt: bool,
-        required: bool,
+        // the range of the 'required' keyword, if present
+        required: Option<Range<usize>>,
     ) {
         let system = LanguageSystem { script, language };
         if let Some((id, _name)) = self.lookups.finish_current() {
@@ -371,15 +1109,30 @@ impl<'a> CompilationCtx<'a> {
             .unwrap()
             .set_system(system, exclude_dflt);
 
-        if required {
-            self.required_features.insert(key);
+        if let Some(range) = required {
+            // only one feature can be required for a given language system
+            if let Some(existing) = self.required_features.iter().find(|other| {
+                other.script == key.script
+                    && other.language == key.language
+                    && other.feature != key.feature
+            }) {
+                let message = format!(
+                    "feature '{}' is already required for language system '{}/{}'",
+                    existing.feature, key.script, key.language
+                );
+                self.error(range, message);
+            } else {
+                self.required_features.insert(key);
+            }
         }
     }
 
     fn set_lookup_flag(&mut self, node: typed::LookupFlag) {
         if let Some(number) = node.number() {
-            self.lookup_flags.flags =
-                LookupFlag::from_bits_truncate(number.parse_unsigned().unwrap());
+            // a numeric value replaces everything, including any filter set
+            let flags = LookupFlag::from_bits_truncate(number.parse_unsigned().unwrap());
+            self.record_gdef_class_flags(node.range(), flags);
+            self.lookup_flags = LookupFlagInfo::new(flags, None);
             return;
         }
 
@@ -394,9 +1147,7 @@ impl<'a> CompilationCtx<'a> {
                 Kind::IgnoreLigaturesKw => flags.set_ignore_ligatures(true),
                 Kind::IgnoreMarksKw => flags.set_ignore_marks(true),
 
-                //FIXME: we are not enforcing some requirements here. in particular,
-                // The glyph sets of the referenced classes must not overlap, and the MarkAttachmentType statement can reference at most 15 different classes.
-                // ALSO: this should accept mark classes.
+                //FIXME: this should accept mark classes.
                 Kind::MarkAttachmentTypeKw => {
                     let node = iter
                         .next()
@@ -417,31 +1168,88 @@ impl<'a> CompilationCtx<'a> {
                 other => unreachable!("mark statements have been validated: '{:?}'", other),
             }
         }
+        self.record_gdef_class_flags(node.range(), flags);
         self.lookup_flags = LookupFlagInfo::new(flags, mark_filter_set);
     }
 
+    /// Remember lookup flags that skip glyphs by GDEF class, so that we can
+    /// warn if the font ends up without any classes.
+    fn record_gdef_class_flags(&mut self, range: Range<usize>, flags: LookupFlag) {
+        if flags.ignore_base_glyphs() || flags.ignore_ligatures() || flags.ignore_marks() {
+            self.gdef_class_flags.push((range, flags));
+        }
+    }
+
     fn resolve_mark_attach_class(&mut self, glyphs: &typed::GlyphClass) -> u16 {
+        let range = glyphs.range();
         let glyphs = self.resolve_glyph_class(glyphs);
         let mark_set = glyphs.sort_and_dedupe();
         if let Some(id) = self.mark_attach_class_id.get(&mark_set) {
             return *id;
         }
 
-        let id = self.mark_attach_class_id.len() as u16 + 1;
-        //FIXME: I don't understand what is not allowed here
+        // the mark attachment type is stored in the high byte of the lookup flag
+        let id = self.mark_attach_class_id.len() + 1;
+        if id > u8::MAX as usize {
+            self.error(
+                range,
+                "too many mark attachment classes: at most 255 can be defined",
+            );
+            // compilation fails anyway; this leaves the flag unset
+            return 0;
+        }
+        let id = id as u16;
+        // each glyph can only belong to one class in the class definition
+        let shared = self
+            .mark_attach_class_id
+            .keys()
+            .flat_map(GlyphClass::iter)
+            .find(|gid| mark_set.items().binary_search(gid).is_ok());
+        if let Some(gid) = shared {
+            self.error(
+                range,
+                format!(
+                    "glyph '{}' is already in a different mark attachment class",
+                    self.reverse_glyph_map[&gid]
+                ),
+            );
+        }
 
         self.mark_attach_class_id.insert(mark_set, id);
         id
     }
 
     fn resolve_mark_filter_set(&mut self, glyphs: &typed::GlyphClass) -> u16 {
+        let range = glyphs.range();
         let glyphs = self.resolve_glyph_class(glyphs);
+        match self.add_mark_filter_set(glyphs) {
+            Some(id) => id,
+            None => {
+                self.error(
+                    range,
+                    "too many mark filtering sets: at most 65535 can be defined",
+                );
+                u16::MAX
+            }
+        }
+    }
+
+    /// Return the id of this mark filtering set, adding it if it is new.
+    ///
+    /// Returns `None` if there are already too many sets.
+    fn add_mark_filter_set(&mut self, glyphs: GlyphClass) -> Option<u16> {
         let set = glyphs.sort_and_dedupe();
+        if let Some(id) = self.mark_filter_sets.get(&set) {
+            return Some(*id);
+        }
+        // the number of sets is stored as a u16
         let id = self.mark_filter_sets.len();
-        *self
-            .mark_filter_sets
-            .entry(set)
-            .or_insert_with(|| id.try_into().unwrap())
+        if id >= u16::MAX as usize {
+            return None;
+        }
+        let id = id as u16;
+        self.mark_filter_sets.insert(set, id);
+        Some(id)
     }
 
     pub fn add_subtable_break(&mut self) {
@@ -451,7 +1259,7 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn ensure_current_lookup_type(&mut self, kind: Kind) -> &mut SomeLookup {
-        if self.lookups.needs_new_lookup(kind) {
+        if self.lookups.needs_new_lookup(kind, self.lookup_flags) {
             //FIXME: find another way of ensuring that named lookup blocks don't
             //contain mismatched rules
             //assert!(!self.lookups.is_named(), "ensure rule type in validation");
@@ -470,7 +1278,29 @@ impl<'a> CompilationCtx<'a> {
         }
     }
 
+    /// Record that this statement added rules to the current lookup, as well
+    /// as to any lookups created since `counts` were taken.
+    fn record_lookup_source(&mut self, counts: (usize, usize), range: Range<usize>) {
+        let (gpos, gsub) = self.lookups.lookup_counts();
+        let created = (counts.0..gpos)
+            .map(LookupId::Gpos)
+            .chain((counts.1..gsub).map(LookupId::Gsub));
+        let current = self.lookups.current_id();
+        let ids = current
+            .into_iter()
+            .chain(created.filter(|id| Some(*id) != current))
+            .collect::<Vec<_>>();
+        for id in ids {
+            let sources = self.lookup_sources.entry(id).or_default();
+            if sources.last() != Some(&range) {
+                sources.push(range.clone());
+            }
+        }
+    }
+
     fn add_gpos_statement(&mut self, node: typed::GposStatement) {
+        let counts = self.lookups.lookup_counts();
+        let range = node.range();
         match node {
             typed::GposStatement::Type1(rule) => self.add_single_pos(&rule),
             typed::GposStatement::Type2(rule) => self.add_pair_pos(&rule),
@@ -481,9 +1311,12 @@ impl<'a> CompilationCtx<'a> {
             typed::GposStatement::Type8(rule) => self.add_contextual_pos_rule(&rule),
             typed::GposStatement::Ignore(rule) => self.add_contextual_pos_ignore(&rule),
         }
+        self.record_lookup_source(counts, range);
     }
 
     fn add_gsub_statement(&mut self, node: typed::GsubStatement) {
+        let counts = self.lookups.lookup_counts();
+        let range = node.range();
         match node {
             typed::GsubStatement::Type1(rule) => self.add_single_sub(&rule),
             typed::GsubStatement::Type2(rule) => self.add_multiple_sub(&rule),
@@ -494,6 +1327,7 @@ impl<'a> CompilationCtx<'a> {
             typed::GsubStatement::Type8(rule) => self.add_reverse_contextual_sub(&rule),
             _ => self.warning(node.range(), "unimplemented rule type"),
         }
+        self.record_lookup_source(counts, range);
     }
 
     fn add_single_sub(&mut self, node: &typed::Gsub1) {
@@ -511,8 +1345,25 @@ impl<'a> CompilationCtx<'a> {
                 }
             } else {
                 let lookup = self.ensure_current_lookup_type(Kind::GsubType1);
-                for (target, replacement) in target.iter().zip(replacement.into_iter_for_target()) {
-                    lookup.add_gsub_type_1(target, replacement);
+                let conflicts = target
+                    .iter()
+                    .zip(replacement.into_iter_for_target())
+                    .filter_map(|(target, replacement)| {
+                        lookup
+                            .add_gsub_type_1(target, replacement)
+                            .map(|existing| (target, existing, replacement))
+                    })
+                    .collect::<Vec<_>>();
+                for (target, existing, replacement) in conflicts {
+                    let [target, existing, replacement] =
+                        [target, existing, replacement].map(|gid| &self.reverse_glyph_map[&gid]);
+                    self.warning(
+                        node.range(),
+                        format!(
+                            "'{target}' is already substituted by '{existing}' in this lookup; \
+                             ignoring substitution by '{replacement}'"
+                        ),
+                    );
                 }
             }
         }
@@ -535,11 +1386,20 @@ impl<'a> CompilationCtx<'a> {
         let replace_ids = replace
             .map(|r| self.resolve_glyph_or_class(r))
             .unwrap_or(GlyphOrClass::Null);
+        if self.skip_rule_with_empty_class(target.range(), [&target_ids])
+            || replace.is_some_and(|r| self.skip_rule_with_empty_class(r.range(), [&replace_ids]))
+        {
+            return None;
+        }
         match (target_ids, replace_ids) {
             (GlyphOrClass::Null, _) => {
                 self.error(target.range(), "NULL is not a valid substitution target");
                 None
             }
+            // as in feaLib, a single replacement is used for every target
+            (target @ GlyphOrClass::Class(_), GlyphOrClass::Class(c2)) if c2.len() == 1 => {
+                Some((target, GlyphOrClass::Glyph(c2.items()[0])))
+            }
             (GlyphOrClass::Glyph(_), GlyphOrClass::Class(_)) => {
                 self.error(replace.unwrap().range(), "cannot sub glyph by glyph class");
                 None
@@ -549,8 +1409,8 @@ impl<'a> CompilationCtx<'a> {
                     replace.unwrap().range(),
                     format!(
                         "class has different length ({}) than target ({})",
-                        c1.len(),
-                        c2.len()
+                        c2.len(),
+                        c1.len()
                     ),
                 );
                 None
@@ -559,17 +1419,61 @@ impl<'a> CompilationCtx<'a> {
         }
     }
 
+    /// Add a multiple substitution.
+    ///
+    /// If the target is a class, this adds one rule for each glyph in the class.
+    /// Any classes in the replacement must be the same length as the target,
+    /// and contribute the glyph at the same index.
     fn add_multiple_sub(&mut self, node: &typed::Gsub2) {
-        let target = node.target();
-        let target_id = self.resolve_glyph(&target);
-        let replacement = node.replacement().map(|g| self.resolve_glyph(&g)).collect();
+        let target = self.resolve_glyph_or_class(&node.target());
+        if self.skip_rule_with_empty_class(node.range(), [&target]) {
+            return;
+        }
+        let mut replacement = Vec::new();
+        for item in node.replacement() {
+            let resolved = self.resolve_glyph_or_class(&item);
+            if resolved.is_class() && resolved.len() != target.len() {
+                self.error(
+                    item.range(),
+                    format!(
+                        "class has different length ({}) than target ({})",
+                        resolved.len(),
+                        target.len()
+                    ),
+                );
+                return;
+            }
+            replacement.push(resolved);
+        }
+        self.check_sequence_len(node.range(), replacement.len());
+
+        let targets = target
+            .clone()
+            .into_iter_for_target()
+            .take(target.len())
+            .collect::<Vec<_>>();
+        let mut replacement = replacement
+            .into_iter()
+            .map(GlyphOrClass::into_iter_for_target)
+            .collect::<Vec<_>>();
         let lookup = self.ensure_current_lookup_type(Kind::GsubType2);
-        lookup.add_gsub_type_2(target_id, replacement);
+        for target_id in targets {
+            let sequence = replacement
+                .iter_mut()
+                .map(|glyphs| glyphs.next().unwrap())
+                .collect();
+            lookup.add_gsub_type_2(target_id, sequence);
+        }
     }
 
     fn add_alternate_sub(&mut self, node: &typed::Gsub3) {
         let target = self.resolve_glyph(&node.target());
         let alts = self.resolve_glyph_class(&node.alternates());
+        if alts.is_empty() {
+            self.warn_empty_class(node.range());
+            return;
+        }
+        self.check_sequence_len(node.alternates().range(), alts.len());
         let lookup = self.ensure_current_lookup_type(Kind::GsubType3);
         lookup.add_gsub_type_3(target, alts.iter().collect());
     }
@@ -580,25 +1484,71 @@ impl<'a> CompilationCtx<'a> {
             .map(|g| self.resolve_glyph_or_class(&g))
             .collect::<Vec<_>>();
         let replacement = self.resolve_glyph(&node.replacement());
+        if self.skip_rule_with_empty_class(node.range(), &target) {
+            return;
+        }
+        self.check_sequence_len(node.range(), target.len());
+        let sequences = sequence_enumerator(&target);
         let lookup = self.ensure_current_lookup_type(Kind::GsubType4);
+        let conflicts = sequences
+            .iter()
+            .filter_map(|target| {
+                lookup
+                    .add_gsub_type_4(target.clone(), replacement)
+                    .map(|existing| (target.clone(), existing))
+            })
+            .collect::<Vec<_>>();
 
-        for target in sequence_enumerator(&target) {
-            lookup.add_gsub_type_4(target, replacement);
+        let current_lookup = self.lookups.current_id().expect("we just created it");
+        for target in sequences {
+            self.ligature_rules
+                .entry((current_lookup, target))
+                .or_insert_with(|| node.range());
+        }
+        for (target, existing) in conflicts {
+            let previous = self
+                .ligature_rules
+                .get(&(current_lookup, target.clone()))
+                .cloned();
+            let sequence = target
+                .iter()
+                .map(|gid| self.reverse_glyph_map[gid].to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let [existing, replacement] =
+                [existing, replacement].map(|gid| &self.reverse_glyph_map[&gid]);
+            let message = format!(
+                "'{sequence}' is already substituted by '{existing}' in this lookup; \
+                 ignoring substitution by '{replacement}'"
+            );
+            let previous_message = format!("'{sequence}' is substituted by '{existing}' here");
+            self.warning(node.range(), message);
+            if let Some(previous) = previous {
+                self.info(previous, previous_message);
+            }
         }
     }
 
     fn add_contextual_sub(&mut self, node: &typed::Gsub6) {
         let backtrack = self.resolve_backtrack_sequence(node.backtrack().items());
         let lookahead = self.resolve_lookahead_sequence(node.lookahead().items());
+        let inputs = node
+            .input()
+            .items()
+            .map(|item| self.resolve_glyph_or_class(&item.target()))
+            .collect::<Vec<_>>();
+        if self.skip_rule_with_empty_class(
+            node.range(),
+            backtrack.iter().chain(&inputs).chain(&lookahead),
+        ) {
+            return;
+        }
         // does this have an inline rule?
         let mut inline = node.inline_rule().and_then(|rule| {
             let input = node.input();
             if input.items().nth(1).is_some() {
                 // more than one input: this is a ligature rule
-                let target = input
-                    .items()
-                    .map(|inp| self.resolve_glyph_or_class(&inp.target()))
-                    .collect::<Vec<_>>();
+                let target = inputs.clone();
                 let replacement = self.resolve_glyph(&rule.replacement_glyphs().next().unwrap());
                 let lookup = self.ensure_current_lookup_type(Kind::GsubType6);
                 //FIXME: we should check that the whole sequence is not present the
@@ -633,8 +1583,8 @@ impl<'a> CompilationCtx<'a> {
         let context = node
             .input()
             .items()
-            .map(|item| {
-                let glyphs = self.resolve_glyph_or_class(&item.target());
+            .zip(inputs)
+            .map(|(item, glyphs)| {
                 let mut lookups = Vec::new();
                 // if there's an inline rule it always belongs to the first marked
                 // glyph, so this should work? it may need to change for fancier
@@ -645,11 +1595,14 @@ impl<'a> CompilationCtx<'a> {
 
                 for lookup in item.lookups() {
                     let id = self.lookups.get_named(&lookup.label().text).unwrap(); // validated already
-                    if matches!(id, LookupId::Gpos(_)) {
-                        self.error(
+                    match id {
+                        LookupId::Gpos(_) => self.error(
                             lookup.label().range(),
                             "Invalid lookup: expected GSUB, found GPOS",
-                        );
+                        ),
+                        // a lookup with no rules does nothing
+                        LookupId::Empty => continue,
+                        LookupId::Gsub(_) => (),
                     }
                     lookups.push(id);
                 }
@@ -657,6 +1610,7 @@ impl<'a> CompilationCtx<'a> {
             })
             .collect::<Vec<_>>();
 
+        self.check_context_lens(node.range(), &backtrack, &context, &lookahead);
         let lookup = self.ensure_current_lookup_type(Kind::GsubType6);
         lookup.add_contextual_rule(backtrack, context, lookahead);
     }
@@ -670,6 +1624,9 @@ impl<'a> CompilationCtx<'a> {
     fn add_reverse_contextual_sub(&mut self, node: &typed::Gsub8) {
         let backtrack = self.resolve_backtrack_sequence(node.backtrack().items());
         let lookahead = self.resolve_lookahead_sequence(node.lookahead().items());
+        if self.skip_rule_with_empty_class(node.range(), backtrack.iter().chain(&lookahead)) {
+            return;
+        }
         let input = node.input().items().next().unwrap();
         let target = input.target();
         let replacement = node.inline_rule().and_then(|r| r.replacements().next());
@@ -689,68 +1646,283 @@ impl<'a> CompilationCtx<'a> {
     fn add_single_pos(&mut self, node: &typed::Gpos1) {
         let ids = self.resolve_glyph_or_class(&node.target());
         let record = self.resolve_value_record(&node.value());
+        if self.skip_rule_with_empty_class(node.range(), [&ids]) {
+            return;
+        }
         let lookup = self.ensure_current_lookup_type(Kind::GposType1);
-        for id in ids.iter() {
-            lookup.add_gpos_type_1(id, record.clone());
+        let conflicts = ids
+            .iter()
+            .filter(|id| lookup.add_gpos_type_1(*id, record.clone()).is_some())
+            .collect::<Vec<_>>();
+        for id in conflicts {
+            let name = &self.reverse_glyph_map[&id];
+            self.warning(
+                node.range(),
+                format!(
+                    "'{name}' already has a different position in this lookup; ignoring this rule"
+                ),
+            );
         }
     }
 
     fn add_pair_pos(&mut self, node: &typed::Gpos2) {
         let in_vert_feature = self.vertical_feature.in_eligible_vertical_feature();
+        let rtl_placement = self.opts.rtl_pair_pos_placement
+            && self.lookup_flags.flags.right_to_left()
+            && !in_vert_feature;
 
         let first_ids = self.resolve_glyph_or_class(&node.first_item());
         let second_ids = self.resolve_glyph_or_class(&node.second_item());
-        let first_value = self
-            .resolve_value_record_raw(&node.first_value())
-            .for_pair_pos(in_vert_feature);
+        let mut resolve_value = |record: &typed::ValueRecord| {
+            let value = self
+                .resolve_value_record_raw(record)
+                .for_pair_pos(in_vert_feature);
+            if rtl_placement && record.advance().is_some() {
+                value.with_rtl_placement()
+            } else {
+                value
+            }
+        };
+        let first_value = resolve_value(&node.first_value());
         let second_value = node
             .second_value()
-            .map(|val| self.resolve_value_record_raw(&val))
-            .unwrap_or_default()
-            .for_pair_pos(in_vert_feature);
+            .map(|val| resolve_value(&val))
+            .unwrap_or_default();
+        if self.skip_rule_with_empty_class(node.range(), [&first_ids, &second_ids]) {
+            return;
+        }
 
+        let conflicts = self.opts.pair_pos_conflicts;
         let lookup = self.ensure_current_lookup_type(Kind::GposType2);
 
         if (first_ids.is_class() || second_ids.is_class()) && node.enum_().is_none() {
-            lookup.add_gpos_type_2_class(
-                first_ids.to_class().unwrap(),
-                second_ids.to_class().unwrap(),
-                first_value,
-                second_value,
-            )
+            let first_class = first_ids.to_class().unwrap();
+            let second_class = second_ids.to_class().unwrap();
+            let shadowed = lookup.pair_pos_glyphs_before_break(&first_class);
+            // a glyph pair before a class pair wins in both other modes
+            let conflicting_pairs = if conflicts == PairPosConflicts::Error {
+                lookup.pair_pos_glyph_conflicts(
+                    &first_class,
+                    &second_class,
+                    &(first_value.clone(), second_value.clone()),
+                )
+            } else {
+                Vec::new()
+            };
+            let subtable = lookup.pair_pos_subtable_count();
+            if conflicts != PairPosConflicts::SpecificWins {
+                let current_lookup = self.lookups.current_id().expect("we just created it");
+                let key = (current_lookup, subtable);
+                self.pair_rules
+                    .entry(key)
+                    .or_default()
+                    .classes
+                    .entry((first_class.clone(), second_class.clone()))
+                    .or_insert_with(|| node.range());
+                self.report_class_pair_conflicts(node, key, conflicting_pairs);
+            }
+            let lookup = self.ensure_current_lookup_type(Kind::GposType2);
+            let overflow =
+                lookup.add_gpos_type_2_class(first_class, second_class, first_value, second_value);
+            if overflow {
+                self.error(
+                    node.range(),
+                    "class kerning subtable exceeds the 64KiB offset limit; \
+                    insert a 'subtable;' statement before this rule",
+                );
+            }
+            if !shadowed.is_empty() {
+                let names = shadowed
+                    .iter()
+                    .map(|gid| format!("'{}'", self.reverse_glyph_map[gid]))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                self.warning(
+                    node.first_item().range(),
+                    format!(
+                        "glyphs in a class kerned before the last 'subtable' statement \
+                         will never be kerned by this rule: {names}"
+                    ),
+                );
+            }
         } else {
+            let values = (first_value, second_value);
+            let mut added = Vec::new();
+            let mut conflicting_classes = Vec::new();
             for first in first_ids.iter() {
                 for second in second_ids.iter() {
-                    lookup.add_gpos_type_2_pair(
-                        first,
-                        second,
-                        first_value.clone(),
-                        second_value.clone(),
-                    );
+                    if conflicts != PairPosConflicts::SpecificWins {
+                        if let Some(classes) =
+                            lookup.pair_pos_class_conflict(first, second, &values)
+                        {
+                            conflicting_classes.push(((first, second), classes));
+                            if conflicts == PairPosConflicts::FirstWins {
+                                continue;
+                            }
+                        }
+                    }
+                    lookup.add_gpos_type_2_pair(first, second, values.0.clone(), values.1.clone());
+                    added.push((first, second));
                 }
             }
+            if conflicts != PairPosConflicts::SpecificWins {
+                let subtable = lookup.pair_pos_subtable_count();
+                let current_lookup = self.lookups.current_id().expect("we just created it");
+                let key = (current_lookup, subtable);
+                let sources = self.pair_rules.entry(key).or_default();
+                for pair in added {
+                    sources.pairs.entry(pair).or_insert_with(|| node.range());
+                }
+                self.report_glyph_pair_conflicts(node, key, conflicting_classes);
+            }
+        }
+    }
+
+    /// Report glyph pairs that are positioned differently by an earlier
+    /// class pair rule in the same subtable.
+    fn report_glyph_pair_conflicts(
+        &mut self,
+        node: &typed::Gpos2,
+        key: PairSubtableKey,
+        conflicts: Vec<((GlyphId, GlyphId), (GlyphClass, GlyphClass))>,
+    ) {
+        // group the pairs by the class rule they conflict with
+        let mut by_rule = BTreeMap::<_, Vec<_>>::new();
+        for (pair, classes) in conflicts {
+            let range = self.pair_rules[&key].classes[&classes].clone();
+            by_rule
+                .entry((range.start, range.end))
+                .or_default()
+                .push(self.describe_pair(pair));
+        }
+        for ((start, end), pairs) in by_rule {
+            let pairs = pairs.join(", ");
+            if self.opts.pair_pos_conflicts == PairPosConflicts::Error {
+                self.error(
+                    node.range(),
+                    format!(
+                        "a class pair rule in this subtable gives a different value to {pairs}"
+                    ),
+                );
+            } else {
+                self.warning(
+                    node.range(),
+                    format!(
+                        "a class pair rule earlier in this subtable gives a different value \
+                         to {pairs}; ignoring this rule for these pairs"
+                    ),
+                );
+            }
+            self.info(
+                start..end,
+                format!("the class pair rule for {pairs} is here"),
+            );
+        }
+    }
+
+    /// Report glyph pairs from earlier rules in the same subtable that this
+    /// class pair rule would give a different value.
+    fn report_class_pair_conflicts(
+        &mut self,
+        node: &typed::Gpos2,
+        key: PairSubtableKey,
+        conflicts: Vec<(GlyphId, GlyphId)>,
+    ) {
+        let mut by_rule = BTreeMap::<_, Vec<_>>::new();
+        for pair in conflicts {
+            let range = self.pair_rules[&key].pairs[&pair].clone();
+            by_rule
+                .entry((range.start, range.end))
+                .or_default()
+                .push(self.describe_pair(pair));
+        }
+        for ((start, end), pairs) in by_rule {
+            let pairs = pairs.join(", ");
+            self.error(
+                node.range(),
+                format!("a glyph pair rule in this subtable gives a different value to {pairs}"),
+            );
+            self.info(
+                start..end,
+                format!("the glyph pair rule for {pairs} is here"),
+            );
         }
     }
 
+    fn describe_pair(&self, (first, second): (GlyphId, GlyphId)) -> String {
+        format!(
+            "'{} {}'",
+            self.reverse_glyph_map[&first], self.reverse_glyph_map[&second]
+        )
+    }
+
     fn add_cursive_pos(&mut self, node: &typed::Gpos3) {
         let ids = self.resolve_glyph_or_class(&node.target());
         // if null it means we've already reported an error and compilation
         // will fail.
         let entry = self.resolve_anchor(&node.entry());
         let exit = self.resolve_anchor(&node.exit());
+        if self.skip_rule_with_empty_class(node.range(), [&ids]) {
+            return;
+        }
+        if node.entry().null().is_some() && node.exit().null().is_some() {
+            self.warning(
+                node.range(),
+                "cursive rule with NULL entry and exit anchors has no effect",
+            );
+        }
         let lookup = self.ensure_current_lookup_type(Kind::GposType3);
+        let conflicts = ids
+            .iter()
+            .filter(|id| lookup.add_gpos_type_3(*id, entry.clone(), exit.clone()))
+            .collect::<Vec<_>>();
+        let current_lookup = self.lookups.current_id().expect("we just created it");
+        self.cursive_rules
+            .entry(current_lookup)
+            .or_insert_with(|| node.range());
         for id in ids.iter() {
-            lookup.add_gpos_type_3(id, entry.clone(), exit.clone())
+            self.cursive_glyph_rules
+                .entry((current_lookup, id))
+                .or_insert_with(|| node.range());
+        }
+
+        // group the conflicts by the rule they conflict with
+        let mut previous_rules = BTreeMap::<_, Vec<_>>::new();
+        for id in conflicts {
+            let previous = self.cursive_glyph_rules[&(current_lookup, id)].clone();
+            previous_rules
+                .entry((previous.start, previous.end))
+                .or_default()
+                .push(format!("'{}'", self.reverse_glyph_map[&id]));
+        }
+        for ((start, end), names) in previous_rules {
+            let verb = if names.len() == 1 { "has" } else { "have" };
+            let names = names.join(", ");
+            self.error(
+                node.range(),
+                format!("{names} already {verb} different cursive anchors in this lookup"),
+            );
+            self.info(
+                start..end,
+                format!("the cursive anchors of {names} are set here"),
+            );
         }
     }
 
     fn add_mark_to_base(&mut self, node: &typed::Gpos4) {
         let base_ids = self.resolve_glyph_or_class(&node.base());
+        if self.skip_rule_with_empty_class(node.range(), [&base_ids]) {
+            return;
+        }
+        self.record_class_inference(&base_ids, ClassId::Base, 0, node.range());
         let _ = self.ensure_current_lookup_type(Kind::GposType4);
         for mark in node.attachments() {
             let base_anchor = self.resolve_anchor(&mark.anchor());
 
             let mark_class_node = mark.mark_class_name().expect("checked in validation");
+            if self.skip_empty_mark_class(&mark_class_node) {
+                continue;
+            }
             let class_name = mark_class_node.text().to_owned();
             let mark_class = self.mark_classes.get(&class_name).unwrap();
 
@@ -771,23 +1943,102 @@ impl<'a> CompilationCtx<'a> {
                             subtable.insert_mark(glyph, class_name.clone(), anchor.clone())?;
                         }
                     }
-                    for base in base_ids.iter() {
-                        subtable.insert_base(
-                            base,
-                            &class_name,
-                            base_anchor
-                                .clone()
-                                .expect("no null anchors in mark-to-base"),
-                        )
-                    }
-                    Ok(())
+                    let base_anchor = base_anchor
+                        .clone()
+                        .expect("no null anchors in mark-to-base");
+                    Ok(base_ids
+                        .iter()
+                        .filter(|base| {
+                            subtable
+                                .insert_base(*base, &class_name, base_anchor.clone())
+                                .is_some()
+                        })
+                        .collect::<Vec<_>>())
                 });
-            self.maybe_report_mark_class_conflict(mark_class_node.range(), maybe_err.err())
+            match maybe_err {
+                Ok(conflicts) => {
+                    for base in conflicts {
+                        let name = &self.reverse_glyph_map[&base];
+                        self.warning(
+                            mark.range(),
+                            format!(
+                                "'{name}' already has a different anchor for mark class \
+                                 '{class_name}' in this lookup; ignoring this anchor"
+                            ),
+                        );
+                    }
+                }
+                Err(err) => self.maybe_report_mark_class_conflict(&mark_class_node, Some(err)),
+            }
+        }
+    }
+
+    /// Warn about a rule that uses an empty class, returning `true` if it does.
+    ///
+    /// Classes can be left empty when missing glyphs are dropped, or when the
+    /// glyphs of a font are subset. A rule that uses one can never apply, so
+    /// it is compiled as a no-op instead of producing empty subtables.
+    fn skip_rule_with_empty_class<'b>(
+        &mut self,
+        range: Range<usize>,
+        items: impl IntoIterator<Item = &'b GlyphOrClass>,
+    ) -> bool {
+        let has_empty = items
+            .into_iter()
+            .any(|item| item.is_class() && item.len() == 0);
+        if has_empty {
+            self.warn_empty_class(range);
+        }
+        has_empty
+    }
+
+    fn warn_empty_class(&mut self, range: Range<usize>) {
+        self.warning(
+            range,
+            "this rule has no effect, because it uses an empty glyph class",
+        );
+    }
+
+    /// Warn about an attachment to a mark class with no glyphs, returning
+    /// `true` if the class is empty.
+    fn skip_empty_mark_class(&mut self, mark_class_node: &typed::GlyphClassName) -> bool {
+        let is_empty = self
+            .mark_classes
+            .get(mark_class_node.text())
+            .is_some_and(|class| class.members.iter().all(|(glyphs, _)| glyphs.is_empty()));
+        if is_empty {
+            self.warning(
+                mark_class_node.range(),
+                format!(
+                    "mark class '{}' has no glyphs; ignoring this anchor",
+                    mark_class_node.text()
+                ),
+            );
+        }
+        is_empty
+    }
+
+    fn record_class_inference(
+        &mut self,
+        glyphs: &GlyphOrClass,
+        class: ClassId,
+        n_components: usize,
+        range: Range<usize>,
+    ) {
+        for glyph in glyphs.iter() {
+            self.class_inference_rules.entry(glyph).or_default().push((
+                class,
+                n_components,
+                range.clone(),
+            ));
         }
     }
 
     fn add_mark_to_lig(&mut self, node: &typed::Gpos5) {
         let base_ids = self.resolve_glyph_or_class(&node.base());
+        if self.skip_rule_with_empty_class(node.range(), [&base_ids]) {
+            return;
+        }
         // okay so:
         // for each lig glyph in the input, we create a lig array table.
         // for each component in each lig glyph, we add a component record
@@ -812,6 +2063,9 @@ impl<'a> CompilationCtx<'a> {
                         continue;
                     }
                 };
+                if self.skip_empty_mark_class(&mark_class_node) {
+                    continue;
+                }
                 let component_anchor = component_anchor.unwrap();
                 let class_name = mark_class_node.text();
                 let mark_class = self.mark_classes.get(class_name).unwrap();
@@ -836,19 +2090,36 @@ impl<'a> CompilationCtx<'a> {
                         }
                         Ok(())
                     });
-                self.maybe_report_mark_class_conflict(mark_class_node.range(), maybe_err.err());
+                self.maybe_report_mark_class_conflict(&mark_class_node, maybe_err.err());
             }
             components.push(anchor_records);
         }
+        self.record_class_inference(&base_ids, ClassId::Ligature, components.len(), node.range());
 
-        self.lookups
+        let conflicts = self
+            .lookups
             .current_mut()
             .unwrap()
             .with_gpos_type_5(|subtable| {
-                for base in base_ids.iter() {
-                    subtable.add_lig(base, components.clone());
-                }
-            })
+                base_ids
+                    .iter()
+                    .filter_map(|base| {
+                        subtable
+                            .add_lig(base, components.clone())
+                            .map(|n_components| (base, n_components))
+                    })
+                    .collect::<Vec<_>>()
+            });
+        for (base, n_components) in conflicts {
+            let name = &self.reverse_glyph_map[&base];
+            self.warning(
+                node.range(),
+                format!(
+                    "'{name}' already has {n_components} components in this lookup; \
+                     ignoring this rule"
+                ),
+            );
+        }
     }
 
     //FIXME: this is basically identical to type 4, but the validation stuff
@@ -856,10 +2127,17 @@ impl<'a> CompilationCtx<'a> {
     //significantly.
     fn add_mark_to_mark(&mut self, node: &typed::Gpos6) {
         let base_ids = self.resolve_glyph_or_class(&node.base());
+        if self.skip_rule_with_empty_class(node.range(), [&base_ids]) {
+            return;
+        }
+        self.record_class_inference(&base_ids, ClassId::Mark, 0, node.range());
         let _ = self.ensure_current_lookup_type(Kind::GposType6);
         for mark in node.attachments() {
             let base_anchor = self.resolve_anchor(&mark.anchor());
             let mark_class_node = mark.mark_class_name().expect("checked in validation");
+            if self.skip_empty_mark_class(&mark_class_node) {
+                continue;
+            }
             let class_name = mark_class_node.text();
             let mark_class = self.mark_classes.get(mark_class_node.text()).unwrap();
 
@@ -889,19 +2167,30 @@ impl<'a> CompilationCtx<'a> {
                     }
                     Ok(())
                 });
-            self.maybe_report_mark_class_conflict(mark_class_node.range(), maybe_err.err())
+            self.maybe_report_mark_class_conflict(&mark_class_node, maybe_err.err())
         }
     }
 
+    // if we know where the glyph was added to this mark class, point at it
     fn maybe_report_mark_class_conflict(
         &mut self,
-        range: Range<usize>,
+        mark_class_node: &typed::GlyphClassName,
         maybe_err: Option<PreviouslyAssignedClass>,
     ) {
-        if let Some(PreviouslyAssignedClass { class, .. }) = maybe_err {
+        if let Some(PreviouslyAssignedClass { glyph_id, class }) = maybe_err {
+            let this_class = mark_class_node.text();
+            let range = self
+                .mark_classes
+                .get(this_class)
+                .and_then(|cls| cls.glyph_spans.get(&glyph_id).cloned())
+                .unwrap_or_else(|| mark_class_node.range());
+            let name = &self.reverse_glyph_map[&glyph_id];
             self.error(
                 range,
-                format!("mark class includes glyph in class '{class}', already used in lookup.",),
+                format!(
+                    "'{name}' in mark class '{this_class}' is also in mark class '{class}', \
+                     which is already used in this lookup"
+                ),
             );
         };
     }
@@ -909,11 +2198,22 @@ impl<'a> CompilationCtx<'a> {
     fn add_contextual_pos_rule(&mut self, node: &typed::Gpos8) {
         let backtrack = self.resolve_backtrack_sequence(node.backtrack().items());
         let lookahead = self.resolve_lookahead_sequence(node.lookahead().items());
+        let inputs = node
+            .input()
+            .items()
+            .map(|item| self.resolve_glyph_or_class(&item.target()))
+            .collect::<Vec<_>>();
+        if self.skip_rule_with_empty_class(
+            node.range(),
+            backtrack.iter().chain(&inputs).chain(&lookahead),
+        ) {
+            return;
+        }
         let context = node
             .input()
             .items()
-            .map(|item| {
-                let glyphs = self.resolve_glyph_or_class(&item.target());
+            .zip(inputs)
+            .map(|(item, glyphs)| {
                 let mut lookups = Vec::new();
                 if let Some(value) = item.valuerecord() {
                     let value = self.resolve_value_record(&value);
@@ -926,18 +2226,21 @@ impl<'a> CompilationCtx<'a> {
 
                 for lookup in item.lookups() {
                     let id = self.lookups.get_named(&lookup.label().text).unwrap();
-                    if matches!(id, LookupId::Gsub(_)) {
-                        self.error(
+                    match id {
+                        LookupId::Gsub(_) => self.error(
                             lookup.label().range(),
                             "Invalid lookup type: expected GPOS, found GSUB",
-                        );
+                        ),
+                        LookupId::Empty => continue,
+                        LookupId::Gpos(_) => (),
                     }
                     lookups.push(id);
                 }
 
                 (glyphs, lookups)
             })
-            .collect();
+            .collect::<Vec<_>>();
+        self.check_context_lens(node.range(), &backtrack, &context, &lookahead);
         self.ensure_current_lookup_type(Kind::GposType8)
             .add_contextual_rule(backtrack, context, lookahead);
     }
@@ -955,7 +2258,15 @@ impl<'a> CompilationCtx<'a> {
             .input()
             .items()
             .map(|item| (self.resolve_glyph_or_class(&item.target()), Vec::new()))
-            .collect();
+            .collect::<Vec<_>>();
+        let inputs = context.iter().map(|(glyphs, _)| glyphs);
+        if self.skip_rule_with_empty_class(
+            rule.range(),
+            backtrack.iter().chain(inputs).chain(&lookahead),
+        ) {
+            return;
+        }
+        self.check_context_lens(rule.range(), &backtrack, &context, &lookahead);
         let lookup = self.ensure_current_lookup_type(kind);
         lookup.add_contextual_rule(backtrack, context, lookahead);
     }
@@ -1016,6 +2327,11 @@ impl<'a> CompilationCtx<'a> {
 
     fn define_glyph_class(&mut self, class_decl: typed::GlyphClassDef) {
         let name = class_decl.class_name();
+        // with forward references, each class is defined once, but it may
+        // already have been resolved by an earlier reference
+        if self.opts.forward_class_references && self.glyph_class_defs.contains_key(name.text()) {
+            return;
+        }
         let glyphs = if let Some(class) = class_decl.class_def() {
             self.resolve_glyph_class_literal(&class)
         } else if let Some(alias) = class_decl.class_alias() {
@@ -1028,16 +2344,36 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn define_mark_class(&mut self, class_decl: typed::MarkClassDef) {
-        let class_items = class_decl.glyph_class();
-        let class_items = self.resolve_glyph_or_class(&class_items).into();
+        let mut builder = GlyphClassBuilder::with_spans();
+        match class_decl.glyph_class() {
+            typed::GlyphOrClass::Glyph(name)
+                if self.drop_missing_glyphs() && !self.glyph_map.contains(name.text()) => {}
+            typed::GlyphOrClass::Cid(cid)
+                if self.drop_missing_glyphs() && !self.glyph_map.contains(&cid.parse()) => {}
+            typed::GlyphOrClass::Glyph(name) => {
+                builder.insert(self.resolve_glyph_name(&name), name.range());
+            }
+            typed::GlyphOrClass::Cid(cid) => {
+                builder.insert(self.resolve_cid(&cid), cid.range());
+            }
+            typed::GlyphOrClass::Class(class) => self.add_glyph_class_literal(&class, &mut builder),
+            typed::GlyphOrClass::NamedClass(name) => {
+                let glyphs = self.resolve_named_glyph_class(&name);
+                builder.extend(glyphs.iter(), name.range());
+            }
+            typed::GlyphOrClass::Null(_) => (),
+        }
 
         let anchor = self.resolve_anchor(&class_decl.anchor());
         let class_name = class_decl.mark_class_name();
-        self.mark_classes
+        let mark_class = self
+            .mark_classes
             .entry(class_name.text().clone())
-            .or_default()
-            .members
-            .push((class_items, anchor));
+            .or_default();
+        for (glyph, span) in builder.spans() {
+            mark_class.glyph_spans.entry(glyph).or_insert(span);
+        }
+        mark_class.members.push((builder.build(), anchor));
     }
 
     fn add_feature(&mut self, feature: typed::Feature) {
@@ -1063,8 +2399,13 @@ impl<'a> CompilationCtx<'a> {
     fn resolve_aalt_feature(&mut self, feature: &typed::Feature) {
         let mut aalt = AaltFeature::default();
         for item in feature.statements() {
+            if self.references_missing_glyph(item) {
+                continue;
+            }
             if let Some(node) = typed::Gsub1::cast(item) {
-                let Some((target, replacement)) = self.resolve_single_sub_glyphs(&node) else { continue };
+                let Some((target, replacement)) = self.resolve_single_sub_glyphs(&node) else {
+                    continue;
+                };
                 aalt.extend(target.iter().zip(replacement.into_iter_for_target()))
             } else if let Some(node) = typed::Gsub3::cast(item) {
                 let target = self.resolve_glyph(&node.target());
@@ -1179,6 +2520,43 @@ impl<'a> CompilationCtx<'a> {
         self.size = Some(size);
     }
 
+    /// Build the FeatureParams of a feature other than 'size', with the
+    /// handler registered for its tag.
+    fn resolve_feature_params(&mut self, params: &typed::Parameters) {
+        let tag = self
+            .active_feature
+            .as_ref()
+            .expect("only in features")
+            .tag();
+        let range = params.range();
+        let Some(handler) = self.feature_params_handlers.and_then(|h| h.get(&tag)) else {
+            self.error(
+                range,
+                format!("no FeatureParams handler for feature '{tag}'"),
+            );
+            return;
+        };
+        if self.tables.stylistic_sets.contains_key(&tag)
+            || self.tables.character_variants.contains_key(&tag)
+        {
+            self.error(
+                range,
+                format!("feature '{tag}' already has FeatureParams from its names"),
+            );
+            return;
+        }
+        let values = params
+            .values()
+            .map(|value| value.parse() as f64)
+            .collect::<Vec<_>>();
+        match handler.handle(&values) {
+            Ok(built) => {
+                self.feature_params.insert(tag, built);
+            }
+            Err(message) => self.error(range, message),
+        }
+    }
+
     fn resolve_table(&mut self, table: typed::Table) {
         match table {
             typed::Table::Base(table) => self.resolve_base(&table),
@@ -1243,12 +2621,12 @@ impl<'a> CompilationCtx<'a> {
             match item {
                 typed::Os2TableItem::Number(val) => {
                     let value = val.number().parse_unsigned().unwrap();
-                    match val.keyword().text.as_str() {
-                        "WeightClass" => os2.us_weight_class = value,
-                        "WidthClass" => os2.us_width_class = value,
-                        "LowerOpSize" => os2.us_lower_optical_point_size = Some(value),
-                        "UpperOpSize" => os2.us_upper_optical_point_size = Some(value),
-                        "FSType" => os2.fs_type = value,
+                    match val.keyword().kind {
+                        Kind::WeightClassKw => os2.us_weight_class = value,
+                        Kind::WidthClassKw => os2.us_width_class = value,
+                        Kind::LowerOpSizeKw => os2.us_lower_optical_point_size = Some(value),
+                        Kind::UpperOpSizeKw => os2.us_upper_optical_point_size = Some(value),
+                        Kind::FsTypeKw => os2.fs_type = value,
                         _ => unreachable!("checked at parse time"),
                     }
                 }
@@ -1425,6 +2803,14 @@ impl<'a> CompilationCtx<'a> {
     fn resolve_vmtx(&mut self, table: &typed::VmtxTable) {
         let mut vmtx = super::tables::VmtxBuilder::default();
         for item in table.statements() {
+            if self.drop_missing_glyphs()
+                && item
+                    .iter()
+                    .filter_map(NodeOrToken::as_token)
+                    .any(|token| self.is_missing_glyph(token))
+            {
+                continue;
+            }
             let glyph = self.resolve_glyph(&item.glyph());
             let value = item.value().parse_signed();
             match item.keyword().kind {
@@ -1440,6 +2826,9 @@ impl<'a> CompilationCtx<'a> {
         let mut gdef = super::tables::GdefBuilder::default();
         for statement in table.statements() {
             match statement {
+                typed::GdefTableItem::Attach(rule) if self.is_missing_target(&rule.target()) => {}
+                typed::GdefTableItem::LigatureCaret(rule)
+                    if self.is_missing_target(&rule.target()) => {}
                 typed::GdefTableItem::Attach(rule) => {
                     let glyphs = self.resolve_glyph_or_class(&rule.target());
                     let indices = rule
@@ -1477,6 +2866,8 @@ impl<'a> CompilationCtx<'a> {
                             .entry(glyph)
                             .or_insert_with(|| carets.clone());
                     }
+                    self.ligature_carets
+                        .push((glyphs, carets.len(), rule.range()));
                 }
 
                 typed::GdefTableItem::ClassDef(rule) => {
@@ -1528,7 +2919,10 @@ impl<'a> CompilationCtx<'a> {
             platform_id,
             encoding_id,
             language_id,
-            string: node.string().text.clone(),
+            string: node
+                .decoded_string(platform_id)
+                .expect("checked in validation pass")
+                .into(),
         }
     }
 
@@ -1537,9 +2931,34 @@ impl<'a> CompilationCtx<'a> {
             .lookups
             .get_named(&lookup.label().text)
             .expect("checked in validation pass");
+        self.check_lookup_ref_flags(&lookup);
         self.add_lookup_to_current_feature_if_present(id);
     }
 
+    /// Warn if a lookup is referenced where a different lookupflag is active.
+    ///
+    /// A lookup always uses the flags it was defined with, and the flags of
+    /// the feature that references it are ignored.
+    fn check_lookup_ref_flags(&mut self, lookup: &typed::LookupRef) {
+        if self.active_feature.is_none() || self.lookup_flags == LookupFlagInfo::default() {
+            return;
+        }
+        let name = lookup.label();
+        let Some(flags) = self.lookups.named_flags(&name.text) else {
+            return;
+        };
+        if flags != self.lookup_flags {
+            self.warning(
+                name.range(),
+                format!(
+                    "lookup '{}' uses the lookupflag it was defined with, \
+                     not the lookupflag that is active here",
+                    name.text
+                ),
+            );
+        }
+    }
+
     fn resolve_lookup_block(&mut self, lookup: typed::LookupBlock) {
         self.start_lookup_block(lookup.tag());
 
@@ -1567,10 +2986,20 @@ impl<'a> CompilationCtx<'a> {
             self.resolve_lookup_ref(lookup);
         } else if let Some(lookup) = typed::LookupBlock::cast(item) {
             self.resolve_lookup_block(lookup);
+        } else if let Some(params) = typed::Parameters::cast(item) {
+            self.resolve_feature_params(&params);
+        } else if self.references_missing_glyph(item) {
+            // already reported during validation
         } else if let Some(rule) = typed::GsubStatement::cast(item) {
+            let counts = self.lookups.lookup_counts();
+            let range = rule.range();
             self.add_gsub_statement(rule);
+            self.check_lookup_counts(counts, range);
         } else if let Some(rule) = typed::GposStatement::cast(item) {
-            self.add_gpos_statement(rule)
+            let counts = self.lookups.lookup_counts();
+            let range = rule.range();
+            self.add_gpos_statement(rule);
+            self.check_lookup_counts(counts, range);
         } else {
             let span = match item {
                 NodeOrToken::Token(t) => t.range(),
@@ -1584,6 +3013,94 @@ impl<'a> CompilationCtx<'a> {
         }
     }
 
+    /// `true` if this is a rule that should be ignored because it references
+    /// a glyph that is not in the font.
+    ///
+    /// This only happens if missing glyphs are dropped; otherwise they are
+    /// either an error, or compiled as a placeholder.
+    fn references_missing_glyph(&self, item: &NodeOrToken) -> bool {
+        if !self.drop_missing_glyphs()
+            || (typed::GsubStatement::cast(item).is_none()
+                && typed::GposStatement::cast(item).is_none())
+        {
+            return false;
+        }
+        item.as_node()
+            .is_some_and(|node| node.iter_tokens().any(|token| self.is_missing_glyph(token)))
+    }
+
+    /// `true` if this is a single glyph that is not in the font, and should be
+    /// dropped.
+    ///
+    /// Missing members of classes are already dropped when the class is
+    /// resolved, so only a bare glyph needs to be checked.
+    fn is_missing_target(&self, item: &typed::GlyphOrClass) -> bool {
+        if !self.drop_missing_glyphs() {
+            return false;
+        }
+        match item {
+            typed::GlyphOrClass::Glyph(name) => self.is_missing_glyph(name.token()),
+            typed::GlyphOrClass::Cid(cid) => self.is_missing_glyph(cid.token()),
+            _ => false,
+        }
+    }
+
+    fn drop_missing_glyphs(&self) -> bool {
+        self.opts.missing_glyphs == MissingGlyphs::Drop
+    }
+
+    /// `true` if this is a glyph name or CID token that is not in the font.
+    fn is_missing_glyph(&self, token: &Token) -> bool {
+        match token.kind {
+            Kind::GlyphName => !self.glyph_map.contains(token.as_str()),
+            Kind::Cid => token
+                .as_str()
+                .parse::<u16>()
+                .is_ok_and(|cid| !self.glyph_map.contains(&cid)),
+            _ => false,
+        }
+    }
+
+    /// Report an error if the rule at `range` pushed the number of lookups
+    /// past the limit of the 16-bit lookup count.
+    fn check_lookup_counts(&mut self, before: (usize, usize), range: Range<usize>) {
+        const MAX_LOOKUPS: usize = u16::MAX as usize;
+        let (gpos, gsub) = self.lookups.lookup_counts();
+        for (table, before, after) in [("GPOS", before.0, gpos), ("GSUB", before.1, gsub)] {
+            if before <= MAX_LOOKUPS && after > MAX_LOOKUPS {
+                self.error(
+                    range.clone(),
+                    format!("this rule exceeds the limit of {MAX_LOOKUPS} {table} lookups"),
+                );
+            }
+        }
+    }
+
+    /// Report an error if a sequence is too long to have its length encoded
+    /// as a 16-bit count.
+    fn check_sequence_len(&mut self, range: Range<usize>, len: usize) {
+        if len > u16::MAX as usize {
+            self.error(
+                range,
+                format!("sequence of {len} glyphs exceeds the limit of {}", u16::MAX),
+            );
+        }
+    }
+
+    fn check_context_lens(
+        &mut self,
+        range: Range<usize>,
+        backtrack: &[GlyphOrClass],
+        input: &[(GlyphOrClass, Vec<LookupId>)],
+        lookahead: &[GlyphOrClass],
+    ) {
+        for len in [backtrack.len(), input.len(), lookahead.len()] {
+            self.check_sequence_len(range.clone(), len);
+        }
+        let n_lookups = input.iter().map(|(_, lookups)| lookups.len()).sum();
+        self.check_sequence_len(range, n_lookups);
+    }
+
     fn define_named_anchor(&mut self, anchor_def: typed::AnchorDef) {
         let anchor_block = anchor_def.anchor();
         let name = anchor_def.name();
@@ -1606,7 +3123,11 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn resolve_anchor(&mut self, item: &typed::Anchor) -> Option<AnchorTable> {
-        if let Some((x, y)) = item.coords().map(|(x, y)| (x.parse(), y.parse())) {
+        if let Some((x, y)) = item.coords() {
+            let (typed::AnchorMetric::Scalar(x), typed::AnchorMetric::Scalar(y)) = (&x, &y) else {
+                return self.resolve_variable_anchor(&x, &y);
+            };
+            let (x, y) = (x.parse(), y.parse());
             if let Some(point) = item.contourpoint() {
                 match point.parse_unsigned() {
                     Some(point) => return Some(AnchorTable::format_2(x, y, point)),
@@ -1636,6 +3157,39 @@ impl<'a> CompilationCtx<'a> {
         panic!("bad anchor {:?} go check your parser", item);
     }
 
+    /// Resolve an anchor where either coordinate is variable.
+    ///
+    /// Variable anchors cannot have a contour point or device tables; this is
+    /// checked during validation.
+    fn resolve_variable_anchor(
+        &mut self,
+        x: &typed::AnchorMetric,
+        y: &typed::AnchorMetric,
+    ) -> Option<AnchorTable> {
+        let (x, x_var) = self.resolve_anchor_metric(x);
+        let (y, y_var) = self.resolve_anchor_metric(y);
+        if x_var.is_none() && y_var.is_none() {
+            return Some(AnchorTable::format_1(x, y));
+        }
+        Some(AnchorTable::format_3(x, y, x_var, y_var))
+    }
+
+    /// The default value of a metric, and its VariationIndex table if it varies.
+    fn resolve_anchor_metric(&mut self, item: &typed::AnchorMetric) -> (i16, Option<Device>) {
+        let metric = match item {
+            typed::AnchorMetric::Scalar(metric) => return (metric.parse(), None),
+            typed::AnchorMetric::Variable(metric) => metric,
+        };
+        let locations = metric.locations().expect("checked by parser");
+        let masters =
+            variations::parse_variable_metric(locations.as_str()).expect("already validated");
+        // on error, carry on with a placeholder; the error fails compilation
+        self.variations.add_metric(&masters).unwrap_or_else(|err| {
+            self.error(metric.range(), err);
+            (0, None)
+        })
+    }
+
     fn resolve_glyph_or_class(&mut self, item: &typed::GlyphOrClass) -> GlyphOrClass {
         match item {
             typed::GlyphOrClass::Glyph(name) => GlyphOrClass::Glyph(self.resolve_glyph_name(name)),
@@ -1666,8 +3220,23 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn resolve_glyph_class_literal(&mut self, class: &typed::GlyphClassLiteral) -> GlyphClass {
-        let mut glyphs = Vec::new();
+        let mut builder = GlyphClassBuilder::default();
+        self.add_glyph_class_literal(class, &mut builder);
+        builder.build()
+    }
+
+    fn add_glyph_class_literal(
+        &mut self,
+        class: &typed::GlyphClassLiteral,
+        builder: &mut GlyphClassBuilder,
+    ) {
         for item in class.items() {
+            if self.drop_missing_glyphs()
+                && item.as_token().is_some_and(|t| self.is_missing_glyph(t))
+            {
+                continue;
+            }
+            let mut glyphs = Vec::new();
             if let Some(id) =
                 typed::GlyphName::cast(item).map(|name| self.resolve_glyph_name(&name))
             {
@@ -1681,11 +3250,38 @@ impl<'a> CompilationCtx<'a> {
             } else {
                 panic!("unexptected kind in class literal: '{}'", item.kind());
             }
+            self.add_glyphs_to_class(builder, glyphs, item.range());
+        }
+    }
+
+    fn add_glyphs_to_class(
+        &mut self,
+        builder: &mut GlyphClassBuilder,
+        glyphs: impl IntoIterator<Item = GlyphId>,
+        range: Range<usize>,
+    ) {
+        // placeholders for missing glyphs are all .notdef, so don't count
+        let duplicates = builder
+            .extend(glyphs, range.clone())
+            .into_iter()
+            .filter(|gid| *gid != GlyphId::NOTDEF)
+            .map(|gid| format!("'{}'", self.reverse_glyph_map[&gid]))
+            .collect::<Vec<_>>();
+        if !duplicates.is_empty() {
+            self.warning(
+                range,
+                format!("class contains duplicate glyphs: {}", duplicates.join(", ")),
+            );
         }
-        glyphs.into()
     }
 
     fn resolve_named_glyph_class(&mut self, name: &typed::GlyphClassName) -> GlyphClass {
+        // validation ensures that forward references do not form a cycle
+        if !self.glyph_class_defs.contains_key(name.text()) {
+            if let Some(class_decl) = self.forward_class_defs.get(name.text()).cloned() {
+                self.define_glyph_class(class_decl);
+            }
+        }
         self.glyph_class_defs
             .get(name.text())
             .cloned()
@@ -1700,8 +3296,10 @@ impl<'a> CompilationCtx<'a> {
             .unwrap()
     }
 
+    // missing glyphs only get past validation if the user has opted in; any
+    // that have not already been dropped are compiled as .notdef.
     fn resolve_glyph_name(&mut self, name: &typed::GlyphName) -> GlyphId {
-        self.glyph_map.get(name.text()).unwrap()
+        self.glyph_map.get(name.text()).unwrap_or(GlyphId::NOTDEF)
     }
 
     fn resolve_lookahead_sequence(
@@ -1721,51 +3319,78 @@ impl<'a> CompilationCtx<'a> {
     }
 
     fn resolve_cid(&mut self, cid: &typed::Cid) -> GlyphId {
-        self.glyph_map.get(&cid.parse()).unwrap()
+        self.glyph_map.get(&cid.parse()).unwrap_or(GlyphId::NOTDEF)
     }
 
     fn add_glyphs_from_range(&mut self, range: &typed::GlyphRange, out: &mut Vec<GlyphId>) {
         let start = range.start();
         let end = range.end();
+        let glyph_map = self.glyph_map;
+        let placeholder = self.opts.missing_glyphs == MissingGlyphs::Placeholder;
+        let mut missing = Vec::new();
+        let mut add_member = |id: Option<GlyphId>, member: &dyn std::fmt::Display| match id {
+            Some(id) => out.push(id),
+            None => {
+                if placeholder {
+                    out.push(GlyphId::NOTDEF);
+                }
+                missing.push(member.to_string());
+            }
+        };
 
-        match (start.kind, end.kind) {
+        let result = match (start.kind, end.kind) {
             (Kind::Cid, Kind::Cid) => {
-                if let Err(err) = glyph_range::cid(start, end, |cid| {
-                    match self.glyph_map.get(&cid) {
-                        Some(id) => out.push(id),
-                        None => {
-                            // this is techincally allowed, but we error for now
-                            self.error(
-                                range.range(),
-                                format!("Range member '{}' does not exist in font", cid),
-                            );
-                        }
-                    }
-                }) {
-                    self.error(range.range(), err);
-                }
+                glyph_range::cid(start, end, |cid| add_member(glyph_map.get(&cid), &cid))
             }
             (Kind::GlyphName, Kind::GlyphName) => {
-                if let Err(err) = glyph_range::named(start, end, |name| {
-                    match self.glyph_map.get(name) {
-                        Some(id) => out.push(id),
-                        None => {
-                            // this is techincally allowed, but we error for now
-                            self.error(
-                                range.range(),
-                                format!("Range member '{}' does not exist in font", name),
-                            );
-                        }
-                    }
-                }) {
-                    self.error(range.range(), err);
-                }
+                glyph_range::named(start, end, |name| add_member(glyph_map.get(name), &name))
             }
-            (_, _) => self.error(range.range(), "Invalid types in glyph range"),
+            (_, _) => Err("Invalid types in glyph range".to_string()),
+        };
+        match result {
+            Err(err) => self.error(range.range(), err),
+            // this is techincally allowed, but we error for now; with the
+            // other options, this was already reported during validation
+            Ok(()) if !missing.is_empty() && self.opts.missing_glyphs == MissingGlyphs::Error => {
+                self.error(range.range(), glyph_range::missing_members(&missing))
+            }
+            Ok(()) => (),
+        }
+    }
+}
+
+// feature and lookup blocks are the unit of reuse between compilations
+fn is_block(item: &NodeOrToken) -> bool {
+    matches!(item.kind(), Kind::FeatureNode | Kind::LookupBlockNode)
+}
+
+/// Collect the first definition of each glyph class, including those in
+/// feature and lookup blocks.
+fn collect_class_defs<'b>(
+    items: impl Iterator<Item = &'b NodeOrToken>,
+    defs: &mut HashMap<SmolStr, typed::GlyphClassDef>,
+) {
+    for item in items {
+        if let Some(class_decl) = typed::GlyphClassDef::cast(item) {
+            defs.entry(class_decl.class_name().text().clone())
+                .or_insert(class_decl);
+        } else if is_block(item) {
+            collect_class_defs(item.as_node().unwrap().iter_children(), defs);
         }
     }
 }
 
+/// A description of a top-level block, for logging.
+fn describe_block(item: &NodeOrToken) -> String {
+    if let Some(feature) = typed::Feature::cast(item) {
+        format!("feature '{}'", feature.tag().text())
+    } else if let Some(lookup) = typed::LookupBlock::cast(item) {
+        format!("lookup '{}'", lookup.label().text)
+    } else {
+        item.kind().to_string()
+    }
+}
+
 fn sequence_enumerator(sequence: &[GlyphOrClass]) -> Vec<Vec<GlyphId>> {
     assert!(sequence.len() >= 2);
     let split = sequence.split_first();
@@ -1826,4 +3451,555 @@ mod tests {
             ]
         );
     }
+
+    fn compile_diagnostics(fea: &'static str) -> Vec<Diagnostic> {
+        compile_diagnostics_with_opts(fea, Opts::new())
+    }
+
+    fn compile_diagnostics_with_opts(fea: &'static str, opts: Opts) -> Vec<Diagnostic> {
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let tree = crate::util::ttx::parse_str(fea, &glyph_map);
+        assert!(
+            crate::compile::validation_diagnostics(&tree, &glyph_map, &Default::default())
+                .is_empty()
+        );
+        let mut ctx = CompilationCtx::new(&glyph_map, tree.source_map(), opts);
+        ctx.compile(&tree.typed_root());
+        ctx.errors
+    }
+
+    #[test]
+    fn pedantic_fealib_divergences() {
+        let fea = "
+markClass acute <anchor 0 500> @TOP;
+feature liga {
+    sub f i by f_i;
+    sub f l by f_l;
+} liga;
+feature mark {
+    pos base f_i <anchor 200 500> mark @TOP;
+} mark;
+lookup KERN { pos b 5; } KERN;
+feature kern {
+    pos a b' lookup KERN c;
+} kern;
+feature dist {
+    pos a' lookup KERN b' lookup KERN;
+} dist;
+";
+        assert!(compile_diagnostics(fea).is_empty());
+        let errs = compile_diagnostics_with_opts(fea, Opts::new().pedantic(true));
+        let messages = errs
+            .iter()
+            .map(|err| {
+                assert_eq!(err.level, Level::Warning);
+                err.text()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "contextual positioning lookup is compiled as chained contextual \
+                 positioning (GPOS type 8) instead of GPOS type 7, to match feaLib",
+                "ligature glyphs are not given the ligature class in the inferred \
+                 GDEF glyph classes, to match feaLib: 'f_l'",
+            ]
+        );
+    }
+
+    #[test]
+    fn lookup_ref_flag_mismatch() {
+        let errs = compile_diagnostics(
+            "
+lookup NOFLAGS {
+    sub a by b;
+} NOFLAGS;
+lookup MARKS {
+    lookupflag IgnoreMarks;
+    sub c by d;
+} MARKS;
+feature liga {
+    lookup NOFLAGS;
+    lookupflag IgnoreMarks;
+    lookup MARKS;
+    lookup NOFLAGS;
+    lookupflag RightToLeft;
+    lookup MARKS;
+} liga;
+",
+        );
+        let messages = errs
+            .iter()
+            .map(Diagnostic::text)
+            .filter(|text| text.starts_with("lookup '"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "lookup 'NOFLAGS' uses the lookupflag it was defined with, \
+                 not the lookupflag that is active here",
+                "lookup 'MARKS' uses the lookupflag it was defined with, \
+                 not the lookupflag that is active here",
+            ]
+        );
+    }
+
+    #[test]
+    fn pair_pos_conflicts() {
+        use crate::compile::PairPosConflicts;
+
+        let fea = "
+feature kern {
+    pos a c -10;
+    pos [a b] [c d] -20;
+    pos b d -20;
+    enum pos b [c e] -30;
+} kern;
+";
+        let diagnostics = |mode| {
+            compile_diagnostics_with_opts(fea, Opts::new().pair_pos_conflicts(mode))
+                .into_iter()
+                .map(|err| (err.level, err.text().to_owned(), err.span().start))
+                .collect::<Vec<_>>()
+        };
+        let glyph_rule = fea.find("pos a c").unwrap();
+        let class_rule = fea.find("pos [a b]").unwrap();
+        let enum_rule = fea.find("enum pos").unwrap();
+
+        assert!(diagnostics(PairPosConflicts::SpecificWins).is_empty());
+        // 'a c' comes first, so wins anyway; 'b d' has the same value
+        assert_eq!(
+            diagnostics(PairPosConflicts::FirstWins),
+            [
+                (
+                    Level::Warning,
+                    "a class pair rule earlier in this subtable gives a different value \
+                     to 'b c'; ignoring this rule for these pairs"
+                        .to_owned(),
+                    enum_rule
+                ),
+                (
+                    Level::Info,
+                    "the class pair rule for 'b c' is here".to_owned(),
+                    class_rule
+                ),
+            ]
+        );
+        assert_eq!(
+            diagnostics(PairPosConflicts::Error),
+            [
+                (
+                    Level::Error,
+                    "a glyph pair rule in this subtable gives a different value to 'a c'"
+                        .to_owned(),
+                    class_rule
+                ),
+                (
+                    Level::Info,
+                    "the glyph pair rule for 'a c' is here".to_owned(),
+                    glyph_rule
+                ),
+                (
+                    Level::Error,
+                    "a class pair rule in this subtable gives a different value to 'b c'"
+                        .to_owned(),
+                    enum_rule
+                ),
+                (
+                    Level::Info,
+                    "the class pair rule for 'b c' is here".to_owned(),
+                    class_rule
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn class_kerning_after_subtable_break() {
+        let errs = compile_diagnostics(
+            "
+feature kern {
+    pos [a b c] [x y] 10;
+    subtable;
+    pos [b c d] [x z] 20;
+    pos [e f] [x] 30;
+} kern;
+",
+        );
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(errs[0].level, Level::Warning);
+        assert_eq!(
+            errs[0].text(),
+            "glyphs in a class kerned before the last 'subtable' statement \
+             will never be kerned by this rule: 'b', 'c'"
+        );
+    }
+
+    #[test]
+    fn duplicate_glyphs_in_class() {
+        let errs = compile_diagnostics(
+            "
+@lc = [a b c];
+@all = [@lc b d a-c];
+feature kern {
+    pos [x y x] 10;
+} kern;
+",
+        );
+        let messages = errs
+            .iter()
+            .map(|err| {
+                assert_eq!(err.level, Level::Warning);
+                err.text()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "class contains duplicate glyphs: 'b'",
+                "class contains duplicate glyphs: 'a', 'b', 'c'",
+                "class contains duplicate glyphs: 'x'",
+            ]
+        );
+    }
+
+    #[test]
+    fn mark_class_conflict_points_at_glyph() {
+        let fea = "
+markClass [acute grave] <anchor 0 500> @TOP;
+markClass [cedilla acute] <anchor 0 0> @BOTTOM;
+feature mark {
+    pos base a <anchor 250 450> mark @TOP <anchor 250 0> mark @BOTTOM;
+} mark;
+";
+        let errs = compile_diagnostics(fea);
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert!(errs[0].is_error());
+        assert_eq!(
+            errs[0].text(),
+            "'acute' in mark class '@BOTTOM' is also in mark class '@TOP', \
+             which is already used in this lookup"
+        );
+        let start = fea.find("cedilla acute").unwrap() + "cedilla ".len();
+        assert_eq!(errs[0].span(), start..start + "acute".len());
+    }
+
+    #[test]
+    fn mark_to_base_anchor_redefined() {
+        let errs = compile_diagnostics(
+            "
+markClass acute <anchor 0 500> @TOP;
+markClass cedilla <anchor 0 0> @BOTTOM;
+feature mark {
+    pos base [a b] <anchor 250 450> mark @TOP;
+    pos base a <anchor 250 0> mark @BOTTOM;
+    pos base b <anchor 250 450> mark @TOP;
+    pos base a <anchor 300 450> mark @TOP;
+} mark;
+",
+        );
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(errs[0].level, Level::Warning);
+        assert_eq!(
+            errs[0].text(),
+            "'a' already has a different anchor for mark class '@TOP' in this lookup; \
+             ignoring this anchor"
+        );
+    }
+
+    #[test]
+    fn cursive_lookups_that_attach_nothing() {
+        let errs = compile_diagnostics(
+            "
+feature curs {
+    pos cursive [a b] <anchor NULL> <anchor 500 0>;
+    pos cursive c <anchor NULL> <anchor NULL>;
+} curs;
+feature kern {
+    pos cursive d <anchor 0 0> <anchor NULL>;
+    pos cursive e <anchor 0 0> <anchor 500 0>;
+} kern;
+",
+        );
+        let messages = errs.iter().map(|err| err.text()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "cursive rule with NULL entry and exit anchors has no effect",
+                "no glyph in this cursive lookup has an entry anchor, so no glyphs will be attached",
+            ]
+        );
+        assert!(errs.iter().all(|err| err.level == Level::Warning));
+    }
+
+    #[test]
+    fn cursive_anchor_conflicts() {
+        let fea = "
+@medials = [a b c];
+feature curs {
+    pos cursive @medials <anchor 0 0> <anchor 500 0>;
+    pos cursive b <anchor 0 0> <anchor 500 0>;
+    pos cursive [c d e] <anchor 0 0> <anchor 400 0>;
+    subtable;
+    pos cursive [a e] <anchor NULL> <anchor 500 0>;
+} curs;
+";
+        let errs = compile_diagnostics(fea);
+        let messages = errs
+            .iter()
+            .map(|err| (err.level, err.text(), &fea[err.span()]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                (
+                    Level::Error,
+                    "'c' already has different cursive anchors in this lookup",
+                    "pos cursive [c d e] <anchor 0 0> <anchor 400 0>;"
+                ),
+                (
+                    Level::Info,
+                    "the cursive anchors of 'c' are set here",
+                    "pos cursive @medials <anchor 0 0> <anchor 500 0>;"
+                ),
+                (
+                    Level::Error,
+                    "'a' already has different cursive anchors in this lookup",
+                    "pos cursive [a e] <anchor NULL> <anchor 500 0>;"
+                ),
+                (
+                    Level::Info,
+                    "the cursive anchors of 'a' are set here",
+                    "pos cursive @medials <anchor 0 0> <anchor 500 0>;"
+                ),
+                (
+                    Level::Error,
+                    "'e' already has different cursive anchors in this lookup",
+                    "pos cursive [a e] <anchor NULL> <anchor 500 0>;"
+                ),
+                (
+                    Level::Info,
+                    "the cursive anchors of 'e' are set here",
+                    "pos cursive [c d e] <anchor 0 0> <anchor 400 0>;"
+                ),
+            ]
+        );
+
+        // different anchors in another lookup are fine
+        let errs = compile_diagnostics(
+            "
+feature curs { pos cursive [a b] <anchor 0 0> <anchor 500 0>; } curs;
+feature kern { pos cursive a <anchor 0 0> <anchor 400 0>; } kern;
+",
+        );
+        assert!(errs.is_empty(), "{errs:?}");
+    }
+
+    #[test]
+    fn mark_to_lig_component_count_conflict() {
+        let fea = "
+markClass acute <anchor 0 500> @TOP;
+markClass cedilla <anchor 0 0> @BOTTOM;
+feature mark {
+    pos ligature f_i <anchor 100 500> mark @TOP ligComponent <anchor 300 500> mark @TOP;
+    pos ligature f_i <anchor 100 0> mark @BOTTOM ligComponent <anchor 300 0> mark @BOTTOM;
+    pos ligature [f_i f_l] <anchor 100 0> mark @BOTTOM;
+} mark;
+";
+        let errs = compile_diagnostics(fea);
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(errs[0].level, Level::Warning);
+        assert_eq!(
+            errs[0].text(),
+            "'f_i' already has 2 components in this lookup; ignoring this rule"
+        );
+        assert_eq!(
+            &fea[errs[0].span()],
+            "pos ligature [f_i f_l] <anchor 100 0> mark @BOTTOM;"
+        );
+    }
+
+    #[test]
+    fn gdef_class_flags_without_classes() {
+        let fea = "
+lookup ONE {
+    lookupflag IgnoreMarks;
+    sub a by b;
+} ONE;
+lookup TWO {
+    lookupflag RightToLeft IgnoreBaseGlyphs IgnoreLigatures;
+    sub a by c;
+} TWO;
+lookup THREE {
+    lookupflag 14;
+    sub b by c;
+} THREE;
+lookup FOUR {
+    lookupflag RightToLeft;
+    sub c by d;
+} FOUR;
+";
+        let errs = compile_diagnostics(fea);
+        let messages = errs
+            .iter()
+            .map(|err| {
+                assert_eq!(err.level, Level::Warning);
+                err.text()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "IgnoreMarks has no effect, because the font has no GDEF glyph classes",
+                "IgnoreBaseGlyphs and IgnoreLigatures have no effect, because the font \
+                 has no GDEF glyph classes",
+                "IgnoreBaseGlyphs and IgnoreLigatures and IgnoreMarks have no effect, \
+                 because the font has no GDEF glyph classes",
+            ]
+        );
+        assert_eq!(&fea[errs[0].span()], "lookupflag IgnoreMarks;");
+        assert_eq!(&fea[errs[2].span()], "lookupflag 14;");
+    }
+
+    #[test]
+    fn gdef_class_flags_with_classes() {
+        let explicit = "
+table GDEF { GlyphClassDef [a], , [acute], ; } GDEF;
+feature liga { lookupflag IgnoreMarks; sub f i by f_i; } liga;
+";
+        assert!(compile_diagnostics(explicit).is_empty());
+
+        let inferred = "
+markClass acute <anchor 0 500> @TOP;
+feature mark { pos base a <anchor 250 450> mark @TOP; } mark;
+feature liga { lookupflag IgnoreMarks; sub f i by f_i; } liga;
+";
+        assert!(compile_diagnostics(inferred).is_empty());
+        // if classes are not inferred, there are none
+        let errs = compile_diagnostics_with_opts(inferred, Opts::new().infer_glyph_classes(false));
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(
+            errs[0].text(),
+            "IgnoreMarks has no effect, because the font has no GDEF glyph classes"
+        );
+    }
+
+    #[test]
+    fn ligature_caret_on_explicit_non_ligature() {
+        let errs = compile_diagnostics(
+            "
+table GDEF {
+    GlyphClassDef [a], [f_i], , ;
+    LigatureCaretByPos f_i 300;
+    LigatureCaretByPos a 200;
+} GDEF;
+",
+        );
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(errs[0].level, Level::Warning);
+        assert_eq!(
+            errs[0].text(),
+            "'a' has ligature carets, but is in the GDEF Base class"
+        );
+    }
+
+    #[test]
+    fn ligature_caret_on_inferred_base() {
+        let errs = compile_diagnostics(
+            "
+markClass acute <anchor 0 500> @TOP;
+table GDEF { LigatureCaretByPos f_i 300; } GDEF;
+feature mark { pos base f_i <anchor 250 450> mark @TOP; } mark;
+",
+        );
+        assert_eq!(errs.len(), 2, "{errs:?}");
+        assert_eq!(
+            errs[0].text(),
+            "'f_i' has ligature carets, but is inferred to be a Base glyph"
+        );
+        assert_eq!(errs[1].level, Level::Info);
+        assert_eq!(errs[1].text(), "'f_i' is inferred to be a Base glyph here");
+        assert!(errs[0].span().start < errs[1].span().start);
+    }
+
+    #[test]
+    fn ligature_caret_component_count_mismatch() {
+        let fea = "
+markClass acute <anchor 0 500> @TOP;
+table GDEF { LigatureCaretByIndex f_f_i 3; } GDEF;
+feature mark {
+    pos ligature f_f_i <anchor 100 500> mark @TOP
+        ligComponent <anchor 300 500> mark @TOP
+        ligComponent <anchor 500 500> mark @TOP;
+} mark;
+";
+        let errs = compile_diagnostics(fea);
+        assert_eq!(errs.len(), 2, "{errs:?}");
+        assert_eq!(
+            errs[0].text(),
+            "'f_f_i' has 1 ligature carets, but 3 components in a mark-to-ligature rule"
+        );
+        assert_eq!(errs[1].text(), "'f_f_i' has 3 components here");
+    }
+
+    #[test]
+    fn ligature_caret_matching_mark_to_lig() {
+        let errs = compile_diagnostics(
+            "
+markClass acute <anchor 0 500> @TOP;
+table GDEF { LigatureCaretByPos f_i 300; } GDEF;
+feature mark {
+    pos ligature f_i <anchor 100 500> mark @TOP ligComponent <anchor 300 500> mark @TOP;
+} mark;
+",
+        );
+        assert!(errs.is_empty(), "{errs:?}");
+    }
+
+    #[test]
+    fn conflicting_single_sub() {
+        let errs = compile_diagnostics(
+            "feature test { sub a by b; sub a by b; sub [a c] by [d e]; } test;",
+        );
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(errs[0].level, Level::Warning);
+        assert_eq!(
+            errs[0].text(),
+            "'a' is already substituted by 'b' in this lookup; ignoring substitution by 'd'"
+        );
+    }
+
+    #[test]
+    fn conflicting_ligature_sub() {
+        let fea = "feature test { sub f i by f_i; sub f i by f_i; sub [f g] i by f_f_i; } test;";
+        let errs = compile_diagnostics(fea);
+        assert_eq!(errs.len(), 2, "{errs:?}");
+        assert_eq!(errs[0].level, Level::Warning);
+        assert_eq!(
+            errs[0].text(),
+            "'f i' is already substituted by 'f_i' in this lookup; ignoring substitution by 'f_f_i'"
+        );
+        assert_eq!(&fea[errs[0].span()], "sub [f g] i by f_f_i;");
+        assert_eq!(errs[1].level, Level::Info);
+        assert_eq!(&fea[errs[1].span()], "sub f i by f_i;");
+    }
+
+    #[test]
+    fn conflicting_single_pos() {
+        let errs =
+            compile_diagnostics("feature test { pos a 10; pos a 10; pos [a b] <0 0 20 0>; } test;");
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(
+            errs[0].text(),
+            "'a' already has a different position in this lookup; ignoring this rule"
+        );
+    }
+
+    #[test]
+    fn single_rule_conflicts_are_per_subtable() {
+        let errs = compile_diagnostics(
+            "feature test { sub a by b; subtable; sub a by d; pos a 10; subtable; pos a 20; } test;",
+        );
+        assert!(errs.is_empty(), "{errs:?}");
+    }
 }
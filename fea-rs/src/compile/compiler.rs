@@ -1,10 +1,15 @@
 //! The main public API for compilation
 
 use std::{
+    collections::HashMap,
     ffi::OsString,
     path::{Path, PathBuf},
+    time::Instant,
 };
 
+use smol_str::SmolStr;
+use write_fonts::types::Tag;
+
 use crate::{
     parse::{FileSystemResolver, SourceResolver},
     Diagnostic, GlyphMap, ParseTree,
@@ -12,7 +17,8 @@ use crate::{
 
 use super::{
     error::{CompilerError, DiagnosticSet},
-    Compilation, Opts,
+    transform, AnonBlockHandler, Compilation, FeatureParamsHandler, Kerning, MarkAnchors, Opts,
+    TreeTransform,
 };
 
 /// A builder-style entry point for the compiler.
@@ -31,10 +37,17 @@ use super::{
 pub struct Compiler<'a> {
     root_path: OsString,
     project_root: Option<PathBuf>,
+    restrict_to_project_root: bool,
+    max_include_depth: usize,
     glyph_map: &'a GlyphMap,
     verbose: bool,
     opts: Opts,
     resolver: Option<Box<dyn SourceResolver>>,
+    anon_handlers: HashMap<SmolStr, Box<dyn AnonBlockHandler>>,
+    feature_params_handlers: HashMap<Tag, Box<dyn FeatureParamsHandler>>,
+    transforms: Vec<Box<dyn TreeTransform>>,
+    kerning: Option<Kerning>,
+    mark_anchors: Option<MarkAnchors>,
 }
 
 impl<'a> Compiler<'a> {
@@ -54,6 +67,13 @@ impl<'a> Compiler<'a> {
             verbose: false,
             resolver: Default::default(),
             project_root: Default::default(),
+            restrict_to_project_root: false,
+            max_include_depth: crate::parse::DEFAULT_MAX_INCLUDE_DEPTH,
+            anon_handlers: Default::default(),
+            feature_params_handlers: Default::default(),
+            transforms: Default::default(),
+            kerning: None,
+            mark_anchors: None,
         }
     }
 
@@ -63,6 +83,63 @@ impl<'a> Compiler<'a> {
         self
     }
 
+    /// Register a handler for `anonymous` blocks with the given tag.
+    ///
+    /// The handler receives the raw contents of each block with this tag, and
+    /// may return additional tables to include in the font. Blocks without a
+    /// registered handler are ignored, with a warning.
+    pub fn with_anon_handler(
+        mut self,
+        tag: impl Into<SmolStr>,
+        handler: impl AnonBlockHandler + 'static,
+    ) -> Self {
+        self.anon_handlers.insert(tag.into(), Box::new(handler));
+        self
+    }
+
+    /// Register a handler for the `parameters` statement of the given feature.
+    ///
+    /// The handler receives the values of the feature's `parameters`
+    /// statement, and returns the FeatureParams table to attach to it. A
+    /// `parameters` statement in a feature without a registered handler
+    /// (other than `size`) is an error.
+    pub fn with_feature_params_handler(
+        mut self,
+        feature: Tag,
+        handler: impl FeatureParamsHandler + 'static,
+    ) -> Self {
+        self.feature_params_handlers
+            .insert(feature, Box::new(handler));
+        self
+    }
+
+    /// Register a transform to run on the parse tree before validation.
+    ///
+    /// Transforms run in the order they are registered. See [`TreeTransform`]
+    /// for more information.
+    pub fn with_transform(mut self, transform: impl TreeTransform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Provide kerning to compile into the `kern` feature.
+    ///
+    /// This is compiled alongside the feature file, without generating FEA
+    /// for it; see [`Kerning`] for more information.
+    pub fn with_kerning(mut self, kerning: Kerning) -> Self {
+        self.kerning = Some(kerning);
+        self
+    }
+
+    /// Provide glyph anchors to compile into the `mark` and `mkmk` features.
+    ///
+    /// This is compiled alongside the feature file, without generating FEA
+    /// for it; see [`MarkAnchors`] for more information.
+    pub fn with_mark_anchors(mut self, anchors: MarkAnchors) -> Self {
+        self.mark_anchors = Some(anchors);
+        self
+    }
+
     /// Specify verbosity.
     ///
     /// When verbose is true, we will print all warnings.
@@ -80,6 +157,27 @@ impl<'a> Compiler<'a> {
         self
     }
 
+    /// Refuse to include files outside of the project root.
+    ///
+    /// This is useful when compiling untrusted sources. It has no effect if
+    /// a custom resolver is provided with [`with_resolver`].
+    ///
+    /// [`with_resolver`]: Self::with_resolver
+    pub fn restrict_includes_to_project_root(mut self, flag: bool) -> Self {
+        self.restrict_to_project_root = flag;
+        self
+    }
+
+    /// Specify the maximum depth of nested include statements.
+    ///
+    /// A file included by the root file has a depth of 1. Include statements
+    /// beyond this depth are an error. The default is 5, the limit given in the
+    /// spec.
+    pub fn max_include_depth(mut self, depth: usize) -> Self {
+        self.max_include_depth = depth;
+        self
+    }
+
     /// Specify additional compiler options.
     pub fn with_opts(mut self, opts: Opts) -> Self {
         self.opts = opts;
@@ -101,25 +199,47 @@ impl<'a> Compiler<'a> {
                     .map(PathBuf::from)
                     .unwrap_or_default()
             });
-            Box::new(FileSystemResolver::new(project_root))
+            Box::new(
+                FileSystemResolver::new(project_root)
+                    .restrict_to_project_root(self.restrict_to_project_root),
+            )
         });
 
-        let (tree, diagnostics) =
+        let start = Instant::now();
+        let (mut tree, diagnostics) =
             crate::parse::ParseContext::parse(self.root_path, Some(self.glyph_map), resolver)?
+                .with_max_include_depth(self.max_include_depth)
                 .generate_parse_tree();
+        log::debug!("parsed in {:?}", start.elapsed());
         print_warnings_return_errors(diagnostics, &tree, self.verbose)
             .map_err(CompilerError::ParseFail)?;
-        let diagnostics = super::validate(&tree, self.glyph_map);
+        for (i, item) in self.transforms.iter().enumerate() {
+            let start = Instant::now();
+            let name = format!("<transform {}>", i + 1);
+            tree = transform::apply_transform(item.as_ref(), tree, &name)?;
+            log::debug!("ran transform {:?} in {:?}", item, start.elapsed());
+        }
+        let start = Instant::now();
+        let diagnostics = super::validation_diagnostics(&tree, self.glyph_map, &self.opts);
+        log::debug!("validated in {:?}", start.elapsed());
         print_warnings_return_errors(diagnostics, &tree, self.verbose)
             .map_err(CompilerError::ValidationFail)?;
-        let mut ctx = super::CompilationCtx::new(self.glyph_map, tree.source_map());
+        let start = Instant::now();
+        let mut ctx = super::CompilationCtx::new(self.glyph_map, tree.source_map(), self.opts)
+            .with_anon_handlers(&self.anon_handlers)
+            .with_feature_params_handlers(&self.feature_params_handlers)
+            .with_kerning(self.kerning.as_ref())
+            .with_mark_anchors(self.mark_anchors.as_ref());
         ctx.compile(&tree.typed_root());
+        log::debug!("compiled in {:?}", start.elapsed());
 
         // we 'take' the errors here because it's easier for us to handle the
         // warnings using our helper method.
         print_warnings_return_errors(std::mem::take(&mut ctx.errors), &tree, self.verbose)
             .map_err(CompilerError::CompilationFail)?;
-        Ok(ctx.build().unwrap()) // we've taken the errors, so this can't fail
+        let mut compilation = ctx.build().unwrap(); // we've taken the errors, so this can't fail
+        compilation.sources = tree.sources.sorted_paths();
+        Ok(compilation)
     }
 
     /// Compile to a binary font.
@@ -1,14 +1,17 @@
 //! Error types related to compilation
 
-use std::sync::Arc;
+use std::{ops::Range, path::PathBuf, sync::Arc};
 
-use write_fonts::{read::ReadError, validate::ValidationReport};
+use write_fonts::{read::ReadError, types::Tag, validate::ValidationReport};
 
 use crate::{
-    parse::{SourceList, SourceLoadError},
-    Diagnostic,
+    parse::{LineCol, SourceList, SourceLoadError},
+    Diagnostic, ParseTree,
 };
 
+#[cfg(feature = "shape-check")]
+use crate::GlyphName;
+
 /// An error that occurs when extracting a glyph order from a UFO.
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum UfoGlyphOrderError {
@@ -47,9 +50,23 @@ pub enum GlyphOrderError {
     MissingNotDef,
 }
 
+/// An error that occurs when creating a [`VariationAxis`] with invalid values.
+///
+/// [`VariationAxis`]: super::VariationAxis
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("invalid range for axis '{tag}': expected finite values with min <= default <= max, found {min}:{default}:{max}")]
+#[allow(missing_docs)]
+pub struct InvalidAxisError {
+    pub tag: Tag,
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+}
+
 /// An error reported by the compiler
 #[derive(Debug, thiserror::Error)]
 #[allow(missing_docs)]
+#[non_exhaustive]
 pub enum CompilerError {
     #[error("{0}")]
     SourceLoad(
@@ -63,14 +80,118 @@ pub enum CompilerError {
     ValidationFail(DiagnosticSet),
     #[error("Compilation failed with {} errors\n{0}", .0.messages.len())]
     CompilationFail(DiagnosticSet),
+    #[error("Transform failed with {} errors\n{0}", .0.messages.len())]
+    TransformFail(DiagnosticSet),
     #[error("Binary generation failed: '{0}'")]
     WriteFail(#[from] BinaryCompilationError),
 }
 
+/// An error that occurs when compiling a [`FeaProject`].
+///
+/// [`FeaProject`]: super::FeaProject
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+#[non_exhaustive]
+pub enum ProjectError {
+    #[cfg(feature = "manifest")]
+    #[error("Failed to load manifest '{}': {source}", .path.display())]
+    Manifest {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Target '{0}' has no glyph source")]
+    MissingGlyphSource(String),
+    #[error("Failed to load glyphs from '{}': {source}", .path.display())]
+    GlyphSource {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Target '{target}' failed: {source}")]
+    Target {
+        target: String,
+        #[source]
+        source: CompilerError,
+    },
+}
+
 /// An error that occured when generating the binary font
 #[derive(Debug, thiserror::Error)]
-#[error("Binary generation failed: '{0}'")]
-pub struct BinaryCompilationError(ValidationReport);
+pub enum BinaryCompilationError {
+    /// A table failed validation before being written
+    #[error("Binary generation failed: '{0}'")]
+    Validation(ValidationReport),
+    /// The compilation could not be turned into tables
+    #[error("Binary generation failed: {0}")]
+    Compile(
+        #[from]
+        #[source]
+        CompileError,
+    ),
+}
+
+/// An error that occurs when building the final tables from a [`Compilation`].
+///
+/// These are limits of the OpenType format that could not be checked while
+/// compiling individual statements.
+///
+/// [`Compilation`]: super::Compilation
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum CompileError {
+    /// A lookup list is longer than a 16-bit index can address
+    #[error("{table} has {count} lookups, but at most 65535 are allowed")]
+    #[allow(missing_docs)]
+    TooManyLookups { table: Tag, count: usize },
+    /// A feature list is longer than a 16-bit index can address
+    #[error("{table} has {count} features, but at most 65535 are allowed")]
+    #[allow(missing_docs)]
+    TooManyFeatures { table: Tag, count: usize },
+    /// A contextual lookup references a lookup from the other table
+    #[error("a contextual lookup in {table} references a lookup in another table")]
+    #[allow(missing_docs)]
+    LookupTableMismatch { table: Tag },
+    /// A class-based pair positioning rule uses a device table
+    ///
+    /// The records for pairs of classes without a rule would need null
+    /// device offsets, which cannot currently be written.
+    #[error(
+        "GPOS lookup {lookup} uses device tables in a class pair rule, which is not supported"
+    )]
+    #[allow(missing_docs)]
+    ClassPairDevice { lookup: usize },
+}
+
+/// An error that occurs when running shaping tests against a compilation.
+#[cfg(feature = "shape-check")]
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum ShapeCheckError {
+    #[error("{0}")]
+    WriteFail(#[from] BinaryCompilationError),
+    /// A test refers to a glyph that is not in the glyph map
+    #[error("{test}: unknown glyph '{name}'")]
+    UnknownGlyph { test: String, name: GlyphName },
+    /// A test contains a feature setting that harfbuzz does not understand
+    #[error("{test}: invalid feature setting '{feature}'")]
+    InvalidFeature { test: String, feature: String },
+}
+
+/// An error that occurs when parsing a list of shaping tests.
+#[cfg(feature = "shape-check")]
+#[derive(Clone, Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum ShapeTestParseError {
+    /// A test is missing the '->' separating input and expected output
+    #[error("line {line}: expected 'input -> expected output'")]
+    MissingArrow { line: usize },
+    /// An option other than 'features', 'script' or 'language'
+    #[error("line {line}: unknown option '{option}'")]
+    UnknownOption { line: usize, option: String },
+    /// A script or language tag was not a valid tag
+    #[error("line {line}: invalid tag '{tag}'")]
+    InvalidTag { line: usize, tag: String },
+}
 
 /// A set of diagnostics with the associated source info
 #[derive(Clone)]
@@ -79,6 +200,55 @@ pub struct DiagnosticSet {
     pub(crate) sources: Arc<SourceList>,
 }
 
+impl DiagnosticSet {
+    /// Separate errors from warnings.
+    ///
+    /// If there are no errors, this returns the warnings; otherwise it returns
+    /// the errors, along with the sources needed to display them.
+    pub(crate) fn split(
+        mut diagnostics: Vec<Diagnostic>,
+        tree: &ParseTree,
+    ) -> Result<Vec<Diagnostic>, DiagnosticSet> {
+        diagnostics.sort_unstable_by_key(|diag| diag.level);
+        let split_at = diagnostics
+            .iter()
+            .position(|x| !x.is_error())
+            .unwrap_or(diagnostics.len());
+        let warnings = diagnostics.split_off(split_at);
+        if diagnostics.is_empty() {
+            Ok(warnings)
+        } else {
+            Err(DiagnosticSet {
+                messages: diagnostics,
+                sources: tree.sources.clone(),
+            })
+        }
+    }
+
+    /// The diagnostics in this set.
+    pub fn messages(&self) -> &[Diagnostic] {
+        &self.messages
+    }
+
+    /// The start and end of a diagnostic's span, as lines and columns in
+    /// its source.
+    pub fn line_cols(&self, err: &Diagnostic) -> Option<Range<LineCol>> {
+        self.sources.line_cols(err)
+    }
+
+    /// Render every diagnostic in this set as an annotated snippet.
+    ///
+    /// See [`ParseTree::render_diagnostic`].
+    #[cfg(feature = "snippets")]
+    pub fn render(&self, styled: bool) -> String {
+        self.messages
+            .iter()
+            .map(|err| self.sources.render_diagnostic(err, styled))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
 impl std::fmt::Display for DiagnosticSet {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let mut first = true;
@@ -104,7 +274,7 @@ impl std::fmt::Debug for DiagnosticSet {
 
 impl From<ValidationReport> for BinaryCompilationError {
     fn from(src: ValidationReport) -> BinaryCompilationError {
-        BinaryCompilationError(src)
+        BinaryCompilationError::Validation(src)
     }
 }
 
@@ -0,0 +1,53 @@
+//! Support for custom FeatureParams.
+//!
+//! The spec only defines the contents of the `parameters` statement for the
+//! `size` feature, but the OpenType feature table can carry a FeatureParams
+//! table for any feature. A `parameters` statement in any other feature is
+//! passed to a handler registered for that feature's tag, which decides what
+//! FeatureParams table (if any) it describes.
+
+use write_fonts::tables::layout::FeatureParams;
+
+/// A trait for compiling the `parameters` statement of a feature.
+///
+/// A handler is registered for a specific feature tag with
+/// [`Compiler::with_feature_params_handler`][crate::Compiler::with_feature_params_handler].
+/// The `size` feature is always compiled by the compiler itself, and does not
+/// use a handler.
+///
+/// This trait is implemented for closures with the signature of [`handle`],
+/// so in most cases you will not need to implement it yourself.
+///
+/// [`handle`]: FeatureParamsHandler::handle
+pub trait FeatureParamsHandler {
+    /// Build the FeatureParams table for the values of a `parameters` statement.
+    ///
+    /// The table is attached to every feature record with this tag, in both
+    /// GSUB and GPOS; a feature that has no lookups is not compiled, and so
+    /// has no params.
+    ///
+    /// On failure, the returned message is reported as an error at the
+    /// statement.
+    fn handle(&self, values: &[f64]) -> Result<FeatureParams, String>;
+
+    // a little helper used in our debug impl
+    #[doc(hidden)]
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+impl<F> FeatureParamsHandler for F
+where
+    F: Fn(&[f64]) -> Result<FeatureParams, String>,
+{
+    fn handle(&self, values: &[f64]) -> Result<FeatureParams, String> {
+        (self)(values)
+    }
+}
+
+impl std::fmt::Debug for dyn FeatureParamsHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.type_name().fmt(f)
+    }
+}
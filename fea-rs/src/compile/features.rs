@@ -21,6 +21,8 @@ pub(crate) struct ActiveFeature {
     current_lang_sys: Option<LanguageSystem>,
     lookups: HashMap<LanguageSystem, Vec<LookupId>>,
     script_default_lookups: HashMap<Tag, Vec<LookupId>>,
+    // scripts whose default language has been explicitly set in this feature
+    seen_scripts: HashSet<Tag>,
 }
 
 /// State required to generate the aalt feature.
@@ -64,12 +66,17 @@ impl ActiveFeature {
         ActiveFeature {
             tag,
             script_default_lookups: Default::default(),
+            seen_scripts: Default::default(),
             lookups: Default::default(),
             current_lang_sys: Default::default(),
             default_systems,
         }
     }
 
+    pub(crate) fn tag(&self) -> Tag {
+        self.tag
+    }
+
     /// Change the active language system.
     ///
     /// This method is called when encountering 'script' and 'language' statements
@@ -99,7 +106,7 @@ impl ActiveFeature {
                 // seen a script keyword, add the default lookups
                 if self.default_systems.contains(&system)
                     || (self.default_systems.contains(&script_dflt)
-                        && self.script_default_lookups.contains_key(&system.script))
+                        && self.seen_scripts.contains(&system.script))
                 {
                     lookups.extend(
                         self.lookups
@@ -116,6 +123,8 @@ impl ActiveFeature {
                 );
             }
             self.lookups.entry(system).or_insert_with(|| lookups);
+        } else {
+            self.seen_scripts.insert(system.script);
         }
 
         self.current_lang_sys = Some(system);
@@ -245,15 +254,8 @@ impl Extend<(GlyphId, GlyphId)> for AaltFeature {
 }
 
 impl SpecialVerticalFeatureState {
-    const VERTICAL_FEATURES: &[Tag] = &[
-        Tag::new(b"valt"),
-        Tag::new(b"vhal"),
-        Tag::new(b"vkrn"),
-        Tag::new(b"vpal"),
-    ];
-
     pub(crate) fn begin_feature(&mut self, tag: Tag) {
-        if Self::VERTICAL_FEATURES.contains(&tag) {
+        if tags::is_vertical_positioning(tag) {
             *self = Self::Root;
         }
     }
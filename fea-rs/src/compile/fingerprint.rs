@@ -0,0 +1,85 @@
+//! A stable hash of the output of a compilation
+
+use std::{ffi::OsStr, fmt::Display};
+
+/// A content hash of the tables generated by a compilation, along with the
+/// set of sources it was parsed from.
+///
+/// This is returned by [`Compilation::fingerprint`]. If the fingerprint of a
+/// compilation is unchanged, then so are its tables and includes, and a build
+/// system can skip re-embedding the tables.
+///
+/// The hash is 64-bit FNV-1a, which is stable across runs, platforms and
+/// versions of this crate's dependencies; it is not a cryptographic hash.
+/// Fingerprints are displayed as 16 hexadecimal digits.
+///
+/// [`Compilation::fingerprint`]: super::Compilation::fingerprint
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Fingerprint(u64);
+
+/// A hasher whose output only depends on the bytes written to it.
+///
+/// Unlike the hashers in std, this does not depend on the platform or on the
+/// version of Rust, so its output can be stored between builds.
+pub(crate) struct StableHasher(u64);
+
+impl Fingerprint {
+    /// The raw value of the hash.
+    pub fn to_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    pub(crate) fn new() -> Self {
+        StableHasher(Self::OFFSET_BASIS)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Write a length-prefixed item, so that adjacent items cannot run together.
+    pub(crate) fn write_item(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+
+    pub(crate) fn write_path(&mut self, path: &OsStr) {
+        self.write_item(path.to_string_lossy().as_bytes());
+    }
+
+    pub(crate) fn finish(&self) -> Fingerprint {
+        Fingerprint(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv_1a() {
+        // reference values for 64-bit FNV-1a
+        let hash = |bytes: &[u8]| {
+            let mut hasher = StableHasher::new();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        assert_eq!(hash(b"").to_u64(), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a").to_u64(), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar").to_string(), "85944171f73967e8");
+    }
+}
@@ -1,6 +1,6 @@
-use std::ops::Range;
+use std::ops::{Range, RangeInclusive};
 
-use crate::token_tree::Token;
+use crate::token_tree::{typed, Token};
 
 //NOTE: in order to save allocation for each item in the range, we adopt
 //the pattern of having the caller pass in a callback that is called with
@@ -12,8 +12,8 @@ use crate::token_tree::Token;
 /// Returns an error if the range is not well-formed. If it is well-formed,
 /// the `callback` is called with each cid in the range.
 pub(crate) fn cid(start: &Token, end: &Token, mut callback: impl FnMut(u16)) -> Result<(), String> {
-    let start_cid = start.text.parse::<u16>().unwrap();
-    let end_cid = end.text.parse::<u16>().unwrap();
+    let start_cid = typed::parse_u16(&start.text)?;
+    let end_cid = typed::parse_u16(&end.text)?;
     if start_cid >= end_cid {
         return Err("Range end must be greater than start".into());
     }
@@ -51,12 +51,26 @@ pub(crate) fn named(start: &Token, end: &Token, callback: impl FnMut(&str)) -> R
     let one = &start.text[diff_range.clone()];
     let two = &end.text[diff_range.clone()];
     match (one.parse::<u16>(), two.parse::<u16>()) {
-    (Ok(one), Ok(two)) if one < two => num_range(&start.text, one..two, diff_range, callback),
+    (Ok(one), Ok(two)) if one < two => num_range(&start.text, one..=two, diff_range, callback),
         _ => return Err("range glyphs must differ by a single letter a-Z or A-Z, or by a run of up to three decimal digits".into()),
     };
     Ok(())
 }
 
+/// The message reported for members of a range that are not in the font.
+pub(crate) fn missing_members(members: &[String]) -> String {
+    match members {
+        [member] => format!("Range member '{member}' does not exist in font"),
+        _ => {
+            let members = members
+                .iter()
+                .map(|member| format!("'{member}'"))
+                .collect::<Vec<_>>();
+            format!("Range members {} do not exist in font", members.join(", "))
+        }
+    }
+}
+
 fn alpha_range(start: &str, end: &str, sub_range: Range<usize>, mut out: impl FnMut(&str)) {
     let mut template = start.to_string();
     let start_char = start.as_bytes()[sub_range.start] as char;
@@ -74,7 +88,7 @@ fn alpha_range(start: &str, end: &str, sub_range: Range<usize>, mut out: impl Fn
 
 fn num_range(
     start: &str,
-    sub_range: Range<u16>,
+    sub_range: RangeInclusive<u16>,
     text_range: Range<usize>,
     mut out: impl FnMut(&str),
 ) {
@@ -237,6 +251,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn named_range_numeric() {
+        let range = make_range_node(Kind::GlyphName, "a.alt08", Kind::GlyphName, "a.alt11");
+        let idents = glyph_range(&range).unwrap();
+        let map: GlyphMap = idents.into_iter().collect();
+        assert_eq!(map.len(), 4, "{:?}", map);
+        for val in ["a.alt08", "a.alt09", "a.alt10", "a.alt11"] {
+            assert!(map.contains(val));
+        }
+    }
+
     #[test]
     fn named_range_bad() {
         let range = make_range_node(Kind::GlyphName, "A.hi", Kind::GlyphName, "Ez.hi");
@@ -0,0 +1,396 @@
+//! Compiling kerning data from outside the feature file.
+//!
+//! Font sources usually store kerning as data rather than as FEA: a UFO has
+//! `groups.plist` and `kerning.plist`, and Glyphs has its own kerning groups
+//! and pairs. Instead of generating a (potentially enormous) `kern` feature
+//! from this data and parsing it again, it can be passed to the compiler
+//! directly, and compiled alongside the feature file.
+
+use std::collections::{btree_map::Entry, BTreeMap, HashMap, HashSet};
+
+use smol_str::SmolStr;
+
+use crate::{
+    common::{GlyphClass, GlyphId},
+    GlyphMap, GlyphName,
+};
+
+/// The prefix of UFO groups used on the first side of a kerning pair.
+#[cfg(feature = "ufo")]
+static UFO_KERN1_PREFIX: &str = "public.kern1.";
+/// The prefix of UFO groups used on the second side of a kerning pair.
+#[cfg(feature = "ufo")]
+static UFO_KERN2_PREFIX: &str = "public.kern2.";
+
+// the class records of a subtable are written inline, and there is one for
+// every combination of first class and second class (including class 0).
+// Each record holds a single x advance.
+const CLASS_RECORD_SIZE: usize = 2;
+const MAX_CLASS_RECORDS: usize = (u16::MAX as usize - 16) / CLASS_RECORD_SIZE;
+
+/// Kerning groups and pairs, to be compiled into the `kern` feature.
+///
+/// Each side of a pair is either a glyph or a group; a name that has been
+/// added with [`add_group`] refers to that group, and any other name refers
+/// to a glyph. Values are horizontal advance adjustments of the first glyph,
+/// as in a UFO.
+///
+/// As in a UFO, a more specific pair takes precedence over a less specific
+/// one: glyph pairs win over pairs with a group, and pairs where only the
+/// second side is a group win over pairs where only the first side is.
+///
+/// The kerning is compiled into pair positioning lookups, without lookup
+/// flags, which are added to the `kern` feature of every default language
+/// system (as well as any other language system that has a `kern` feature in
+/// the feature file). They come after any lookups defined in the feature
+/// file. Glyphs that are not in the glyph map are ignored.
+///
+/// As with ufo2ft, pairs that include a right-to-left glyph (see
+/// [`add_rtl_glyphs`]) are compiled into a separate lookup, where the value
+/// adjusts both the placement and the advance of the first glyph. This
+/// lookup is used by right-to-left scripts and the `DFLT` script; the other
+/// pairs are used by every other script, and by right-to-left scripts if
+/// there are no right-to-left pairs.
+///
+/// Pairs that are already kerned by the `kern` feature of the feature file
+/// are skipped, so that they are not kerned twice.
+///
+/// Register kerning with [`Compiler::with_kerning`].
+///
+/// [`add_group`]: Kerning::add_group
+/// [`add_rtl_glyphs`]: Kerning::add_rtl_glyphs
+/// [`Compiler::with_kerning`]: crate::Compiler::with_kerning
+#[derive(Clone, Debug, Default)]
+pub struct Kerning {
+    groups: BTreeMap<SmolStr, Vec<GlyphName>>,
+    pairs: BTreeMap<(SmolStr, SmolStr), i16>,
+    rtl_glyphs: HashSet<GlyphName>,
+}
+
+/// Kerning in one direction, resolved against a glyph map.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ResolvedKerning {
+    /// `true` if these pairs include right-to-left glyphs.
+    pub(crate) rtl: bool,
+    /// Pairs with at least one glyph, with groups expanded.
+    pub(crate) glyph_pairs: BTreeMap<(GlyphId, GlyphId), i16>,
+    /// Group pairs, split so that each fits in a single subtable.
+    pub(crate) class_subtables: Vec<Vec<(GlyphClass, GlyphClass, i16)>>,
+}
+
+enum Side {
+    Glyph(GlyphId),
+    Group(GlyphClass),
+}
+
+impl Kerning {
+    /// Create a new, empty set of kerning.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load the kerning groups and pairs of a UFO.
+    ///
+    /// Only groups with the `public.kern1.` and `public.kern2.` prefixes are
+    /// used. Values are rounded to the nearest integer. Glyphs in the default
+    /// layer with a right-to-left codepoint are right-to-left glyphs.
+    #[cfg(feature = "ufo")]
+    pub fn from_ufo(font: &norad::Font) -> Self {
+        let mut kerning = Kerning::new();
+        kerning.add_rtl_glyphs(
+            font.default_layer()
+                .iter()
+                .filter(|glyph| glyph.codepoints.iter().any(is_rtl_char))
+                .map(|glyph| GlyphName::new(glyph.name().as_str())),
+        );
+        for (name, glyphs) in &font.groups {
+            if name.starts_with(UFO_KERN1_PREFIX) || name.starts_with(UFO_KERN2_PREFIX) {
+                kerning.add_group(name.as_str(), glyphs.iter().map(GlyphName::new));
+            }
+        }
+        for (first, seconds) in &font.kerning {
+            for (second, value) in seconds {
+                kerning.add_pair(first.as_str(), second.as_str(), value.round() as i16);
+            }
+        }
+        kerning
+    }
+
+    /// Add a kerning group.
+    ///
+    /// If a group with this name already exists, it is replaced.
+    pub fn add_group(
+        &mut self,
+        name: impl Into<SmolStr>,
+        glyphs: impl IntoIterator<Item = GlyphName>,
+    ) {
+        self.groups
+            .insert(name.into(), glyphs.into_iter().collect());
+    }
+
+    /// Add a kerning pair.
+    ///
+    /// Either side may be the name of a glyph or of a group. If this pair
+    /// already exists, its value is replaced.
+    pub fn add_pair(&mut self, first: impl Into<SmolStr>, second: impl Into<SmolStr>, value: i16) {
+        self.pairs.insert((first.into(), second.into()), value);
+    }
+
+    /// Mark glyphs as right-to-left.
+    ///
+    /// Pairs that include one of these glyphs, on either side or in a group,
+    /// are compiled into a separate right-to-left lookup.
+    pub fn add_rtl_glyphs(&mut self, glyphs: impl IntoIterator<Item = GlyphName>) {
+        self.rtl_glyphs.extend(glyphs);
+    }
+
+    /// `true` if there are no kerning pairs.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Resolve glyph names, and split and order the pairs for compilation.
+    ///
+    /// Pairs in any of the class pairs in `covered` are already kerned
+    /// elsewhere, and are skipped. The kerning in each direction is returned,
+    /// left-to-right first, without any that is empty.
+    pub(crate) fn resolve(
+        &self,
+        glyph_map: &GlyphMap,
+        covered: &[(GlyphClass, GlyphClass)],
+    ) -> Vec<ResolvedKerning> {
+        let groups = self
+            .groups
+            .iter()
+            .map(|(name, glyphs)| {
+                let class = glyphs
+                    .iter()
+                    .filter_map(|glyph| {
+                        let gid = glyph_map.get(glyph);
+                        if gid.is_none() {
+                            log::warn!(
+                                "glyph '{glyph}' in kerning group '{name}' is not in the font"
+                            );
+                        }
+                        gid
+                    })
+                    .collect::<GlyphClass>();
+                (name, class.sort_and_dedupe())
+            })
+            .collect::<HashMap<_, _>>();
+        let resolve_side = |name: &SmolStr| match groups.get(name) {
+            Some(class) => (!class.is_empty()).then(|| Side::Group(class.clone())),
+            None => {
+                let gid = glyph_map.get(name);
+                if gid.is_none() {
+                    log::warn!("kerned glyph '{name}' is not in the font");
+                }
+                gid.map(Side::Glyph)
+            }
+        };
+
+        // pairs with a glyph are expanded to glyph pairs; the lower the
+        // priority, the more specific the pair
+        let mut glyph_pairs = BTreeMap::new();
+        let mut add_glyph_pair =
+            |pair: (GlyphId, GlyphId), value: i16, priority: u8| match glyph_pairs.entry(pair) {
+                Entry::Vacant(entry) => {
+                    entry.insert((value, priority));
+                }
+                Entry::Occupied(mut entry) if priority < entry.get().1 => {
+                    entry.insert((value, priority));
+                }
+                Entry::Occupied(_) => (),
+            };
+        let mut class_pairs = BTreeMap::<_, Vec<_>>::new();
+        for ((first, second), value) in &self.pairs {
+            let (Some(first), Some(second)) = (resolve_side(first), resolve_side(second)) else {
+                continue;
+            };
+            let value = *value;
+            match (first, second) {
+                (Side::Glyph(first), Side::Glyph(second)) => {
+                    add_glyph_pair((first, second), value, 0)
+                }
+                (Side::Glyph(first), Side::Group(second)) => second
+                    .iter()
+                    .for_each(|second| add_glyph_pair((first, second), value, 1)),
+                (Side::Group(first), Side::Glyph(second)) => first
+                    .iter()
+                    .for_each(|first| add_glyph_pair((first, second), value, 2)),
+                (Side::Group(first), Side::Group(second)) => {
+                    class_pairs.entry(first).or_default().push((second, value))
+                }
+            }
+        }
+
+        let mut glyph_pairs = glyph_pairs
+            .into_iter()
+            .map(|(pair, (value, _))| (pair, value))
+            .collect::<BTreeMap<_, _>>();
+
+        // the members of the second classes of each first class, to find the
+        // class pair that kerns a glyph pair
+        let rows = class_pairs
+            .iter()
+            .map(|(class1, row)| {
+                let seconds = row
+                    .iter()
+                    .map(|(class2, _)| (class2, class2.iter().collect::<HashSet<_>>()))
+                    .collect::<Vec<_>>();
+                (class1, seconds)
+            })
+            .collect::<Vec<_>>();
+        let first_rows = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (class1, _))| class1.iter().map(move |gid| (gid, i)))
+            .collect::<HashMap<_, _>>();
+        let class_rule = |first: GlyphId, second: GlyphId| {
+            let (class1, seconds) = &rows[*first_rows.get(&first)?];
+            seconds
+                .iter()
+                .find(|(_, members)| members.contains(&second))
+                .map(|(class2, _)| (*class1, *class2))
+        };
+
+        // a covered pair can't just be removed if a class pair would still
+        // kern it; it needs a zero-valued glyph pair to take precedence.
+        let covered_seconds = covered
+            .iter()
+            .map(|(_, class2)| class2.iter().collect::<HashSet<_>>())
+            .collect::<Vec<_>>();
+        let mut covered_firsts = HashMap::<_, Vec<_>>::new();
+        for (i, (class1, _)) in covered.iter().enumerate() {
+            for gid in class1.iter() {
+                covered_firsts.entry(gid).or_default().push(i);
+            }
+        }
+        let is_covered = |first: GlyphId, second: GlyphId| {
+            covered_firsts
+                .get(&first)
+                .is_some_and(|ids| ids.iter().any(|i| covered_seconds[*i].contains(&second)))
+        };
+        glyph_pairs.retain(|&(first, second), value| {
+            if !is_covered(first, second) {
+                return true;
+            }
+            *value = 0;
+            class_rule(first, second).is_some()
+        });
+        // the covered pairs kerned by a class pair are the glyphs that the
+        // covered classes share with it
+        for (class1, class2) in covered {
+            let mut firsts_by_row = HashMap::<_, Vec<_>>::new();
+            for gid in class1.iter() {
+                if let Some(row) = first_rows.get(&gid) {
+                    firsts_by_row.entry(*row).or_default().push(gid);
+                }
+            }
+            for (row, firsts) in firsts_by_row {
+                for (_, members) in &rows[row].1 {
+                    let seconds = class2
+                        .iter()
+                        .filter(|gid| members.contains(gid))
+                        .collect::<Vec<_>>();
+                    for &first in &firsts {
+                        for &second in &seconds {
+                            glyph_pairs.insert((first, second), 0);
+                        }
+                    }
+                }
+            }
+        }
+
+        // the direction of a glyph pair follows the class pair it overrides
+        let rtl_gids = self
+            .rtl_glyphs
+            .iter()
+            .filter_map(|name| glyph_map.get(name))
+            .collect::<HashSet<_>>();
+        let is_rtl_class = |class: &GlyphClass| class.iter().any(|gid| rtl_gids.contains(&gid));
+        let mut ltr = ResolvedKerning::default();
+        let mut rtl = ResolvedKerning {
+            rtl: true,
+            ..Default::default()
+        };
+        for ((first, second), value) in glyph_pairs {
+            let is_rtl = match class_rule(first, second) {
+                Some((class1, class2)) => is_rtl_class(class1) || is_rtl_class(class2),
+                None => rtl_gids.contains(&first) || rtl_gids.contains(&second),
+            };
+            let resolved = if is_rtl { &mut rtl } else { &mut ltr };
+            resolved.glyph_pairs.insert((first, second), value);
+        }
+        let (mut ltr_classes, mut rtl_classes) = (BTreeMap::new(), BTreeMap::new());
+        for (first, row) in class_pairs {
+            let (rtl_row, ltr_row): (Vec<_>, Vec<_>) = row
+                .into_iter()
+                .partition(|(second, _)| is_rtl_class(&first) || is_rtl_class(second));
+            if !ltr_row.is_empty() {
+                ltr_classes.insert(first.clone(), ltr_row);
+            }
+            if !rtl_row.is_empty() {
+                rtl_classes.insert(first, rtl_row);
+            }
+        }
+        ltr.class_subtables = split_class_pairs(ltr_classes);
+        rtl.class_subtables = split_class_pairs(rtl_classes);
+        [ltr, rtl]
+            .into_iter()
+            .filter(|resolved| !resolved.is_empty())
+            .collect()
+    }
+}
+
+impl ResolvedKerning {
+    fn is_empty(&self) -> bool {
+        self.glyph_pairs.is_empty() && self.class_subtables.is_empty()
+    }
+}
+
+/// `true` if this character belongs to a right-to-left block.
+///
+/// This is an approximation of the bidi class of the character, which is
+/// close enough to decide which lookup a kerning pair belongs to.
+#[cfg(feature = "ufo")]
+fn is_rtl_char(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x0590..=0x08FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF | 0x10800..=0x10FFF | 0x1E800..=0x1EFFF
+    )
+}
+
+/// Split class pairs into subtables that can be addressed with 16-bit offsets.
+///
+/// A class-based subtable applies to every pair whose first glyph it covers,
+/// so all of the pairs of a first class are kept in the same subtable.
+fn split_class_pairs(
+    class_pairs: BTreeMap<GlyphClass, Vec<(GlyphClass, i16)>>,
+) -> Vec<Vec<(GlyphClass, GlyphClass, i16)>> {
+    let mut subtables = Vec::new();
+    let mut current = Vec::new();
+    let mut n_first = 0;
+    let mut seconds = HashSet::new();
+    for (first, row) in class_pairs {
+        let n_second = seconds.len()
+            + row
+                .iter()
+                .filter(|(second, _)| !seconds.contains(second))
+                .count();
+        if n_first > 0 && (n_first + 1) * (n_second + 1) > MAX_CLASS_RECORDS {
+            subtables.push(std::mem::take(&mut current));
+            n_first = 0;
+            seconds.clear();
+        }
+        n_first += 1;
+        for (second, value) in row {
+            seconds.insert(second.clone());
+            current.push((first.clone(), second, value));
+        }
+    }
+    if !current.is_empty() {
+        subtables.push(current);
+    }
+    subtables
+}
@@ -32,6 +32,19 @@ impl DefaultLanguageSystems {
         Rc::get_mut(&mut self.items).unwrap().insert(system);
     }
 
+    /// Register 'DFLT dflt' in addition to any explicit entries.
+    pub(crate) fn insert_implicit_default(&mut self) {
+        self.insert_implicit(LanguageSystem::default());
+    }
+
+    /// Register a system in addition to any explicit entries.
+    ///
+    /// Unlike [`insert`](Self::insert), this does not replace the implicit
+    /// 'DFLT dflt' entry when no explicit entries have been seen.
+    pub(crate) fn insert_implicit(&mut self, system: LanguageSystem) {
+        Rc::make_mut(&mut self.items).insert(system);
+    }
+
     pub(crate) fn contains(&self, key: &LanguageSystem) -> bool {
         self.items.contains(key)
     }
@@ -0,0 +1,88 @@
+//! Optional checks that do not affect compilation
+
+use std::collections::HashSet;
+
+use crate::{
+    parse::{Symbol, XrefIndex},
+    typed::{self, AstNode},
+    Diagnostic, ParseTree,
+};
+
+/// Warn about named items that are defined but never used.
+///
+/// Lookups defined inside a feature block are always used by that feature,
+/// so only lookups defined at the top level are checked.
+pub(crate) fn unused_definitions(tree: &ParseTree) -> Vec<Diagnostic> {
+    let index = XrefIndex::new(tree);
+    let top_level_lookups = tree
+        .typed_root()
+        .statements()
+        .filter_map(typed::LookupBlock::cast)
+        .map(|lookup| lookup.label().text.clone())
+        .collect::<HashSet<_>>();
+
+    let mut result = Vec::new();
+    for symbol in index.symbols() {
+        let message = match symbol {
+            Symbol::GlyphClass(_) => format!("glyph class '{symbol}' is never used"),
+            Symbol::MarkClass(_) => format!("mark class '{symbol}' is never used"),
+            Symbol::Anchor(_) => format!("anchor '{symbol}' is never used"),
+            Symbol::Lookup(name) if top_level_lookups.contains(name) => {
+                format!("lookup '{symbol}' is defined outside of a feature and never used")
+            }
+            _ => continue,
+        };
+        if !index.references(symbol).is_empty() {
+            continue;
+        }
+        // report at the first definition; for lookups, that is the opening label
+        if let Some(def) = index.definitions(symbol).first() {
+            result.push(Diagnostic::warning(def.file, def.range.clone(), message));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(fea: &'static str) -> Vec<String> {
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let tree = crate::util::ttx::parse_str(fea, &glyph_map);
+        unused_definitions(&tree)
+            .iter()
+            .map(|diag| diag.text().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn unused_definitions_are_reported() {
+        let warnings = lint(
+            "
+@used = [a b];
+@unused = [c d];
+markClass acute <anchor 0 500> @TOP;
+markClass grave <anchor 0 500> @UNUSED_MARKS;
+anchorDef 100 200 ANCHOR_1;
+anchorDef 300 400 ANCHOR_2;
+lookup USED { sub @used by e; } USED;
+lookup UNUSED { sub f by g; } UNUSED;
+feature test {
+    lookup USED;
+    lookup INSIDE { sub h by i; } INSIDE;
+    pos base j <anchor ANCHOR_1> mark @TOP;
+} test;
+",
+        );
+        assert_eq!(
+            warnings,
+            [
+                "glyph class '@unused' is never used",
+                "mark class '@UNUSED_MARKS' is never used",
+                "lookup 'UNUSED' is defined outside of a feature and never used",
+                "anchor 'ANCHOR_2' is never used",
+            ]
+        );
+    }
+}
@@ -6,8 +6,10 @@ mod gsub;
 mod helpers;
 
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     convert::TryInto,
+    fmt::{Display, Formatter},
+    time::Instant,
 };
 
 use smol_str::SmolStr;
@@ -22,6 +24,8 @@ use write_fonts::{
         },
     },
     types::Tag,
+    validate::Validate,
+    FontWrite,
 };
 
 use crate::{
@@ -30,7 +34,14 @@ use crate::{
     Kind,
 };
 
-use super::{tables::ClassId, tags};
+use super::{
+    cache::PrebuiltLookups,
+    error::CompileError,
+    report::{SubtableReport, SubtableStats},
+    summary::{LookupInfo, LookupKind},
+    tables::ClassId,
+    tags, FeatureRecordOrder, Opts,
+};
 
 use contextual::{
     ContextualLookupBuilder, PosChainContextBuilder, PosContextBuilder, ReverseChainBuilder,
@@ -49,15 +60,61 @@ pub trait Builder {
     fn build(self) -> Self::Output;
 }
 
+/// A trait for subtable builders that can report the number of rules they contain.
+///
+/// See [`LookupInfo::rule_count`] for what is counted for each lookup type.
+pub(crate) trait RuleCount {
+    fn rule_count(&self) -> usize;
+}
+
+/// A trait for subtable builders that can remove rules that use certain glyphs.
+///
+/// This is used when compiling for a subset of the glyphs in a font.
+pub(crate) trait RetainGlyphs {
+    /// Remove any rule that references a glyph for which `keep` returns `false`.
+    ///
+    /// Where a rule can be narrowed instead (for instance, by removing glyphs
+    /// from a class) this is preferred.
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool);
+}
+
+/// A marker for types that can be built on another thread.
+///
+/// When the `parallel` feature is enabled this requires `Send`; otherwise it
+/// is implemented for all types.
+#[cfg(feature = "parallel")]
+pub(crate) trait MaybeSend: Send {}
+#[cfg(feature = "parallel")]
+impl<T: Send> MaybeSend for T {}
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) trait MaybeSend {}
+#[cfg(not(feature = "parallel"))]
+impl<T> MaybeSend for T {}
+
 pub(crate) type FilterSetId = u16;
 
 #[derive(Clone, Debug, Default)]
 pub(crate) struct AllLookups {
     current: Option<SomeLookup>,
+    // the flags the current lookup was started with
+    current_flags: LookupFlagInfo,
     current_name: Option<SmolStr>,
     gpos: Vec<PositionLookup>,
     gsub: Vec<SubstitutionLookup>,
-    named: HashMap<SmolStr, LookupId>,
+    // named lookups, with the flags they were defined with
+    named: HashMap<SmolStr, (LookupId, LookupFlagInfo)>,
+    // lookups that will be removed when ids are finalized
+    removed: HashSet<LookupId>,
+    // the aalt lookups, which are moved to the front of the GSUB lookup list
+    // when ids are finalized
+    aalt_ids: Vec<LookupId>,
+    // contextual positioning lookups compiled as chained contextual lookups
+    forced_chain_pos: Vec<LookupId>,
+    force_chain_pos: bool,
+    pair_pos_class_0: bool,
+    compact_value_formats: bool,
+    feature_record_order: FeatureRecordOrder,
 }
 
 #[derive(Clone, Debug)]
@@ -75,8 +132,8 @@ pub(crate) enum PositionLookup {
     MarkToBase(LookupBuilder<MarkToBaseBuilder>),
     MarkToLig(LookupBuilder<MarkToLigBuilder>),
     MarkToMark(LookupBuilder<MarkToMarkBuilder>),
-    // currently unused, matching feaLib: <https://github.com/fonttools/fonttools/issues/2539>
-    #[allow(dead_code)]
+    // only used if `Opts::force_chain_pos` is false; by default we match
+    // feaLib: <https://github.com/fonttools/fonttools/issues/2539>
     Contextual(LookupBuilder<PosContextBuilder>),
     ChainedContextual(LookupBuilder<PosChainContextBuilder>),
 }
@@ -110,25 +167,44 @@ pub(crate) enum LookupId {
     Empty,
 }
 
+/// A mapping from the ids assigned to lookups during compilation to their
+/// final ids.
+///
+/// Lookup ids are stable while we compile: passes that remove or reorder
+/// lookups only record what they want, and the resulting map is applied to
+/// every reference at once, at the end of compilation.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LookupIdMap {
+    mapping: HashMap<LookupId, LookupId>,
+}
+
 /// Tracks the current lookupflags state
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub(crate) struct LookupFlagInfo {
     pub(crate) flags: LookupFlag,
     pub(crate) mark_filter_set: Option<FilterSetId>,
 }
 
+/// A feature in a particular language system.
+///
+/// Each feature key in a compilation has a list of lookups; see
+/// [`Compilation::features`](crate::compile::Compilation::features).
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub(crate) struct FeatureKey {
-    pub(crate) feature: Tag,
-    pub(crate) language: Tag,
-    pub(crate) script: Tag,
+pub struct FeatureKey {
+    /// The feature tag.
+    pub feature: Tag,
+    /// The language tag.
+    pub language: Tag,
+    /// The script tag.
+    pub script: Tag,
 }
 
 /// A helper for building GSUB/GPOS tables
-pub(crate) struct PosSubBuilder<T> {
-    lookups: Vec<T>,
+pub(crate) struct PosSubBuilder<T: Builder> {
+    lookups: Vec<MaybeBuilt<T>>,
     scripts: BTreeMap<Tag, BTreeMap<Tag, LangSys>>,
     features: BTreeMap<(Tag, Vec<u16>), u16>,
+    feature_order: FeatureRecordOrder,
 }
 
 impl<T: Default> LookupBuilder<T> {
@@ -166,6 +242,81 @@ impl<T: Default> LookupBuilder<T> {
     }
 }
 
+impl<U, T> LookupBuilder<T>
+where
+    T: Builder<Output = Vec<U>> + RuleCount + Clone,
+{
+    /// Summarize this lookup.
+    ///
+    /// The subtable count requires building each subtable.
+    fn info(&self, kind: LookupKind) -> LookupInfo {
+        LookupInfo {
+            kind,
+            flags: self.flags,
+            mark_filter_set: self.mark_set,
+            subtable_count: self
+                .subtables
+                .iter()
+                .map(|sub| sub.clone().build().len())
+                .sum(),
+            rule_count: self.subtables.iter().map(RuleCount::rule_count).sum(),
+            sources: Vec::new(),
+        }
+    }
+
+    /// Build each subtable, and report its size and structure.
+    fn subtable_reports(&self) -> Vec<SubtableReport>
+    where
+        U: SubtableStats + FontWrite + Validate,
+    {
+        self.subtables
+            .iter()
+            .flat_map(|sub| sub.clone().build())
+            .map(|subtable| SubtableReport {
+                size: write_fonts::dump_table(&subtable)
+                    .ok()
+                    .map(|data| data.len()),
+                ..subtable.stats()
+            })
+            .collect()
+    }
+}
+
+/// Writes the rules of each subtable in FEA syntax, with glyph ids in place
+/// of glyph names.
+impl<T: Display> Display for LookupBuilder<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.flags.to_bits() != 0 {
+            writeln!(f, "lookupflag {};", self.flags.to_bits())?;
+        }
+        if let Some(mark_set) = self.mark_set {
+            writeln!(f, "# mark filtering set {mark_set}")?;
+        }
+        for (i, subtable) in self.subtables.iter().enumerate() {
+            if i > 0 {
+                writeln!(f, "subtable;")?;
+            }
+            write!(f, "{subtable}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: RetainGlyphs + RuleCount> LookupBuilder<T> {
+    /// Remove rules that reference glyphs that are not kept, as well as any
+    /// subtables that are left empty.
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.subtables
+            .iter_mut()
+            .for_each(|sub| sub.retain_glyphs(keep));
+        self.subtables.retain(|sub| sub.rule_count() > 0);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.subtables.iter().all(|sub| sub.rule_count() == 0)
+    }
+}
+
 impl<U> LookupBuilder<U> {
     /// A helper method for converting from (say) ContextBuilder to PosContextBuilder
     fn convert<T: From<U>>(self) -> LookupBuilder<T> {
@@ -197,6 +348,225 @@ impl PositionLookup {
     }
 }
 
+impl PositionLookup {
+    fn info(&self) -> LookupInfo {
+        match self {
+            PositionLookup::Single(lookup) => lookup.info(LookupKind::GposSingle),
+            PositionLookup::Pair(lookup) => lookup.info(LookupKind::GposPair),
+            PositionLookup::Cursive(lookup) => lookup.info(LookupKind::GposCursive),
+            PositionLookup::MarkToBase(lookup) => lookup.info(LookupKind::GposMarkToBase),
+            PositionLookup::MarkToLig(lookup) => lookup.info(LookupKind::GposMarkToLig),
+            PositionLookup::MarkToMark(lookup) => lookup.info(LookupKind::GposMarkToMark),
+            PositionLookup::Contextual(lookup) => lookup.info(LookupKind::GposContextual),
+            PositionLookup::ChainedContextual(lookup) => {
+                lookup.info(LookupKind::GposChainContextual)
+            }
+        }
+    }
+}
+
+impl PositionLookup {
+    /// The ids of any lookups referenced by contextual rules in this lookup.
+    fn referenced_lookups(&self) -> Vec<LookupId> {
+        match self {
+            PositionLookup::Contextual(lookup) => lookup
+                .subtables
+                .iter()
+                .flat_map(|sub| sub.iter_lookups())
+                .collect(),
+            PositionLookup::ChainedContextual(lookup) => lookup
+                .subtables
+                .iter()
+                .flat_map(|sub| sub.iter_lookups())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        match self {
+            PositionLookup::Single(lookup) => lookup.retain_glyphs(keep),
+            PositionLookup::Pair(lookup) => lookup.retain_glyphs(keep),
+            PositionLookup::Cursive(lookup) => lookup.retain_glyphs(keep),
+            PositionLookup::MarkToBase(lookup) => lookup.retain_glyphs(keep),
+            PositionLookup::MarkToLig(lookup) => lookup.retain_glyphs(keep),
+            PositionLookup::MarkToMark(lookup) => lookup.retain_glyphs(keep),
+            PositionLookup::Contextual(lookup) => lookup.retain_glyphs(keep),
+            PositionLookup::ChainedContextual(lookup) => lookup.retain_glyphs(keep),
+        }
+    }
+
+    /// Update the lookups referenced by contextual rules; see
+    /// [`ContextBuilder::remap_lookups`](contextual::ContextBuilder::remap_lookups).
+    fn remap_lookups(&mut self, f: &dyn Fn(LookupId) -> Option<LookupId>) {
+        match self {
+            PositionLookup::Contextual(lookup) => {
+                lookup
+                    .subtables
+                    .iter_mut()
+                    .for_each(|sub| sub.remap_lookups(f));
+                lookup.subtables.retain(|sub| sub.rule_count() > 0);
+            }
+            PositionLookup::ChainedContextual(lookup) => {
+                lookup
+                    .subtables
+                    .iter_mut()
+                    .for_each(|sub| sub.remap_lookups(f));
+                lookup.subtables.retain(|sub| sub.rule_count() > 0);
+            }
+            _ => (),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            PositionLookup::Single(lookup) => lookup.is_empty(),
+            PositionLookup::Pair(lookup) => lookup.is_empty(),
+            PositionLookup::Cursive(lookup) => lookup.is_empty(),
+            PositionLookup::MarkToBase(lookup) => lookup.is_empty(),
+            PositionLookup::MarkToLig(lookup) => lookup.is_empty(),
+            PositionLookup::MarkToMark(lookup) => lookup.is_empty(),
+            PositionLookup::Contextual(lookup) => lookup.is_empty(),
+            PositionLookup::ChainedContextual(lookup) => lookup.is_empty(),
+        }
+    }
+
+    fn subtable_reports(&self) -> Vec<SubtableReport> {
+        match self {
+            PositionLookup::Single(lookup) => lookup.subtable_reports(),
+            PositionLookup::Pair(lookup) => lookup.subtable_reports(),
+            PositionLookup::Cursive(lookup) => lookup.subtable_reports(),
+            PositionLookup::MarkToBase(lookup) => lookup.subtable_reports(),
+            PositionLookup::MarkToLig(lookup) => lookup.subtable_reports(),
+            PositionLookup::MarkToMark(lookup) => lookup.subtable_reports(),
+            PositionLookup::Contextual(lookup) => lookup.subtable_reports(),
+            PositionLookup::ChainedContextual(lookup) => lookup.subtable_reports(),
+        }
+    }
+}
+
+impl Display for PositionLookup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PositionLookup::Single(lookup) => lookup.fmt(f),
+            PositionLookup::Pair(lookup) => lookup.fmt(f),
+            PositionLookup::Cursive(lookup) => lookup.fmt(f),
+            PositionLookup::MarkToBase(lookup) => lookup.fmt(f),
+            PositionLookup::MarkToLig(lookup) => lookup.fmt(f),
+            PositionLookup::MarkToMark(lookup) => lookup.fmt(f),
+            PositionLookup::Contextual(lookup) => lookup.fmt(f),
+            PositionLookup::ChainedContextual(lookup) => lookup.fmt(f),
+        }
+    }
+}
+
+impl SubstitutionLookup {
+    /// The ids of any lookups referenced by contextual rules in this lookup.
+    fn referenced_lookups(&self) -> Vec<LookupId> {
+        match self {
+            SubstitutionLookup::Contextual(lookup) => lookup
+                .subtables
+                .iter()
+                .flat_map(|sub| sub.iter_lookups())
+                .collect(),
+            SubstitutionLookup::ChainedContextual(lookup) => lookup
+                .subtables
+                .iter()
+                .flat_map(|sub| sub.iter_lookups())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        match self {
+            SubstitutionLookup::Single(lookup) => lookup.retain_glyphs(keep),
+            SubstitutionLookup::Multiple(lookup) => lookup.retain_glyphs(keep),
+            SubstitutionLookup::Alternate(lookup) => lookup.retain_glyphs(keep),
+            SubstitutionLookup::Ligature(lookup) => lookup.retain_glyphs(keep),
+            SubstitutionLookup::Contextual(lookup) => lookup.retain_glyphs(keep),
+            SubstitutionLookup::ChainedContextual(lookup) => lookup.retain_glyphs(keep),
+            SubstitutionLookup::Reverse(lookup) => lookup.retain_glyphs(keep),
+        }
+    }
+
+    /// Update the lookups referenced by contextual rules; see
+    /// [`ContextBuilder::remap_lookups`](contextual::ContextBuilder::remap_lookups).
+    fn remap_lookups(&mut self, f: &dyn Fn(LookupId) -> Option<LookupId>) {
+        match self {
+            SubstitutionLookup::Contextual(lookup) => {
+                lookup
+                    .subtables
+                    .iter_mut()
+                    .for_each(|sub| sub.remap_lookups(f));
+                lookup.subtables.retain(|sub| sub.rule_count() > 0);
+            }
+            SubstitutionLookup::ChainedContextual(lookup) => {
+                lookup
+                    .subtables
+                    .iter_mut()
+                    .for_each(|sub| sub.remap_lookups(f));
+                lookup.subtables.retain(|sub| sub.rule_count() > 0);
+            }
+            _ => (),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            SubstitutionLookup::Single(lookup) => lookup.is_empty(),
+            SubstitutionLookup::Multiple(lookup) => lookup.is_empty(),
+            SubstitutionLookup::Alternate(lookup) => lookup.is_empty(),
+            SubstitutionLookup::Ligature(lookup) => lookup.is_empty(),
+            SubstitutionLookup::Contextual(lookup) => lookup.is_empty(),
+            SubstitutionLookup::ChainedContextual(lookup) => lookup.is_empty(),
+            SubstitutionLookup::Reverse(lookup) => lookup.is_empty(),
+        }
+    }
+
+    fn subtable_reports(&self) -> Vec<SubtableReport> {
+        match self {
+            SubstitutionLookup::Single(lookup) => lookup.subtable_reports(),
+            SubstitutionLookup::Multiple(lookup) => lookup.subtable_reports(),
+            SubstitutionLookup::Alternate(lookup) => lookup.subtable_reports(),
+            SubstitutionLookup::Ligature(lookup) => lookup.subtable_reports(),
+            SubstitutionLookup::Contextual(lookup) => lookup.subtable_reports(),
+            SubstitutionLookup::ChainedContextual(lookup) => lookup.subtable_reports(),
+            SubstitutionLookup::Reverse(lookup) => lookup.subtable_reports(),
+        }
+    }
+}
+
+impl SubstitutionLookup {
+    fn info(&self) -> LookupInfo {
+        match self {
+            SubstitutionLookup::Single(lookup) => lookup.info(LookupKind::GsubSingle),
+            SubstitutionLookup::Multiple(lookup) => lookup.info(LookupKind::GsubMultiple),
+            SubstitutionLookup::Alternate(lookup) => lookup.info(LookupKind::GsubAlternate),
+            SubstitutionLookup::Ligature(lookup) => lookup.info(LookupKind::GsubLigature),
+            SubstitutionLookup::Contextual(lookup) => lookup.info(LookupKind::GsubContextual),
+            SubstitutionLookup::ChainedContextual(lookup) => {
+                lookup.info(LookupKind::GsubChainContextual)
+            }
+            SubstitutionLookup::Reverse(lookup) => lookup.info(LookupKind::GsubReverseChain),
+        }
+    }
+}
+
+impl Display for SubstitutionLookup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubstitutionLookup::Single(lookup) => lookup.fmt(f),
+            SubstitutionLookup::Multiple(lookup) => lookup.fmt(f),
+            SubstitutionLookup::Alternate(lookup) => lookup.fmt(f),
+            SubstitutionLookup::Ligature(lookup) => lookup.fmt(f),
+            SubstitutionLookup::Contextual(lookup) => lookup.fmt(f),
+            SubstitutionLookup::ChainedContextual(lookup) => lookup.fmt(f),
+            SubstitutionLookup::Reverse(lookup) => lookup.fmt(f),
+        }
+    }
+}
+
 impl SubstitutionLookup {
     fn force_subtable_break(&mut self) {
         match self {
@@ -286,6 +656,52 @@ impl Builder for SubstitutionLookup {
 }
 
 impl AllLookups {
+    pub(crate) fn new(opts: &Opts) -> Self {
+        AllLookups {
+            force_chain_pos: opts.force_chain_pos,
+            pair_pos_class_0: opts.pair_pos_class_0,
+            compact_value_formats: opts.compact_value_formats,
+            feature_record_order: opts.feature_record_order,
+            ..Default::default()
+        }
+    }
+
+    /// Summaries of the GSUB lookups, in lookup list order.
+    pub(crate) fn gsub_info(&self) -> Vec<LookupInfo> {
+        self.gsub.iter().map(SubstitutionLookup::info).collect()
+    }
+
+    /// Summaries of the GPOS lookups, in lookup list order.
+    pub(crate) fn gpos_info(&self) -> Vec<LookupInfo> {
+        self.gpos.iter().map(PositionLookup::info).collect()
+    }
+
+    /// Reports on the subtables of each GSUB lookup, in lookup list order.
+    pub(crate) fn gsub_subtable_reports(&self) -> Vec<Vec<SubtableReport>> {
+        self.gsub
+            .iter()
+            .map(SubstitutionLookup::subtable_reports)
+            .collect()
+    }
+
+    /// Reports on the subtables of each GPOS lookup, in lookup list order.
+    pub(crate) fn gpos_subtable_reports(&self) -> Vec<Vec<SubtableReport>> {
+        self.gpos
+            .iter()
+            .map(PositionLookup::subtable_reports)
+            .collect()
+    }
+
+    /// The rules of each GSUB lookup, in lookup list order.
+    pub(crate) fn gsub_rules(&self) -> Vec<String> {
+        self.gsub.iter().map(ToString::to_string).collect()
+    }
+
+    /// The rules of each GPOS lookup, in lookup list order.
+    pub(crate) fn gpos_rules(&self) -> Vec<String> {
+        self.gpos.iter().map(ToString::to_string).collect()
+    }
+
     fn push(&mut self, lookup: SomeLookup) -> LookupId {
         match lookup {
             SomeLookup::GsubLookup(sub) => {
@@ -301,11 +717,16 @@ impl AllLookups {
                 assert_eq!(id, lookup.root_id); // sanity check
                 let (lookup, anon_lookups) = lookup.into_lookups();
                 match lookup {
-                    ChainOrNot::Context(lookup) => self
-                        .gpos
-                        //NOTE: we currently force all GPOS7 into GPOS8, to match
-                        //the behaviour of fonttools.
-                        .push(PositionLookup::ChainedContextual(lookup.convert())),
+                    //NOTE: by default we force all GPOS7 into GPOS8, to match
+                    //the behaviour of fonttools.
+                    ChainOrNot::Context(lookup) if self.force_chain_pos => {
+                        self.forced_chain_pos.push(id);
+                        self.gpos
+                            .push(PositionLookup::ChainedContextual(lookup.convert()))
+                    }
+                    ChainOrNot::Context(lookup) => {
+                        self.gpos.push(PositionLookup::Contextual(lookup.convert()))
+                    }
                     ChainOrNot::Chain(lookup) => self
                         .gpos
                         .push(PositionLookup::ChainedContextual(lookup.convert())),
@@ -332,7 +753,17 @@ impl AllLookups {
     }
 
     pub(crate) fn get_named(&self, name: &str) -> Option<LookupId> {
-        self.named.get(name).copied()
+        self.named.get(name).map(|(id, _)| *id)
+    }
+
+    /// The flags that a named lookup was defined with.
+    ///
+    /// Returns `None` if there is no lookup with this name, or if it has no rules.
+    pub(crate) fn named_flags(&self, name: &str) -> Option<LookupFlagInfo> {
+        self.named
+            .get(name)
+            .filter(|(id, _)| *id != LookupId::Empty)
+            .map(|(_, flags)| *flags)
     }
 
     pub(crate) fn current_mut(&mut self) -> Option<&mut SomeLookup> {
@@ -343,9 +774,35 @@ impl AllLookups {
         self.current.is_some()
     }
 
+    /// The number of (GPOS, GSUB) lookups, including the current lookup and
+    /// any anonymous lookups it has created.
+    pub(crate) fn lookup_counts(&self) -> (usize, usize) {
+        let (mut gpos, mut gsub) = (self.gpos.len(), self.gsub.len());
+        match &self.current {
+            Some(SomeLookup::GposLookup(_)) => gpos += 1,
+            Some(SomeLookup::GsubLookup(_)) => gsub += 1,
+            Some(SomeLookup::GposContextual(lookup)) => gpos += lookup.lookup_count(),
+            Some(SomeLookup::GsubContextual(lookup)) => gsub += lookup.lookup_count(),
+            None => (),
+        }
+        (gpos, gsub)
+    }
+
+    /// The id the current lookup will have once it is finished.
+    pub(crate) fn current_id(&self) -> Option<LookupId> {
+        match self.current.as_ref()? {
+            SomeLookup::GposLookup(_) => Some(LookupId::Gpos(self.gpos.len())),
+            SomeLookup::GsubLookup(_) => Some(LookupId::Gsub(self.gsub.len())),
+            SomeLookup::GposContextual(lookup) => Some(lookup.root_id),
+            SomeLookup::GsubContextual(lookup) => Some(lookup.root_id),
+        }
+    }
+
     /// should be called before each new rule.
-    pub(crate) fn needs_new_lookup(&self, kind: Kind) -> bool {
-        self.current.is_none() || self.current.as_ref().map(SomeLookup::kind) != Some(kind)
+    pub(crate) fn needs_new_lookup(&self, kind: Kind, flags: LookupFlagInfo) -> bool {
+        self.current.is_none()
+            || self.current.as_ref().map(SomeLookup::kind) != Some(kind)
+            || self.current_flags != flags
     }
 
     // `false` if we didn't have an active lookup
@@ -385,6 +842,7 @@ impl AllLookups {
             SomeLookup::GsubLookup(_) | SomeLookup::GposLookup(_) => (),
         }
         self.current = Some(new_one);
+        self.current_flags = flags;
         finished_id
     }
 
@@ -392,13 +850,14 @@ impl AllLookups {
         if let Some(lookup) = self.current.take() {
             let id = self.push(lookup);
             if let Some(name) = self.current_name.take() {
-                self.named.insert(name.clone(), id);
+                self.named.insert(name.clone(), (id, self.current_flags));
                 Some((id, Some(name)))
             } else {
                 Some((id, None))
             }
         } else if let Some(name) = self.current_name.take() {
-            self.named.insert(name.clone(), LookupId::Empty);
+            self.named
+                .insert(name.clone(), (LookupId::Empty, Default::default()));
             // there was a named block with no rules, return the empty lookup
             Some((LookupId::Empty, Some(name)))
         } else {
@@ -435,6 +894,107 @@ impl AllLookups {
         //TODO: the spec says to do gsub too, but fonttools doesn't?
     }
 
+    /// The mark classes of a mark-to-base lookup, and the bases with an anchor
+    /// for each, or `None` if this is not a mark-to-base lookup.
+    pub(crate) fn mark_to_base_classes(
+        &self,
+        id: LookupId,
+    ) -> Option<Vec<(SmolStr, Vec<GlyphId>)>> {
+        let LookupId::Gpos(idx) = id else {
+            return None;
+        };
+        let PositionLookup::MarkToBase(lookup) = self.gpos.get(idx)? else {
+            return None;
+        };
+        Some(
+            lookup
+                .subtables
+                .iter()
+                .flat_map(|subtable| subtable.class_bases())
+                .map(|(name, bases)| (name.into(), bases))
+                .collect(),
+        )
+    }
+
+    /// The pairs of classes kerned by any of these pair positioning lookups.
+    pub(crate) fn pair_pos_pairs(&self, ids: &[LookupId]) -> Vec<(GlyphClass, GlyphClass)> {
+        let mut pairs = Vec::new();
+        for id in ids {
+            if let LookupId::Gpos(idx) = id {
+                if let Some(PositionLookup::Pair(lookup)) = self.gpos.get(*idx) {
+                    lookup
+                        .subtables
+                        .iter()
+                        .for_each(|subtable| pairs.extend(subtable.covered_class_pairs()));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// The base glyphs of all mark-to-base lookups, and the base marks of all
+    /// mark-to-mark lookups.
+    pub(crate) fn mark_attachment_bases(&self) -> (HashSet<GlyphId>, HashSet<GlyphId>) {
+        let mut bases = HashSet::new();
+        let mut base_marks = HashSet::new();
+        for lookup in &self.gpos {
+            match lookup {
+                PositionLookup::MarkToBase(lookup) => lookup
+                    .subtables
+                    .iter()
+                    .for_each(|subtable| bases.extend(subtable.base_glyphs())),
+                PositionLookup::MarkToMark(lookup) => lookup
+                    .subtables
+                    .iter()
+                    .for_each(|subtable| base_marks.extend(subtable.mark2_glyphs())),
+                _ => (),
+            }
+        }
+        (bases, base_marks)
+    }
+
+    /// The ligature glyphs produced by each ligature substitution lookup.
+    pub(crate) fn ligature_glyphs(&self) -> Vec<(LookupId, BTreeSet<GlyphId>)> {
+        self.gsub
+            .iter()
+            .enumerate()
+            .filter_map(|(i, lookup)| match lookup {
+                SubstitutionLookup::Ligature(lookup) => Some((
+                    LookupId::Gsub(i),
+                    lookup
+                        .subtables
+                        .iter()
+                        .flat_map(LigatureSubBuilder::ligature_glyphs)
+                        .collect(),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Contextual positioning lookups that were compiled as chained
+    /// contextual lookups, because of [`Opts::force_chain_pos`].
+    pub(crate) fn forced_chain_pos(&self) -> &[LookupId] {
+        &self.forced_chain_pos
+    }
+
+    /// For a cursive lookup, whether any glyph has an entry anchor, and whether
+    /// any glyph has an exit anchor.
+    pub(crate) fn cursive_entry_and_exit(&self, id: LookupId) -> Option<(bool, bool)> {
+        let LookupId::Gpos(idx) = id else {
+            return None;
+        };
+        match self.gpos.get(idx)? {
+            PositionLookup::Cursive(lookup) => Some(
+                lookup
+                    .iter_subtables()
+                    .map(CursivePosBuilder::has_entry_and_exit)
+                    .fold((false, false), |acc, sub| (acc.0 || sub.0, acc.1 || sub.1)),
+            ),
+            _ => None,
+        }
+    }
+
     /// Return the aalt-relevant lookups for this lookup Id.
     ///
     /// If lookup is GSUB type 1 or 3, return a single lookup.
@@ -504,57 +1064,223 @@ impl AllLookups {
             ))
         });
 
-        let lookups = one.into_iter().chain(two).collect::<Vec<_>>();
-        let lookup_ids = (0..lookups.len()).map(LookupId::Gsub).collect();
+        // these go at the front of the lookup list, but we append them for now
+        // so that existing ids stay valid; they are moved in `finalize_ids`.
+        let first_id = self.gsub.len();
+        self.gsub.extend(one.into_iter().chain(two));
+        let lookup_ids = (first_id..self.gsub.len())
+            .map(LookupId::Gsub)
+            .collect::<Vec<_>>();
+        self.aalt_ids.extend(lookup_ids.iter().copied());
+        lookup_ids
+    }
 
-        // now we need to insert these lookups at the front of our gsub lookups,
-        // and bump all of their ids:
+    /// Remove any rules that reference glyphs for which `keep` returns `false`,
+    /// and then any lookups that are left empty.
+    ///
+    /// Returns the ids of the removed lookups. These ids remain valid until
+    /// [`finalize_ids`](Self::finalize_ids) is called, but the lookups they
+    /// refer to no longer contain any rules.
+    pub(crate) fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) -> HashSet<LookupId> {
+        self.gpos
+            .iter_mut()
+            .for_each(|lookup| lookup.retain_glyphs(keep));
+        self.gsub
+            .iter_mut()
+            .for_each(|lookup| lookup.retain_glyphs(keep));
+
+        // removing references to an empty lookup can leave a contextual
+        // lookup empty in turn, so we go until nothing changes
+        let mut empty = HashSet::new();
+        loop {
+            let now_empty = self.empty_lookups();
+            if now_empty.len() == empty.len() {
+                break;
+            }
+            empty = now_empty;
+            self.remap_lookups(&|id| (!empty.contains(&id)).then_some(id));
+        }
+        self.removed.extend(empty.iter().copied());
+        empty
+    }
 
-        self.gsub.iter_mut().for_each(|lookup| match lookup {
-            SubstitutionLookup::Contextual(lookup) => lookup
-                .subtables
-                .iter_mut()
-                .for_each(|sub| sub.bump_all_lookup_ids(lookups.len())),
-            SubstitutionLookup::ChainedContextual(lookup) => lookup
-                .subtables
-                .iter_mut()
-                .for_each(|sub| sub.bump_all_lookup_ids(lookups.len())),
-            _ => (),
-        });
+    /// Remove the lookups that can be reached from `dropped` but not from
+    /// `kept`, following the lookups referenced by contextual rules.
+    ///
+    /// Lookups that cannot be reached from either are left alone. As with
+    /// [`retain_glyphs`](Self::retain_glyphs), the removed lookups are dropped
+    /// when ids are finalized.
+    pub(crate) fn remove_unreachable(&mut self, kept: &[LookupId], dropped: &[LookupId]) {
+        let kept = self.reachable(kept);
+        let unreachable = self
+            .reachable(dropped)
+            .into_iter()
+            .filter(|id| !kept.contains(id));
+        self.removed.extend(unreachable);
+    }
 
-        let prev_lookups = std::mem::replace(&mut self.gsub, lookups);
-        self.gsub.extend(prev_lookups);
+    fn reachable(&self, roots: &[LookupId]) -> HashSet<LookupId> {
+        let mut seen = HashSet::new();
+        let mut stack = roots.to_vec();
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let referenced = match id {
+                LookupId::Gpos(idx) => self.gpos.get(idx).map(PositionLookup::referenced_lookups),
+                LookupId::Gsub(idx) => self
+                    .gsub
+                    .get(idx)
+                    .map(SubstitutionLookup::referenced_lookups),
+                LookupId::Empty => None,
+            };
+            stack.extend(referenced.into_iter().flatten());
+        }
+        seen
+    }
 
-        lookup_ids
+    /// Assign each lookup its final id, applying any pending removals and
+    /// reordering.
+    ///
+    /// This updates all references to lookups held here; the returned map
+    /// should be used to update any references held elsewhere.
+    pub(crate) fn finalize_ids(&mut self) -> LookupIdMap {
+        let gpos_order = (0..self.gpos.len()).map(LookupId::Gpos);
+        let gsub_order = self.aalt_ids.iter().copied().chain(
+            (0..self.gsub.len())
+                .map(LookupId::Gsub)
+                .filter(|id| !self.aalt_ids.contains(id)),
+        );
+        let mut map = LookupIdMap::default();
+        for (new_idx, id) in gpos_order
+            .filter(|id| !self.removed.contains(id))
+            .enumerate()
+        {
+            map.mapping.insert(id, LookupId::Gpos(new_idx));
+        }
+        for (new_idx, id) in gsub_order
+            .filter(|id| !self.removed.contains(id))
+            .enumerate()
+        {
+            map.mapping.insert(id, LookupId::Gsub(new_idx));
+        }
+
+        self.remap_lookups(&|id| Some(map.get(id)).filter(|id| *id != LookupId::Empty));
+        self.gpos = map.reorder(std::mem::take(&mut self.gpos), LookupId::Gpos);
+        self.gsub = map.reorder(std::mem::take(&mut self.gsub), LookupId::Gsub);
+        for (id, _) in self.named.values_mut() {
+            *id = map.get(*id);
+        }
+        self.removed.clear();
+        self.aalt_ids.clear();
+        map
+    }
+
+    fn empty_lookups(&self) -> HashSet<LookupId> {
+        let gpos = self
+            .gpos
+            .iter()
+            .enumerate()
+            .filter(|(_, lookup)| lookup.is_empty())
+            .map(|(i, _)| LookupId::Gpos(i));
+        let gsub = self
+            .gsub
+            .iter()
+            .enumerate()
+            .filter(|(_, lookup)| lookup.is_empty())
+            .map(|(i, _)| LookupId::Gsub(i));
+        gpos.chain(gsub).collect()
+    }
+
+    fn remap_lookups(&mut self, f: &dyn Fn(LookupId) -> Option<LookupId>) {
+        self.gpos
+            .iter_mut()
+            .for_each(|lookup| lookup.remap_lookups(f));
+        self.gsub
+            .iter_mut()
+            .for_each(|lookup| lookup.remap_lookups(f));
+    }
+
+    /// Check that every lookup id can be written as a 16-bit index into the
+    /// lookup list of its own table, and that every lookup can be written.
+    ///
+    /// After this passes, converting any of our lookup ids cannot fail.
+    fn check_lookup_ids(&self) -> Result<(), CompileError> {
+        for (table, count) in [(tags::GSUB, self.gsub.len()), (tags::GPOS, self.gpos.len())] {
+            if count > u16::MAX as usize {
+                return Err(CompileError::TooManyLookups { table, count });
+            }
+        }
+
+        for id in self
+            .gpos
+            .iter()
+            .flat_map(PositionLookup::referenced_lookups)
+        {
+            id.to_gpos_id()?;
+        }
+        for (i, lookup) in self.gpos.iter().enumerate() {
+            if let PositionLookup::Pair(lookup) = lookup {
+                if lookup
+                    .subtables
+                    .iter()
+                    .any(PairPosBuilder::has_class_pair_devices)
+                {
+                    return Err(CompileError::ClassPairDevice { lookup: i });
+                }
+            }
+        }
+        for id in self
+            .gsub
+            .iter()
+            .flat_map(SubstitutionLookup::referenced_lookups)
+        {
+            id.to_gsub_id()?;
+        }
+        Ok(())
     }
 
     pub(crate) fn build(
         &self,
         features: &BTreeMap<FeatureKey, Vec<LookupId>>,
         required_features: &HashSet<FeatureKey>,
-    ) -> (Option<write_gsub::Gsub>, Option<write_gpos::Gpos>) {
-        let mut gpos_builder = PosSubBuilder::new(self.gpos.clone());
-        let mut gsub_builder = PosSubBuilder::new(self.gsub.clone());
+        prebuilt: PrebuiltLookups,
+    ) -> Result<(Option<write_gsub::Gsub>, Option<write_gpos::Gpos>), CompileError> {
+        self.check_lookup_ids()?;
+        let mut gpos = self.gpos.clone();
+        for lookup in gpos.iter_mut() {
+            if let PositionLookup::Pair(lookup) = lookup {
+                for sub in lookup.subtables.iter_mut() {
+                    if !self.pair_pos_class_0 {
+                        sub.set_use_class_0(false);
+                    }
+                    sub.set_compact_value_formats(self.compact_value_formats);
+                }
+            }
+        }
+        let order = self.feature_record_order;
+        let mut gpos_builder = PosSubBuilder::new(gpos, prebuilt.gpos, order);
+        let mut gsub_builder = PosSubBuilder::new(self.gsub.clone(), prebuilt.gsub, order);
 
         for (key, feature_indices) in features {
             let required = required_features.contains(key);
 
             if key.feature == tags::SIZE {
-                gpos_builder.add(*key, Vec::new(), required);
+                gpos_builder.add(*key, Vec::new(), required, tags::GPOS)?;
                 continue;
             }
 
-            let (gpos_idxes, gsub_idxes) = split_lookups(feature_indices);
+            let (gpos_idxes, gsub_idxes) = split_lookups(feature_indices)?;
             if !gpos_idxes.is_empty() {
-                gpos_builder.add(*key, gpos_idxes, required);
+                gpos_builder.add(*key, gpos_idxes, required, tags::GPOS)?;
             }
 
             if !gsub_idxes.is_empty() {
-                gsub_builder.add(*key, gsub_idxes, required);
+                gsub_builder.add(*key, gsub_idxes, required, tags::GSUB)?;
             }
         }
 
-        (gsub_builder.build(), gpos_builder.build())
+        Ok((gsub_builder.build(), gpos_builder.build()))
     }
 }
 
@@ -563,9 +1289,9 @@ impl AllLookups {
 /// In general, a feature only has either GSUB or GPOS lookups, but this is not
 /// a requirement, and in the wild we will encounter features that contain mixed
 /// lookups.
-fn split_lookups(lookups: &[LookupId]) -> (Vec<u16>, Vec<u16>) {
+pub(crate) fn split_lookups(lookups: &[LookupId]) -> Result<(Vec<u16>, Vec<u16>), CompileError> {
     if lookups.is_empty() {
-        return (Vec::new(), Vec::new());
+        return Ok((Vec::new(), Vec::new()));
     }
 
     // in the majority of cases, a given feature only has lookups of one kind,
@@ -576,15 +1302,21 @@ fn split_lookups(lookups: &[LookupId]) -> (Vec<u16>, Vec<u16>) {
         .all(|x| matches!(x, LookupId::Gpos(_)) == is_gpos)
     {
         if is_gpos {
-            return (
-                lookups.iter().map(|x| x.to_gpos_id_or_die()).collect(),
+            return Ok((
+                lookups
+                    .iter()
+                    .map(|x| x.to_gpos_id())
+                    .collect::<Result<_, _>>()?,
                 Vec::new(),
-            );
+            ));
         } else {
-            return (
+            return Ok((
                 Vec::new(),
-                lookups.iter().map(|x| x.to_gsub_id_or_die()).collect(),
-            );
+                lookups
+                    .iter()
+                    .map(|x| x.to_gsub_id())
+                    .collect::<Result<_, _>>()?,
+            ));
         }
     }
 
@@ -595,13 +1327,43 @@ fn split_lookups(lookups: &[LookupId]) -> (Vec<u16>, Vec<u16>) {
     let mut gsub = Vec::new();
     for lookup in lookups {
         match lookup {
-            LookupId::Gpos(_) => gpos.push(lookup.to_gpos_id_or_die()),
-            LookupId::Gsub(_) => gsub.push(lookup.to_gsub_id_or_die()),
+            LookupId::Gpos(_) => gpos.push(lookup.to_gpos_id()?),
+            LookupId::Gsub(_) => gsub.push(lookup.to_gsub_id()?),
             LookupId::Empty => (),
         }
     }
 
-    (gpos, gsub)
+    Ok((gpos, gsub))
+}
+
+impl LookupIdMap {
+    /// The final id of `id`, or [`LookupId::Empty`] if the lookup was removed.
+    pub(crate) fn get(&self, id: LookupId) -> LookupId {
+        self.mapping.get(&id).copied().unwrap_or(LookupId::Empty)
+    }
+
+    /// Update each id in `ids`, dropping any lookups that were removed.
+    pub(crate) fn remap_ids(&self, ids: &mut Vec<LookupId>) {
+        *ids = ids
+            .iter()
+            .map(|id| self.get(*id))
+            .filter(|id| *id != LookupId::Empty)
+            .collect();
+    }
+
+    /// Move each lookup to its final position, dropping any that were removed.
+    fn reorder<T>(&self, lookups: Vec<T>, make_id: fn(usize) -> LookupId) -> Vec<T> {
+        let mut slots = lookups
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, lookup)| {
+                let new_id = self.get(make_id(i));
+                (new_id != LookupId::Empty).then(|| (new_id.to_raw(), lookup))
+            })
+            .collect::<Vec<_>>();
+        slots.sort_unstable_by_key(|(idx, _)| *idx);
+        slots.into_iter().map(|(_, lookup)| lookup).collect()
+    }
 }
 
 impl LookupId {
@@ -613,20 +1375,47 @@ impl LookupId {
         }
     }
 
-    pub(crate) fn adjust_if_gsub(&mut self, value: usize) {
-        if let LookupId::Gsub(idx) = self {
-            *idx += value;
-        }
+    /// The index of this lookup in the GPOS lookup list.
+    pub(crate) fn to_gpos_id(self) -> Result<u16, CompileError> {
+        Self::to_u16(self, tags::GPOS)
     }
 
-    pub(crate) fn to_gpos_id_or_die(self) -> u16 {
-        let LookupId::Gpos(x) = self else { panic!("this *really* shouldn't happen") };
-        x.try_into().unwrap()
+    /// The index of this lookup in the GSUB lookup list.
+    pub(crate) fn to_gsub_id(self) -> Result<u16, CompileError> {
+        Self::to_u16(self, tags::GSUB)
     }
 
-    pub(crate) fn to_gsub_id_or_die(self) -> u16 {
-        let LookupId::Gsub(x) = self else { panic!("this *really* shouldn't happen") };
-        x.try_into().unwrap()
+    fn to_u16(self, table: Tag) -> Result<u16, CompileError> {
+        let idx = match self {
+            LookupId::Gpos(x) if table == tags::GPOS => x,
+            LookupId::Gsub(x) if table == tags::GSUB => x,
+            _ => return Err(CompileError::LookupTableMismatch { table }),
+        };
+        // the index of the last lookup is one less than the count
+        idx.try_into().map_err(|_| CompileError::TooManyLookups {
+            table,
+            count: idx + 1,
+        })
+    }
+}
+
+impl Display for LookupId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupId::Gpos(idx) | LookupId::Gsub(idx) => write!(f, "{idx}"),
+            LookupId::Empty => write!(f, "EMPTY"),
+        }
+    }
+}
+
+impl FeatureKey {
+    /// Create a key for a feature in the given script and language.
+    pub fn new(feature: Tag, script: Tag, language: Tag) -> Self {
+        FeatureKey {
+            feature,
+            language,
+            script,
+        }
     }
 }
 
@@ -711,10 +1500,23 @@ impl SomeLookup {
         }
     }
 
-    pub(crate) fn add_gpos_type_1(&mut self, id: GlyphId, record: ValueRecord) {
+    /// Add a single positioning rule.
+    ///
+    /// If this glyph is already positioned in the current subtable the
+    /// existing rule is kept; if its value differs, that value is returned.
+    /// Earlier subtables are not checked.
+    pub(crate) fn add_gpos_type_1(
+        &mut self,
+        id: GlyphId,
+        record: ValueRecord,
+    ) -> Option<ValueRecord> {
         if let SomeLookup::GposLookup(PositionLookup::Single(table)) = self {
             let subtable = table.last_mut().unwrap();
+            if let Some(existing) = subtable.get(id) {
+                return (existing != &record).then(|| existing.clone());
+            }
             subtable.insert(id, record);
+            None
         } else {
             panic!("lookup mismatch");
         }
@@ -735,13 +1537,15 @@ impl SomeLookup {
         }
     }
 
+    /// Returns `true` if the subtable receiving this rule has now grown too
+    /// large to be addressed with a 16-bit offset.
     pub(crate) fn add_gpos_type_2_class(
         &mut self,
         one: GlyphClass,
         two: GlyphClass,
         val_one: ValueRecord,
         val_two: ValueRecord,
-    ) {
+    ) -> bool {
         if let SomeLookup::GposLookup(PositionLookup::Pair(table)) = self {
             let subtable = table.last_mut().unwrap();
             subtable.insert_classes(one, val_one, two, val_two)
@@ -749,15 +1553,87 @@ impl SomeLookup {
             panic!("lookup mismatch");
         }
     }
+
+    /// The number of subtables in this pair positioning lookup, which
+    /// identifies the subtable that new rules are added to.
+    pub(crate) fn pair_pos_subtable_count(&self) -> usize {
+        let SomeLookup::GposLookup(PositionLookup::Pair(table)) = self else {
+            panic!("lookup mismatch");
+        };
+        table.subtables.len()
+    }
+
+    /// The classes of the rule in the current subtable that covers this
+    /// glyph pair, if it has a different value.
+    pub(crate) fn pair_pos_class_conflict(
+        &self,
+        one: GlyphId,
+        two: GlyphId,
+        values: &(ValueRecord, ValueRecord),
+    ) -> Option<(GlyphClass, GlyphClass)> {
+        let SomeLookup::GposLookup(PositionLookup::Pair(table)) = self else {
+            panic!("lookup mismatch");
+        };
+        let (class1, class2, existing) = table.subtables.last()?.class_rule(one, two)?;
+        (existing != values).then(|| (class1.clone(), class2.clone()))
+    }
+
+    /// The glyph pairs in the current subtable that are covered by these
+    /// classes, and have a different value.
+    pub(crate) fn pair_pos_glyph_conflicts(
+        &self,
+        one: &GlyphClass,
+        two: &GlyphClass,
+        values: &(ValueRecord, ValueRecord),
+    ) -> Vec<(GlyphId, GlyphId)> {
+        let SomeLookup::GposLookup(PositionLookup::Pair(table)) = self else {
+            panic!("lookup mismatch");
+        };
+        let Some(subtable) = table.subtables.last() else {
+            return Vec::new();
+        };
+        subtable
+            .glyph_pairs_in(one, two)
+            .filter(|(_, _, existing)| *existing != values)
+            .map(|(one, two, _)| (one, two))
+            .collect()
+    }
+
+    /// The glyphs of `class` that are in the first class of a class-based
+    /// pair rule before the last 'subtable' statement.
+    ///
+    /// Pairs starting with these glyphs are handled by that earlier subtable,
+    /// so a rule after the break will never apply to them.
+    pub(crate) fn pair_pos_glyphs_before_break(&self, class: &GlyphClass) -> Vec<GlyphId> {
+        let SomeLookup::GposLookup(PositionLookup::Pair(table)) = self else {
+            panic!("lookup mismatch");
+        };
+        let Some((_, earlier)) = table.subtables.split_last() else {
+            return Vec::new();
+        };
+        class
+            .iter()
+            .filter(|gid| earlier.iter().any(|sub| sub.covers_first_class_glyph(*gid)))
+            .collect()
+    }
+
+    /// Add the entry and exit anchors of a glyph.
+    ///
+    /// Returns `true` if the glyph already has different anchors in this
+    /// lookup, in which case nothing is added.
     pub(crate) fn add_gpos_type_3(
         &mut self,
         id: GlyphId,
         entry: Option<AnchorTable>,
         exit: Option<AnchorTable>,
-    ) {
+    ) -> bool {
         if let SomeLookup::GposLookup(PositionLookup::Cursive(table)) = self {
+            if let Some(existing) = table.subtables.iter().find(|sub| sub.contains(id)) {
+                return !existing.has_anchors(id, entry.as_ref(), exit.as_ref());
+            }
             let subtable = table.last_mut().unwrap();
             subtable.insert(id, entry, exit);
+            false
         } else {
             panic!("lookup mismatch");
         }
@@ -808,10 +1684,19 @@ impl SomeLookup {
         }
     }
 
-    pub(crate) fn add_gsub_type_1(&mut self, id: GlyphId, replacement: GlyphId) {
+    /// Add a single substitution rule.
+    ///
+    /// If this glyph is already substituted in the current subtable the
+    /// existing rule is kept; if its replacement differs, that replacement is
+    /// returned. Earlier subtables are not checked.
+    pub(crate) fn add_gsub_type_1(&mut self, id: GlyphId, replacement: GlyphId) -> Option<GlyphId> {
         if let SomeLookup::GsubLookup(SubstitutionLookup::Single(table)) = self {
             let subtable = table.last_mut().unwrap();
+            if let Some(existing) = subtable.get(id) {
+                return (existing != replacement).then_some(existing);
+            }
             subtable.insert(id, replacement);
+            None
         } else {
             panic!("lookup mismatch");
         }
@@ -835,10 +1720,22 @@ impl SomeLookup {
         }
     }
 
-    pub(crate) fn add_gsub_type_4(&mut self, target: Vec<GlyphId>, replacement: GlyphId) {
+    /// Add a ligature substitution.
+    ///
+    /// If this sequence is already substituted by a different glyph in this
+    /// lookup, the rule is ignored and the existing replacement is returned.
+    pub(crate) fn add_gsub_type_4(
+        &mut self,
+        target: Vec<GlyphId>,
+        replacement: GlyphId,
+    ) -> Option<GlyphId> {
         if let SomeLookup::GsubLookup(SubstitutionLookup::Ligature(table)) = self {
+            if let Some(existing) = table.subtables.iter().find_map(|sub| sub.get(&target)) {
+                return (existing != replacement).then_some(existing);
+            }
             let subtable = table.last_mut().unwrap();
             subtable.insert(target, replacement);
+            None
         } else {
             panic!("lookup mismatch");
         }
@@ -874,22 +1771,56 @@ impl SomeLookup {
     }
 }
 
-impl<T> PosSubBuilder<T> {
-    fn new(lookups: Vec<T>) -> Self {
+/// A lookup, or the output of building the same lookup in an earlier compilation.
+enum MaybeBuilt<T: Builder> {
+    Unbuilt(T),
+    Built(T::Output),
+}
+
+impl<T: Builder> PosSubBuilder<T> {
+    fn new(
+        lookups: Vec<T>,
+        mut prebuilt: HashMap<usize, T::Output>,
+        feature_order: FeatureRecordOrder,
+    ) -> Self {
+        let lookups = lookups
+            .into_iter()
+            .enumerate()
+            .map(|(i, lookup)| match prebuilt.remove(&i) {
+                Some(output) => MaybeBuilt::Built(output),
+                None => MaybeBuilt::Unbuilt(lookup),
+            })
+            .collect();
         PosSubBuilder {
             lookups,
             scripts: Default::default(),
             features: Default::default(),
+            feature_order,
         }
     }
 
-    fn add(&mut self, key: FeatureKey, lookups: Vec<u16>, required: bool) {
+    fn add(
+        &mut self,
+        key: FeatureKey,
+        lookups: Vec<u16>,
+        required: bool,
+        table: Tag,
+    ) -> Result<(), CompileError> {
         let feat_key = (key.feature, lookups);
         let next_feature = self.features.len();
-        let idx = *self
-            .features
-            .entry(feat_key)
-            .or_insert_with(|| next_feature.try_into().expect("ran out of u16s"));
+        let idx = match self.features.get(&feat_key) {
+            Some(idx) => *idx,
+            None => {
+                let idx = next_feature
+                    .try_into()
+                    .map_err(|_| CompileError::TooManyFeatures {
+                        table,
+                        count: next_feature + 1,
+                    })?;
+                self.features.insert(feat_key, idx);
+                idx
+            }
+        };
 
         let lang_sys = self
             .scripts
@@ -903,31 +1834,61 @@ impl<T> PosSubBuilder<T> {
         } else {
             lang_sys.feature_indices.push(idx);
         }
+        Ok(())
     }
 }
 
 impl<T> PosSubBuilder<T>
 where
-    T: Builder,
-    T::Output: Default,
+    T: Builder + MaybeSend,
+    T::Output: Default + MaybeSend,
 {
-    fn build_raw(self) -> Option<(LookupList<T::Output>, ScriptList, FeatureList)> {
+    fn build_raw(self, table: Tag) -> Option<(LookupList<T::Output>, ScriptList, FeatureList)> {
         if self.lookups.is_empty() && self.features.is_empty() {
             return None;
         }
-
-        // push empty items so we can insert by index
-        let mut features = vec![Default::default(); self.features.len()];
-        for ((tag, lookups), idx) in self.features {
-            features[idx as usize] = FeatureRecord::new(tag, Feature::new(None, lookups));
+        let start = Instant::now();
+        let reused = self
+            .lookups
+            .iter()
+            .filter(|lookup| matches!(lookup, MaybeBuilt::Built(_)))
+            .count();
+
+        // feature records must be sorted by tag. Indices are assigned in the
+        // order features are added (by tag, then language, then script) so
+        // sorting by index as well gives the order of first use.
+        let mut features = self.features.into_iter().collect::<Vec<_>>();
+        match self.feature_order {
+            FeatureRecordOrder::FirstLanguageSystem => {
+                features.sort_by_key(|((tag, _), idx)| (*tag, *idx))
+            }
+            FeatureRecordOrder::LookupIndices => features.sort(),
+        }
+        let mut new_indices = vec![0; features.len()];
+        for (new_idx, (_, old_idx)) in features.iter().enumerate() {
+            // there are at most u16::MAX features, checked when they're added
+            new_indices[*old_idx as usize] = new_idx as u16;
         }
+        let features = features
+            .into_iter()
+            .map(|((tag, lookups), _)| FeatureRecord::new(tag, Feature::new(None, lookups)))
+            .collect::<Vec<_>>();
 
+        // scripts and language systems are sorted by tag, via the BTreeMap
         let scripts = self
             .scripts
             .into_iter()
             .map(|(script_tag, entry)| {
                 let mut script = Script::default();
-                for (lang_tag, lang_sys) in entry {
+                for (lang_tag, mut lang_sys) in entry {
+                    for idx in lang_sys.feature_indices.iter_mut() {
+                        *idx = new_indices[*idx as usize];
+                    }
+                    lang_sys.feature_indices.sort_unstable();
+                    if lang_sys.required_feature_index != 0xFFFF {
+                        lang_sys.required_feature_index =
+                            new_indices[lang_sys.required_feature_index as usize];
+                    }
                     if lang_tag == tags::LANG_DFLT {
                         script.default_lang_sys = lang_sys.into();
                     } else {
@@ -940,7 +1901,12 @@ where
             })
             .collect::<Vec<_>>();
 
-        let lookups = self.lookups.into_iter().map(|x| x.build()).collect();
+        let n_lookups = self.lookups.len();
+        let lookups = build_lookups(table, self.lookups);
+        log::debug!(
+            "built {table} with {n_lookups} lookups ({reused} reused) in {:?}",
+            start.elapsed()
+        );
         Some((
             LookupList::new(lookups),
             ScriptList::new(scripts),
@@ -953,7 +1919,7 @@ impl Builder for PosSubBuilder<PositionLookup> {
     type Output = Option<write_gpos::Gpos>;
 
     fn build(self) -> Self::Output {
-        self.build_raw()
+        self.build_raw(tags::GPOS)
             .map(|(lookups, scripts, features)| write_gpos::Gpos::new(scripts, features, lookups))
     }
 }
@@ -962,11 +1928,50 @@ impl Builder for PosSubBuilder<SubstitutionLookup> {
     type Output = Option<write_gsub::Gsub>;
 
     fn build(self) -> Self::Output {
-        self.build_raw()
+        self.build_raw(tags::GSUB)
             .map(|(lookups, scripts, features)| write_gsub::Gsub::new(scripts, features, lookups))
     }
 }
 
+/// Build each lookup in the list.
+///
+/// Lookups are independent of one another, so if the `parallel` feature is
+/// enabled we build them concurrently.
+#[cfg(feature = "parallel")]
+fn build_lookups<T>(table: Tag, lookups: Vec<MaybeBuilt<T>>) -> Vec<T::Output>
+where
+    T: Builder + Send,
+    T::Output: Send,
+{
+    use rayon::prelude::*;
+    lookups
+        .into_par_iter()
+        .enumerate()
+        .map(|(i, lookup)| build_lookup(table, i, lookup))
+        .collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn build_lookups<T: Builder>(table: Tag, lookups: Vec<MaybeBuilt<T>>) -> Vec<T::Output> {
+    lookups
+        .into_iter()
+        .enumerate()
+        .map(|(i, lookup)| build_lookup(table, i, lookup))
+        .collect()
+}
+
+fn build_lookup<T: Builder>(table: Tag, index: usize, lookup: MaybeBuilt<T>) -> T::Output {
+    match lookup {
+        MaybeBuilt::Unbuilt(lookup) => {
+            let start = Instant::now();
+            let output = lookup.build();
+            log::trace!("built {table} lookup {index} in {:?}", start.elapsed());
+            output
+        }
+        MaybeBuilt::Built(output) => output,
+    }
+}
+
 fn is_gpos_rule(kind: Kind) -> bool {
     matches!(
         kind,
@@ -980,3 +1985,85 @@ fn is_gpos_rule(kind: Kind) -> bool {
             | Kind::GposType8
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_id_conversion() {
+        assert_eq!(LookupId::Gpos(5).to_gpos_id(), Ok(5));
+        assert_eq!(
+            LookupId::Gpos(5).to_gsub_id(),
+            Err(CompileError::LookupTableMismatch { table: tags::GSUB })
+        );
+        assert_eq!(
+            LookupId::Gsub(u16::MAX as usize + 1).to_gsub_id(),
+            Err(CompileError::TooManyLookups {
+                table: tags::GSUB,
+                count: u16::MAX as usize + 2
+            })
+        );
+        assert!(split_lookups(&[LookupId::Gpos(1), LookupId::Gsub(70_000)]).is_err());
+    }
+
+    #[test]
+    fn too_many_lookups_is_an_error() {
+        let mut lookups = AllLookups::default();
+        let single = SubstitutionLookup::Single(LookupBuilder::new(LookupFlag::empty(), None));
+        lookups.gsub = vec![single; u16::MAX as usize + 1];
+        let result = lookups.build(&Default::default(), &Default::default(), Default::default());
+        assert_eq!(
+            result.err(),
+            Some(CompileError::TooManyLookups {
+                table: tags::GSUB,
+                count: u16::MAX as usize + 1
+            })
+        );
+    }
+
+    #[test]
+    fn finalize_ids_removes_and_reorders() {
+        let single = |flags| {
+            SubstitutionLookup::Single(LookupBuilder::new(
+                LookupFlag::from_bits_truncate(flags),
+                None,
+            ))
+        };
+        let mut lookups = AllLookups {
+            gsub: vec![single(1), single(2), single(4)],
+            ..Default::default()
+        };
+        lookups
+            .named
+            .insert("removed".into(), (LookupId::Gsub(1), Default::default()));
+        lookups
+            .named
+            .insert("kept".into(), (LookupId::Gsub(2), Default::default()));
+        lookups.removed.insert(LookupId::Gsub(1));
+        let aalt = lookups.insert_aalt_lookups([(GlyphId::new(1), vec![GlyphId::new(2)])].into());
+        // existing ids are unchanged until we finalize
+        assert_eq!(aalt, [LookupId::Gsub(3)]);
+
+        let map = lookups.finalize_ids();
+        assert_eq!(map.get(LookupId::Gsub(3)), LookupId::Gsub(0));
+        assert_eq!(map.get(LookupId::Gsub(0)), LookupId::Gsub(1));
+        assert_eq!(map.get(LookupId::Gsub(1)), LookupId::Empty);
+        assert_eq!(map.get(LookupId::Gsub(2)), LookupId::Gsub(2));
+        let flags = lookups
+            .gsub
+            .iter()
+            .map(|lookup| match lookup {
+                SubstitutionLookup::Single(lookup) => lookup.flags.to_bits(),
+                _ => panic!("only single lookups here"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(flags, [0, 1, 4]);
+        assert_eq!(lookups.get_named("removed"), Some(LookupId::Empty));
+        assert_eq!(lookups.get_named("kept"), Some(LookupId::Gsub(2)));
+
+        let mut ids = vec![LookupId::Gsub(1), LookupId::Gsub(2), LookupId::Gsub(3)];
+        map.remap_ids(&mut ids);
+        assert_eq!(ids, [LookupId::Gsub(2), LookupId::Gsub(0)]);
+    }
+}
@@ -3,6 +3,7 @@
 use std::{
     collections::{BTreeMap, HashMap},
     convert::TryInto,
+    fmt::{Display, Formatter},
 };
 
 use write_fonts::{
@@ -17,11 +18,11 @@ use write_fonts::{
     FontWrite,
 };
 
-use crate::common::GlyphOrClass;
+use crate::common::{GlyphClass, GlyphOrClass};
 
 use super::{
-    Builder, ClassDefBuilder2, FilterSetId, LookupBuilder, LookupId, PositionLookup,
-    SubstitutionLookup,
+    Builder, ClassDefBuilder2, FilterSetId, LookupBuilder, LookupId, PositionLookup, RetainGlyphs,
+    RuleCount, SubstitutionLookup,
 };
 
 /// When building a contextual/chaining contextual rule, we also build a
@@ -32,6 +33,9 @@ pub(crate) struct ContextualLookupBuilder<T> {
     mark_set: Option<FilterSetId>,
     subtables: Vec<ContextBuilder>,
     anon_lookups: Vec<T>,
+    // anonymous lookups before this index were created before an explicit
+    // subtable break, and are not reused for identical inline rules.
+    first_shareable_lookup: usize,
     pub(super) root_id: LookupId,
     force_subtable_break: bool,
 }
@@ -49,6 +53,7 @@ impl<T> ContextualLookupBuilder<T> {
             flags,
             mark_set,
             anon_lookups: Vec::new(),
+            first_shareable_lookup: 0,
             subtables: vec![Default::default()],
             root_id: LookupId::Empty,
             force_subtable_break: false,
@@ -75,6 +80,11 @@ impl<T> ContextualLookupBuilder<T> {
         (lookup, anon_lookups)
     }
 
+    /// The number of lookups this will produce, including anonymous lookups
+    pub(crate) fn lookup_count(&self) -> usize {
+        1 + self.anon_lookups.len()
+    }
+
     //TODO: if we keep this, make it unwrap and ensure we always have a subtable
     pub fn last_mut(&mut self) -> &mut ContextBuilder {
         self.subtables.last_mut().unwrap()
@@ -82,9 +92,23 @@ impl<T> ContextualLookupBuilder<T> {
 
     pub fn force_subtable_break(&mut self) {
         self.subtables.push(Default::default());
+        self.first_shareable_lookup = self.anon_lookups.len();
         self.force_subtable_break = true;
     }
 
+    /// The index of an anonymous lookup that already performs an inline
+    /// rule's action, if one exists.
+    ///
+    /// Identical inline rules in different contextual rules can then share a
+    /// single lookup, instead of each adding another.
+    fn find_shareable_lookup(&self, performs_action: impl Fn(&T) -> bool) -> Option<usize> {
+        self.anon_lookups
+            .iter()
+            .enumerate()
+            .skip(self.first_shareable_lookup)
+            .find_map(|(i, lookup)| performs_action(lookup).then_some(i))
+    }
+
     fn add_new_lookup_if_necessary(
         &mut self,
         check_fn: impl FnOnce(&T) -> bool,
@@ -104,8 +128,8 @@ impl<T> ContextualLookupBuilder<T> {
 }
 
 impl ContextualLookupBuilder<PositionLookup> {
-    fn current_anon_lookup_id(&self) -> LookupId {
-        LookupId::Gpos(self.root_id.to_raw() + self.anon_lookups.len())
+    fn anon_lookup_id(&self, index: usize) -> LookupId {
+        LookupId::Gpos(self.root_id.to_raw() + index + 1)
     }
 
     pub(crate) fn add_anon_gpos_type_1(
@@ -113,18 +137,31 @@ impl ContextualLookupBuilder<PositionLookup> {
         glyphs: &GlyphOrClass,
         value: ValueRecord,
     ) -> LookupId {
-        self.add_new_lookup_if_necessary(
-            |existing| match existing {
-                PositionLookup::Single(lookup) => lookup
+        // like feaLib, we use the first lookup (since the last subtable break)
+        // that either already has these values or doesn't position these
+        // glyphs at all, and only add a lookup if there is no such lookup.
+        let existing = self.find_shareable_lookup(|lookup| match lookup {
+            PositionLookup::Single(lookup) => glyphs.iter().all(|gid| {
+                lookup
                     .subtables
                     .iter()
-                    .any(|subt| glyphs.iter().any(|gid| !subt.can_add_rule(gid, &value))),
-                _ => true,
-            },
-            |flags, mark_set| PositionLookup::Single(super::LookupBuilder::new(flags, mark_set)),
-        );
-        let lookup = self.anon_lookups.last_mut().unwrap();
-        let PositionLookup::Single(lookup) = lookup else {
+                    .all(|subt| subt.can_add_rule(gid, &value))
+            }),
+            _ => false,
+        });
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                self.force_subtable_break = false;
+                self.anon_lookups
+                    .push(PositionLookup::Single(LookupBuilder::new(
+                        self.flags,
+                        self.mark_set,
+                    )));
+                self.anon_lookups.len() - 1
+            }
+        };
+        let PositionLookup::Single(lookup) = &mut self.anon_lookups[index] else {
             panic!("this shouldn't happen");
         };
 
@@ -132,13 +169,17 @@ impl ContextualLookupBuilder<PositionLookup> {
         for id in glyphs.iter() {
             sub.insert(id, value.clone());
         }
-        self.current_anon_lookup_id()
+        self.anon_lookup_id(index)
     }
 }
 
 impl ContextualLookupBuilder<SubstitutionLookup> {
+    fn anon_lookup_id(&self, index: usize) -> LookupId {
+        LookupId::Gsub(self.root_id.to_raw() + index + 1)
+    }
+
     fn current_anon_lookup_id(&self) -> LookupId {
-        LookupId::Gsub(self.root_id.to_raw() + self.anon_lookups.len())
+        self.anon_lookup_id(self.anon_lookups.len() - 1)
     }
 
     pub(crate) fn add_anon_gsub_type_1(
@@ -146,6 +187,19 @@ impl ContextualLookupBuilder<SubstitutionLookup> {
         target: GlyphOrClass,
         replacement: GlyphOrClass,
     ) -> LookupId {
+        let existing = self.find_shareable_lookup(|lookup| match lookup {
+            SubstitutionLookup::Single(lookup) => target
+                .iter()
+                .zip(replacement.clone().into_iter_for_target())
+                .all(|(target, replacement)| {
+                    lookup.subtables.iter().find_map(|subt| subt.get(target)) == Some(replacement)
+                }),
+            _ => false,
+        });
+        if let Some(index) = existing {
+            return self.anon_lookup_id(index);
+        }
+
         // do we need a new lookup or can we use the existing one?
         self.add_new_lookup_if_necessary(
             |existing| match existing {
@@ -176,6 +230,17 @@ impl ContextualLookupBuilder<SubstitutionLookup> {
         target: Vec<GlyphId>,
         replacement: GlyphId,
     ) -> LookupId {
+        let existing = self.find_shareable_lookup(|lookup| match lookup {
+            SubstitutionLookup::Ligature(lookup) => lookup
+                .subtables
+                .iter()
+                .any(|subt| subt.get(&target) == Some(replacement)),
+            _ => false,
+        });
+        if let Some(index) = existing {
+            return self.anon_lookup_id(index);
+        }
+
         // do we need a new lookup or can we use the existing one?
         self.add_new_lookup_if_necessary(
             |existing| match existing {
@@ -253,11 +318,16 @@ impl ContextBuilder {
         })
     }
 
-    // for adjusting ids if we insert aalt at the front
-    pub(crate) fn bump_all_lookup_ids(&mut self, by: usize) {
-        self.rules
-            .iter_mut()
-            .for_each(|rule| rule.bump_all_lookup_ids(by))
+    /// Replace each referenced lookup with `f(id)`, or remove it if that is `None`.
+    ///
+    /// A rule whose lookups are all removed is kept, with no lookups: it still
+    /// matches, which stops later rules in the subtable from applying.
+    pub(crate) fn remap_lookups(&mut self, f: &dyn Fn(LookupId) -> Option<LookupId>) {
+        for rule in &mut self.rules {
+            for (_, ids) in &mut rule.context {
+                *ids = ids.iter().filter_map(|id| f(*id)).collect();
+            }
+        }
     }
 
     /// Iterate all referenced lookups
@@ -331,22 +401,134 @@ impl ContextBuilder {
 
         Some(write_layout::SequenceContext::format_1(coverage, rule_sets))
     }
+
+    /// If the input sequences can be expressed as a class def, generate format 2
+    fn build_format_2(&self, in_gpos: bool) -> Option<write_layout::SequenceContext> {
+        let (input_class_def, input_map) = self.input_class_def()?.build();
+        let coverage = self
+            .rules
+            .iter()
+            .flat_map(|rule| rule.first_input_sequence_item().iter())
+            .collect::<CoverageTableBuilder>()
+            .build();
+
+        let mut rule_sets = vec![Vec::new(); input_map.len() + 1];
+        for rule in &self.rules {
+            let cls_idx = *input_map
+                .get(&rule.first_input_sequence_item().to_class().unwrap())
+                .unwrap();
+            let input = rule
+                .context
+                .iter()
+                .skip(1)
+                .map(|(cls, _)| input_map.get(&cls.to_class().unwrap()).unwrap())
+                .copied()
+                .collect();
+            rule_sets[cls_idx as usize].push(write_layout::ClassSequenceRule::new(
+                input,
+                rule.lookup_records(in_gpos),
+            ));
+        }
+        let rule_sets = rule_sets
+            .into_iter()
+            .map(|rules| {
+                (!rules.is_empty()).then_some(write_layout::ClassSequenceRuleSet::new(rules))
+            })
+            .collect();
+
+        Some(write_layout::SequenceContext::format_2(
+            coverage,
+            input_class_def,
+            rule_sets,
+        ))
+    }
 }
 
 impl SubContextBuilder {
     pub(crate) fn iter_lookups(&self) -> impl Iterator<Item = LookupId> + '_ {
         self.0.iter_lookups()
     }
+
+    pub(crate) fn remap_lookups(&mut self, f: &dyn Fn(LookupId) -> Option<LookupId>) {
+        self.0.remap_lookups(f)
+    }
 }
 
-impl ContextRule {
-    pub(crate) fn bump_all_lookup_ids(&mut self, by: usize) {
-        for (_, lookups) in &mut self.context {
-            lookups
+impl PosContextBuilder {
+    pub(crate) fn iter_lookups(&self) -> impl Iterator<Item = LookupId> + '_ {
+        self.0.iter_lookups()
+    }
+
+    pub(crate) fn remap_lookups(&mut self, f: &dyn Fn(LookupId) -> Option<LookupId>) {
+        self.0.remap_lookups(f)
+    }
+}
+
+impl RetainGlyphs for ContextBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.rules.retain_mut(|rule| {
+            rule.backtrack
                 .iter_mut()
-                .for_each(|x| *x = LookupId::Gsub(x.to_raw() + by))
+                .chain(rule.context.iter_mut().map(|(glyphs, _)| glyphs))
+                .chain(rule.lookahead.iter_mut())
+                .all(|item| retain_in_item(item, keep))
+        })
+    }
+}
+
+impl RetainGlyphs for ReverseChainBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.rules.retain_mut(|rule| {
+            rule.context
+                .retain(|target, replacement| keep(*target) && keep(*replacement));
+            !rule.context.is_empty()
+                && rule
+                    .backtrack
+                    .iter_mut()
+                    .chain(rule.lookahead.iter_mut())
+                    .all(|item| retain_in_item(item, keep))
+        })
+    }
+}
+
+impl RetainGlyphs for PosContextBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.0.retain_glyphs(keep)
+    }
+}
+
+impl RetainGlyphs for SubContextBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.0.retain_glyphs(keep)
+    }
+}
+
+impl RetainGlyphs for PosChainContextBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.0 .0.retain_glyphs(keep)
+    }
+}
+
+impl RetainGlyphs for SubChainContextBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.0 .0.retain_glyphs(keep)
+    }
+}
+
+/// Remove the glyphs in this item that are not kept, returning `false` if
+/// nothing is left.
+fn retain_in_item(item: &mut GlyphOrClass, keep: &dyn Fn(GlyphId) -> bool) -> bool {
+    match item {
+        GlyphOrClass::Glyph(gid) => keep(*gid),
+        GlyphOrClass::Class(class) => {
+            *class = class.iter().filter(|gid| keep(*gid)).collect();
+            !class.is_empty()
         }
+        GlyphOrClass::Null => true,
     }
+}
+
+impl ContextRule {
     fn is_chain_rule(&self) -> bool {
         !self.backtrack.is_empty() || !self.lookahead.is_empty()
     }
@@ -369,9 +551,12 @@ impl ContextRule {
             .enumerate()
             .flat_map(|(i, (_, lookups))| {
                 lookups.iter().map(move |lookup_id| {
-                    let lookup_id = in_gpos
-                        .then(|| lookup_id.to_gpos_id_or_die())
-                        .unwrap_or_else(|| lookup_id.to_gsub_id_or_die());
+                    let lookup_id = if in_gpos {
+                        lookup_id.to_gpos_id()
+                    } else {
+                        lookup_id.to_gsub_id()
+                    }
+                    .expect("checked in AllLookups::build");
                     write_layout::SequenceLookupRecord::new(i.try_into().unwrap(), lookup_id)
                 })
             })
@@ -379,6 +564,36 @@ impl ContextRule {
     }
 }
 
+impl RuleCount for PosContextBuilder {
+    fn rule_count(&self) -> usize {
+        self.0.rules.len()
+    }
+}
+
+impl RuleCount for SubContextBuilder {
+    fn rule_count(&self) -> usize {
+        self.0.rules.len()
+    }
+}
+
+impl RuleCount for PosChainContextBuilder {
+    fn rule_count(&self) -> usize {
+        self.0 .0.rules.len()
+    }
+}
+
+impl RuleCount for SubChainContextBuilder {
+    fn rule_count(&self) -> usize {
+        self.0 .0.rules.len()
+    }
+}
+
+impl RuleCount for ReverseChainBuilder {
+    fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+}
+
 impl Builder for PosContextBuilder {
     type Output = Vec<write_layout::SequenceContext>;
 
@@ -399,8 +614,7 @@ impl ContextBuilder {
     fn build(self, in_gpos: bool) -> Vec<write_layout::SequenceContext> {
         assert!(self.rules.iter().all(|rule| !rule.is_chain_rule()));
         let format_1 = self.build_format_1(in_gpos);
-        //TODO: I'm skipping format_2 because it seems consistently larger
-        // than format 3? but I have no verified this.
+        let format_2 = self.build_format_2(in_gpos);
         let format_3 = self
             .rules
             .into_iter()
@@ -408,7 +622,7 @@ impl ContextBuilder {
                 let cov_tables = rule
                     .context
                     .iter()
-                    .map(|(seq, _)| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(|(seq, _)| seq.to_coverage())
                     .collect();
                 let seq_lookups = rule.lookup_records(in_gpos);
 
@@ -416,7 +630,11 @@ impl ContextBuilder {
             })
             .collect();
 
-        pick_best_format([format_1.map(|x| vec![x]), None, Some(format_3)])
+        pick_best_format([
+            format_1.map(|x| vec![x]),
+            format_2.map(|x| vec![x]),
+            Some(format_3),
+        ])
     }
 }
 
@@ -549,19 +767,24 @@ impl ChainContextBuilder {
     }
 }
 
-impl SubContextBuilder {
-    pub(crate) fn bump_all_lookup_ids(&mut self, by: usize) {
-        self.0.bump_all_lookup_ids(by)
-    }
-}
 impl SubChainContextBuilder {
-    pub(crate) fn bump_all_lookup_ids(&mut self, by: usize) {
-        self.0 .0.bump_all_lookup_ids(by)
+    pub(crate) fn iter_lookups(&self) -> impl Iterator<Item = LookupId> + '_ {
+        self.0.iter_lookups()
+    }
+
+    pub(crate) fn remap_lookups(&mut self, f: &dyn Fn(LookupId) -> Option<LookupId>) {
+        self.0 .0.remap_lookups(f)
     }
+}
 
+impl PosChainContextBuilder {
     pub(crate) fn iter_lookups(&self) -> impl Iterator<Item = LookupId> + '_ {
         self.0.iter_lookups()
     }
+
+    pub(crate) fn remap_lookups(&mut self, f: &dyn Fn(LookupId) -> Option<LookupId>) {
+        self.0 .0.remap_lookups(f)
+    }
 }
 
 impl Builder for PosChainContextBuilder {
@@ -594,17 +817,17 @@ impl ChainContextBuilder {
                 let backtrack = rule
                     .backtrack
                     .iter()
-                    .map(|seq| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(GlyphOrClass::to_coverage)
                     .collect();
                 let lookahead = rule
                     .lookahead
                     .iter()
-                    .map(|seq| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(GlyphOrClass::to_coverage)
                     .collect();
                 let input = rule
                     .context
                     .iter()
-                    .map(|(seq, _)| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(|(seq, _)| seq.to_coverage())
                     .collect();
                 let seq_lookups = rule.lookup_records(in_gpos);
 
@@ -652,6 +875,77 @@ fn compute_size<T: FontWrite + Validate>(item: Option<&T>) -> Option<usize> {
         .map(|x| x.len())
 }
 
+impl ContextBuilder {
+    /// Write each rule, as a `pos` or `sub` rule depending on `keyword`.
+    ///
+    /// A rule without lookups is written as an `ignore` rule.
+    fn fmt_rules(&self, f: &mut Formatter<'_>, keyword: &str) -> std::fmt::Result {
+        for rule in &self.rules {
+            if rule.context.iter().all(|(_, lookups)| lookups.is_empty()) {
+                write!(f, "ignore ")?;
+            }
+            write!(f, "{keyword}")?;
+            for item in &rule.backtrack {
+                write!(f, " {item}")?;
+            }
+            for (item, lookups) in &rule.context {
+                write!(f, " {item}'")?;
+                for lookup in lookups {
+                    write!(f, " lookup {lookup}")?;
+                }
+            }
+            for item in &rule.lookahead {
+                write!(f, " {item}")?;
+            }
+            writeln!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PosContextBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_rules(f, "pos")
+    }
+}
+
+impl Display for SubContextBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt_rules(f, "sub")
+    }
+}
+
+impl Display for PosChainContextBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0 .0.fmt_rules(f, "pos")
+    }
+}
+
+impl Display for SubChainContextBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0 .0.fmt_rules(f, "sub")
+    }
+}
+
+impl Display for ReverseChainBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for rule in &self.rules {
+            let (targets, replacements): (Vec<_>, Vec<_>) =
+                rule.context.iter().map(|(a, b)| (*a, *b)).unzip();
+            write!(f, "rsub")?;
+            for item in &rule.backtrack {
+                write!(f, " {item}")?;
+            }
+            write!(f, " {}'", GlyphClass::from(targets))?;
+            for item in &rule.lookahead {
+                write!(f, " {item}")?;
+            }
+            writeln!(f, " by {};", GlyphClass::from(replacements))?;
+        }
+        Ok(())
+    }
+}
+
 impl ReverseChainBuilder {
     pub fn add(
         &mut self,
@@ -677,12 +971,12 @@ impl Builder for ReverseChainBuilder {
                 let backtrack = rule
                     .backtrack
                     .iter()
-                    .map(|seq| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(GlyphOrClass::to_coverage)
                     .collect();
                 let lookahead = rule
                     .lookahead
                     .iter()
-                    .map(|seq| seq.iter().collect::<CoverageTableBuilder>().build())
+                    .map(GlyphOrClass::to_coverage)
                     .collect();
                 let input = rule
                     .context
@@ -1,6 +1,9 @@
 //! GPOS subtable builders
 
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{btree_map, BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+};
 
 use smol_str::SmolStr;
 use write_fonts::{
@@ -11,9 +14,15 @@ use write_fonts::{
     types::GlyphId,
 };
 
-use crate::common::GlyphClass;
+use crate::{common::GlyphClass, compile::valuerecordext::ValueRecordExt};
+
+use super::{
+    helpers::{DisplayAnchor, DisplayValueRecord},
+    Builder, ClassDefBuilder2, RetainGlyphs, RuleCount,
+};
 
-use super::{Builder, ClassDefBuilder2};
+// the largest subtable we can reference with a 16-bit offset
+const MAX_SUBTABLE_SIZE: usize = u16::MAX as usize;
 
 #[derive(Clone, Debug, Default)]
 pub struct SinglePosBuilder {
@@ -26,6 +35,10 @@ impl SinglePosBuilder {
         self.items.insert(glyph, record);
     }
 
+    pub(crate) fn get(&self, glyph: GlyphId) -> Option<&ValueRecord> {
+        self.items.get(&glyph)
+    }
+
     pub(crate) fn can_add_rule(&self, glyph: GlyphId, value: &ValueRecord) -> bool {
         self.items
             .get(&glyph)
@@ -34,6 +47,18 @@ impl SinglePosBuilder {
     }
 }
 
+impl RuleCount for SinglePosBuilder {
+    fn rule_count(&self) -> usize {
+        self.items.len()
+    }
+}
+
+impl RetainGlyphs for SinglePosBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.items.retain(|gid, _| keep(*gid));
+    }
+}
+
 impl Builder for SinglePosBuilder {
     type Output = Vec<write_gpos::SinglePos>;
 
@@ -102,10 +127,20 @@ fn cmp_coverage_key(coverage: &CoverageTable) -> impl Ord {
     (std::cmp::Reverse(coverage.len()), coverage.iter().next())
 }
 
+impl Display for SinglePosBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (gid, value) in &self.items {
+            writeln!(f, "pos {gid} {};", DisplayValueRecord(value))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct PairPosBuilder {
     pairs: GlyphPairPosBuilder,
     classes: ClassPairPosBuilder,
+    compact_value_formats: bool,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -132,13 +167,15 @@ impl Default for ClassPairPosSubtable {
 struct ClassPairPosBuilder(BTreeMap<(ValueFormat, ValueFormat), Vec<ClassPairPosSubtable>>);
 
 impl ClassPairPosBuilder {
+    /// Returns `true` if this insertion caused the subtable to exceed
+    /// the maximum size addressable with a 16-bit offset.
     fn insert(
         &mut self,
         class1: GlyphClass,
         record1: ValueRecord,
         class2: GlyphClass,
         record2: ValueRecord,
-    ) {
+    ) -> bool {
         let key = (record1.format(), record2.format());
         let entry = self.0.entry(key).or_default();
         let add_sub = match entry.last() {
@@ -148,14 +185,56 @@ impl ClassPairPosBuilder {
         if add_sub {
             entry.push(Default::default());
         }
-        entry
-            .last_mut()
-            .unwrap()
-            .add(class1, class2, record1, record2);
+        let subtable = entry.last_mut().unwrap();
+        let size_before = subtable.estimated_size();
+        subtable.add(class1, class2, record1, record2);
+        size_before <= MAX_SUBTABLE_SIZE && subtable.estimated_size() > MAX_SUBTABLE_SIZE
     }
 }
 
 impl ClassPairPosSubtable {
+    /// The approximate size of this subtable, excluding coverage and classdefs.
+    ///
+    /// The class records dominate the size of this table, and since they
+    /// are written inline, this is what limits the size of a subtable.
+    fn estimated_size(&self) -> usize {
+        const HEADER_SIZE: usize = 16;
+        let record_size = self
+            .items
+            .values()
+            .flat_map(BTreeMap::values)
+            .next()
+            .map(|(v1, v2)| v1.encoded_size() + v2.encoded_size())
+            .unwrap_or_default();
+        // class 0 is always present in the second classdef
+        let n_class2 = self.classdef_2.len() + 1;
+        HEADER_SIZE + self.classdef_1.len() * n_class2 * record_size
+    }
+
+    /// Return a copy of this subtable with only the glyphs for which `keep`
+    /// is `true`, removing any classes that are left empty.
+    ///
+    /// Since the classes in each classdef are disjoint, the filtered
+    /// classes are too.
+    fn retain_glyphs(self, keep: &dyn Fn(GlyphId) -> bool) -> Self {
+        let filter =
+            |class: GlyphClass| -> GlyphClass { class.iter().filter(|gid| keep(*gid)).collect() };
+        let mut result = Self::default();
+        for (class1, seconds) in self.items {
+            let class1 = filter(class1);
+            if class1.is_empty() {
+                continue;
+            }
+            for (class2, (record1, record2)) in seconds {
+                let class2 = filter(class2);
+                if !class2.is_empty() {
+                    result.add(class1.clone(), class2, record1, record2);
+                }
+            }
+        }
+        result
+    }
+
     fn can_add(&self, class1: &GlyphClass, class2: &GlyphClass) -> bool {
         self.classdef_1.can_add(class1) && self.classdef_2.can_add(class2)
     }
@@ -191,15 +270,191 @@ impl PairPosBuilder {
             .insert(glyph2, (record1, record2));
     }
 
+    /// Returns `true` if this caused a subtable to exceed the offset limit.
     pub(crate) fn insert_classes(
         &mut self,
         class1: GlyphClass,
         record1: ValueRecord,
         class2: GlyphClass,
         record2: ValueRecord,
-    ) {
+    ) -> bool {
         self.classes.insert(class1, record1, class2, record2)
     }
+
+    /// The class-based rule that covers this glyph pair, if any.
+    pub(crate) fn class_rule(
+        &self,
+        first: GlyphId,
+        second: GlyphId,
+    ) -> Option<(&GlyphClass, &GlyphClass, &(ValueRecord, ValueRecord))> {
+        self.classes.0.values().flatten().find_map(|sub| {
+            let (class1, seconds) = sub
+                .items
+                .iter()
+                .find(|(class1, _)| class1.iter().any(|gid| gid == first))?;
+            seconds
+                .iter()
+                .find(|(class2, _)| class2.iter().any(|gid| gid == second))
+                .map(|(class2, values)| (class1, class2, values))
+        })
+    }
+
+    /// The glyph pair rules for pairs in these classes.
+    pub(crate) fn glyph_pairs_in<'a>(
+        &'a self,
+        first: &'a GlyphClass,
+        second: &'a GlyphClass,
+    ) -> impl Iterator<Item = (GlyphId, GlyphId, &'a (ValueRecord, ValueRecord))> + 'a {
+        first
+            .iter()
+            .filter_map(|gid1| self.pairs.0.get(&gid1).map(|seconds| (gid1, seconds)))
+            .flat_map(move |(gid1, seconds)| {
+                second
+                    .iter()
+                    .filter_map(move |gid2| seconds.get(&gid2).map(|values| (gid1, gid2, values)))
+            })
+    }
+
+    /// The pairs of classes that this builder has rules for.
+    ///
+    /// Glyph pair rules are grouped into one pair for each first glyph. The
+    /// classes are not expanded, since class pairs can cover a lot of glyph
+    /// pairs.
+    pub(crate) fn covered_class_pairs(
+        &self,
+    ) -> impl Iterator<Item = (GlyphClass, GlyphClass)> + '_ {
+        let glyph_pairs = self.pairs.0.iter().map(|(gid1, seconds)| {
+            let seconds = seconds.keys().copied().collect::<GlyphClass>();
+            (GlyphClass::from(*gid1), seconds)
+        });
+        let class_pairs = self
+            .classes
+            .0
+            .values()
+            .flatten()
+            .flat_map(|sub| sub.items.iter())
+            .flat_map(|(class1, seconds)| {
+                seconds
+                    .keys()
+                    .map(move |class2| (class1.clone(), class2.clone()))
+            });
+        glyph_pairs.chain(class_pairs)
+    }
+
+    /// Whether any class-based rule has a device table.
+    ///
+    /// Every pair of classes in a subtable needs a value record, and the
+    /// records of pairs without a rule would need null device offsets, which
+    /// we can't currently write.
+    pub(crate) fn has_class_pair_devices(&self) -> bool {
+        let devices = ValueFormat::X_PLACEMENT_DEVICE
+            | ValueFormat::Y_PLACEMENT_DEVICE
+            | ValueFormat::X_ADVANCE_DEVICE
+            | ValueFormat::Y_ADVANCE_DEVICE;
+        self.classes
+            .0
+            .keys()
+            .any(|(format1, format2)| (*format1 | *format2).intersects(devices))
+    }
+
+    /// Whether this glyph is in the first class of any class-based rule.
+    ///
+    /// A class-based subtable applies to every pair whose first glyph it
+    /// covers, so a shaper never looks at later subtables for these glyphs.
+    pub(crate) fn covers_first_class_glyph(&self, glyph: GlyphId) -> bool {
+        self.classes
+            .0
+            .values()
+            .flatten()
+            .any(|sub| sub.classdef_1.contains_glyph(glyph))
+    }
+
+    /// Set whether class-based subtables assign class 0 in their first classdef.
+    pub(crate) fn set_use_class_0(&mut self, flag: bool) {
+        self.classes
+            .0
+            .values_mut()
+            .flatten()
+            .for_each(|sub| sub.classdef_1.set_use_class_0(flag));
+    }
+
+    /// Set whether value formats are minimized for each subtable.
+    ///
+    /// This corresponds to [`Opts::compact_value_formats`](crate::compile::Opts::compact_value_formats).
+    pub(crate) fn set_compact_value_formats(&mut self, flag: bool) {
+        self.compact_value_formats = flag;
+    }
+}
+
+impl RuleCount for PairPosBuilder {
+    fn rule_count(&self) -> usize {
+        let glyph_pairs = self.pairs.0.values().map(BTreeMap::len).sum::<usize>();
+        let class_pairs = self
+            .classes
+            .0
+            .values()
+            .flatten()
+            .flat_map(|subtable| subtable.items.values())
+            .map(BTreeMap::len)
+            .sum::<usize>();
+        glyph_pairs + class_pairs
+    }
+}
+
+impl RetainGlyphs for PairPosBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.pairs.0.retain(|first, seconds| {
+            seconds.retain(|second, _| keep(*second));
+            keep(*first) && !seconds.is_empty()
+        });
+        for subtables in self.classes.0.values_mut() {
+            *subtables = std::mem::take(subtables)
+                .into_iter()
+                .map(|subtable| subtable.retain_glyphs(keep))
+                .filter(|subtable| !subtable.items.is_empty())
+                .collect();
+        }
+        self.classes.0.retain(|_, subtables| !subtables.is_empty());
+    }
+}
+
+impl Display for PairPosBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (first, seconds) in &self.pairs.0 {
+            for (second, records) in seconds {
+                fmt_pair_rule(f, first, second, records)?;
+            }
+        }
+        // each class subtable is a separate subtable in the lookup
+        let subtables = self.classes.0.values().flatten();
+        for (i, subtable) in subtables.enumerate() {
+            if i > 0 {
+                writeln!(f, "subtable;")?;
+            }
+            for (first, seconds) in &subtable.items {
+                for (second, records) in seconds {
+                    fmt_pair_rule(f, first, second, records)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// a pair rule with a value for the second glyph can only be written in the
+// long form
+fn fmt_pair_rule(
+    f: &mut Formatter<'_>,
+    first: impl Display,
+    second: impl Display,
+    (record1, record2): &(ValueRecord, ValueRecord),
+) -> std::fmt::Result {
+    let (record1, record2) = (DisplayValueRecord(record1), DisplayValueRecord(record2));
+    if record2.0.format().is_empty() {
+        writeln!(f, "pos {first} {second} {record1};")
+    } else {
+        writeln!(f, "pos {first} {record1} {second} {record2};")
+    }
 }
 
 impl Builder for PairPosBuilder {
@@ -208,6 +463,9 @@ impl Builder for PairPosBuilder {
     fn build(self) -> Self::Output {
         let mut out = self.pairs.build();
         out.extend(self.classes.build());
+        if self.compact_value_formats {
+            out.iter_mut().for_each(compact_value_formats);
+        }
         out
     }
 }
@@ -281,7 +539,11 @@ impl Builder for ClassPairPosSubtable {
             .collect::<CoverageTableBuilder>()
             .build();
 
-        let mut out = vec![write_gpos::Class1Record::default(); self.items.len()];
+        // if class 0 is not assigned explicitly, it still needs an (empty) record
+        let n_class1 = class1map.values().max().map(|id| *id as usize + 1);
+        let empty_class1 =
+            write_gpos::Class1Record::new(vec![empty_record.clone(); class2map.len() + 1]);
+        let mut out = vec![empty_class1; n_class1.unwrap_or_default()];
         for (cls1, stuff) in self.items {
             let idx = class1map.get(&cls1).unwrap();
             let mut records = vec![empty_record.clone(); class2map.len() + 1];
@@ -295,6 +557,37 @@ impl Builder for ClassPairPosSubtable {
     }
 }
 
+/// Drop the components of a subtable's value formats that are zero in every record.
+///
+/// Records are built with the formats written in the FEA, and an explicit zero
+/// (such as the `0` in `pos a b 0;`) is kept, as it is by feaLib. Only the
+/// components that are non-zero in at least one record of the subtable are
+/// actually needed.
+fn compact_value_formats(subtable: &mut write_gpos::PairPos) {
+    let mut records = match subtable {
+        write_gpos::PairPos::Format1(table) => table
+            .pair_sets
+            .iter_mut()
+            .flat_map(|set| set.pair_value_records.iter_mut())
+            .map(|rec| (&mut rec.value_record1, &mut rec.value_record2))
+            .collect::<Vec<_>>(),
+        write_gpos::PairPos::Format2(table) => table
+            .class1_records
+            .iter_mut()
+            .flat_map(|rec| rec.class2_records.iter_mut())
+            .map(|rec| (&mut rec.value_record1, &mut rec.value_record2))
+            .collect(),
+    };
+    let (format1, format2) = records.iter().fold(
+        (ValueFormat::empty(), ValueFormat::empty()),
+        |(f1, f2), (v1, v2)| (f1 | v1.effective_format(), f2 | v2.effective_format()),
+    );
+    for (v1, v2) in records.iter_mut() {
+        **v1 = std::mem::take(*v1).retain_format(format1);
+        **v2 = std::mem::take(*v2).retain_format(format2);
+    }
+}
+
 fn empty_record_with_format(format: ValueFormat) -> ValueRecord {
     let mut result = ValueRecord::default();
     if format.contains(ValueFormat::X_PLACEMENT) {
@@ -309,17 +602,9 @@ fn empty_record_with_format(format: ValueFormat) -> ValueRecord {
     if format.contains(ValueFormat::Y_ADVANCE) {
         result.y_advance = Some(0);
     }
-    if format.intersects(
-        ValueFormat::X_PLACEMENT_DEVICE
-            | ValueFormat::Y_PLACEMENT_DEVICE
-            | ValueFormat::X_ADVANCE_DEVICE
-            | ValueFormat::Y_ADVANCE_DEVICE,
-    ) {
-        //FIXME idk here. I think we need to update code in write-fonts so that
-        // we take value format into account when deciding what we write,
-        // instead of just ignoring null values
-        panic!("writing explicit null offsets is currently broken, sorry")
-    }
+    // write-fonts can't write a null offset, so device tables are left out;
+    // subtables that would need them are rejected before the final tables
+    // are built (see `PairPosBuilder::has_class_pair_devices`)
 
     result
 }
@@ -339,6 +624,50 @@ impl CursivePosBuilder {
         let record = write_gpos::EntryExitRecord::new(entry, exit);
         self.items.insert(glyph, record);
     }
+
+    pub(crate) fn contains(&self, glyph: GlyphId) -> bool {
+        self.items.contains_key(&glyph)
+    }
+
+    /// Whether this glyph has exactly these anchors.
+    pub(crate) fn has_anchors(
+        &self,
+        glyph: GlyphId,
+        entry: Option<&AnchorTable>,
+        exit: Option<&AnchorTable>,
+    ) -> bool {
+        let same = |one: Option<&AnchorTable>, two: Option<&AnchorTable>| match (one, two) {
+            (Some(one), Some(two)) => same_anchor(one, two),
+            (one, two) => one.is_none() && two.is_none(),
+        };
+        self.items.get(&glyph).is_some_and(|record| {
+            same(record.entry_anchor.as_ref(), entry) && same(record.exit_anchor.as_ref(), exit)
+        })
+    }
+
+    /// Whether any glyph has an entry anchor, and whether any has an exit anchor.
+    pub(crate) fn has_entry_and_exit(&self) -> (bool, bool) {
+        self.items
+            .values()
+            .fold((false, false), |(entry, exit), record| {
+                (
+                    entry || record.entry_anchor.is_some(),
+                    exit || record.exit_anchor.is_some(),
+                )
+            })
+    }
+}
+
+impl RuleCount for CursivePosBuilder {
+    fn rule_count(&self) -> usize {
+        self.items.len()
+    }
+}
+
+impl RetainGlyphs for CursivePosBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.items.retain(|gid, _| keep(*gid));
+    }
 }
 
 impl Builder for CursivePosBuilder {
@@ -354,6 +683,20 @@ impl Builder for CursivePosBuilder {
     }
 }
 
+impl Display for CursivePosBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (gid, record) in &self.items {
+            writeln!(
+                f,
+                "pos cursive {gid} {} {};",
+                DisplayAnchor(record.entry_anchor.as_ref()),
+                DisplayAnchor(record.exit_anchor.as_ref())
+            )?;
+        }
+        Ok(())
+    }
+}
+
 // shared between several tables
 #[derive(Clone, Debug, Default)]
 struct MarkList {
@@ -397,12 +740,43 @@ impl MarkList {
         self.glyphs.keys().copied()
     }
 
+    // class ids are left as they are, since they are referenced by the
+    // base records; a class with no remaining glyphs is harmless.
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.glyphs.retain(|gid, _| keep(*gid));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
     fn get_class(&self, class_name: &SmolStr) -> u16 {
         *self
             .classes
             .get(class_name)
             .expect("marks added before bases")
     }
+
+    fn class_name(&self, id: u16) -> &str {
+        self.classes
+            .iter()
+            .find_map(|(name, idx)| (*idx == id).then_some(name.as_str()))
+            .unwrap()
+    }
+}
+
+impl Display for MarkList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (gid, record) in &self.glyphs {
+            writeln!(
+                f,
+                "markClass {gid} {} {};",
+                DisplayAnchor(Some(&record.mark_anchor)),
+                self.class_name(record.mark_class)
+            )?;
+        }
+        Ok(())
+    }
 }
 
 impl Builder for MarkList {
@@ -418,7 +792,7 @@ impl Builder for MarkList {
 #[derive(Clone, Debug, Default)]
 pub struct MarkToBaseBuilder {
     marks: MarkList,
-    bases: BTreeMap<GlyphId, Vec<(u16, AnchorTable)>>,
+    bases: BTreeMap<GlyphId, BTreeMap<u16, AnchorTable>>,
 }
 
 /// An error indicating a given glyph is has be
@@ -441,9 +815,27 @@ impl MarkToBaseBuilder {
         self.marks.insert(glyph, class, anchor)
     }
 
-    pub fn insert_base(&mut self, glyph: GlyphId, class: &SmolStr, anchor: AnchorTable) {
+    /// Add the anchor for a mark class on a base glyph.
+    ///
+    /// A base can have anchors for any number of mark classes. If it already
+    /// has a different anchor for this class, the existing anchor is kept and
+    /// returned.
+    pub fn insert_base(
+        &mut self,
+        glyph: GlyphId,
+        class: &SmolStr,
+        anchor: AnchorTable,
+    ) -> Option<AnchorTable> {
         let class = self.marks.get_class(class);
-        self.bases.entry(glyph).or_default().push((class, anchor))
+        match self.bases.entry(glyph).or_default().entry(class) {
+            btree_map::Entry::Occupied(existing) => {
+                (!same_anchor(existing.get(), &anchor)).then(|| existing.get().clone())
+            }
+            btree_map::Entry::Vacant(slot) => {
+                slot.insert(anchor);
+                None
+            }
+        }
     }
 
     pub fn base_glyphs(&self) -> impl Iterator<Item = GlyphId> + Clone + '_ {
@@ -453,6 +845,38 @@ impl MarkToBaseBuilder {
     pub fn mark_glyphs(&self) -> impl Iterator<Item = GlyphId> + Clone + '_ {
         self.marks.glyphs()
     }
+
+    /// The names of the mark classes, and the bases with an anchor for each.
+    pub fn class_bases(&self) -> impl Iterator<Item = (&str, Vec<GlyphId>)> + '_ {
+        self.marks.classes.iter().map(|(name, id)| {
+            let bases = self
+                .bases
+                .iter()
+                .filter(|(_, anchors)| anchors.contains_key(id))
+                .map(|(gid, _)| *gid)
+                .collect();
+            (name.as_str(), bases)
+        })
+    }
+}
+
+// anchor tables don't implement PartialEq, so we compare what they write
+fn same_anchor(one: &AnchorTable, two: &AnchorTable) -> bool {
+    write_fonts::dump_table(one).ok() == write_fonts::dump_table(two).ok()
+}
+
+impl RuleCount for MarkToBaseBuilder {
+    fn rule_count(&self) -> usize {
+        self.bases.len()
+    }
+}
+
+impl RetainGlyphs for MarkToBaseBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.marks.retain_glyphs(keep);
+        let has_marks = !self.marks.is_empty();
+        self.bases.retain(|gid, _| has_marks && keep(*gid));
+    }
 }
 
 impl Builder for MarkToBaseBuilder {
@@ -500,8 +924,33 @@ impl MarkToLigBuilder {
         self.marks.insert(glyph, class, anchor)
     }
 
-    pub fn add_lig(&mut self, glyph: GlyphId, components: Vec<BTreeMap<SmolStr, AnchorTable>>) {
-        self.ligatures.insert(glyph, components);
+    /// Add the anchors for each component of a ligature glyph.
+    ///
+    /// Rules for the same ligature are merged component by component, keeping
+    /// any existing anchor for a mark class. If the ligature already has a
+    /// different number of components, nothing is added and the existing
+    /// number of components is returned.
+    pub fn add_lig(
+        &mut self,
+        glyph: GlyphId,
+        components: Vec<BTreeMap<SmolStr, AnchorTable>>,
+    ) -> Option<usize> {
+        match self.ligatures.entry(glyph) {
+            btree_map::Entry::Occupied(mut existing) => {
+                if existing.get().len() != components.len() {
+                    return Some(existing.get().len());
+                }
+                for (existing, new) in existing.get_mut().iter_mut().zip(components) {
+                    for (class, anchor) in new {
+                        existing.entry(class).or_insert(anchor);
+                    }
+                }
+            }
+            btree_map::Entry::Vacant(slot) => {
+                slot.insert(components);
+            }
+        }
+        None
     }
 
     pub fn mark_glyphs(&self) -> impl Iterator<Item = GlyphId> + Clone + '_ {
@@ -513,6 +962,20 @@ impl MarkToLigBuilder {
     }
 }
 
+impl RuleCount for MarkToLigBuilder {
+    fn rule_count(&self) -> usize {
+        self.ligatures.len()
+    }
+}
+
+impl RetainGlyphs for MarkToLigBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.marks.retain_glyphs(keep);
+        let has_marks = !self.marks.is_empty();
+        self.ligatures.retain(|gid, _| has_marks && keep(*gid));
+    }
+}
+
 impl Builder for MarkToLigBuilder {
     type Output = Vec<write_gpos::MarkLigPosFormat1>;
 
@@ -583,6 +1046,20 @@ impl MarkToMarkBuilder {
     }
 }
 
+impl RuleCount for MarkToMarkBuilder {
+    fn rule_count(&self) -> usize {
+        self.base_marks.len()
+    }
+}
+
+impl RetainGlyphs for MarkToMarkBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.attaching_marks.retain_glyphs(keep);
+        let has_marks = !self.attaching_marks.is_empty();
+        self.base_marks.retain(|gid, _| has_marks && keep(*gid));
+    }
+}
+
 impl Builder for MarkToMarkBuilder {
     type Output = Vec<write_gpos::MarkMarkPosFormat1>;
 
@@ -614,3 +1091,63 @@ impl Builder for MarkToMarkBuilder {
         )]
     }
 }
+
+impl Display for MarkToBaseBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.marks)?;
+        for (gid, anchors) in &self.bases {
+            write!(f, "pos base {gid}")?;
+            for (class, anchor) in anchors {
+                write!(
+                    f,
+                    " {} mark {}",
+                    DisplayAnchor(Some(anchor)),
+                    self.marks.class_name(*class)
+                )?;
+            }
+            writeln!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for MarkToLigBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.marks)?;
+        for (gid, components) in &self.ligatures {
+            write!(f, "pos ligature {gid}")?;
+            for (i, anchors) in components.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ligComponent")?;
+                }
+                if anchors.is_empty() {
+                    write!(f, " {}", DisplayAnchor(None))?;
+                }
+                for (class, anchor) in anchors {
+                    write!(f, " {} mark {class}", DisplayAnchor(Some(anchor)))?;
+                }
+            }
+            writeln!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for MarkToMarkBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.attaching_marks)?;
+        for (gid, anchors) in &self.base_marks {
+            write!(f, "pos mark {gid}")?;
+            for (class, anchor) in anchors {
+                write!(
+                    f,
+                    " {} mark {}",
+                    DisplayAnchor(Some(anchor)),
+                    self.attaching_marks.class_name(*class)
+                )?;
+            }
+            writeln!(f, ";")?;
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,17 @@
 //! GSUB lookup builders
 
-use std::{collections::BTreeMap, convert::TryFrom};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+};
 
 use write_fonts::{
     tables::{gsub as write_gsub, layout::CoverageTableBuilder},
     types::{FixedSize, GlyphId},
 };
 
-use super::Builder;
+use super::{helpers::DisplayGlyphs, Builder, RetainGlyphs, RuleCount};
 
 #[derive(Clone, Debug, Default)]
 pub struct SingleSubBuilder {
@@ -32,6 +36,10 @@ impl SingleSubBuilder {
         self.items.insert(target, (replacement, delta));
     }
 
+    pub(crate) fn get(&self, target: GlyphId) -> Option<GlyphId> {
+        self.items.get(&target).map(|(replacement, _)| *replacement)
+    }
+
     pub fn contains_target(&self, target: GlyphId) -> bool {
         self.items.contains_key(&target)
     }
@@ -46,6 +54,19 @@ impl SingleSubBuilder {
     }
 }
 
+impl RuleCount for SingleSubBuilder {
+    fn rule_count(&self) -> usize {
+        self.items.len()
+    }
+}
+
+impl RetainGlyphs for SingleSubBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.items
+            .retain(|target, (replacement, _)| keep(*target) && keep(*replacement));
+    }
+}
+
 impl Builder for SingleSubBuilder {
     type Output = Vec<write_gsub::SingleSubst>;
 
@@ -138,11 +159,34 @@ impl Builder for SingleSubBuilder {
     }
 }
 
+impl Display for SingleSubBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (target, (replacement, _)) in &self.items {
+            writeln!(f, "sub {target} by {replacement};")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct MultipleSubBuilder {
     items: BTreeMap<GlyphId, Vec<GlyphId>>,
 }
 
+impl RuleCount for MultipleSubBuilder {
+    fn rule_count(&self) -> usize {
+        self.items.len()
+    }
+}
+
+impl RetainGlyphs for MultipleSubBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.items.retain(|target, replacement| {
+            keep(*target) && replacement.iter().all(|gid| keep(*gid))
+        });
+    }
+}
+
 impl Builder for MultipleSubBuilder {
     type Output = Vec<write_gsub::MultipleSubstFormat1>;
 
@@ -166,6 +210,15 @@ impl MultipleSubBuilder {
     }
 }
 
+impl Display for MultipleSubBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (target, replacement) in &self.items {
+            writeln!(f, "sub {target} by {};", DisplayGlyphs(replacement))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct AlternateSubBuilder {
     items: BTreeMap<GlyphId, Vec<GlyphId>>,
@@ -188,6 +241,22 @@ impl AlternateSubBuilder {
     }
 }
 
+impl RuleCount for AlternateSubBuilder {
+    fn rule_count(&self) -> usize {
+        self.items.len()
+    }
+}
+
+impl RetainGlyphs for AlternateSubBuilder {
+    // an alternate set is only removed if none of its alternates are kept
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.items.retain(|target, alts| {
+            alts.retain(|gid| keep(*gid));
+            keep(*target) && !alts.is_empty()
+        });
+    }
+}
+
 impl Builder for AlternateSubBuilder {
     type Output = Vec<write_gsub::AlternateSubstFormat1>;
 
@@ -205,20 +274,47 @@ impl Builder for AlternateSubBuilder {
     }
 }
 
+impl Display for AlternateSubBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (target, alternates) in &self.items {
+            writeln!(f, "sub {target} from [{}];", DisplayGlyphs(alternates))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct LigatureSubBuilder {
     items: BTreeMap<GlyphId, Vec<(Vec<GlyphId>, GlyphId)>>,
 }
 
 impl LigatureSubBuilder {
+    /// Add a ligature.
+    ///
+    /// If this component sequence is already present, the existing ligature
+    /// is kept.
     pub fn insert(&mut self, target: Vec<GlyphId>, replacement: GlyphId) {
         let mut iter = target.into_iter();
         let first = iter.next().unwrap();
         let rest = iter.collect::<Vec<_>>();
+        let ligs = self.items.entry(first).or_default();
+        if !ligs.iter().any(|(components, _)| *components == rest) {
+            ligs.push((rest, replacement));
+        }
+    }
+
+    /// The ligature glyph for this component sequence, if any.
+    pub(crate) fn get(&self, target: &[GlyphId]) -> Option<GlyphId> {
+        let (first, rest) = target.split_first()?;
         self.items
-            .entry(first)
-            .or_default()
-            .push((rest, replacement));
+            .get(first)?
+            .iter()
+            .find_map(|(components, lig)| (components == rest).then_some(*lig))
+    }
+
+    /// The ligature glyphs produced by this subtable.
+    pub(crate) fn ligature_glyphs(&self) -> impl Iterator<Item = GlyphId> + '_ {
+        self.items.values().flatten().map(|(_, lig)| *lig)
     }
 
     pub fn contains_target(&self, target: GlyphId) -> bool {
@@ -228,6 +324,21 @@ impl LigatureSubBuilder {
     }
 }
 
+impl RuleCount for LigatureSubBuilder {
+    fn rule_count(&self) -> usize {
+        self.items.values().map(Vec::len).sum()
+    }
+}
+
+impl RetainGlyphs for LigatureSubBuilder {
+    fn retain_glyphs(&mut self, keep: &dyn Fn(GlyphId) -> bool) {
+        self.items.retain(|first, ligs| {
+            ligs.retain(|(components, lig)| keep(*lig) && components.iter().all(|gid| keep(*gid)));
+            keep(*first) && !ligs.is_empty()
+        });
+    }
+}
+
 impl Builder for LigatureSubBuilder {
     type Output = Vec<write_gsub::LigatureSubstFormat1>;
 
@@ -259,3 +370,18 @@ impl Builder for LigatureSubBuilder {
         )]
     }
 }
+
+impl Display for LigatureSubBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (first, ligatures) in &self.items {
+            for (rest, ligature) in ligatures {
+                write!(f, "sub {first}")?;
+                for gid in rest {
+                    write!(f, " {gid}")?;
+                }
+                writeln!(f, " by {ligature};")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,14 @@
 //! utils and types shared between multiple lookups
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+};
 
-use write_fonts::tables::layout::{ClassDef, ClassDefBuilder};
+use write_fonts::tables::{
+    gpos::{AnchorTable, ValueRecord},
+    layout::{ClassDef, ClassDefBuilder},
+};
 
 use crate::common::{GlyphClass, GlyphId};
 
@@ -37,6 +43,21 @@ impl ClassDefBuilder2 {
         }
     }
 
+    /// Set whether or not class 0 should be assigned to one of the classes.
+    pub(crate) fn set_use_class_0(&mut self, flag: bool) {
+        self.use_class_0 = flag;
+    }
+
+    /// The number of distinct classes added so far.
+    pub(crate) fn len(&self) -> usize {
+        self.classes.len()
+    }
+
+    /// Whether this glyph is in any of the classes added so far.
+    pub(crate) fn contains_glyph(&self, gid: GlyphId) -> bool {
+        self.glyphs.contains(&gid)
+    }
+
     pub(crate) fn can_add(&self, cls: &GlyphClass) -> bool {
         self.classes.contains(cls) || cls.iter().all(|gid| !self.glyphs.contains(&gid))
     }
@@ -65,6 +86,9 @@ impl ClassDefBuilder2 {
             )),)
         });
         classes.dedup();
+        // this can't overflow: the classes are disjoint and not empty, so there
+        // are no more of them than there are glyphs, and a font has at most
+        // 65535 glyphs.
         let add_one = u16::from(!self.use_class_0);
         let mapping = classes
             .into_iter()
@@ -81,6 +105,84 @@ impl ClassDefBuilder2 {
     }
 }
 
+/// Formats a sequence of glyphs, separated by spaces.
+pub(crate) struct DisplayGlyphs<'a>(pub(crate) &'a [GlyphId]);
+
+/// Formats a value record as in FEA.
+///
+/// A record with only an x advance is written as a single number. Device
+/// tables are not shown.
+pub(crate) struct DisplayValueRecord<'a>(pub(crate) &'a ValueRecord);
+
+/// Formats an anchor as in FEA, or as a null anchor if it is missing.
+///
+/// Device tables are not shown.
+pub(crate) struct DisplayAnchor<'a>(pub(crate) Option<&'a AnchorTable>);
+
+impl Display for DisplayGlyphs<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, gid) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{gid}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for DisplayValueRecord<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let record = self.0;
+        if record.format().is_empty() {
+            return write!(f, "<NULL>");
+        }
+        match (
+            record.x_placement,
+            record.y_placement,
+            record.x_advance,
+            record.y_advance,
+        ) {
+            (None, None, Some(x_advance), None) => write!(f, "{x_advance}"),
+            (x_placement, y_placement, x_advance, y_advance) => write!(
+                f,
+                "<{} {} {} {}>",
+                x_placement.unwrap_or_default(),
+                y_placement.unwrap_or_default(),
+                x_advance.unwrap_or_default(),
+                y_advance.unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl Display for DisplayAnchor<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            None => write!(f, "<anchor NULL>"),
+            Some(AnchorTable::Format1(anchor)) => {
+                write!(
+                    f,
+                    "<anchor {} {}>",
+                    anchor.x_coordinate, anchor.y_coordinate
+                )
+            }
+            Some(AnchorTable::Format2(anchor)) => write!(
+                f,
+                "<anchor {} {} contourpoint {}>",
+                anchor.x_coordinate, anchor.y_coordinate, anchor.anchor_point
+            ),
+            Some(AnchorTable::Format3(anchor)) => {
+                write!(
+                    f,
+                    "<anchor {} {}>",
+                    anchor.x_coordinate, anchor.y_coordinate
+                )
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
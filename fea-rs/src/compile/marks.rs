@@ -0,0 +1,163 @@
+//! Compiling mark attachment from glyph anchors.
+//!
+//! Font sources usually store the positions that marks attach to as named
+//! anchors on each glyph, and leave it to the compiler to generate the `mark`
+//! and `mkmk` features, as ufo2ft's MarkFeatureWriter does. Instead of
+//! generating FEA for these features, the anchors can be passed to the
+//! compiler directly, and compiled alongside the feature file.
+
+use std::collections::{BTreeMap, HashSet};
+
+use smol_str::SmolStr;
+
+use crate::{common::GlyphId, GlyphMap, GlyphName};
+
+/// The prefix of the anchor names of mark glyphs.
+static MARK_ANCHOR_PREFIX: char = '_';
+
+/// Named glyph anchors, to be compiled into the `mark` and `mkmk` features.
+///
+/// Anchors follow the usual UFO convention: a glyph with an anchor named
+/// `_top` is a mark, which attaches to the `top` anchor of another glyph. A
+/// glyph with any anchor whose name starts with an underscore is a mark
+/// glyph; any other glyph is a base. Anchor names without a matching mark
+/// anchor (such as the `top_1` anchors of ligatures) are ignored.
+///
+/// Marks are attached to bases in the `mark` feature, with one mark-to-base
+/// lookup for each anchor name. Marks are attached to other marks in the
+/// `mkmk` feature, with one mark-to-mark lookup for each anchor name, which
+/// uses a mark filtering set of the marks that have that anchor. These
+/// lookups are added to the features of every default language system (as
+/// well as any other language system that has a `mark` or `mkmk` feature in
+/// the feature file), after any lookups defined in the feature file.
+///
+/// Rules in the feature file take precedence: glyphs that are in a mark
+/// class of the feature file are not attached by these lookups, and neither
+/// are glyphs that are already the base of a mark-to-base (or, for `mkmk`,
+/// a mark-to-mark) rule. Glyphs that are not in the glyph map are ignored.
+///
+/// Register anchors with [`Compiler::with_mark_anchors`].
+///
+/// [`Compiler::with_mark_anchors`]: crate::Compiler::with_mark_anchors
+#[derive(Clone, Debug, Default)]
+pub struct MarkAnchors {
+    glyphs: BTreeMap<GlyphName, BTreeMap<SmolStr, (i16, i16)>>,
+}
+
+/// The attachments of a single anchor name, resolved against a glyph map.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ResolvedAttachment {
+    /// The name of the anchor on the base, without the mark prefix.
+    pub(crate) name: SmolStr,
+    /// The marks that attach to this anchor, with their mark anchors.
+    pub(crate) marks: BTreeMap<GlyphId, (i16, i16)>,
+    /// The base glyphs with this anchor.
+    pub(crate) bases: BTreeMap<GlyphId, (i16, i16)>,
+    /// The mark glyphs with this anchor.
+    pub(crate) base_marks: BTreeMap<GlyphId, (i16, i16)>,
+}
+
+impl MarkAnchors {
+    /// Create a new, empty set of anchors.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Load the anchors of the glyphs in the default layer of a UFO.
+    ///
+    /// Anchors without a name are skipped. Coordinates are rounded to the
+    /// nearest integer.
+    #[cfg(feature = "ufo")]
+    pub fn from_ufo(font: &norad::Font) -> Self {
+        let mut anchors = MarkAnchors::new();
+        for glyph in font.default_layer().iter() {
+            for anchor in &glyph.anchors {
+                if let Some(name) = &anchor.name {
+                    anchors.add_anchor(
+                        GlyphName::new(glyph.name().as_str()),
+                        name.as_str(),
+                        anchor.x.round() as i16,
+                        anchor.y.round() as i16,
+                    );
+                }
+            }
+        }
+        anchors
+    }
+
+    /// Add a named anchor to a glyph.
+    ///
+    /// If the glyph already has an anchor with this name, it is replaced.
+    pub fn add_anchor(&mut self, glyph: GlyphName, name: impl Into<SmolStr>, x: i16, y: i16) {
+        self.glyphs
+            .entry(glyph)
+            .or_default()
+            .insert(name.into(), (x, y));
+    }
+
+    /// `true` if there are no anchors.
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    /// Resolve glyph names, and group anchors by the name they attach to.
+    ///
+    /// Only names with at least one mark and at least one base (or base mark)
+    /// are returned, ordered by name. Glyphs for which `skip_mark` returns
+    /// `true` are not used as marks.
+    pub(crate) fn resolve(
+        &self,
+        glyph_map: &GlyphMap,
+        skip_mark: impl Fn(GlyphId) -> bool,
+    ) -> Vec<ResolvedAttachment> {
+        let mut attachments = BTreeMap::<SmolStr, ResolvedAttachment>::new();
+        let mut mark_glyphs = HashSet::new();
+        for (glyph, anchors) in &self.glyphs {
+            let Some(gid) = glyph_map.get(glyph) else {
+                log::warn!("glyph '{glyph}' with anchors is not in the font");
+                continue;
+            };
+            for (name, pos) in anchors {
+                if let Some(name) = name.strip_prefix(MARK_ANCHOR_PREFIX) {
+                    mark_glyphs.insert(gid);
+                    if !skip_mark(gid) {
+                        attachment(&mut attachments, name).marks.insert(gid, *pos);
+                    }
+                }
+            }
+        }
+        for (glyph, anchors) in &self.glyphs {
+            let Some(gid) = glyph_map.get(glyph) else {
+                continue;
+            };
+            for (name, pos) in anchors
+                .iter()
+                .filter(|(name, _)| !name.starts_with(MARK_ANCHOR_PREFIX))
+            {
+                let item = attachment(&mut attachments, name);
+                if mark_glyphs.contains(&gid) {
+                    item.base_marks.insert(gid, *pos);
+                } else {
+                    item.bases.insert(gid, *pos);
+                }
+            }
+        }
+        attachments
+            .into_values()
+            .filter(|item| !item.marks.is_empty())
+            .filter(|item| !item.bases.is_empty() || !item.base_marks.is_empty())
+            .collect()
+    }
+}
+
+fn attachment<'a>(
+    attachments: &'a mut BTreeMap<SmolStr, ResolvedAttachment>,
+    name: &str,
+) -> &'a mut ResolvedAttachment {
+    attachments
+        .entry(name.into())
+        .or_insert_with(|| ResolvedAttachment {
+            name: name.into(),
+            ..Default::default()
+        })
+}
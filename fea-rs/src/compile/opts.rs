@@ -1,9 +1,121 @@
 //! Options used during compilation
 
+use std::collections::{HashMap, HashSet};
+
+use write_fonts::types::Tag;
+
+use crate::common::GlyphId;
+
+use super::VariationAxis;
+
 /// Options for configuring compilation behaviour.
-#[derive(Clone, Debug, Default)]
+///
+/// By default we try to match the output of [feaLib], the feature compiler
+/// in fontTools; in a few places this means deviating from the most compact
+/// encoding, or from a strict reading of the spec. Each such behaviour has
+/// a corresponding toggle here.
+///
+/// [feaLib]: https://fonttools.readthedocs.io/en/latest/feaLib/index.html
+#[derive(Clone, Debug)]
 pub struct Opts {
     pub(crate) make_post_table: bool,
+    pub(crate) force_chain_pos: bool,
+    pub(crate) pair_pos_class_0: bool,
+    pub(crate) compact_value_formats: bool,
+    pub(crate) rtl_pair_pos_placement: bool,
+    pub(crate) pair_pos_conflicts: PairPosConflicts,
+    pub(crate) feature_record_order: FeatureRecordOrder,
+    pub(crate) infer_glyph_classes: bool,
+    pub(crate) warn_unused: bool,
+    pub(crate) pedantic: bool,
+    pub(crate) missing_glyphs: MissingGlyphs,
+    pub(crate) implicit_default_lang_system: bool,
+    pub(crate) implicit_script_lang_systems: bool,
+    pub(crate) forward_class_references: bool,
+    pub(crate) unicode_name_cmap: Option<HashMap<u32, GlyphId>>,
+    pub(crate) glyph_subset: Option<HashSet<GlyphId>>,
+    // (from, to, whether 'from' is also kept)
+    pub(crate) feature_remap: Vec<(Tag, Tag, bool)>,
+    // (script, language), where no language means every language
+    pub(crate) language_system_subset: Option<Vec<(Tag, Option<Tag>)>>,
+    pub(crate) variation_axes: Vec<VariationAxis>,
+}
+
+/// How to handle glyphs that are referenced in the FEA but are not in the font.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MissingGlyphs {
+    /// A missing glyph is an error. This is the default.
+    #[default]
+    Error,
+    /// Warn, and ignore any rule that references a missing glyph.
+    ///
+    /// Missing glyphs are also removed from glyph class definitions and
+    /// glyph ranges. This is useful when compiling FEA written for a larger
+    /// glyph set, and matches the behaviour of feaLib's `ignore missing
+    /// glyphs` option.
+    Drop,
+    /// Warn, and compile each missing glyph as `.notdef`.
+    Placeholder,
+}
+
+/// How to compile a glyph pair that is also covered by a class pair rule with
+/// a different value, in the same pair positioning subtable.
+///
+/// Glyph pairs are written to their own subtables, which come before the
+/// class-based subtables, so by default a glyph pair takes precedence over a
+/// class pair wherever it appears in the lookup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PairPosConflicts {
+    /// The glyph pair is used. This matches feaLib, and is the default.
+    #[default]
+    SpecificWins,
+    /// The rule that comes first is used; a glyph pair after a class pair
+    /// that covers it is ignored, with a warning.
+    FirstWins,
+    /// A conflict is an error.
+    Error,
+}
+
+/// How to order the feature records that share a tag.
+///
+/// Feature records are always sorted by tag, as the spec requires. A feature
+/// has more than one record when it has different lookups in different
+/// language systems, and the spec does not say how these should be ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FeatureRecordOrder {
+    /// Order records by the first language system that uses each of them,
+    /// comparing language tags and then script tags. This matches feaLib, and
+    /// is the default.
+    #[default]
+    FirstLanguageSystem,
+    /// Order records by their lists of lookup indices.
+    LookupIndices,
+}
+
+impl Default for Opts {
+    fn default() -> Self {
+        Self {
+            make_post_table: false,
+            force_chain_pos: true,
+            pair_pos_class_0: true,
+            compact_value_formats: false,
+            rtl_pair_pos_placement: false,
+            pair_pos_conflicts: PairPosConflicts::SpecificWins,
+            feature_record_order: FeatureRecordOrder::FirstLanguageSystem,
+            infer_glyph_classes: true,
+            warn_unused: false,
+            pedantic: false,
+            missing_glyphs: MissingGlyphs::Error,
+            implicit_default_lang_system: false,
+            implicit_script_lang_systems: false,
+            forward_class_references: false,
+            unicode_name_cmap: None,
+            glyph_subset: None,
+            feature_remap: Vec::new(),
+            language_system_subset: None,
+            variation_axes: Vec::new(),
+        }
+    }
 }
 
 impl Opts {
@@ -17,4 +129,285 @@ impl Opts {
         self.make_post_table = flag;
         self
     }
+
+    /// If `true` (the default), contextual positioning is always compiled to
+    /// chained contextual lookups (GPOS type 8), matching feaLib.
+    ///
+    /// If `false`, rules without backtrack or lookahead sequences are compiled
+    /// to the more compact contextual lookup type (GPOS type 7).
+    ///
+    /// See <https://github.com/fonttools/fonttools/issues/2539>.
+    pub fn force_chain_pos(mut self, flag: bool) -> Self {
+        self.force_chain_pos = flag;
+        self
+    }
+
+    /// If `true` (the default), the first class definition of class-based
+    /// pair positioning subtables will assign class 0 to one of the classes
+    /// in the rules, matching feaLib.
+    ///
+    /// If `false`, class 0 is left unused, and implicitly refers to all
+    /// glyphs not otherwise assigned a class.
+    pub fn pair_pos_class_0(mut self, flag: bool) -> Self {
+        self.pair_pos_class_0 = flag;
+        self
+    }
+
+    /// If `true`, each pair positioning subtable uses the smallest value
+    /// formats that can represent its records, dropping any component that is
+    /// zero in every record.
+    ///
+    /// This is off by default because feaLib keeps explicit zero values: the
+    /// `0` in `pos a b 0;` is written as a zero x advance (or y advance, in
+    /// vertical features). Keeping them means the output matches feaLib's
+    /// byte for byte; turning this on makes GPOS smaller instead.
+    ///
+    /// Single positioning records never include zero values, and each single
+    /// positioning subtable only contains records with the same format, so
+    /// those subtables already use the smallest formats either way.
+    pub fn compact_value_formats(mut self, flag: bool) -> Self {
+        self.compact_value_formats = flag;
+        self
+    }
+
+    /// If `true`, a single-number value record in a pair positioning rule in
+    /// a lookup with the `RightToLeft` flag adjusts both the x placement and
+    /// the x advance, so that `pos a b -20;` is compiled as
+    /// `pos a b <-20 0 -20 0>;`.
+    ///
+    /// In right-to-left text the advance adjustment moves the glyphs that
+    /// come after the pair, so adjusting the placement as well keeps the
+    /// first glyph in place relative to the glyph before it; this is the
+    /// convention used by kerning generated for Arabic and Hebrew, and gives
+    /// the expected result in shaping engines. The default, `false`, matches
+    /// feaLib, which always adjusts only the advance. Vertical features and
+    /// value records with explicit placements are not affected.
+    pub fn rtl_pair_pos_placement(mut self, flag: bool) -> Self {
+        self.rtl_pair_pos_placement = flag;
+        self
+    }
+
+    /// How to handle a glyph pair rule that conflicts with a class pair rule
+    /// in the same subtable.
+    ///
+    /// The default, [`PairPosConflicts::SpecificWins`], matches feaLib. Since
+    /// kerning exceptions are usually written this way, conflicts are only
+    /// reported with the other modes; each diagnostic points at both rules.
+    pub fn pair_pos_conflicts(mut self, mode: PairPosConflicts) -> Self {
+        self.pair_pos_conflicts = mode;
+        self
+    }
+
+    /// How to order feature records that have the same tag.
+    ///
+    /// The default, [`FeatureRecordOrder::FirstLanguageSystem`], matches
+    /// feaLib. Records with different tags are always sorted by tag.
+    pub fn feature_record_order(mut self, order: FeatureRecordOrder) -> Self {
+        self.feature_record_order = order;
+        self
+    }
+
+    /// If `true` (the default), when the FEA does not explicitly declare
+    /// GDEF glyph classes we will infer them from mark classes and
+    /// mark-attachment lookups, matching feaLib.
+    ///
+    /// If `false`, the GDEF glyph class definition table will only be written
+    /// if it is declared explicitly.
+    pub fn infer_glyph_classes(mut self, flag: bool) -> Self {
+        self.infer_glyph_classes = flag;
+        self
+    }
+
+    /// If `true`, we will warn when the input triggers behaviour that
+    /// matches feaLib but differs from the FEA spec.
+    ///
+    /// Currently this reports contextual positioning lookups that are
+    /// compiled as chained contextual lookups (see
+    /// [`force_chain_pos`](Self::force_chain_pos)), and ligature glyphs that
+    /// are left out of inferred GDEF glyph classes, which the spec would
+    /// infer from ligature substitutions.
+    ///
+    /// This is `false` by default.
+    pub fn pedantic(mut self, flag: bool) -> Self {
+        self.pedantic = flag;
+        self
+    }
+
+    /// If `true`, we will warn about glyph classes, mark classes, named
+    /// anchors and top-level lookups that are defined but never used.
+    ///
+    /// This is `false` by default.
+    pub fn warn_unused(mut self, flag: bool) -> Self {
+        self.warn_unused = flag;
+        self
+    }
+
+    /// Set how glyphs that are not in the glyph map are handled.
+    ///
+    /// By default these are an error; see [`MissingGlyphs`] for the
+    /// alternatives.
+    pub fn missing_glyphs(mut self, mode: MissingGlyphs) -> Self {
+        self.missing_glyphs = mode;
+        self
+    }
+
+    /// If `true`, `languagesystem DFLT dflt` is registered implicitly when the
+    /// FEA declares other language systems but not this one.
+    ///
+    /// With no `languagesystem` statements at all, `DFLT dflt` is always used,
+    /// as in feaLib. When only other systems are declared, rules in feature
+    /// blocks are not registered for the default script, and so will not
+    /// apply to text in scripts the font does not list; setting this registers
+    /// them there as well, with a warning.
+    ///
+    /// This is `false` by default.
+    pub fn implicit_default_lang_system(mut self, flag: bool) -> Self {
+        self.implicit_default_lang_system = flag;
+        self
+    }
+
+    /// If `true`, `languagesystem <script> dflt` is registered implicitly for
+    /// each script named in a `script` statement that has no such
+    /// `languagesystem`.
+    ///
+    /// Without this, rules after a `script` statement for an undeclared
+    /// script are only registered for that script in that feature, and rules
+    /// in other features (or before the `script` statement) are not
+    /// registered for it at all. Some older sources rely on makeotf adding
+    /// these script records; setting this approximates that behaviour, with a
+    /// warning for each script that is added.
+    ///
+    /// This is `false` by default.
+    pub fn implicit_script_lang_systems(mut self, flag: bool) -> Self {
+        self.implicit_script_lang_systems = flag;
+        self
+    }
+
+    /// If `true`, a glyph class can be referenced before it is defined.
+    ///
+    /// The spec requires a class to be defined before it is used, but some
+    /// generators emit classes in an arbitrary order. With this set, a
+    /// reference to a class that is defined later in the file is allowed, and
+    /// classes that reference each other in a cycle are an error. Since a
+    /// reference may come before or after the definition, each class can only
+    /// be defined once.
+    ///
+    /// This is `false` by default.
+    pub fn forward_class_references(mut self, flag: bool) -> Self {
+        self.forward_class_references = flag;
+        self
+    }
+
+    /// Check glyph names in Unicode notation against a character map.
+    ///
+    /// A glyph named `uniXXXX` or `uXXXX` (with four to six uppercase hex
+    /// digits) is expected to be the glyph that the font maps that codepoint
+    /// to. If it is not, rules that use it won't apply to text with that
+    /// character, which is a common source of dead rules in generated
+    /// feature files; with this option, each such glyph name gets a warning.
+    /// Names with a suffix, such as `uni0041.sc`, are not checked.
+    ///
+    /// The map goes from codepoints to glyph ids, as in the `cmap` table.
+    pub fn check_unicode_names(mut self, cmap: impl IntoIterator<Item = (u32, GlyphId)>) -> Self {
+        self.unicode_name_cmap = Some(cmap.into_iter().collect());
+        self
+    }
+
+    /// Compile for a subset of the glyphs in the glyph map.
+    ///
+    /// Rules that reference glyphs outside of this set are removed, or
+    /// narrowed where a glyph class allows it, and any lookups and features
+    /// that are left empty are removed too. The resulting tables are
+    /// consistent with a font subset to these glyphs, with glyph ids
+    /// unchanged.
+    ///
+    /// `.notdef` is always part of the subset.
+    pub fn glyph_subset(mut self, glyphs: impl IntoIterator<Item = GlyphId>) -> Self {
+        self.glyph_subset = Some(glyphs.into_iter().collect());
+        self
+    }
+
+    /// Register the lookups of the feature `from` under the feature `to`
+    /// instead, in every language system.
+    ///
+    /// If `to` is also defined in the FEA, the lookups of both are merged. A
+    /// required feature stays required under its new tag. Feature parameters,
+    /// such as the names of stylistic sets, are not moved.
+    ///
+    /// This can be used to produce 'feature frozen' builds, where the rules
+    /// of an optional feature such as `ss01` are applied by default, without
+    /// editing the source. Each feature is remapped once, using the features
+    /// as they are written in the FEA, so renaming `a` to `b` and `b` to `c`
+    /// does not move the lookups of `a` to `c`.
+    pub fn rename_feature(mut self, from: Tag, to: Tag) -> Self {
+        self.feature_remap.push((from, to, false));
+        self
+    }
+
+    /// Register the lookups of the feature `from` under the feature `to` as
+    /// well, in every language system.
+    ///
+    /// This is like [`rename_feature`](Self::rename_feature), except that
+    /// `from` is kept as well.
+    pub fn copy_feature(mut self, from: Tag, to: Tag) -> Self {
+        self.feature_remap.push((from, to, true));
+        self
+    }
+
+    /// Compile only the features registered for these scripts.
+    ///
+    /// Features registered for any other script, including `DFLT`, are
+    /// removed along with their script records, as are any lookups that are
+    /// only used by those features. Lookups that are not used by any feature
+    /// are kept, as usual.
+    ///
+    /// This can be combined with
+    /// [`language_system_subset`](Self::language_system_subset); a language
+    /// system is kept if either allows it.
+    pub fn script_subset(mut self, scripts: impl IntoIterator<Item = Tag>) -> Self {
+        self.language_system_subset
+            .get_or_insert_with(Vec::new)
+            .extend(scripts.into_iter().map(|script| (script, None)));
+        self
+    }
+
+    /// Compile only the features registered for these (script, language)
+    /// pairs.
+    ///
+    /// This is like [`script_subset`](Self::script_subset), but only keeps
+    /// the given languages of each script.
+    pub fn language_system_subset(mut self, systems: impl IntoIterator<Item = (Tag, Tag)>) -> Self {
+        self.language_system_subset
+            .get_or_insert_with(Vec::new)
+            .extend(
+                systems
+                    .into_iter()
+                    .map(|(script, lang)| (script, Some(lang))),
+            );
+        self
+    }
+
+    /// The variation axes of the font, for compiling variable metrics.
+    ///
+    /// Variable anchors, such as `<anchor (wght=200:100 wght=900:150) 0>`,
+    /// give a value at each of a number of locations; these are normalized
+    /// using the ranges of these axes, and the deltas are stored in the item
+    /// variation store of the GDEF table. Any `avar` mapping is not applied.
+    ///
+    /// Variable metrics are an error if no axes are set, which is the default.
+    pub fn variation_axes(mut self, axes: impl IntoIterator<Item = VariationAxis>) -> Self {
+        self.variation_axes = axes.into_iter().collect();
+        self
+    }
+
+    /// `false` if this language system is excluded by a script or language
+    /// system subset.
+    pub(crate) fn keeps_language_system(&self, script: Tag, language: Tag) -> bool {
+        let Some(subset) = self.language_system_subset.as_ref() else {
+            return true;
+        };
+        subset
+            .iter()
+            .any(|(s, lang)| *s == script && lang.unwrap_or(language) == language)
+    }
 }
@@ -1,6 +1,9 @@
 //! The result of a compilation
 
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsString,
+};
 
 use write_fonts::{
     dump_table,
@@ -14,14 +17,17 @@ use write_fonts::{
 };
 
 use super::{
-    error::BinaryCompilationError,
+    cache::{BlockLookups, CompilationCache},
+    error::{BinaryCompilationError, CompileError},
     features::SizeFeature,
-    lookups::{AllLookups, FeatureKey, LookupId},
+    fingerprint::{Fingerprint, StableHasher},
+    lookups::{self, AllLookups, FeatureKey, LookupId},
+    summary::{FeatureInfo, LookupInfo},
     tables::Tables,
     tags, Opts,
 };
 
-use crate::{Diagnostic, GlyphMap};
+use crate::{common::GlyphId, parse::Location, Diagnostic, GlyphMap};
 
 /// The output of a compilation operation.
 ///
@@ -36,9 +42,173 @@ pub struct Compilation {
     pub(crate) features: BTreeMap<FeatureKey, Vec<LookupId>>,
     pub(crate) required_features: HashSet<FeatureKey>,
     pub(crate) size: Option<SizeFeature>,
+    pub(crate) feature_params: HashMap<Tag, FeatureParams>,
+    pub(crate) blocks: Vec<BlockLookups>,
+    pub(crate) lookup_sources: HashMap<LookupId, Vec<Location>>,
+    // the canonical paths of the sources, sorted
+    pub(crate) sources: Vec<OsString>,
 }
 
 impl Compilation {
+    /// The features in this compilation, for each language system.
+    ///
+    /// Features are sorted by script, then language, then feature tag.
+    ///
+    /// This returns an error if a feature uses a lookup whose index does not
+    /// fit in 16 bits, which [`assemble`](Self::assemble) would also reject.
+    pub fn features(&self) -> Result<Vec<FeatureInfo>, CompileError> {
+        let mut result = self
+            .features
+            .iter()
+            .map(|(key, lookups)| {
+                let (gpos_lookups, gsub_lookups) = lookups::split_lookups(lookups)?;
+                Ok(FeatureInfo {
+                    feature: key.feature,
+                    script: key.script,
+                    language: key.language,
+                    required: self.required_features.contains(key),
+                    gsub_lookups,
+                    gpos_lookups,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        result.sort_by_key(|info| (info.script, info.language, info.feature));
+        Ok(result)
+    }
+
+    /// Add GSUB lookups to a feature, in one language system.
+    ///
+    /// This is for callers that generate lookups for a feature themselves,
+    /// or that want to reuse lookups from the feature file in another
+    /// feature. `lookups` are indices in the GSUB lookup list, as in
+    /// [`FeatureInfo::gsub_lookups`]. If the feature does not exist for this
+    /// language system it is created, and lookups it already has are not
+    /// added again. The changes are reflected in [`features`] and in the
+    /// assembled tables.
+    ///
+    /// # Panics
+    ///
+    /// If an index is not in the GSUB lookup list.
+    ///
+    /// [`features`]: Self::features
+    pub fn add_gsub_feature_lookups(
+        &mut self,
+        key: FeatureKey,
+        lookups: impl IntoIterator<Item = u16>,
+    ) {
+        let (_, count) = self.lookups.lookup_counts();
+        self.add_feature_lookups(key, lookups, count, LookupId::Gsub);
+    }
+
+    /// Add GPOS lookups to a feature, in one language system.
+    ///
+    /// `lookups` are indices in the GPOS lookup list, as in
+    /// [`FeatureInfo::gpos_lookups`]; see [`add_gsub_feature_lookups`] for
+    /// details.
+    ///
+    /// # Panics
+    ///
+    /// If an index is not in the GPOS lookup list.
+    ///
+    /// [`add_gsub_feature_lookups`]: Self::add_gsub_feature_lookups
+    pub fn add_gpos_feature_lookups(
+        &mut self,
+        key: FeatureKey,
+        lookups: impl IntoIterator<Item = u16>,
+    ) {
+        let (count, _) = self.lookups.lookup_counts();
+        self.add_feature_lookups(key, lookups, count, LookupId::Gpos);
+    }
+
+    fn add_feature_lookups(
+        &mut self,
+        key: FeatureKey,
+        lookups: impl IntoIterator<Item = u16>,
+        count: usize,
+        to_id: fn(usize) -> LookupId,
+    ) {
+        let feature_lookups = self.features.entry(key).or_default();
+        for index in lookups {
+            let index = index as usize;
+            assert!(
+                index < count,
+                "lookup index {index} out of range for {count} lookups"
+            );
+            let id = to_id(index);
+            if !feature_lookups.contains(&id) {
+                feature_lookups.push(id);
+            }
+        }
+    }
+
+    /// Summaries of the lookups in the GSUB table, in lookup list order.
+    ///
+    /// Indices into this list match the indices in [`FeatureInfo::gsub_lookups`].
+    /// Computing subtable counts requires building each lookup.
+    pub fn gsub_lookups(&self) -> Vec<LookupInfo> {
+        self.with_sources(self.lookups.gsub_info(), LookupId::Gsub)
+    }
+
+    /// Summaries of the lookups in the GPOS table, in lookup list order.
+    ///
+    /// Indices into this list match the indices in [`FeatureInfo::gpos_lookups`].
+    /// Computing subtable counts requires building each lookup.
+    pub fn gpos_lookups(&self) -> Vec<LookupInfo> {
+        self.with_sources(self.lookups.gpos_info(), LookupId::Gpos)
+    }
+
+    /// The mark attachment class of each glyph, as written to the
+    /// `MarkAttachClassDef` of the GDEF table.
+    ///
+    /// Classes are generated from `lookupflag MarkAttachmentType` statements:
+    /// each distinct glyph class is numbered from 1, in the order it is first
+    /// used, and this number is the mark attachment type in the flags of the
+    /// lookups that use it.
+    pub fn mark_attach_classes(&self) -> BTreeMap<GlyphId, u16> {
+        self.tables
+            .gdef
+            .as_ref()
+            .map(|gdef| gdef.mark_attach_class.clone())
+            .unwrap_or_default()
+    }
+
+    /// The statements that added rules to the GSUB lookup at this index.
+    ///
+    /// This is the same as [`LookupInfo::sources`], without needing to build
+    /// the lookup.
+    pub fn gsub_lookup_sources(&self, index: usize) -> &[Location] {
+        self.lookup_sources
+            .get(&LookupId::Gsub(index))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The statements that added rules to the GPOS lookup at this index.
+    ///
+    /// This is the same as [`LookupInfo::sources`], without needing to build
+    /// the lookup.
+    pub fn gpos_lookup_sources(&self, index: usize) -> &[Location] {
+        self.lookup_sources
+            .get(&LookupId::Gpos(index))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn with_sources(
+        &self,
+        mut lookups: Vec<LookupInfo>,
+        to_id: fn(usize) -> LookupId,
+    ) -> Vec<LookupInfo> {
+        for (i, info) in lookups.iter_mut().enumerate() {
+            info.sources = self
+                .lookup_sources
+                .get(&to_id(i))
+                .cloned()
+                .unwrap_or_default();
+        }
+        lookups
+    }
+
     /// Generate all the final tables and add them to a builder.
     ///
     /// This builder can be used to get generate the final binary.
@@ -47,7 +217,65 @@ impl Compilation {
         glyph_map: &GlyphMap,
         opts: Opts,
     ) -> Result<FontBuilder<'static>, BinaryCompilationError> {
-        let mut builder = self.apply(None)?;
+        self.assemble_impl(glyph_map, opts, None)
+    }
+
+    /// Generate all the final tables, reusing lookups from an earlier assembly.
+    ///
+    /// Lookups generated by feature and lookup blocks that have not changed
+    /// since the last time `cache` was used are not built again; the cache is
+    /// then updated with the lookups from this compilation. See
+    /// [`CompilationCache`] for details.
+    pub fn assemble_with_cache(
+        &self,
+        glyph_map: &GlyphMap,
+        opts: Opts,
+        cache: &mut CompilationCache,
+    ) -> Result<FontBuilder<'static>, BinaryCompilationError> {
+        self.assemble_impl(glyph_map, opts, Some(cache))
+    }
+
+    /// The canonical paths of the root source and every file it includes.
+    ///
+    /// Paths are sorted, and are canonicalized by the [`SourceResolver`] used
+    /// for parsing.
+    ///
+    /// [`SourceResolver`]: crate::parse::SourceResolver
+    pub fn sources(&self) -> &[OsString] {
+        &self.sources
+    }
+
+    /// Compute a [`Fingerprint`] of the tables this compilation generates and
+    /// of its [`sources`].
+    ///
+    /// The tables are assembled as they would be by [`assemble`], with the
+    /// same arguments. Changes to the sources that do not affect the tables,
+    /// such as to whitespace or comments, do not change the fingerprint,
+    /// unless they change which files are included.
+    ///
+    /// [`sources`]: Self::sources
+    /// [`assemble`]: Self::assemble
+    pub fn fingerprint(
+        &self,
+        glyph_map: &GlyphMap,
+        opts: Opts,
+    ) -> Result<Fingerprint, BinaryCompilationError> {
+        let font = self.assemble(glyph_map, opts)?.build();
+        let mut hasher = StableHasher::new();
+        hasher.write_item(&font);
+        for path in &self.sources {
+            hasher.write_path(path);
+        }
+        Ok(hasher.finish())
+    }
+
+    fn assemble_impl(
+        &self,
+        glyph_map: &GlyphMap,
+        opts: Opts,
+        cache: Option<&mut CompilationCache>,
+    ) -> Result<FontBuilder<'static>, BinaryCompilationError> {
+        let mut builder = self.apply(None, cache)?;
         // because we often inspect our output with ttx, and ttx fails if maxp is
         // missing, we create a maxp table.
         let maxp = Maxp::new(glyph_map.len().try_into().unwrap());
@@ -64,6 +292,7 @@ impl Compilation {
     fn apply<'a>(
         &self,
         font: impl Into<Option<FontRef<'a>>>,
+        mut cache: Option<&mut CompilationCache>,
     ) -> Result<FontBuilder<'a>, BinaryCompilationError> {
         let font = font.into();
         let mut builder = FontBuilder::default();
@@ -106,7 +335,16 @@ impl Compilation {
             builder.add_table(Tag::new(b"STAT"), dump_table(&stat)?);
         }
 
-        let (mut gsub, mut gpos) = self.lookups.build(&self.features, &self.required_features);
+        let prebuilt = cache
+            .as_mut()
+            .map(|cache| cache.prebuilt(&self.blocks))
+            .unwrap_or_default();
+        let (mut gsub, mut gpos) =
+            self.lookups
+                .build(&self.features, &self.required_features, prebuilt)?;
+        if let Some(cache) = cache {
+            cache.update(&self.blocks, gsub.as_ref(), gpos.as_ref());
+        }
 
         let mut feature_params = HashMap::new();
         if let Some(size) = self.size.as_ref() {
@@ -127,6 +365,11 @@ impl Compilation {
             feature_params.insert((tags::GSUB, *tag), FeatureParams::CharacterVariant(params));
         }
 
+        for (tag, params) in self.feature_params.iter() {
+            feature_params.insert((tags::GSUB, *tag), params.clone());
+            feature_params.insert((tags::GPOS, *tag), params.clone());
+        }
+
         // actually add feature_params as appropriate
         if !feature_params.is_empty() {
             if let Some(gsub) = gsub.as_mut() {
@@ -157,6 +400,10 @@ impl Compilation {
             builder.add_table(Tag::new(b"name"), dump_table(&name)?);
         }
 
+        for (tag, data) in &self.tables.custom {
+            builder.add_table(*tag, data.clone());
+        }
+
         if let Some(font) = font {
             for record in font.table_directory.table_records() {
                 if !builder.contains(record.tag()) {
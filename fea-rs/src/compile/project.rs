@@ -0,0 +1,338 @@
+//! Compiling several related targets at once
+
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::{
+    parse::{SourceLoadError, SourceResolver},
+    util::paths,
+    GlyphMap,
+};
+
+use super::{
+    error::{CompilerError, ProjectError},
+    Compilation, CompilationCache, Compiler, Opts,
+};
+
+#[cfg(feature = "manifest")]
+mod manifest;
+
+/// A set of feature files that are compiled together.
+///
+/// A project describes one or more [targets][ProjectTarget], such as the
+/// roman and italic masters of a family, which may share included files and
+/// a glyph source. All targets are compiled with a single call to
+/// [`compile`][Self::compile]:
+///
+/// ```no_run
+/// # use fea_rs::compile::{FeaProject, GlyphSource, Opts, ProjectTarget};
+/// let mut project = FeaProject::new("path/to/family")
+///     .with_include_dir("shared")
+///     .with_glyph_source(GlyphSource::GlyphOrderFile("glyph_order.txt".into()))
+///     .with_target(ProjectTarget::new("Roman", "roman/features.fea"))
+///     .with_target(
+///         ProjectTarget::new("Italic", "italic/features.fea")
+///             .with_opts(Opts::new().compact_value_formats(true)),
+///     );
+/// for output in project.compile().unwrap() {
+///     std::fs::write(format!("{}.ttf", output.name), output.binary).unwrap();
+/// }
+/// ```
+///
+/// With the `manifest` feature, a project can also be described by a file;
+/// see `FeaProject::from_manifest`.
+///
+/// Each included file is only loaded once per call to `compile`, however
+/// many targets include it, and each glyph source is only loaded once. The
+/// targets also share a [`CompilationCache`], so that a block that compiles to
+/// the same lookups in several targets is only built once, and so that when
+/// the project is compiled again after an edit, the lookups of unchanged
+/// blocks are reused.
+#[derive(Debug)]
+pub struct FeaProject {
+    project_root: PathBuf,
+    include_dirs: Vec<PathBuf>,
+    glyph_source: Option<GlyphSource>,
+    targets: Vec<ProjectTarget>,
+    cache: CompilationCache,
+}
+
+/// A single feature file to compile as part of a [`FeaProject`].
+#[derive(Clone, Debug)]
+pub struct ProjectTarget {
+    name: String,
+    root_file: PathBuf,
+    glyph_source: Option<GlyphSource>,
+    opts: Opts,
+}
+
+/// Where to find the glyph order for a target.
+///
+/// Relative paths are resolved against the root of the [`FeaProject`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum GlyphSource {
+    /// A glyph map that has already been loaded.
+    Map(GlyphMap),
+    /// A text file with one glyph name per line.
+    GlyphOrderFile(PathBuf),
+    /// A binary font with a 'post' table.
+    Font(PathBuf),
+    /// A UFO with a 'public.glyphOrder' key.
+    #[cfg(feature = "ufo")]
+    Ufo(PathBuf),
+}
+
+/// The result of compiling one target of a [`FeaProject`].
+pub struct ProjectOutput {
+    /// The name of the target.
+    pub name: String,
+    /// The compiled features and lookups.
+    pub compilation: Compilation,
+    /// The compiled tables, as a binary font.
+    pub binary: Vec<u8>,
+    /// The number of lookups reused from an earlier target, or from the
+    /// previous compilation of the project.
+    pub reused_lookups: usize,
+}
+
+impl FeaProject {
+    /// Create a new project, with no targets.
+    ///
+    /// Relative paths in the project, such as those of the targets and of
+    /// any include directories, are resolved against `project_root`. It is
+    /// also the first directory that include statements are resolved against.
+    pub fn new(project_root: impl Into<PathBuf>) -> Self {
+        FeaProject {
+            project_root: project_root.into(),
+            include_dirs: Vec::new(),
+            glyph_source: None,
+            targets: Vec::new(),
+            cache: CompilationCache::new(),
+        }
+    }
+
+    /// Add a directory to search for included files.
+    ///
+    /// An include statement is resolved against the project root and the
+    /// directory of the including file, as usual; if neither contains the
+    /// file, each include directory is tried in the order they were added.
+    pub fn with_include_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    /// The glyph source for targets that do not specify their own.
+    pub fn with_glyph_source(mut self, source: GlyphSource) -> Self {
+        self.glyph_source = Some(source);
+        self
+    }
+
+    /// Add a target to this project.
+    ///
+    /// Targets are compiled in the order they are added. If a target with the
+    /// same name already exists, it is replaced.
+    pub fn with_target(mut self, target: ProjectTarget) -> Self {
+        match self.targets.iter_mut().find(|t| t.name == target.name) {
+            Some(existing) => *existing = target,
+            None => self.targets.push(target),
+        }
+        self
+    }
+
+    /// The targets of this project.
+    pub fn targets(&self) -> &[ProjectTarget] {
+        &self.targets
+    }
+
+    /// Compile every target.
+    ///
+    /// Compilation stops at the first target that fails.
+    pub fn compile(&mut self) -> Result<Vec<ProjectOutput>, ProjectError> {
+        self.cache.start_batch();
+        let result = self.compile_targets();
+        self.cache.finish_batch();
+        result
+    }
+
+    fn compile_targets(&mut self) -> Result<Vec<ProjectOutput>, ProjectError> {
+        let resolver = ProjectResolver {
+            project_root: self.project_root.clone(),
+            include_dirs: self
+                .include_dirs
+                .iter()
+                .map(|dir| self.project_root.join(dir))
+                .collect(),
+            contents: Default::default(),
+        };
+        let default_glyphs = match &self.glyph_source {
+            Some(source) => Some(source.load(&self.project_root)?),
+            None => None,
+        };
+
+        let mut result = Vec::with_capacity(self.targets.len());
+        for target in &self.targets {
+            let glyph_map = match (&target.glyph_source, &default_glyphs) {
+                (Some(source), _) => source.load(&self.project_root)?,
+                (None, Some(glyphs)) => Cow::Borrowed(glyphs.as_ref()),
+                (None, None) => return Err(ProjectError::MissingGlyphSource(target.name.clone())),
+            };
+            let output = target
+                .compile(
+                    &self.project_root,
+                    &glyph_map,
+                    resolver.clone(),
+                    &mut self.cache,
+                )
+                .map_err(|source| ProjectError::Target {
+                    target: target.name.clone(),
+                    source,
+                })?;
+            result.push(output);
+        }
+        Ok(result)
+    }
+}
+
+impl ProjectTarget {
+    /// Create a new target with a name and the path to its root feature file.
+    pub fn new(name: impl Into<String>, root_file: impl Into<PathBuf>) -> Self {
+        ProjectTarget {
+            name: name.into(),
+            root_file: root_file.into(),
+            glyph_source: None,
+            opts: Opts::default(),
+        }
+    }
+
+    /// Use this glyph source instead of the one for the project.
+    pub fn with_glyph_source(mut self, source: GlyphSource) -> Self {
+        self.glyph_source = Some(source);
+        self
+    }
+
+    /// Specify compiler options for this target.
+    pub fn with_opts(mut self, opts: Opts) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// The name of this target.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The path of this target's root feature file.
+    pub fn root_file(&self) -> &Path {
+        &self.root_file
+    }
+
+    fn compile(
+        &self,
+        project_root: &Path,
+        glyph_map: &GlyphMap,
+        resolver: ProjectResolver,
+        cache: &mut CompilationCache,
+    ) -> Result<ProjectOutput, CompilerError> {
+        let compilation = Compiler::new(project_root.join(&self.root_file), glyph_map)
+            .with_resolver(resolver)
+            .with_opts(self.opts.clone())
+            .compile()?;
+        let binary = compilation
+            .assemble_with_cache(glyph_map, self.opts.clone(), cache)?
+            .build();
+        Ok(ProjectOutput {
+            name: self.name.clone(),
+            compilation,
+            binary,
+            reused_lookups: cache.reused_lookups(),
+        })
+    }
+}
+
+impl GlyphSource {
+    fn load(&self, project_root: &Path) -> Result<Cow<'_, GlyphMap>, ProjectError> {
+        let path = match self {
+            GlyphSource::Map(map) => return Ok(Cow::Borrowed(map)),
+            GlyphSource::GlyphOrderFile(path) | GlyphSource::Font(path) => project_root.join(path),
+            #[cfg(feature = "ufo")]
+            GlyphSource::Ufo(path) => project_root.join(path),
+        };
+        self.load_path(&path)
+            .map(Cow::Owned)
+            .map_err(|source| ProjectError::GlyphSource { path, source })
+    }
+
+    fn load_path(&self, path: &Path) -> Result<GlyphMap, Box<dyn Error + Send + Sync>> {
+        let map = match self {
+            GlyphSource::Map(map) => map.clone(),
+            GlyphSource::GlyphOrderFile(_) => {
+                super::parse_glyph_order(&std::fs::read_to_string(path)?)?
+            }
+            GlyphSource::Font(_) => super::get_post_glyph_order(&std::fs::read(path)?)?,
+            #[cfg(feature = "ufo")]
+            GlyphSource::Ufo(_) => {
+                let request = norad::DataRequest::none().lib(true);
+                super::get_ufo_glyph_order(&norad::Font::load_requested_data(path, request)?)?
+            }
+        };
+        Ok(map)
+    }
+}
+
+/// Resolves includes for the targets of a project.
+///
+/// This behaves like [`FileSystemResolver`], with the addition of include
+/// directories; the contents of each file are shared between targets.
+///
+/// [`FileSystemResolver`]: crate::parse::FileSystemResolver
+#[derive(Clone)]
+struct ProjectResolver {
+    project_root: PathBuf,
+    include_dirs: Rc<[PathBuf]>,
+    contents: Rc<RefCell<HashMap<OsString, Arc<str>>>>,
+}
+
+impl SourceResolver for ProjectResolver {
+    fn get_contents(&self, path: &OsStr) -> Result<Arc<str>, SourceLoadError> {
+        if let Some(contents) = self.contents.borrow().get(path) {
+            return Ok(contents.clone());
+        }
+        let contents: Arc<str> = std::fs::read_to_string(path)
+            .map_err(|cause| SourceLoadError::new(path.into(), cause))?
+            .into();
+        self.contents
+            .borrow_mut()
+            .insert(path.to_owned(), contents.clone());
+        Ok(contents)
+    }
+
+    fn resolve_raw_path(&self, path: &OsStr, included_from: Option<&OsStr>) -> OsString {
+        let path = Path::new(path);
+        let included_from = included_from.map(Path::new).and_then(Path::parent);
+        let resolved = paths::resolve_path(path, &self.project_root, included_from);
+        if resolved.is_absolute() || resolved.exists() {
+            return resolved.into_os_string();
+        }
+        self.include_dirs
+            .iter()
+            .find(|dir| dir.join(path).exists())
+            .map(|dir| paths::resolve_path(path, dir, None))
+            .unwrap_or(resolved)
+            .into_os_string()
+    }
+
+    fn canonicalize(&self, path: &OsStr) -> Result<OsString, SourceLoadError> {
+        std::fs::canonicalize(path)
+            .map(PathBuf::into_os_string)
+            .map_err(|io_err| SourceLoadError::new(path.into(), io_err))
+    }
+}
@@ -0,0 +1,212 @@
+//! Loading a project from a manifest file
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::{FeaProject, GlyphSource, ProjectTarget};
+use crate::compile::{error::ProjectError, Opts};
+
+/// The contents of a manifest file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    include_dirs: Vec<PathBuf>,
+    glyph_source: Option<ManifestGlyphSource>,
+    targets: Vec<ManifestTarget>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestTarget {
+    name: String,
+    root_file: PathBuf,
+    glyph_source: Option<ManifestGlyphSource>,
+    #[serde(default)]
+    opts: ManifestOpts,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ManifestGlyphSource {
+    GlyphOrderFile(PathBuf),
+    Font(PathBuf),
+    #[cfg(feature = "ufo")]
+    Ufo(PathBuf),
+}
+
+/// The options of a target, named after the methods on [`Opts`].
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestOpts {
+    make_post_table: Option<bool>,
+    force_chain_pos: Option<bool>,
+    pair_pos_class_0: Option<bool>,
+    compact_value_formats: Option<bool>,
+    rtl_pair_pos_placement: Option<bool>,
+    infer_glyph_classes: Option<bool>,
+    pedantic: Option<bool>,
+    warn_unused: Option<bool>,
+    implicit_default_lang_system: Option<bool>,
+    implicit_script_lang_systems: Option<bool>,
+    forward_class_references: Option<bool>,
+}
+
+impl FeaProject {
+    /// Load a project from a manifest file.
+    ///
+    /// The manifest is a JSON file; relative paths in it are resolved against
+    /// the directory that contains it, which is the project root. Only
+    /// `targets` is required:
+    ///
+    /// ```json
+    /// {
+    ///   "include_dirs": ["shared"],
+    ///   "glyph_source": { "glyph_order_file": "glyph_order.txt" },
+    ///   "targets": [
+    ///     { "name": "Roman", "root_file": "roman/features.fea" },
+    ///     {
+    ///       "name": "Italic",
+    ///       "root_file": "italic/features.fea",
+    ///       "glyph_source": { "font": "italic.ttf" },
+    ///       "opts": { "compact_value_formats": true }
+    ///     }
+    ///   ]
+    /// }
+    /// ```
+    ///
+    /// A glyph source is one of `glyph_order_file`, `font` or (with the `ufo`
+    /// feature) `ufo`, as in [`GlyphSource`]. The `opts` of a target can set
+    /// any of the boolean [`Opts`], by the name of their method.
+    pub fn from_manifest(path: impl AsRef<Path>) -> Result<Self, ProjectError> {
+        let path = path.as_ref();
+        let project_root = path.parent().map(PathBuf::from).unwrap_or_default();
+        std::fs::read_to_string(path)
+            .map_err(Into::into)
+            .and_then(|text| from_manifest_str(&text, project_root).map_err(Into::into))
+            .map_err(|source| ProjectError::Manifest {
+                path: path.to_owned(),
+                source,
+            })
+    }
+}
+
+fn from_manifest_str(text: &str, project_root: PathBuf) -> Result<FeaProject, serde_json::Error> {
+    let manifest: Manifest = serde_json::from_str(text)?;
+    let mut project = manifest
+        .include_dirs
+        .into_iter()
+        .fold(FeaProject::new(project_root), FeaProject::with_include_dir);
+    if let Some(source) = manifest.glyph_source {
+        project = project.with_glyph_source(source.into());
+    }
+    for target in manifest.targets {
+        let mut result =
+            ProjectTarget::new(target.name, target.root_file).with_opts(target.opts.to_opts());
+        if let Some(source) = target.glyph_source {
+            result = result.with_glyph_source(source.into());
+        }
+        project = project.with_target(result);
+    }
+    Ok(project)
+}
+
+impl ManifestOpts {
+    fn to_opts(&self) -> Opts {
+        let mut opts = Opts::new();
+        let mut set = |flag: Option<bool>, setter: fn(Opts, bool) -> Opts| {
+            if let Some(flag) = flag {
+                opts = setter(std::mem::take(&mut opts), flag);
+            }
+        };
+        set(self.make_post_table, Opts::make_post_table);
+        set(self.force_chain_pos, Opts::force_chain_pos);
+        set(self.pair_pos_class_0, Opts::pair_pos_class_0);
+        set(self.compact_value_formats, Opts::compact_value_formats);
+        set(self.rtl_pair_pos_placement, Opts::rtl_pair_pos_placement);
+        set(self.infer_glyph_classes, Opts::infer_glyph_classes);
+        set(self.pedantic, Opts::pedantic);
+        set(self.warn_unused, Opts::warn_unused);
+        set(
+            self.implicit_default_lang_system,
+            Opts::implicit_default_lang_system,
+        );
+        set(
+            self.implicit_script_lang_systems,
+            Opts::implicit_script_lang_systems,
+        );
+        set(
+            self.forward_class_references,
+            Opts::forward_class_references,
+        );
+        opts
+    }
+}
+
+impl From<ManifestGlyphSource> for GlyphSource {
+    fn from(src: ManifestGlyphSource) -> GlyphSource {
+        match src {
+            ManifestGlyphSource::GlyphOrderFile(path) => GlyphSource::GlyphOrderFile(path),
+            ManifestGlyphSource::Font(path) => GlyphSource::Font(path),
+            #[cfg(feature = "ufo")]
+            ManifestGlyphSource::Ufo(path) => GlyphSource::Ufo(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_manifest() {
+        let text = r#"{
+            "include_dirs": ["shared"],
+            "glyph_source": { "glyph_order_file": "glyph_order.txt" },
+            "targets": [
+                { "name": "Roman", "root_file": "roman/features.fea" },
+                {
+                    "name": "Italic",
+                    "root_file": "italic/features.fea",
+                    "glyph_source": { "font": "italic.ttf" },
+                    "opts": { "compact_value_formats": true, "force_chain_pos": false }
+                }
+            ]
+        }"#;
+        let project = from_manifest_str(text, "family".into()).unwrap();
+        assert_eq!(project.project_root, Path::new("family"));
+        assert_eq!(project.include_dirs, [Path::new("shared")]);
+        assert!(matches!(
+            &project.glyph_source,
+            Some(GlyphSource::GlyphOrderFile(path)) if path == Path::new("glyph_order.txt")
+        ));
+        let [roman, italic] = project.targets() else {
+            panic!("expected two targets");
+        };
+        assert_eq!(roman.name(), "Roman");
+        assert!(roman.glyph_source.is_none());
+        assert!(!roman.opts.compact_value_formats);
+        assert_eq!(italic.root_file(), Path::new("italic/features.fea"));
+        assert!(matches!(&italic.glyph_source, Some(GlyphSource::Font(_))));
+        assert!(italic.opts.compact_value_formats);
+        assert!(!italic.opts.force_chain_pos);
+        // unset options keep their defaults
+        assert!(italic.opts.pair_pos_class_0);
+    }
+
+    #[test]
+    fn bad_manifest() {
+        for text in [
+            r#"{ "include_dirs": [] }"#,
+            r#"{ "targets": [], "glyph_order": "glyph_order.txt" }"#,
+            r#"{ "targets": [{ "name": "Roman", "root_file": "a.fea", "opts": { "fast": true } }] }"#,
+            r#"{ "targets": [], "glyph_source": { "otf": "a.otf" } }"#,
+        ] {
+            assert!(from_manifest_str(text, PathBuf::new()).is_err(), "{text}");
+        }
+
+        let err = FeaProject::from_manifest("does/not/exist.json").unwrap_err();
+        assert!(matches!(err, ProjectError::Manifest { .. }), "{err}");
+    }
+}
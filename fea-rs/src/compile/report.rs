@@ -0,0 +1,1041 @@
+//! Reports on compiled lookups and features
+//!
+//! The lookup [`Report`] is intended to help find lookups that are larger
+//! than they need to be, such as kerning that is not using classes. The
+//! [`FeatureCoverage`] report shows which features apply in each language
+//! system, to help find languages that are missing features. The
+//! [`MarkCoverage`] report lists base glyphs that are missing anchors for
+//! some mark classes, which would leave those marks floating. An
+//! [`Explanation`] follows a single rule of the source into the lookups it
+//! compiles into, and the features that use them.
+//!
+//! The alternate form of the lookup report (`{:#}`) also lists the rules of
+//! each lookup, which can be useful when debugging the compiler.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Display, Formatter},
+};
+
+use write_fonts::{
+    tables::{
+        gpos as write_gpos, gsub as write_gsub,
+        layout::{ChainedSequenceContext, ClassDef, CoverageTable, SequenceContext},
+    },
+    types::Tag,
+};
+
+use super::{error::CompileError, tags, Compilation, FeatureKey, LookupInfo};
+use crate::{common::GlyphId, parse::Location, GlyphIdent, GlyphMap, ParseTree};
+
+/// A report on the lookups in a compilation.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// The GSUB lookups, in lookup list order.
+    pub gsub: Vec<LookupReport>,
+    /// The GPOS lookups, in lookup list order.
+    pub gpos: Vec<LookupReport>,
+}
+
+/// A report on a single lookup.
+#[derive(Clone, Debug)]
+pub struct LookupReport {
+    /// A summary of the lookup.
+    pub info: LookupInfo,
+    /// The features that reference this lookup, in sorted order.
+    pub features: Vec<Tag>,
+    /// The subtables of this lookup, in order.
+    pub subtables: Vec<SubtableReport>,
+    /// The rules of this lookup, one per line, for the alternate form.
+    ///
+    /// These are written in FEA syntax, but with glyph ids in place of glyph
+    /// names, and with lookups referenced by their index.
+    pub(crate) rules: String,
+}
+
+/// A report on a single subtable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubtableReport {
+    /// The subtable format.
+    pub format: u16,
+    /// The size of the subtable and its children, in bytes.
+    ///
+    /// This is an estimate, since tables shared between subtables are counted
+    /// once for each subtable. It is `None` if the subtable could not be
+    /// written, for instance because it overflows.
+    pub size: Option<usize>,
+    /// The number of glyphs in the subtable's first coverage table.
+    pub coverage: usize,
+    /// The number of classes in each of the subtable's class definitions.
+    pub classes: Vec<usize>,
+}
+
+impl Report {
+    /// Generate a report for this compilation.
+    ///
+    /// This requires building each lookup, which may be slow. It fails if
+    /// [`Compilation::features`] does.
+    pub fn new(compilation: &Compilation) -> Result<Self, CompileError> {
+        let features = compilation.features()?;
+        let features_for = |index, use_gpos: bool| {
+            let mut tags = features
+                .iter()
+                .filter(|info| {
+                    let lookups = if use_gpos {
+                        &info.gpos_lookups
+                    } else {
+                        &info.gsub_lookups
+                    };
+                    lookups.contains(&index)
+                })
+                .map(|info| info.feature)
+                .collect::<Vec<_>>();
+            tags.sort_unstable();
+            tags.dedup();
+            tags
+        };
+
+        let gsub = compilation
+            .gsub_lookups()
+            .into_iter()
+            .zip(compilation.lookups.gsub_subtable_reports())
+            .zip(compilation.lookups.gsub_rules())
+            .enumerate()
+            .map(|(i, ((info, subtables), rules))| LookupReport {
+                info,
+                features: features_for(i as u16, false),
+                subtables,
+                rules,
+            })
+            .collect();
+        let gpos = compilation
+            .gpos_lookups()
+            .into_iter()
+            .zip(compilation.lookups.gpos_subtable_reports())
+            .zip(compilation.lookups.gpos_rules())
+            .enumerate()
+            .map(|(i, ((info, subtables), rules))| LookupReport {
+                info,
+                features: features_for(i as u16, true),
+                subtables,
+                rules,
+            })
+            .collect();
+        Ok(Report { gsub, gpos })
+    }
+}
+
+impl LookupReport {
+    /// The total size of this lookup's subtables, ignoring any that overflow.
+    pub fn size(&self) -> usize {
+        self.subtables.iter().filter_map(|sub| sub.size).sum()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (table, lookups) in [("GSUB", &self.gsub), ("GPOS", &self.gpos)] {
+            for (i, lookup) in lookups.iter().enumerate() {
+                if f.alternate() {
+                    write!(f, "{table} lookup {i}: {lookup:#}")?;
+                } else {
+                    write!(f, "{table} lookup {i}: {lookup}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for LookupReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let info = &self.info;
+        write!(
+            f,
+            "{}, {} rules in {} subtables, {} bytes",
+            info.kind,
+            info.rule_count,
+            info.subtable_count,
+            self.size()
+        )?;
+        if !self.features.is_empty() {
+            let features = self.features.iter().map(Tag::to_string).collect::<Vec<_>>();
+            write!(f, " ({})", features.join(", "))?;
+        }
+        writeln!(f)?;
+        for subtable in &self.subtables {
+            writeln!(f, "    {subtable}")?;
+        }
+        if f.alternate() {
+            for rule in self.rules.lines() {
+                writeln!(f, "        {rule}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for SubtableReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "format {}: ", self.format)?;
+        match self.size {
+            Some(size) => write!(f, "{size} bytes")?,
+            None => write!(f, "overflow")?,
+        }
+        write!(f, ", {} glyphs covered", self.coverage)?;
+        if !self.classes.is_empty() {
+            let classes = self
+                .classes
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>();
+            write!(f, ", {} classes", classes.join("/"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Which features apply in each language system of a compilation.
+///
+/// When a shaper is asked for a script and language that are not in the
+/// script list of a table, it uses the default language of the script
+/// instead, or the `DFLT` script if the script is not in the list either.
+/// This is done separately for GSUB and GPOS. A language system that is in
+/// the script list only gets the features registered for it, so adding a
+/// language in one feature can leave it without the features of others.
+#[derive(Clone, Debug)]
+pub struct FeatureCoverage {
+    /// The language systems in the GSUB or GPOS script lists, as
+    /// `(script, language)`, in sorted order.
+    pub language_systems: Vec<(Tag, Tag)>,
+    /// The features in the GSUB or GPOS feature lists, in sorted order.
+    pub features: Vec<Tag>,
+    // the features of each language system in GSUB and in GPOS
+    tables: [BTreeMap<(Tag, Tag), BTreeSet<Tag>>; 2],
+}
+
+/// Whether a feature applies in a language system.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FeatureStatus {
+    /// The feature is registered for this language system.
+    Registered,
+    /// The language system is not in the script list of the feature's table,
+    /// and the feature is registered for the language system that is used
+    /// in its place.
+    Fallback {
+        /// The script that is used instead.
+        script: Tag,
+        /// The language that is used instead.
+        language: Tag,
+    },
+    /// The feature does not apply in this language system.
+    Missing,
+}
+
+impl FeatureCoverage {
+    /// Generate a feature coverage report for this compilation.
+    ///
+    /// This fails if [`Compilation::features`] does.
+    pub fn new(compilation: &Compilation) -> Result<Self, CompileError> {
+        let mut tables: [BTreeMap<_, BTreeSet<_>>; 2] = Default::default();
+        for info in compilation.features()? {
+            let system = (info.script, info.language);
+            // features without lookups are not written, except for 'size'
+            if !info.gsub_lookups.is_empty() {
+                tables[0].entry(system).or_default().insert(info.feature);
+            }
+            if !info.gpos_lookups.is_empty() || info.feature == tags::SIZE {
+                tables[1].entry(system).or_default().insert(info.feature);
+            }
+        }
+        let language_systems = tables
+            .iter()
+            .flat_map(BTreeMap::keys)
+            .copied()
+            .collect::<BTreeSet<_>>();
+        let features = tables
+            .iter()
+            .flat_map(BTreeMap::values)
+            .flatten()
+            .copied()
+            .collect::<BTreeSet<_>>();
+        Ok(FeatureCoverage {
+            language_systems: language_systems.into_iter().collect(),
+            features: features.into_iter().collect(),
+            tables,
+        })
+    }
+
+    /// Whether this feature applies for this script and language.
+    ///
+    /// The language system does not need to be in the compilation, so this
+    /// can be used to check the fallback for languages that are not
+    /// declared.
+    pub fn status(&self, script: Tag, language: Tag, feature: Tag) -> FeatureStatus {
+        self.tables
+            .iter()
+            .filter_map(|table| {
+                let system = resolve_language_system(table, script, language)?;
+                if !table[&system].contains(&feature) {
+                    None
+                } else if system == (script, language) {
+                    Some(FeatureStatus::Registered)
+                } else {
+                    Some(FeatureStatus::Fallback {
+                        script: system.0,
+                        language: system.1,
+                    })
+                }
+            })
+            .min()
+            .unwrap_or(FeatureStatus::Missing)
+    }
+
+    /// The language systems of the compilation in which this feature does
+    /// not apply.
+    pub fn missing(&self, feature: Tag) -> Vec<(Tag, Tag)> {
+        self.language_systems
+            .iter()
+            .filter(|(script, language)| {
+                self.status(*script, *language, feature) == FeatureStatus::Missing
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// The language system a shaper uses for this script and language.
+fn resolve_language_system(
+    table: &BTreeMap<(Tag, Tag), BTreeSet<Tag>>,
+    script: Tag,
+    language: Tag,
+) -> Option<(Tag, Tag)> {
+    let script = [script, tags::SCRIPT_DFLT]
+        .into_iter()
+        .find(|script| table.keys().any(|(s, _)| s == script))?;
+    [language, tags::LANG_DFLT]
+        .into_iter()
+        .map(|language| (script, language))
+        .find(|system| table.contains_key(system))
+}
+
+impl Display for FeatureCoverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.language_systems.is_empty() {
+            return Ok(());
+        }
+        write!(f, "{:9}", "")?;
+        for feature in &self.features {
+            write!(f, " {feature}")?;
+        }
+        writeln!(f)?;
+        for (script, language) in &self.language_systems {
+            write!(f, "{script}/{language}")?;
+            for feature in &self.features {
+                let cell = match self.status(*script, *language, *feature) {
+                    FeatureStatus::Registered => "x",
+                    FeatureStatus::Fallback { .. } => "*",
+                    FeatureStatus::Missing => "-",
+                };
+                write!(f, " {cell:>4}")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "x: registered, *: registered for the language system used instead, -: missing"
+        )
+    }
+}
+
+/// A report on the mark-to-base attachment of each script.
+///
+/// For each script and feature, this considers the mark-to-base lookups of
+/// the feature in every language of the script together. A base glyph that
+/// has an anchor for some mark class in these lookups, but not for another,
+/// is listed as missing an anchor for that class: a mark of that class that
+/// follows the base will not be attached to it.
+///
+/// Only base glyphs that the caller considers reachable are listed; glyphs
+/// that can't be displayed, such as unencoded glyphs that are not produced by
+/// any substitution, usually don't matter.
+#[derive(Clone, Debug, Default)]
+pub struct MarkCoverage {
+    /// The mark classes with missing anchors, sorted by script, feature and
+    /// class name.
+    pub missing: Vec<MissingAnchors>,
+    names: BTreeMap<GlyphId, GlyphIdent>,
+}
+
+/// The base glyphs without an anchor for a mark class.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingAnchors {
+    /// The script whose lookups these are.
+    pub script: Tag,
+    /// The feature whose lookups these are.
+    pub feature: Tag,
+    /// The name of the mark class.
+    pub mark_class: String,
+    /// The base glyphs that have anchors for other mark classes, but not for
+    /// this one, in glyph id order.
+    pub bases: Vec<GlyphId>,
+}
+
+impl MarkCoverage {
+    /// Generate a mark coverage report for this compilation.
+    ///
+    /// Base glyphs for which `is_reachable` returns `false` are ignored. The
+    /// glyph map is used to print the names of the base glyphs.
+    pub fn new(
+        compilation: &Compilation,
+        glyph_map: &GlyphMap,
+        is_reachable: impl Fn(GlyphId) -> bool,
+    ) -> Self {
+        // the bases with an anchor for each class, for each script and feature
+        let mut scripts = BTreeMap::<(Tag, Tag), BTreeMap<String, BTreeSet<GlyphId>>>::new();
+        for (key, lookups) in &compilation.features {
+            for id in lookups {
+                let Some(classes) = compilation.lookups.mark_to_base_classes(*id) else {
+                    continue;
+                };
+                let entry = scripts.entry((key.script, key.feature)).or_default();
+                for (class, bases) in classes {
+                    entry.entry(class.to_string()).or_default().extend(bases);
+                }
+            }
+        }
+        let mut missing = Vec::new();
+        for ((script, feature), classes) in scripts {
+            let all_bases = classes.values().flatten().collect::<BTreeSet<_>>();
+            for (mark_class, bases) in classes.iter() {
+                let without = all_bases
+                    .iter()
+                    .filter(|gid| !bases.contains(gid) && is_reachable(***gid))
+                    .map(|gid| **gid)
+                    .collect::<Vec<_>>();
+                if !without.is_empty() {
+                    missing.push(MissingAnchors {
+                        script,
+                        feature,
+                        mark_class: mark_class.clone(),
+                        bases: without,
+                    });
+                }
+            }
+        }
+        let mut names = glyph_map.reverse_map();
+        let reported = missing
+            .iter()
+            .flat_map(|item| item.bases.iter())
+            .collect::<BTreeSet<_>>();
+        names.retain(|gid, _| reported.contains(gid));
+        MarkCoverage { missing, names }
+    }
+}
+
+impl Display for MarkCoverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for item in &self.missing {
+            let n = item.bases.len();
+            let s = if n == 1 { "" } else { "s" };
+            write!(
+                f,
+                "{}/{}: {} has no anchor on {n} base{s}:",
+                item.script, item.feature, item.mark_class
+            )?;
+            for gid in &item.bases {
+                match self.names.get(gid) {
+                    Some(name) => write!(f, " {name}")?,
+                    None => write!(f, " {gid}")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// How the rule at some position in the root source is compiled.
+#[derive(Clone, Debug)]
+pub struct Explanation {
+    /// The text of the rule, as written.
+    pub statement: String,
+    /// The location of the rule.
+    pub location: Location,
+    /// The lookups the rule adds to, GSUB before GPOS, in lookup list order.
+    ///
+    /// For a contextual rule, this includes any lookups created for its
+    /// inline rules.
+    pub lookups: Vec<ExplainedLookup>,
+}
+
+/// A lookup that a rule compiles into.
+#[derive(Clone, Debug)]
+pub struct ExplainedLookup {
+    /// The index of the lookup in the GSUB or GPOS lookup list.
+    pub index: u16,
+    /// A summary of the lookup.
+    pub info: LookupInfo,
+    /// The features and language systems that reference this lookup, sorted
+    /// by script, then language, then feature tag.
+    pub features: Vec<FeatureKey>,
+}
+
+impl Explanation {
+    /// Explain the rule containing `offset`, a byte offset in the root source.
+    ///
+    /// The compilation must have been compiled from this tree. Returns `None`
+    /// if the offset is not in a substitution or positioning rule, and an
+    /// error if [`Compilation::features`] fails.
+    pub fn new(
+        tree: &ParseTree,
+        compilation: &Compilation,
+        offset: usize,
+    ) -> Result<Option<Self>, CompileError> {
+        let map = tree.source_map();
+        let Some(global) = map
+            .root_file()
+            .and_then(|root| map.global_offset(root, offset))
+        else {
+            return Ok(None);
+        };
+        let Some((range, rule)) = tree
+            .root()
+            .node_at_offset(global)
+            .into_iter()
+            .rev()
+            .find(|(_, node)| node.kind().is_rule() || node.kind().is_ignore_rule())
+        else {
+            return Ok(None);
+        };
+        let (file, range) = map.resolve_range(range);
+        let location = Location { file, range };
+        let statement = rule
+            .iter_tokens()
+            .map(|token| token.as_str())
+            .collect::<String>()
+            .trim()
+            .to_string();
+
+        let features = compilation.features()?;
+        let explain = |index: usize, info: LookupInfo| {
+            let index = index as u16;
+            let features = features
+                .iter()
+                .filter(|feature| {
+                    let lookups = if info.kind.is_gpos() {
+                        &feature.gpos_lookups
+                    } else {
+                        &feature.gsub_lookups
+                    };
+                    lookups.contains(&index)
+                })
+                .map(|feature| feature.key())
+                .collect();
+            ExplainedLookup {
+                index,
+                info,
+                features,
+            }
+        };
+        let lookups = compilation
+            .gsub_lookups()
+            .into_iter()
+            .enumerate()
+            .chain(compilation.gpos_lookups().into_iter().enumerate())
+            .filter(|(_, info)| info.sources.contains(&location))
+            .map(|(index, info)| explain(index, info))
+            .collect();
+        Ok(Some(Explanation {
+            statement,
+            location,
+            lookups,
+        }))
+    }
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.statement)?;
+        if self.lookups.is_empty() {
+            return writeln!(f, "not compiled into any lookup");
+        }
+        for lookup in &self.lookups {
+            let info = &lookup.info;
+            let table = if info.kind.is_gpos() { "GPOS" } else { "GSUB" };
+            writeln!(f, "{table} lookup {}: {}", lookup.index, info.kind)?;
+            writeln!(f, "    lookupflag {}", FlagNames(info))?;
+            if lookup.features.is_empty() {
+                writeln!(f, "    not used by any feature")?;
+            }
+            for key in &lookup.features {
+                writeln!(f, "    {} {}/{}", key.feature, key.script, key.language)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The flags of a lookup, as in a `lookupflag` statement.
+struct FlagNames<'a>(&'a LookupInfo);
+
+impl Display for FlagNames<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let flags = self.0.flags;
+        let mut names = [
+            (flags.right_to_left(), "RightToLeft"),
+            (flags.ignore_base_glyphs(), "IgnoreBaseGlyphs"),
+            (flags.ignore_ligatures(), "IgnoreLigatures"),
+            (flags.ignore_marks(), "IgnoreMarks"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name.to_string()))
+        .collect::<Vec<_>>();
+        if let Some(class) = flags.mark_attachment_type_mask() {
+            names.push(format!("MarkAttachmentType {class}"));
+        }
+        if let Some(set) = self.0.mark_filter_set {
+            names.push(format!("UseMarkFilteringSet {set}"));
+        }
+        if names.is_empty() {
+            f.write_str("0")
+        } else {
+            f.write_str(&names.join(" "))
+        }
+    }
+}
+
+/// Subtables that can describe their format, coverage and classes.
+pub(crate) trait SubtableStats {
+    /// Return a report with the size unset.
+    fn stats(&self) -> SubtableReport;
+}
+
+impl SubtableReport {
+    fn new(format: u16, coverage: &CoverageTable) -> Self {
+        SubtableReport {
+            format,
+            size: None,
+            coverage: coverage_len(coverage),
+            classes: Vec::new(),
+        }
+    }
+
+    fn with_classes(mut self, classes: impl IntoIterator<Item = usize>) -> Self {
+        self.classes = classes.into_iter().collect();
+        self
+    }
+}
+
+fn coverage_len(coverage: &CoverageTable) -> usize {
+    match coverage {
+        CoverageTable::Format1(table) => table.glyph_array.len(),
+        CoverageTable::Format2(table) => table
+            .range_records
+            .iter()
+            .map(|rec| (rec.end_glyph_id.to_u16() - rec.start_glyph_id.to_u16()) as usize + 1)
+            .sum(),
+    }
+}
+
+// the number of classes, including class 0
+fn class_count(class_def: &ClassDef) -> usize {
+    let max = match class_def {
+        ClassDef::Format1(table) => table.class_value_array.iter().max().copied(),
+        ClassDef::Format2(table) => table.class_range_records.iter().map(|r| r.class).max(),
+    };
+    max.unwrap_or_default() as usize + 1
+}
+
+impl SubtableStats for write_gpos::SinglePos {
+    fn stats(&self) -> SubtableReport {
+        match self {
+            write_gpos::SinglePos::Format1(table) => SubtableReport::new(1, &table.coverage),
+            write_gpos::SinglePos::Format2(table) => SubtableReport::new(2, &table.coverage),
+        }
+    }
+}
+
+impl SubtableStats for write_gpos::PairPos {
+    fn stats(&self) -> SubtableReport {
+        match self {
+            write_gpos::PairPos::Format1(table) => SubtableReport::new(1, &table.coverage),
+            write_gpos::PairPos::Format2(table) => SubtableReport::new(2, &table.coverage)
+                .with_classes([
+                    class_count(&table.class_def1),
+                    class_count(&table.class_def2),
+                ]),
+        }
+    }
+}
+
+impl SubtableStats for write_gpos::CursivePosFormat1 {
+    fn stats(&self) -> SubtableReport {
+        SubtableReport::new(1, &self.coverage)
+    }
+}
+
+impl SubtableStats for write_gpos::MarkBasePosFormat1 {
+    fn stats(&self) -> SubtableReport {
+        SubtableReport::new(1, &self.mark_coverage)
+    }
+}
+
+impl SubtableStats for write_gpos::MarkLigPosFormat1 {
+    fn stats(&self) -> SubtableReport {
+        SubtableReport::new(1, &self.mark_coverage)
+    }
+}
+
+impl SubtableStats for write_gpos::MarkMarkPosFormat1 {
+    fn stats(&self) -> SubtableReport {
+        SubtableReport::new(1, &self.mark1_coverage)
+    }
+}
+
+impl SubtableStats for write_gsub::SingleSubst {
+    fn stats(&self) -> SubtableReport {
+        match self {
+            write_gsub::SingleSubst::Format1(table) => SubtableReport::new(1, &table.coverage),
+            write_gsub::SingleSubst::Format2(table) => SubtableReport::new(2, &table.coverage),
+        }
+    }
+}
+
+impl SubtableStats for write_gsub::MultipleSubstFormat1 {
+    fn stats(&self) -> SubtableReport {
+        SubtableReport::new(1, &self.coverage)
+    }
+}
+
+impl SubtableStats for write_gsub::AlternateSubstFormat1 {
+    fn stats(&self) -> SubtableReport {
+        SubtableReport::new(1, &self.coverage)
+    }
+}
+
+impl SubtableStats for write_gsub::LigatureSubstFormat1 {
+    fn stats(&self) -> SubtableReport {
+        SubtableReport::new(1, &self.coverage)
+    }
+}
+
+impl SubtableStats for write_gsub::ReverseChainSingleSubstFormat1 {
+    fn stats(&self) -> SubtableReport {
+        SubtableReport::new(1, &self.coverage)
+    }
+}
+
+impl SubtableStats for SequenceContext {
+    fn stats(&self) -> SubtableReport {
+        match self {
+            SequenceContext::Format1(table) => SubtableReport::new(1, &table.coverage),
+            SequenceContext::Format2(table) => SubtableReport::new(2, &table.coverage)
+                .with_classes([class_count(&table.class_def)]),
+            SequenceContext::Format3(table) => SubtableReport::new(3, &table.coverages[0]),
+        }
+    }
+}
+
+impl SubtableStats for ChainedSequenceContext {
+    fn stats(&self) -> SubtableReport {
+        match self {
+            ChainedSequenceContext::Format1(table) => SubtableReport::new(1, &table.coverage),
+            ChainedSequenceContext::Format2(table) => SubtableReport::new(2, &table.coverage)
+                .with_classes(
+                    [
+                        &table.backtrack_class_def,
+                        &table.input_class_def,
+                        &table.lookahead_class_def,
+                    ]
+                    .map(|class_def| class_count(class_def)),
+                ),
+            ChainedSequenceContext::Format3(table) => {
+                SubtableReport::new(3, &table.input_coverages[0])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::identity;
+
+    use crate::{compile::LookupKind, util::ttx as test_utils};
+
+    use super::*;
+
+    #[test]
+    fn kerning_report() {
+        let fea = "
+feature kern {
+    pos [a b] [c d] -10;
+    pos [e f] [c d] -20;
+    subtable;
+    pos a e -5;
+} kern;
+";
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+        let report = Report::new(&compilation).unwrap();
+        assert!(report.gsub.is_empty());
+        let [kern] = report.gpos.as_slice() else {
+            panic!("expected one lookup");
+        };
+        assert_eq!(kern.info.kind, LookupKind::GposPair);
+        assert_eq!(kern.features, [Tag::new(b"kern")]);
+        assert_eq!(kern.subtables.len(), 2);
+        assert_eq!(kern.subtables[0].format, 2);
+        assert_eq!(kern.subtables[0].coverage, 4);
+        assert_eq!(kern.subtables[0].classes, [2, 2]);
+        assert_eq!(kern.subtables[1].format, 1);
+        assert_eq!(kern.subtables[1].coverage, 1);
+        assert!(kern.subtables.iter().all(|sub| sub.size.unwrap() > 0));
+
+        let text = report.to_string();
+        assert!(
+            text.starts_with("GPOS lookup 0: pair positioning, 3 rules in 2 subtables"),
+            "{text}"
+        );
+        assert!(text.contains("    format 2: "), "{text}");
+        assert!(text.contains("4 glyphs covered, 2/2 classes"), "{text}");
+    }
+
+    #[test]
+    fn lookup_rules() {
+        let fea = "
+markClass acute <anchor 0 500> @TOP;
+lookup SINGLE { sub a by b; } SINGLE;
+feature liga {
+    sub f i by f_i;
+    sub a' lookup SINGLE c;
+} liga;
+feature kern {
+    lookupflag IgnoreMarks;
+    pos a b -10;
+    pos a <0 0 5 0> c <1 2 3 4>;
+    pos [d e] [f g] 20;
+} kern;
+feature mark { pos base [a b] <anchor 250 450> mark @TOP; } mark;
+";
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+        let report = Report::new(&compilation).unwrap();
+        let rules = |lookups: &[LookupReport]| {
+            lookups
+                .iter()
+                .map(|lookup| lookup.rules.clone())
+                .collect::<Vec<_>>()
+        };
+        let gid = |name: &str| glyph_map.get(name).unwrap();
+        let [a, b, c, d, e, f, g, i, f_i, acute] =
+            ["a", "b", "c", "d", "e", "f", "g", "i", "f_i", "acute"].map(gid);
+        assert_eq!(
+            rules(&report.gsub),
+            [
+                format!("sub {a} by {b};\n"),
+                format!("sub {f} {i} by {f_i};\n"),
+                format!("sub {a}' lookup 0 {c};\n"),
+            ]
+        );
+        assert_eq!(
+            rules(&report.gpos),
+            [
+                format!(
+                    "lookupflag 8;\n\
+                     pos {a} {b} -10;\n\
+                     pos {a} 5 {c} <1 2 3 4>;\n\
+                     pos [{d} {e}] [{f} {g}] 20;\n"
+                ),
+                format!(
+                    "markClass {acute} <anchor 0 500> @TOP;\n\
+                     pos base {a} <anchor 250 450> mark @TOP;\n\
+                     pos base {b} <anchor 250 450> mark @TOP;\n"
+                ),
+            ]
+        );
+
+        // the rules are only shown in the alternate form
+        assert!(!report.to_string().contains("markClass"));
+        let text = format!("{report:#}");
+        assert!(
+            text.contains(&format!(
+                "\n        pos base {a} <anchor 250 450> mark @TOP;\n"
+            )),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn feature_coverage() {
+        let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+feature liga { sub f i by f_i; } liga;
+feature locl { script latn; language TRK exclude_dflt; sub a by b; } locl;
+feature kern { pos a b -10; } kern;
+";
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+        let coverage = FeatureCoverage::new(&compilation).unwrap();
+        let [dflt, latn, trk, cyrl] = [b"DFLT", b"latn", b"TRK ", b"cyrl"].map(|tag| Tag::new(tag));
+        let lang_dflt = Tag::new(b"dflt");
+        let [kern, liga, locl] = [b"kern", b"liga", b"locl"].map(|tag| Tag::new(tag));
+        assert_eq!(
+            coverage.language_systems,
+            [(dflt, lang_dflt), (latn, trk), (latn, lang_dflt)]
+        );
+        assert_eq!(coverage.features, [kern, liga, locl]);
+
+        // latn/TRK is in the GSUB script list, but only has 'locl'
+        assert_eq!(coverage.status(latn, trk, locl), FeatureStatus::Registered);
+        assert_eq!(coverage.status(latn, trk, liga), FeatureStatus::Missing);
+        // it is not in the GPOS script list, so uses latn/dflt instead
+        assert_eq!(
+            coverage.status(latn, trk, kern),
+            FeatureStatus::Fallback {
+                script: latn,
+                language: lang_dflt
+            }
+        );
+        // an undeclared script uses DFLT
+        assert_eq!(
+            coverage.status(cyrl, lang_dflt, liga),
+            FeatureStatus::Fallback {
+                script: dflt,
+                language: lang_dflt
+            }
+        );
+        assert_eq!(coverage.missing(liga), [(latn, trk)]);
+        assert_eq!(
+            coverage.missing(locl),
+            [(dflt, lang_dflt), (latn, lang_dflt)]
+        );
+
+        let text = coverage.to_string();
+        assert!(
+            text.starts_with(
+                "          kern liga locl
+DFLT/dflt    x    x    -
+latn/TRK     *    -    x
+latn/dflt    x    x    -
+"
+            ),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn mark_coverage() {
+        let fea = "
+languagesystem DFLT dflt;
+languagesystem arab dflt;
+markClass acute <anchor 100 450> @TOP;
+markClass cedilla <anchor 90 0> @BOTTOM;
+feature mark {
+    pos base a <anchor 250 500> mark @TOP <anchor 250 0> mark @BOTTOM;
+    pos base b <anchor 250 700> mark @TOP;
+    script arab;
+    lookup arab_bottom {
+        pos base c <anchor 250 0> mark @BOTTOM;
+    } arab_bottom;
+} mark;
+";
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+        let coverage = MarkCoverage::new(&compilation, &glyph_map, |_| true);
+        let [b, c] = ["b", "c"].map(|name| glyph_map.get(name).unwrap());
+        let mark = Tag::new(b"mark");
+        // DFLT only has the first lookup; in arab, 'c' has a bottom anchor too
+        assert_eq!(
+            coverage.missing,
+            [
+                MissingAnchors {
+                    script: Tag::new(b"DFLT"),
+                    feature: mark,
+                    mark_class: "@BOTTOM".into(),
+                    bases: vec![b],
+                },
+                MissingAnchors {
+                    script: Tag::new(b"arab"),
+                    feature: mark,
+                    mark_class: "@BOTTOM".into(),
+                    bases: vec![b],
+                },
+                MissingAnchors {
+                    script: Tag::new(b"arab"),
+                    feature: mark,
+                    mark_class: "@TOP".into(),
+                    bases: vec![c],
+                },
+            ]
+        );
+        assert_eq!(
+            coverage.to_string().lines().next(),
+            Some("DFLT/mark: @BOTTOM has no anchor on 1 base: b")
+        );
+
+        // unreachable bases are not reported
+        let coverage = MarkCoverage::new(&compilation, &glyph_map, |gid| gid != c);
+        assert_eq!(coverage.missing.len(), 2);
+        assert!(coverage.missing.iter().all(|item| item.bases == [b]));
+    }
+
+    #[test]
+    fn explain_rule() {
+        let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+lookup SINGLE { sub a by b; } SINGLE;
+feature liga {
+    lookupflag IgnoreMarks;
+    sub f i by f_i;
+    sub a' lookup SINGLE c;
+} liga;
+include(kern.fea);
+";
+        let kern = "feature kern { pos a b -10; } kern;";
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let resolver =
+            move |path: &std::ffi::OsStr| Ok(if path == "kern.fea" { kern } else { fea }.into());
+        let (tree, errs) =
+            crate::parse::parse_root("test.fea".into(), Some(&glyph_map), resolver).unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+        let compilation = crate::compile(&tree, &glyph_map, Default::default()).unwrap();
+        let explain = |needle: &str| {
+            Explanation::new(&tree, &compilation, fea.find(needle).unwrap() + 1).unwrap()
+        };
+
+        let liga = explain("sub f i").unwrap();
+        assert_eq!(liga.statement, "sub f i by f_i;");
+        let [lookup] = liga.lookups.as_slice() else {
+            panic!("expected one lookup");
+        };
+        assert_eq!(lookup.index, 1);
+        assert_eq!(lookup.info.kind, LookupKind::GsubLigature);
+        assert_eq!(
+            lookup.features,
+            [
+                FeatureKey::new(Tag::new(b"liga"), tags::SCRIPT_DFLT, tags::LANG_DFLT),
+                FeatureKey::new(Tag::new(b"liga"), Tag::new(b"latn"), tags::LANG_DFLT),
+            ]
+        );
+        let text = liga.to_string();
+        assert!(
+            text.starts_with("sub f i by f_i;\nGSUB lookup 1: ligature substitution\n"),
+            "{text}"
+        );
+        assert!(text.contains("    lookupflag IgnoreMarks\n"), "{text}");
+        assert!(text.contains("    liga latn/dflt\n"), "{text}");
+
+        // a named lookup is only registered through the rules that use it
+        let single = explain("sub a by b").unwrap();
+        assert_eq!(single.lookups[0].index, 0);
+        assert!(single.lookups[0].features.is_empty());
+        assert!(single.to_string().contains("lookupflag 0\n"));
+
+        // offsets are in the root file, and the include statement is not a rule
+        assert!(explain("include").is_none());
+        assert!(explain("languagesystem").is_none());
+    }
+}
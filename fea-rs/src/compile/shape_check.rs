@@ -0,0 +1,499 @@
+//! Checking compiled tables by shaping test strings.
+//!
+//! This is only available with the `shape-check` feature. After compilation,
+//! the generated tables are shaped with [rustybuzz] and the output is compared
+//! against an expected glyph sequence.
+//!
+//! Tests are written in terms of glyph names, not text: the font we build has
+//! no real `cmap`, so each glyph is given a private-use codepoint instead. This
+//! means that shapers that depend on character properties (such as arabic
+//! joining) will not behave as they would with real text.
+//!
+//! Tests can also be written in a simple line-based format, one per line:
+//!
+//! ```text
+//! # blank lines and comments are ignored
+//! f i -> f_i
+//! f i -> f i; features=-liga
+//! a -> a.sc; features=+smcp; script=latn; language=TRK
+//! ```
+//!
+//! [rustybuzz]: https://docs.rs/rustybuzz
+
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use rustybuzz::{Face, Feature, Language, Script, UnicodeBuffer};
+use write_fonts::{
+    dump_table,
+    tables::{head::Head, hhea::Hhea},
+    types::{Fixed, GlyphId, LongDateTime, Tag},
+    FontBuilder,
+};
+
+use super::{
+    error::{ShapeCheckError, ShapeTestParseError},
+    Compilation, Opts,
+};
+use crate::{GlyphIdent, GlyphMap, GlyphName};
+
+/// The first codepoint assigned to a glyph; gid `n` is mapped to `PUA_START + n`.
+const PUA_START: u32 = 0xF0000;
+
+/// A single shaping test case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShapeTest {
+    label: Option<String>,
+    input: Vec<GlyphName>,
+    expected: Vec<GlyphName>,
+    features: Vec<String>,
+    script: Option<Tag>,
+    language: Option<Tag>,
+}
+
+/// A test whose shaped output did not match its expectation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShapeMismatch {
+    /// A description of the test, such as 'line 4'.
+    pub test: String,
+    /// The input glyphs
+    pub input: Vec<GlyphName>,
+    /// The glyphs we expected to see
+    pub expected: Vec<GlyphName>,
+    /// The glyphs the shaper actually produced
+    pub actual: Vec<GlyphName>,
+}
+
+impl ShapeTest {
+    /// Create a new test that expects `input` to be shaped as `expected`.
+    pub fn new(
+        input: impl IntoIterator<Item = impl Into<GlyphName>>,
+        expected: impl IntoIterator<Item = impl Into<GlyphName>>,
+    ) -> Self {
+        ShapeTest {
+            label: None,
+            input: input.into_iter().map(Into::into).collect(),
+            expected: expected.into_iter().map(Into::into).collect(),
+            features: Vec::new(),
+            script: None,
+            language: None,
+        }
+    }
+
+    /// Set a label used to identify this test when reporting failures.
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Add a feature setting, in the format understood by harfbuzz
+    /// (e.g. `-liga`, `+smcp`, or `salt=2`).
+    pub fn with_feature(mut self, feature: impl Into<String>) -> Self {
+        self.features.push(feature.into());
+        self
+    }
+
+    /// Set the OpenType script tag to shape with.
+    ///
+    /// If this is not set, the script is left for the shaper to guess.
+    pub fn with_script(mut self, script: Tag) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    /// Set the OpenType language system tag to shape with.
+    pub fn with_language(mut self, language: Tag) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    fn describe(&self, index: usize) -> String {
+        self.label
+            .clone()
+            .unwrap_or_else(|| format!("test {index}"))
+    }
+
+    fn shape(
+        &self,
+        index: usize,
+        face: &Face,
+        glyph_map: &GlyphMap,
+        reverse_map: &BTreeMap<GlyphId, GlyphIdent>,
+    ) -> Result<Vec<GlyphName>, ShapeCheckError> {
+        let resolve = |name: &GlyphName| {
+            resolve_glyph(glyph_map, name).ok_or_else(|| ShapeCheckError::UnknownGlyph {
+                test: self.describe(index),
+                name: name.clone(),
+            })
+        };
+        // we resolve the expected glyphs only to report typos early
+        for name in &self.expected {
+            resolve(name)?;
+        }
+        let text = self
+            .input
+            .iter()
+            .map(|name| resolve(name).map(encode_glyph))
+            .collect::<Result<String, _>>()?;
+        let features = self
+            .features
+            .iter()
+            .map(|feature| {
+                Feature::from_str(feature).map_err(|_| ShapeCheckError::InvalidFeature {
+                    test: self.describe(index),
+                    feature: feature.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(&text);
+        if let Some(script) = self.script.and_then(shaping_script) {
+            buffer.set_script(script);
+        }
+        if let Some(language) = private_use_language(self.script, self.language) {
+            buffer.set_language(language);
+        }
+        buffer.guess_segment_properties();
+
+        let output = rustybuzz::shape(face, &features, buffer);
+        Ok(output
+            .glyph_infos()
+            .iter()
+            .map(|info| glyph_name(reverse_map, GlyphId::new(info.glyph_id as u16)))
+            .collect())
+    }
+}
+
+impl FromStr for ShapeTest {
+    type Err = ShapeTestParseError;
+
+    /// Parse a single test, reporting errors on line 1.
+    ///
+    /// See [`parse_shape_tests`] for the format.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_test(s, 1)
+    }
+}
+
+impl Display for ShapeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: '{}' expected '{}', found '{}'",
+            self.test,
+            self.input.join(" "),
+            self.expected.join(" "),
+            self.actual.join(" ")
+        )
+    }
+}
+
+/// Parse a list of shaping tests, one per line.
+///
+/// Each test is a sequence of input glyph names, followed by `->`, followed
+/// by the expected glyph names. The test can then be followed by
+/// semicolon-separated options: `features=` (a comma-separated list of
+/// harfbuzz-style feature settings), `script=` and `language=` (OpenType tags).
+///
+/// Blank lines and lines starting with `#` are ignored. Each test is labeled
+/// with its line number.
+pub fn parse_shape_tests(text: &str) -> Result<Vec<ShapeTest>, ShapeTestParseError> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_no, line)| parse_test(line, line_no))
+        .collect()
+}
+
+fn parse_test(line: &str, line_no: usize) -> Result<ShapeTest, ShapeTestParseError> {
+    let mut parts = line.split(';');
+    let (input, expected) = parts
+        .next()
+        .and_then(|rule| rule.split_once("->"))
+        .ok_or(ShapeTestParseError::MissingArrow { line: line_no })?;
+    let mut test = ShapeTest::new(input.split_whitespace(), expected.split_whitespace())
+        .with_label(format!("line {line_no}"));
+
+    let parse_tag = |tag: &str| {
+        Tag::new_checked(tag.as_bytes()).map_err(|_| ShapeTestParseError::InvalidTag {
+            line: line_no,
+            tag: tag.to_owned(),
+        })
+    };
+
+    for option in parts.map(str::trim).filter(|opt| !opt.is_empty()) {
+        match option.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("features", features)) => test.features.extend(
+                features
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|f| !f.is_empty())
+                    .map(String::from),
+            ),
+            Some(("script", script)) => test.script = Some(parse_tag(script)?),
+            Some(("language", language)) => test.language = Some(parse_tag(language)?),
+            _ => {
+                return Err(ShapeTestParseError::UnknownOption {
+                    line: line_no,
+                    option: option.to_owned(),
+                })
+            }
+        }
+    }
+    Ok(test)
+}
+
+/// Shape each of the provided tests with the compiled tables.
+///
+/// Returns the tests whose output did not match their expectations. An error
+/// is returned if a test cannot be run, for instance because it refers to a
+/// glyph that is not in the glyph map.
+pub fn check_shaping(
+    compilation: &Compilation,
+    glyph_map: &GlyphMap,
+    tests: &[ShapeTest],
+) -> Result<Vec<ShapeMismatch>, ShapeCheckError> {
+    let mut builder = compilation.assemble(glyph_map, Opts::new())?;
+    add_shaping_tables(&mut builder, glyph_map.len());
+    let font_data = builder.build();
+    let face = Face::from_slice(&font_data, 0).expect("we just built this font");
+    let reverse_map = glyph_map.reverse_map();
+
+    let mut mismatches = Vec::new();
+    for (i, test) in tests.iter().enumerate() {
+        let actual = test.shape(i, &face, glyph_map, &reverse_map)?;
+        if actual != test.expected {
+            mismatches.push(ShapeMismatch {
+                test: test.describe(i),
+                input: test.input.clone(),
+                expected: test.expected.clone(),
+                actual,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Add the tables (beyond the layout tables) that are required by the shaper.
+fn add_shaping_tables(builder: &mut FontBuilder, num_glyphs: usize) {
+    let head = Head::new(
+        Fixed::from_i32(1),
+        0,
+        0,
+        1000,
+        LongDateTime::new(0),
+        LongDateTime::new(0),
+        0,
+        0,
+        0,
+        0,
+        0,
+        3,
+        0,
+    );
+    builder.add_table(Tag::new(b"head"), dump_table(&head).unwrap());
+    builder.add_table(Tag::new(b"hhea"), dump_table(&Hhea::default()).unwrap());
+    builder.add_table(Tag::new(b"cmap"), private_use_cmap(num_glyphs));
+}
+
+/// A cmap that maps every glyph to a codepoint in the supplementary private use area.
+///
+/// This is a single format 12 subtable with a single group.
+fn private_use_cmap(num_glyphs: usize) -> Vec<u8> {
+    const HEADER_LEN: u32 = 4 + 8;
+    const SUBTABLE_LEN: u32 = 16 + 12;
+    let last_gid = num_glyphs.saturating_sub(1) as u32;
+
+    let mut data = Vec::with_capacity((HEADER_LEN + SUBTABLE_LEN) as usize);
+    // version, numTables
+    data.extend(0u16.to_be_bytes());
+    data.extend(1u16.to_be_bytes());
+    // windows platform, full unicode repertoire
+    data.extend(3u16.to_be_bytes());
+    data.extend(10u16.to_be_bytes());
+    data.extend(HEADER_LEN.to_be_bytes());
+    // format, reserved, length, language, numGroups
+    data.extend(12u16.to_be_bytes());
+    data.extend(0u16.to_be_bytes());
+    data.extend(SUBTABLE_LEN.to_be_bytes());
+    data.extend(0u32.to_be_bytes());
+    data.extend(1u32.to_be_bytes());
+    // startCharCode, endCharCode, startGlyphID
+    data.extend(PUA_START.to_be_bytes());
+    data.extend((PUA_START + last_gid).to_be_bytes());
+    data.extend(0u32.to_be_bytes());
+    data
+}
+
+fn encode_glyph(gid: GlyphId) -> char {
+    char::from_u32(PUA_START + gid.to_u16() as u32).unwrap()
+}
+
+/// Resolve a glyph name, also accepting names of the form 'cid01234'.
+fn resolve_glyph(glyph_map: &GlyphMap, name: &GlyphName) -> Option<GlyphId> {
+    glyph_map.get(name).or_else(|| {
+        name.strip_prefix("cid")
+            .and_then(|cid| cid.parse::<u16>().ok())
+            .and_then(|cid| glyph_map.get(&cid))
+    })
+}
+
+fn glyph_name(reverse_map: &BTreeMap<GlyphId, GlyphIdent>, gid: GlyphId) -> GlyphName {
+    match reverse_map.get(&gid) {
+        Some(GlyphIdent::Name(name)) => name.clone(),
+        Some(GlyphIdent::Cid(cid)) => format!("cid{cid:05}").into(),
+        None => format!("glyph{:05}", gid.to_u16()).into(),
+    }
+}
+
+/// Convert an OpenType script tag into the script used to select a shaper.
+fn shaping_script(tag: Tag) -> Option<Script> {
+    let mut bytes = tag.to_be_bytes();
+    if &bytes == b"DFLT" {
+        return None;
+    }
+    // the 'new' indic tags ('dev2' etc) map to the same script as the old ones
+    if let Some(old) = match &bytes {
+        b"bng2" => Some(b"beng"),
+        b"dev2" => Some(b"deva"),
+        b"gjr2" => Some(b"gujr"),
+        b"gur2" => Some(b"guru"),
+        b"knd2" => Some(b"knda"),
+        b"mlm2" => Some(b"mlym"),
+        b"ory2" => Some(b"orya"),
+        b"tml2" => Some(b"taml"),
+        b"tel2" => Some(b"telu"),
+        b"mym2" => Some(b"mymr"),
+        _ => None,
+    } {
+        bytes = *old;
+    }
+    // short tags are padded with spaces in OpenType, and by repeating the
+    // last letter in ISO 15924 ('nko ' vs 'Nkoo')
+    let len = bytes.iter().position(|b| *b == b' ').unwrap_or(4);
+    if len == 0 {
+        return None;
+    }
+    for i in len..4 {
+        bytes[i] = bytes[len - 1];
+    }
+    Script::from_iso15924_tag(rustybuzz::ttf_parser::Tag::from_bytes(&bytes))
+}
+
+/// Build a BCP 47 private-use language tag that tells harfbuzz which
+/// OpenType script and language tags to use directly.
+fn private_use_language(script: Option<Tag>, language: Option<Tag>) -> Option<Language> {
+    let mut lang = String::from("x");
+    if let Some(script) = script {
+        lang.push_str(&format!("-hbsc{}", script.to_string().trim_end()));
+    }
+    if let Some(language) = language {
+        lang.push_str(&format!("-hbot{}", language.to_string().trim_end()));
+    }
+    if lang.len() == 1 {
+        return None;
+    }
+    Language::from_str(&lang).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::identity;
+
+    use super::*;
+    use crate::util::ttx as test_utils;
+
+    fn glyph_map() -> GlyphMap {
+        [
+            ".notdef", "a", "b", "f", "i", "f_i", "a.sc", "b.sc", "a.alt",
+        ]
+        .iter()
+        .copied()
+        .map(GlyphName::new)
+        .collect()
+    }
+
+    fn run_tests(fea: &str, tests: &str) -> Vec<ShapeMismatch> {
+        let glyph_map = glyph_map();
+        let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+        let tests = parse_shape_tests(tests).unwrap();
+        check_shaping(&compilation, &glyph_map, &tests).unwrap()
+    }
+
+    static FEA: &str = "
+        languagesystem DFLT dflt;
+        languagesystem latn dflt;
+        languagesystem latn TRK;
+        feature liga { sub f i by f_i; } liga;
+        feature smcp { sub [a b] by [a.sc b.sc]; } smcp;
+        feature locl { script latn; language TRK; sub a by a.alt; } locl;
+        ";
+
+    #[test]
+    fn passing_tests() {
+        let mismatches = run_tests(
+            FEA,
+            "
+            # default features are applied
+            f i -> f_i
+            f i a -> f i a; features=-liga
+            a b -> a.sc b.sc; features=+smcp
+            a -> a.alt; script=latn; language=TRK
+            a -> a; script=latn
+            ",
+        );
+        assert!(mismatches.is_empty(), "{mismatches:?}");
+    }
+
+    #[test]
+    fn report_mismatch() {
+        let mismatches = run_tests(FEA, "f i -> f_i\n\na b -> a.sc b\n");
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(
+            mismatches[0].to_string(),
+            "line 3: 'a b' expected 'a.sc b', found 'a b'"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(
+            "a b".parse::<ShapeTest>(),
+            Err(ShapeTestParseError::MissingArrow { line: 1 })
+        ));
+        assert!(matches!(
+            parse_shape_tests("a -> b\na -> b; colour=red"),
+            Err(ShapeTestParseError::UnknownOption { line: 2, .. })
+        ));
+        assert_eq!(
+            "f i -> f_i; features=-liga, +kern; script=latn"
+                .parse::<ShapeTest>()
+                .unwrap(),
+            ShapeTest::new(["f", "i"], ["f_i"])
+                .with_label("line 1")
+                .with_feature("-liga")
+                .with_feature("+kern")
+                .with_script(Tag::new(b"latn"))
+        );
+    }
+
+    #[test]
+    fn unknown_glyph() {
+        let glyph_map = glyph_map();
+        let compilation = test_utils::compile_str(FEA, &glyph_map, identity).unwrap();
+        let test = ShapeTest::new(["f", "j"], ["f_j"]);
+        let err = check_shaping(&compilation, &glyph_map, &[test]).unwrap_err();
+        assert_eq!(err.to_string(), "test 0: unknown glyph 'f_j'");
+    }
+
+    #[test]
+    fn script_tags() {
+        assert_eq!(
+            shaping_script(Tag::new(b"dev2")),
+            shaping_script(Tag::new(b"deva"))
+        );
+        assert!(shaping_script(Tag::new(b"nko ")).is_some());
+        assert!(shaping_script(Tag::new(b"DFLT")).is_none());
+    }
+}
@@ -0,0 +1,137 @@
+//! A read-only view of the features and lookups in a compilation
+
+use write_fonts::{tables::layout::LookupFlag, types::Tag};
+
+use crate::parse::Location;
+
+use super::FeatureKey;
+
+/// A feature registered for a particular script and language.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeatureInfo {
+    /// The feature tag.
+    pub feature: Tag,
+    /// The script tag.
+    pub script: Tag,
+    /// The language tag.
+    pub language: Tag,
+    /// `true` if this is the required feature for this language system.
+    pub required: bool,
+    /// Indices of this feature's lookups in the GSUB lookup list.
+    pub gsub_lookups: Vec<u16>,
+    /// Indices of this feature's lookups in the GPOS lookup list.
+    pub gpos_lookups: Vec<u16>,
+}
+
+impl FeatureInfo {
+    /// The feature, script and language of this feature.
+    pub fn key(&self) -> FeatureKey {
+        FeatureKey::new(self.feature, self.script, self.language)
+    }
+}
+
+/// The type of a lookup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum LookupKind {
+    GsubSingle,
+    GsubMultiple,
+    GsubAlternate,
+    GsubLigature,
+    GsubContextual,
+    GsubChainContextual,
+    GsubReverseChain,
+    GposSingle,
+    GposPair,
+    GposCursive,
+    GposMarkToBase,
+    GposMarkToLig,
+    GposMarkToMark,
+    GposContextual,
+    GposChainContextual,
+}
+
+/// A summary of a single lookup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LookupInfo {
+    /// The type of this lookup.
+    pub kind: LookupKind,
+    /// The lookup flags.
+    pub flags: LookupFlag,
+    /// The mark filtering set, if any.
+    pub mark_filter_set: Option<u16>,
+    /// The number of subtables this lookup will have when written.
+    pub subtable_count: usize,
+    /// The number of rules in this lookup.
+    ///
+    /// What counts as a rule depends on the kind of lookup:
+    ///
+    /// - single, multiple, alternate and cursive lookups count covered glyphs
+    /// - ligature lookups count ligatures
+    /// - pair lookups count glyph pairs and class pairs
+    /// - mark attachment lookups count base glyphs, ligatures, or base marks
+    /// - contextual lookups count rules
+    pub rule_count: usize,
+    /// The statements that added rules to this lookup, in source order.
+    ///
+    /// The statement that starts a contextual lookup is also the source of
+    /// any anonymous lookups created for its inline rules. Lookups that are
+    /// not generated from rules, such as those of the `aalt` feature, have no
+    /// sources.
+    pub sources: Vec<Location>,
+}
+
+impl LookupKind {
+    /// The OpenType lookup type, as written in the lookup table.
+    pub fn lookup_type(self) -> u16 {
+        match self {
+            LookupKind::GsubSingle | LookupKind::GposSingle => 1,
+            LookupKind::GsubMultiple | LookupKind::GposPair => 2,
+            LookupKind::GsubAlternate | LookupKind::GposCursive => 3,
+            LookupKind::GsubLigature | LookupKind::GposMarkToBase => 4,
+            LookupKind::GsubContextual | LookupKind::GposMarkToLig => 5,
+            LookupKind::GsubChainContextual | LookupKind::GposMarkToMark => 6,
+            LookupKind::GposContextual => 7,
+            LookupKind::GposChainContextual => 8,
+            LookupKind::GsubReverseChain => 8,
+        }
+    }
+
+    /// `true` if this lookup belongs in the GPOS table.
+    pub fn is_gpos(self) -> bool {
+        matches!(
+            self,
+            LookupKind::GposSingle
+                | LookupKind::GposPair
+                | LookupKind::GposCursive
+                | LookupKind::GposMarkToBase
+                | LookupKind::GposMarkToLig
+                | LookupKind::GposMarkToMark
+                | LookupKind::GposContextual
+                | LookupKind::GposChainContextual
+        )
+    }
+}
+
+impl std::fmt::Display for LookupKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LookupKind::GsubSingle => "single substitution",
+            LookupKind::GsubMultiple => "multiple substitution",
+            LookupKind::GsubAlternate => "alternate substitution",
+            LookupKind::GsubLigature => "ligature substitution",
+            LookupKind::GsubContextual => "contextual substitution",
+            LookupKind::GsubChainContextual => "chained contextual substitution",
+            LookupKind::GsubReverseChain => "reverse chained contextual substitution",
+            LookupKind::GposSingle => "single positioning",
+            LookupKind::GposPair => "pair positioning",
+            LookupKind::GposCursive => "cursive attachment",
+            LookupKind::GposMarkToBase => "mark-to-base attachment",
+            LookupKind::GposMarkToLig => "mark-to-ligature attachment",
+            LookupKind::GposMarkToMark => "mark-to-mark attachment",
+            LookupKind::GposContextual => "contextual positioning",
+            LookupKind::GposChainContextual => "chained contextual positioning",
+        };
+        f.write_str(name)
+    }
+}
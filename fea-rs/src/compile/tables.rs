@@ -15,15 +15,13 @@ use write_fonts::{
             MarkGlyphSets,
         },
         layout::{ClassDef, ClassDefBuilder, CoverageTableBuilder},
+        variations::ItemVariationStore,
     },
     types::{Fixed, LongDateTime, NameId, Tag, Uint24},
     validate::ValidationReport,
 };
 
-use crate::{
-    common::{GlyphClass, GlyphId},
-    compile::tags::{MAC_PLATFORM_ID, WIN_PLATFORM_ID},
-};
+use crate::common::{GlyphClass, GlyphId};
 
 /// The explicit tables allowed in a fea file
 #[derive(Clone, Debug, Default)]
@@ -33,12 +31,14 @@ pub(crate) struct Tables {
     pub vhea: Option<tables::vhea::Vhea>,
     pub vmtx: Option<VmtxBuilder>,
     pub name: NameBuilder,
-    pub stylistic_sets: HashMap<Tag, Vec<NameSpec>>,
-    pub character_variants: HashMap<Tag, CvParams>,
+    pub stylistic_sets: BTreeMap<Tag, Vec<NameSpec>>,
+    pub character_variants: BTreeMap<Tag, CvParams>,
     pub gdef: Option<GdefBuilder>,
     pub base: Option<Base>,
     pub os2: Option<Os2Builder>,
     pub stat: Option<StatBuilder>,
+    // tables produced by the handlers of anonymous blocks
+    pub custom: Vec<(Tag, Vec<u8>)>,
 }
 #[derive(Clone, Debug, Default)]
 #[allow(non_camel_case_types)]
@@ -66,6 +66,7 @@ pub struct NameSpec {
     pub platform_id: u16,
     pub encoding_id: u16,
     pub language_id: u16,
+    /// The string, with any escape sequences decoded
     pub string: SmolStr,
 }
 
@@ -96,6 +97,7 @@ pub struct GdefBuilder {
     pub ligature_pos: BTreeMap<GlyphId, Vec<CaretValue>>,
     pub mark_attach_class: BTreeMap<GlyphId, u16>,
     pub mark_glyph_sets: Vec<GlyphClass>,
+    pub var_store: Option<ItemVariationStore>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -384,13 +386,12 @@ impl NameSpec {
 
     //TODO: rename me to build
     pub fn to_otf(&self, name_id: NameId) -> write_fonts::tables::name::NameRecord {
-        let string = parse_string(self.platform_id, self.string.trim_matches('"'));
         write_fonts::tables::name::NameRecord::new(
             self.platform_id,
             self.encoding_id,
             self.language_id,
             name_id,
-            string.into(),
+            self.string.to_string().into(),
         )
     }
 }
@@ -428,56 +429,6 @@ impl CvParams {
     }
 }
 
-fn parse_string(platform: u16, s: &str) -> String {
-    debug_assert!(platform == WIN_PLATFORM_ID || platform == MAC_PLATFORM_ID);
-    if !s.as_bytes().contains(&b'\\') {
-        return s.to_string();
-    }
-
-    if platform == WIN_PLATFORM_ID {
-        parse_win(s)
-    } else {
-        parse_mac(s)
-    }
-}
-
-fn parse_win(s: &str) -> String {
-    let mut out_u16 = Vec::with_capacity(s.len());
-    let mut work = s;
-    while !work.is_empty() {
-        let pos = work.bytes().position(|b| b == b'\\');
-        if let Some(pos) = pos {
-            out_u16.extend(work[..pos].encode_utf16());
-            let code = &work[pos + 1..pos + 5];
-            let num = u16::from_str_radix(code, 16).unwrap();
-            out_u16.push(num);
-            work = &work[pos + 5..];
-        } else {
-            out_u16.extend(work.encode_utf16());
-        }
-    }
-    String::from_utf16(&out_u16).unwrap()
-}
-
-fn parse_mac(s: &str) -> String {
-    let mut out = String::with_capacity(s.len());
-    let mut work = s;
-    while !work.is_empty() {
-        let pos = work.bytes().position(|b| b == b'\\');
-        if let Some(pos) = pos {
-            out.push_str(&work[..pos]);
-            let code = &work[pos + 1..pos + 3];
-            let num = u8::from_str_radix(code, 16).unwrap();
-            out.push(mac_roman_to_char(num));
-            work = &work[pos + 3..];
-        } else {
-            out.push_str(work);
-            break;
-        }
-    }
-    out
-}
-
 // this is the value used in python fonttools when writing this table
 const DATE_2011_12_13_H11_M22_S33: LongDateTime = LongDateTime::new(1323780153);
 
@@ -580,6 +531,7 @@ impl GdefBuilder {
         );
 
         table.mark_glyph_sets_def = self.build_mark_glyph_sets().into();
+        table.item_var_store = self.var_store.clone().into();
         dump_table(&table)
     }
 
@@ -618,7 +570,7 @@ impl GdefBuilder {
             MarkGlyphSets::new(
                 self.mark_glyph_sets
                     .iter()
-                    .map(|cls| cls.iter().collect::<CoverageTableBuilder>().build())
+                    .map(GlyphClass::to_coverage)
                     .collect(),
             )
         })
@@ -656,6 +608,7 @@ impl GdefBuilder {
             && self.ligature_pos.is_empty()
             && self.mark_attach_class.is_empty()
             && self.mark_glyph_sets.is_empty()
+            && self.var_store.is_none()
     }
 }
 
@@ -703,56 +656,3 @@ static CODEPAGE_TO_BIT: &[(u16, u8)] = &[
     (1258, 8),
     (1361, 21),
 ];
-
-fn mac_roman_to_char(inp: u8) -> char {
-    if inp < 0x80 {
-        inp as char
-    } else {
-        MAC_ROMAN_LOOKUP[inp as usize - 0x80]
-    }
-}
-
-#[rustfmt::skip]
-/// char equivalents of macroman values 0x80 - 0xFF
-static MAC_ROMAN_LOOKUP: &[char] = &[
-    'Ä', 'Å', 'Ç', 'É', 'Ñ', 'Ö', 'Ü', 'á',
-    'à', 'â', 'ä', 'ã', 'å', 'ç', 'é', 'è',
-    'ê', 'ë', 'í', 'ì', 'î', 'ï', 'ñ', 'ó',
-    'ò', 'ô', 'ö', 'õ', 'ú', 'ù', 'û', 'ü',
-    '†', '°', '¢', '£', '§', '•', '¶', 'ß',
-    '®', '©', '™', '´', '¨', '≠', 'Æ', 'Ø',
-    '∞', '±', '≤', '≥', '¥', 'µ', '∂', '∑',
-    '∏', 'π', '∫', 'ª', 'º', 'Ω', 'æ', 'ø',
-    '¿', '¡', '¬', '√', 'ƒ', '≈', '∆', '«',
-    '»', '…', '\u{ca}', //nbsp
-    'À', 'Ã', 'Õ', 'Œ', 'œ',
-    '–', '—', '“', '”', '‘', '’', '÷', '◊',
-    'ÿ', 'Ÿ', '⁄', '€', '‹', '›', 'ﬁ', 'ﬂ',
-    '‡', '·', '‚', '„', '‰', 'Â', 'Ê', 'Á',
-    'Ë', 'È', 'Í', 'Î', 'Ï', 'Ì', 'Ó', 'Ô',
-    '\u{f8ff}', //
-    'Ò', 'Ú', 'Û', 'Ù', 'ı', 'ˆ', '˜',
-    '¯', '˘', '˙', '˚', '¸', '˝', '˛', 'ˇ',
-];
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn smoke_test_conversion() {
-        assert_eq!(MAC_ROMAN_LOOKUP.len(), 128);
-        assert_eq!(mac_roman_to_char(0x20), ' ');
-        assert_eq!(mac_roman_to_char(0x7E), '~');
-        assert_eq!(mac_roman_to_char(0x7F), 0x7f as char);
-        assert_eq!(mac_roman_to_char(0x80), 'Ä');
-        assert_eq!(mac_roman_to_char(0xFF), 'ˇ');
-        assert_eq!(mac_roman_to_char(0x8e), 'é');
-    }
-
-    #[test]
-    fn parse_mac_str() {
-        let inp = "M\\9fller";
-        assert_eq!(parse_mac(inp), "Müller");
-    }
-}
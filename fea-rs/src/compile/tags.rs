@@ -5,6 +5,9 @@ use std::ops::RangeInclusive;
 use write_fonts::types::Tag;
 
 pub const AALT: Tag = Tag::new(b"aalt");
+pub const KERN: Tag = Tag::new(b"kern");
+pub const MARK: Tag = Tag::new(b"mark");
+pub const MKMK: Tag = Tag::new(b"mkmk");
 pub const SIZE: Tag = Tag::new(b"size");
 pub const LANG_DFLT: Tag = Tag::new(b"dflt");
 pub const SCRIPT_DFLT: Tag = Tag::new(b"DFLT");
@@ -14,6 +17,29 @@ pub const GPOS: Tag = Tag::new(b"GPOS");
 pub const WIN_PLATFORM_ID: u16 = 3;
 pub const MAC_PLATFORM_ID: u16 = 1;
 
+/// `true` if this is a positioning feature used only in vertical layout.
+///
+/// In these features a value record with a single number sets the y advance.
+pub fn is_vertical_positioning(tag: Tag) -> bool {
+    [b"valt", b"vhal", b"vkrn", b"vpal"].contains(&&tag.into_bytes())
+}
+
+/// `true` if this is the tag of a script that is written right-to-left.
+pub fn is_rtl_script(tag: Tag) -> bool {
+    [
+        b"adlm", b"arab", b"armi", b"avst", b"cprt", b"hatr", b"hebr", b"khar", b"lydi", b"mand",
+        b"mani", b"mend", b"merc", b"mero", b"narb", b"nbat", b"nko ", b"orkh", b"palm", b"phli",
+        b"phlp", b"phnx", b"prti", b"rohg", b"samr", b"sarb", b"sogd", b"sogo", b"syrc", b"thaa",
+        b"yezi",
+    ]
+    .contains(&&tag.into_bytes())
+}
+
+/// `true` if this is a substitution feature used only in vertical layout.
+pub fn is_vertical_substitution(tag: Tag) -> bool {
+    [b"vert", b"vrt2"].contains(&&tag.into_bytes())
+}
+
 /// `true` if this tag is ss01-ss20
 pub fn is_stylistic_set(tag: Tag) -> bool {
     is_numbered_tag(tag, b"ss", 1..=20)
@@ -0,0 +1,136 @@
+//! Transforming the parse tree before it is validated.
+//!
+//! This lets a caller do their own preprocessing of a source, such as
+//! expanding a marker comment into generated rules, by editing the tree
+//! directly.
+
+use std::{ops::Range, sync::Arc};
+
+use crate::{
+    parse::{FileId, SourceMap},
+    Diagnostic, Level, Node, ParseTree,
+};
+
+use super::error::{CompilerError, DiagnosticSet};
+
+/// A transformation of the parse tree, run before validation.
+///
+/// Transforms are registered with
+/// [`Compiler::with_transform`][crate::Compiler::with_transform], and run in
+/// the order they were registered. Each transform inspects the tree and returns
+/// a set of replacements; these are applied to produce a new tree, which is
+/// passed to the next transform, or validated and compiled.
+///
+/// Each replacement is the range of a node or token in the tree, as returned by
+/// [`Node::range`] or [`Node::iter_tokens_with_ranges`], along with a node
+/// whose children are inserted in its place. Replacement nodes can be built
+/// from parts of the existing tree, or parsed from a snippet of FEA with
+/// [`parse_string`][crate::parse_string]. Since they are not parsed in
+/// context, `include` statements in replacement nodes are not resolved.
+///
+/// The text of the inserted nodes is added to the tree's sources, so that
+/// diagnostics about generated code can be reported like any other.
+///
+/// This trait is implemented for closures with the signature of [`transform`],
+/// so in most cases you will not need to implement it yourself.
+///
+/// [`transform`]: TreeTransform::transform
+pub trait TreeTransform {
+    /// Return the replacements to make in this tree.
+    ///
+    /// On failure, the returned diagnostic is reported as an error; its
+    /// location can be found with [`ParseTree::location`].
+    fn transform(&self, tree: &ParseTree) -> Result<Vec<(Range<usize>, Node)>, Diagnostic>;
+
+    // a little helper used in our debug impl
+    #[doc(hidden)]
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+impl<F> TreeTransform for F
+where
+    F: Fn(&ParseTree) -> Result<Vec<(Range<usize>, Node)>, Diagnostic>,
+{
+    fn transform(&self, tree: &ParseTree) -> Result<Vec<(Range<usize>, Node)>, Diagnostic> {
+        (self)(tree)
+    }
+}
+
+impl std::fmt::Debug for dyn TreeTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.type_name().fmt(f)
+    }
+}
+
+/// Run a transform, returning the edited tree.
+///
+/// `name` is used as the path of the source holding the generated text. If the
+/// transform makes no replacements, the tree is returned unchanged.
+pub(crate) fn apply_transform(
+    transform: &dyn TreeTransform,
+    tree: ParseTree,
+    name: &str,
+) -> Result<ParseTree, CompilerError> {
+    let fail = |mut diagnostic: Diagnostic, tree: &ParseTree| {
+        diagnostic.level = Level::Error;
+        let errors = DiagnosticSet::split(vec![diagnostic], tree).unwrap_err();
+        CompilerError::TransformFail(errors)
+    };
+    let mut edits = transform
+        .transform(&tree)
+        .map_err(|diagnostic| fail(diagnostic, &tree))?;
+    if edits.is_empty() {
+        return Ok(tree);
+    }
+    edits.sort_by_key(|(range, _)| (range.start, range.end));
+    let mut prev_end = 0;
+    for (range, _) in &edits {
+        if range.start < prev_end || !is_element_range(tree.root(), range) {
+            let message = "transform must replace a single node or token, without overlapping";
+            // a range outside the tree is reported at the start of the root source
+            let (file, range) = match tree.location(range.clone()) {
+                Some(location) => (location.file, location.range),
+                None => (
+                    tree.source_map()
+                        .root_file()
+                        .unwrap_or(FileId::CURRENT_FILE),
+                    0..0,
+                ),
+            };
+            return Err(fail(Diagnostic::error(file, range, message), &tree));
+        }
+        prev_end = range.end;
+    }
+
+    let generated = edits
+        .iter()
+        .flat_map(|(_, node)| node.iter_tokens().map(|token| token.as_str()))
+        .collect::<String>();
+    let mut sources = (*tree.sources).clone();
+    let file = sources.add_generated(name, generated.into());
+    let replaced = edits
+        .iter()
+        .map(|(range, node)| (range.clone(), node.text_len()))
+        .collect::<Vec<_>>();
+    let map = SourceMap::replace_ranges(&tree.map, &replaced, file);
+    let root = tree.root().edit(edits, true);
+    Ok(ParseTree {
+        root,
+        sources: Arc::new(sources),
+        map: Arc::new(map),
+    })
+}
+
+/// `true` if `range` is exactly the range of some node or token in the tree.
+fn is_element_range(root: &Node, range: &Range<usize>) -> bool {
+    // the root itself cannot be replaced, since its children are spliced in
+    root.node_at_offset(range.start)
+        .iter()
+        .skip(1)
+        .any(|(node_range, _)| node_range == range)
+        || root
+            .token_at_offset(range.start)
+            .is_some_and(|(token_range, _)| token_range == *range)
+}
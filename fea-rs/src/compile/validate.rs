@@ -6,19 +6,22 @@
 
 use std::{
     collections::{HashMap, HashSet},
+    iter::Peekable,
     ops::Range,
     str::FromStr,
 };
 
 use smol_str::SmolStr;
-use write_fonts::{read::tables::name::Encoding, types::Tag};
+use write_fonts::{read::tables::name::Encoding, tables::layout::LookupFlag, types::Tag};
 
 use super::{
     glyph_range,
     tags::{self, WIN_PLATFORM_ID},
+    variations, MissingGlyphs,
 };
 use crate::{
-    parse::SourceMap,
+    common::GlyphId,
+    parse::{Location, SourceMap, TextEdit},
     token_tree::{
         typed::{self, AstNode},
         Token,
@@ -31,35 +34,85 @@ pub struct ValidationCtx<'a> {
     pub errors: Vec<Diagnostic>,
     glyph_map: &'a GlyphMap,
     source_map: &'a SourceMap,
+    missing_glyphs: MissingGlyphs,
+    implicit_default_lang_system: bool,
+    implicit_script_lang_systems: bool,
+    forward_class_references: bool,
+    unicode_name_cmap: Option<&'a HashMap<u32, GlyphId>>,
+    // glyph names in unicode notation that have been checked against the cmap
+    checked_unicode_names: HashSet<SmolStr>,
     default_lang_systems: HashSet<(SmolStr, SmolStr)>,
     seen_non_default_script: bool,
     lookup_defs: HashMap<SmolStr, Token>,
+    // the named lookup block being validated, if any
+    current_lookup: Option<SmolStr>,
+    // the feature block being validated, if any
+    current_feature: Option<Tag>,
+    // the lookups referenced by contextual rules in each named lookup
+    lookup_refs: HashMap<SmolStr, Vec<SmolStr>>,
+    // references to lookups that were not yet defined, and the named lookup
+    // containing each reference; these are reported once all lookups are known
+    forward_lookup_refs: Vec<(Token, Option<SmolStr>)>,
     // class and position
     glyph_class_defs: HashMap<SmolStr, Token>,
+    // the glyph class definition being validated, if any
+    current_class_def: Option<SmolStr>,
+    // the classes referenced by each glyph class definition
+    class_refs: HashMap<SmolStr, Vec<SmolStr>>,
+    // with forward class references, references to classes that were not yet
+    // defined, and the class definition containing each reference
+    forward_class_refs: Vec<(Token, Option<SmolStr>)>,
     mark_class_defs: HashSet<SmolStr>,
     mark_class_used: Option<Token>,
     anchor_defs: HashMap<SmolStr, Token>,
     value_record_defs: HashMap<SmolStr, Token>,
     aalt_referenced_features: HashMap<Tag, typed::Tag>,
     all_features: HashSet<Tag>,
+    // the number of unknown names we have looked for a suggestion for
+    suggestions: usize,
 }
 
+/// The most unknown names in a source that we suggest a similar name for.
+const MAX_SUGGESTIONS: usize = 100;
+
 impl<'a> ValidationCtx<'a> {
-    pub(crate) fn new(glyph_map: &'a GlyphMap, source_map: &'a SourceMap) -> Self {
+    pub(crate) fn new(
+        glyph_map: &'a GlyphMap,
+        source_map: &'a SourceMap,
+        missing_glyphs: MissingGlyphs,
+        implicit_default_lang_system: bool,
+        implicit_script_lang_systems: bool,
+        forward_class_references: bool,
+        unicode_name_cmap: Option<&'a HashMap<u32, GlyphId>>,
+    ) -> Self {
         ValidationCtx {
             glyph_map,
             source_map,
+            missing_glyphs,
+            implicit_default_lang_system,
+            implicit_script_lang_systems,
+            forward_class_references,
+            unicode_name_cmap,
+            checked_unicode_names: Default::default(),
             errors: Vec::new(),
             default_lang_systems: Default::default(),
             seen_non_default_script: false,
             glyph_class_defs: Default::default(),
+            current_class_def: None,
+            class_refs: Default::default(),
+            forward_class_refs: Default::default(),
             lookup_defs: Default::default(),
+            current_lookup: None,
+            current_feature: None,
+            lookup_refs: Default::default(),
+            forward_lookup_refs: Default::default(),
             mark_class_defs: Default::default(),
             mark_class_used: None,
             anchor_defs: Default::default(),
             value_record_defs: Default::default(),
             aalt_referenced_features: Default::default(),
             all_features: Default::default(),
+            suggestions: 0,
         }
     }
 
@@ -68,6 +121,64 @@ impl<'a> ValidationCtx<'a> {
         self.errors.push(Diagnostic::error(file, range, message));
     }
 
+    /// Report an unknown name, suggesting a similar known name if one exists.
+    fn undefined<'b>(
+        &mut self,
+        range: Range<usize>,
+        message: impl Into<String>,
+        name: &str,
+        candidates: impl IntoIterator<Item = &'b str>,
+    ) {
+        let (file, range) = self.source_map.resolve_range(range);
+        let diagnostic = Diagnostic::error(file, range, message);
+        self.push_with_suggestion(diagnostic, name, candidates);
+    }
+
+    /// Report a glyph that is not in the font.
+    ///
+    /// Unless missing glyphs are an error, this is a warning that describes
+    /// what the compiler will do with the glyph instead.
+    fn missing_glyph<'b>(
+        &mut self,
+        range: Range<usize>,
+        what: &str,
+        name: &str,
+        candidates: impl IntoIterator<Item = &'b str>,
+    ) {
+        let (file, range) = self.source_map.resolve_range(range);
+        let diagnostic = match self.missing_glyphs {
+            MissingGlyphs::Error => Diagnostic::error(file, range, format!("{what} not in font")),
+            MissingGlyphs::Drop => Diagnostic::warning(
+                file,
+                range,
+                format!("{what} not in font; rules that use it will be ignored"),
+            ),
+            MissingGlyphs::Placeholder => Diagnostic::warning(
+                file,
+                range,
+                format!("{what} not in font; using '.notdef' in its place"),
+            ),
+        };
+        self.push_with_suggestion(diagnostic, name, candidates);
+    }
+
+    fn push_with_suggestion<'b>(
+        &mut self,
+        mut diagnostic: Diagnostic,
+        name: &str,
+        candidates: impl IntoIterator<Item = &'b str>,
+    ) {
+        // each suggestion is a scan of every known name, so we stop looking
+        // once a source has had a lot of them
+        if self.suggestions < MAX_SUGGESTIONS {
+            self.suggestions += 1;
+            if let Some(suggestion) = closest_match(name, candidates) {
+                diagnostic = diagnostic.with_help(format!("did you mean '{suggestion}'?"));
+            }
+        }
+        self.errors.push(diagnostic);
+    }
+
     fn warning(&mut self, range: Range<usize>, message: impl Into<String>) {
         let (file, range) = self.source_map.resolve_range(range);
         self.errors.push(Diagnostic::warning(file, range, message));
@@ -91,9 +202,9 @@ impl<'a> ValidationCtx<'a> {
                 self.validate_lookup_block(&lookup, None);
             } else if let Some(_value_record_def) = typed::ValueRecordDef::cast(item) {
                 unimplemented!("valueRecordDef")
-            } else if item.kind() == Kind::AnonKw {
-                unimplemented!("anon")
             }
+            // anonymous blocks are opaque; they are passed to their handlers
+            // during compilation.
         }
         self.finalize();
     }
@@ -101,6 +212,71 @@ impl<'a> ValidationCtx<'a> {
     /// perform any analysis required after seeing all items
     fn finalize(&mut self) {
         self.finalize_aalt();
+        self.finalize_lookup_refs();
+        self.finalize_class_refs();
+    }
+
+    /// Report references to lookups that were not defined before they were
+    /// used.
+    ///
+    /// A lookup must be defined before it is referenced, which also means
+    /// that lookups cannot reference each other in a cycle; where a reference
+    /// would create a cycle, we show it.
+    fn finalize_lookup_refs(&mut self) {
+        for (label, referrer) in std::mem::take(&mut self.forward_lookup_refs) {
+            if !self.lookup_defs.contains_key(&label.text) {
+                let candidates = self.lookup_defs.keys().cloned().collect::<Vec<_>>();
+                self.undefined(
+                    label.range(),
+                    "lookup is not defined",
+                    &label.text,
+                    candidates.iter().map(SmolStr::as_str),
+                );
+            } else if let Some(cycle) = referrer
+                .as_ref()
+                .and_then(|referrer| reference_cycle(&self.lookup_refs, referrer, &label.text))
+            {
+                self.error(
+                    label.range(),
+                    format!("lookup references form a cycle: {}", cycle.join(" -> ")),
+                );
+            } else {
+                self.error(
+                    label.range(),
+                    format!("lookup '{}' is used before it is defined", label.text),
+                );
+            }
+        }
+    }
+
+    /// Report references to glyph classes that were defined after they were
+    /// used, when forward class references are allowed.
+    ///
+    /// The class must be defined somewhere, and classes cannot reference each
+    /// other in a cycle; where a reference would create a cycle, we show it.
+    fn finalize_class_refs(&mut self) {
+        for (name, referrer) in std::mem::take(&mut self.forward_class_refs) {
+            if !self.glyph_class_defs.contains_key(&name.text) {
+                let candidates = self.glyph_class_defs.keys().cloned().collect::<Vec<_>>();
+                self.undefined(
+                    name.range(),
+                    "undefined glyph class",
+                    &name.text,
+                    candidates.iter().map(SmolStr::as_str),
+                );
+            } else if let Some(cycle) = referrer
+                .as_ref()
+                .and_then(|referrer| reference_cycle(&self.class_refs, referrer, &name.text))
+            {
+                self.error(
+                    name.range(),
+                    format!(
+                        "glyph class references form a cycle: {}",
+                        cycle.join(" -> ")
+                    ),
+                );
+            }
+        }
     }
 
     fn finalize_aalt(&mut self) {
@@ -149,16 +325,56 @@ impl<'a> ValidationCtx<'a> {
         }
     }
 
-    fn validate_glyph_class_def(&mut self, node: &typed::GlyphClassDef) {
-        let name = node.class_name();
-        if let Some(_prev) = self
-            .glyph_class_defs
-            .insert(name.text().to_owned(), name.token().clone())
+    /// If requested, register 'DFLT dflt' when other language systems were
+    /// declared without it, warning at the first feature that is affected.
+    fn check_implicit_default_lang_system(&mut self, feature: &typed::Tag) {
+        let default = (SmolStr::new("DFLT"), SmolStr::new("dflt"));
+        if self.implicit_default_lang_system
+            && !self.default_lang_systems.is_empty()
+            && self.default_lang_systems.insert(default)
         {
-            self.warning(name.range(), "duplicate glyph class definition");
-            //TODO: use previous span to show previous declaration
-            //TODO: have help message
+            self.warning(
+                feature.range(),
+                "no 'languagesystem DFLT dflt' statement; registering it implicitly, so that rules are not dropped for the default script",
+            );
+        }
+    }
+
+    /// If requested, register '<script> dflt' for a script that has no
+    /// language system, warning at the first 'script' statement for it.
+    fn check_implicit_script_lang_system(&mut self, script: &typed::Tag) {
+        if !self.implicit_script_lang_systems {
+            return;
+        }
+        let dflt = SmolStr::new("dflt");
+        if self.default_lang_systems.is_empty() {
+            if script.text() == "DFLT" {
+                return;
+            }
+            // no statements is the same as 'DFLT dflt'
+            self.default_lang_systems
+                .insert((SmolStr::new("DFLT"), dflt.clone()));
         }
+        if self
+            .default_lang_systems
+            .insert((script.text().clone(), dflt))
+        {
+            self.warning(
+                script.range(),
+                format!(
+                    "no 'languagesystem {} dflt' statement; registering it implicitly, so that rules are not dropped for this script",
+                    script.text()
+                ),
+            );
+        }
+    }
+
+    fn validate_glyph_class_def(&mut self, node: &typed::GlyphClassDef) {
+        let name = node.class_name();
+        // the contents are validated first, since they cannot refer to the
+        // class being defined (although they can refer to an earlier
+        // definition of the same name)
+        self.current_class_def = Some(name.text().clone());
         if let Some(literal) = node.class_def() {
             self.validate_glyph_class_literal(&literal, false);
         } else if let Some(alias) = node.class_alias() {
@@ -166,9 +382,27 @@ impl<'a> ValidationCtx<'a> {
         } else {
             self.error(node.range(), "unknown parser bug?");
         }
+        self.current_class_def = None;
+        if let Some(_prev) = self
+            .glyph_class_defs
+            .insert(name.text().to_owned(), name.token().clone())
+        {
+            if self.forward_class_references {
+                self.error(
+                    name.range(),
+                    "glyph class is already defined; with forward class references, \
+                     each class can only be defined once",
+                );
+            } else {
+                self.warning(name.range(), "duplicate glyph class definition");
+            }
+            //TODO: use previous span to show previous declaration
+            //TODO: have help message
+        }
     }
 
     fn validate_anchor_def(&mut self, node: &typed::AnchorDef) {
+        self.validate_anchor(&node.anchor());
         if let Some(_prev) = self
             .anchor_defs
             .insert(node.name().text.clone(), node.name().clone())
@@ -217,21 +451,47 @@ impl<'a> ValidationCtx<'a> {
         }
     }
 
-    fn validate_base(&mut self, _node: &typed::BaseTable) {
+    fn validate_base(&mut self, node: &typed::BaseTable) {
         //TODO: same number of records as there are number of baseline tags
+        let lists = node
+            .horiz_base_script_record_list()
+            .into_iter()
+            .chain(node.vert_base_script_record_list());
+        for list in lists {
+            for record in list.script_records() {
+                for value in record.values() {
+                    if let Err(err) = value.try_parse_signed() {
+                        self.error(value.range(), err);
+                    }
+                }
+            }
+        }
     }
 
-    fn validate_hhea(&mut self, _node: &typed::HheaTable) {
-        // lgtm
+    fn validate_hhea(&mut self, node: &typed::HheaTable) {
+        self.validate_metrics(node.metrics());
     }
 
-    fn validate_vhea(&mut self, _node: &typed::VheaTable) {
-        // lgtm
+    fn validate_vhea(&mut self, node: &typed::VheaTable) {
+        self.validate_metrics(node.metrics());
+    }
+
+    fn validate_metrics(&mut self, metrics: impl Iterator<Item = typed::MetricRecord>) {
+        for record in metrics {
+            let metric = record.metric();
+            if let Err(err) = metric.try_parse() {
+                self.error(metric.range(), err);
+            }
+        }
     }
 
     fn validate_vmtx(&mut self, node: &typed::VmtxTable) {
         for statement in node.statements() {
             self.validate_glyph(&statement.glyph());
+            let value = statement.value();
+            if let Err(err) = value.try_parse_signed() {
+                self.error(value.range(), err);
+            }
         }
     }
 
@@ -252,7 +512,7 @@ impl<'a> ValidationCtx<'a> {
                     }
                     Kind::UnicodeRangeKw => {
                         for number in item.values() {
-                            if !(0..128).contains(&number.parse_signed()) {
+                            if !matches!(number.try_parse_signed(), Ok(0..=127)) {
                                 self.error(
                                     number.range(),
                                     "expected value in unicode character range 0..=127",
@@ -262,10 +522,10 @@ impl<'a> ValidationCtx<'a> {
                     }
                     Kind::CodePageRangeKw => {
                         for number in item.values() {
-                            if super::tables::CodePageRange::bit_for_code_page(
-                                number.parse_signed() as u16,
-                            )
-                            .is_none()
+                            if number
+                                .parse_unsigned()
+                                .and_then(super::tables::CodePageRange::bit_for_code_page)
+                                .is_none()
                             {
                                 self.error(number.range(), "not a valid code page");
                             }
@@ -292,9 +552,11 @@ impl<'a> ValidationCtx<'a> {
                 typed::Os2TableItem::Metric(i) => {
                     if matches!(i.keyword().kind, Kind::WinAscentKw | Kind::WinDescentKw) {
                         let val = i.metric();
-                        if val.parse().is_negative() {
+                        if val.try_parse().map_or(true, i16::is_negative) {
                             self.error(val.range(), "expected positive number");
                         }
+                    } else if let Err(err) = i.metric().try_parse() {
+                        self.error(i.metric().range(), err);
                     }
                 }
                 typed::Os2TableItem::Number(item) => {
@@ -317,21 +579,41 @@ impl<'a> ValidationCtx<'a> {
         let mut seen_fallback_name = false;
         for item in node.statements() {
             match item {
-                typed::StatTableItem::ElidedFallbackName(_) => {
+                typed::StatTableItem::ElidedFallbackName(name) => {
                     if seen_fallback_name {
-                        self.error(item.range(), "fallback name must only be defined once");
+                        self.error(name.range(), "fallback name must only be defined once");
                     }
                     seen_fallback_name = true;
+                    if let Some(id) = name.elided_fallback_name_id() {
+                        if let Err(err) = id.try_parse_unsigned() {
+                            self.error(id.range(), err);
+                        }
+                    }
+                }
+                typed::StatTableItem::DesignAxis(axis) => {
+                    let ordering = axis.ordering();
+                    if let Err(err) = ordering.try_parse_unsigned() {
+                        self.error(ordering.range(), err);
+                    }
                 }
                 typed::StatTableItem::AxisValue(axis) => {
                     let mut seen_location_format = None;
                     for item in axis.statements() {
                         if let typed::StatAxisValueItem::Location(loc) = item {
-                            let format = match loc.value() {
-                                typed::LocationValue::Value(_) => 'a',
-                                typed::LocationValue::MinMax { .. } => 'b',
-                                typed::LocationValue::Linked { .. } => 'c',
+                            let (format, values) = match loc.value() {
+                                typed::LocationValue::Value(value) => ('a', vec![value]),
+                                typed::LocationValue::MinMax { nominal, min, max } => {
+                                    ('b', vec![nominal, min, max])
+                                }
+                                typed::LocationValue::Linked { value, linked } => {
+                                    ('c', vec![value, linked])
+                                }
                             };
+                            for value in values {
+                                if let Err(err) = value.try_parse() {
+                                    self.error(value.range(), err);
+                                }
+                            }
                             let prev_format = seen_location_format.replace(format);
                             match (prev_format, format) {
                                 (Some('a'), 'a') => (),
@@ -342,7 +624,6 @@ impl<'a> ValidationCtx<'a> {
                         }
                     }
                 }
-                _ => (),
             }
         }
         if !seen_fallback_name {
@@ -375,7 +656,7 @@ impl<'a> ValidationCtx<'a> {
 
         let platform = platform.unwrap_or(WIN_PLATFORM_ID);
 
-        if let Err((range, err)) = validate_name_string_encoding(platform, spec.string()) {
+        if let Err((range, err)) = spec.decoded_string(platform) {
             self.error(range, err);
         }
         if let Some((platspec, language)) = spec.platform_and_language_ids() {
@@ -427,10 +708,22 @@ impl<'a> ValidationCtx<'a> {
                 //to resolve glyphs here in order to track that.
                 typed::GdefTableItem::LigatureCaret(node) => {
                     self.validate_glyph_or_class(&node.target());
-                    if let typed::LigatureCaretValue::Pos(node) = node.values() {
-                        for idx in node.values() {
-                            if idx.parse_unsigned().is_none() {
-                                self.error(idx.range(), "contourpoint index must be non-negative");
+                    match node.values() {
+                        typed::LigatureCaretValue::Pos(node) => {
+                            for pos in node.values() {
+                                if let Err(err) = pos.try_parse_signed() {
+                                    self.error(pos.range(), err);
+                                }
+                            }
+                        }
+                        typed::LigatureCaretValue::Index(node) => {
+                            for idx in node.values() {
+                                if idx.parse_unsigned().is_none() {
+                                    self.error(
+                                        idx.range(),
+                                        "contourpoint index must be non-negative",
+                                    );
+                                }
                             }
                         }
                     }
@@ -470,6 +763,7 @@ impl<'a> ValidationCtx<'a> {
         let tag = node.tag();
         let tag_raw = tag.to_raw();
         self.all_features.insert(tag_raw);
+        self.check_implicit_default_lang_system(&tag);
 
         if tag_raw == tags::SIZE {
             return self.validate_size_feature(node);
@@ -479,7 +773,7 @@ impl<'a> ValidationCtx<'a> {
             return self.validate_aalt_feature(node);
         }
 
-        let mut statement_iter = node.statements();
+        let mut statement_iter = node.statements().peekable();
 
         if tags::is_stylistic_set(tag_raw) {
             self.validate_stylistic_set_items(&mut statement_iter);
@@ -489,6 +783,10 @@ impl<'a> ValidationCtx<'a> {
             self.validate_character_variant_items(&mut statement_iter);
         }
 
+        self.validate_language_system_order(node);
+
+        self.current_feature = Some(tag_raw);
+        let mut has_params = false;
         for item in statement_iter {
             if item.kind() == Kind::ScriptNode
                 || item.kind() == Kind::LanguageNode
@@ -496,7 +794,7 @@ impl<'a> ValidationCtx<'a> {
             {
                 // lgtm
             } else if let Some(node) = typed::LookupRef::cast(item) {
-                self.validate_lookup_ref(&node);
+                self.validate_lookup_ref(&node, false);
             } else if let Some(node) = typed::LookupBlock::cast(item) {
                 self.validate_lookup_block(&node, Some(tag_raw));
             } else if let Some(node) = typed::LookupFlag::cast(item) {
@@ -509,6 +807,16 @@ impl<'a> ValidationCtx<'a> {
                 self.validate_glyph_class_def(&node);
             } else if let Some(node) = typed::MarkClassDef::cast(item) {
                 self.validate_mark_class_def(&node);
+            } else if let Some(node) = typed::Parameters::cast(item) {
+                // only compiled with a FeatureParams handler for this tag,
+                // which is checked during compilation
+                if has_params {
+                    self.error(
+                        node.range(),
+                        "feature can have only one 'parameters' statement",
+                    );
+                }
+                has_params = true;
             } else if let Some(_node) = typed::FeatureNames::cast(item) {
                 self.warning(item.range(), "Only one featureNames block is allowed, it must preceed all rules, and it is only valid in features ss01-ss20");
             } else if let Some(node) = typed::FeatureRef::cast(item) {
@@ -523,13 +831,113 @@ impl<'a> ValidationCtx<'a> {
                 );
             }
         }
+        self.current_feature = None;
+    }
+
+    /// Check for statement orderings that are legal, but that likely register
+    /// rules under a different language system than the author intended.
+    fn validate_language_system_order(&mut self, node: &typed::Feature) {
+        let script_has_defaults = |ctx: &Self, script: &SmolStr| {
+            if ctx.default_lang_systems.is_empty() {
+                script == "DFLT"
+            } else {
+                ctx.default_lang_systems
+                    .contains(&(script.clone(), SmolStr::new("dflt")))
+            }
+        };
+
+        let mut seen_script = false;
+        let mut rules_before_script = 0;
+        let mut warned_scripts = HashSet::new();
+        let mut lookupflag_is_set = false;
+        // a script statement that reset a non-zero lookupflag
+        let mut pending_flag_reset = None;
+
+        for item in node.statements() {
+            if let Some(script) = typed::Script::cast(item) {
+                seen_script = true;
+                let tag = script.tag();
+                self.check_implicit_script_lang_system(&tag);
+                if rules_before_script > 0
+                    && !script_has_defaults(self, tag.text())
+                    && warned_scripts.insert(tag.text().clone())
+                {
+                    self.warning(
+                        tag.range(),
+                        format!(
+                            "rules before the first 'script' statement are not registered for script '{}', as it has no 'languagesystem {} dflt' statement",
+                            tag.text(),
+                            tag.text()
+                        ),
+                    );
+                }
+                if lookupflag_is_set {
+                    pending_flag_reset = Some(script.range());
+                    lookupflag_is_set = false;
+                }
+            } else if let Some(language) = typed::Language::cast(item) {
+                self.check_deprecated_language_keywords(&language);
+                if !seen_script && language.tag().text() != "dflt" {
+                    self.warning(
+                        language.range(),
+                        "'language' statement before any 'script' statement applies to the 'DFLT' script",
+                    );
+                }
+            } else if let Some(flag) = typed::LookupFlag::cast(item) {
+                lookupflag_is_set = flag
+                    .number()
+                    .map(|num| num.parse_unsigned() != Some(0))
+                    .unwrap_or(true);
+                pending_flag_reset = None;
+            } else if item.kind().is_rule()
+                || item.kind() == Kind::LookupBlockNode
+                || item.kind() == Kind::LookupRefNode
+            {
+                if !seen_script {
+                    rules_before_script += 1;
+                }
+                if let Some(range) = pending_flag_reset.take() {
+                    self.warning(
+                        range,
+                        "'script' statement resets the lookupflag to 0 for the rules that follow",
+                    );
+                }
+            }
+        }
+    }
+
+    /// Warn about the makeotf spellings 'excludeDFLT' and 'includeDFLT', which
+    /// mean the same as 'exclude_dflt' and 'include_dflt', with an edit that
+    /// replaces them.
+    fn check_deprecated_language_keywords(&mut self, node: &typed::Language) {
+        let keywords = [
+            (node.exclude_dflt(), "excludeDFLT", "exclude_dflt"),
+            (node.include_dflt(), "includeDFLT", "include_dflt"),
+        ];
+        for (token, old, new) in keywords {
+            let Some(token) = token.filter(|token| token.as_str() == old) else {
+                continue;
+            };
+            let (file, range) = self.source_map.resolve_range(token.range());
+            let fix = TextEdit::new(
+                Location {
+                    file,
+                    range: range.clone(),
+                },
+                new,
+            );
+            self.errors.push(
+                Diagnostic::warning(file, range, format!("'{old}' is deprecated"))
+                    .with_help(format!("replace it with '{new}'"))
+                    .with_fix(fix),
+            );
+        }
     }
 
     fn validate_stylistic_set_items<'b>(
         &mut self,
-        iter: &mut impl Iterator<Item = &'b NodeOrToken>,
+        iter: &mut Peekable<impl Iterator<Item = &'b NodeOrToken>>,
     ) {
-        let mut iter = iter.peekable();
         if let Some(node) = iter.peek().and_then(|x| typed::FeatureNames::cast(x)) {
             for name in node.statements() {
                 self.validate_name_spec(&name);
@@ -540,9 +948,8 @@ impl<'a> ValidationCtx<'a> {
 
     fn validate_character_variant_items<'b>(
         &mut self,
-        iter: &mut impl Iterator<Item = &'b NodeOrToken>,
+        iter: &mut Peekable<impl Iterator<Item = &'b NodeOrToken>>,
     ) {
-        let mut iter = iter.peekable();
         if let Some(node) = iter.peek().and_then(|x| typed::CvParameters::cast(x)) {
             for kind in [
                 Kind::FeatUiLabelNameIdKw,
@@ -554,6 +961,12 @@ impl<'a> ValidationCtx<'a> {
                     self.warning(node.keyword().range(), format!("missing '{kind}' node"));
                 }
             }
+            for character in node.characters() {
+                let value = character.value();
+                if let Err(err) = value.parse_char() {
+                    self.error(value.range(), err);
+                }
+            }
 
             iter.next();
         }
@@ -616,6 +1029,37 @@ impl<'a> ValidationCtx<'a> {
                 "size feature must include a 'parameters' statement",
             ),
             Some(param) => {
+                let n_values = param.values().count();
+                if n_values != 2 && n_values != 4 {
+                    return self.error(
+                        param.range(),
+                        "size 'parameters' must have two or four values",
+                    );
+                }
+                if let Some(typed::FloatLike::Float(subfamily)) = param.values().nth(1) {
+                    return self
+                        .error(subfamily.range(), "subfamily identifier must be an integer");
+                }
+                let mut in_range = true;
+                for value in param.values() {
+                    // sizes are stored in decipoints
+                    let valid = match &value {
+                        typed::FloatLike::Number(number) => number.parse_unsigned().is_some(),
+                        typed::FloatLike::Float(float) => {
+                            (0.0..=i16::MAX as f32).contains(&(float.parse() * 10.0).round())
+                        }
+                    };
+                    if !valid {
+                        self.error(
+                            value.range(),
+                            "expected a positive number no greater than 3276.7",
+                        );
+                        in_range = false;
+                    }
+                }
+                if !in_range {
+                    return;
+                }
                 if param.subfamily().parse_signed() == 0
                     && param.range_start().map(|x| x.parse() as i32).unwrap_or(0) == 0
                     && param.range_end().map(|x| x.parse() as i32).unwrap_or(0) == 0
@@ -643,7 +1087,8 @@ impl<'a> ValidationCtx<'a> {
             );
         }
         let mut kind = None;
-        if let Some(_prev) = self.lookup_defs.insert(name.text.clone(), name.clone()) {
+        self.current_lookup = Some(name.text.clone());
+        if self.lookup_defs.contains_key(&name.text) {
             //TODO: annotate with previous location
             self.error(
                 name.range(),
@@ -681,7 +1126,7 @@ impl<'a> ValidationCtx<'a> {
                         "lookup reference outside of feature is ignored",
                     );
                 }
-                self.validate_lookup_ref(&node);
+                self.validate_lookup_ref(&node, false);
             } else if let Some(node) = typed::LookupBlock::cast(item) {
                 self.error(
                     node.keyword().range(),
@@ -710,6 +1155,9 @@ impl<'a> ValidationCtx<'a> {
                 );
             }
         }
+        // the lookup is only defined after its body, so it cannot reference itself
+        self.lookup_defs.insert(name.text.clone(), name.clone());
+        self.current_lookup = None;
     }
 
     fn validate_gpos_statement(&mut self, node: &typed::GposStatement) {
@@ -736,10 +1184,11 @@ impl<'a> ValidationCtx<'a> {
             typed::GposStatement::Type4(rule) => {
                 self.validate_glyph_or_class(&rule.base());
                 for mark in rule.attachments() {
-                    self.validate_anchor(&mark.anchor());
+                    let anchor = mark.anchor();
+                    self.validate_anchor(&anchor);
                     match mark.mark_class_name() {
-                        Some(name) => self.validate_mark_class(&name),
-                        None => {
+                        Some(name) if anchor.null().is_none() => self.validate_mark_class(&name),
+                        _ => {
                             self.error(mark.range(), "mark-to-base attachments should not be null")
                         }
                     }
@@ -750,9 +1199,28 @@ impl<'a> ValidationCtx<'a> {
                 //number of ligature components? not sure how we check this.
                 self.validate_glyph_or_class(&rule.base());
                 for component in rule.ligature_components() {
+                    let mut n_anchors = 0;
+                    let mut has_null = false;
+                    let mut seen_classes = HashSet::new();
                     for mark in component.attachments() {
                         let anchor = mark.anchor();
+                        n_anchors += 1;
+                        has_null |= anchor.null().is_some();
+                        if let Some(name) = mark.mark_class_name() {
+                            if !seen_classes.insert(name.text().clone()) {
+                                self.error(
+                                    name.range(),
+                                    format!(
+                                        "mark class '{}' is used more than once in this component",
+                                        name.text()
+                                    ),
+                                );
+                            }
+                        }
                         match mark.mark_class_name() {
+                            Some(_) if anchor.null().is_some() => {
+                                self.error(anchor.range(), "NULL anchor cannot specify mark class")
+                            }
                             Some(name) => self.validate_mark_class(&name),
                             None => {
                                 if anchor.null().is_none() {
@@ -765,15 +1233,27 @@ impl<'a> ValidationCtx<'a> {
                         }
                         self.validate_anchor(&anchor);
                     }
+                    if n_anchors == 0 {
+                        self.error(
+                            component.range(),
+                            "ligature component must have at least one anchor",
+                        );
+                    } else if has_null && n_anchors > 1 {
+                        self.error(
+                            component.range(),
+                            "a NULL anchor must be the only anchor in its ligature component",
+                        );
+                    }
                 }
             }
             typed::GposStatement::Type6(rule) => {
                 self.validate_glyph_or_class(&rule.base());
                 for mark in rule.attachments() {
-                    self.validate_anchor(&mark.anchor());
+                    let anchor = mark.anchor();
+                    self.validate_anchor(&anchor);
                     match mark.mark_class_name() {
-                        Some(name) => self.validate_mark_class(&name),
-                        None => {
+                        Some(name) if anchor.null().is_none() => self.validate_mark_class(&name),
+                        _ => {
                             self.error(mark.range(), "mark-to-mark attachments should not be null")
                         }
                     }
@@ -803,7 +1283,7 @@ impl<'a> ValidationCtx<'a> {
         for item in node.input().items() {
             self.validate_glyph_or_class(&item.target());
             for lookup in item.lookups() {
-                self.validate_lookup_ref(&lookup);
+                self.validate_lookup_ref(&lookup, true);
                 if seen_inline {
                     self.error(
                         lookup.range(),
@@ -826,6 +1306,22 @@ impl<'a> ValidationCtx<'a> {
     }
 
     fn validate_gsub_statement(&mut self, node: &typed::GsubStatement) {
+        if let Some(tag) = self
+            .current_feature
+            .filter(|tag| tags::is_vertical_substitution(*tag))
+        {
+            if matches!(
+                node,
+                typed::GsubStatement::Type2(_)
+                    | typed::GsubStatement::Type3(_)
+                    | typed::GsubStatement::Type4(_)
+            ) {
+                self.warning(
+                    node.range(),
+                    format!("'{tag}' should only contain single substitutions"),
+                );
+            }
+        }
         match node {
             typed::GsubStatement::Type1(rule) => {
                 //TODO: ensure equal lengths, other requirements
@@ -835,10 +1331,10 @@ impl<'a> ValidationCtx<'a> {
                 }
             }
             typed::GsubStatement::Type2(rule) => {
-                self.validate_glyph(&rule.target());
+                self.validate_glyph_or_class(&rule.target());
                 let mut count = 0;
                 for item in rule.replacement() {
-                    self.validate_glyph(&item);
+                    self.validate_glyph_or_class(&item);
                     count += 1;
                 }
                 if count < 2 {
@@ -889,14 +1385,17 @@ impl<'a> ValidationCtx<'a> {
                 }
 
                 let input_seq = rule.input();
-                for (i, item) in input_seq.items().enumerate() {
-                    let target = item.target();
-                    if i == 0 && inline_class_sub && !target.is_class() {
+                if inline_class_sub {
+                    let mut items = input_seq.items();
+                    let first_is_class = items.next().map(|item| item.target().is_class());
+                    if first_is_class != Some(true) || items.next().is_some() {
                         self.error(
                             input_seq.range(),
                             "if replacing by glyph class, input sequence must be a single glyph class",
                         );
                     }
+                }
+                for item in input_seq.items() {
                     self.validate_glyph_or_class(&item.target());
                     for lookup in item.lookups() {
                         if has_inline_rule {
@@ -905,7 +1404,7 @@ impl<'a> ValidationCtx<'a> {
                                 "named lookup not allowed in statement that includes inline rule",
                             );
                         }
-                        self.validate_lookup_ref(&lookup);
+                        self.validate_lookup_ref(&lookup, true);
                     }
                 }
             }
@@ -956,12 +1455,25 @@ impl<'a> ValidationCtx<'a> {
 
     fn validate_lookupflag(&mut self, node: &typed::LookupFlag) {
         if let Some(number) = node.number() {
-            if number.text().parse::<u16>().is_err() {
-                self.error(number.range(), "value must be a positive 16 bit integer");
+            match number.text().parse::<u16>() {
+                Err(_) => self.error(number.range(), "value must be a positive 16 bit integer"),
+                Ok(value) if LookupFlag::from_bits_truncate(value).use_mark_filtering_set() => self.error(
+                    number.range(),
+                    "UseMarkFilteringSet cannot be set numerically; use 'UseMarkFilteringSet' with a glyph class",
+                ),
+                Ok(_) => (),
             }
             return;
         }
 
+        if node.values().next().is_none() {
+            self.error(
+                node.range(),
+                "lookupflag requires a number or at least one named flag",
+            );
+            return;
+        }
+
         let mut rtl = false;
         let mut ignore_base = false;
         let mut ignore_lig = false;
@@ -1067,14 +1579,65 @@ impl<'a> ValidationCtx<'a> {
     }
 
     fn validate_glyph_name(&mut self, name: &typed::GlyphName) {
-        if self.glyph_map.get(name.text()).is_none() {
-            self.error(name.range(), "glyph not in font");
+        let glyph_map = self.glyph_map;
+        let exists = glyph_map.get(name.text()).is_some();
+        match glyph_map.alias_error(name.text()) {
+            Some(err) if exists => self.warning(
+                name.range(),
+                format!("{err}; using the glyph named '{}'", name.text()),
+            ),
+            Some(err) => self.error(name.range(), err.to_string()),
+            None if !exists => self.missing_glyph(
+                name.range(),
+                "glyph",
+                name.text(),
+                glyph_map.names().map(SmolStr::as_str),
+            ),
+            None => self.check_unicode_name(name),
+        }
+    }
+
+    /// Warn if a glyph named for a codepoint is not the glyph that the cmap
+    /// maps that codepoint to. Each name is only reported once.
+    fn check_unicode_name(&mut self, name: &typed::GlyphName) {
+        let Some(cmap) = self.unicode_name_cmap else {
+            return;
+        };
+        let Some(codepoint) = unicode_name_codepoint(name.text()) else {
+            return;
+        };
+        if !self.checked_unicode_names.insert(name.text().clone()) {
+            return;
         }
+        let glyph_map = self.glyph_map;
+        let message = match cmap.get(&codepoint) {
+            None => format!(
+                "'{}' is named for U+{codepoint:04X}, which is not in the cmap",
+                name.text()
+            ),
+            Some(gid) if glyph_map.get(name.text()) == Some(*gid) => return,
+            Some(gid) => {
+                let mapped = glyph_map
+                    .names()
+                    .find(|other| glyph_map.get(*other) == Some(*gid))
+                    .map(|other| format!("'{other}'"))
+                    .unwrap_or_else(|| gid.to_string());
+                format!(
+                    "'{}' is named for U+{codepoint:04X}, but the cmap maps it to {mapped}",
+                    name.text()
+                )
+            }
+        };
+        self.warning(name.range(), message);
     }
 
     fn validate_cid(&mut self, cid: &typed::Cid) {
-        if self.glyph_map.get(&cid.parse()).is_none() {
-            self.error(cid.range(), "CID not in font");
+        match cid.try_parse() {
+            Err(err) => self.error(cid.range(), err),
+            Ok(id) if self.glyph_map.get(&id).is_none() => {
+                self.missing_glyph(cid.range(), "CID", "", [])
+            }
+            Ok(_) => (),
         }
     }
 
@@ -1082,48 +1645,80 @@ impl<'a> ValidationCtx<'a> {
         if accept_mark_class && self.mark_class_defs.contains(node.text()) {
             return;
         }
+        if let Some(current) = self.current_class_def.clone() {
+            self.class_refs
+                .entry(current)
+                .or_default()
+                .push(node.text().clone());
+        }
         if !self.glyph_class_defs.contains_key(node.text()) {
-            self.error(node.range(), "undefined glyph class");
+            if self.forward_class_references {
+                let referrer = self.current_class_def.clone();
+                self.forward_class_refs
+                    .push((node.token().clone(), referrer));
+                return;
+            }
+            let candidates = self
+                .glyph_class_defs
+                .keys()
+                .chain(self.mark_class_defs.iter().filter(|_| accept_mark_class))
+                .cloned()
+                .collect::<Vec<_>>();
+            self.undefined(
+                node.range(),
+                "undefined glyph class",
+                node.text(),
+                candidates.iter().map(SmolStr::as_str),
+            );
         }
     }
 
-    fn validate_lookup_ref(&mut self, node: &typed::LookupRef) {
-        if !self.lookup_defs.contains_key(&node.label().text) {
-            self.error(node.label().range(), "lookup is not defined");
+    /// Check a reference to a lookup.
+    ///
+    /// `contextual` is `true` if the reference is part of a contextual rule,
+    /// as opposed to a `lookup` statement that adds a lookup to a feature.
+    fn validate_lookup_ref(&mut self, node: &typed::LookupRef, contextual: bool) {
+        let label = node.label();
+        if contextual {
+            if let Some(current) = self.current_lookup.clone() {
+                self.lookup_refs
+                    .entry(current)
+                    .or_default()
+                    .push(label.text.clone());
+            }
+        }
+        if !self.lookup_defs.contains_key(&label.text) {
+            let referrer = self.current_lookup.clone().filter(|_| contextual);
+            self.forward_lookup_refs.push((label.clone(), referrer));
         }
     }
 
     fn validate_glyph_range(&mut self, range: &typed::GlyphRange) {
         let start = range.start();
         let end = range.end();
+        let glyph_map = self.glyph_map;
+        let mut missing = Vec::new();
 
-        match (start.kind, end.kind) {
-            (Kind::Cid, Kind::Cid) => {
-                if let Err(err) = glyph_range::cid(start, end, |cid| {
-                    if self.glyph_map.get(&cid).is_none() {
-                        // this is techincally allowed, but we error for now
-                        self.warning(
-                            range.range(),
-                            format!("Range member '{}' does not exist in font", cid),
-                        );
-                    }
-                }) {
-                    self.error(range.range(), err);
+        let result = match (start.kind, end.kind) {
+            (Kind::Cid, Kind::Cid) => glyph_range::cid(start, end, |cid| {
+                if glyph_map.get(&cid).is_none() {
+                    missing.push(cid.to_string());
                 }
-            }
-            (Kind::GlyphName, Kind::GlyphName) => {
-                if let Err(err) = glyph_range::named(start, end, |name| {
-                    if self.glyph_map.get(name).is_none() {
-                        self.warning(
-                            range.range(),
-                            format!("Range member '{}' does not exist in font", name),
-                        );
-                    }
-                }) {
-                    self.error(range.range(), err);
+            }),
+            (Kind::GlyphName, Kind::GlyphName) => glyph_range::named(start, end, |name| {
+                if glyph_map.get(name).is_none() {
+                    missing.push(name.to_string());
                 }
+            }),
+            (_, _) => Err("Invalid types in glyph range".to_string()),
+        };
+        match result {
+            Err(err) => self.error(range.range(), err),
+            // this is techincally allowed, but we error for now
+            Ok(()) if !missing.is_empty() => {
+                self.warning(range.range(), glyph_range::missing_members(&missing))
             }
-            (_, _) => self.error(range.range(), "Invalid types in glyph range"),
+            Ok(()) => (),
         }
     }
 
@@ -1133,6 +1728,37 @@ impl<'a> ValidationCtx<'a> {
                 self.error(name.range(), "undefined value record name");
             }
         }
+        let numbers = node
+            .advance()
+            .into_iter()
+            .chain(node.placement().into_iter().flatten());
+        for number in numbers {
+            if let Err(err) = number.try_parse_signed() {
+                self.error(number.range(), err);
+            }
+        }
+        for device in node.device().iter().flatten() {
+            self.validate_device(device);
+        }
+        if let Some(tag) = self
+            .current_feature
+            .filter(|tag| tags::is_vertical_positioning(*tag))
+        {
+            let x_advance = node
+                .placement()
+                .and_then(|[_, _, x_adv, _]| x_adv.try_parse_signed().ok())
+                .unwrap_or(0);
+            let x_advance_device = node
+                .device()
+                .map(|[_, _, x_adv, _]| x_adv.null().is_none())
+                .unwrap_or(false);
+            if x_advance != 0 || x_advance_device {
+                self.warning(
+                    node.range(),
+                    format!("x advance has no effect in vertical feature '{tag}'"),
+                );
+            }
+        }
     }
 
     fn validate_anchor(&mut self, anchor: &typed::Anchor) {
@@ -1141,79 +1767,75 @@ impl<'a> ValidationCtx<'a> {
                 self.error(name.range(), "undefined anchor name");
             }
         }
-    }
-}
-
-fn range_for_iter<T: AstNode>(mut iter: impl Iterator<Item = T>) -> Option<Range<usize>> {
-    let start = iter.next()?.range();
-    Some(iter.fold(start, |cur, node| cur.start..node.range().end))
-}
-
-fn validate_name_string_encoding(
-    platform: u16,
-    string: &Token,
-) -> Result<(), (Range<usize>, String)> {
-    let mut to_scan: &str = string.as_str();
-    debug_assert!(to_scan.starts_with('"'));
-    debug_assert!(to_scan.ends_with('"'));
-    to_scan = &to_scan[1..to_scan.len() - 1];
-    let token_start = string.range().start;
-    let mut cur_off = 1;
-    while !to_scan.is_empty() {
-        match to_scan.bytes().position(|b| b == b'\\') {
-            None => to_scan = "",
-            Some(pos) if platform == WIN_PLATFORM_ID => {
-                let range_start = token_start + cur_off + pos;
-                if let Some(val) = to_scan.get(pos + 1..pos + 5) {
-                    if let Some(idx) = val.bytes().position(|b| !b.is_ascii_hexdigit()) {
-                        return Err((
-                            range_start + idx..range_start + idx + 1,
-                            format!(
-                                "invalid escape sequence: '{}' is not a hex digit",
-                                val.as_bytes()[idx] as char
-                            ),
-                        ));
+        if let Some((x, y)) = anchor.coords() {
+            let mut is_variable = false;
+            for metric in [x, y] {
+                match metric {
+                    typed::AnchorMetric::Scalar(metric) => {
+                        if let Err(err) = metric.try_parse() {
+                            self.error(metric.range(), err);
+                        }
                     }
-                } else {
-                    return Err((
-                        range_start..range_start + to_scan[pos..].len(),
-                        "windows escape sequences must be four hex digits long".into(),
-                    ));
-                }
-                cur_off += to_scan[..pos].len();
-                to_scan = &to_scan[pos + 5..];
-            }
-            Some(pos) => {
-                let range_start = token_start + cur_off + pos;
-                if let Some(val) = to_scan.get(pos + 1..pos + 3) {
-                    if let Some(idx) = val.bytes().position(|b| !b.is_ascii_hexdigit()) {
-                        return Err((
-                            range_start + idx..range_start + idx + 1,
-                            format!(
-                                "invalid escape sequence: '{}' is not a hex digit",
-                                val.as_bytes()[idx] as char
-                            ),
-                        ));
+                    typed::AnchorMetric::Variable(metric) => {
+                        is_variable = true;
+                        self.validate_variable_metric(&metric);
                     }
-
-                    if let Err(e) = u8::from_str_radix(val, 16) {
-                        return Err((
-                            range_start..range_start + 3,
-                            format!("invalid escape sequence '{}'", e),
-                        ));
-                    }
-                } else {
-                    return Err((
-                        range_start..range_start + to_scan[pos..].len(),
-                        "mac escape sequences must be two hex digits long".into(),
-                    ));
                 }
-                cur_off += to_scan[..pos].len();
-                to_scan = &to_scan[pos + 3..];
+            }
+            if is_variable && (anchor.contourpoint().is_some() || anchor.devices().is_some()) {
+                self.error(
+                    anchor.range(),
+                    "variable anchors cannot have a contour point or device tables",
+                );
+            }
+        }
+        if let Some(point) = anchor.contourpoint() {
+            if point.parse_unsigned().is_none() {
+                self.error(point.range(), "expected positive number");
+            }
+        }
+        if let Some((x, y)) = anchor.devices() {
+            self.validate_device(&x);
+            self.validate_device(&y);
+        }
+    }
+
+    fn validate_variable_metric(&mut self, metric: &typed::VariableMetric) {
+        let Some(locations) = metric.locations() else {
+            return;
+        };
+        if let Err(err) = variations::parse_variable_metric(locations.as_str()) {
+            self.error(locations.range(), err);
+        }
+    }
+
+    fn validate_device(&mut self, device: &typed::Device) {
+        for (ppem, delta) in device.entries() {
+            if ppem.parse_unsigned().is_none() {
+                self.error(ppem.range(), "expected positive number");
+            }
+            if !delta
+                .try_parse_signed()
+                .map(|delta| i8::try_from(delta).is_ok())
+                .unwrap_or(false)
+            {
+                self.error(
+                    delta.range(),
+                    format!(
+                        "device delta '{}' is out of range: expected a number from {} to {}",
+                        delta.text(),
+                        i8::MIN,
+                        i8::MAX
+                    ),
+                );
             }
         }
     }
-    Ok(())
+}
+
+fn range_for_iter<T: AstNode>(mut iter: impl Iterator<Item = T>) -> Option<Range<usize>> {
+    let start = iter.next()?.range();
+    Some(iter.fold(start, |cur, node| cur.start..node.range().end))
 }
 
 /// adapted from <https://learn.microsoft.com/en-us/typography/opentype/spec/ibmfc>
@@ -1235,6 +1857,101 @@ fn validate_os2_family_class(raw: u16) -> Result<u16, (u8, u8)> {
     }
 }
 
+/// The codepoint of a glyph name in Unicode notation, `uniXXXX` or `uXXXX`
+/// to `uXXXXXX`, with uppercase hex digits.
+fn unicode_name_codepoint(name: &str) -> Option<u32> {
+    let hex = name
+        .strip_prefix("uni")
+        .filter(|hex| hex.len() == 4)
+        .or_else(|| {
+            name.strip_prefix('u')
+                .filter(|hex| (4..=6).contains(&hex.len()))
+        })?;
+    if !hex
+        .bytes()
+        .all(|b| b.is_ascii_digit() || (b'A'..=b'F').contains(&b))
+    {
+        return None;
+    }
+    let codepoint = u32::from_str_radix(hex, 16).ok()?;
+    char::from_u32(codepoint).map(|_| codepoint)
+}
+
+/// Find the candidate closest to `name`, if any is close enough to be a
+/// plausible misspelling.
+///
+/// Ties are broken alphabetically, so that results do not depend on the
+/// iteration order of the candidates.
+fn closest_match<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let name_len = name.chars().count();
+    let max_distance = (name_len / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        // the distance is at least the difference in length; this is much
+        // cheaper to check, and rules out most glyphs in a large font
+        .filter(|candidate| candidate.chars().count().abs_diff(name_len) <= max_distance)
+        .filter_map(|candidate| {
+            edit_distance(name, candidate, max_distance).map(|distance| (distance, candidate))
+        })
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings, counted in chars, if it is
+/// no greater than `max`.
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev_row = (0..=b.len()).collect::<Vec<_>>();
+    let mut row = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = prev_row[j] + usize::from(a_char != *b_char);
+            row[j + 1] = substitution.min(prev_row[j + 1] + 1).min(row[j] + 1);
+        }
+        // distances never decrease from one row to the next
+        if row.iter().all(|distance| *distance > max) {
+            return None;
+        }
+        std::mem::swap(&mut prev_row, &mut row);
+    }
+    Some(prev_row[b.len()]).filter(|distance| *distance <= max)
+}
+
+/// If `from` references `to`, the chain of references that leads from `to`
+/// back to `from`, starting and ending with `from`.
+fn reference_cycle<'b>(
+    refs: &'b HashMap<SmolStr, Vec<SmolStr>>,
+    from: &'b SmolStr,
+    to: &'b SmolStr,
+) -> Option<Vec<&'b str>> {
+    // breadth-first, so that we show the shortest cycle
+    let mut parents = HashMap::from([(to, to)]);
+    let mut queue = std::collections::VecDeque::from([to]);
+    while let Some(name) = queue.pop_front() {
+        if name == from {
+            // walk back to `to`, then close the cycle
+            let mut chain = vec![name.as_str()];
+            let mut current = name;
+            while current != to {
+                current = parents[&current];
+                chain.push(current.as_str());
+            }
+            chain.push(from.as_str());
+            chain.reverse();
+            return Some(chain);
+        }
+        for next in refs.get(name).into_iter().flatten() {
+            if !parents.contains_key(next) {
+                parents.insert(next, name);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1247,4 +1964,471 @@ mod tests {
         assert!(validate_os2_family_class(0x0203).is_err());
         assert!(validate_os2_family_class(0x0600).is_err());
     }
+
+    #[test]
+    fn edit_distance_basics() {
+        assert_eq!(edit_distance("", "", 0), Some(0));
+        assert_eq!(edit_distance("a", "", 1), Some(1));
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+        assert_eq!(edit_distance("a.sc", "a.sc", 0), Some(0));
+        assert_eq!(edit_distance("é", "e", 1), Some(1));
+        assert_eq!(edit_distance("abcdef", "uvwxyz", 1), None);
+    }
+
+    #[test]
+    fn suggest_similar_names() {
+        let names = ["a", "b", "a.sc", "b.sc", "@lowercase", "@uppercase"];
+        assert_eq!(closest_match("a.cs", names), None);
+        assert_eq!(closest_match("a.sx", names), Some("a.sc"));
+        assert_eq!(closest_match("c.sc", names), Some("a.sc"));
+        assert_eq!(closest_match("@lowercas", names), Some("@lowercase"));
+        assert_eq!(closest_match("@upercase", names), Some("@uppercase"));
+        assert_eq!(closest_match("zzzz", names), None);
+    }
+
+    #[test]
+    fn suggestions_are_capped() {
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let fea = format!(
+            "feature test {{\n{}}} test;",
+            "sub zerx by one;\n".repeat(MAX_SUGGESTIONS + 5)
+        );
+        let tree = crate::util::ttx::parse_str(&fea, &glyph_map);
+        let diagnostics =
+            crate::compile::validation_diagnostics(&tree, &glyph_map, &Default::default());
+        assert_eq!(diagnostics.len(), MAX_SUGGESTIONS + 5);
+        let helps = diagnostics.iter().map(Diagnostic::help).collect::<Vec<_>>();
+        assert_eq!(helps[0], Some("did you mean 'zero'?"));
+        assert!(helps[..MAX_SUGGESTIONS].iter().all(Option::is_some));
+        assert!(helps[MAX_SUGGESTIONS..].iter().all(Option::is_none));
+    }
+
+    fn validation_messages(fea: &'static str) -> Vec<String> {
+        validation_messages_with_opts(fea, &Default::default())
+    }
+
+    fn validation_messages_with_opts(
+        fea: &'static str,
+        opts: &crate::compile::Opts,
+    ) -> Vec<String> {
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let tree = crate::util::ttx::parse_str(fea, &glyph_map);
+        crate::compile::validation_diagnostics(&tree, &glyph_map, opts)
+            .iter()
+            .map(|diag| diag.text().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn ligature_component_anchors() {
+        let messages = validation_messages(
+            "
+markClass acute <anchor 0 500> @TOP;
+markClass cedilla <anchor 0 0> @BOTTOM;
+feature mark {
+    pos ligature f_i <anchor 100 500> mark @TOP <anchor 120 500> mark @TOP
+        ligComponent <anchor NULL>;
+    pos ligature f_f_i <anchor 100 500> mark @TOP
+        ligComponent <anchor 300 0> mark @BOTTOM <anchor NULL>
+        ligComponent;
+} mark;
+",
+        );
+        assert_eq!(
+            messages,
+            [
+                "mark class '@TOP' is used more than once in this component",
+                "a NULL anchor must be the only anchor in its ligature component",
+                "ligature component must have at least one anchor",
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_before_script_without_defaults() {
+        let messages = validation_messages(
+            "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+feature liga {
+    sub f i by f_i;
+    script latn;
+    sub f l by f_l;
+    script arab;
+    sub a by b;
+    script arab;
+} liga;
+",
+        );
+        assert_eq!(
+            messages,
+            ["rules before the first 'script' statement are not registered for script 'arab', as it has no 'languagesystem arab dflt' statement"]
+        );
+    }
+
+    #[test]
+    fn implicit_default_lang_system() {
+        let fea = "
+languagesystem latn dflt;
+feature liga {
+    sub f i by f_i;
+    script DFLT;
+    sub a by b;
+} liga;
+feature smcp { sub a by b; } smcp;
+";
+        assert_eq!(
+            validation_messages(fea),
+            ["rules before the first 'script' statement are not registered for script 'DFLT', as it has no 'languagesystem DFLT dflt' statement"]
+        );
+        let opts = crate::compile::Opts::new().implicit_default_lang_system(true);
+        assert_eq!(
+            validation_messages_with_opts(fea, &opts),
+            ["no 'languagesystem DFLT dflt' statement; registering it implicitly, so that rules are not dropped for the default script"]
+        );
+    }
+
+    #[test]
+    fn implicit_script_lang_systems() {
+        let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+feature liga {
+    sub f i by f_i;
+    script cyrl;
+    sub a by b;
+} liga;
+feature smcp { script cyrl; sub a by b; } smcp;
+";
+        assert_eq!(
+            validation_messages(fea),
+            ["rules before the first 'script' statement are not registered for script 'cyrl', as it has no 'languagesystem cyrl dflt' statement"]
+        );
+        let opts = crate::compile::Opts::new().implicit_script_lang_systems(true);
+        assert_eq!(
+            validation_messages_with_opts(fea, &opts),
+            ["no 'languagesystem cyrl dflt' statement; registering it implicitly, so that rules are not dropped for this script"]
+        );
+    }
+
+    #[test]
+    fn language_before_script() {
+        let messages = validation_messages(
+            "feature liga { language dflt; language TRK; sub f i by f_i; } liga;",
+        );
+        assert_eq!(
+            messages,
+            ["'language' statement before any 'script' statement applies to the 'DFLT' script"]
+        );
+    }
+
+    #[test]
+    fn script_resets_lookupflag() {
+        let messages = validation_messages(
+            "
+languagesystem latn dflt;
+feature liga {
+    lookupflag IgnoreMarks;
+    sub f i by f_i;
+    script latn;
+    sub f l by f_l;
+} liga;
+feature kern {
+    lookupflag IgnoreMarks;
+    pos a b 10;
+    script latn;
+    lookupflag IgnoreMarks;
+    pos c d 10;
+} kern;
+",
+        );
+        assert_eq!(
+            messages,
+            ["'script' statement resets the lookupflag to 0 for the rules that follow"]
+        );
+    }
+
+    #[test]
+    fn null_anchor_with_mark_class() {
+        let messages = validation_messages(
+            "
+markClass [acute] <anchor 0 500> @TOP;
+feature mark {
+    pos base a <anchor NULL> mark @TOP;
+    pos ligature f_i <anchor NULL> mark @TOP;
+} mark;
+feature mkmk { pos mark grave <anchor NULL> mark @TOP; } mkmk;
+",
+        );
+        assert_eq!(
+            messages,
+            [
+                "mark-to-base attachments should not be null",
+                "NULL anchor cannot specify mark class",
+                "mark-to-mark attachments should not be null",
+            ]
+        );
+    }
+
+    #[test]
+    fn first_statement_in_stylistic_set() {
+        let messages = validation_messages("feature ss01 { sub a by \\1; } ss01;");
+        assert_eq!(messages, ["CID not in font"]);
+    }
+
+    #[test]
+    fn lookup_cannot_reference_itself() {
+        let messages = validation_messages("lookup ONE { sub a' lookup ONE b; } ONE;");
+        assert_eq!(messages, ["lookup references form a cycle: ONE -> ONE"]);
+    }
+
+    #[test]
+    fn lookup_reference_cycles() {
+        let messages = validation_messages(
+            "
+lookup ONE { sub a' lookup TWO b; } ONE;
+lookup TWO { sub c' lookup THREE d; } TWO;
+lookup THREE { sub e' lookup ONE f; } THREE;
+feature calt {
+    sub a' lookup LATER b;
+    lookup LATER;
+    sub a' lookup MISSING b;
+} calt;
+lookup LATER { sub a by b; } LATER;
+",
+        );
+        assert_eq!(
+            messages,
+            [
+                "lookup references form a cycle: ONE -> TWO -> THREE -> ONE",
+                "lookup references form a cycle: TWO -> THREE -> ONE -> TWO",
+                "lookup 'LATER' is used before it is defined",
+                "lookup 'LATER' is used before it is defined",
+                "lookup is not defined",
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_class_references() {
+        let fea = "
+@upper = [@vowels_uc B C];
+@vowels_uc = [@a_uc E];
+@a_uc = [A];
+@self = [@self a];
+@one = [@two a];
+@two = [@three b];
+@three = [@one c];
+feature liga { sub @lower by @upper; } liga;
+@lower = [a b c];
+";
+        let messages = validation_messages(fea);
+        assert_eq!(messages.len(), 6);
+        assert!(messages.iter().all(|msg| msg == "undefined glyph class"));
+
+        let opts = crate::compile::Opts::new().forward_class_references(true);
+        assert_eq!(
+            validation_messages_with_opts(fea, &opts),
+            [
+                "glyph class references form a cycle: @self -> @self",
+                "glyph class references form a cycle: @one -> @two -> @three -> @one",
+                "glyph class references form a cycle: @two -> @three -> @one -> @two",
+            ]
+        );
+
+        let messages = validation_messages_with_opts("@a = [@b]; @a = [a]; @b = [b];", &opts);
+        assert_eq!(
+            messages,
+            ["glyph class is already defined; with forward class references, each class can only be defined once"]
+        );
+        let messages = validation_messages_with_opts("@a = [@nope];", &opts);
+        assert_eq!(messages, ["undefined glyph class"]);
+    }
+
+    #[test]
+    fn inline_class_sub_needs_single_input() {
+        let messages = validation_messages("feature calt { sub [a b]' [c d]' by [e f]; } calt;");
+        assert_eq!(
+            messages,
+            ["if replacing by glyph class, input sequence must be a single glyph class"]
+        );
+    }
+
+    #[test]
+    fn numbers_out_of_range() {
+        let messages = validation_messages(
+            "
+anchorDef 40000 0 TOO_BIG;
+feature kern {
+    pos a -32768;
+    pos b 32768;
+    pos c <0 0 -40000 0>;
+    pos d <0 0 0 0 <device 11 -128, 12 200> <device NULL> <device NULL> <device NULL>>;
+} kern;
+feature curs {
+    pos cursive a <anchor 0 -70000> <anchor 0 0 contourpoint -1>;
+} curs;
+",
+        );
+        assert_eq!(
+            messages,
+            [
+                "value '40000' is out of range: expected a number from -32768 to 32767",
+                "value '32768' is out of range: expected a number from -32768 to 32767",
+                "value '-40000' is out of range: expected a number from -32768 to 32767",
+                "device delta '200' is out of range: expected a number from -128 to 127",
+                "value '-70000' is out of range: expected a number from -32768 to 32767",
+                "expected positive number",
+            ]
+        );
+    }
+
+    #[test]
+    fn variable_anchors() {
+        let messages = validation_messages(
+            "
+markClass acute <anchor 0 0> @TOP;
+feature mark {
+    pos base a <anchor (wght=400:250 wght=900:300) 0> mark @TOP;
+    pos base b <anchor (wght=400:250 wght:300) 0> mark @TOP;
+    pos base c <anchor 0 (wght=400:250 wght=900,wght=100:300)> mark @TOP;
+    pos base d <anchor (wght=400:250) 0 contourpoint 2> mark @TOP;
+} mark;
+",
+        );
+        assert_eq!(
+            messages,
+            [
+                "expected a location and value, like 'wght=200:100', found 'wght:300'",
+                "axis 'wght' appears more than once in 'wght=900,wght=100:300'",
+                "variable anchors cannot have a contour point or device tables",
+            ]
+        );
+    }
+
+    #[test]
+    fn name_string_escapes() {
+        let name_messages = |names: &str| {
+            let fea = format!("table name {{ {names} }} name;");
+            validation_messages(Box::leak(fea.into_boxed_str()))
+        };
+        assert!(name_messages(r#"nameid 9 "M\00fcller \d83d\de00 \005c";"#).is_empty());
+        assert!(name_messages(r#"nameid 9 1 "M\9fller \ca";"#).is_empty());
+        assert_eq!(
+            name_messages(r#"nameid 9 "\00zz";"#),
+            ["invalid escape sequence: 'z' is not a hex digit"]
+        );
+        assert_eq!(
+            name_messages(r#"nameid 9 "\d83d!";"#),
+            ["unpaired UTF-16 surrogate"]
+        );
+        assert_eq!(
+            name_messages(r#"nameid 9 "\de00";"#),
+            ["unpaired UTF-16 surrogate"]
+        );
+        assert_eq!(
+            name_messages(r#"nameid 9 "\d83d";"#),
+            ["unpaired UTF-16 surrogate"]
+        );
+        assert_eq!(
+            name_messages(r#"nameid 9 1 "\f";"#),
+            ["mac escape sequences must be two hex digits long"]
+        );
+        assert_eq!(
+            name_messages(r#"nameid 9 1 "caf\8e ☃";"#),
+            ["'☃' cannot be represented in the Mac Roman encoding"]
+        );
+    }
+
+    #[test]
+    fn deprecated_language_keywords() {
+        let messages = validation_messages(
+            "
+feature locl {
+    script latn;
+    language TRK excludeDFLT;
+    sub a by b;
+    language ROM includeDFLT;
+    sub c by d;
+    language MOL exclude_dflt;
+    sub e by f;
+} locl;
+",
+        );
+        assert_eq!(
+            messages,
+            ["'excludeDFLT' is deprecated", "'includeDFLT' is deprecated",]
+        );
+    }
+
+    #[test]
+    fn deprecated_language_keyword_fixes() {
+        let fea = "feature locl { script latn; language TRK excludeDFLT; sub a by b; } locl;";
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let tree = crate::util::ttx::parse_str(fea, &glyph_map);
+        let fixes = crate::compile::validation_diagnostics(&tree, &glyph_map, &Default::default())
+            .iter()
+            .filter_map(|diag| diag.fix().cloned())
+            .collect::<Vec<_>>();
+        assert_eq!(fixes.len(), 1);
+        let edited = tree.apply_edits(fixes).unwrap();
+        let [text] = edited.values().collect::<Vec<_>>()[..] else {
+            panic!("expected one edited file");
+        };
+        assert_eq!(text, &fea.replace("excludeDFLT", "exclude_dflt"));
+    }
+
+    #[test]
+    fn parameters_statements() {
+        assert!(validation_messages("feature size { parameters 10.0 0; } size;").is_empty());
+        assert_eq!(
+            validation_messages("feature size { parameters 10.0 3 80; } size;"),
+            ["size 'parameters' must have two or four values"]
+        );
+        assert_eq!(
+            validation_messages("feature size { parameters 10.0 3.5 80 120; } size;"),
+            ["subfamily identifier must be an integer"]
+        );
+        assert_eq!(
+            validation_messages("feature liga { parameters 1; parameters 2 3; } liga;"),
+            ["feature can have only one 'parameters' statement"]
+        );
+    }
+
+    #[test]
+    fn unicode_name_codepoints() {
+        assert_eq!(unicode_name_codepoint("uni0041"), Some(0x41));
+        assert_eq!(unicode_name_codepoint("u1F600"), Some(0x1F600));
+        assert_eq!(unicode_name_codepoint("u0041"), Some(0x41));
+        assert_eq!(unicode_name_codepoint("uni0041.sc"), None);
+        assert_eq!(unicode_name_codepoint("uni00e9"), None);
+        assert_eq!(unicode_name_codepoint("uniD800"), None);
+        assert_eq!(unicode_name_codepoint("u110000"), None);
+        assert_eq!(unicode_name_codepoint("uni00410042"), None);
+        assert_eq!(unicode_name_codepoint("union"), None);
+    }
+
+    #[test]
+    fn check_unicode_names() {
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let gid = |name: &str| glyph_map.get(name).unwrap();
+        let fea = "
+feature test {
+    sub uni0327 by cedilla;
+    sub [uni0327 uni0328] by ogonek;
+} test;
+";
+        let opts = crate::compile::Opts::new().check_unicode_names([(0x327, gid("cedilla"))]);
+        assert_eq!(
+            validation_messages_with_opts(fea, &opts),
+            [
+                "'uni0327' is named for U+0327, but the cmap maps it to 'cedilla'",
+                "'uni0328' is named for U+0328, which is not in the cmap",
+            ]
+        );
+        let opts = crate::compile::Opts::new()
+            .check_unicode_names([(0x327, gid("uni0327")), (0x328, gid("uni0328"))]);
+        assert!(validation_messages_with_opts(fea, &opts).is_empty());
+    }
 }
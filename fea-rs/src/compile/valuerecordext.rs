@@ -5,7 +5,10 @@ use write_fonts::tables::gpos::{ValueFormat, ValueRecord};
 pub(crate) trait ValueRecordExt {
     fn clear_zeros(self) -> Self;
     fn for_pair_pos(self, in_vert_feature: bool) -> Self;
+    fn with_rtl_placement(self) -> Self;
     fn is_all_zeros(&self) -> bool;
+    fn effective_format(&self) -> ValueFormat;
+    fn retain_format(self, format: ValueFormat) -> Self;
 }
 
 impl ValueRecordExt for ValueRecord {
@@ -49,6 +52,28 @@ impl ValueRecordExt for ValueRecord {
         all_values.iter().all(|v| v.unwrap_or_default() == 0)
     }
 
+    /// The format of this record, ignoring any fields that are zero
+    fn effective_format(&self) -> ValueFormat {
+        self.clone().clear_zeros().format()
+    }
+
+    /// Clear any non-device fields that are not in `format`
+    fn retain_format(mut self, format: ValueFormat) -> Self {
+        if !format.contains(ValueFormat::X_PLACEMENT) {
+            self.x_placement = None;
+        }
+        if !format.contains(ValueFormat::Y_PLACEMENT) {
+            self.y_placement = None;
+        }
+        if !format.contains(ValueFormat::X_ADVANCE) {
+            self.x_advance = None;
+        }
+        if !format.contains(ValueFormat::Y_ADVANCE) {
+            self.y_advance = None;
+        }
+        self
+    }
+
     // Modify this value record for the special requirements of pairpos lookups
     //
     // In pair pos tables, if a value record is all zeros (but not null) then
@@ -66,4 +91,15 @@ impl ValueRecordExt for ValueRecord {
         }
         out
     }
+
+    // Apply a non-zero x advance to the x placement as well.
+    //
+    // This is the right-to-left kerning convention; see
+    // `Opts::rtl_pair_pos_placement`.
+    fn with_rtl_placement(mut self) -> Self {
+        if self.x_advance.unwrap_or(0) != 0 {
+            self.x_placement = self.x_advance;
+        }
+        self
+    }
 }
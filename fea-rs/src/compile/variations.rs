@@ -0,0 +1,692 @@
+//! Variable metrics, and the item variation store they are compiled into.
+//!
+//! A variable metric gives a value at each of a number of locations in the
+//! font's design space, such as `(wght=200:100 wght=900:150)`. These are
+//! compiled to a default value, and a set of deltas stored in the GDEF item
+//! variation store. The deltas are computed in the same way as fontTools'
+//! `VariationModel`.
+
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use write_fonts::{
+    tables::{
+        layout::{DeltaFormat, Device},
+        variations::{
+            ItemVariationData, ItemVariationStore, RegionAxisCoordinates, VariationRegion,
+            VariationRegionList,
+        },
+    },
+    types::{F2Dot14, Tag},
+};
+
+use super::error::InvalidAxisError;
+
+/// A variation axis of the font being compiled.
+///
+/// The locations in variable metrics are given in user coordinates, and are
+/// normalized using the range of each axis; `avar` mappings are not applied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VariationAxis {
+    tag: Tag,
+    min: f32,
+    default: f32,
+    max: f32,
+}
+
+/// A location in user coordinates, and the value of a metric at it.
+pub(crate) type Master = (Vec<(Tag, f32)>, i16);
+
+// a normalized location, with a coordinate (as F2Dot14 bits) for each axis
+type Location = Vec<i16>;
+
+// a region, with a (start, peak, end) triple for each axis
+type Region = Vec<[i16; 3]>;
+
+// the non-zero deltas of an item, by region index
+type DeltaSet = Vec<(u16, i16)>;
+
+// the number of non-zero axes, how many of those are on-point, the axes,
+// and the sign and magnitude of each coordinate
+type SortKey = (usize, Reverse<usize>, Vec<usize>, Vec<i16>, Vec<u16>);
+
+// items are grouped into subtables, each of which can hold this many
+const MAX_ITEMS_PER_SUBTABLE: usize = u16::MAX as usize;
+
+impl VariationAxis {
+    /// Create a new axis, with its range in user coordinates.
+    ///
+    /// This returns an error if any value is not finite, or if they are not
+    /// ordered as `min <= default <= max`.
+    pub fn new(tag: Tag, min: f32, default: f32, max: f32) -> Result<Self, InvalidAxisError> {
+        if ![min, default, max].iter().all(|v| v.is_finite()) || min > default || default > max {
+            return Err(InvalidAxisError {
+                tag,
+                min,
+                default,
+                max,
+            });
+        }
+        Ok(VariationAxis {
+            tag,
+            min,
+            default,
+            max,
+        })
+    }
+
+    /// The axis tag, such as `wght`.
+    pub fn tag(&self) -> Tag {
+        self.tag
+    }
+
+    /// The minimum value of the axis, in user coordinates.
+    pub fn min(&self) -> f32 {
+        self.min
+    }
+
+    /// The default value of the axis, in user coordinates.
+    pub fn default(&self) -> f32 {
+        self.default
+    }
+
+    /// The maximum value of the axis, in user coordinates.
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    /// Map a user coordinate to a normalized coordinate, as F2Dot14 bits.
+    fn normalize(&self, value: f32) -> i16 {
+        let value = value.clamp(self.min, self.max);
+        let normalized = if value < self.default {
+            (value - self.default) / (self.default - self.min)
+        } else if value > self.default {
+            (value - self.default) / (self.max - self.default)
+        } else {
+            0.0
+        };
+        F2Dot14::from_f32(normalized).to_bits()
+    }
+}
+
+/// Parse the contents of a variable metric.
+///
+/// This is a list of locations and values, separated by whitespace; each
+/// location is a list of `axis=value` pairs separated by commas, such as
+/// `wght=200:100 wght=900,wdth=150:120`.
+pub(crate) fn parse_variable_metric(text: &str) -> Result<Vec<Master>, String> {
+    let masters = text
+        .split_ascii_whitespace()
+        .map(parse_master)
+        .collect::<Result<Vec<_>, _>>()?;
+    if masters.is_empty() {
+        return Err("expected at least one location and value, like 'wght=200:100'".into());
+    }
+    Ok(masters)
+}
+
+fn parse_master(item: &str) -> Result<Master, String> {
+    let expected = || format!("expected a location and value, like 'wght=200:100', found '{item}'");
+    let (location, value) = item.split_once(':').ok_or_else(expected)?;
+    let value = value.parse::<i16>().map_err(|_| {
+        format!(
+            "value '{value}' is out of range: expected a number from {} to {}",
+            i16::MIN,
+            i16::MAX
+        )
+    })?;
+    let mut coords = Vec::new();
+    for coord in location.split(',') {
+        let (tag, coord) = coord.split_once('=').ok_or_else(expected)?;
+        let tag = tag
+            .parse::<Tag>()
+            .map_err(|_| format!("invalid axis tag '{tag}'"))?;
+        let coord = coord
+            .parse::<f32>()
+            .ok()
+            .filter(|coord| coord.is_finite())
+            .ok_or_else(expected)?;
+        if coords.iter().any(|(prev, _)| *prev == tag) {
+            return Err(format!("axis '{tag}' appears more than once in '{item}'"));
+        }
+        coords.push((tag, coord));
+    }
+    Ok((coords, value))
+}
+
+/// Collects the deltas of variable metrics, to build an item variation store.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct VariationStoreBuilder {
+    axes: Vec<VariationAxis>,
+    regions: Vec<Region>,
+    region_ids: HashMap<Region, u16>,
+    delta_sets: Vec<DeltaSet>,
+    delta_set_ids: HashMap<DeltaSet, usize>,
+    // a hash of the delta sets added so far, which determine the indices of
+    // any that are added later
+    digest: u64,
+}
+
+impl VariationStoreBuilder {
+    pub(crate) fn new(axes: Vec<VariationAxis>) -> Self {
+        VariationStoreBuilder {
+            axes,
+            ..Default::default()
+        }
+    }
+
+    /// A hash of the contents of the store so far.
+    pub(crate) fn digest(&self) -> u64 {
+        self.digest
+    }
+
+    /// Add a variable metric.
+    ///
+    /// Returns the value at the default location, and the VariationIndex
+    /// table for the deltas, if the metric varies.
+    pub(crate) fn add_metric(
+        &mut self,
+        masters: &[Master],
+    ) -> Result<(i16, Option<Device>), String> {
+        if self.axes.is_empty() {
+            return Err("variable metrics require the variation axes of the font".into());
+        }
+        let mut locations = Vec::with_capacity(masters.len());
+        for (coords, _) in masters {
+            let mut location = vec![0; self.axes.len()];
+            for (tag, coord) in coords {
+                let Some(idx) = self.axes.iter().position(|axis| axis.tag == *tag) else {
+                    return Err(format!("'{tag}' is not a variation axis of this font"));
+                };
+                location[idx] = self.axes[idx].normalize(*coord);
+            }
+            if locations.contains(&location) {
+                return Err("the same location appears more than once".into());
+            }
+            locations.push(location);
+        }
+
+        let model = VariationModel::new(locations)?;
+        let values = masters.iter().map(|(_, value)| *value).collect::<Vec<_>>();
+        let (default, deltas) = model.deltas(&values)?;
+
+        let mut delta_set = Vec::new();
+        for (region, delta) in model.regions().zip(deltas) {
+            if delta != 0 {
+                delta_set.push((self.region_id(region), delta));
+            }
+        }
+        if delta_set.is_empty() {
+            return Ok((default, None));
+        }
+        delta_set.sort_unstable();
+        let id = match self.delta_set_ids.get(&delta_set) {
+            Some(id) => *id,
+            None => {
+                let id = self.delta_sets.len();
+                let mut hasher = DefaultHasher::new();
+                (self.digest, &delta_set).hash(&mut hasher);
+                self.digest = hasher.finish();
+                self.delta_set_ids.insert(delta_set.clone(), id);
+                self.delta_sets.push(delta_set);
+                id
+            }
+        };
+        let outer = (id / MAX_ITEMS_PER_SUBTABLE) as u16;
+        let inner = (id % MAX_ITEMS_PER_SUBTABLE) as u16;
+        Ok((default, Some(variation_index(outer, inner))))
+    }
+
+    fn region_id(&mut self, region: &Region) -> u16 {
+        if let Some(id) = self.region_ids.get(region) {
+            return *id;
+        }
+        let id = self.regions.len() as u16;
+        self.regions.push(region.clone());
+        self.region_ids.insert(region.clone(), id);
+        id
+    }
+
+    pub(crate) fn build(&self) -> Option<ItemVariationStore> {
+        if self.delta_sets.is_empty() {
+            return None;
+        }
+        let regions = self
+            .regions
+            .iter()
+            .map(|region| {
+                VariationRegion::new(
+                    region
+                        .iter()
+                        .map(|coords| {
+                            let [start, peak, end] = coords.map(F2Dot14::from_bits);
+                            RegionAxisCoordinates::new(start, peak, end)
+                        })
+                        .collect(),
+                )
+            })
+            .collect();
+        let n_regions = self.regions.len() as u16;
+        // every delta is stored as a 16-bit word, for every region
+        let subtables = self
+            .delta_sets
+            .chunks(MAX_ITEMS_PER_SUBTABLE)
+            .map(|delta_sets| {
+                let mut data = Vec::with_capacity(delta_sets.len() * n_regions as usize * 2);
+                for delta_set in delta_sets {
+                    let mut row = vec![0i16; n_regions as usize];
+                    for (region, delta) in delta_set {
+                        row[*region as usize] = *delta;
+                    }
+                    data.extend(row.iter().flat_map(|delta| delta.to_be_bytes()));
+                }
+                Some(ItemVariationData::new(
+                    delta_sets.len() as u16,
+                    n_regions,
+                    (0..n_regions).collect(),
+                    data,
+                ))
+            })
+            .collect();
+        Some(ItemVariationStore::new(
+            1,
+            VariationRegionList::new(regions),
+            subtables,
+        ))
+    }
+}
+
+/// A VariationIndex table, which shares its layout with a device table.
+fn variation_index(outer: u16, inner: u16) -> Device {
+    Device {
+        start_size: outer,
+        end_size: inner,
+        delta_format: DeltaFormat::VariationIndex,
+        delta_value: Vec::new(),
+    }
+}
+
+/// The regions and weights used to compute deltas for a set of locations.
+///
+/// This is a port of fontTools' `VariationModel`.
+struct VariationModel {
+    // the masters, sorted so that each master only depends on earlier ones
+    order: Vec<usize>,
+    supports: Vec<Region>,
+    // the weight of each earlier delta at the location of each master
+    weights: Vec<Vec<(usize, f64)>>,
+}
+
+impl VariationModel {
+    fn new(locations: Vec<Location>) -> Result<Self, String> {
+        if !locations
+            .iter()
+            .any(|loc| loc.iter().all(|coord| *coord == 0))
+        {
+            return Err("variable metric has no value at the default location".into());
+        }
+        let mut order = (0..locations.len()).collect::<Vec<_>>();
+        let key = sort_key(&locations);
+        order.sort_by(|a, b| key(&locations[*a]).cmp(&key(&locations[*b])));
+        let locations = order
+            .iter()
+            .map(|idx| locations[*idx].clone())
+            .collect::<Vec<_>>();
+
+        let supports = master_supports(&locations);
+        let weights = locations
+            .iter()
+            .enumerate()
+            .map(|(i, loc)| {
+                supports[..i]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(j, support)| {
+                        let scalar = support_scalar(loc, support);
+                        (scalar != 0.0).then_some((j, scalar))
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(VariationModel {
+            order,
+            supports,
+            weights,
+        })
+    }
+
+    /// The regions of the deltas, excluding the default master.
+    fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.supports.iter().skip(1)
+    }
+
+    /// Compute the default value, and the delta for each region.
+    fn deltas(&self, values: &[i16]) -> Result<(i16, Vec<i16>), String> {
+        let mut out: Vec<f64> = Vec::with_capacity(values.len());
+        for (idx, weights) in self.order.iter().zip(&self.weights) {
+            let mut delta = values[*idx] as f64;
+            for (j, weight) in weights {
+                delta -= out[*j] * weight;
+            }
+            out.push(delta);
+        }
+        let mut deltas = out.into_iter().map(|delta| {
+            let rounded = (delta + 0.5).floor();
+            i16::try_from(rounded as i64)
+                .map_err(|_| format!("variation delta {rounded} is out of range"))
+        });
+        let default = deltas.next().expect("default is always present")?;
+        Ok((default, deltas.collect::<Result<_, _>>()?))
+    }
+}
+
+/// The sort key for a location, from fontTools' `getMasterLocationsSortKeyFunc`.
+///
+/// Locations with fewer non-zero axes come first; of those, locations on
+/// a single axis come before others.
+fn sort_key(locations: &[Location]) -> impl Fn(&Location) -> SortKey {
+    // the coordinates of locations on a single axis, by axis
+    let mut axis_points: HashMap<usize, Vec<i16>> = HashMap::new();
+    for loc in locations {
+        let mut axes = loc.iter().enumerate().filter(|(_, coord)| **coord != 0);
+        if let (Some((axis, coord)), None) = (axes.next(), axes.next()) {
+            axis_points
+                .entry(axis)
+                .or_insert_with(|| vec![0])
+                .push(*coord);
+        }
+    }
+    move |loc: &Location| {
+        let axes = loc
+            .iter()
+            .enumerate()
+            .filter(|(_, coord)| **coord != 0)
+            .collect::<Vec<_>>();
+        let on_point = axes
+            .iter()
+            .filter(|(axis, coord)| {
+                axis_points
+                    .get(axis)
+                    .map(|points| points.contains(coord))
+                    .unwrap_or(false)
+            })
+            .count();
+        (
+            axes.len(),
+            Reverse(on_point),
+            axes.iter().map(|(axis, _)| *axis).collect::<Vec<_>>(),
+            axes.iter()
+                .map(|(_, coord)| coord.signum())
+                .collect::<Vec<_>>(),
+            axes.iter()
+                .map(|(_, coord)| coord.unsigned_abs())
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// The region of influence of each master, from fontTools'
+/// `_computeMasterSupports`.
+fn master_supports(locations: &[Location]) -> Vec<Region> {
+    let n_axes = locations.first().map(Vec::len).unwrap_or_default();
+    let mut min = vec![0i16; n_axes];
+    let mut max = vec![0i16; n_axes];
+    for loc in locations {
+        for (axis, coord) in loc.iter().enumerate() {
+            min[axis] = min[axis].min(*coord);
+            max[axis] = max[axis].max(*coord);
+        }
+    }
+
+    let mut regions = locations
+        .iter()
+        .map(|loc| {
+            loc.iter()
+                .enumerate()
+                .map(|(axis, coord)| match *coord {
+                    0 => [0, 0, 0],
+                    c if c > 0 => [0, c, max[axis]],
+                    c => [min[axis], c, 0],
+                })
+                .collect::<Region>()
+        })
+        .collect::<Vec<_>>();
+
+    let axes_of = |region: &Region| {
+        region
+            .iter()
+            .map(|[_, peak, _]| *peak != 0)
+            .collect::<Vec<_>>()
+    };
+    for i in 0..regions.len() {
+        let loc_axes = axes_of(&regions[i]);
+        for prev in 0..i {
+            let prev_region = &regions[prev];
+            // masters with different axes do not participate
+            if axes_of(prev_region) != loc_axes {
+                continue;
+            }
+            // and nor do masters outside of the current box
+            let relevant = regions[i].iter().zip(prev_region).all(
+                |([lower, peak, upper], [_, prev_peak, _])| {
+                    prev_peak == peak || (lower < prev_peak && prev_peak < upper)
+                },
+            );
+            if !relevant {
+                continue;
+            }
+
+            // split the box for the new master, in whichever direction has
+            // the largest range ratio.
+            let mut best_axes = Vec::new();
+            let mut best_ratio = -1.0;
+            for (axis, [_, prev_peak, _]) in prev_region.iter().enumerate() {
+                if *prev_peak == 0 {
+                    continue;
+                }
+                let val = *prev_peak as f64;
+                let [lower, peak, upper] = regions[i][axis];
+                let (new_lower, new_upper, ratio) = if val < peak as f64 {
+                    (
+                        *prev_peak,
+                        upper,
+                        (val - peak as f64) / (lower as f64 - peak as f64),
+                    )
+                } else if (peak as f64) < val {
+                    (
+                        lower,
+                        *prev_peak,
+                        (val - peak as f64) / (upper as f64 - peak as f64),
+                    )
+                } else {
+                    continue;
+                };
+                if ratio > best_ratio {
+                    best_axes.clear();
+                    best_ratio = ratio;
+                }
+                if ratio == best_ratio {
+                    best_axes.push((axis, [new_lower, peak, new_upper]));
+                }
+            }
+            for (axis, triple) in best_axes {
+                regions[i][axis] = triple;
+            }
+        }
+    }
+    regions
+}
+
+/// The scalar of a region at a location, from fontTools' `supportScalar`.
+fn support_scalar(loc: &Location, support: &Region) -> f64 {
+    let mut scalar = 1.0;
+    for (coord, [lower, peak, upper]) in loc.iter().zip(support) {
+        if *peak == 0 || lower > peak || peak > upper || (*lower < 0 && *upper > 0) {
+            continue;
+        }
+        let (v, lower, peak, upper) = (*coord as f64, *lower as f64, *peak as f64, *upper as f64);
+        if v == peak {
+            continue;
+        }
+        if v <= lower || upper <= v {
+            return 0.0;
+        }
+        if v < peak {
+            scalar *= (v - lower) / (peak - lower);
+        } else {
+            scalar *= (v - upper) / (peak - upper);
+        }
+    }
+    scalar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wght() -> VariationAxis {
+        VariationAxis::new(Tag::new(b"wght"), 100.0, 400.0, 900.0).unwrap()
+    }
+
+    fn wdth() -> VariationAxis {
+        VariationAxis::new(Tag::new(b"wdth"), 50.0, 100.0, 200.0).unwrap()
+    }
+
+    #[test]
+    fn invalid_axes() {
+        let tag = Tag::new(b"wght");
+        assert!(VariationAxis::new(tag, 900.0, 400.0, 100.0).is_err());
+        assert!(VariationAxis::new(tag, 100.0, 50.0, 900.0).is_err());
+        assert!(VariationAxis::new(tag, f32::NAN, 400.0, 900.0).is_err());
+        assert!(VariationAxis::new(tag, 100.0, 400.0, f32::INFINITY).is_err());
+        assert!(VariationAxis::new(tag, 400.0, 400.0, 400.0).is_ok());
+    }
+
+    #[test]
+    fn parse() {
+        let masters = parse_variable_metric(" wght=400:10  wght=900,wdth=50:-20 ").unwrap();
+        assert_eq!(
+            masters,
+            [
+                (vec![(Tag::new(b"wght"), 400.0)], 10),
+                (
+                    vec![(Tag::new(b"wght"), 900.0), (Tag::new(b"wdth"), 50.0)],
+                    -20
+                ),
+            ]
+        );
+        assert!(parse_variable_metric("").is_err());
+        assert!(parse_variable_metric("wght=400").is_err());
+        assert!(parse_variable_metric("wght:400").is_err());
+        assert!(parse_variable_metric("wght=400:40000").is_err());
+        assert!(parse_variable_metric("wght=400,wght=500:1").is_err());
+        assert!(parse_variable_metric("toolong=400:1").is_err());
+    }
+
+    #[test]
+    fn normalize() {
+        let axis = wght();
+        assert_eq!(axis.normalize(400.0), 0);
+        assert_eq!(axis.normalize(900.0), 1 << 14);
+        assert_eq!(axis.normalize(100.0), -(1 << 14));
+        assert_eq!(axis.normalize(650.0), 1 << 13);
+        assert_eq!(axis.normalize(1000.0), 1 << 14);
+    }
+
+    #[test]
+    fn deltas_on_one_axis() {
+        let mut store = VariationStoreBuilder::new(vec![wght()]);
+        let masters = parse_variable_metric("wght=400:100 wght=100:80 wght=900:150").unwrap();
+        let (default, device) = store.add_metric(&masters).unwrap();
+        assert_eq!(default, 100);
+        let device = device.unwrap();
+        assert_eq!((device.start_size, device.end_size), (0, 0));
+        assert_eq!(store.delta_sets, [vec![(0, -20), (1, 50)]]);
+        assert_eq!(
+            store.regions,
+            [
+                vec![[-(1 << 14), -(1 << 14), 0]],
+                vec![[0, 1 << 14, 1 << 14]]
+            ]
+        );
+
+        // an identical metric reuses the delta set
+        let (_, device) = store.add_metric(&masters).unwrap();
+        assert_eq!(device.unwrap().end_size, 0);
+        // and one that doesn't vary has no device
+        let masters = parse_variable_metric("wght=400:100 wght=900:100").unwrap();
+        assert_eq!(store.add_metric(&masters).unwrap(), (100, None));
+    }
+
+    #[test]
+    fn intermediate_and_corner_masters() {
+        let mut store = VariationStoreBuilder::new(vec![wght(), wdth()]);
+        let masters = parse_variable_metric(
+            "wght=400:0 wght=900:100 wght=650:60 wdth=200:10 wght=900,wdth=200:130",
+        )
+        .unwrap();
+        let (default, _) = store.add_metric(&masters).unwrap();
+        assert_eq!(default, 0);
+        let half = 1 << 13;
+        let one = 1 << 14;
+        assert_eq!(
+            store.regions,
+            [
+                vec![[0, half, one], [0, 0, 0]],
+                vec![[half, one, one], [0, 0, 0]],
+                vec![[0, 0, 0], [0, one, one]],
+                vec![[0, one, one], [0, one, one]],
+            ]
+        );
+        // the intermediate master splits the region of the one after it,
+        // and the corner is 20 more than the sum of the deltas on each axis
+        assert_eq!(
+            store.delta_sets,
+            [vec![(0, 60), (1, 100), (2, 10), (3, 20)]]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut store = VariationStoreBuilder::new(Vec::new());
+        let masters = parse_variable_metric("wght=400:100 wght=900:150").unwrap();
+        assert!(store.add_metric(&masters).is_err());
+
+        let mut store = VariationStoreBuilder::new(vec![wght()]);
+        let masters = parse_variable_metric("wght=500:100 wght=900:150").unwrap();
+        assert_eq!(
+            store.add_metric(&masters).unwrap_err(),
+            "variable metric has no value at the default location"
+        );
+        let masters = parse_variable_metric("wght=400:100 opsz=12:150").unwrap();
+        assert_eq!(
+            store.add_metric(&masters).unwrap_err(),
+            "'opsz' is not a variation axis of this font"
+        );
+        let masters = parse_variable_metric("wght=400:100 wght=900:150 wght=1000:160").unwrap();
+        assert_eq!(
+            store.add_metric(&masters).unwrap_err(),
+            "the same location appears more than once"
+        );
+    }
+
+    #[test]
+    fn build_store() {
+        let mut store = VariationStoreBuilder::new(vec![wght()]);
+        let masters = parse_variable_metric("wght=400:100 wght=900:150").unwrap();
+        store.add_metric(&masters).unwrap();
+        let masters = parse_variable_metric("wght=400:100 wght=100:-300").unwrap();
+        let (_, device) = store.add_metric(&masters).unwrap();
+        assert_eq!(device.unwrap().end_size, 1);
+
+        let built = store.build().unwrap();
+        assert_eq!(built.variation_region_list.variation_regions.len(), 2);
+        let data = built.item_variation_datas[0].as_ref().unwrap();
+        assert_eq!(data.item_count, 2);
+        assert_eq!(data.region_indexes, [0, 1]);
+        assert_eq!(data.delta_sets, [0, 50, 0, 0, 0, 0, 0xfe, 0x70]);
+    }
+}
@@ -1,5 +1,5 @@
 //! Reporting errors, warnings, and other information to the user.
-use crate::parse::FileId;
+use crate::parse::{FileId, TextEdit};
 use std::{convert::TryInto, ops::Range};
 
 /// A span of a source file.
@@ -30,7 +30,7 @@ pub struct Message {
 }
 
 /// A diagnostic, including a message and additional annotations
-//TODO: would this be more useful with additional annotations or a help field?
+//TODO: would this be more useful with additional annotations?
 //some fancy error reporting crates have these.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Diagnostic {
@@ -38,6 +38,8 @@ pub struct Diagnostic {
     pub message: Message,
     /// The diagnostic level
     pub level: Level,
+    help: Option<String>,
+    fix: Option<TextEdit>,
 }
 
 impl Span {
@@ -65,9 +67,23 @@ impl Diagnostic {
                 file,
             },
             level,
+            help: None,
+            fix: None,
         }
     }
 
+    /// Attach a help message to this diagnostic
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Attach an edit to the source that addresses this diagnostic
+    pub fn with_fix(mut self, fix: TextEdit) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+
     /// Create a new error, at the provided location
     pub fn error(file: FileId, span: Range<usize>, message: impl Into<String>) -> Self {
         Diagnostic::new(Level::Error, file, span, message)
@@ -83,6 +99,16 @@ impl Diagnostic {
         &self.message.text
     }
 
+    /// The diagnostic's help text, if any
+    pub fn help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+
+    /// The edit that addresses this diagnostic, if any
+    pub fn fix(&self) -> Option<&TextEdit> {
+        self.fix.as_ref()
+    }
+
     /// The location of the main span, as a `Range<usize>`
     pub fn span(&self) -> Range<usize> {
         self.message.span.range()
@@ -2,6 +2,16 @@
 //!
 //! The main entry point for this crate is the [`Compiler`] struct, which provides
 //! a builder-like interface for compiliing from source.
+//!
+//! The earlier stages of compilation are also available on their own: a
+//! source can be parsed with [`parse_root_file`], [`parse_root`] (which accepts
+//! a custom [`SourceResolver`][parse::SourceResolver]) or [`parse_string`],
+//! and the resulting [`ParseTree`] can then be checked with [`validate`], or
+//! validated and compiled with [`compile()`].
+//!
+//! Each stage logs its timing with the [`log`] crate: a summary for each stage
+//! at the `debug` level, and the time taken by each source file, top-level
+//! block and lookup at the `trace` level.
 
 #![deny(missing_docs)]
 
@@ -15,8 +25,10 @@ pub mod util;
 #[cfg(test)]
 mod tests;
 
-pub use common::{GlyphIdent, GlyphMap, GlyphName};
-pub use compile::Compiler;
+pub use common::{AliasError, GlyphIdent, GlyphMap, GlyphMapUpdate, GlyphName};
+pub use compile::{compile, validate, Compiler};
 pub use diagnostic::{Diagnostic, Level};
-pub use parse::{ParseTree, TokenSet};
-pub use token_tree::{typed, Kind, Node, NodeOrToken, Token};
+pub use parse::{parse_root, parse_root_file, parse_string, ParseTree, TokenSet};
+pub use token_tree::{
+    typed, AttachedComments, CommentedStatement, Cursor, Kind, Node, NodeOrToken, Token, TreeFormat,
+};
@@ -3,20 +3,26 @@
 //! In general, you should not need to use this module directly; it is exposed
 //! so that it can be used for things like syntax highlighting.
 
+mod completion;
 mod context;
+mod edit;
 pub(crate) mod grammar;
 mod lexer;
 mod parser;
 mod source;
 mod tree;
+mod xref;
 
 use std::{ffi::OsString, path::PathBuf, sync::Arc};
 
+pub use completion::{CompletionContext, Expected};
+pub use edit::{EditError, TextEdit};
 pub use lexer::TokenSet;
-pub use source::{FileSystemResolver, SourceLoadError, SourceResolver};
+pub use source::{FileSystemResolver, LineCol, LineIndex, SourceLoadError, SourceResolver};
 pub use tree::ParseTree;
+pub use xref::{Location, Symbol, XrefIndex};
 
-pub(crate) use context::{IncludeStatement, ParseContext};
+pub(crate) use context::{IncludeStatement, ParseContext, DEFAULT_MAX_INCLUDE_DEPTH};
 pub(crate) use parser::Parser;
 pub(crate) use source::{FileId, Source, SourceList, SourceMap};
 
@@ -69,6 +75,14 @@ pub fn parse_root(
         .map(|ctx| ctx.generate_parse_tree())
 }
 
+/// Tokenize a block of FEA without parsing it, returning the number of tokens.
+///
+/// This only exists so that the lexer can be benchmarked on its own.
+#[doc(hidden)]
+pub fn count_tokens(text: &str) -> usize {
+    lexer::iter_tokens(text).count()
+}
+
 /// Convenience method to parse a block of FEA from memory.
 ///
 /// This is useful for things like testing or syntax highlighting of a single file,
@@ -0,0 +1,395 @@
+//! Working out what can be written at a position, for editor completions.
+
+use std::ops::Range;
+
+use super::lexer::{keyword_spellings, Kind, TokenSet};
+use crate::{token_tree::Kind as AstKind, Node, Token};
+
+/// Something that may be written at a position in a feature file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Expected {
+    /// A keyword, in one of its spellings.
+    Keyword(&'static str),
+    /// A glyph name or CID.
+    Glyph,
+    /// A named glyph class.
+    GlyphClass,
+    /// A mark class.
+    MarkClass,
+    /// The name of a lookup.
+    Lookup,
+    /// A script, language, feature or table tag.
+    Tag,
+}
+
+/// What can be written at a position in a feature file.
+///
+/// This is intended to drive completions in an editor. It describes the kinds
+/// of item that are valid at the position, but not which names exist; those
+/// can be found with an [`XrefIndex`].
+///
+/// The context is worked out from the tokens before the position, so it is
+/// still useful when the statement being written does not yet parse.
+///
+/// [`XrefIndex`]: super::XrefIndex
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompletionContext {
+    /// The range of the partially written word that ends at the position.
+    ///
+    /// A completion should replace this range. It is empty if the position is
+    /// not at the end of a word.
+    pub range: Range<usize>,
+    /// Everything that may be written here.
+    pub expected: Vec<Expected>,
+}
+
+/// The kind of block containing a position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Block {
+    Feature,
+    Lookup,
+    Table,
+    Other,
+}
+
+const LOOKUP_FLAGS: TokenSet = TokenSet::new(&[
+    Kind::RightToLeftKw,
+    Kind::IgnoreBaseGlyphsKw,
+    Kind::IgnoreLigaturesKw,
+    Kind::IgnoreMarksKw,
+    Kind::MarkAttachmentTypeKw,
+    Kind::UseMarkFilteringSetKw,
+]);
+
+const LANGUAGE_OPTIONS: TokenSet =
+    TokenSet::new(&[Kind::ExcludeDfltKw, Kind::IncludeDfltKw, Kind::RequiredKw]);
+
+impl CompletionContext {
+    /// Determine what can be written at `offset` in this node, such as one
+    /// returned by [`parse_string`].
+    ///
+    /// [`parse_string`]: super::parse_string
+    pub fn new(root: &Node, offset: usize) -> Self {
+        let mut blocks = Vec::new();
+        // the block closed by the most recent '}', until the end of its label
+        let mut closed = None;
+        let mut statement: Vec<&Token> = Vec::new();
+        let mut word = None;
+        for (range, token) in root.iter_tokens_with_ranges() {
+            if range.start >= offset {
+                break;
+            }
+            if range.end >= offset {
+                match token.kind {
+                    AstKind::Comment | AstKind::String | AstKind::StringUnterminated => {
+                        return CompletionContext {
+                            range: offset..offset,
+                            expected: Vec::new(),
+                        }
+                    }
+                    _ if is_word(token) => {
+                        word = Some((range.start..offset, token));
+                        break;
+                    }
+                    _ => (),
+                }
+            }
+            match token.kind {
+                kind if kind.is_trivia() => (),
+                AstKind::LBrace => {
+                    blocks.push(block_kind(&statement));
+                    statement.clear();
+                }
+                AstKind::RBrace => {
+                    closed = blocks.pop();
+                    statement.clear();
+                }
+                AstKind::Semi => {
+                    closed = None;
+                    statement.clear();
+                }
+                _ => statement.push(token),
+            }
+        }
+
+        let mut expected = expected(blocks.last().copied(), closed, &statement);
+        let range = match word {
+            Some((range, token)) => {
+                if token.text.starts_with('@') {
+                    expected
+                        .retain(|item| matches!(item, Expected::GlyphClass | Expected::MarkClass));
+                }
+                range
+            }
+            None => offset..offset,
+        };
+        CompletionContext { range, expected }
+    }
+}
+
+fn is_word(token: &Token) -> bool {
+    token
+        .text
+        .starts_with(|c: char| c.is_alphanumeric() || "@\\_.".contains(c))
+}
+
+fn block_kind(statement: &[&Token]) -> Block {
+    match statement.first().map(|token| token.kind) {
+        Some(AstKind::FeatureKw) => Block::Feature,
+        Some(AstKind::LookupKw) => Block::Lookup,
+        Some(AstKind::TableKw) => Block::Table,
+        _ => Block::Other,
+    }
+}
+
+fn keywords(set: TokenSet) -> Vec<Expected> {
+    keyword_spellings(set).map(Expected::Keyword).collect()
+}
+
+fn glyphs() -> Vec<Expected> {
+    vec![Expected::Glyph, Expected::GlyphClass]
+}
+
+fn expected(block: Option<Block>, closed: Option<Block>, statement: &[&Token]) -> Vec<Expected> {
+    // the label after a closing brace
+    if let Some(closed) = closed {
+        return match closed {
+            _ if !statement.is_empty() => Vec::new(),
+            Block::Feature | Block::Table => vec![Expected::Tag],
+            Block::Lookup => vec![Expected::Lookup],
+            Block::Other => Vec::new(),
+        };
+    }
+
+    let (Some(first), Some(last)) = (statement.first(), statement.last()) else {
+        let set = match block {
+            None => TokenSet::TOP_LEVEL,
+            Some(Block::Feature) => TokenSet::FEATURE_STATEMENT,
+            Some(Block::Lookup) => TokenSet::STATEMENT,
+            Some(Block::Table | Block::Other) => return Vec::new(),
+        };
+        let mut result = keywords(set);
+        if set.contains(Kind::NamedGlyphClass) {
+            result.push(Expected::GlyphClass);
+        }
+        return result;
+    };
+
+    let open_classes = statement
+        .iter()
+        .map(|token| match token.kind {
+            AstKind::LSquare => 1,
+            AstKind::RSquare => -1,
+            _ => 0,
+        })
+        .sum::<i32>();
+    if open_classes > 0 {
+        return glyphs();
+    }
+    if last.kind == AstKind::LAngle {
+        return keywords(TokenSet::new(&[Kind::AnchorKw, Kind::NullKw]));
+    }
+
+    let len = statement.len();
+    match first.kind {
+        AstKind::LanguagesystemKw if len <= 2 => vec![Expected::Tag],
+        AstKind::FeatureKw | AstKind::ScriptKw | AstKind::LanguageKw | AstKind::TableKw
+            if len == 1 =>
+        {
+            vec![Expected::Tag]
+        }
+        AstKind::LanguageKw if len == 2 => keywords(LANGUAGE_OPTIONS),
+        AstKind::LookupKw if len == 1 => vec![Expected::Lookup],
+        AstKind::FeatureKw | AstKind::LookupKw if len == 2 && block.is_none() => {
+            keywords(Kind::UseExtensionKw.into())
+        }
+        AstKind::LookupflagKw => match last.kind {
+            AstKind::MarkAttachmentTypeKw | AstKind::UseMarkFilteringSetKw => {
+                vec![Expected::GlyphClass, Expected::MarkClass]
+            }
+            _ => keywords(LOOKUP_FLAGS),
+        },
+        AstKind::MarkClassKw if last.kind == AstKind::RAngle => vec![Expected::MarkClass],
+        AstKind::MarkClassKw if len == 1 => glyphs(),
+        AstKind::NamedGlyphClass if statement.iter().any(|t| t.kind == AstKind::Eq) => glyphs(),
+        AstKind::PosKw | AstKind::SubKw | AstKind::RsubKw | AstKind::EnumKw | AstKind::IgnoreKw => {
+            rule(statement)
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// What can come next in a substitution or positioning rule.
+fn rule(statement: &[&Token]) -> Vec<Expected> {
+    let last = statement.last().unwrap().kind;
+    let Some(idx) = statement
+        .iter()
+        .position(|t| matches!(t.kind, AstKind::PosKw | AstKind::SubKw | AstKind::RsubKw))
+    else {
+        // 'enum' or 'ignore'
+        return keywords(TokenSet::new(&[Kind::PosKw, Kind::SubKw]));
+    };
+    if last == AstKind::LookupKw {
+        return vec![Expected::Lookup];
+    }
+    let rule_kind = statement[idx].kind;
+    let rest = &statement[idx + 1..];
+    let mut result = glyphs();
+    if rule_kind != AstKind::PosKw {
+        if rest.is_empty() {
+            return result;
+        }
+        if rest
+            .iter()
+            .any(|t| matches!(t.kind, AstKind::ByKw | AstKind::FromKw))
+        {
+            result.extend(keywords(Kind::NullKw.into()));
+            return result;
+        }
+        result.extend(keywords(TokenSet::new(&[
+            Kind::ByKw,
+            Kind::FromKw,
+            Kind::LookupKw,
+        ])));
+        return result;
+    }
+
+    let Some(first) = rest.first() else {
+        result.extend(keywords(TokenSet::new(&[Kind::CursiveKw, Kind::MarkKw])));
+        result.extend([Expected::Keyword("base"), Expected::Keyword("ligature")]);
+        return result;
+    };
+    let attachment = matches!(first.as_str(), "base" | "ligature" | "mark");
+    if attachment && last == AstKind::MarkKw {
+        return vec![Expected::MarkClass];
+    }
+    result.extend(keywords(Kind::LookupKw.into()));
+    if attachment {
+        result.extend(keywords(Kind::MarkKw.into()));
+        if first.as_str() == "ligature" {
+            result.push(Expected::Keyword("ligComponent"));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the position is marked with '$'
+    fn context(fea: &str) -> CompletionContext {
+        let offset = fea.find('$').unwrap();
+        let fea = fea.replace('$', "");
+        let (root, _errs) = crate::parse::parse_string(fea.as_str());
+        CompletionContext::new(&root, offset)
+    }
+
+    fn expected(fea: &str) -> Vec<Expected> {
+        context(fea).expected
+    }
+
+    fn has_keyword(expected: &[Expected], keyword: &'static str) -> bool {
+        expected.contains(&Expected::Keyword(keyword))
+    }
+
+    #[test]
+    fn statement_keywords() {
+        let top = expected("languagesystem DFLT dflt;\n$");
+        assert!(has_keyword(&top, "feature"));
+        assert!(has_keyword(&top, "languagesystem"));
+        assert!(!has_keyword(&top, "sub"));
+        assert!(top.contains(&Expected::GlyphClass));
+
+        let feature = expected("feature liga {\n    sub f i by f_i;\n    $\n} liga;");
+        assert!(has_keyword(&feature, "sub"));
+        assert!(has_keyword(&feature, "substitute"));
+        assert!(has_keyword(&feature, "lookup"));
+        assert!(!has_keyword(&feature, "languagesystem"));
+
+        // 'lookup' is only a statement in a feature
+        let lookup = expected("lookup ONE {\n    $\n} ONE;");
+        assert!(has_keyword(&lookup, "lookupflag"));
+        assert!(!has_keyword(&lookup, "lookup"));
+    }
+
+    #[test]
+    fn partial_word() {
+        let fea = "feature liga {\n    lookupf$\n} liga;";
+        let ctx = context(fea);
+        assert_eq!(&fea[ctx.range.clone()], "lookupf");
+        assert!(has_keyword(&ctx.expected, "lookupflag"));
+
+        let ctx = context("feature liga { sub @lo$");
+        assert_eq!(ctx.range.len(), 3);
+        assert_eq!(ctx.expected, [Expected::GlyphClass]);
+
+        // the position is after whitespace, so no word is being written
+        let ctx = context("feature liga { sub $");
+        assert!(ctx.range.is_empty());
+    }
+
+    #[test]
+    fn names_and_tags() {
+        assert_eq!(expected("languagesystem $"), [Expected::Tag]);
+        assert_eq!(expected("languagesystem DFLT $"), [Expected::Tag]);
+        assert_eq!(expected("feature $"), [Expected::Tag]);
+        assert_eq!(expected("feature kern { pos a b 5; } $"), [Expected::Tag]);
+        assert_eq!(expected("feature kern { script $"), [Expected::Tag]);
+        assert_eq!(expected("lookup ONE { sub a by b; } $"), [Expected::Lookup]);
+        assert_eq!(expected("feature kern { lookup $"), [Expected::Lookup]);
+        assert_eq!(
+            expected("feature calt { sub a' lookup $"),
+            [Expected::Lookup]
+        );
+        assert!(has_keyword(
+            &expected("feature kern { language TRK $"),
+            "exclude_dflt"
+        ));
+    }
+
+    #[test]
+    fn rules() {
+        let sub = expected("feature liga { sub $");
+        assert_eq!(sub, [Expected::Glyph, Expected::GlyphClass]);
+        let sub = expected("feature liga { sub f i $");
+        assert!(sub.contains(&Expected::Glyph));
+        assert!(has_keyword(&sub, "by"));
+        assert!(has_keyword(&sub, "from"));
+        assert!(!has_keyword(&expected("feature liga { sub f i by $"), "by"));
+
+        let pos = expected("feature mark { pos $");
+        assert!(has_keyword(&pos, "base"));
+        assert!(has_keyword(&pos, "cursive"));
+        assert_eq!(
+            expected("feature mark { pos base a <anchor 1 2> mark $"),
+            [Expected::MarkClass]
+        );
+        assert!(has_keyword(
+            &expected("feature mark { pos base a $"),
+            "mark"
+        ));
+        assert_eq!(
+            expected("feature mark { pos base a <$"),
+            keywords(TokenSet::new(&[Kind::AnchorKw, Kind::NullKw]))
+        );
+        assert_eq!(
+            expected("feature liga { sub [a b $"),
+            [Expected::Glyph, Expected::GlyphClass]
+        );
+        assert_eq!(
+            expected("feature liga { lookupflag UseMarkFilteringSet $"),
+            [Expected::GlyphClass, Expected::MarkClass]
+        );
+        assert!(has_keyword(
+            &expected("feature liga { lookupflag $"),
+            "IgnoreMarks"
+        ));
+    }
+
+    #[test]
+    fn nothing_in_comments_or_strings() {
+        assert!(expected("# feature $").is_empty());
+        assert!(expected("feature ss01 { featureNames { name \"Alt $\"; }; } ss01;").is_empty());
+    }
+}
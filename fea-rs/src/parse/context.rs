@@ -4,7 +4,9 @@ use std::{
     collections::{HashMap, HashSet},
     ffi::OsString,
     ops::Range,
+    path::Path,
     sync::Arc,
+    time::Instant,
 };
 
 use super::source::{Source, SourceLoadError, SourceLoader, SourceResolver};
@@ -17,7 +19,8 @@ use crate::{
     Diagnostic, GlyphMap, Node,
 };
 
-const MAX_INCLUDE_DEPTH: usize = 50;
+/// The default maximum depth of nested include statements, as given in the spec.
+pub(crate) const DEFAULT_MAX_INCLUDE_DEPTH: usize = 5;
 
 /// Oversees parsing, following, resolving and validating input statements.
 ///
@@ -36,7 +39,8 @@ const MAX_INCLUDE_DEPTH: usize = 50;
 /// we avoid it by keeping track of what we've already parsed.
 ///
 /// Once parsing is finished, we use our `IncludeGraph` to validate that there
-/// are no cycles, and that the depth limit is not exceeded.
+/// are no cycles, and that the depth limit (by default 50, see
+/// [`with_max_include_depth`]) is not exceeded.
 ///
 /// After parsing, you use [`generate_parse_tree`] to validate and assemble
 /// the parsed sources into a single parse tree. This is also where validation
@@ -52,23 +56,27 @@ const MAX_INCLUDE_DEPTH: usize = 50;
 /// an intermediate type for generating a `ParseTree`.
 ///
 /// [`generate_parse_tree`]: ParseContext::generate_parse_tree
+/// [`with_max_include_depth`]: ParseContext::with_max_include_depth
 #[derive(Debug)]
 pub(crate) struct ParseContext {
     root_id: FileId,
     sources: Arc<SourceList>,
     parsed_files: HashMap<FileId, (Node, Vec<Diagnostic>)>,
     graph: IncludeGraph,
+    max_include_depth: usize,
 }
 
 /// A simple graph of files and their includes.
 ///
 /// We maintain this in order to validate that the input does not contain
 /// any cyclical include statements, and does not exceed the maximum include
-/// depth of 50.
+/// depth.
 #[derive(Clone, Debug, Default)]
 struct IncludeGraph {
     // source file -> (destination file, span-in-source-for-error)
     nodes: HashMap<FileId, Vec<(FileId, Range<usize>)>>,
+    // each file -> the first file found to include it
+    included_by: HashMap<FileId, FileId>,
 }
 
 /// An include statement in a source file.
@@ -80,6 +88,8 @@ struct IncludeError {
     statement_idx: usize,
     range: Range<usize>,
     kind: IncludeErrorKind,
+    /// the files from the root to the problem include, inclusive
+    chain: Vec<FileId>,
 }
 
 enum IncludeErrorKind {
@@ -133,7 +143,13 @@ impl ParseContext {
                 continue;
             }
             let source = sources.get(&id).unwrap();
+            let start = Instant::now();
             let (node, mut errors, include_stmts) = parse_src(source, glyph_map);
+            log::trace!(
+                "parsed '{}' in {:?}",
+                Path::new(source.path()).display(),
+                start.elapsed()
+            );
             errors.iter_mut().for_each(|e| e.message.file = id);
 
             parsed_files.insert(source.id(), (node, errors));
@@ -152,11 +168,15 @@ impl ParseContext {
                     }
                     Err(e) => {
                         let range = include.path_range();
-                        parsed_files.get_mut(&id).unwrap().1.push(Diagnostic::error(
-                            id,
-                            range,
-                            e.to_string(),
-                        ));
+                        let mut diagnostic = Diagnostic::error(id, range, e.to_string());
+                        if id != root_id {
+                            let chain = includes.chain_to(id);
+                            diagnostic = diagnostic.with_help(format!(
+                                "included via {}",
+                                format_chain(sources.list(), &chain)
+                            ));
+                        }
+                        parsed_files.get_mut(&id).unwrap().1.push(diagnostic);
                     }
                 }
             }
@@ -167,9 +187,19 @@ impl ParseContext {
             sources: sources.into_inner(),
             parsed_files,
             graph: includes,
+            max_include_depth: DEFAULT_MAX_INCLUDE_DEPTH,
         })
     }
 
+    /// Set the maximum depth of nested include statements.
+    ///
+    /// A file included by the root file has a depth of 1. Include statements
+    /// that exceed this depth are reported as errors, and are not resolved.
+    pub(crate) fn with_max_include_depth(mut self, depth: usize) -> Self {
+        self.max_include_depth = depth;
+        self
+    }
+
     pub(crate) fn root_id(&self) -> FileId {
         self.root_id
     }
@@ -184,18 +214,26 @@ impl ParseContext {
             .flat_map(|(_, (_, errs))| errs.iter())
             .cloned()
             .collect::<Vec<_>>();
-        let include_errors = self.graph.validate(self.root_id());
+        let include_errors = self.graph.validate(self.root_id(), self.max_include_depth);
         // record any errors:
         for IncludeError {
-            file, range, kind, ..
+            file,
+            range,
+            kind,
+            chain,
+            ..
         } in &include_errors
         {
             // find statement
             let message = match kind {
-                IncludeErrorKind::Cycle => "cyclical include statement",
-                IncludeErrorKind::ToDeep => "exceded maximum include depth",
+                IncludeErrorKind::Cycle => "cyclical include statement".to_string(),
+                IncludeErrorKind::ToDeep => format!(
+                    "exceeded maximum include depth of {}",
+                    self.max_include_depth
+                ),
             };
-            all_errors.push(Diagnostic::error(*file, range.clone(), message));
+            let help = format!("include chain: {}", format_chain(&self.sources, chain));
+            all_errors.push(Diagnostic::error(*file, range.clone(), message).with_help(help));
         }
 
         let mut map = SourceMap::default();
@@ -257,9 +295,24 @@ impl ParseContext {
 
 impl IncludeGraph {
     fn add_edge(&mut self, from: FileId, to: (FileId, Range<usize>)) {
+        self.included_by.entry(to.0).or_insert(from);
         self.nodes.entry(from).or_default().push(to);
     }
 
+    /// The files from the root to `file`, following the first include of each.
+    fn chain_to(&self, file: FileId) -> Vec<FileId> {
+        let mut chain = vec![file];
+        while let Some(parent) = self.included_by.get(chain.last().unwrap()) {
+            // the root can be included by another file, in a cycle
+            if chain.contains(parent) {
+                break;
+            }
+            chain.push(*parent);
+        }
+        chain.reverse();
+        chain
+    }
+
     fn includes_for_file(&self, file: FileId) -> Option<&[(FileId, Range<usize>)]> {
         self.nodes.get(&file).map(|f| f.as_slice())
     }
@@ -269,7 +322,7 @@ impl IncludeGraph {
     /// If the result is non-empty, each returned error should be converted to
     /// d to diagnostics by the caller, and those statements should
     /// not be resolved when building the final tree.
-    fn validate(&self, root: FileId) -> Vec<IncludeError> {
+    fn validate(&self, root: FileId, max_depth: usize) -> Vec<IncludeError> {
         let edges = match self.nodes.get(&root) {
             None => return Vec::new(),
             Some(edges) => edges,
@@ -283,12 +336,19 @@ impl IncludeGraph {
             if let Some((child, stmt)) = edges.get(cur_edge) {
                 // push parent, advancing idx
                 stack.push((node, edges, cur_edge + 1));
-                if stack.len() >= MAX_INCLUDE_DEPTH - 1 {
+                let chain = || {
+                    let mut chain = stack.iter().map(|(id, ..)| *id).collect::<Vec<_>>();
+                    chain.push(*child);
+                    chain
+                };
+                // the child's depth is the number of files on the stack
+                if stack.len() > max_depth {
                     bad_edges.push(IncludeError {
                         file: node,
                         statement_idx: cur_edge,
                         range: stmt.clone(),
                         kind: IncludeErrorKind::ToDeep,
+                        chain: chain(),
                     });
                     continue;
                 }
@@ -305,6 +365,7 @@ impl IncludeGraph {
                         statement_idx: cur_edge,
                         range: stmt.clone(),
                         kind: IncludeErrorKind::Cycle,
+                        chain: chain(),
                     });
                 }
             }
@@ -313,6 +374,19 @@ impl IncludeGraph {
     }
 }
 
+/// Format a chain of includes for a diagnostic, e.g. `a.fea -> b.fea`.
+fn format_chain(sources: &SourceList, chain: &[FileId]) -> String {
+    chain
+        .iter()
+        .map(|id| {
+            Path::new(sources.get(id).unwrap().path())
+                .display()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
 /// Parse a single source file.
 pub(crate) fn parse_src(
     src: &Source,
@@ -365,9 +439,10 @@ mod tests {
         graph.add_edge(c, (d, statement.range()));
         graph.add_edge(d, (b, statement.range()));
 
-        let result = graph.validate(a);
+        let result = graph.validate(a, DEFAULT_MAX_INCLUDE_DEPTH);
         assert_eq!(result[0].file, d);
         assert_eq!(result[0].range, 0..18);
+        assert_eq!(result[0].chain, [a, b, c, d, b]);
     }
 
     #[test]
@@ -390,6 +465,99 @@ mod tests {
         assert_eq!(resolved.root.text_len(), "include(bb);".len());
     }
 
+    fn chained_includes(path: &OsStr) -> Result<Arc<str>, SourceLoadError> {
+        match path.to_str().unwrap() {
+            "a" => Ok("include(b);".into()),
+            "b" => Ok("include(c);".into()),
+            "c" => Ok("include(d);\ninclude(missing);".into()),
+            "d" => Ok("languagesystem DFLT dflt;".into()),
+            _ => Err(SourceLoadError::new(
+                path.to_owned(),
+                std::io::Error::new(std::io::ErrorKind::NotFound, "oh no"),
+            )),
+        }
+    }
+
+    #[test]
+    fn max_include_depth() {
+        let parse = ParseContext::parse("a".into(), None, Box::new(chained_includes)).unwrap();
+        let (_, errs) = parse.with_max_include_depth(2).generate_parse_tree();
+        let errs = errs
+            .iter()
+            .map(|err| (err.text(), err.help().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errs,
+            [
+                (
+                    "Failed to load source at 'missing': 'oh no'",
+                    "included via a -> b -> c"
+                ),
+                (
+                    "exceeded maximum include depth of 2",
+                    "include chain: a -> b -> c -> d"
+                ),
+            ]
+        );
+
+        // 'd' is at a depth of 3
+        let parse = ParseContext::parse("a".into(), None, Box::new(chained_includes)).unwrap();
+        let (tree, errs) = parse.with_max_include_depth(3).generate_parse_tree();
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert!(tree
+            .root()
+            .iter_children()
+            .any(|child| child.kind() == Kind::LanguageSystemNode));
+    }
+
+    #[test]
+    fn default_max_include_depth() {
+        // each file includes the next, so file 'fn' is at a depth of n
+        let resolver = |path: &OsStr| match path.to_str().unwrap() {
+            "f6" => Ok("languagesystem DFLT dflt;".into()),
+            path => {
+                let depth: usize = path[1..].parse().unwrap();
+                Ok(format!("include(f{});", depth + 1).into())
+            }
+        };
+        let parse = ParseContext::parse("f0".into(), None, Box::new(resolver)).unwrap();
+        let (_, errs) = parse.generate_parse_tree();
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(errs[0].text(), "exceeded maximum include depth of 5");
+        assert_eq!(
+            errs[0].help(),
+            Some("include chain: f0 -> f1 -> f2 -> f3 -> f4 -> f5 -> f6")
+        );
+    }
+
+    #[test]
+    fn include_without_path() {
+        let parse = ParseContext::parse(
+            "a".into(),
+            None,
+            Box::new(|_: &OsStr| Ok("include(;\nlanguagesystem DFLT dflt;".into())),
+        )
+        .unwrap();
+        let (_, errs) = parse.generate_parse_tree();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].text(), "Include statement missing path");
+    }
+
+    #[test]
+    fn include_at_end_of_unclosed_block() {
+        let parse = ParseContext::parse(
+            "a".into(),
+            None,
+            Box::new(|path: &OsStr| match path.to_str() {
+                Some("a") => Ok("feature;include(b".into()),
+                _ => Ok("languagesystem DFLT dflt;".into()),
+            }),
+        )
+        .unwrap();
+        let (_, errs) = parse.generate_parse_tree();
+        assert!(!errs.is_empty());
+    }
+
     #[test]
     fn assembly_basic() {
         let file_a = "\
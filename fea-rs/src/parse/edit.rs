@@ -0,0 +1,273 @@
+//! Writing source text back out, with edits.
+//!
+//! The token tree is lossless, so regenerating source from it reproduces the
+//! input exactly. Edits replace the text of whole tokens or nodes, and leave
+//! all other text (including whitespace and comments) untouched, so that the
+//! resulting diff is as small as possible.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+};
+
+use super::{FileId, Location, ParseTree};
+use crate::Node;
+
+/// A replacement of some text in a single source file.
+///
+/// The replaced range must start and end on token boundaries; this ensures
+/// that an edit always replaces some set of complete tokens or nodes.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextEdit {
+    /// The text to be replaced.
+    pub location: Location,
+    /// The new text.
+    pub new_text: String,
+}
+
+/// An error that occurs when applying a set of [`TextEdit`]s.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum EditError {
+    /// The edit refers to a file that is not part of the tree
+    #[error("no source with id {0:?}")]
+    UnknownFile(FileId),
+    /// The start or end of the edit falls inside a token
+    #[error("edit at {0:?} does not start and end on token boundaries")]
+    NotOnTokenBoundary(Range<usize>),
+    /// Two edits replace some of the same text
+    #[error("edits at {0:?} and {1:?} overlap")]
+    Overlapping(Range<usize>, Range<usize>),
+}
+
+impl TextEdit {
+    /// Create a new edit, replacing the text at `location` with `new_text`.
+    pub fn new(location: Location, new_text: impl Into<String>) -> Self {
+        TextEdit {
+            location,
+            new_text: new_text.into(),
+        }
+    }
+}
+
+impl ParseTree {
+    /// Return the location in a source file of a range in this tree.
+    ///
+    /// The ranges of nodes and tokens reached from [`root`][Self::root] are
+    /// positions in the combined text of all sources; this maps them back
+    /// to the file they came from, for instance to construct a [`TextEdit`].
+    ///
+    /// Returns `None` if the start of `range` is not in the tree.
+    pub fn location(&self, range: Range<usize>) -> Option<Location> {
+        let (file, range) = self.map.try_resolve_range(range)?;
+        Some(Location { file, range })
+    }
+
+    /// Apply a set of edits, returning the new text of each edited source.
+    ///
+    /// Sources without edits are not included in the output. The tree itself
+    /// is not modified; to see the effect of the edits, parse the new text.
+    pub fn apply_edits(
+        &self,
+        edits: impl IntoIterator<Item = TextEdit>,
+    ) -> Result<HashMap<FileId, String>, EditError> {
+        let mut by_file = HashMap::<_, Vec<_>>::new();
+        for edit in edits {
+            let TextEdit { location, new_text } = edit;
+            if self.get_source(location.file).is_none() {
+                return Err(EditError::UnknownFile(location.file));
+            }
+            by_file
+                .entry(location.file)
+                .or_default()
+                .push((location.range, new_text));
+        }
+
+        // the token boundaries in each edited file, in that file's coordinates
+        let mut boundaries = HashMap::<_, BTreeSet<_>>::new();
+        for token in self.root.iter_tokens() {
+            let Some(loc) = self.location(token.range()) else {
+                continue;
+            };
+            if by_file.contains_key(&loc.file) {
+                let set = boundaries.entry(loc.file).or_default();
+                set.insert(loc.range.start);
+                set.insert(loc.range.end);
+            }
+        }
+
+        by_file
+            .into_iter()
+            .map(|(file, edits)| {
+                let text = self.get_source(file).unwrap().text();
+                let bounds = boundaries.remove(&file).unwrap_or_default();
+                apply(text, &bounds, edits).map(|text| (file, text))
+            })
+            .collect()
+    }
+}
+
+impl Node {
+    /// Return the text of this node, with edits applied.
+    ///
+    /// This is intended for a node that represents a single source, such as
+    /// one returned by [`parse_string`]; the file of each edit is ignored, and
+    /// ranges are relative to the start of the node.
+    ///
+    /// [`parse_string`]: super::parse_string
+    pub fn apply_edits(
+        &self,
+        edits: impl IntoIterator<Item = TextEdit>,
+    ) -> Result<String, EditError> {
+        let mut text = String::with_capacity(self.text_len());
+        let mut bounds = BTreeSet::from([0]);
+        for token in self.iter_tokens() {
+            text.push_str(token.as_str());
+            bounds.insert(text.len());
+        }
+        let edits = edits
+            .into_iter()
+            .map(|edit| (edit.location.range, edit.new_text))
+            .collect();
+        apply(&text, &bounds, edits)
+    }
+}
+
+fn apply(
+    text: &str,
+    bounds: &BTreeSet<usize>,
+    mut edits: Vec<(Range<usize>, String)>,
+) -> Result<String, EditError> {
+    edits.sort_by_key(|(range, _)| (range.start, range.end));
+    for (range, _) in &edits {
+        if range.start > range.end || !bounds.contains(&range.start) || !bounds.contains(&range.end)
+        {
+            return Err(EditError::NotOnTokenBoundary(range.clone()));
+        }
+    }
+    for pair in edits.windows(2) {
+        let (prev, next) = (&pair[0].0, &pair[1].0);
+        // two insertions at the same position would have an ambiguous order
+        if next.start < prev.end || next == prev {
+            return Err(EditError::Overlapping(prev.clone(), next.clone()));
+        }
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut pos = 0;
+    for (range, new_text) in &edits {
+        result.push_str(&text[pos..range.start]);
+        result.push_str(new_text);
+        pos = range.end;
+    }
+    result.push_str(&text[pos..]);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+    use crate::typed::{self, AstNode};
+
+    fn edit(range: Range<usize>, text: &str) -> TextEdit {
+        TextEdit::new(
+            Location {
+                file: FileId::CURRENT_FILE,
+                range,
+            },
+            text,
+        )
+    }
+
+    #[test]
+    fn change_value_record() {
+        let fea = "\
+feature kern {
+    # a comment
+    pos a   b -20;  # another
+} kern;
+";
+        let (node, errs) = crate::parse::parse_string(fea);
+        assert!(errs.is_empty());
+        let root = typed::Root::try_from_node(&node).unwrap();
+        let feature = root.statements().find_map(typed::Feature::cast).unwrap();
+        let value = feature
+            .statements()
+            .find_map(typed::Gpos2::cast)
+            .unwrap()
+            .first_value();
+        let new_text = node
+            .apply_edits([edit(value.range(), "<0 0 -30 0>")])
+            .unwrap();
+        assert_eq!(
+            new_text,
+            "\
+feature kern {
+    # a comment
+    pos a   b <0 0 -30 0>;  # another
+} kern;
+"
+        );
+    }
+
+    #[test]
+    fn edits_must_fall_on_token_boundaries() {
+        let (node, _) = crate::parse::parse_string("@class = [a b];");
+        assert_eq!(
+            node.apply_edits([edit(1..3, "x")]),
+            Err(EditError::NotOnTokenBoundary(1..3))
+        );
+        assert_eq!(
+            node.apply_edits([edit(0..6, "@other"), edit(0..6, "@else")]),
+            Err(EditError::Overlapping(0..6, 0..6))
+        );
+        assert_eq!(
+            node.apply_edits([edit(0..6, "@other"), edit(10..11, "c")])
+                .unwrap(),
+            "@other = [c b];"
+        );
+    }
+
+    #[test]
+    fn edit_included_file() {
+        let (tree, errs) =
+            crate::parse::parse_root("a".into(), None, |path: &OsStr| match path.to_str() {
+                Some("a") => Ok("@class = [a b];\ninclude(b);\n".into()),
+                _ => Ok("# included\n@other = [@class c];\n".into()),
+            })
+            .unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+        let edits = tree
+            .typed_root()
+            .statements()
+            .filter_map(typed::GlyphClassDef::cast)
+            .map(|def| {
+                let name = def.class_name();
+                let new_name = format!("{}_renamed", name.text());
+                TextEdit::new(tree.location(name.range()).unwrap(), new_name)
+            })
+            .collect::<Vec<_>>();
+        let new_text = tree.apply_edits(edits).unwrap();
+        assert_eq!(new_text.len(), 2);
+        let mut texts = new_text.values().collect::<Vec<_>>();
+        texts.sort();
+        assert_eq!(
+            texts,
+            [
+                "# included\n@other_renamed = [@class c];\n",
+                "@class_renamed = [a b];\ninclude(b);\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn location_outside_tree() {
+        let (tree, _) =
+            crate::parse::parse_root("a".into(), None, |_: &OsStr| Ok("@class = [a b];".into()))
+                .unwrap();
+        let len = tree.root().text_len();
+        assert!(tree.location(0..6).is_some());
+        assert!(tree.location(len + 10..len + 12).is_none());
+    }
+}
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use super::super::lexer::{Kind as LexemeKind, TokenSet};
 use super::{glyph, gpos, gsub, metrics};
 
@@ -5,6 +7,22 @@ use crate::parse::Parser;
 use crate::token_tree::Kind;
 
 const LABEL_RECOVERY: TokenSet = TokenSet::new(&[LexemeKind::UseExtensionKw, LexemeKind::LBrace]);
+/// Tokens that may start the item following a feature block.
+///
+/// Glyph class definitions, mark classes and lookups can also be statements
+/// inside a feature, so these are only a hint that the feature has ended.
+const AFTER_FEATURE: TokenSet = TokenSet::new(&[
+    LexemeKind::TableKw,
+    LexemeKind::IncludeKw,
+    LexemeKind::LanguagesystemKw,
+    LexemeKind::AnchorDefKw,
+    LexemeKind::FeatureKw,
+    LexemeKind::AnonKw,
+    LexemeKind::NamedGlyphClass,
+    LexemeKind::MarkClassKw,
+    LexemeKind::LookupKw,
+    LexemeKind::Eof,
+]);
 
 pub(crate) fn feature(parser: &mut Parser) {
     fn feature_body(parser: &mut Parser) {
@@ -14,28 +32,37 @@ pub(crate) fn feature(parser: &mut Parser) {
         parser.eat(Kind::UseExtensionKw);
         parser.expect(Kind::LBrace);
         while !parser.at_eof() && !parser.matches(0, Kind::RBrace) {
-            if !statement(parser, TokenSet::FEATURE_STATEMENT, false) {
-                if let Some(tag) = open_tag.as_ref() {
-                    parser.raw_error(tag.range.clone(), "Feature block is unclosed");
-                }
+            let feature_tag = open_tag.as_ref().map(|tag| tag.range.clone());
+            if !statement(parser, TokenSet::FEATURE_STATEMENT, false, feature_tag) {
                 break;
             }
         }
-        parser.expect_recover(Kind::RBrace, TokenSet::TOP_SEMI);
-        let close_tag = parser.expect_tag(TokenSet::TOP_LEVEL);
-        if let (Some(open), Some(close)) = (open_tag, close_tag) {
-            if open.tag != close.tag {
-                parser.raw_error(close.range, format!("expected tag '{}'", open.tag));
+        // if we're at EOF or at the start of some other top-level item, the
+        // block is missing its closing brace. Report this once, and leave the
+        // next item to be parsed normally.
+        if !parser.matches(0, Kind::RBrace) {
+            if let Some(tag) = open_tag.as_ref() {
+                parser.raw_error(tag.range.clone(), "Feature block is unclosed");
             }
+            return;
         }
-        parser.expect_semi();
+        parser.expect_recover(Kind::RBrace, TokenSet::TOP_SEMI);
+        parser.expect_closing_name(Kind::Tag, open_tag.map(|tag| tag.range));
     }
 
     parser.in_node(Kind::FeatureNode, feature_body);
 }
 
-pub(crate) fn lookup_block(parser: &mut Parser, recovery: TokenSet) {
-    fn lookup_body(parser: &mut Parser, recovery: TokenSet) {
+/// Parse a lookup block.
+///
+/// If this block is inside a feature block, `feature_tag` is the range of
+/// that feature's tag.
+pub(crate) fn lookup_block(
+    parser: &mut Parser,
+    recovery: TokenSet,
+    feature_tag: Option<Range<usize>>,
+) {
+    fn lookup_body(parser: &mut Parser, recovery: TokenSet, feature_tag: Option<Range<usize>>) {
         assert!(parser.eat(Kind::LookupKw));
         let raw_label_range = parser.matches(0, Kind::Ident).then(|| parser.nth_range(0));
         parser.expect_remap_recover(
@@ -47,32 +74,50 @@ pub(crate) fn lookup_block(parser: &mut Parser, recovery: TokenSet) {
         parser.eat(Kind::UseExtensionKw);
         parser.expect(Kind::LBrace);
         while !parser.at_eof() && !parser.matches(0, Kind::RBrace) {
-            if !statement(parser, recovery, true) {
-                if let Some(range) = raw_label_range {
-                    parser.raw_error(range, "Table is unclosed");
-                }
+            if !statement(parser, recovery, true, None) {
                 break;
             }
         }
+
+        // a nested lookup that is missing its closing brace will otherwise
+        // consume the closing brace of the enclosing feature. Any other
+        // mismatched label is just a typo, though.
+        let closes_parent = parser.matches(1, TokenSet::IDENT_LIKE)
+            && parser.matches(2, LexemeKind::Semi)
+            && parser.matches(3, AFTER_FEATURE)
+            && feature_tag.is_some_and(|tag| parser.raw_range(tag) == parser.nth_raw(1))
+            && raw_label_range
+                .as_ref()
+                .map(|range| parser.raw_range(range.clone()) != parser.nth_raw(1))
+                .unwrap_or(false);
+        if !parser.matches(0, Kind::RBrace) || closes_parent {
+            if let Some(range) = raw_label_range {
+                parser.raw_error(range, "Table is unclosed");
+            }
+            return;
+        }
         parser.expect_recover(
             Kind::RBrace,
             recovery.union(TokenSet::IDENT_LIKE.union(TokenSet::SEMI)),
         );
-        parser.expect_remap_recover(
-            TokenSet::IDENT_LIKE,
-            Kind::Label,
-            recovery.union(TokenSet::SEMI),
-        );
-        parser.expect_semi();
+        parser.expect_closing_name(Kind::Label, raw_label_range);
     }
 
     parser.in_node(Kind::LookupBlockNode, |parser| {
-        lookup_body(parser, recovery)
+        lookup_body(parser, recovery, feature_tag)
     });
 }
 
 /// returns true if we advanced the parser.
-fn statement(parser: &mut Parser, recovery: TokenSet, in_lookup: bool) -> bool {
+///
+/// `feature_tag` is the range of the enclosing feature's tag, if this
+/// statement is directly inside a feature block.
+fn statement(
+    parser: &mut Parser,
+    recovery: TokenSet,
+    in_lookup: bool,
+    feature_tag: Option<Range<usize>>,
+) -> bool {
     let start_pos = parser.nth_range(0).start;
     match parser.nth(0).kind.to_token_kind() {
         Kind::PosKw | Kind::SubKw | Kind::RsubKw | Kind::IgnoreKw | Kind::EnumKw => {
@@ -91,7 +136,10 @@ fn statement(parser: &mut Parser, recovery: TokenSet, in_lookup: bool) -> bool {
             parser.eat_until(recovery);
         }
         Kind::IncludeKw => super::include(parser),
-        Kind::LookupKw => super::lookup_block_or_reference(parser, recovery),
+        Kind::LookupKw => super::lookup_block_or_reference(parser, recovery, feature_tag),
+        // these can only occur at the top level; if we see one, the current
+        // block was not closed. We don't advance, and the caller reports the error.
+        Kind::TableKw | Kind::LanguagesystemKw | Kind::AnchorDefKw | Kind::AnonKw => (),
         Kind::LookupflagKw => lookupflag(parser, recovery),
         Kind::ScriptKw => {
             super::eat_script(parser, recovery);
@@ -203,6 +251,9 @@ fn cv_parameters(parser: &mut Parser, recovery: TokenSet) {
                 parser.expect_recover(Kind::RBrace, recovery);
                 parser.expect_semi();
             });
+        } else {
+            // always advance, so that the caller's loop terminates
+            parser.err_and_bump("Expected 'Character' or a name id keyword");
         }
     }
 
@@ -75,16 +75,46 @@ pub(crate) fn eat_glyph_class_list(parser: &mut Parser, recovery: TokenSet) -> b
     parser.in_node(AstKind::GlyphClass, |parser| {
         let range = parser.nth_range(0);
         assert!(parser.eat(Kind::LSquare));
-        super::greedy(glyph_class_list_member)(parser, recovery);
+        loop {
+            super::greedy(glyph_class_list_member)(parser, recovery);
+            if parser.at_eof() || parser.matches(0, recovery.union(CLASS_STOP)) {
+                break;
+            }
+            // a bad token inside the class. Classes can be very long, so
+            // instead of abandoning the class (and reporting an error for
+            // each of its remaining members) we report one error for this
+            // run of bad tokens, and resume at the next member.
+            parser.err_and_bump("Expected glyph name, CID, or glyph class.");
+            while !parser.at_eof()
+                && !parser.matches(0, CLASS_MEMBER_START.union(recovery).union(CLASS_STOP))
+            {
+                parser.eat_raw();
+            }
+        }
 
         if !parser.eat(Kind::RSquare) {
             parser.raw_error(range, "Unclosed glyph class.");
-            parser.err_recover("Expected closing ']'.", recovery);
+            parser.err_recover("Expected closing ']'.", recovery.union(CLASS_STOP));
         }
     });
     true
 }
 
+/// Tokens that can start a member of a glyph class.
+const CLASS_MEMBER_START: TokenSet = TokenSet::IDENT_LIKE.union(TokenSet::new(&[
+    Kind::Cid,
+    Kind::NamedGlyphClass,
+    Kind::Backslash,
+]));
+
+/// Tokens that end a glyph class, or suggest that it was never closed.
+const CLASS_STOP: TokenSet = TokenSet::TOP_AND_FEATURE.union(TokenSet::new(&[
+    Kind::RSquare,
+    Kind::Semi,
+    Kind::LBrace,
+    Kind::RBrace,
+]));
+
 fn glyph_class_list_member(parser: &mut Parser, recovery: TokenSet) -> bool {
     if parser.eat(Kind::NamedGlyphClass) {
         return true;
@@ -94,7 +124,8 @@ fn glyph_class_list_member(parser: &mut Parser, recovery: TokenSet) -> bool {
     // an escaped glyph name
     // an escaped CID
 
-    let looks_like_range = parser.matches(1, Kind::Hyphen)
+    let looks_like_range = (parser.matches(0, TokenSet::IDENT_LIKE)
+        && parser.matches(1, Kind::Hyphen))
         || (parser.matches(0, Kind::Backslash) && parser.matches(2, Kind::Hyphen));
     if looks_like_range {
         parser.in_node(AstKind::GlyphRange, |parser| {
@@ -234,6 +265,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn recover_in_long_class() {
+        let mut fea = String::from("[");
+        for i in 0..10_000 {
+            fea.push_str(&format!("g{i} "));
+            if i == 5_000 {
+                fea.push_str("12 <anchor> ");
+            }
+        }
+        fea.push(']');
+        let mut sink = AstSink::new(&fea, FileId::CURRENT_FILE, None);
+        let mut parser = Parser::new(&fea, &mut sink);
+        assert!(eat_glyph_class_list(&mut parser, TokenSet::EMPTY));
+        assert!(parser.at_eof());
+
+        let (node, errs, _) = sink.finish();
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert_eq!(errs[0].text(), "Expected glyph name, CID, or glyph class.");
+        assert_eq!(node.kind(), AstKind::GlyphClass);
+        let n_glyphs = node
+            .iter_tokens()
+            .filter(|token| token.kind == AstKind::GlyphName)
+            .count();
+        assert_eq!(n_glyphs, 10_000);
+    }
+
+    #[test]
+    fn unclosed_class_stops_at_statement() {
+        let fea = "[a b c sub";
+        let mut sink = AstSink::new(fea, FileId::CURRENT_FILE, None);
+        let mut parser = Parser::new(fea, &mut sink);
+        eat_glyph_class_list(&mut parser, TokenSet::EMPTY);
+        assert_eq!(parser.nth_raw(0), b"sub");
+        let (_, errs, _) = sink.finish();
+        assert_eq!(errs[0].text(), "Unclosed glyph class.");
+    }
+
     #[test]
     fn disambiguate_range() {
         let fea = "[a-b]";
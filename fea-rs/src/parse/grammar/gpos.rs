@@ -97,7 +97,7 @@ fn parse_ignore(parser: &mut Parser, recovery: TokenSet) -> AstKind {
 
 fn gpos_cursive(parser: &mut Parser, recovery: TokenSet) {
     assert!(parser.eat(Kind::CursiveKw));
-    glyph::eat_glyph_or_glyph_class(parser, recovery.union(Kind::LAngle.into()));
+    glyph::expect_glyph_or_glyph_class(parser, recovery.union(Kind::LAngle.into()));
     metrics::anchor(parser, recovery.union(Kind::LAngle.into()));
     metrics::anchor(parser, recovery);
     parser.expect_semi();
@@ -115,7 +115,7 @@ fn gpos_mark_to_base(parser: &mut Parser, recovery: TokenSet) {
 }
 
 fn gpos_mark_to_(parser: &mut Parser, recovery: TokenSet) {
-    glyph::eat_glyph_or_glyph_class(
+    glyph::expect_glyph_or_glyph_class(
         parser,
         recovery.union(TokenSet::new(&[Kind::LAngle, Kind::AnchorKw])),
     );
@@ -126,7 +126,7 @@ fn gpos_mark_to_(parser: &mut Parser, recovery: TokenSet) {
 fn gpos_ligature(parser: &mut Parser, recovery: TokenSet) {
     assert!(parser.nth_raw(0) == b"ligature");
     parser.eat_remap(Kind::Ident, AstKind::LigatureKw);
-    glyph::eat_glyph_or_glyph_class(
+    glyph::expect_glyph_or_glyph_class(
         parser,
         recovery.union(TokenSet::new(&[Kind::LAngle, Kind::AnchorKw])),
     );
@@ -206,8 +206,9 @@ fn anchor_mark(parser: &mut Parser, recovery: TokenSet) -> bool {
     }
     parser.in_node(AstKind::AnchorMarkNode, |parser| {
         metrics::anchor(parser, recovery.union(RECOVERY));
-        // we will verify later that the anchor was NULL
-        if !parser.matches(0, Kind::Semi) {
+        // an anchor without a mark class ends the rule or the ligature
+        // component; we will verify later that the anchor was NULL
+        if !(parser.matches(0, Kind::Semi) || parser.nth_raw(0) == b"ligComponent") {
             parser.expect_recover(Kind::MarkKw, recovery.union(RECOVERY));
             parser.expect_recover(Kind::NamedGlyphClass, recovery.union(RECOVERY));
         }
@@ -43,16 +43,22 @@ pub(crate) fn gsub(parser: &mut Parser, recovery: TokenSet) {
                 return AstKind::GsubType1;
             }
 
-            if is_class && glyph::eat_named_or_unnamed_glyph_class(parser, recovery.union(RECOVERY))
+            // if the target is a class, the replacement may contain classes;
+            // a single class is type 1, format C, and a sequence is type 2
+            let eat_item = |parser: &mut Parser| {
+                if is_class {
+                    glyph::eat_glyph_or_glyph_class(parser, recovery.union(RECOVERY))
+                } else {
+                    glyph::eat_glyph_name_like(parser)
+                }
+            };
+            if !(is_class
+                && glyph::eat_named_or_unnamed_glyph_class(parser, recovery.union(RECOVERY)))
             {
-                // type 1, format C
-                parser.expect_semi();
-                return AstKind::GsubType1;
+                glyph::expect_glyph_name_like(parser, recovery.union(RECOVERY));
             }
-
-            glyph::expect_glyph_name_like(parser, recovery.union(RECOVERY));
-            let is_seq = glyph::eat_glyph_name_like(parser);
-            while glyph::eat_glyph_name_like(parser) {
+            let is_seq = eat_item(parser);
+            while eat_item(parser) {
                 continue;
             }
             parser.expect_semi();
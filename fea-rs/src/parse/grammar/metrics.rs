@@ -12,6 +12,8 @@ use crate::token_tree::Kind as AstKind;
 //    (<anchor 120 -20 <device 11 1> <device NULL>>)
 // D: <anchor NULL>
 // E: <anchor <name>> (<anchor TOP_ANCHOR_1>)
+// in A, B and C, either metric may be variable:
+//    (<anchor (wght=200:100 wght=900:150) -20>)
 pub(crate) fn anchor(parser: &mut Parser, recovery: TokenSet) -> bool {
     fn anchor_body(parser: &mut Parser, recovery: TokenSet) -> bool {
         parser.expect(Kind::LAngle);
@@ -26,8 +28,9 @@ pub(crate) fn anchor(parser: &mut Parser, recovery: TokenSet) -> bool {
         // <metric> metric>
         // <metric> <metric> <contour point>
         // <metric> <metric> <device> <device>
-        parser.expect_remap_recover(Kind::Number, AstKind::Metric, recovery);
-        parser.expect_remap_recover(Kind::Number, AstKind::Metric, recovery);
+        // where either metric may be variable
+        anchor_metric(parser, recovery);
+        anchor_metric(parser, recovery);
         if parser.eat(Kind::ContourpointKw) {
             parser.expect_recover(Kind::Number, recovery);
         } else if eat_device(parser, recovery) {
@@ -39,7 +42,25 @@ pub(crate) fn anchor(parser: &mut Parser, recovery: TokenSet) -> bool {
     parser.in_node(AstKind::AnchorNode, |parser| anchor_body(parser, recovery))
 }
 
-// A: <metric> (-5)
+// either a number, or a variable metric with a value at each location:
+// (wght=200:100 wght=900:150 wght=900,wdth=150:170)
+//
+// the lexer treats everything inside parentheses as a single path token, so
+// the locations are parsed during validation.
+fn anchor_metric(parser: &mut Parser, recovery: TokenSet) {
+    if !parser.matches(0, Kind::LParen) {
+        parser.expect_remap_recover(Kind::Number, AstKind::Metric, recovery);
+        return;
+    }
+
+    parser.in_node(AstKind::VariableMetricNode, |parser| {
+        assert!(parser.eat(Kind::LParen));
+        parser.expect_recover(Kind::Path, recovery.union(Kind::RParen.into()));
+        parser.expect_recover(Kind::RParen, recovery);
+    });
+}
+
+// A: <metric> (-5 or <-5>)
 // B: <<metric> <metric> <metric> <metric>> (<1 2 -5 242>)
 // C: <<metric> <metric> <metric> <metric> <device> <device> <device> <device>>
 // (<1 2 -5 242 <device 1 2, 3 4> <device NULL> <device 1 1, 2 2> <device NULL>>)
@@ -58,6 +79,10 @@ pub(crate) fn eat_value_record(parser: &mut Parser, recovery: TokenSet) -> bool
         }
 
         parser.expect_recover(Kind::Number, recovery);
+        // type A, in brackets:
+        if parser.eat(Kind::RAngle) {
+            return;
+        }
         parser.expect_recover(Kind::Number, recovery);
         parser.expect_recover(Kind::Number, recovery);
         parser.expect_recover(Kind::Number, recovery);
@@ -140,17 +165,17 @@ fn eat_device(parser: &mut Parser, recovery: TokenSet) -> bool {
     }
 }
 
+/// A 'parameters' statement.
+///
+/// In the size feature this has two or four values, which is checked during
+/// validation; other features may use any number of values.
 pub(crate) fn parameters(parser: &mut Parser, recovery: TokenSet) {
     let recovery = recovery.union(Kind::Semi.into());
 
     parser.in_node(AstKind::ParametersNode, |parser| {
         assert!(parser.eat(Kind::ParametersKw));
         parser.expect_recover(TokenSet::FLOAT_LIKE, recovery);
-        parser.expect_recover(Kind::Number, recovery);
-        if !parser.matches(0, Kind::Semi) {
-            parser.expect_recover(TokenSet::FLOAT_LIKE, recovery);
-            parser.expect_recover(TokenSet::FLOAT_LIKE, recovery);
-        }
+        while parser.eat(TokenSet::FLOAT_LIKE) {}
         parser.expect_semi();
     })
 }
@@ -187,6 +212,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn anchor_variable_metrics() {
+        let fea = "<anchor (wght=200:100 wght=900,wdth=150:150) 20 >";
+        let (out, _, errstr) = debug_parse_output(fea, |parser| {
+            anchor(parser, TokenSet::EMPTY);
+        });
+        assert!(errstr.is_empty(), "{}", errstr);
+        let tree = out.format(crate::TreeFormat::Tree);
+        assert_eq!(tree.matches("VariableMetricNode").count(), 1, "{tree}");
+
+        let fea = "<anchor () 20>";
+        let (_out, errors, _errstr) = debug_parse_output(fea, |parser| {
+            anchor(parser, TokenSet::EMPTY);
+        });
+        assert_eq!(errors.len(), 1);
+    }
+
     #[test]
     fn device_record_smoke_test() {
         let fea = "\
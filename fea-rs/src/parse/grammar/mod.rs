@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use super::lexer::{Kind, TokenSet};
 use super::Parser;
 use crate::token_tree::Kind as AstKind;
@@ -34,7 +36,7 @@ fn top_level_element(parser: &mut Parser) {
     } else if parser.matches(0, Kind::TableKw) {
         table(parser)
     } else if parser.matches(0, Kind::LookupKw) {
-        lookup_block_or_reference(parser, TokenSet::TOP_LEVEL)
+        lookup_block_or_reference(parser, TokenSet::TOP_LEVEL, None)
     } else if parser.matches(0, Kind::LanguagesystemKw) {
         language_system(parser)
     } else if parser.matches(0, Kind::FeatureKw) {
@@ -48,7 +50,8 @@ fn top_level_element(parser: &mut Parser) {
     } else if parser.matches(0, Kind::NamedGlyphClass) {
         glyph::named_glyph_class_decl(parser, TokenSet::TOP_LEVEL)
     } else if parser.matches(0, Kind::ValueRecordDefKw) {
-        unimplemented!()
+        parser.err_and_bump("'valueRecordDef' is not supported");
+        advance_to_top_level(parser);
     } else {
         parser.err_and_bump(format!(
             "Unexpected token '{}', expected global keyword.",
@@ -105,10 +108,14 @@ fn table(parser: &mut Parser) {
 
 //either lookup <label> { ... } <label>;
 //or     lookup <label>;
-fn lookup_block_or_reference(parser: &mut Parser, recovery: TokenSet) {
+fn lookup_block_or_reference(
+    parser: &mut Parser,
+    recovery: TokenSet,
+    feature_tag: Option<Range<usize>>,
+) {
     assert!(parser.matches(0, Kind::LookupKw));
     if parser.matches(2, Kind::LBrace) {
-        feature::lookup_block(parser, recovery.union(TokenSet::STATEMENT));
+        feature::lookup_block(parser, recovery.union(TokenSet::STATEMENT), feature_tag);
     } else if parser.matches(2, Kind::Semi) {
         parser.in_node(AstKind::LookupRefNode, |parser| {
             assert!(parser.eat(Kind::LookupKw));
@@ -325,6 +332,7 @@ fn debug_parse_output(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Level;
     #[test]
     fn no_cv_param_in_lookup() {
         let fea = "lookup hi {cvParameters {}; } hi ;";
@@ -332,4 +340,185 @@ mod tests {
         assert!(!errors.is_empty(), "{}", fea);
         assert!(errors.first().unwrap().text().contains("cvParameters"));
     }
+
+    #[test]
+    fn missing_semi_single_error() {
+        let fea = "feature liga {\n sub f i by f_i\n sub f l by f_l;\n} liga;\nfeature kern { pos a b 10; } kern;";
+        let (_out, errors, errstr) = debug_parse_output(fea, root);
+        assert_eq!(errors.len(), 1, "{errstr}");
+        assert_eq!(errors[0].text(), "Expected ';'");
+    }
+
+    #[test]
+    fn unclosed_feature_single_error() {
+        let fea = "feature liga {\n sub f i by f_i;\n\nfeature kern { pos a b 10; } kern;\ntable GDEF { } GDEF;";
+        let (out, errors, errstr) = debug_parse_output(fea, root);
+        assert_eq!(errors.len(), 1, "{errstr}");
+        assert_eq!(errors[0].text(), "Feature block is unclosed");
+        // the following items are parsed normally
+        let kinds = out
+            .iter_children()
+            .filter(|t| !t.kind().is_trivia())
+            .map(|t| t.kind())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                AstKind::FeatureNode,
+                AstKind::FeatureNode,
+                AstKind::GdefTableNode
+            ]
+        );
+
+        let fea = "feature liga {\n sub f i by f_i;\n";
+        let (_out, errors, errstr) = debug_parse_output(fea, root);
+        assert_eq!(errors.len(), 1, "{errstr}");
+    }
+
+    #[test]
+    fn unclosed_nested_lookup_single_error() {
+        let fea = "feature liga {\n lookup foo {\n  sub f i by f_i;\n\n} liga;\nfeature kern { pos a b 10; } kern;";
+        let (_out, errors, errstr) = debug_parse_output(fea, root);
+        assert_eq!(errors.len(), 1, "{errstr}");
+        assert_eq!(errors[0].text(), "Table is unclosed");
+
+        // any top-level item can follow the feature
+        for next in [
+            "@foo = [a b];",
+            "markClass acute <anchor 0 0> @TOP;",
+            "lookup bar { sub a by b; } bar;",
+        ] {
+            let fea = format!("feature liga {{ lookup one {{ sub f i by f_i; }} liga;\n{next}");
+            let (out, errors, errstr) = debug_parse_output(&fea, root);
+            assert_eq!(errors.len(), 1, "{errstr}");
+            assert_eq!(errors[0].text(), "Table is unclosed");
+            let kinds = out
+                .iter_children()
+                .filter(|t| !t.kind().is_trivia())
+                .map(|t| t.kind())
+                .collect::<Vec<_>>();
+            assert_eq!(kinds.len(), 2, "{next}: {kinds:?}");
+            assert_eq!(kinds[0], AstKind::FeatureNode);
+        }
+    }
+
+    #[test]
+    fn mistyped_nested_lookup_label_before_top_level_item() {
+        // only a label matching the feature tag means the lookup is unclosed
+        for next in [
+            "@foo = [a b];",
+            "markClass acute <anchor 0 0> @TOP;",
+            "lookup two { sub a by b; } two;",
+        ] {
+            let fea =
+                format!("feature liga {{ lookup one {{ sub f i by f_i; }} on;\n{next}\n}} liga;");
+            let (out, errors, errstr) = debug_parse_output(&fea, root);
+            assert_eq!(errors.len(), 2, "{errstr}");
+            assert_eq!(
+                errors[0].text(),
+                "closing label 'on' does not match opening label 'one'"
+            );
+            let kinds = out
+                .iter_children()
+                .filter(|t| !t.kind().is_trivia())
+                .map(|t| t.kind())
+                .collect::<Vec<_>>();
+            assert_eq!(kinds, [AstKind::FeatureNode], "{next}");
+        }
+    }
+
+    #[test]
+    fn mismatched_closing_names() {
+        let fea = "\
+feature kern { pos a b 10; } kren;
+lookup foo { sub a by b; } bar;
+table GDEF { GlyphClassDef [a],,,; } GDFE;
+feature liga { lookup one { sub f i by f_i; } on; sub a by c; } liga;
+feature calt { sub a by b; } calt;";
+        let (out, errors, errstr) = debug_parse_output(fea, root);
+        let messages = errors
+            .iter()
+            .map(|err| (err.level, err.text()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                (
+                    Level::Error,
+                    "closing tag 'kren' does not match opening tag 'kern'"
+                ),
+                (Level::Info, "the block is opened with 'kern' here"),
+                (
+                    Level::Error,
+                    "closing label 'bar' does not match opening label 'foo'"
+                ),
+                (Level::Info, "the block is opened with 'foo' here"),
+                (
+                    Level::Error,
+                    "closing tag 'GDFE' does not match opening tag 'GDEF'"
+                ),
+                (Level::Info, "the block is opened with 'GDEF' here"),
+                (
+                    Level::Error,
+                    "closing label 'on' does not match opening label 'one'"
+                ),
+                (Level::Info, "the block is opened with 'one' here"),
+            ],
+            "{errstr}"
+        );
+        assert_eq!(&fea[errors[0].span()], "kren");
+        assert_eq!(&fea[errors[1].span()], "kern");
+
+        // every block is still parsed
+        let kinds = out
+            .iter_children()
+            .filter(|t| !t.kind().is_trivia())
+            .map(|t| t.kind())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                AstKind::FeatureNode,
+                AstKind::LookupBlockNode,
+                AstKind::GdefTableNode,
+                AstKind::FeatureNode,
+                AstKind::FeatureNode,
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_closing_tag_single_error() {
+        let fea = "feature kern { pos a b 10; } ;\nfeature liga { sub f i by f_i; } liga;";
+        let (_out, errors, errstr) = debug_parse_output(fea, root);
+        assert_eq!(errors.len(), 1, "{errstr}");
+        assert_eq!(errors[0].text(), "expected closing tag 'kern'");
+    }
+
+    #[test]
+    fn unexpected_token_in_cv_params() {
+        let fea = "feature cv01 { cvParameters { i } ; } cv01;";
+        let (_out, errors, errstr) = debug_parse_output(fea, root);
+        assert!(!errors.is_empty(), "{errstr}");
+        assert_eq!(
+            errors[0].text(),
+            "Expected 'Character' or a name id keyword"
+        );
+    }
+
+    // inputs that used to hang or panic the parser
+    #[test]
+    fn malformed_input_is_an_error() {
+        for fea in [
+            "feature aalt { sub [a] -; } aalt;",
+            "table mark } mark",
+            "valueRecordDef <1 2 3 4> KERN;",
+            "feature mark { pos base <anchor 0 0> mark @TOP; } mark;",
+            "feature liga { pos ligature <anchor 0 0> mark @TOP; } liga;",
+            "feature curs { pos cursive <anchor 0 0> <anchor 1 1>; } curs;",
+        ] {
+            let (_out, errors, _errstr) = debug_parse_output(fea, root);
+            assert!(!errors.is_empty(), "{fea}");
+        }
+    }
 }
@@ -40,15 +40,15 @@ pub(crate) fn table(parser: &mut Parser) {
     };
 
     match tag.tag {
-        tags::BASE => table_impl(parser, tags::BASE, base::table_entry),
-        tags::GDEF => table_impl(parser, tags::GDEF, gdef::table_entry),
-        tags::head => table_impl(parser, tags::head, head::table_entry),
-        tags::hhea => table_impl(parser, tags::hhea, hhea::table_entry),
-        tags::name => table_impl(parser, tags::name, name::table_entry),
-        tags::OS2 => table_impl(parser, tags::OS2, os2::table_entry),
-        tags::vhea => table_impl(parser, tags::vhea, vhea::table_entry),
-        tags::vmtx => table_impl(parser, tags::vmtx, vmtx::table_entry),
-        tags::STAT => table_impl(parser, tags::STAT, stat::table_entry),
+        tags::BASE => table_impl(parser, tag.range.clone(), base::table_entry),
+        tags::GDEF => table_impl(parser, tag.range.clone(), gdef::table_entry),
+        tags::head => table_impl(parser, tag.range.clone(), head::table_entry),
+        tags::hhea => table_impl(parser, tag.range.clone(), hhea::table_entry),
+        tags::name => table_impl(parser, tag.range.clone(), name::table_entry),
+        tags::OS2 => table_impl(parser, tag.range.clone(), os2::table_entry),
+        tags::vhea => table_impl(parser, tag.range.clone(), vhea::table_entry),
+        tags::vmtx => table_impl(parser, tag.range.clone(), vmtx::table_entry),
+        tags::STAT => table_impl(parser, tag.range.clone(), stat::table_entry),
         _ => unknown_table(parser, tag.range),
     }
 
@@ -57,20 +57,18 @@ pub(crate) fn table(parser: &mut Parser) {
 }
 
 // build any table, given a function that parses items from that table.
-fn table_impl(parser: &mut Parser, tag: Tag, table_fn: impl Fn(&mut Parser, TokenSet)) {
+fn table_impl(
+    parser: &mut Parser,
+    open_tag: Range<usize>,
+    table_fn: impl Fn(&mut Parser, TokenSet),
+) {
     parser.expect_recover(Kind::LBrace, TokenSet::TOP_SEMI);
     while !parser.at_eof() && !parser.matches(0, TokenSet::TOP_LEVEL.add(Kind::RBrace)) {
         table_fn(parser, TokenSet::TOP_LEVEL);
     }
 
     parser.expect_recover(Kind::RBrace, TokenSet::TOP_SEMI);
-    if let Some(close) = parser.expect_tag(TokenSet::TOP_SEMI) {
-        if close.tag != tag {
-            parser.raw_error(close.range, format!("expected tag '{}'", tag));
-        }
-    }
-
-    parser.expect_semi();
+    parser.expect_closing_name(AstKind::Tag, Some(open_tag));
 }
 
 // if we don't recognize a table tag we still want to try parsing.
@@ -78,7 +76,9 @@ fn unknown_table(parser: &mut Parser, open_tag: Range<usize>) {
     loop {
         match parser.nth(0).kind {
             Kind::RBrace if parser.nth_raw(1) == parser.raw_range(open_tag.clone()) => {
-                assert!(parser.eat(Kind::RBrace) && parser.eat(Kind::Ident));
+                assert!(parser.eat(Kind::RBrace));
+                // the closing tag may also be a keyword, such as 'mark'
+                parser.expect_tag(TokenSet::TOP_SEMI);
                 parser.expect_semi();
                 break;
             }
@@ -319,19 +319,18 @@ mod os2 {
     const NUM_LISTS: TokenSet =
         TokenSet::new(&[Kind::PanoseKw, Kind::UnicodeRangeKw, Kind::CodePageRangeKw]);
 
+    const NUMBERS: TokenSet = TokenSet::new(&[
+        Kind::FsTypeKw,
+        Kind::LowerOpSizeKw,
+        Kind::UpperOpSizeKw,
+        Kind::WeightClassKw,
+        Kind::WidthClassKw,
+    ]);
+
     const OS2_KEYWORDS: TokenSet = METRICS
         .union(NUM_LISTS)
-        .union(TokenSet::new(&[Kind::VendorKw]));
-
-    // these are not keywords per the spec, so we have to handle them specially??
-    static RAW_KEYWORDS: &[&[u8]] = &[
-        //b"FamilyClass",
-        b"FSType",
-        b"LowerOpSize",
-        b"WeightClass",
-        b"WidthClass",
-        b"UpperOpSize",
-    ];
+        .union(NUMBERS)
+        .union(TokenSet::new(&[Kind::VendorKw, Kind::FamilyClassKw]));
 
     pub(crate) fn table_entry(parser: &mut Parser, recovery: TokenSet) {
         let recovery = recovery.union(OS2_KEYWORDS);
@@ -355,15 +354,15 @@ mod os2 {
                 parser.expect_recover(Kind::String, recovery_semi);
                 parser.expect_semi();
             })
-        } else if RAW_KEYWORDS.contains(&parser.nth_raw(0)) {
+        } else if parser.matches(0, NUMBERS) {
             parser.in_node(AstKind::NumberValueNode, |parser| {
-                parser.eat_raw();
+                assert!(parser.eat(NUMBERS));
                 parser.expect_recover(Kind::Number, recovery_semi);
                 parser.expect_semi();
             })
-        } else if parser.nth_raw(0) == b"FamilyClass" {
+        } else if parser.matches(0, Kind::FamilyClassKw) {
             parser.in_node(AstKind::Os2FamilyClassNode, |parser| {
-                parser.eat_raw();
+                assert!(parser.eat(Kind::FamilyClassKw));
                 parser.expect_recover(TokenSet::NUM_TYPES, recovery.union(TokenSet::SEMI));
                 parser.expect_semi();
             })
@@ -5,8 +5,10 @@
 //! keyword from a glyph name. Instead we are just describing the most basic
 //! structure of the document.
 
+mod keywords;
 mod lexeme;
 mod token_set;
+pub(crate) use keywords::spellings as keyword_spellings;
 pub(crate) use lexeme::{Kind, Lexeme};
 pub use token_set::TokenSet;
 
@@ -46,9 +48,23 @@ impl<'a> Lexer<'a> {
 
     pub(crate) fn next_token(&mut self) -> Lexeme {
         let start_pos = self.pos;
-        let first = self.bump().unwrap_or(EOF);
+        // a byte order mark is trivia, so that offsets still match the source
+        if start_pos == 0 && self.input.starts_with('\u{feff}') {
+            self.pos = '\u{feff}'.len_utf8();
+            self.whitespace();
+            return Lexeme {
+                len: self.pos,
+                kind: Kind::Whitespace,
+            };
+        }
+        // a NUL byte in the input is not the end of the input
+        let Some(first) = self.bump() else {
+            return Lexeme {
+                len: 0,
+                kind: Kind::Eof,
+            };
+        };
         let kind = match first {
-            EOF => Kind::Eof,
             byte if is_ascii_whitespace(byte) => self.whitespace(),
             b'#' => self.comment(),
             b'"' => self.string(),
@@ -89,7 +105,8 @@ impl<'a> Lexer<'a> {
     }
 
     fn comment(&mut self) -> Kind {
-        while ![b'\n', EOF].contains(&self.nth(0)) {
+        // the line ending, including a '\r' before the '\n', is whitespace
+        while ![b'\n', EOF].contains(&self.nth(0)) && (self.nth(0), self.nth(1)) != (b'\r', b'\n') {
             self.bump();
         }
         Kind::Comment
@@ -189,6 +206,9 @@ impl<'a> Lexer<'a> {
             match self.nth(0) {
                 EOF => break,
                 b if is_ascii_whitespace(b) => break,
+                // a glyph name can't contain a backslash, so this is a range
+                // whose last glyph is escaped, as in 'a-\c'
+                b'-' if self.nth(1) == b'\\' => break,
                 b'-' => (),
                 b if is_special(b) => break,
                 _ => (),
@@ -225,7 +245,6 @@ pub(crate) fn tokenize(text: &str) -> Vec<Lexeme> {
     iter_tokens(text).collect()
 }
 
-#[cfg(test)]
 pub(crate) fn iter_tokens(text: &str) -> impl Iterator<Item = Lexeme> + '_ {
     let mut cursor = Lexer::new(text);
     std::iter::from_fn(move || {
@@ -238,6 +257,9 @@ pub(crate) fn iter_tokens(text: &str) -> impl Iterator<Item = Lexeme> + '_ {
 }
 
 // [\ , ' - ; < = > @ \ ( ) [ ] { }]
+//
+// other implementations of this (a match, a binary search and a linear scan)
+// are compared in the `is_special` group in benches/lexing.rs; this one wins.
 fn is_special(byte: u8) -> bool {
     (39..=45).contains(&byte)
         || (59..=64).contains(&byte)
@@ -277,35 +299,6 @@ pub(crate) fn debug_tokens2(tokens: &[Lexeme], src: &str) -> Vec<String> {
     result
 }
 
-// microbenchmarks to do, one day. Who do you think will win??
-
-//fn is_special_match(byte: u8) -> bool {
-//match byte {
-//b';' | b',' | b'@' | b'\\' | b'-' | b'=' | b'{' | b'}' | b'[' | b']' | b'(' | b')'
-//| b'<' | b'>' | b'\'' => true,
-//_ => false,
-//}
-//}
-
-//fn is_special_ranges(byte: u8) -> bool {
-//(39..=45).contains(&byte)
-//|| (59..=64).contains(&byte)
-//|| (91..=93).contains(&byte)
-//|| byte == 123
-//|| byte == 125
-//}
-
-//fn is_special_bsearch(byte: u8) -> bool {
-//[39, 40, 41, 44, 45, 59, 60, 61, 62, 64, 91, 92, 93, 123, 125]
-//.binary_search(&byte)
-//.is_ok()
-//}
-
-//// could improve this by sorting by frequency
-//fn is_special_linear_scan(byte: u8) -> bool {
-//[39, 40, 41, 44, 45, 59, 60, 61, 62, 64, 91, 92, 93, 123, 125].contains(&byte)
-//}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -323,6 +316,26 @@ mod tests {
         assert_eq!(token_strs[5], "10..12 ID");
     }
 
+    #[test]
+    fn bom_and_crlf() {
+        let fea = "\u{feff}\r\n# hi\r\nsub a;\r\n";
+        let tokens = tokenize(fea);
+        let token_strs = debug_tokens(&tokens);
+        assert_eq!(
+            token_strs,
+            [
+                "0..5 WS",
+                "5..9 #",
+                "9..11 WS",
+                "11..14 SubKw",
+                "14..15 WS",
+                "15..16 ID",
+                "16..17 ;",
+                "17..19 WS"
+            ]
+        );
+    }
+
     #[test]
     fn numbers() {
         let fea = "0 001 10 1. 1.0 -1 -1. -1.5";
@@ -352,6 +365,15 @@ mod tests {
         assert_eq!(token_strs[10], "ID(ff)");
     }
 
+    #[test]
+    fn nul_is_not_eof() {
+        let fea = "a\0b \0";
+        let tokens = tokenize(fea);
+        assert_eq!(tokens.iter().map(|t| t.len).sum::<usize>(), fea.len());
+        let token_strs = debug_tokens2(&tokens, fea);
+        assert_eq!(token_strs, ["ID(a)", "ID(\0b)", "WS( )", "ID(\0)"]);
+    }
+
     #[test]
     fn languagesystem() {
         let fea = "languagesystem dflt cool;";
@@ -404,6 +426,32 @@ mod tests {
         assert_eq!(token_strs[16], ";");
     }
 
+    #[test]
+    fn range_with_escaped_last_glyph() {
+        let fea = "[a-\\c \\a-\\c a-b-c]";
+        let tokens = tokenize(fea);
+        let token_strs = debug_tokens2(&tokens, fea);
+        assert_eq!(
+            token_strs,
+            [
+                "[",
+                "ID(a)",
+                "-",
+                "\\",
+                "ID(c)",
+                "WS( )",
+                "\\",
+                "ID(a)",
+                "-",
+                "\\",
+                "ID(c)",
+                "WS( )",
+                "ID(a-b-c)",
+                "]"
+            ]
+        );
+    }
+
     #[test]
     fn trivia() {
         let fea = "# OpenType 4.h\n# -@,\nlanguagesystem DFLT cool;";
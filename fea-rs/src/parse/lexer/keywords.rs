@@ -0,0 +1,259 @@
+//! Recognizing keywords
+//!
+//! Keywords are looked up in a perfect hash table, which is generated at
+//! compile time from the list of keywords. The hash only looks at the length
+//! and the first and last bytes of a word (which are enough to tell every
+//! keyword apart) so it is cheap to compute; most identifiers are not keywords,
+//! and lookups for them usually end at an empty slot.
+
+use super::{Kind, TokenSet};
+
+/// Every keyword, and the kind of token it is lexed as.
+const KEYWORDS: &[(&[u8], Kind)] = &[
+    (b"anchor", Kind::AnchorKw),
+    (b"anchorDef", Kind::AnchorDefKw),
+    (b"anon", Kind::AnonKw),
+    (b"anonymous", Kind::AnonKw),
+    (b"by", Kind::ByKw),
+    (b"contourpoint", Kind::ContourpointKw),
+    (b"cursive", Kind::CursiveKw),
+    (b"device", Kind::DeviceKw), //[ Not implemented ];
+    (b"enum", Kind::EnumKw),
+    (b"enumerate", Kind::EnumKw),
+    (b"exclude_dflt", Kind::ExcludeDfltKw),
+    (b"excludeDFLT", Kind::ExcludeDfltKw),
+    (b"feature", Kind::FeatureKw), //(used as a block and as a statement);
+    (b"from", Kind::FromKw),
+    (b"ignore", Kind::IgnoreKw), //(used with substitute and position);
+    (b"IgnoreBaseGlyphs", Kind::IgnoreBaseGlyphsKw),
+    (b"IgnoreLigatures", Kind::IgnoreLigaturesKw),
+    (b"IgnoreMarks", Kind::IgnoreMarksKw),
+    (b"include", Kind::IncludeKw),
+    (b"include_dflt", Kind::IncludeDfltKw),
+    (b"includeDFLT", Kind::IncludeDfltKw),
+    (b"language", Kind::LanguageKw),
+    (b"languagesystem", Kind::LanguagesystemKw),
+    (b"lookup", Kind::LookupKw),
+    (b"lookupflag", Kind::LookupflagKw),
+    (b"mark", Kind::MarkKw),
+    (b"MarkAttachmentType", Kind::MarkAttachmentTypeKw),
+    (b"markClass", Kind::MarkClassKw),
+    (b"nameid", Kind::NameIdKw),
+    (b"NULL", Kind::NullKw), //(used in substitute, device, value record, anchor);
+    (b"parameters", Kind::ParametersKw),
+    (b"pos", Kind::PosKw),
+    (b"position", Kind::PosKw),
+    (b"required", Kind::RequiredKw), //[ Not implemented ];
+    (b"reversesub", Kind::RsubKw),
+    (b"rsub", Kind::RsubKw),
+    (b"RightToLeft", Kind::RightToLeftKw),
+    (b"script", Kind::ScriptKw),
+    (b"substitute", Kind::SubKw),
+    (b"sub", Kind::SubKw),
+    (b"subtable", Kind::SubtableKw),
+    (b"table", Kind::TableKw),
+    (b"useExtension", Kind::UseExtensionKw),
+    (b"UseMarkFilteringSet", Kind::UseMarkFilteringSetKw),
+    (b"valueRecordDef", Kind::ValueRecordDefKw),
+    (b"HorizAxis.BaseScriptList", Kind::HorizAxisBaseScriptListKw),
+    (b"HorizAxis.BaseTagList", Kind::HorizAxisBaseTagListKw),
+    (b"HorizAxis.MinMax", Kind::HorizAxisMinMaxKw),
+    (b"VertAxis.BaseScriptList", Kind::VertAxisBaseScriptListKw),
+    (b"VertAxis.BaseTagList", Kind::VertAxisBaseTagListKw),
+    (b"VertAxis.MinMax", Kind::VertAxisMinMaxKw),
+    (b"Attach", Kind::AttachKw),
+    (b"GlyphClassDef", Kind::GlyphClassDefKw),
+    (b"LigatureCaretByDev", Kind::LigatureCaretByDevKw),
+    (b"LigatureCaretByIndex", Kind::LigatureCaretByIndexKw),
+    (b"LigatureCaretByPos", Kind::LigatureCaretByPosKw),
+    (b"MarkAttachClass", Kind::MarkAttachClassKw),
+    (b"FontRevision", Kind::FontRevisionKw),
+    (b"Ascender", Kind::AscenderKw),
+    (b"CaretOffset", Kind::CaretOffsetKw),
+    (b"Descender", Kind::DescenderKw),
+    (b"LineGap", Kind::LineGapKw),
+    (b"CapHeight", Kind::CapHeightKw),
+    (b"CodePageRange", Kind::CodePageRangeKw),
+    (b"Panose", Kind::PanoseKw),
+    (b"TypoAscender", Kind::TypoAscenderKw),
+    (b"TypoDescender", Kind::TypoDescenderKw),
+    (b"TypoLineGap", Kind::TypoLineGapKw),
+    (b"UnicodeRange", Kind::UnicodeRangeKw),
+    (b"Vendor", Kind::VendorKw),
+    (b"winAscent", Kind::WinAscentKw),
+    (b"winDescent", Kind::WinDescentKw),
+    (b"XHeight", Kind::XHeightKw),
+    (b"FamilyClass", Kind::FamilyClassKw),
+    (b"FSType", Kind::FsTypeKw),
+    (b"LowerOpSize", Kind::LowerOpSizeKw),
+    (b"UpperOpSize", Kind::UpperOpSizeKw),
+    (b"WeightClass", Kind::WeightClassKw),
+    (b"WidthClass", Kind::WidthClassKw),
+    (b"sizemenuname", Kind::SizemenunameKw),
+    (b"VertTypoAscender", Kind::VertTypoAscenderKw),
+    (b"VertTypoDescender", Kind::VertTypoDescenderKw),
+    (b"VertTypoLineGap", Kind::VertTypoLineGapKw),
+    (b"VertAdvanceY", Kind::VertAdvanceYKw),
+    (b"VertOriginY", Kind::VertOriginYKw),
+    (b"ElidedFallbackName", Kind::ElidedFallbackNameKw),
+    (b"ElidedFallbackNameID", Kind::ElidedFallbackNameIDKw),
+    (b"DesignAxis", Kind::DesignAxisKw),
+    (b"AxisValue", Kind::AxisValueKw),
+    (b"flag", Kind::FlagKw),
+    (b"location", Kind::LocationKw),
+    (b"ElidableAxisValueName", Kind::ElidableAxisValueNameKw),
+    (
+        b"OlderSiblingFontAttribute",
+        Kind::OlderSiblingFontAttributeKw,
+    ),
+    (b"featureNames", Kind::FeatureNamesKw),
+    (b"name", Kind::NameKw),
+    (b"cvParameters", Kind::CvParametersKw),
+    (b"Character", Kind::CharacterKw),
+    (b"FeatUILabelNameID", Kind::FeatUiLabelNameIdKw),
+    (b"FeatUITooltipTextNameID", Kind::FeatUiTooltipTextNameIdKw),
+    (b"SampleTextNameID", Kind::SampleTextNameIdKw),
+    (b"ParamUILabelNameID", Kind::ParamUiLabelNameIdKw),
+];
+
+// the number of slots in the table; with more slots, a seed that gives a
+// perfect hash is found more quickly.
+const TABLE_BITS: u32 = 10;
+const TABLE_SIZE: usize = 1 << TABLE_BITS;
+// anything longer than this can be rejected without hashing it
+const MAX_LEN: usize = max_len(KEYWORDS);
+
+static TABLE: KeywordTable = KeywordTable::new(KEYWORDS);
+
+/// Return the kind of this keyword, if it is one.
+pub(crate) fn lookup(word: &[u8]) -> Option<Kind> {
+    TABLE.get(word)
+}
+
+struct KeywordTable {
+    seed: u32,
+    // the index of the keyword in each slot, plus one; zero for empty slots
+    slots: [u8; TABLE_SIZE],
+}
+
+impl KeywordTable {
+    /// Find a seed for which no two keywords hash to the same slot.
+    const fn new(keywords: &[(&[u8], Kind)]) -> Self {
+        assert!(keywords.len() < u8::MAX as usize);
+        let mut seed = 0;
+        while seed < 10_000 {
+            if let Some(slots) = Self::try_seed(keywords, seed) {
+                return KeywordTable { seed, slots };
+            }
+            seed += 1;
+        }
+        panic!("no perfect hash found for keywords; is a keyword listed twice?")
+    }
+
+    const fn try_seed(keywords: &[(&[u8], Kind)], seed: u32) -> Option<[u8; TABLE_SIZE]> {
+        let mut slots = [0; TABLE_SIZE];
+        let mut i = 0;
+        while i < keywords.len() {
+            let slot = slot(keywords[i].0, seed);
+            if slots[slot] != 0 {
+                return None;
+            }
+            slots[slot] = i as u8 + 1;
+            i += 1;
+        }
+        Some(slots)
+    }
+
+    fn get(&self, word: &[u8]) -> Option<Kind> {
+        if word.len() > MAX_LEN {
+            return None;
+        }
+        let idx = self.slots[slot(word, self.seed)].checked_sub(1)?;
+        let (keyword, kind) = KEYWORDS[idx as usize];
+        (keyword == word).then_some(kind)
+    }
+}
+
+/// Hash the length and the first and last bytes of a word, to a slot in the table.
+const fn slot(word: &[u8], seed: u32) -> usize {
+    let (first, last) = match word {
+        [] => (0, 0),
+        [first, .., last] => (*first, *last),
+        [only] => (*only, *only),
+    };
+    let key = (word.len() as u32) | (first as u32) << 8 | (last as u32) << 16;
+    ((key ^ seed).wrapping_mul(0x9e37_79b1) >> (32 - TABLE_BITS)) as usize
+}
+
+const fn max_len(keywords: &[(&[u8], Kind)]) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < keywords.len() {
+        if keywords[i].0.len() > max {
+            max = keywords[i].0.len();
+        }
+        i += 1;
+    }
+    max
+}
+
+/// Every spelling of the keywords in this set.
+pub(crate) fn spellings(set: TokenSet) -> impl Iterator<Item = &'static str> {
+    KEYWORDS
+        .iter()
+        .filter(move |(_, kind)| set.contains(*kind))
+        .map(|(word, _)| std::str::from_utf8(word).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_keywords() {
+        for (keyword, kind) in KEYWORDS {
+            assert_eq!(
+                lookup(keyword),
+                Some(*kind),
+                "{}",
+                String::from_utf8_lossy(keyword)
+            );
+        }
+    }
+
+    #[test]
+    fn keyword_kinds() {
+        for (keyword, kind) in KEYWORDS {
+            let kind = kind.to_token_kind();
+            assert!(kind.is_keyword(), "{}", String::from_utf8_lossy(keyword));
+        }
+        use crate::Kind as AstKind;
+        for kind in [
+            AstKind::Ident,
+            AstKind::NamedGlyphClass,
+            AstKind::Path,
+            AstKind::GlyphName,
+        ] {
+            assert!(!kind.is_keyword(), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn not_keywords() {
+        for word in [
+            "",
+            "a",
+            "f_i",
+            "Lookup",
+            "lookups",
+            "looku",
+            "HorizAxis",
+            "HorizAxis.BaseTagLists",
+            "VertAxis.Base",
+            "WeightClasses",
+            "OlderSiblingFontAttributes",
+        ] {
+            assert_eq!(lookup(word.as_bytes()), None, "{word}");
+        }
+    }
+}
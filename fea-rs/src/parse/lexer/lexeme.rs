@@ -127,6 +127,12 @@ pub enum Kind {
     WinAscentKw,                 //OS/2 table
     WinDescentKw,                //OS/2 table
     XHeightKw,                   //OS/2 table
+    FamilyClassKw,               //OS/2 table
+    FsTypeKw,                    //OS/2 table
+    LowerOpSizeKw,               //OS/2 table
+    UpperOpSizeKw,               //OS/2 table
+    WeightClassKw,               //OS/2 table
+    WidthClassKw,                //OS/2 table
     SizemenunameKw,              //size feature
     VertTypoAscenderKw,          //vhea table
     VertTypoDescenderKw,         //vhea table
@@ -181,97 +187,7 @@ impl Kind {
     }
 
     pub(crate) fn from_keyword(word: &[u8]) -> Option<Kind> {
-        match word {
-            b"anchor" => Some(Kind::AnchorKw),
-            b"anchorDef" => Some(Kind::AnchorDefKw),
-            b"anon" | b"anonymous" => Some(Kind::AnonKw),
-            b"by" => Some(Kind::ByKw),
-            b"contourpoint" => Some(Kind::ContourpointKw),
-            b"cursive" => Some(Kind::CursiveKw),
-            b"device" => Some(Kind::DeviceKw), //[ Not implemented ];
-            b"enum" | b"enumerate" => Some(Kind::EnumKw),
-            b"exclude_dflt" | b"excludeDFLT" => Some(Kind::ExcludeDfltKw),
-            b"feature" => Some(Kind::FeatureKw), //(used as a block and as a statement);
-            b"from" => Some(Kind::FromKw),
-            b"ignore" => Some(Kind::IgnoreKw), //(used with substitute and position);
-            b"IgnoreBaseGlyphs" => Some(Kind::IgnoreBaseGlyphsKw),
-            b"IgnoreLigatures" => Some(Kind::IgnoreLigaturesKw),
-            b"IgnoreMarks" => Some(Kind::IgnoreMarksKw),
-            b"include" => Some(Kind::IncludeKw),
-            b"include_dflt" | b"includeDFLT" => Some(Kind::IncludeDfltKw),
-            b"language" => Some(Kind::LanguageKw),
-            b"languagesystem" => Some(Kind::LanguagesystemKw),
-            b"lookup" => Some(Kind::LookupKw),
-            b"lookupflag" => Some(Kind::LookupflagKw),
-            b"mark" => Some(Kind::MarkKw),
-            b"MarkAttachmentType" => Some(Kind::MarkAttachmentTypeKw),
-            b"markClass" => Some(Kind::MarkClassKw),
-            b"nameid" => Some(Kind::NameIdKw),
-            b"NULL" => Some(Kind::NullKw), //(used in substitute, device, value record, anchor);
-            b"parameters" => Some(Kind::ParametersKw),
-            b"pos" | b"position" => Some(Kind::PosKw),
-            b"required" => Some(Kind::RequiredKw), //[ Not implemented ];
-            b"reversesub" | b"rsub" => Some(Kind::RsubKw),
-            b"RightToLeft" => Some(Kind::RightToLeftKw),
-            b"script" => Some(Kind::ScriptKw),
-            b"substitute" | b"sub" => Some(Kind::SubKw),
-            b"subtable" => Some(Kind::SubtableKw),
-            b"table" => Some(Kind::TableKw),
-            b"useExtension" => Some(Kind::UseExtensionKw),
-            b"UseMarkFilteringSet" => Some(Kind::UseMarkFilteringSetKw),
-            b"valueRecordDef" => Some(Kind::ValueRecordDefKw),
-            b"HorizAxis.BaseScriptList" => Some(Kind::HorizAxisBaseScriptListKw),
-            b"HorizAxis.BaseTagList" => Some(Kind::HorizAxisBaseTagListKw),
-            b"HorizAxis.MinMax" => Some(Kind::HorizAxisMinMaxKw),
-            b"VertAxis.BaseScriptList" => Some(Kind::VertAxisBaseScriptListKw),
-            b"VertAxis.BaseTagList" => Some(Kind::VertAxisBaseTagListKw),
-            b"VertAxis.MinMax" => Some(Kind::VertAxisMinMaxKw),
-            b"Attach" => Some(Kind::AttachKw),
-            b"GlyphClassDef" => Some(Kind::GlyphClassDefKw),
-            b"LigatureCaretByDev" => Some(Kind::LigatureCaretByDevKw),
-            b"LigatureCaretByIndex" => Some(Kind::LigatureCaretByIndexKw),
-            b"LigatureCaretByPos" => Some(Kind::LigatureCaretByPosKw),
-            b"MarkAttachClass" => Some(Kind::MarkAttachClassKw),
-            b"FontRevision" => Some(Kind::FontRevisionKw),
-            b"Ascender" => Some(Kind::AscenderKw),
-            b"CaretOffset" => Some(Kind::CaretOffsetKw),
-            b"Descender" => Some(Kind::DescenderKw),
-            b"LineGap" => Some(Kind::LineGapKw),
-            b"CapHeight" => Some(Kind::CapHeightKw),
-            b"CodePageRange" => Some(Kind::CodePageRangeKw),
-            b"Panose" => Some(Kind::PanoseKw),
-            b"TypoAscender" => Some(Kind::TypoAscenderKw),
-            b"TypoDescender" => Some(Kind::TypoDescenderKw),
-            b"TypoLineGap" => Some(Kind::TypoLineGapKw),
-            b"UnicodeRange" => Some(Kind::UnicodeRangeKw),
-            b"Vendor" => Some(Kind::VendorKw),
-            b"winAscent" => Some(Kind::WinAscentKw),
-            b"winDescent" => Some(Kind::WinDescentKw),
-            b"XHeight" => Some(Kind::XHeightKw),
-            b"sizemenuname" => Some(Kind::SizemenunameKw),
-            b"VertTypoAscender" => Some(Kind::VertTypoAscenderKw),
-            b"VertTypoDescender" => Some(Kind::VertTypoDescenderKw),
-            b"VertTypoLineGap" => Some(Kind::VertTypoLineGapKw),
-            b"VertAdvanceY" => Some(Kind::VertAdvanceYKw),
-            b"VertOriginY" => Some(Kind::VertOriginYKw),
-            b"ElidedFallbackName" => Some(Kind::ElidedFallbackNameKw),
-            b"ElidedFallbackNameID" => Some(Kind::ElidedFallbackNameIDKw),
-            b"DesignAxis" => Some(Kind::DesignAxisKw),
-            b"AxisValue" => Some(Kind::AxisValueKw),
-            b"flag" => Some(Kind::FlagKw),
-            b"location" => Some(Kind::LocationKw),
-            b"ElidableAxisValueName" => Some(Kind::ElidableAxisValueNameKw),
-            b"OlderSiblingFontAttribute" => Some(Kind::OlderSiblingFontAttributeKw),
-            b"featureNames" => Some(Kind::FeatureNamesKw),
-            b"name" => Some(Kind::NameKw),
-            b"cvParameters" => Some(Kind::CvParametersKw),
-            b"Character" => Some(Kind::CharacterKw),
-            b"FeatUILabelNameID" => Some(Kind::FeatUiLabelNameIdKw),
-            b"FeatUITooltipTextNameID" => Some(Kind::FeatUiTooltipTextNameIdKw),
-            b"SampleTextNameID" => Some(Kind::SampleTextNameIdKw),
-            b"ParamUILabelNameID" => Some(Kind::ParamUiLabelNameIdKw),
-            _ => None,
-        }
+        super::keywords::lookup(word)
     }
 
     /// Convert this lex kind into the more robust token kind used in the rest
@@ -373,6 +289,12 @@ impl Kind {
             Self::WinAscentKw => AstKind::WinAscentKw,
             Self::WinDescentKw => AstKind::WinDescentKw,
             Self::XHeightKw => AstKind::XHeightKw,
+            Self::FamilyClassKw => AstKind::FamilyClassKw,
+            Self::FsTypeKw => AstKind::FsTypeKw,
+            Self::LowerOpSizeKw => AstKind::LowerOpSizeKw,
+            Self::UpperOpSizeKw => AstKind::UpperOpSizeKw,
+            Self::WeightClassKw => AstKind::WeightClassKw,
+            Self::WidthClassKw => AstKind::WidthClassKw,
             Self::SizemenunameKw => AstKind::SizemenunameKw,
             Self::VertTypoAscenderKw => AstKind::VertTypoAscenderKw,
             Self::VertTypoDescenderKw => AstKind::VertTypoDescenderKw,
@@ -499,6 +421,12 @@ impl std::fmt::Display for Kind {
             Self::WinAscentKw => write!(f, "winAscent"),
             Self::WinDescentKw => write!(f, "winDescent"),
             Self::XHeightKw => write!(f, "XHeight"),
+            Self::FamilyClassKw => write!(f, "FamilyClass"),
+            Self::FsTypeKw => write!(f, "FSType"),
+            Self::LowerOpSizeKw => write!(f, "LowerOpSize"),
+            Self::UpperOpSizeKw => write!(f, "UpperOpSize"),
+            Self::WeightClassKw => write!(f, "WeightClass"),
+            Self::WidthClassKw => write!(f, "WidthClass"),
             Self::SizemenunameKw => write!(f, "sizemenuname"),
             Self::VertTypoAscenderKw => write!(f, "VertTypoAscender"),
             Self::VertTypoDescenderKw => write!(f, "VertTypoDescender"),
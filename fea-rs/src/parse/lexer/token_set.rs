@@ -64,6 +64,12 @@ impl TokenSet {
         Kind::WinAscentKw,
         Kind::WinDescentKw,
         Kind::XHeightKw,
+        Kind::FamilyClassKw,
+        Kind::FsTypeKw,
+        Kind::LowerOpSizeKw,
+        Kind::UpperOpSizeKw,
+        Kind::WeightClassKw,
+        Kind::WidthClassKw,
         Kind::SizemenunameKw,
         Kind::VertTypoAscenderKw,
         Kind::VertTypoDescenderKw,
@@ -11,7 +11,7 @@ use super::{
 };
 use crate::token_tree::{AstSink, Kind};
 
-use crate::diagnostic::Diagnostic;
+use crate::diagnostic::{Diagnostic, Level};
 
 const LOOKAHEAD: usize = 4;
 const LOOKAHEAD_MAX: usize = LOOKAHEAD - 1;
@@ -359,6 +359,66 @@ impl<'b, 'a> Parser<'a, 'b> {
         None
     }
 
+    /// Eat the tag or label that closes a block, and the following semicolon.
+    ///
+    /// `kind` is either [`Kind::Tag`] or [`Kind::Label`], and `open` is the
+    /// range of the name that opened the block, if it was present. If the
+    /// closing name is different, we report an error pointing at both names;
+    /// either way the block is finished, so that parsing continues normally
+    /// with the next item.
+    pub(crate) fn expect_closing_name(&mut self, kind: Kind, open: Option<Range<usize>>) {
+        let (what, expected) = match kind {
+            Kind::Tag => ("tag", TokenSet::TAG_LIKE),
+            _ => ("label", TokenSet::IDENT_LIKE),
+        };
+        let open_name = open
+            .as_ref()
+            .map(|range| self.text[range.clone()].to_owned());
+
+        if !self.matches(0, expected) {
+            let message = match &open_name {
+                Some(name) => format!("expected closing {what} '{name}'"),
+                None => format!("expected closing {what}"),
+            };
+            // a missing name and semicolon is a single error
+            if self.matches(0, LexemeKind::Semi) {
+                self.err(message);
+                self.eat_raw();
+            } else {
+                self.err_before_ws(message);
+            }
+            return;
+        }
+
+        let close_range = self.nth_range(0);
+        let close_name = self.current_token_text().to_owned();
+        let valid = kind != Kind::Tag || Tag::new_checked(close_name.as_bytes()).is_ok();
+        match (open, open_name) {
+            (Some(open), Some(open_name)) if open_name != close_name => {
+                if valid {
+                    self.do_bump::<1>(kind);
+                } else {
+                    self.eat_raw();
+                }
+                self.raw_error(
+                    close_range,
+                    format!(
+                        "closing {what} '{close_name}' does not match opening {what} '{open_name}'"
+                    ),
+                );
+                self.sink.error(Diagnostic::new(
+                    Level::Info,
+                    FileId::CURRENT_FILE,
+                    open,
+                    format!("the block is opened with '{open_name}' here"),
+                ));
+            }
+            _ if !valid => self.err_and_bump("invalid tag"),
+            _ => self.do_bump::<1>(kind),
+        }
+        self.expect_semi();
+    }
+
     pub(crate) fn matches(&self, nth: usize, token: impl TokenComparable) -> bool {
         token.matches(self.nth(nth).kind)
     }
@@ -28,10 +28,43 @@ pub struct Source {
     id: FileId,
     /// The non-canonicalized path to this source, suitable for printing.
     path: OsString,
-    contents: Arc<str>,
-    /// The index of each newline character, for efficiently fetching lines
+    contents: SourceText,
+    /// The start of each line, for efficiently fetching lines
     /// (for error reporting, e.g.)
-    line_offsets: Arc<[usize]>,
+    line_index: LineIndex,
+}
+
+/// A position in a source, as a line and column.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+    /// The (1-indexed) line number.
+    pub line: usize,
+    /// The (0-indexed) utf-8 offset from the start of the line.
+    pub column: usize,
+}
+
+/// The positions of the lines in a source, for converting between byte
+/// offsets and lines and columns.
+///
+/// Lines may end with either `\n` or `\r\n`. A byte order mark at the start
+/// of the source is not part of the first line, so it does not shift the
+/// columns on that line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineIndex {
+    /// The offset of the start of each line
+    line_starts: Arc<[usize]>,
+    /// The total length of the text
+    len: usize,
+}
+
+/// The text of a source, either owned or memory-mapped.
+///
+/// A mapped source is validated as utf-8 when it is loaded.
+#[derive(Clone)]
+enum SourceText {
+    Owned(Arc<str>),
+    #[cfg(feature = "mmap")]
+    Mapped(Arc<memmap2::Mmap>),
 }
 
 /// A list of sources in a project.
@@ -135,6 +168,9 @@ where
 /// This is the common case.
 pub struct FileSystemResolver {
     project_root: PathBuf,
+    restrict_to_project_root: bool,
+    #[cfg(feature = "mmap")]
+    mmap: bool,
 }
 
 impl FileSystemResolver {
@@ -144,7 +180,36 @@ impl FileSystemResolver {
     /// cases, it is likely the directory containing the root feature file.
     /// If the path is empty (i.e. ""), the current working directory is assumed.
     pub fn new(project_root: PathBuf) -> Self {
-        Self { project_root }
+        Self {
+            project_root,
+            restrict_to_project_root: false,
+            #[cfg(feature = "mmap")]
+            mmap: false,
+        }
+    }
+
+    /// Refuse to load files outside of the project root.
+    ///
+    /// This is useful when compiling untrusted sources, which might otherwise
+    /// include arbitrary files. Paths are compared after resolving symlinks
+    /// and `..` components, and a file that is refused is reported as an
+    /// error at the include statement.
+    pub fn restrict_to_project_root(mut self, flag: bool) -> Self {
+        self.restrict_to_project_root = flag;
+        self
+    }
+
+    /// Memory-map source files instead of reading them into memory.
+    ///
+    /// This can reduce peak memory use when parsing very large (generated)
+    /// feature files.
+    ///
+    /// Note that the contents of a mapped file must not be modified while it
+    /// is in use; if this cannot be guaranteed, do not use this option.
+    #[cfg(feature = "mmap")]
+    pub fn use_mmap(mut self, flag: bool) -> Self {
+        self.mmap = flag;
+        self
     }
 }
 
@@ -162,9 +227,39 @@ impl SourceResolver for FileSystemResolver {
     }
 
     fn canonicalize(&self, path: &OsStr) -> Result<OsString, SourceLoadError> {
-        std::fs::canonicalize(path)
-            .map_err(|io_err| SourceLoadError::new(path.into(), io_err))
-            .map(PathBuf::into_os_string)
+        let canonical = std::fs::canonicalize(path)
+            .map_err(|io_err| SourceLoadError::new(path.into(), io_err))?;
+        if self.restrict_to_project_root {
+            let root = match self.project_root.as_os_str() {
+                root if root.is_empty() => Path::new("."),
+                _ => self.project_root.as_path(),
+            };
+            let root = std::fs::canonicalize(root)
+                .map_err(|io_err| SourceLoadError::new(root.into(), io_err))?;
+            if !canonical.starts_with(&root) {
+                return Err(SourceLoadError::new(
+                    path.into(),
+                    format!("path is outside the project root '{}'", root.display()),
+                ));
+            }
+        }
+        Ok(canonical.into_os_string())
+    }
+
+    #[cfg(feature = "mmap")]
+    fn resolve(&self, path: &OsStr) -> Result<Source, SourceLoadError> {
+        if !self.mmap {
+            let contents = self.get_contents(path)?;
+            return Ok(Source::new(path.to_owned(), contents));
+        }
+        let file =
+            std::fs::File::open(path).map_err(|cause| SourceLoadError::new(path.into(), cause))?;
+        // safety: the caller has opted in to mapping, and is responsible for
+        // ensuring the file is not modified while we are using it.
+        let map = unsafe { memmap2::Mmap::map(&file) }
+            .map_err(|cause| SourceLoadError::new(path.into(), cause))?;
+        Source::new_mapped(path.to_owned(), map)
+            .map_err(|cause| SourceLoadError::new(path.into(), cause))
     }
 }
 
@@ -181,18 +276,35 @@ impl FileId {
 
 impl Source {
     pub(crate) fn new(path: impl Into<OsString>, contents: Arc<str>) -> Self {
-        let line_offsets = line_offsets(&contents);
+        let line_index = LineIndex::new(&contents);
         Source {
             path: path.into(),
             id: FileId::next(),
-            contents,
-            line_offsets,
+            contents: SourceText::Owned(contents),
+            line_index,
         }
     }
 
+    /// Create a source from a memory-mapped file.
+    ///
+    /// Returns an error if the file is not valid utf-8.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn new_mapped(
+        path: impl Into<OsString>,
+        map: memmap2::Mmap,
+    ) -> Result<Self, std::str::Utf8Error> {
+        let line_index = LineIndex::new(std::str::from_utf8(&map)?);
+        Ok(Source {
+            path: path.into(),
+            id: FileId::next(),
+            contents: SourceText::Mapped(Arc::new(map)),
+            line_index,
+        })
+    }
+
     /// The raw text for this source
     pub fn text(&self) -> &str {
-        &self.contents
+        self.contents.as_str()
     }
 
     /// The source's path.
@@ -209,33 +321,26 @@ impl Source {
         self.id
     }
 
+    /// The index of lines in this source.
+    pub fn line_index(&self) -> &LineIndex {
+        &self.line_index
+    }
+
     /// Compute the line and column for a given utf-8 offset.
     pub fn line_col_for_offset(&self, offset: usize) -> (usize, usize) {
-        let offset_idx = match self.line_offsets.binary_search(&offset) {
-            Ok(x) => x,
-            Err(x) => x - 1, // cannot underflow as 0 is always in list
-        };
-        let offset_of_line = self.line_offsets[offset_idx];
-        let offset_in_line = offset - offset_of_line;
-        (offset_idx + 1, offset_in_line)
+        let LineCol { line, column } = self.line_index.line_col(offset);
+        (line, column)
     }
 
-    /// returns the (1-indexed) number and text.
+    /// returns the (1-indexed) number and text, without the line ending.
     pub fn line_containing_offset(&self, offset: usize) -> (usize, &str) {
-        let offset_idx = match self.line_offsets.binary_search(&offset) {
-            Ok(x) => x,
-            Err(x) => x - 1, // cannot underflow as 0 is always in list
-        };
-        let start_offset = self.line_offsets[offset_idx];
-        let end_offset = self
-            .line_offsets
-            .get(offset_idx + 1)
-            .copied()
-            .unwrap_or(self.contents.len());
-
+        let line = self.line_index.line_col(offset).line;
+        let range = self.line_index.line_range(line).unwrap();
         (
-            offset_idx + 1,
-            self.contents[start_offset..end_offset].trim_end_matches('\n'),
+            line,
+            self.text()[range]
+                .trim_end_matches('\n')
+                .trim_end_matches('\r'),
         )
     }
 
@@ -243,19 +348,83 @@ impl Source {
     ///
     /// Panics if the line number exceeds the total number of lines in the file.
     pub fn offset_for_line_number(&self, line_number: usize) -> usize {
-        self.line_offsets[line_number - 1]
+        self.line_index.line_starts[line_number - 1]
+    }
+}
+
+impl SourceText {
+    fn as_str(&self) -> &str {
+        match self {
+            SourceText::Owned(text) => text,
+            // safety: we validate that the map is utf-8 when it is created
+            #[cfg(feature = "mmap")]
+            SourceText::Mapped(map) => unsafe { std::str::from_utf8_unchecked(map) },
+        }
     }
 }
 
-fn line_offsets(text: &str) -> Arc<[usize]> {
-    // we could use memchar for this; benefits would require benchmarking
-    let mut result = vec![0];
-    result.extend(
-        text.bytes()
-            .enumerate()
-            .filter_map(|(i, b)| if b == b'\n' { Some(i + 1) } else { None }),
-    );
-    result.into()
+impl Debug for SourceText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl LineIndex {
+    /// Index the lines of `text`.
+    pub fn new(text: &str) -> Self {
+        let first = if text.starts_with('\u{feff}') { 3 } else { 0 };
+        // we could use memchar for this; benefits would require benchmarking
+        let mut line_starts = vec![first];
+        line_starts.extend(text.bytes().enumerate().filter_map(|(i, b)| {
+            if b == b'\n' {
+                Some(i + 1)
+            } else {
+                None
+            }
+        }));
+        LineIndex {
+            line_starts: line_starts.into(),
+            len: text.len(),
+        }
+    }
+
+    /// The number of lines.
+    ///
+    /// Text that ends with a newline has an empty final line.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The line and column of a utf-8 offset.
+    ///
+    /// Offsets within a leading byte order mark are at the start of the first
+    /// line, and offsets past the end of the text are on the last line.
+    pub fn line_col(&self, offset: usize) -> LineCol {
+        let idx = self
+            .line_starts
+            .partition_point(|start| *start <= offset)
+            .saturating_sub(1);
+        LineCol {
+            line: idx + 1,
+            column: offset.saturating_sub(self.line_starts[idx]),
+        }
+    }
+
+    /// The utf-8 offset of a line and column.
+    ///
+    /// Returns `None` if the position is past the end of its line.
+    pub fn offset(&self, pos: LineCol) -> Option<usize> {
+        let range = self.line_range(pos.line)?;
+        let offset = range.start + pos.column;
+        (offset <= range.end).then_some(offset)
+    }
+
+    /// The range of a (1-indexed) line, including its line ending.
+    pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self.line_starts.get(line).copied().unwrap_or(self.len);
+        Some(start..end)
+    }
 }
 
 impl SourceMap {
@@ -267,18 +436,81 @@ impl SourceMap {
 
     /// panics if `global_range` crosses a file barrier?
     pub(crate) fn resolve_range(&self, global_range: Range<usize>) -> (FileId, Range<usize>) {
+        self.try_resolve_range(global_range).unwrap()
+    }
+
+    /// Like [`resolve_range`](Self::resolve_range), but returns `None` if the
+    /// start of `global_range` is not part of the tree.
+    pub(crate) fn try_resolve_range(
+        &self,
+        global_range: Range<usize>,
+    ) -> Option<(FileId, Range<usize>)> {
         // it is hard to imagine more than a couple hundred include statements,
         // and even that would be extremely rare, so I don't think it's really
         // worth doing a binary search here?
         let (chunk, (file, local_offset)) = self
             .offsets
             .iter()
-            .find(|item| item.0.contains(&global_range.start))
-            .unwrap();
+            .find(|item| item.0.contains(&global_range.start))?;
         let chunk_offset = global_range.start - chunk.start;
         let range_start = *local_offset + chunk_offset;
-        let len = global_range.end - global_range.start;
-        (*file, range_start..range_start + len)
+        let len = global_range.end.saturating_sub(global_range.start);
+        Some((*file, range_start..range_start + len))
+    }
+
+    /// A new map for a tree in which each of `replaced` (a range in this tree,
+    /// and the length of its replacement) has been replaced.
+    ///
+    /// The replacements are mapped to consecutive ranges of `file`, starting
+    /// at its beginning. `replaced` must be sorted, and must not overlap.
+    pub(crate) fn replace_ranges(&self, replaced: &[(Range<usize>, usize)], file: FileId) -> Self {
+        let mut result = SourceMap::default();
+        // copy the entries covering `old` in this map to `new_start`
+        let copy = |result: &mut SourceMap, old: Range<usize>, new_start: usize| {
+            for (chunk, (chunk_file, local_offset)) in &self.offsets {
+                let start = chunk.start.max(old.start);
+                let end = chunk.end.min(old.end);
+                if start < end {
+                    let new_pos = new_start + start - old.start;
+                    let local = local_offset + start - chunk.start;
+                    result.add_entry(new_pos..new_pos + end - start, (*chunk_file, local));
+                }
+            }
+        };
+        let mut old_pos = 0;
+        let mut new_pos = 0;
+        let mut generated_pos = 0;
+        for (range, len) in replaced {
+            copy(&mut result, old_pos..range.start, new_pos);
+            new_pos += range.start - old_pos;
+            result.add_entry(new_pos..new_pos + len, (file, generated_pos));
+            new_pos += len;
+            generated_pos += len;
+            old_pos = range.end;
+        }
+        let end = self.offsets.last().map(|(chunk, _)| chunk.end).unwrap_or(0);
+        copy(&mut result, old_pos..end, new_pos);
+        result
+    }
+
+    /// The file at the start of the resolved tree, i.e. the root source.
+    pub(crate) fn root_file(&self) -> Option<FileId> {
+        self.offsets.first().map(|(_, (file, _))| *file)
+    }
+
+    /// The position in the resolved tree of an offset in a source file.
+    ///
+    /// Returns `None` if the offset is not part of the tree, for instance
+    /// because the file was not included.
+    pub(crate) fn global_offset(&self, file: FileId, offset: usize) -> Option<usize> {
+        self.offsets
+            .iter()
+            .find(|(chunk, (chunk_file, local_offset))| {
+                *chunk_file == file
+                    && offset >= *local_offset
+                    && offset - local_offset < chunk.end - chunk.start
+            })
+            .map(|(chunk, (_, local_offset))| chunk.start + offset - local_offset)
     }
 }
 
@@ -298,6 +530,10 @@ impl SourceLoader {
         self.sources.get(id)
     }
 
+    pub(crate) fn list(&self) -> &SourceList {
+        &self.sources
+    }
+
     /// Attempt to load the source at the provided path.
     ///
     /// This uses the [`SourceResolver`] that was passed in at construction time,
@@ -337,11 +573,36 @@ impl SourceList {
         self.sources.get(id)
     }
 
+    /// The canonical paths of all sources, sorted.
+    pub(crate) fn sorted_paths(&self) -> Vec<OsString> {
+        let mut paths = self.ids.keys().cloned().collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    /// Add a source for text that was generated, rather than loaded.
+    ///
+    /// This is not associated with its path, so it is not returned by
+    /// [`SourceList::sorted_paths`].
+    pub(crate) fn add_generated(&mut self, path: &str, text: Arc<str>) -> FileId {
+        let source = Source::new(path, text);
+        let id = source.id;
+        self.sources.insert(id, source);
+        id
+    }
+
     fn add(&mut self, canonical_path: OsString, source: Source) {
         self.ids.insert(canonical_path, source.id);
         self.sources.insert(source.id, source);
     }
 
+    /// The start and end of a diagnostic's span, as lines and columns.
+    pub(crate) fn line_cols(&self, err: &Diagnostic) -> Option<Range<LineCol>> {
+        let index = self.get(&err.message.file)?.line_index();
+        let span = err.span();
+        Some(index.line_col(span.start)..index.line_col(span.end))
+    }
+
     /// Generate a string suitable for presenting a [`Diagnostic`] to the user.
     ///
     /// This associates the message with the appropriate source location and
@@ -352,6 +613,13 @@ impl SourceList {
         crate::util::highlighting::write_diagnostic(&mut s, err, source, None);
         s
     }
+
+    /// Render a [`Diagnostic`] as an annotated snippet of its source.
+    #[cfg(feature = "snippets")]
+    pub(crate) fn render_diagnostic(&self, err: &Diagnostic, styled: bool) -> String {
+        let source = self.get(&err.message.file).unwrap();
+        crate::util::snippets::render_diagnostic(err, source, styled)
+    }
 }
 
 impl SourceLoadError {
@@ -366,3 +634,27 @@ impl SourceLoadError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_index_crlf_and_bom() {
+        let text = "\u{feff}feature\r\nfoo\n\r\nbar";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line_col(0), LineCol { line: 1, column: 0 });
+        assert_eq!(index.line_col(3), LineCol { line: 1, column: 0 });
+        assert_eq!(index.line_col(5), LineCol { line: 1, column: 2 });
+        let bar = text.find("bar").unwrap();
+        assert_eq!(index.line_col(bar + 1), LineCol { line: 4, column: 1 });
+        assert_eq!(index.offset(LineCol { line: 4, column: 1 }), Some(bar + 1));
+        assert_eq!(index.offset(LineCol { line: 5, column: 0 }), None);
+        assert_eq!(&text[index.line_range(1).unwrap()], "feature\r\n");
+
+        let source = Source::new("test", text.into());
+        assert_eq!(source.line_containing_offset(5), (1, "feature"));
+        assert_eq!(source.line_containing_offset(bar), (4, "bar"));
+    }
+}
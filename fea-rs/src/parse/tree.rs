@@ -1,9 +1,9 @@
 //! the result of a parsing operation
 
-use std::sync::Arc;
+use std::{ops::Range, sync::Arc};
 
 use super::source::Source;
-use super::{FileId, SourceList, SourceMap};
+use super::{FileId, LineCol, SourceList, SourceMap};
 use crate::{token_tree::typed, Diagnostic, Node};
 
 /// A fully parsed feature file, with attached imports and a sourcemap.
@@ -42,6 +42,14 @@ impl ParseTree {
         self.sources.get(&id)
     }
 
+    /// The start and end of a [`Diagnostic`]'s span, as lines and columns in
+    /// its source.
+    ///
+    /// Returns `None` if the diagnostic's file is not part of this tree.
+    pub fn line_cols(&self, err: &Diagnostic) -> Option<Range<LineCol>> {
+        self.sources.line_cols(err)
+    }
+
     /// Generate a string suitable for presenting a [`Diagnostic`] to the user.
     ///
     /// This associates the message with the appropriate source location and
@@ -49,4 +57,16 @@ impl ParseTree {
     pub fn format_diagnostic(&self, err: &Diagnostic) -> String {
         self.sources.format_diagnostic(err)
     }
+
+    /// Render a [`Diagnostic`] as an annotated snippet of its source.
+    ///
+    /// This is an alternative to [`format_diagnostic`] that underlines the
+    /// whole span, including spans over multiple lines. If `styled` is false
+    /// the output has no colour codes, which is better for logs.
+    ///
+    /// [`format_diagnostic`]: ParseTree::format_diagnostic
+    #[cfg(feature = "snippets")]
+    pub fn render_diagnostic(&self, err: &Diagnostic, styled: bool) -> String {
+        self.sources.render_diagnostic(err, styled)
+    }
 }
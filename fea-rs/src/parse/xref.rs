@@ -0,0 +1,443 @@
+//! An index of the definitions and uses of named items.
+//!
+//! This is intended to support editor features such as 'find references'
+//! and 'rename', as well as lints like finding unused classes.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use smol_str::SmolStr;
+
+use super::{FileId, ParseTree, TextEdit};
+use crate::{Kind, Node, NodeOrToken};
+
+/// A named item that can be defined or referenced in a feature file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Symbol {
+    /// A glyph, referenced by name.
+    ///
+    /// Glyphs are not defined in FEA; they only have references. When parsing
+    /// without a glyph map, a token such as `a-z` may be either a glyph name or
+    /// a range; it is recorded as a glyph name.
+    Glyph(SmolStr),
+    /// A glyph referenced by CID.
+    Cid(u16),
+    /// A named glyph class, without the leading '@'.
+    GlyphClass(SmolStr),
+    /// A mark class, without the leading '@'.
+    MarkClass(SmolStr),
+    /// A named lookup.
+    Lookup(SmolStr),
+    /// A named anchor, defined with `anchorDef`.
+    Anchor(SmolStr),
+}
+
+/// The location of a definition or reference.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Location {
+    /// The file containing this location.
+    pub file: FileId,
+    /// The range of the name in that file.
+    pub range: Range<usize>,
+}
+
+/// An index from named items to the places they are defined and used.
+#[derive(Clone, Debug, Default)]
+pub struct XrefIndex {
+    definitions: BTreeMap<Symbol, Vec<Location>>,
+    references: BTreeMap<Symbol, Vec<Location>>,
+    // names that may be ranges, such as 'a-z', when there was no glyph map
+    maybe_ranges: Vec<(SmolStr, Location)>,
+}
+
+impl XrefIndex {
+    /// Build an index for a parse tree, including any included files.
+    pub fn new(tree: &ParseTree) -> Self {
+        Self::build(tree.root(), |range| {
+            let (file, range) = tree.source_map().resolve_range(range);
+            Location { file, range }
+        })
+    }
+
+    /// Build an index for a single node, such as one returned by [`parse_string`].
+    ///
+    /// Locations are relative to the start of the node's text, and their file
+    /// is always the same (unspecified) id.
+    ///
+    /// [`parse_string`]: super::parse_string
+    pub fn for_node(node: &Node) -> Self {
+        Self::build(node, |range| Location {
+            file: FileId::CURRENT_FILE,
+            range,
+        })
+    }
+
+    fn build(root: &Node, resolve: impl Fn(Range<usize>) -> Location) -> Self {
+        let mut builder = IndexBuilder::default();
+        builder.visit_node(root);
+
+        // a named class is a mark class if it was defined with `markClass`;
+        // mark classes can be used anywhere a glyph class can.
+        let IndexBuilder {
+            definitions,
+            references,
+            class_refs,
+            maybe_ranges,
+        } = builder;
+        let mut index = XrefIndex::default();
+        for (symbol, range) in definitions {
+            index
+                .definitions
+                .entry(symbol)
+                .or_default()
+                .push(resolve(range));
+        }
+        for (symbol, range) in references {
+            index
+                .references
+                .entry(symbol)
+                .or_default()
+                .push(resolve(range));
+        }
+        for (name, range) in class_refs {
+            let as_mark = Symbol::MarkClass(name.clone());
+            let symbol = if index.definitions.contains_key(&as_mark) {
+                as_mark
+            } else {
+                Symbol::GlyphClass(name)
+            };
+            index
+                .references
+                .entry(symbol)
+                .or_default()
+                .push(resolve(range));
+        }
+        index.maybe_ranges = maybe_ranges
+            .into_iter()
+            .map(|(name, range)| (name, resolve(range)))
+            .collect();
+        index
+            .references
+            .values_mut()
+            .for_each(|locs| locs.sort_by_key(|loc| (loc.file, loc.range.start)));
+        index
+    }
+
+    /// The locations where this symbol is defined.
+    ///
+    /// For a lookup, this includes the label at both the start and the end of
+    /// the block. Mark classes may be defined more than once.
+    pub fn definitions(&self, symbol: &Symbol) -> &[Location] {
+        self.definitions
+            .get(symbol)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The locations where this symbol is used, in source order.
+    pub fn references(&self, symbol: &Symbol) -> &[Location] {
+        self.references
+            .get(symbol)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Iterate over all symbols that are defined or referenced, in sorted order.
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> + '_ {
+        let mut all = self
+            .definitions
+            .keys()
+            .chain(self.references.keys())
+            .collect::<Vec<_>>();
+        all.sort_unstable();
+        all.dedup();
+        all.into_iter()
+    }
+
+    /// Return the edits needed to rename a glyph.
+    ///
+    /// This includes glyphs that are the start or end of a range. If a range
+    /// such as `a-z` was parsed without a glyph map, it is a single token; if
+    /// one of its endpoints is `old` then the whole token is rewritten, adding
+    /// spaces around the hyphen if `new` itself contains a hyphen.
+    ///
+    /// The edits can be applied with [`ParseTree::apply_edits`].
+    pub fn rename_glyph(&self, old: &str, new: &str) -> Vec<TextEdit> {
+        let mut edits = self
+            .references(&Symbol::Glyph(old.into()))
+            .iter()
+            .map(|loc| TextEdit::new(loc.clone(), new))
+            .collect::<Vec<_>>();
+
+        for (name, loc) in &self.maybe_ranges {
+            if name == old {
+                // already handled as a glyph reference
+                continue;
+            }
+            if let Some(new_text) = rename_range_endpoints(name, old, new) {
+                edits.push(TextEdit::new(loc.clone(), new_text));
+            }
+        }
+        edits.sort_by_key(|edit| (edit.location.file, edit.location.range.start));
+        edits
+    }
+
+    /// Return the edits needed to rename a glyph class or mark class.
+    ///
+    /// Names are given without the leading '@'. This renames both the
+    /// definition(s) and all uses of the class.
+    ///
+    /// The edits can be applied with [`ParseTree::apply_edits`].
+    pub fn rename_class(&self, old: &str, new: &str) -> Vec<TextEdit> {
+        let new_text = format!("@{new}");
+        let mut edits = [
+            Symbol::GlyphClass(old.into()),
+            Symbol::MarkClass(old.into()),
+        ]
+        .iter()
+        .flat_map(|symbol| {
+            self.definitions(symbol)
+                .iter()
+                .chain(self.references(symbol))
+        })
+        .map(|loc| TextEdit::new(loc.clone(), new_text.as_str()))
+        .collect::<Vec<_>>();
+        edits.sort_by_key(|edit| (edit.location.file, edit.location.range.start));
+        edits
+    }
+
+    /// Return the symbol defined or referenced at this position, if any.
+    pub fn symbol_at(&self, file: FileId, offset: usize) -> Option<&Symbol> {
+        self.definitions
+            .iter()
+            .chain(self.references.iter())
+            .find(|(_, locs)| {
+                locs.iter()
+                    .any(|loc| loc.file == file && loc.range.contains(&offset))
+            })
+            .map(|(symbol, _)| symbol)
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Symbol::Glyph(name) | Symbol::Lookup(name) | Symbol::Anchor(name) => {
+                write!(f, "{name}")
+            }
+            Symbol::Cid(cid) => write!(f, "\\{cid}"),
+            Symbol::GlyphClass(name) | Symbol::MarkClass(name) => write!(f, "@{name}"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct IndexBuilder {
+    definitions: Vec<(Symbol, Range<usize>)>,
+    references: Vec<(Symbol, Range<usize>)>,
+    // uses of named classes, which may be glyph classes or mark classes
+    class_refs: Vec<(SmolStr, Range<usize>)>,
+    maybe_ranges: Vec<(SmolStr, Range<usize>)>,
+}
+
+impl IndexBuilder {
+    fn visit_node(&mut self, node: &Node) {
+        let mut seen_class_name = false;
+        for child in node.iter_children() {
+            let token = match child {
+                NodeOrToken::Node(child) => {
+                    self.visit_node(child);
+                    continue;
+                }
+                NodeOrToken::Token(token) => token,
+            };
+            let range = child.range();
+            match (node.kind(), token.kind) {
+                // the first class name in a class definition is the one defined
+                (Kind::GlyphClassDefNode, Kind::NamedGlyphClass) if !seen_class_name => {
+                    seen_class_name = true;
+                    self.definitions
+                        .push((Symbol::GlyphClass(class_name(token.as_str())), range));
+                }
+                (Kind::MarkClassNode, Kind::NamedGlyphClass) => self
+                    .definitions
+                    .push((Symbol::MarkClass(class_name(token.as_str())), range)),
+                (Kind::AnchorMarkNode, Kind::NamedGlyphClass) => self
+                    .references
+                    .push((Symbol::MarkClass(class_name(token.as_str())), range)),
+                (_, Kind::NamedGlyphClass) => {
+                    self.class_refs.push((class_name(token.as_str()), range))
+                }
+                (Kind::LookupBlockNode, Kind::Label) => self
+                    .definitions
+                    .push((Symbol::Lookup(token.text.clone()), range)),
+                (Kind::LookupRefNode, Kind::Ident) => self
+                    .references
+                    .push((Symbol::Lookup(token.text.clone()), range)),
+                (Kind::AnchorDefNode, Kind::Ident) => self
+                    .definitions
+                    .push((Symbol::Anchor(token.text.clone()), range)),
+                (Kind::AnchorNode, Kind::Ident) => self
+                    .references
+                    .push((Symbol::Anchor(token.text.clone()), range)),
+                (_, Kind::GlyphName) => self
+                    .references
+                    .push((Symbol::Glyph(token.text.clone()), range)),
+                (_, Kind::GlyphNameOrRange) => {
+                    self.maybe_ranges.push((token.text.clone(), range.clone()));
+                    self.references
+                        .push((Symbol::Glyph(token.text.clone()), range));
+                }
+                (_, Kind::Cid) => {
+                    if let Ok(cid) = token.as_str().parse() {
+                        self.references.push((Symbol::Cid(cid), range));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn class_name(text: &str) -> SmolStr {
+    text.trim_start_matches('@').into()
+}
+
+/// If either end of a possible range is `old`, return the range with those
+/// endpoints renamed.
+fn rename_range_endpoints(range: &str, old: &str, new: &str) -> Option<String> {
+    let (start, end) = range
+        .match_indices('-')
+        .map(|(idx, _)| (&range[..idx], &range[idx + 1..]))
+        .find(|(start, end)| *start == old || *end == old)?;
+    let start = if start == old { new } else { start };
+    let end = if end == old { new } else { end };
+    if new.contains('-') {
+        Some(format!("{start} - {end}"))
+    } else {
+        Some(format!("{start}-{end}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges<'a>(text: &'a str, locs: &[Location]) -> Vec<&'a str> {
+        locs.iter().map(|loc| &text[loc.range.clone()]).collect()
+    }
+
+    #[test]
+    fn classes_and_lookups() {
+        let fea = "\
+@lower = [a b c];
+@all = [@lower d];
+markClass [acute grave] <anchor 0 0> @TOP;
+lookup ONE { sub @lower by A; } ONE;
+feature test {
+    lookup ONE;
+    pos base @all <anchor 1 1> mark @TOP;
+    sub a' lookup ONE b;
+} test;
+";
+        let (node, errs) = crate::parse::parse_string(fea);
+        assert!(errs.is_empty());
+        let index = XrefIndex::for_node(&node);
+
+        let lower = Symbol::GlyphClass("lower".into());
+        assert_eq!(ranges(fea, index.definitions(&lower)), ["@lower"]);
+        assert_eq!(index.references(&lower).len(), 2);
+
+        let top = Symbol::MarkClass("TOP".into());
+        assert_eq!(index.definitions(&top).len(), 1);
+        assert_eq!(index.references(&top).len(), 1);
+
+        let one = Symbol::Lookup("ONE".into());
+        assert_eq!(ranges(fea, index.definitions(&one)), ["ONE", "ONE"]);
+        assert_eq!(ranges(fea, index.references(&one)), ["ONE", "ONE"]);
+
+        let a = Symbol::Glyph("a".into());
+        assert_eq!(index.references(&a).len(), 2);
+        assert!(index.definitions(&a).is_empty());
+
+        let offset = fea.find("@all <").unwrap() + 2;
+        assert_eq!(
+            index.symbol_at(FileId::CURRENT_FILE, offset),
+            Some(&Symbol::GlyphClass("all".into()))
+        );
+    }
+
+    #[test]
+    fn mark_class_used_as_glyph_class() {
+        let fea = "markClass acute <anchor 0 0> @TOP;\nfeature test { sub @TOP by A; } test;";
+        let (node, _) = crate::parse::parse_string(fea);
+        let index = XrefIndex::for_node(&node);
+        assert_eq!(index.references(&Symbol::MarkClass("TOP".into())).len(), 1);
+        assert!(!index
+            .symbols()
+            .any(|sym| matches!(sym, Symbol::GlyphClass(_))));
+    }
+
+    #[test]
+    fn rename_class_in_included_file() {
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let (tree, errs) =
+            crate::parse::parse_root("a".into(), Some(&glyph_map), |path: &std::ffi::OsStr| {
+                match path.to_str() {
+                    Some("a") => Ok("@lower = [a b];\ninclude(b);\n".into()),
+                    _ => Ok("feature test { sub @lower by A; } test;\n".into()),
+                }
+            })
+            .unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+        let index = XrefIndex::new(&tree);
+        let edits = index.rename_class("lower", "lc");
+        assert_eq!(edits.len(), 2);
+        let mut texts = tree
+            .apply_edits(edits)
+            .unwrap()
+            .into_values()
+            .collect::<Vec<_>>();
+        texts.sort();
+        assert_eq!(
+            texts,
+            [
+                "@lc = [a b];\ninclude(b);\n",
+                "feature test { sub @lc by A; } test;\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn rename_glyph_range_endpoints() {
+        let fea = "@c = [a-c a - c b-a];\nfeature test { sub a by b; } test;";
+        // with a glyph map, ranges are split into their endpoints
+        let glyph_map = crate::util::ttx::make_glyph_map();
+        let (tree, errs) =
+            crate::parse::parse_root("a".into(), Some(&glyph_map), move |_: &std::ffi::OsStr| {
+                Ok(fea.into())
+            })
+            .unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+        let edits = XrefIndex::new(&tree).rename_glyph("a", "alpha");
+        assert_eq!(edits.len(), 4);
+        let text = tree
+            .apply_edits(edits)
+            .unwrap()
+            .into_values()
+            .next()
+            .unwrap();
+        assert_eq!(
+            text,
+            "@c = [alpha-c alpha - c b-alpha];\nfeature test { sub alpha by b; } test;"
+        );
+
+        // without one, names that might be ranges are rewritten as a whole
+        let (node, _) = crate::parse::parse_string(fea);
+        let edits = XrefIndex::for_node(&node).rename_glyph("a", "a-1");
+        let text = node.apply_edits(edits).unwrap();
+        assert_eq!(
+            text,
+            "@c = [a-1 - c a-1 - c b - a-1];\nfeature test { sub a-1 by b; } test;"
+        );
+    }
+}
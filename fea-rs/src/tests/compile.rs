@@ -1,10 +1,13 @@
 //! tests of the full compiler, including expected successes and failures
 
-use std::path::{Path, PathBuf};
+use std::{
+    convert::identity,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    compile::{error::CompilerError, Compiler, Opts},
-    util::ttx::{self as test_utils, Report, TestCase, TestResult},
+    compile::{error::CompilerError, Compilation, Compiler, Opts},
+    util::ttx::{self as test_utils, Report, TestCase, TestResult, TtxSource},
     GlyphMap, GlyphName,
 };
 
@@ -21,7 +24,7 @@ static IMPORT_RESOLUTION_TEST: &str = "./test-data/include-resolution-tests/dir1
 #[ignore = "disabled so we can use CI"]
 fn fonttools_tests() -> Result<(), Report> {
     test_utils::assert_has_ttx_executable();
-    test_utils::run_all_tests(FONTTOOLS_TESTS, None).into_error()
+    test_utils::run_all_tests(FONTTOOLS_TESTS, None, TtxSource::Executable).into_error()
 }
 
 #[test]
@@ -37,7 +40,7 @@ fn should_fail() -> Result<(), Report> {
 fn import_resolution() {
     let glyph_map = test_utils::make_glyph_map();
     let path = PathBuf::from(IMPORT_RESOLUTION_TEST);
-    match test_utils::run_test(path, &glyph_map) {
+    match test_utils::run_test(path, &glyph_map, TtxSource::Executable) {
         Ok(_) => (),
         Err(e) => panic!("{:?}", e.reason),
     }
@@ -50,7 +53,7 @@ fn should_pass() -> Result<(), Report> {
         results.extend(
             tests
                 .into_iter()
-                .map(|path| test_utils::run_test(path, &glyph_map)),
+                .map(|path| test_utils::run_test(path, &glyph_map, TtxSource::Executable)),
         );
     }
     test_utils::finalize_results(results).into_error()
@@ -100,15 +103,3190 @@ fn bad_test_body(path: &Path, glyph_map: &GlyphMap) -> Result<(), TestResult> {
         // this means we have a test case that doesn't exist or something weird
         Err(CompilerError::SourceLoad(err)) => panic!("{err}"),
         Err(CompilerError::WriteFail(err)) => panic!("{err}"),
+        Err(CompilerError::TransformFail(errs)) => panic!("{errs}"),
         Err(CompilerError::ParseFail(errs)) => Err(TestResult::ParseFail(errs.to_string())),
         Err(CompilerError::ValidationFail(errs) | CompilerError::CompilationFail(errs)) => {
             let msg = errs.to_string();
-            let result = test_utils::compare_to_expected_output(&msg, path, BAD_OUTPUT_EXTENSION);
-            if result.is_err() && std::env::var(crate::util::WRITE_RESULTS_VAR).is_ok() {
-                let to_path = path.with_extension(BAD_OUTPUT_EXTENSION);
-                std::fs::write(to_path, &msg).expect("failed to write output");
+            test_utils::compare_bad_output(&errs.messages, &msg, path, BAD_OUTPUT_EXTENSION)
+                .map_err(|e| e.reason)
+        }
+    }
+}
+
+pub(super) fn compile_from_str(fea: impl AsRef<str>, opts: Opts) -> Result<Vec<u8>, CompilerError> {
+    let glyph_map = test_utils::make_glyph_map();
+    test_utils::compile_str_to_binary(fea.as_ref(), &glyph_map, |compiler| {
+        compiler.with_opts(opts)
+    })
+}
+
+// out of range values that used to panic after parsing
+#[test]
+fn out_of_range_values_are_errors() {
+    for fea in [
+        "feature test { sub \\999999 by a; } test;",
+        "table hhea { Ascender 99999; } hhea;",
+        "table vhea { VertTypoAscender 99999; } vhea;",
+        "table BASE { HorizAxis.BaseTagList romn; HorizAxis.BaseScriptList latn romn 99999; } BASE;",
+        "table STAT { ElidedFallbackNameID 99999; } STAT;",
+        "table GDEF { LigatureCaretByIndex f_i 99999; } GDEF;",
+        "feature cv01 { cvParameters { Character 0x99999999; }; } cv01;",
+        "feature size { parameters 99999 0; } size;",
+        "feature size { parameters 9999.5 0; } size;",
+        "feature test { sub [\\1-\\999999] by a; } test;",
+        "feature test { lookupflag 99999; pos a 1; } test;",
+        "table vmtx { VertOriginY a 99999; } vmtx;",
+        "table OS/2 { UnicodeRange 99999; } OS/2;",
+        "table OS/2 { CodePageRange 99999; } OS/2;",
+        "table OS/2 { TypoAscender 99999; } OS/2;",
+        "table OS/2 { winAscent 99999; } OS/2;",
+        "table OS/2 { WeightClass 99999; } OS/2;",
+        "table OS/2 { Panose 99999 0 0 0 0 0 0 0 0 0; } OS/2;",
+        "table OS/2 { FSType 99999; } OS/2;",
+        "table GDEF { LigatureCaretByPos f_i 99999; } GDEF;",
+        "table GDEF { Attach a 99999; } GDEF;",
+        "table STAT { ElidedFallbackName { name \"Regular\"; }; DesignAxis wght 99999 { name \"Weight\"; }; } STAT;",
+        "table STAT { ElidedFallbackName { name \"Regular\"; }; AxisValue { location wght 99999; name \"x\"; }; } STAT;",
+        "table STAT { ElidedFallbackName { name \"Regular\"; }; AxisValue { location wght 400 99999 0; name \"x\"; }; } STAT;",
+        "table name { nameid 99999 \"x\"; } name;",
+        "table name { nameid 9 99999 \"x\"; } name;",
+        "table head { FontRevision 99999.5; } head;",
+        "feature cv01 { cvParameters { Character 0xD800; }; } cv01;",
+        "feature test { pos a <99999 0 0 0>; } test;",
+        "feature test { pos a b 99999; } test;",
+        "feature test { pos cursive a <anchor 99999 0> <anchor NULL>; } test;",
+        "feature test { pos a <0 0 0 0 <device 99999 1> <device NULL> <device NULL> <device NULL>>; } test;",
+        "feature test { featureNames { name 99999 \"x\"; }; } test;",
+        "feature ss01 { featureNames { name 3 99999 99999 99999 \"x\"; }; } ss01;",
+    ] {
+        assert!(compile_from_str(fea, Opts::new()).is_err(), "{fea}");
+    }
+}
+
+#[test]
+fn opts_force_chain_pos() {
+    use write_fonts::read::{tables::gpos::PositionLookup, FontRef, TableProvider};
+
+    let fea = "
+lookup ADJUST { pos a 20; } ADJUST;
+feature kern { pos a' lookup ADJUST b'; } kern;
+";
+    let contextual_is_chain = |opts| {
+        let data = compile_from_str(fea, opts).unwrap();
+        let font = FontRef::new(&data).unwrap();
+        let lookups = font.gpos().unwrap().lookup_list().unwrap();
+        let is_chain = match lookups.lookups().nth(1).unwrap().unwrap() {
+            PositionLookup::ChainContextual(_) => true,
+            PositionLookup::Contextual(_) => false,
+            _ => panic!("unexpected lookup type"),
+        };
+        is_chain
+    };
+
+    assert!(contextual_is_chain(Opts::new()));
+    assert!(!contextual_is_chain(Opts::new().force_chain_pos(false)));
+}
+
+#[test]
+fn opts_pair_pos_class_0() {
+    use write_fonts::read::{
+        tables::gpos::{PairPos, PositionLookup},
+        FontRef, TableProvider,
+    };
+
+    let fea = "feature kern { pos [a b] [c d] -10; pos [e f] [c d] -20; } kern;";
+    let class1_count = |opts| {
+        let data = compile_from_str(fea, opts).unwrap();
+        let font = FontRef::new(&data).unwrap();
+        let lookups = font.gpos().unwrap().lookup_list().unwrap();
+        let PositionLookup::Pair(lookup) = lookups.lookups().next().unwrap().unwrap() else {
+            panic!("expected pair pos");
+        };
+        let count = match lookup.subtables().next().unwrap().unwrap() {
+            PairPos::Format2(table) => table.class1_count(),
+            PairPos::Format1(_) => panic!("expected class-based subtable"),
+        };
+        count
+    };
+
+    assert_eq!(class1_count(Opts::new()), 2);
+    assert_eq!(class1_count(Opts::new().pair_pos_class_0(false)), 3);
+}
+
+#[test]
+fn parse_and_validate_stages() {
+    let glyph_map = test_utils::make_glyph_map();
+    let parse = |fea: &'static str| test_utils::parse_str(fea, &glyph_map);
+
+    let tree = parse("@unused = [a b]; feature liga { sub f i by f_i; } liga;");
+    assert!(crate::validate(&tree, &glyph_map, &Opts::new())
+        .unwrap()
+        .is_empty());
+    let warnings = crate::validate(&tree, &glyph_map, &Opts::new().warn_unused(true)).unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(!warnings[0].is_error());
+
+    let tree = parse("@unused = [a b]; feature liga { sub @nope by a; } liga;");
+    let errs = crate::validate(&tree, &glyph_map, &Opts::new().warn_unused(true)).unwrap_err();
+    assert_eq!(errs.messages().len(), 1);
+    assert_eq!(errs.messages()[0].text(), "undefined glyph class");
+}
+
+#[test]
+fn compile_with_cache() {
+    use crate::compile::CompilationCache;
+
+    let glyph_map = test_utils::make_glyph_map();
+    let template = "
+languagesystem DFLT dflt;
+@LIG = [f_i f_f];
+feature liga { sub f i by f_i; sub f f by f_f; } liga;
+lookup SHIFT { pos a 10; } SHIFT;
+feature kern { KERN } kern;
+feature mark { pos [b c]' lookup SHIFT d; } mark;
+";
+    let compile = |kern: &str, cache: &mut CompilationCache| {
+        let fea = template.replace("KERN", kern);
+        let tree = test_utils::parse_str(&fea, &glyph_map);
+        let compilation = crate::compile(&tree, &glyph_map, Opts::new()).unwrap();
+        let cached = compilation
+            .assemble_with_cache(&glyph_map, Opts::new(), cache)
+            .unwrap()
+            .build();
+        let uncached = compilation
+            .assemble(&glyph_map, Opts::new())
+            .unwrap()
+            .build();
+        assert_eq!(cached, uncached);
+        cached
+    };
+
+    let mut cache = CompilationCache::new();
+    let first = compile("pos a b -10;", &mut cache);
+    assert_eq!(cache.reused_lookups(), 0);
+    // liga, SHIFT and mark are unchanged; kern is rebuilt
+    let second = compile("pos a b -20;", &mut cache);
+    assert_eq!(cache.reused_lookups(), 3);
+    assert_ne!(first, second);
+    // same again, so everything is reused
+    compile("pos a b -20;", &mut cache);
+    assert_eq!(cache.reused_lookups(), 4);
+    // an extra lookup in kern shifts the ids of the lookups after it
+    compile("pos a b -20; pos c d -10; pos c 5;", &mut cache);
+    assert_eq!(cache.reused_lookups(), 2);
+}
+
+#[test]
+fn name_string_escapes() {
+    use write_fonts::read::{FontRef, TableProvider};
+
+    let fea = r#"
+table name {
+    nameid 9 "M\00fcller \d83d\de00 \005c";
+    nameid 9 1 "M\9fller\ca\5c";
+} name;
+"#;
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let name = font.name().unwrap();
+    let strings = name
+        .name_record()
+        .iter()
+        .map(|record| {
+            let string = record.string(name.string_data()).unwrap();
+            (record.platform_id(), string.chars().collect::<String>())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        strings,
+        [
+            (1, "Müller\u{a0}\\".to_string()),
+            (3, "Müller 😀 \\".to_string())
+        ]
+    );
+}
+
+#[test]
+fn opts_compact_value_formats() {
+    use write_fonts::read::{
+        tables::gpos::{PairPos, PositionLookup},
+        FontRef, TableProvider,
+    };
+
+    // the zero records only exist to stop the class kerning from applying
+    let fea = "
+feature kern {
+    pos a c 0;
+    pos b c <0 0 0 0>;
+    pos [a b] [c d] -10;
+} kern;
+feature vkrn {
+    pos a c 0;
+    pos [a b] [c d] <0 0 0 -20>;
+} vkrn;
+";
+    let value_formats = |opts| {
+        let data = compile_from_str(fea, opts).unwrap();
+        let font = FontRef::new(&data).unwrap();
+        let lookups = font.gpos().unwrap().lookup_list().unwrap();
+        lookups
+            .lookups()
+            .flat_map(|lookup| {
+                let PositionLookup::Pair(lookup) = lookup.unwrap() else {
+                    panic!("expected pair pos");
+                };
+                lookup
+                    .subtables()
+                    .map(|sub| match sub.unwrap() {
+                        PairPos::Format1(table) => table.value_format1().bits(),
+                        PairPos::Format2(table) => table.value_format1().bits(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(value_formats(Opts::new()), [4, 4, 8, 8]);
+    assert_eq!(
+        value_formats(Opts::new().compact_value_formats(true)),
+        [0, 4, 0, 8]
+    );
+}
+
+#[test]
+fn os2_number_keywords() {
+    use write_fonts::read::{FontRef, TableProvider};
+
+    let fea = "
+table OS/2 {
+    FSType 8;
+    WeightClass 700;
+    WidthClass 3;
+    LowerOpSize 80;
+    UpperOpSize 240;
+    FamilyClass 0x0105;
+} OS/2;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let os2 = font.os2().unwrap();
+    assert_eq!(os2.fs_type(), 8);
+    assert_eq!(os2.us_weight_class(), 700);
+    assert_eq!(os2.us_width_class(), 3);
+    assert_eq!(os2.us_lower_optical_point_size(), Some(80));
+    assert_eq!(os2.us_upper_optical_point_size(), Some(240));
+    assert_eq!(os2.s_family_class(), 0x0105);
+}
+
+#[test]
+fn single_pos_value_formats_are_minimal() {
+    use write_fonts::read::{
+        tables::gpos::{PositionLookup, SinglePos},
+        FontRef, TableProvider,
+    };
+
+    let fea = "
+feature kern {
+    pos a <0 0 0 0>;
+    pos b <10 0 0 0>;
+    pos c <0 0 20 0>;
+    pos d 0;
+} kern;
+";
+    let value_formats = |opts| {
+        let data = compile_from_str(fea, opts).unwrap();
+        let font = FontRef::new(&data).unwrap();
+        let lookups = font.gpos().unwrap().lookup_list().unwrap();
+        let PositionLookup::Single(lookup) = lookups.lookups().next().unwrap().unwrap() else {
+            panic!("expected single pos");
+        };
+        lookup
+            .subtables()
+            .map(|sub| match sub.unwrap() {
+                SinglePos::Format1(table) => table.value_format().bits(),
+                SinglePos::Format2(table) => table.value_format().bits(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // zero values are always dropped, so the option changes nothing
+    assert_eq!(value_formats(Opts::new()), [0, 1, 4]);
+    assert_eq!(
+        value_formats(Opts::new().compact_value_formats(true)),
+        [0, 1, 4]
+    );
+}
+
+#[test]
+fn opts_rtl_pair_pos_placement() {
+    use write_fonts::read::{
+        tables::gpos::{PairPos, PositionLookup},
+        FontRef, TableProvider,
+    };
+
+    let fea = "
+feature kern {
+    lookup rtl {
+        lookupflag RightToLeft;
+        pos a b -20;
+        pos a c <0 0 -10 0>;
+    } rtl;
+    lookup ltr {
+        lookupflag 0;
+        pos b c -30;
+    } ltr;
+} kern;
+";
+    // the value format and first value record of each subtable
+    let values = |opts| {
+        let data = compile_from_str(fea, opts).unwrap();
+        let font = FontRef::new(&data).unwrap();
+        let lookups = font.gpos().unwrap().lookup_list().unwrap();
+        lookups
+            .lookups()
+            .flat_map(|lookup| {
+                let PositionLookup::Pair(lookup) = lookup.unwrap() else {
+                    panic!("expected pair pos");
+                };
+                lookup
+                    .subtables()
+                    .map(|sub| {
+                        let PairPos::Format1(table) = sub.unwrap() else {
+                            panic!("expected glyph pairs");
+                        };
+                        let pair_set = table.pair_sets().next().unwrap().unwrap();
+                        let record = pair_set.pair_value_records().get(0).unwrap();
+                        let value = record.value_record1();
+                        (
+                            table.value_format1().bits(),
+                            value.x_placement(),
+                            value.x_advance(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        values(Opts::new()),
+        [(4, None, Some(-20)), (4, None, Some(-30))]
+    );
+    assert_eq!(
+        values(Opts::new().rtl_pair_pos_placement(true)),
+        [
+            // explicit value records are unchanged
+            (4, None, Some(-10)),
+            (5, Some(-20), Some(-20)),
+            (4, None, Some(-30))
+        ]
+    );
+}
+
+#[test]
+fn opts_infer_glyph_classes() {
+    use write_fonts::read::{FontRef, TableProvider};
+
+    let fea = "
+markClass acute <anchor 0 500> @TOP;
+feature mark { pos base a <anchor 250 450> mark @TOP; } mark;
+";
+    let has_glyph_class_def = |opts| {
+        let data = compile_from_str(fea, opts).unwrap();
+        let font = FontRef::new(&data).unwrap();
+        font.gdef()
+            .map(|gdef| gdef.glyph_class_def().is_some())
+            .unwrap_or(false)
+    };
+
+    assert!(has_glyph_class_def(Opts::new()));
+    assert!(!has_glyph_class_def(Opts::new().infer_glyph_classes(false)));
+}
+
+#[test]
+fn class_kerning_subtable_overflow() {
+    // 100 * 101 class records of 8 bytes each won't fit behind a 16-bit offset
+    let mut fea = String::from("feature kern {\n");
+    for first in 800..900 {
+        for second in 900..1000 {
+            fea.push_str(&format!("pos [\\{first}] [\\{second}] <1 2 3 4>;\n"));
+        }
+    }
+    fea.push_str("} kern;\n");
+
+    let Err(CompilerError::CompilationFail(errs)) = compile_from_str(fea, Opts::new()) else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert!(errs.messages[0].text().contains("offset limit"));
+}
+
+#[test]
+fn anon_block_handler() {
+    use std::{cell::RefCell, rc::Rc};
+    use write_fonts::{
+        read::{FontRef, TableProvider},
+        types::Tag,
+    };
+
+    let fea = "
+anon sbit {
+  /* sbit table specifications */
+  72  % ppem {weird} stuff;
+} sbit;
+feature liga { sub f i by f_i; } liga;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let seen = Rc::new(RefCell::new(String::new()));
+    let seen2 = seen.clone();
+    let data = test_utils::compile_str_to_binary(fea, &glyph_map, |compiler| {
+        compiler.with_anon_handler("sbit", move |contents: &str| {
+            seen2.borrow_mut().push_str(contents);
+            Ok(vec![(Tag::new(b"TEST"), b"hello".to_vec())])
+        })
+    })
+    .unwrap();
+
+    assert_eq!(
+        seen.borrow().as_str(),
+        "\n  /* sbit table specifications */\n  72  % ppem {weird} stuff;\n"
+    );
+    let font = FontRef::new(&data).unwrap();
+    assert_eq!(
+        font.data_for_tag(Tag::new(b"TEST")).unwrap().as_ref(),
+        b"hello"
+    );
+    assert!(font.gsub().is_ok());
+}
+
+#[test]
+fn anon_block_without_handler() {
+    let fea =
+        "anonymous whatever { lots of data } whatever;\nfeature liga { sub f i by f_i; } liga;";
+    assert!(compile_from_str(fea, Opts::new()).is_ok());
+}
+
+#[test]
+fn anon_block_handler_error() {
+    let fea = "anon TST { bad data } TST;";
+    let glyph_map = test_utils::make_glyph_map();
+    let result = test_utils::compile_str(fea, &glyph_map, |compiler| {
+        compiler.with_anon_handler("TST", |_: &str| Err("could not parse TST data".to_string()))
+    });
+    let Err(CompilerError::CompilationFail(errs)) = result else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert_eq!(errs.messages[0].text(), "could not parse TST data");
+}
+
+#[test]
+fn feature_params_handler() {
+    use write_fonts::{
+        read::{tables::layout::FeatureList, FontRef, TableProvider},
+        tables::layout::{FeatureParams, StylisticSetParams},
+        types::{NameId, Tag},
+    };
+
+    let fea = "
+feature liga {
+    parameters 256;
+    sub f i by f_i;
+} liga;
+feature kern {
+    parameters 257 1.5;
+    pos a b -10;
+} kern;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let data = test_utils::compile_str_to_binary(fea, &glyph_map, |compiler| {
+        compiler
+            .with_feature_params_handler(Tag::new(b"liga"), |values: &[f64]| {
+                let name_id = NameId::new(values[0] as u16);
+                Ok(FeatureParams::StylisticSet(StylisticSetParams::new(
+                    name_id,
+                )))
+            })
+            .with_feature_params_handler(Tag::new(b"kern"), |values: &[f64]| {
+                assert_eq!(values, [257.0, 1.5]);
+                Ok(FeatureParams::StylisticSet(StylisticSetParams::new(
+                    NameId::new(257),
+                )))
+            })
+    })
+    .unwrap();
+
+    // read-fonts only parses the params of features that the spec gives
+    // params to, so we read the name id of the stylistic set params directly
+    let params_name_id = |features: FeatureList| {
+        let record = &features.feature_records()[0];
+        let feature = record.feature(features.offset_data()).unwrap();
+        let offset = feature.feature_params_offset().offset().to_u32();
+        let data = features
+            .offset_data()
+            .split_off(record.feature_offset().to_u32() as usize + offset as usize)
+            .unwrap();
+        (record.feature_tag(), data.read_at::<u16>(2).unwrap())
+    };
+    let font = FontRef::new(&data).unwrap();
+    let gsub = font.gsub().unwrap().feature_list().unwrap();
+    assert_eq!(params_name_id(gsub), (Tag::new(b"liga"), 256));
+    let gpos = font.gpos().unwrap().feature_list().unwrap();
+    assert_eq!(params_name_id(gpos), (Tag::new(b"kern"), 257));
+}
+
+#[test]
+fn feature_params_without_handler() {
+    let fea = "feature liga { parameters 1 2 3; sub f i by f_i; } liga;";
+    let glyph_map = test_utils::make_glyph_map();
+    let result = test_utils::compile_str(fea, &glyph_map, identity);
+    let Err(CompilerError::CompilationFail(errs)) = result else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert_eq!(
+        errs.messages[0].text(),
+        "no FeatureParams handler for feature 'liga'"
+    );
+}
+
+/// Return the lookup indices for each language system of a feature in GSUB,
+/// formatted as 'script/lang: [lookups]', along with any required feature.
+fn gsub_lang_sys_lookups(data: &[u8]) -> Vec<String> {
+    use write_fonts::read::{FontRef, TableProvider};
+
+    let font = FontRef::new(data).unwrap();
+    let gsub = font.gsub().unwrap();
+    let scripts = gsub.script_list().unwrap();
+    let features = gsub.feature_list().unwrap();
+    let feature_lookups = |idx: u16| {
+        let record = &features.feature_records()[idx as usize];
+        let feature = record.feature(features.offset_data()).unwrap();
+        let lookups = feature
+            .lookup_list_indices()
+            .iter()
+            .map(|idx| idx.get().to_string())
+            .collect::<Vec<_>>();
+        format!("{}[{}]", record.feature_tag(), lookups.join(", "))
+    };
+    let mut result = Vec::new();
+    for script in scripts.script_records() {
+        let table = script.script(scripts.offset_data()).unwrap();
+        let default = table
+            .default_lang_sys()
+            .map(|sys| (write_fonts::types::Tag::new(b"dflt"), sys.unwrap()));
+        let others = table.lang_sys_records().iter().map(|rec| {
+            (
+                rec.lang_sys_tag(),
+                rec.lang_sys(table.offset_data()).unwrap(),
+            )
+        });
+        for (lang, sys) in default.into_iter().chain(others) {
+            let mut line = format!("{}/{}:", script.script_tag(), lang);
+            if sys.required_feature_index() != 0xFFFF {
+                line.push_str(&format!(
+                    " required {}",
+                    feature_lookups(sys.required_feature_index())
+                ));
+            }
+            for idx in sys.feature_indices() {
+                line.push_str(&format!(" {}", feature_lookups(idx.get())));
             }
-            result.map_err(|e| e.reason)
+            result.push(line);
         }
     }
+    result
+}
+
+#[test]
+fn language_exclude_and_include_dflt() {
+    let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+languagesystem latn TRK;
+feature liga {
+    sub a by b;
+    script latn;
+    sub c by d;
+    language TRK exclude_dflt;
+    sub e by f;
+    language DEU include_dflt;
+    sub g by h;
+    language ROM;
+    sub i by j;
+} liga;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    assert_eq!(
+        gsub_lang_sys_lookups(&data),
+        [
+            "DFLT/dflt: liga[0]",
+            "latn/dflt: liga[0, 1]",
+            "latn/DEU : liga[0, 1, 3]",
+            "latn/ROM : liga[0, 1, 4]",
+            "latn/TRK : liga[2]",
+        ]
+    );
+}
+
+#[test]
+fn language_required() {
+    let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+feature liga { sub a by b; } liga;
+feature ccmp {
+    sub c by d;
+    script latn;
+    language TRK required;
+    sub e by f;
+} ccmp;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    assert_eq!(
+        gsub_lang_sys_lookups(&data),
+        [
+            "DFLT/dflt: ccmp[1] liga[0]",
+            "latn/dflt: ccmp[1] liga[0]",
+            "latn/TRK : required ccmp[1, 2]",
+        ]
+    );
+}
+
+#[test]
+fn implicit_default_lang_system() {
+    let fea = "
+languagesystem latn dflt;
+feature liga { sub a by b; } liga;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    assert_eq!(gsub_lang_sys_lookups(&data), ["latn/dflt: liga[0]"]);
+
+    let data = compile_from_str(fea, Opts::new().implicit_default_lang_system(true)).unwrap();
+    assert_eq!(
+        gsub_lang_sys_lookups(&data),
+        ["DFLT/dflt: liga[0]", "latn/dflt: liga[0]"]
+    );
+}
+
+#[test]
+fn implicit_script_lang_systems() {
+    let fea = "
+feature liga {
+    sub f i by f_i;
+    script cyrl;
+    sub a by b;
+} liga;
+feature smcp { sub c by d; } smcp;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    assert_eq!(
+        gsub_lang_sys_lookups(&data),
+        ["DFLT/dflt: liga[0] smcp[2]", "cyrl/dflt: liga[1]"]
+    );
+
+    let data = compile_from_str(fea, Opts::new().implicit_script_lang_systems(true)).unwrap();
+    assert_eq!(
+        gsub_lang_sys_lookups(&data),
+        [
+            "DFLT/dflt: liga[0] smcp[2]",
+            "cyrl/dflt: liga[0, 1] smcp[2]"
+        ]
+    );
+}
+
+#[test]
+fn aalt_lookups_come_first() {
+    use write_fonts::read::{
+        tables::{gsub::SubstitutionLookup, layout::ChainedSequenceContext},
+        FontRef, TableProvider,
+    };
+
+    let fea = "
+feature aalt { feature smcp; } aalt;
+feature smcp {
+    sub c' d by e;
+    sub a by A;
+} smcp;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    assert_eq!(
+        gsub_lang_sys_lookups(&data),
+        ["DFLT/dflt: aalt[0] smcp[1, 3]"]
+    );
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gsub().unwrap().lookup_list().unwrap();
+    let Ok(SubstitutionLookup::ChainContextual(lookup)) = lookups.lookups().nth(1).unwrap() else {
+        panic!("expected a chained contextual lookup");
+    };
+    let ChainedSequenceContext::Format3(subtable) = lookup.subtables().next().unwrap().unwrap()
+    else {
+        panic!("expected format 3");
+    };
+    // the inline rule's anonymous lookup follows the contextual lookup
+    let records = subtable.seq_lookup_records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].lookup_list_index(), 2);
+}
+
+#[test]
+fn language_required_twice() {
+    let fea = "
+feature liga { script latn; language TRK required; sub a by b; } liga;
+feature ccmp { script latn; language TRK required; sub c by d; } ccmp;
+";
+    let Err(CompilerError::CompilationFail(errs)) = compile_from_str(fea, Opts::new()) else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert_eq!(
+        errs.messages[0].text(),
+        "feature 'liga' is already required for language system 'latn/TRK '"
+    );
+}
+
+#[test]
+fn multiple_sub_class_expansion() {
+    use write_fonts::read::{tables::gsub::SubstitutionLookup, FontRef, TableProvider};
+
+    let fea = "feature ccmp { sub [f_i f_f_i] by [f f] [i acute] grave; } ccmp;";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gsub().unwrap().lookup_list().unwrap();
+    let SubstitutionLookup::Multiple(lookup) = lookups.lookups().next().unwrap().unwrap() else {
+        panic!("expected multiple substitution");
+    };
+    let subtable = lookup.subtables().next().unwrap().unwrap();
+    let names = test_utils::make_glyph_map().reverse_map();
+    let rules = subtable
+        .coverage()
+        .unwrap()
+        .iter()
+        .zip(subtable.sequences())
+        .map(|(target, seq)| {
+            let replacement = seq
+                .unwrap()
+                .substitute_glyph_ids()
+                .iter()
+                .map(|gid| names[&gid.get()].to_string())
+                .collect::<Vec<_>>();
+            format!("{} -> {}", names[&target], replacement.join(" "))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(rules, ["f_f_i -> f acute grave", "f_i -> f i grave"]);
+}
+
+#[test]
+fn multiple_sub_class_length_mismatch() {
+    let fea = "feature ccmp { sub [f_i f_f_i] by [f f i] i; } ccmp;";
+    let Err(CompilerError::CompilationFail(errs)) = compile_from_str(fea, Opts::new()) else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert_eq!(
+        errs.messages[0].text(),
+        "class has different length (3) than target (2)"
+    );
+}
+
+#[test]
+fn single_sub_class_length_mismatch() {
+    let fea = "feature test { sub [a b] by [c d e]; } test;";
+    let Err(CompilerError::CompilationFail(errs)) = compile_from_str(fea, Opts::new()) else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert_eq!(
+        errs.messages[0].text(),
+        "class has different length (3) than target (2)"
+    );
+}
+
+#[test]
+fn ligature_sets_longest_first() {
+    use write_fonts::read::{tables::gsub::SubstitutionLookup, FontRef, TableProvider};
+
+    let fea = "feature liga { sub f i by f_i; sub f f i by f_f_i; sub f f by f_f; } liga;";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gsub().unwrap().lookup_list().unwrap();
+    let SubstitutionLookup::Ligature(lookup) = lookups.lookups().next().unwrap().unwrap() else {
+        panic!("expected ligature substitution");
+    };
+    let subtable = lookup.subtables().next().unwrap().unwrap();
+    let names = test_utils::make_glyph_map().reverse_map();
+    let lig_set = subtable.ligature_sets().next().unwrap().unwrap();
+    let ligatures = lig_set
+        .ligatures()
+        .map(|lig| names[&lig.unwrap().ligature_glyph()].to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(ligatures, ["f_f_i", "f_f", "f_i"]);
+}
+
+#[test]
+fn contextual_sub_class_based_format() {
+    use write_fonts::read::{
+        tables::{gsub::SubstitutionLookup, layout::SequenceContext},
+        FontRef, TableProvider,
+    };
+
+    let fea = "
+@A = [a c e g];
+@B = [b d f h];
+@C = [i k m o];
+lookup X { sub a by z; } X;
+feature test {
+    sub @A' lookup X @A' @B';
+    sub @A' lookup X @B' @C';
+    sub @A' lookup X @C' @A';
+    sub @B' lookup X @A' @B';
+    sub @B' lookup X @B' @C';
+    sub @B' lookup X @C' @A';
+    sub @C' lookup X @A' @B';
+    sub @C' lookup X @B' @C';
+    sub @C' lookup X @C' @A';
+} test;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gsub().unwrap().lookup_list().unwrap();
+    let SubstitutionLookup::Contextual(lookup) = lookups.lookups().nth(1).unwrap().unwrap() else {
+        panic!("expected contextual substitution");
+    };
+    assert_eq!(lookup.sub_table_count(), 1);
+    let SequenceContext::Format2(subtable) = lookup.subtables().next().unwrap().unwrap() else {
+        panic!("expected class-based subtable");
+    };
+    // one rule set for each class, plus class 0, which is unused
+    assert_eq!(subtable.class_seq_rule_set_count(), 4);
+}
+
+#[test]
+fn lookup_sources() {
+    let fea = "\
+languagesystem DFLT dflt;
+lookup SHIFT { pos a 10; } SHIFT;
+feature kern {
+    pos a b -10;
+    pos c d -20;
+    pos e' lookup SHIFT f;
+    pos g' 5 h;
+} kern;
+feature liga { sub f i by f_i; } liga;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+    let text = |info: &crate::compile::LookupInfo| {
+        info.sources
+            .iter()
+            .map(|loc| &fea[loc.range.clone()])
+            .collect::<Vec<_>>()
+    };
+
+    let gpos = compilation.gpos_lookups();
+    assert_eq!(gpos.len(), 4);
+    assert_eq!(text(&gpos[0]), ["pos a 10;"]);
+    assert_eq!(text(&gpos[1]), ["pos a b -10;", "pos c d -20;"]);
+    // the anonymous lookup belongs to the rule that created it
+    assert_eq!(text(&gpos[2]), ["pos e' lookup SHIFT f;", "pos g' 5 h;"]);
+    assert_eq!(text(&gpos[3]), ["pos g' 5 h;"]);
+    assert_eq!(compilation.gpos_lookup_sources(3), gpos[3].sources);
+
+    let gsub = compilation.gsub_lookups();
+    assert_eq!(text(&gsub[0]), ["sub f i by f_i;"]);
+    assert!(compilation.gsub_lookup_sources(1).is_empty());
+}
+
+#[test]
+fn compilation_summary() {
+    use crate::compile::LookupKind;
+    use write_fonts::types::Tag;
+
+    let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+languagesystem latn TRK;
+feature liga { sub f i by f_i; sub f f i by f_f_i; } liga;
+feature kern { pos [a b] [c d] -10; pos a e -20; subtable; pos f g 5; } kern;
+feature locl { script latn; language TRK required; sub i by j; } locl;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+
+    let gsub = compilation.gsub_lookups();
+    assert_eq!(gsub.len(), 2);
+    assert_eq!(gsub[0].kind, LookupKind::GsubLigature);
+    assert_eq!(gsub[0].rule_count, 2);
+    assert_eq!(gsub[1].kind, LookupKind::GsubSingle);
+
+    let gpos = compilation.gpos_lookups();
+    assert_eq!(gpos.len(), 1);
+    assert_eq!(gpos[0].kind, LookupKind::GposPair);
+    assert_eq!(gpos[0].kind.lookup_type(), 2);
+    // one class pair and two glyph pairs, in a class subtable and two glyph subtables
+    assert_eq!(gpos[0].rule_count, 3);
+    assert_eq!(gpos[0].subtable_count, 3);
+
+    let features = compilation
+        .features()
+        .unwrap()
+        .into_iter()
+        .filter(|info| info.script == Tag::new(b"latn"))
+        .map(|info| {
+            let required = if info.required { " (required)" } else { "" };
+            format!(
+                "{}/{} {}{required}: {:?} {:?}",
+                info.script, info.language, info.feature, info.gsub_lookups, info.gpos_lookups
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        features,
+        [
+            "latn/TRK  kern: [] [0]",
+            "latn/TRK  liga: [0] []",
+            "latn/TRK  locl (required): [1] []",
+            "latn/dflt kern: [] [0]",
+            "latn/dflt liga: [0] []",
+            "latn/dflt locl: [] []",
+        ]
+    );
+}
+
+#[test]
+fn contextual_rule_referencing_empty_lookup() {
+    use write_fonts::read::{FontRef, TableProvider};
+
+    let fea = "
+lookup EMPTY { } EMPTY;
+feature calt { sub a' lookup EMPTY b; } calt;
+feature kern { pos a' lookup EMPTY b; } kern;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    assert_eq!(
+        font.gsub().unwrap().lookup_list().unwrap().lookup_count(),
+        1
+    );
+    assert_eq!(
+        font.gpos().unwrap().lookup_list().unwrap().lookup_count(),
+        1
+    );
+}
+
+#[test]
+fn glyph_name_aliases() {
+    use write_fonts::read::{tables::gsub::SingleSubst, FontRef, TableProvider};
+
+    let mut glyph_map = [".notdef", "uni0066", "uni0069", "uniFB01", "f.alt"]
+        .into_iter()
+        .map(GlyphName::new)
+        .collect::<GlyphMap>();
+    let errors = glyph_map.add_aliases(
+        [
+            ("f", "uni0066"),
+            ("i", "uni0069"),
+            ("f_i", "uniFB01"),
+            ("f.alt", "uni0069"),
+            ("i.alt", "uni0069"),
+            ("i.alt", "uniFB01"),
+            ("missing", "uni0131"),
+        ]
+        .into_iter()
+        .map(|(alias, name)| (alias.into(), name.into())),
+    );
+    assert_eq!(
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        [
+            "alias 'f.alt' for 'uni0069' is also the name of another glyph",
+            "alias 'i.alt' is given for both 'uni0069' and 'uniFB01'",
+            "alias 'missing' refers to unknown glyph 'uni0131'",
+        ]
+    );
+
+    let fea = "feature liga { sub f by f.alt; sub uni0069 by f_i; } liga;";
+    let tree = test_utils::parse_str(fea, &glyph_map);
+    let warnings = crate::compile::validation_diagnostics(&tree, &glyph_map, &Default::default());
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(
+        warnings[0].text(),
+        "alias 'f.alt' for 'uni0069' is also the name of another glyph; using the glyph named 'f.alt'"
+    );
+    let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+    let data = compilation
+        .assemble(&glyph_map, Opts::new())
+        .unwrap()
+        .build();
+    let font = FontRef::new(&data).unwrap();
+    let lookup = font
+        .gsub()
+        .unwrap()
+        .lookup_list()
+        .unwrap()
+        .lookups()
+        .next()
+        .unwrap()
+        .unwrap();
+    let write_fonts::read::tables::gsub::SubstitutionLookup::Single(lookup) = lookup else {
+        panic!("expected single substitution");
+    };
+    let SingleSubst::Format2(subtable) = lookup.subtables().next().unwrap().unwrap() else {
+        panic!("expected format 2");
+    };
+    let rules = subtable
+        .coverage()
+        .unwrap()
+        .iter()
+        .zip(subtable.substitute_glyph_ids())
+        .map(|(target, sub)| (target.to_u16(), sub.get().to_u16()))
+        .collect::<Vec<_>>();
+    assert_eq!(rules, [(1, 4), (2, 3)]);
+
+    let fea = "feature liga { sub i.alt by f; } liga;";
+    let Err(CompilerError::ValidationFail(errs)) =
+        test_utils::compile_str(fea, &glyph_map, identity)
+    else {
+        panic!("expected validation to fail");
+    };
+    assert_eq!(
+        errs.messages[0].text(),
+        "alias 'i.alt' is given for both 'uni0069' and 'uniFB01'"
+    );
+}
+
+#[test]
+fn contextual_rules_share_anon_lookups() {
+    use write_fonts::read::{FontRef, TableProvider};
+
+    let fea = "
+feature test {
+    sub a' b by c;
+    sub a' d by c;
+    sub a' e by d;
+    sub f' i' x by f_i;
+    sub f' i' y by f_i;
+} test;
+feature kern {
+    pos a' 10 b;
+    pos a' 20 c;
+    pos a' 10 d;
+} kern;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    // one contextual lookup, with anonymous lookups for 'a -> c', 'a -> d'
+    // and 'f i -> f_i'
+    let gsub_lookups = font.gsub().unwrap().lookup_list().unwrap();
+    assert_eq!(gsub_lookups.lookup_count(), 4);
+    // one contextual lookup, with anonymous lookups for 'a 10' and 'a 20'
+    let gpos_lookups = font.gpos().unwrap().lookup_list().unwrap();
+    assert_eq!(gpos_lookups.lookup_count(), 3);
+}
+
+#[test]
+fn contextual_inline_single_pos() {
+    use write_fonts::read::{
+        tables::gpos::{PositionLookup, SinglePos},
+        FontRef, TableProvider,
+    };
+
+    // as in feaLib, each inline value goes in the first anonymous lookup
+    // (since the last subtable break) that has no other value for its glyphs
+    let fea = "
+feature kern {
+    pos a' 10 b;
+    pos a' 20 c;
+    pos b' 20 c;
+    pos [a b]' 10 d;
+    pos x' 5 a' 20 y;
+    subtable;
+    pos a' 10 e;
+} kern;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let glyph_map = test_utils::make_glyph_map();
+    let names = glyph_map.reverse_map();
+    // the glyph names and x advances of each single positioning lookup
+    let lookups = font
+        .gpos()
+        .unwrap()
+        .lookup_list()
+        .unwrap()
+        .lookups()
+        .filter_map(|lookup| match lookup.unwrap() {
+            PositionLookup::Single(lookup) => Some(lookup),
+            _ => None,
+        })
+        .map(|lookup| {
+            let mut values = lookup
+                .subtables()
+                .flat_map(|sub| match sub.unwrap() {
+                    SinglePos::Format1(sub) => {
+                        let value = sub.value_record().x_advance().unwrap();
+                        let coverage = sub.coverage().unwrap();
+                        coverage.iter().map(|gid| (gid, value)).collect::<Vec<_>>()
+                    }
+                    SinglePos::Format2(sub) => {
+                        let coverage = sub.coverage().unwrap();
+                        let values = sub.value_records().iter();
+                        coverage
+                            .iter()
+                            .zip(values)
+                            .map(|(gid, value)| (gid, value.unwrap().x_advance().unwrap()))
+                            .collect()
+                    }
+                })
+                .map(|(gid, value)| (names[&gid].to_string(), value))
+                .collect::<Vec<_>>();
+            values.sort();
+            values
+        })
+        .collect::<Vec<_>>();
+
+    let expected: [&[(&str, i16)]; 4] = [
+        &[("a", 10), ("b", 20), ("x", 5)],
+        &[("a", 20)],
+        &[("a", 10), ("b", 10)],
+        &[("a", 10)],
+    ];
+    let expected = expected
+        .iter()
+        .map(|values| {
+            values
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(lookups, expected);
+}
+
+#[test]
+fn pair_pos_subtable_break() {
+    use write_fonts::read::{
+        tables::gpos::{PairPos, PositionLookup},
+        FontRef, TableProvider,
+    };
+
+    // each subtable after a break has its own pairs and classdefs
+    let fea = "
+feature kern {
+    pos a b 10;
+    pos [c d] [e f] 20;
+    subtable;
+    pos a c 15;
+    pos [g h] [c d e] 30;
+} kern;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let Ok(PositionLookup::Pair(lookup)) = lookups.lookups().next().unwrap() else {
+        panic!("expected pair positioning");
+    };
+    let subtables = lookup
+        .subtables()
+        .map(|sub| match sub.unwrap() {
+            PairPos::Format1(sub) => (1, sub.pair_set_count(), 0),
+            PairPos::Format2(sub) => (2, sub.class1_count(), sub.class2_count()),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(subtables, [(1, 1, 0), (2, 1, 2), (1, 1, 0), (2, 1, 2)]);
+
+    // class ids start again from 1 after the break, and 'e' shares a class
+    // with 'c' and 'd' instead of keeping its class from the first subtable
+    let ttx = crate::util::debug::to_ttx_xml(&data, &test_utils::make_glyph_map()).unwrap();
+    let class_defs = ttx
+        .split("<ClassDef2>")
+        .skip(1)
+        .map(|rest| {
+            let class_def = rest.split("</ClassDef2>").next().unwrap();
+            class_def.split_whitespace().collect::<Vec<_>>().join(" ")
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        class_defs,
+        [
+            r#"<ClassDef glyph="e" class="1"/> <ClassDef glyph="f" class="1"/>"#,
+            r#"<ClassDef glyph="c" class="1"/> <ClassDef glyph="d" class="1"/> <ClassDef glyph="e" class="1"/>"#,
+        ]
+    );
+}
+
+#[test]
+fn mark_to_base_anchors_across_statements() {
+    use write_fonts::read::{
+        tables::gpos::{AnchorTable, PositionLookup},
+        FontRef, TableProvider,
+    };
+
+    let fea = "
+markClass acute <anchor 0 500> @TOP;
+markClass cedilla <anchor 0 0> @BOTTOM;
+feature mark {
+    pos base a <anchor 250 450> mark @TOP;
+    pos base a <anchor 250 0> mark @BOTTOM;
+    pos base a <anchor 300 450> mark @TOP;
+} mark;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let Ok(PositionLookup::MarkToBase(lookup)) = lookups.lookups().next().unwrap() else {
+        panic!("expected mark-to-base");
+    };
+    let subtable = lookup.subtables().next().unwrap().unwrap();
+    let base_array = subtable.base_array().unwrap();
+    let records = base_array.base_records();
+    assert_eq!(records.len(), 1);
+    // the first anchor for each class is kept
+    let anchors = records
+        .get(0)
+        .unwrap()
+        .base_anchors(base_array.offset_data())
+        .map(|anchor| match anchor.unwrap().unwrap() {
+            AnchorTable::Format1(anchor) => (anchor.x_coordinate(), anchor.y_coordinate()),
+            _ => panic!("expected format 1 anchors"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(anchors, [(250, 450), (250, 0)]);
+}
+
+#[test]
+fn cursive_null_anchors() {
+    use write_fonts::read::{tables::gpos::PositionLookup, FontRef, TableProvider};
+
+    let fea = "
+feature curs {
+    lookupflag RightToLeft IgnoreMarks;
+    pos cursive [a b] <anchor NULL> <anchor 500 0>;
+    pos cursive c <anchor 0 0> <anchor NULL>;
+} curs;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let Ok(PositionLookup::Cursive(lookup)) = lookups.lookups().next().unwrap() else {
+        panic!("expected cursive positioning");
+    };
+    // RightToLeft | IgnoreMarks
+    assert_eq!(lookup.lookup_flag().to_bits(), 0x0009);
+    let subtable = lookup.subtables().next().unwrap().unwrap();
+    let records = subtable
+        .entry_exit_record()
+        .iter()
+        .map(|rec| {
+            let data = subtable.offset_data();
+            (
+                rec.entry_anchor(data).is_some(),
+                rec.exit_anchor(data).is_some(),
+            )
+        })
+        .collect::<Vec<_>>();
+    // a and b have only an exit, c only an entry
+    assert_eq!(records, [(false, true), (false, true), (true, false)]);
+}
+
+#[test]
+fn single_pos_subtable_formats() {
+    use write_fonts::read::{
+        tables::gpos::{PositionLookup, SinglePos},
+        FontRef, TableProvider,
+    };
+
+    // glyphs sharing a value use format 1 when that is smaller; remaining
+    // values are grouped by value format into format 2 subtables, and a
+    // format with only one value is written as format 1 again (as in feaLib)
+    let fea = "
+feature kern {
+    pos [a b c d e f] 10;
+    pos g <1 2 3 4>;
+    pos h <5 6 7 8>;
+    pos i <0 5 0 0>;
+} kern;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let Ok(PositionLookup::Single(lookup)) = lookups.lookups().next().unwrap() else {
+        panic!("expected single positioning");
+    };
+    let subtables = lookup
+        .subtables()
+        .map(|sub| match sub.unwrap() {
+            SinglePos::Format1(sub) => (1, sub.coverage().unwrap().iter().count()),
+            SinglePos::Format2(sub) => (2, sub.coverage().unwrap().iter().count()),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(subtables, [(1, 6), (2, 2), (1, 1)]);
+}
+
+#[test]
+fn opts_missing_glyphs() {
+    use crate::compile::MissingGlyphs;
+    use write_fonts::read::{
+        tables::gsub::{SingleSubst, SubstitutionLookup},
+        FontRef, TableProvider,
+    };
+
+    let fea = "
+@class = [a b missing];
+markClass nomark <anchor 0 0> @MARK;
+feature test {
+    sub @class by c;
+    sub d by missing;
+    sub e by f;
+} test;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let gid = |name: &str| glyph_map.get(name).unwrap().to_u16();
+    let single_subs = |mode| {
+        let data = compile_from_str(fea, Opts::new().missing_glyphs(mode)).unwrap();
+        let font = FontRef::new(&data).unwrap();
+        let lookup = font
+            .gsub()
+            .unwrap()
+            .lookup_list()
+            .unwrap()
+            .lookups()
+            .next()
+            .unwrap()
+            .unwrap();
+        let SubstitutionLookup::Single(lookup) = lookup else {
+            panic!("expected single substitution");
+        };
+        let mut rules = Vec::new();
+        for subtable in lookup.subtables() {
+            match subtable.unwrap() {
+                SingleSubst::Format1(table) => {
+                    rules.extend(table.coverage().unwrap().iter().map(|gid| {
+                        let sub = gid.to_u16() as i32 + table.delta_glyph_id() as i32;
+                        (gid.to_u16(), sub as u16)
+                    }))
+                }
+                SingleSubst::Format2(table) => rules.extend(
+                    table
+                        .coverage()
+                        .unwrap()
+                        .iter()
+                        .zip(table.substitute_glyph_ids())
+                        .map(|(gid, sub)| (gid.to_u16(), sub.get().to_u16())),
+                ),
+            }
+        }
+        rules.sort_unstable();
+        rules
+    };
+
+    let Err(CompilerError::ValidationFail(errs)) = compile_from_str(fea, Opts::new()) else {
+        panic!("expected validation to fail");
+    };
+    assert_eq!(errs.messages.len(), 3);
+    assert!(errs
+        .messages
+        .iter()
+        .all(|m| m.text() == "glyph not in font"));
+
+    assert_eq!(
+        single_subs(MissingGlyphs::Drop),
+        [
+            (gid("a"), gid("c")),
+            (gid("b"), gid("c")),
+            (gid("e"), gid("f"))
+        ]
+    );
+    assert_eq!(
+        single_subs(MissingGlyphs::Placeholder),
+        [
+            (0, gid("c")),
+            (gid("a"), gid("c")),
+            (gid("b"), gid("c")),
+            (gid("d"), 0),
+            (gid("e"), gid("f")),
+        ]
+    );
+}
+
+#[test]
+fn opts_missing_glyphs_gdef() {
+    use crate::compile::MissingGlyphs;
+    use write_fonts::read::{tables::gdef::ClassDef, FontRef, TableProvider};
+
+    let fea = "
+table GDEF {
+    GlyphClassDef [a missing], [f_i], , ;
+    Attach missing 1;
+    Attach a 2;
+    LigatureCaretByPos missing 200;
+    LigatureCaretByPos f_i 300;
+} GDEF;
+";
+    let data = compile_from_str(fea, Opts::new().missing_glyphs(MissingGlyphs::Drop)).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let gdef = font.gdef().unwrap();
+    let glyph_map = test_utils::make_glyph_map();
+    let gid = |name: &str| glyph_map.get(name).unwrap();
+
+    let ClassDef::Format2(class_def) = gdef.glyph_class_def().unwrap().unwrap() else {
+        panic!("expected format 2 class def");
+    };
+    let classes = class_def
+        .class_range_records()
+        .iter()
+        .map(|r| (r.start_glyph_id(), r.end_glyph_id(), r.class()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        classes,
+        [(gid("a"), gid("a"), 1), (gid("f_i"), gid("f_i"), 2)]
+    );
+
+    let attached = gdef
+        .attach_list()
+        .unwrap()
+        .unwrap()
+        .coverage()
+        .unwrap()
+        .iter()
+        .collect::<Vec<_>>();
+    assert_eq!(attached, [gid("a")]);
+
+    let carets = gdef
+        .lig_caret_list()
+        .unwrap()
+        .unwrap()
+        .coverage()
+        .unwrap()
+        .iter()
+        .collect::<Vec<_>>();
+    assert_eq!(carets, [gid("f_i")]);
+}
+
+#[test]
+fn opts_glyph_subset() {
+    use crate::compile::LookupKind;
+
+    let fea = "
+languagesystem DFLT dflt;
+lookup SUB_A { sub a by b; } SUB_A;
+feature liga {
+    sub f i by f_i;
+    sub [a c d] by [e g h];
+} liga;
+feature calt {
+    sub x' lookup SUB_A y;
+} calt;
+feature kern {
+    pos [a c] [d g] -10;
+    pos a b 20;
+} kern;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let excluded = ["b", "g", "f_i"].map(|name| glyph_map.get(name).unwrap());
+    let subset = (0..glyph_map.len() as u16)
+        .map(write_fonts::types::GlyphId::new)
+        .filter(|gid| !excluded.contains(gid));
+    let compile = |opts| {
+        test_utils::compile_str(fea, &glyph_map, |compiler| compiler.with_opts(opts)).unwrap()
+    };
+
+    let full = compile(Opts::new());
+    assert_eq!(full.features().unwrap().len(), 3);
+    assert_eq!(full.gsub_lookups().len(), 4);
+
+    let subset = compile(Opts::new().glyph_subset(subset));
+    let features = subset
+        .features()
+        .unwrap()
+        .into_iter()
+        .map(|info| {
+            (
+                info.feature.to_string(),
+                info.gsub_lookups,
+                info.gpos_lookups,
+            )
+        })
+        .collect::<Vec<_>>();
+    // the calt rule loses its only lookup, but is kept: it still matches
+    assert_eq!(
+        features,
+        [
+            ("calt".to_string(), vec![1], vec![]),
+            ("kern".to_string(), vec![], vec![0]),
+            ("liga".to_string(), vec![0], vec![]),
+        ]
+    );
+    let gsub = subset.gsub_lookups();
+    assert_eq!(gsub.len(), 2);
+    assert_eq!(gsub[0].kind, LookupKind::GsubSingle);
+    assert_eq!(gsub[0].rule_count, 2);
+    assert_eq!(gsub[1].kind, LookupKind::GsubChainContextual);
+    assert_eq!(gsub[1].rule_count, 1);
+    let gpos = subset.gpos_lookups();
+    assert_eq!(gpos.len(), 1);
+    assert_eq!(gpos[0].kind, LookupKind::GposPair);
+    assert_eq!(gpos[0].rule_count, 1);
+
+    // the subset tables must still be valid
+    subset.assemble(&glyph_map, Opts::new()).unwrap();
+}
+
+#[test]
+fn opts_remap_features() {
+    use write_fonts::types::Tag;
+
+    let fea = "
+languagesystem DFLT dflt;
+languagesystem latn TRK;
+feature ss01 { sub a by b; } ss01;
+feature ss02 { sub c by d; } ss02;
+feature calt { sub e by f; } calt;
+feature locl { script latn; language TRK required; sub i by j; } locl;
+feature kern { pos a b -10; } kern;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let compile = |opts| {
+        let compilation =
+            test_utils::compile_str(fea, &glyph_map, |compiler| compiler.with_opts(opts)).unwrap();
+        // the remapped features must still be valid
+        compilation.assemble(&glyph_map, Opts::new()).unwrap();
+        compilation
+            .features()
+            .unwrap()
+            .into_iter()
+            .map(|info| {
+                let required = if info.required { " (required)" } else { "" };
+                format!(
+                    "{}/{} {}{required}: {:?} {:?}",
+                    info.script, info.language, info.feature, info.gsub_lookups, info.gpos_lookups
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let features = compile(
+        Opts::new()
+            .rename_feature(Tag::new(b"ss01"), Tag::new(b"calt"))
+            .copy_feature(Tag::new(b"ss02"), Tag::new(b"ccmp"))
+            .rename_feature(Tag::new(b"locl"), Tag::new(b"rlig"))
+            .copy_feature(Tag::new(b"kern"), Tag::new(b"dist")),
+    );
+    assert_eq!(
+        features,
+        [
+            "DFLT/dflt calt: [0, 2] []",
+            "DFLT/dflt ccmp: [1] []",
+            "DFLT/dflt dist: [] [0]",
+            "DFLT/dflt kern: [] [0]",
+            "DFLT/dflt rlig: [] []",
+            "DFLT/dflt ss02: [1] []",
+            "latn/TRK  calt: [0, 2] []",
+            "latn/TRK  ccmp: [1] []",
+            "latn/TRK  dist: [] [0]",
+            "latn/TRK  kern: [] [0]",
+            "latn/TRK  rlig (required): [3] []",
+            "latn/TRK  ss02: [1] []",
+        ]
+    );
+}
+
+#[test]
+fn opts_script_subset() {
+    use write_fonts::types::Tag;
+
+    let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+languagesystem latn TRK;
+languagesystem arab dflt;
+lookup UNUSED { sub a by b; } UNUSED;
+lookup SHARED { sub c by d; } SHARED;
+lookup ARAB_ONLY { sub e by f; } ARAB_ONLY;
+feature calt {
+    sub x' lookup SHARED y;
+    script arab;
+    sub x' lookup ARAB_ONLY z;
+} calt;
+feature locl {
+    script latn;
+    language TRK;
+    sub i by j;
+} locl;
+feature aalt { feature salt; } aalt;
+feature salt { sub g by h; } salt;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let compile = |opts| {
+        test_utils::compile_str(fea, &glyph_map, |compiler| compiler.with_opts(opts)).unwrap()
+    };
+    let summarize = |compilation: &crate::compile::Compilation| {
+        compilation
+            .features()
+            .unwrap()
+            .into_iter()
+            .map(|info| {
+                format!(
+                    "{}/{} {}: {:?}",
+                    info.script, info.language, info.feature, info.gsub_lookups
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let full = compile(Opts::new());
+    assert_eq!(full.gsub_lookups().len(), 8);
+
+    let latn = compile(Opts::new().script_subset([Tag::new(b"latn")]));
+    // ARAB_ONLY and the contextual lookup that uses it are removed
+    assert_eq!(latn.gsub_lookups().len(), 6);
+    assert_eq!(
+        summarize(&latn),
+        [
+            "latn/TRK  aalt: [0]",
+            "latn/TRK  calt: [3]",
+            "latn/TRK  locl: [4]",
+            "latn/TRK  salt: [5]",
+            "latn/dflt aalt: [0]",
+            "latn/dflt calt: [3]",
+            "latn/dflt locl: []",
+            "latn/dflt salt: [5]",
+        ]
+    );
+    latn.assemble(&glyph_map, Opts::new()).unwrap();
+
+    let trk = compile(Opts::new().language_system_subset([(Tag::new(b"latn"), Tag::new(b"TRK "))]));
+    assert_eq!(
+        summarize(&trk),
+        [
+            "latn/TRK  aalt: [0]",
+            "latn/TRK  calt: [3]",
+            "latn/TRK  locl: [4]",
+            "latn/TRK  salt: [5]",
+        ]
+    );
+}
+
+#[test]
+fn mark_attach_classes() {
+    let fea = "
+@TOP = [acute grave];
+feature mark {
+    lookupflag MarkAttachmentType @TOP;
+    pos a 10;
+    lookupflag MarkAttachmentType [cedilla];
+    pos b 10;
+    lookupflag MarkAttachmentType [grave acute];
+    pos c 10;
+} mark;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+    // the same glyphs in a different order share a class
+    let expected = [("acute", 1), ("grave", 1), ("cedilla", 2)]
+        .map(|(name, class)| (glyph_map.get(name).unwrap(), class))
+        .into_iter()
+        .collect();
+    assert_eq!(compilation.mark_attach_classes(), expected);
+    let flags = compilation
+        .gpos_lookups()
+        .iter()
+        .map(|info| info.flags.to_bits() >> 8)
+        .collect::<Vec<_>>();
+    assert_eq!(flags, [1, 2, 1]);
+}
+
+#[test]
+fn mark_attach_class_errors() {
+    let overlap = "
+feature mark {
+    lookupflag MarkAttachmentType [acute grave];
+    pos a 10;
+    lookupflag MarkAttachmentType [grave cedilla];
+    pos b 10;
+} mark;
+";
+    let Err(CompilerError::CompilationFail(errs)) = compile_from_str(overlap, Opts::new()) else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert_eq!(
+        errs.messages[0].text(),
+        "glyph 'grave' is already in a different mark attachment class"
+    );
+
+    // each class is a single glyph, so they don't overlap
+    let mut too_many = String::from("feature mark {\n");
+    let glyphs = (b'a'..=b'z')
+        .map(|c| (c as char).to_string())
+        .chain((b'A'..=b'Z').map(|c| (c as char).to_string()))
+        .chain((b'A'..=b'Z').map(|c| format!("{}.sc", c as char)))
+        .chain((800..=1001).map(|cid| format!("\\{cid}")));
+    for glyph in glyphs.take(256) {
+        too_many.push_str(&format!(
+            "lookupflag MarkAttachmentType [{glyph}];\npos a 10;\n"
+        ));
+    }
+    too_many.push_str("} mark;\n");
+    let Err(CompilerError::CompilationFail(errs)) = compile_from_str(too_many, Opts::new()) else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert_eq!(
+        errs.messages[0].text(),
+        "too many mark attachment classes: at most 255 can be defined"
+    );
+}
+
+#[test]
+fn variable_anchors() {
+    use write_fonts::{
+        read::{
+            tables::{
+                gpos::{AnchorTable, PositionLookup},
+                layout::{DeltaFormat, Device},
+            },
+            FontRef, TableProvider,
+        },
+        types::Tag,
+    };
+
+    use crate::compile::VariationAxis;
+
+    let fea = "
+markClass acute <anchor (wght=400:100 wght=900:150) 0> @TOP;
+feature mark {
+    pos base a <anchor (wght=400:250 wght=900:300) (wght=400:500 wght=100:480)> mark @TOP;
+    pos base b <anchor 300 (wght=400:500 wght=900:500)> mark @TOP;
+} mark;
+";
+    let err = compile_from_str(fea, Opts::new()).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("variable metrics require the variation axes of the font"),
+        "{err}"
+    );
+
+    let wght = VariationAxis::new(Tag::new(b"wght"), 100.0, 400.0, 900.0).unwrap();
+    let data = compile_from_str(fea, Opts::new().variation_axes([wght])).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let store = font.gdef().unwrap().item_var_store().unwrap().unwrap();
+    assert_eq!(store.variation_region_list().unwrap().region_count(), 2);
+    // the deltas of the mark anchor and the first base anchor's x are shared
+    assert_eq!(store.item_variation_data_count(), 1);
+    let data = store
+        .item_variation_datas()
+        .next()
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(data.item_count(), 2);
+
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let PositionLookup::MarkToBase(lookup) = lookups.lookups().next().unwrap().unwrap() else {
+        panic!("expected mark-to-base lookup");
+    };
+    let subtable = lookup.subtables().next().unwrap().unwrap();
+    let base_array = subtable.base_array().unwrap();
+    let anchors = base_array
+        .base_records()
+        .iter()
+        .map(|record| {
+            let record = record.unwrap();
+            let anchor = record
+                .base_anchors(base_array.offset_data())
+                .next()
+                .unwrap();
+            anchor.unwrap().unwrap()
+        })
+        .collect::<Vec<_>>();
+    // a VariationIndex table has the same layout as a device table
+    let variation_index = |device: Option<Result<Device, _>>| {
+        device.map(|device| {
+            let device = device.unwrap();
+            assert_eq!(device.delta_format(), DeltaFormat::VariationIndex);
+            (device.start_size(), device.end_size())
+        })
+    };
+    let AnchorTable::Format3(anchor) = &anchors[0] else {
+        panic!("expected a format 3 anchor");
+    };
+    assert_eq!((anchor.x_coordinate(), anchor.y_coordinate()), (250, 500));
+    assert_eq!(variation_index(anchor.x_device()), Some((0, 0)));
+    assert_eq!(variation_index(anchor.y_device()), Some((0, 1)));
+    // a metric that doesn't vary doesn't need a variation index
+    assert!(matches!(anchors[1], AnchorTable::Format1(_)));
+}
+
+#[test]
+fn opts_pair_pos_conflicts() {
+    use write_fonts::read::{
+        tables::gpos::{PairPos, PositionLookup},
+        FontRef, TableProvider,
+    };
+
+    use crate::compile::PairPosConflicts;
+
+    let fea = "feature kern { pos [a b] [c d] -20; pos a c -10; } kern;";
+    let subtable_formats = |mode| {
+        let data = compile_from_str(fea, Opts::new().pair_pos_conflicts(mode)).unwrap();
+        let font = FontRef::new(&data).unwrap();
+        let lookups = font.gpos().unwrap().lookup_list().unwrap();
+        let PositionLookup::Pair(lookup) = lookups.lookups().next().unwrap().unwrap() else {
+            panic!("expected pair pos");
+        };
+        let formats = lookup
+            .subtables()
+            .map(|sub| match sub.unwrap() {
+                PairPos::Format1(_) => 1,
+                PairPos::Format2(_) => 2,
+            })
+            .collect::<Vec<_>>();
+        formats
+    };
+
+    // the glyph pair is written first, so takes precedence
+    assert_eq!(subtable_formats(PairPosConflicts::SpecificWins), [1, 2]);
+    // the glyph pair comes after the class pair, so is dropped
+    assert_eq!(subtable_formats(PairPosConflicts::FirstWins), [2]);
+    assert!(
+        compile_from_str(fea, Opts::new().pair_pos_conflicts(PairPosConflicts::Error)).is_err()
+    );
+}
+
+#[test]
+fn opts_feature_record_order() {
+    use write_fonts::read::{FontRef, TableProvider};
+
+    use crate::compile::FeatureRecordOrder;
+
+    let fea = "
+languagesystem DFLT dflt;
+languagesystem latn TRK;
+languagesystem latn dflt;
+languagesystem arab dflt;
+
+feature smcp { sub a by A.sc; } smcp;
+feature liga {
+    sub f i by f_i;
+    script latn;
+    language TRK;
+    sub f l by f_l;
+} liga;
+feature calt { sub a by a.alt1; } calt;
+";
+    let compile = |order| compile_from_str(fea, Opts::new().feature_record_order(order)).unwrap();
+    let feature_records = |data: &[u8]| {
+        let font = FontRef::new(data).unwrap();
+        let features = font.gsub().unwrap().feature_list().unwrap();
+        features
+            .feature_records()
+            .iter()
+            .map(|record| {
+                let feature = record.feature(features.offset_data()).unwrap();
+                let lookups = feature
+                    .lookup_list_indices()
+                    .iter()
+                    .map(|idx| idx.get().to_string())
+                    .collect::<Vec<_>>();
+                format!("{}[{}]", record.feature_tag(), lookups.join(", "))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // feature records are sorted by tag; 'TRK ' sorts before 'dflt'
+    let first_use = compile(FeatureRecordOrder::FirstLanguageSystem);
+    assert_eq!(
+        feature_records(&first_use),
+        ["calt[3]", "liga[1, 2]", "liga[1]", "smcp[0]"]
+    );
+    let by_lookups = compile(FeatureRecordOrder::LookupIndices);
+    assert_eq!(
+        feature_records(&by_lookups),
+        ["calt[3]", "liga[1]", "liga[1, 2]", "smcp[0]"]
+    );
+
+    // scripts and languages are sorted by tag, and each language system's
+    // feature indices are sorted and refer to the same features either way
+    let expected = [
+        "DFLT/dflt: calt[3] liga[1] smcp[0]",
+        "arab/dflt: calt[3] liga[1] smcp[0]",
+        "latn/dflt: calt[3] liga[1] smcp[0]",
+        "latn/TRK : calt[3] liga[1, 2] smcp[0]",
+    ];
+    assert_eq!(gsub_lang_sys_lookups(&first_use), expected);
+    assert_eq!(gsub_lang_sys_lookups(&by_lookups), expected);
+}
+
+#[test]
+fn lookupflag_numeric_and_named() {
+    use write_fonts::read::{tables::gpos::PositionLookup, FontRef, TableProvider};
+
+    let fea = "
+@marks = [acute grave];
+feature kern {
+    lookupflag 6;
+    pos a 10;
+    lookupflag IgnoreLigatures IgnoreBaseGlyphs;
+    pos b 10;
+    lookupflag IgnoreMarks RightToLeft;
+    pos c 10;
+    lookupflag UseMarkFilteringSet @marks IgnoreLigatures;
+    pos d 10;
+    lookupflag 4;
+    pos e 10;
+    lookupflag 0;
+    pos f 10;
+} kern;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let flags = lookups
+        .lookups()
+        .map(|lookup| {
+            let Ok(PositionLookup::Single(lookup)) = lookup else {
+                panic!("expected single positioning");
+            };
+            let flags = lookup.lookup_flag().to_bits();
+            (
+                flags,
+                (flags & 0x10 != 0).then(|| lookup.mark_filtering_set()),
+            )
+        })
+        .collect::<Vec<_>>();
+    // the first two statements are equivalent, so their rules share a lookup,
+    // and a numeric value clears the mark filtering set
+    assert_eq!(
+        flags,
+        [
+            (0x6, None),
+            (0x9, None),
+            (0x14, Some(0)),
+            (0x4, None),
+            (0, None)
+        ]
+    );
+}
+
+#[test]
+fn lookupflag_reset_by_script() {
+    use write_fonts::read::{tables::gsub::SubstitutionLookup, FontRef, TableProvider};
+
+    let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+languagesystem latn TRK;
+feature liga {
+    script latn;
+    language TRK;
+    lookupflag IgnoreMarks;
+    sub a by b;
+    script latn;
+    sub c by d;
+} liga;
+feature calt {
+    script latn;
+    lookupflag IgnoreMarks;
+    sub e by f;
+} calt;
+feature ccmp {
+    script latn;
+    sub g by h;
+} ccmp;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gsub().unwrap().lookup_list().unwrap();
+    let flags = lookups
+        .lookups()
+        .map(|lookup| {
+            let Ok(SubstitutionLookup::Single(lookup)) = lookup else {
+                panic!("expected single substitution");
+            };
+            lookup.lookup_flag().to_bits()
+        })
+        .collect::<Vec<_>>();
+    // repeating the current script still resets the flag, as it does in a
+    // new feature
+    assert_eq!(flags, [0x8, 0, 0x8, 0]);
+    // and also resets the language to 'dflt'
+    assert_eq!(
+        gsub_lang_sys_lookups(&data),
+        ["latn/dflt: calt[2] ccmp[3] liga[1]", "latn/TRK : liga[0]"]
+    );
+}
+
+#[test]
+fn lookupflag_invalid_values() {
+    for (fea, message) in [
+        (
+            "feature kern { lookupflag 16; pos a 10; } kern;",
+            "UseMarkFilteringSet cannot be set numerically; use 'UseMarkFilteringSet' with a glyph class",
+        ),
+        (
+            "feature kern { lookupflag; pos a 10; } kern;",
+            "lookupflag requires a number or at least one named flag",
+        ),
+        (
+            "feature kern { lookupflag 70000; pos a 10; } kern;",
+            "value must be a positive 16 bit integer",
+        ),
+        (
+            "feature kern { lookupflag IgnoreMarks IgnoreMarks; pos a 10; } kern;",
+            "duplicate value in lookupflag",
+        ),
+    ] {
+        let Err(CompilerError::ValidationFail(errs)) = compile_from_str(fea, Opts::new()) else {
+            panic!("expected validation to fail: '{fea}'");
+        };
+        assert_eq!(errs.messages[0].text(), message, "{fea}");
+    }
+}
+
+#[test]
+fn alternates_from_range() {
+    use write_fonts::read::{tables::gsub::SubstitutionLookup, FontRef, TableProvider};
+
+    let fea =
+        "feature salt { sub a from [a.alt1 - a.alt3]; sub A from [A.alt1-A.alt3 A.sc]; } salt;";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gsub().unwrap().lookup_list().unwrap();
+    let Ok(SubstitutionLookup::Alternate(lookup)) = lookups.lookups().next().unwrap() else {
+        panic!("expected alternate substitution");
+    };
+    let subtable = lookup.subtables().next().unwrap().unwrap();
+    let glyph_map = test_utils::make_glyph_map();
+    let gid = |name: &str| glyph_map.get(name).unwrap().to_u16();
+    let alternates = subtable
+        .alternate_sets()
+        .map(|set| {
+            set.unwrap()
+                .alternate_glyph_ids()
+                .iter()
+                .map(|gid| gid.get().to_u16())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        alternates,
+        [
+            vec![gid("A.alt1"), gid("A.alt2"), gid("A.alt3"), gid("A.sc")],
+            vec![gid("a.alt1"), gid("a.alt2"), gid("a.alt3")],
+        ]
+    );
+
+    // missing members of a range are reported together
+    let fea = "feature salt { sub a from [a.alt1 - a.alt5]; } salt;";
+    let Err(CompilerError::CompilationFail(errs)) = compile_from_str(fea, Opts::new()) else {
+        panic!("expected compilation to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert_eq!(
+        errs.messages[0].text(),
+        "Range members 'a.alt4', 'a.alt5' do not exist in font"
+    );
+}
+
+#[test]
+fn restrict_includes_to_project_root() {
+    let dir = std::env::temp_dir().join(format!("fea-rs-restrict-{}", std::process::id()));
+    let project = dir.join("project");
+    std::fs::create_dir_all(&project).unwrap();
+    std::fs::write(dir.join("outside.fea"), "languagesystem latn dflt;").unwrap();
+    std::fs::write(project.join("inside.fea"), "languagesystem DFLT dflt;").unwrap();
+    let root = project.join("features.fea");
+    std::fs::write(&root, "include(inside.fea);\ninclude(../outside.fea);\n").unwrap();
+
+    let glyph_map = test_utils::make_glyph_map();
+    let compile = |restrict| {
+        Compiler::new(root.clone(), &glyph_map)
+            .restrict_includes_to_project_root(restrict)
+            .compile()
+    };
+    assert!(compile(false).is_ok());
+    let result = compile(true);
+    std::fs::remove_dir_all(&dir).unwrap();
+    let Err(CompilerError::ParseFail(errs)) = result else {
+        panic!("expected parsing to fail");
+    };
+    assert_eq!(errs.messages.len(), 1);
+    assert!(
+        errs.messages[0]
+            .text()
+            .contains("path is outside the project root"),
+        "{}",
+        errs.messages[0].text()
+    );
+}
+
+#[test]
+#[cfg(feature = "mmap")]
+fn compile_mmapped_sources() {
+    use crate::parse::FileSystemResolver;
+
+    let dir = std::env::temp_dir().join(format!("fea-rs-mmap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("features.fea"),
+        "\u{FEFF}include(kern.fea);\r\nfeature liga { sub a b by c; } liga;\r\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("kern.fea"),
+        "feature kern { pos a b -10; } kern;\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("bad.fea"),
+        b"feature kern { pos a \xff -10; } kern;\n",
+    )
+    .unwrap();
+
+    let glyph_map = test_utils::make_glyph_map();
+    let compile = |path: &str| {
+        Compiler::new(dir.join(path), &glyph_map)
+            .with_resolver(FileSystemResolver::new(dir.clone()).use_mmap(true))
+            .compile()
+    };
+    let good = compile("features.fea");
+    let bad = compile("bad.fea");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let compilation = good.unwrap();
+    assert_eq!(compilation.sources().len(), 2);
+    assert_eq!(compilation.gpos_lookups().len(), 1);
+    assert_eq!(compilation.gsub_lookups().len(), 1);
+    let Err(CompilerError::SourceLoad(err)) = bad else {
+        panic!("expected invalid utf-8 to fail");
+    };
+    assert!(err.to_string().contains("utf-8"), "{err}");
+}
+
+#[test]
+fn compile_project_targets() {
+    use crate::compile::{
+        error::ProjectError, FeaProject, GlyphSource, ProjectOutput, ProjectTarget,
+    };
+
+    let dir = std::env::temp_dir().join(format!("fea-rs-project-{}", std::process::id()));
+    for sub in ["shared", "roman", "italic"] {
+        std::fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    std::fs::write(dir.join("glyph_order.txt"), ".notdef\na\nb\nc\n").unwrap();
+    std::fs::write(
+        dir.join("shared/kern.fea"),
+        "feature kern { pos a b -10; } kern;",
+    )
+    .unwrap();
+    std::fs::write(dir.join("roman/features.fea"), "include(kern.fea);\n").unwrap();
+    std::fs::write(
+        dir.join("italic/features.fea"),
+        "include(kern.fea);\nfeature kern { pos a c -20; } kern;\n",
+    )
+    .unwrap();
+
+    let targets = || {
+        [
+            ProjectTarget::new("Roman", "roman/features.fea"),
+            ProjectTarget::new("Italic", "italic/features.fea"),
+        ]
+    };
+    let mut project = targets()
+        .into_iter()
+        .fold(FeaProject::new(&dir), FeaProject::with_target)
+        .with_include_dir("shared")
+        .with_glyph_source(GlyphSource::GlyphOrderFile("glyph_order.txt".into()));
+    let outputs = project.compile();
+    let recompiled = project.compile();
+    let unresolved = targets()
+        .into_iter()
+        .fold(FeaProject::new(&dir), FeaProject::with_target)
+        .with_glyph_source(GlyphSource::GlyphOrderFile("glyph_order.txt".into()))
+        .compile();
+    let no_glyphs = FeaProject::new(&dir)
+        .with_target(targets()[0].clone())
+        .compile();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let outputs = outputs.unwrap();
+    let names = outputs
+        .iter()
+        .map(|out| out.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["Roman", "Italic"]);
+    for (output, n_lookups) in outputs.iter().zip([1, 2]) {
+        assert_eq!(output.compilation.sources().len(), 2);
+        assert_eq!(output.compilation.gpos_lookups().len(), n_lookups);
+        assert!(!output.binary.is_empty());
+    }
+    // the targets share a cache, so the shared kerning is only built once
+    let reused = |outputs: &[ProjectOutput]| {
+        outputs
+            .iter()
+            .map(|out| out.reused_lookups)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(reused(&outputs), [0, 1]);
+    assert_eq!(reused(&recompiled.unwrap()), [1, 2]);
+    assert!(matches!(
+        unresolved,
+        Err(ProjectError::Target { target, source: CompilerError::ParseFail(_) }) if target == "Roman"
+    ));
+    assert!(
+        matches!(no_glyphs, Err(ProjectError::MissingGlyphSource(target)) if target == "Roman")
+    );
+}
+
+#[test]
+fn value_record_forms() {
+    use write_fonts::read::{
+        tables::gpos::{PairPos, PositionLookup, SinglePos},
+        FontRef, TableProvider,
+    };
+
+    // a bare number and a single bracketed number are the same record
+    let bare = compile_from_str("feature kern { pos a 10; pos a b -5; } kern;", Opts::new());
+    let bracketed = compile_from_str(
+        "feature kern { pos a <10>; pos a b <-5>; } kern;",
+        Opts::new(),
+    );
+    assert_eq!(bare.unwrap(), bracketed.unwrap());
+
+    let fea = "
+feature kern {
+    pos a <10>;
+    pos a b <-10>;
+    pos c' <5> d;
+} kern;
+feature vkrn {
+    pos a <10>;
+    pos a b <-10>;
+    pos c' <5> d;
+    pos e' <1 2 3 4 <device 11 -1> <device NULL> <device NULL> <device NULL>> d;
+} vkrn;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let value_formats = lookups
+        .lookups()
+        .filter_map(|lookup| match lookup.unwrap() {
+            PositionLookup::Single(lookup) => Some(
+                lookup
+                    .subtables()
+                    .map(|sub| match sub.unwrap() {
+                        SinglePos::Format1(table) => table.value_format().bits(),
+                        SinglePos::Format2(table) => table.value_format().bits(),
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            PositionLookup::Pair(lookup) => Some(
+                lookup
+                    .subtables()
+                    .map(|sub| match sub.unwrap() {
+                        PairPos::Format1(table) => table.value_format1().bits(),
+                        PairPos::Format2(table) => table.value_format1().bits(),
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // a single number sets the x advance in horizontal features and the
+    // y advance in vertical ones; four numbers (and devices) set everything
+    let x_adv = 0x4;
+    let y_adv = 0x8;
+    assert_eq!(
+        value_formats,
+        [
+            vec![x_adv],
+            vec![x_adv],
+            vec![x_adv],
+            vec![y_adv],
+            vec![y_adv],
+            vec![y_adv, 0x1f]
+        ]
+    );
+}
+
+#[test]
+fn vertical_features() {
+    use write_fonts::read::{
+        tables::gpos::{PositionLookup, SinglePos},
+        FontRef, TableProvider,
+    };
+
+    let fea = "
+feature vpal {
+    pos a 10;
+} vpal;
+feature valt {
+    pos b <0 0 -20 -20>;
+} valt;
+feature vert {
+    sub a by a.alt1;
+    sub f i by f_i;
+    sub b from [b.alt];
+} vert;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let tree = test_utils::parse_str(fea, &glyph_map);
+    let warnings = crate::compile::validation_diagnostics(&tree, &glyph_map, &Default::default());
+    assert_eq!(
+        warnings.iter().map(|w| w.text()).collect::<Vec<_>>(),
+        [
+            "x advance has no effect in vertical feature 'valt'",
+            "'vert' should only contain single substitutions",
+            "'vert' should only contain single substitutions",
+        ]
+    );
+
+    // a single number is the y advance
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let Ok(PositionLookup::Single(lookup)) = lookups.lookups().next().unwrap() else {
+        panic!("expected single positioning");
+    };
+    let Ok(SinglePos::Format1(sub)) = lookup.subtables().next().unwrap() else {
+        panic!("expected format 1");
+    };
+    assert_eq!(sub.value_record().y_advance(), Some(10));
+    assert_eq!(sub.value_record().x_advance(), None);
+}
+
+#[test]
+fn mark_to_ligature_components() {
+    use write_fonts::read::{tables::gpos::PositionLookup, FontRef, TableProvider};
+
+    // rules for the same ligature are merged, and any component can be NULL
+    let fea = "
+markClass acute <anchor 0 500> @TOP;
+markClass cedilla <anchor 0 0> @BOTTOM;
+feature mark {
+    pos ligature f_f_i <anchor 100 500> mark @TOP
+        ligComponent <anchor NULL>
+        ligComponent <anchor 500 500> mark @TOP;
+    pos ligature f_f_i <anchor 100 0> mark @BOTTOM
+        ligComponent <anchor NULL>
+        ligComponent <anchor NULL>;
+} mark;
+";
+    let data = compile_from_str(fea, Opts::new()).unwrap();
+    let font = FontRef::new(&data).unwrap();
+    let lookups = font.gpos().unwrap().lookup_list().unwrap();
+    let Ok(PositionLookup::MarkToLig(lookup)) = lookups.lookups().next().unwrap() else {
+        panic!("expected mark-to-ligature");
+    };
+    let subtable = lookup.subtables().next().unwrap().unwrap();
+    let ligature = subtable
+        .ligature_array()
+        .unwrap()
+        .ligature_attaches()
+        .next()
+        .unwrap()
+        .unwrap();
+    // for each component, whether it has a @TOP and a @BOTTOM anchor
+    let anchors = ligature
+        .component_records()
+        .iter()
+        .map(|component| {
+            let component = component.unwrap();
+            component
+                .ligature_anchors(ligature.offset_data())
+                .map(|anchor| anchor.is_some())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        anchors,
+        [vec![true, true], vec![false, false], vec![true, false]]
+    );
+}
+
+#[test]
+fn glyph_map_append() {
+    use crate::GlyphIdent;
+    use write_fonts::types::GlyphId;
+
+    let mut glyph_map: GlyphMap = ["a", "b"].into_iter().map(GlyphName::new).collect();
+    glyph_map.add_aliases([(GlyphName::new("x"), GlyphName::new("a"))]);
+    let fea = "feature liga { sub a by c; sub x by b; } liga;";
+    let tree = test_utils::parse_str(fea, &glyph_map);
+    assert!(crate::validate(&tree, &glyph_map, &Opts::new()).is_err());
+
+    // the tree is still valid, and only needs to be validated again
+    let update = glyph_map.append(["c", "a", "x"].map(|name| GlyphIdent::Name(name.into())));
+    assert_eq!(update.added(), 2);
+    assert!(!update.requires_reparse());
+    assert_eq!(glyph_map.get("a"), Some(GlyphId::new(0)));
+    assert_eq!(glyph_map.get("c"), Some(GlyphId::new(2)));
+    // the new glyph replaces the alias
+    assert_eq!(glyph_map.get("x"), Some(GlyphId::new(3)));
+    assert!(crate::compile(&tree, &glyph_map, Opts::new()).is_ok());
+
+    // hyphenated names may change how the source is parsed
+    let update = glyph_map.append([GlyphIdent::Name("a-b".into()), GlyphIdent::Cid(5)]);
+    assert_eq!(update.added(), 2);
+    assert!(update.requires_reparse());
+    assert_eq!(glyph_map.len(), 6);
+}
+
+#[test]
+fn tree_transforms() {
+    use crate::{Diagnostic, Kind, ParseTree};
+
+    // replace a marker comment with some parsed FEA
+    fn expand(marker: &'static str, text: &'static str) -> impl crate::compile::TreeTransform {
+        move |tree: &ParseTree| {
+            Ok(tree
+                .root()
+                .iter_tokens_with_ranges()
+                .filter(|(_, token)| token.kind == Kind::Comment && token.as_str() == marker)
+                .map(|(range, _)| (range, crate::parse_string(text).0))
+                .collect())
+        }
+    }
+
+    let fea = "
+# $classes
+feature smcp { sub @lc by @lc_sc; } smcp;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    assert!(matches!(
+        test_utils::compile_str(fea, &glyph_map, identity),
+        Err(CompilerError::ValidationFail(_))
+    ));
+
+    // each transform sees the output of the previous one
+    let compilation = test_utils::compile_str(fea, &glyph_map, |compiler| {
+        compiler
+            .with_transform(expand("# $classes", "@lc = [a b];\n# $small-caps"))
+            .with_transform(expand("# $small-caps", "@lc_sc = [A.sc B.sc];"))
+    })
+    .unwrap();
+    assert_eq!(compilation.features().unwrap().len(), 1);
+
+    // diagnostics in generated code refer to the generated text
+    let err = test_utils::compile_str(fea, &glyph_map, |compiler| {
+        compiler.with_transform(expand("# $classes", "@lc = [a b];\n@lc_sc = [A.sc nope];"))
+    })
+    .err()
+    .unwrap();
+    let CompilerError::ValidationFail(errs) = err else {
+        panic!("expected validation to fail: {err:?}");
+    };
+    let source = errs.sources.get(&errs.messages[0].message.file).unwrap();
+    assert_eq!(source.path(), "<transform 1>");
+    assert_eq!(&source.text()[errs.messages[0].span()], "nope");
+
+    let err = test_utils::compile_str(fea, &glyph_map, |compiler| {
+        compiler.with_transform(|tree: &ParseTree| {
+            let (range, _) = tree
+                .root()
+                .iter_tokens_with_ranges()
+                .find(|(_, token)| token.kind == Kind::Comment)
+                .unwrap();
+            let location = tree.location(range).unwrap();
+            Err(Diagnostic::error(
+                location.file,
+                location.range,
+                "unknown macro",
+            ))
+        })
+    })
+    .err()
+    .unwrap();
+    let CompilerError::TransformFail(errs) = err else {
+        panic!("expected transform to fail: {err:?}");
+    };
+    assert_eq!(errs.messages[0].text(), "unknown macro");
+    assert_eq!(&fea[errs.messages[0].span()], "# $classes");
+
+    // replacements must be whole nodes or tokens
+    let err = test_utils::compile_str(fea, &glyph_map, |compiler| {
+        compiler.with_transform(|_: &ParseTree| Ok(vec![(3..5, crate::parse_string("").0)]))
+    })
+    .err()
+    .unwrap();
+    assert!(matches!(err, CompilerError::TransformFail(_)), "{err:?}");
+}
+
+#[test]
+fn tree_transforms_move_nodes() {
+    use crate::{Kind, Node, ParseTree};
+
+    // the feature uses classes that are only defined after it
+    let fea = "
+feature smcp { sub @lc by @lc_sc; } smcp;
+@lc = [a b];
+@lc_sc = [A.sc B.sc];
+";
+    // so move the feature to the end
+    let move_features = |tree: &ParseTree| {
+        let mut features = Vec::new();
+        let mut edits = Vec::new();
+        let mut last = None;
+        for child in tree.root().iter_children() {
+            match child.kind() {
+                Kind::FeatureNode => {
+                    features.push(child.clone());
+                    let empty = Node::from_children(Kind::SourceFile, []);
+                    edits.push((child.range(), empty));
+                }
+                kind if !kind.is_trivia() => last = Some(child.clone()),
+                _ => (),
+            }
+        }
+        let last = last.unwrap();
+        let moved = Node::from_children(
+            Kind::SourceFile,
+            std::iter::once(last.clone()).chain(features),
+        );
+        edits.push((last.range(), moved));
+        Ok(edits)
+    };
+    let glyph_map = test_utils::make_glyph_map();
+    assert!(test_utils::compile_str(fea, &glyph_map, identity).is_err());
+    let compilation = test_utils::compile_str(fea, &glyph_map, |compiler| {
+        compiler.with_transform(move_features)
+    })
+    .unwrap();
+    assert_eq!(compilation.features().unwrap().len(), 1);
+}
+
+#[test]
+#[cfg(feature = "ufo")]
+fn ufo_kerning() {
+    use crate::compile::Kerning;
+
+    let name = |name: &str| norad::Name::new(name).unwrap();
+    let mut font = norad::Font::new();
+    font.groups
+        .insert(name("public.kern1.O"), vec![name("O"), name("Q")]);
+    font.groups.insert(
+        name("public.kern2.V"),
+        vec![name("V"), name("W"), name("nope")],
+    );
+    let pairs = [
+        ("public.kern1.O", "public.kern2.V", -30.0),
+        ("public.kern1.O", "W", -20.0),
+        ("Q", "public.kern2.V", -10.4),
+        ("Q", "W", 0.0),
+        ("nope", "a", 15.0),
+    ];
+    for (first, second, value) in pairs {
+        font.kerning
+            .entry(name(first))
+            .or_default()
+            .insert(name(second), value);
+    }
+
+    let glyph_map = test_utils::make_glyph_map();
+    let compile = |fea: &'static str, kerning: Option<Kerning>| {
+        test_utils::compile_str_to_binary(fea, &glyph_map, |compiler| match kerning {
+            Some(kerning) => compiler.with_kerning(kerning),
+            None => compiler,
+        })
+        .unwrap()
+    };
+
+    // more specific pairs win, and the kerning comes after the feature file
+    let expected = compile(
+        "
+feature kern {
+    pos a b -5;
+    lookup kerning {
+        pos O W -20;
+        pos Q V -10;
+        pos Q W 0;
+        pos [O Q] [V W] -30;
+    } kerning;
+} kern;
+",
+        None,
+    );
+    let kerning = Kerning::from_ufo(&font);
+    assert_eq!(
+        compile("feature kern { pos a b -5; } kern;", Some(kerning)),
+        expected
+    );
+
+    // without a 'kern' feature, it is added to the default language systems;
+    // pairs with one group are expanded, like 'enum pos'
+    let mut kerning = Kerning::new();
+    kerning.add_group("@MMK_L_O", ["O".into(), "Q".into()]);
+    kerning.add_pair("@MMK_L_O", "A", -15);
+    assert_eq!(
+        compile("languagesystem DFLT dflt;", Some(kerning)),
+        compile("feature kern { enum pos [O Q] A -15; } kern;", None),
+    );
+}
+
+#[test]
+fn kerning_skips_covered_pairs_and_splits_rtl() {
+    use crate::compile::Kerning;
+
+    let glyph_map = test_utils::make_glyph_map();
+    let compile = |fea: &'static str, kerning: Option<Kerning>| {
+        test_utils::compile_str_to_binary(fea, &glyph_map, |compiler| match kerning {
+            Some(kerning) => compiler.with_kerning(kerning),
+            None => compiler,
+        })
+        .unwrap()
+    };
+
+    // covered glyph pairs are dropped, and covered pairs in a class pair get
+    // a zero-valued exception
+    let mut kerning = Kerning::new();
+    kerning.add_group("@MMK_L_O", ["O".into(), "Q".into()]);
+    kerning.add_group("@MMK_R_V", ["V".into(), "W".into()]);
+    kerning.add_pair("@MMK_L_O", "@MMK_R_V", -30);
+    kerning.add_pair("O", "W", -20);
+    kerning.add_pair("A", "V", -10);
+    assert_eq!(
+        compile(
+            "feature kern { pos O V -50; pos A V -5; } kern;",
+            Some(kerning)
+        ),
+        compile(
+            "
+feature kern {
+    pos O V -50;
+    pos A V -5;
+    lookup kerning {
+        pos O V 0;
+        pos O W -20;
+        pos [O Q] [V W] -30;
+    } kerning;
+} kern;
+",
+            None
+        ),
+    );
+
+    // the same goes for pairs covered by a class pair
+    let mut kerning = Kerning::new();
+    kerning.add_group("@MMK_L_O", ["O".into(), "Q".into()]);
+    kerning.add_group("@MMK_R_V", ["V".into(), "W".into()]);
+    kerning.add_pair("@MMK_L_O", "@MMK_R_V", -30);
+    kerning.add_pair("O", "W", -20);
+    kerning.add_pair("A", "W", -10);
+    assert_eq!(
+        compile("feature kern { pos [A Q O] W -40; } kern;", Some(kerning)),
+        compile(
+            "
+feature kern {
+    pos [A Q O] W -40;
+    lookup kerning {
+        pos O W 0;
+        pos Q W 0;
+        pos [O Q] [V W] -30;
+    } kerning;
+} kern;
+",
+            None
+        ),
+    );
+
+    // right-to-left pairs get their own lookup, which adjusts placement
+    let mut kerning = Kerning::new();
+    kerning.add_rtl_glyphs(["noon.final".into(), "hamza".into()]);
+    kerning.add_pair("A", "V", -10);
+    kerning.add_pair("noon.final", "hamza", -20);
+    assert_eq!(
+        compile(
+            "languagesystem DFLT dflt; languagesystem arab dflt; languagesystem latn dflt;",
+            Some(kerning)
+        ),
+        compile(
+            "
+languagesystem DFLT dflt;
+languagesystem arab dflt;
+languagesystem latn dflt;
+lookup kern_ltr {
+    pos A V -10;
+} kern_ltr;
+lookup kern_rtl {
+    pos noon.final hamza <-20 0 -20 0>;
+} kern_rtl;
+feature kern {
+    script DFLT;
+    lookup kern_ltr;
+    lookup kern_rtl;
+    script arab;
+    lookup kern_rtl;
+    script latn;
+    lookup kern_ltr;
+} kern;
+",
+            None
+        ),
+    );
+}
+
+#[test]
+#[cfg(feature = "ufo")]
+fn ufo_mark_anchors() {
+    use crate::compile::MarkAnchors;
+
+    let mut font = norad::Font::new();
+    let anchors = [
+        ("a", "top", 250.0, 500.0),
+        ("a", "bottom", 250.0, 0.0),
+        ("e", "top", 240.0, 480.0),
+        ("acute", "_top", 100.0, 450.0),
+        ("acute", "top", 100.2, 700.0),
+        ("grave", "_top", 80.0, 450.0),
+        ("cedilla", "_bottom", 90.0, 0.0),
+        ("f_i", "top_1", 200.0, 700.0),
+        ("nope", "top", 0.0, 0.0),
+    ];
+    for (glyph, name, x, y) in anchors {
+        let layer = font.default_layer_mut();
+        if layer.get_glyph(glyph).is_none() {
+            layer.insert_glyph(norad::Glyph::new(glyph));
+        }
+        let anchor = norad::Anchor::new(
+            x,
+            y,
+            Some(norad::Name::new(name).unwrap()),
+            None,
+            None,
+            None,
+        );
+        layer.get_glyph_mut(glyph).unwrap().anchors.push(anchor);
+    }
+
+    let glyph_map = test_utils::make_glyph_map();
+    let compile = |fea: &'static str, anchors: Option<MarkAnchors>| {
+        test_utils::compile_str_to_binary(fea, &glyph_map, |compiler| match anchors {
+            Some(anchors) => compiler.with_mark_anchors(anchors),
+            None => compiler,
+        })
+        .unwrap()
+    };
+
+    // the marks and bases of the feature file are left alone, and the
+    // generated lookups come after the feature file
+    let expected = compile(
+        "
+languagesystem DFLT dflt;
+markClass grave <anchor 0 0> @G;
+markClass cedilla <anchor 90 0> @MC_bottom;
+markClass acute <anchor 100 450> @MC_top;
+feature mark {
+    pos base e <anchor 1 1> mark @G;
+    lookup mark_bottom {
+        pos base a <anchor 250 0> mark @MC_bottom;
+    } mark_bottom;
+    lookup mark_top {
+        pos base a <anchor 250 500> mark @MC_top;
+    } mark_top;
+} mark;
+feature mkmk {
+    lookup mkmk_top {
+        lookupflag UseMarkFilteringSet [acute];
+        pos mark acute <anchor 100 700> mark @MC_top;
+    } mkmk_top;
+} mkmk;
+",
+        None,
+    );
+    let fea = "
+languagesystem DFLT dflt;
+markClass grave <anchor 0 0> @G;
+feature mark {
+    pos base e <anchor 1 1> mark @G;
+} mark;
+";
+    assert_eq!(compile(fea, Some(MarkAnchors::from_ufo(&font))), expected);
+}
+
+#[test]
+fn add_feature_lookups() {
+    use crate::compile::{Compilation, FeatureKey};
+    use write_fonts::types::Tag;
+
+    let glyph_map = test_utils::make_glyph_map();
+    let compile = |fea: &'static str| test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+    let mut compilation = compile(
+        "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+lookup SMCP { sub a by b; } SMCP;
+lookup KERN { pos a b -10; } KERN;
+feature liga { sub f i by f_i; } liga;
+",
+    );
+    let [dflt, latn] = [b"DFLT", b"latn"].map(|tag| Tag::new(tag));
+    let lang_dflt = Tag::new(b"dflt");
+    let [kern, liga, smcp] = [b"kern", b"liga", b"smcp"].map(|tag| Tag::new(tag));
+    for script in [dflt, latn] {
+        compilation.add_gsub_feature_lookups(FeatureKey::new(smcp, script, lang_dflt), [0]);
+        // lookups already in the feature are not added again
+        compilation.add_gsub_feature_lookups(FeatureKey::new(liga, script, lang_dflt), [1]);
+    }
+    compilation.add_gpos_feature_lookups(FeatureKey::new(kern, latn, lang_dflt), [0]);
+
+    let features = compilation.features().unwrap();
+    let info = features
+        .iter()
+        .find(|info| info.key() == FeatureKey::new(kern, latn, lang_dflt))
+        .unwrap();
+    assert_eq!(info.gpos_lookups, [0]);
+    assert!(features
+        .iter()
+        .all(|info| info.feature != liga || info.gsub_lookups == [1]));
+
+    let expected = compile(
+        "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+lookup SMCP { sub a by b; } SMCP;
+lookup KERN { pos a b -10; } KERN;
+feature liga { sub f i by f_i; } liga;
+feature smcp { lookup SMCP; } smcp;
+feature kern { script latn; lookup KERN; } kern;
+",
+    );
+    let build = |compilation: &Compilation| {
+        compilation
+            .assemble(&glyph_map, Opts::new())
+            .unwrap()
+            .build()
+    };
+    assert_eq!(build(&compilation), build(&expected));
+}
+
+#[test]
+fn forward_class_references() {
+    let forward = "
+feature liga { sub @lower by @upper; } liga;
+@upper = [@vowels_uc B C];
+@vowels_uc = [@a_uc E];
+@a_uc = [A];
+feature smcp { @lower = [a b c e]; sub @lower by @lower_sc; } smcp;
+@lower_sc = [A.sc B.sc C.sc E.sc];
+";
+    let ordered = "
+@a_uc = [A];
+@vowels_uc = [@a_uc E];
+@upper = [@vowels_uc B C];
+@lower = [a b c e];
+@lower_sc = [A.sc B.sc C.sc E.sc];
+feature liga { sub @lower by @upper; } liga;
+feature smcp { sub @lower by @lower_sc; } smcp;
+";
+    let opts = Opts::new().forward_class_references(true);
+    assert_eq!(
+        compile_from_str(forward, opts).unwrap(),
+        compile_from_str(ordered, Opts::new()).unwrap()
+    );
+    assert!(matches!(
+        compile_from_str(forward, Opts::new()),
+        Err(CompilerError::ValidationFail(_))
+    ));
+
+    // a class can extend an earlier definition of itself, but not its first
+    let extended = "@a = [a]; @a = [@a b]; feature test { sub @a by c; } test;";
+    assert!(compile_from_str(extended, Opts::new()).is_ok());
+    let recursive = "@a = [@a b]; feature test { sub @a by c; } test;";
+    assert!(matches!(
+        compile_from_str(recursive, Opts::new()),
+        Err(CompilerError::ValidationFail(_))
+    ));
+}
+
+/// Stylistic set and character variant names are assigned ids in tag order,
+/// so that the output does not depend on hash map iteration order.
+#[test]
+fn compilation_is_deterministic() {
+    let fea = "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+@caps = [A B C];
+feature ss01 { featureNames { name \"One\"; }; sub a by a.alt1; } ss01;
+feature ss02 { featureNames { name \"Two\"; }; sub b by b.alt; } ss02;
+feature ss03 { featureNames { name \"Three\"; }; sub a by a.alt2; } ss03;
+feature ss04 { featureNames { name \"Four\"; }; sub a by a.alt3; } ss04;
+feature cv01 { cvParameters { FeatUILabelNameID { name \"cv one\"; }; Character 0x61; }; sub a from [a.alt1 a.alt2]; } cv01;
+feature cv02 { cvParameters { FeatUILabelNameID { name \"cv two\"; }; }; sub c by c.mid; } cv02;
+feature aalt { feature ss01; feature ss03; feature cv01; } aalt;
+feature kern { pos @caps a -10; pos [b c] [d e] 20; } kern;
+feature mark { markClass [acute grave] <anchor 0 0> @TOP; pos base [a b] <anchor 100 100> mark @TOP; } mark;
+";
+    let first = compile_from_str(fea, Opts::new()).unwrap();
+    for _ in 0..20 {
+        assert!(first == compile_from_str(fea, Opts::new()).unwrap());
+    }
+}
+
+#[test]
+fn compilation_fingerprint() {
+    let glyph_map = test_utils::make_glyph_map();
+    let fingerprint = |fea: &'static str| {
+        let compilation = Compiler::new("test.fea", &glyph_map)
+            .with_resolver(move |path: &std::ffi::OsStr| match path.to_str() {
+                Some("test.fea") => Ok(fea.into()),
+                _ => Ok("# nothing here".into()),
+            })
+            .compile()
+            .unwrap();
+        let fingerprint = compilation.fingerprint(&glyph_map, Opts::new()).unwrap();
+        (fingerprint, compilation.sources().to_owned())
+    };
+
+    let (base, sources) = fingerprint("feature liga { sub f i by f_i; } liga;");
+    assert_eq!(sources, ["test.fea"]);
+    assert_eq!(base.to_string().len(), 16);
+    assert_eq!(
+        fingerprint("feature liga { sub f i by f_i; } liga;").0,
+        base
+    );
+    // whitespace and comments don't change the tables
+    assert_eq!(
+        fingerprint("# ligatures\nfeature liga {\n    sub f i by f_i;\n} liga;\n").0,
+        base
+    );
+    assert_ne!(
+        fingerprint("feature liga { sub f l by f_l; } liga;").0,
+        base
+    );
+    // but the set of included files does change the fingerprint
+    let (with_include, sources) =
+        fingerprint("include(empty.fea);\nfeature liga { sub f i by f_i; } liga;");
+    assert_eq!(sources, ["empty.fea", "test.fea"]);
+    assert_ne!(with_include, base);
+}
+
+#[test]
+fn escaped_keyword_glyph_names() {
+    // glyphs named like keywords, as in fonts with a 'script' glyph
+    let keywords = ["script", "table", "feature", "by", "from", "pos", "pot"];
+    let plain = ["g1", "g2", "g3", "g4", "g5", "g6", "g7"];
+    let glyph_map = |names: &[&str]| -> crate::GlyphMap {
+        [".notdef", "a", "b"]
+            .iter()
+            .chain(names)
+            .map(|name| crate::GlyphName::new(*name))
+            .collect()
+    };
+    let compile = |fea: &'static str, glyph_map: &crate::GlyphMap| {
+        test_utils::compile_str_to_binary(fea, glyph_map, identity).unwrap()
+    };
+
+    let escaped = compile(
+        r"
+@range = [\pos-\pot];
+@tight = [a-\b];
+markClass [\from] <anchor 0 0> @MARK;
+table GDEF {
+    GlyphClassDef [\script \table], [\feature], [\from], ;
+    LigatureCaretByPos \feature 100;
+} GDEF;
+lookup inner {
+    sub \script by \table;
+} inner;
+feature liga {
+    sub \script \table by \feature;
+    sub \by' lookup inner \from;
+    ignore sub \pos \table';
+} liga;
+feature salt {
+    sub \by from [\script \table];
+    sub @range by \by;
+    sub @tight by \pot;
+} salt;
+feature kern {
+    pos \script \table -10;
+    enum pos \feature [\by \pot] -20;
+} kern;
+feature mark {
+    pos base [\script \table] <anchor 100 500> mark @MARK;
+    pos ligature \feature <anchor 100 500> mark @MARK ligComponent <anchor 300 500> mark @MARK;
+} mark;
+",
+        &glyph_map(&keywords),
+    );
+    let expected = compile(
+        r"
+@range = [g6-g7];
+@tight = [a-b];
+markClass [g5] <anchor 0 0> @MARK;
+table GDEF {
+    GlyphClassDef [g1 g2], [g3], [g5], ;
+    LigatureCaretByPos g3 100;
+} GDEF;
+lookup inner {
+    sub g1 by g2;
+} inner;
+feature liga {
+    sub g1 g2 by g3;
+    sub g4' lookup inner g5;
+    ignore sub g6 g2';
+} liga;
+feature salt {
+    sub g4 from [g1 g2];
+    sub @range by g4;
+    sub @tight by g7;
+} salt;
+feature kern {
+    pos g1 g2 -10;
+    enum pos g3 [g4 g7] -20;
+} kern;
+feature mark {
+    pos base [g1 g2] <anchor 100 500> mark @MARK;
+    pos ligature g3 <anchor 100 500> mark @MARK ligComponent <anchor 300 500> mark @MARK;
+} mark;
+",
+        &glyph_map(&plain),
+    );
+    assert_eq!(escaped, expected);
+}
+
+#[test]
+fn repeated_inline_classes_share_coverage() {
+    let fea = "
+lookup SINGLE { sub d by e; } SINGLE;
+feature calt {
+    sub [a b c] d' lookup SINGLE [a b c];
+    subtable;
+    sub [a b c] [a b c] d' lookup SINGLE [a b c];
+    subtable;
+    sub f d' lookup SINGLE [a b c];
+} calt;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let data = test_utils::compile_str_to_binary(fea, &glyph_map, identity).unwrap();
+
+    // identical coverage tables are shared when the tables are serialized,
+    // so a format 1 coverage table of the class appears once in the font
+    let mut coverage = vec![0, 1, 0, 3];
+    for name in ["a", "b", "c"] {
+        coverage.extend(glyph_map.get(name).unwrap().to_u16().to_be_bytes());
+    }
+    let copies = data
+        .windows(coverage.len())
+        .filter(|bytes| *bytes == coverage.as_slice())
+        .count();
+    assert_eq!(copies, 1);
+}
+
+/// Compile a source, keeping the warnings in the compilation.
+fn compile_with_warnings(fea: impl AsRef<str>) -> Compilation {
+    let glyph_map = test_utils::make_glyph_map();
+    let tree = test_utils::parse_str(fea.as_ref(), &glyph_map);
+    crate::compile(&tree, &glyph_map, Opts::new()).unwrap()
+}
+
+#[test]
+fn empty_classes_compile_to_no_ops() {
+    let rules = [
+        "sub @EMPTY by a;",
+        "sub @EMPTY by [a b];",
+        "sub [a b] by @EMPTY;",
+        "sub @EMPTY by a b;",
+        "sub a from @EMPTY;",
+        "sub a @EMPTY by f_i;",
+        "sub a @EMPTY' by b;",
+        "sub @EMPTY a' lookup SINGLE;",
+        "ignore sub a' @EMPTY;",
+        "rsub @EMPTY a' by b;",
+        "rsub a' @EMPTY by b;",
+        "pos @EMPTY 10;",
+        "pos a @EMPTY -10;",
+        "enum pos @EMPTY b -10;",
+        "pos cursive @EMPTY <anchor 0 0> <anchor 10 10>;",
+        "pos base @EMPTY <anchor 0 0> mark @TOP;",
+        "pos ligature @EMPTY <anchor 0 0> mark @TOP;",
+        "pos mark @EMPTY <anchor 0 0> mark @TOP;",
+        "pos a' 10 @EMPTY;",
+        "ignore pos @EMPTY a';",
+    ];
+    let fea = format!(
+        "@EMPTY = [];\n\
+         markClass acute <anchor 0 500> @TOP;\n\
+         lookup SINGLE {{ sub a by b; }} SINGLE;\n\
+         feature test {{\n{}\n}} test;\n",
+        rules.join("\n")
+    );
+    let compilation = compile_with_warnings(fea);
+    let messages = compilation
+        .warnings
+        .iter()
+        .map(|warning| warning.text())
+        .filter(|text| text.contains("empty glyph class"))
+        .count();
+    assert_eq!(messages, rules.len());
+    // only the named lookup is left
+    assert_eq!(compilation.gsub_lookups().len(), 1);
+    assert!(compilation.gpos_lookups().is_empty());
+}
+
+#[test]
+fn empty_mark_class() {
+    let fea = "
+@EMPTY = [];
+markClass @EMPTY <anchor 0 0> @NONE;
+markClass acute <anchor 0 500> @TOP;
+feature mark {
+    pos base a <anchor 0 0> mark @NONE <anchor 250 500> mark @TOP;
+    pos ligature f_i <anchor 0 0> mark @NONE ligComponent <anchor 0 0> mark @TOP;
+} mark;
+feature mkmk { pos mark acute <anchor 0 0> mark @NONE; } mkmk;
+";
+    let compilation = compile_with_warnings(fea);
+    let messages = compilation
+        .warnings
+        .iter()
+        .map(|warning| warning.text())
+        .filter(|text| text.starts_with("mark class '@NONE' has no glyphs"))
+        .count();
+    assert_eq!(messages, 3);
+    let glyph_map = test_utils::make_glyph_map();
+    assert!(compilation.assemble(&glyph_map, Opts::new()).is_ok());
+}
+
+#[test]
+fn single_glyph_class_replaces_every_target() {
+    let fea = "feature test { sub [a b c] by [d]; } test;";
+    let glyph_map = test_utils::make_glyph_map();
+    let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+    let [lookup] = compilation.gsub_lookups().try_into().unwrap();
+    assert_eq!(lookup.kind, crate::compile::LookupKind::GsubSingle);
+    assert_eq!(lookup.rule_count, 3);
+}
+
+#[test]
+fn class_pair_device_tables_are_an_error() {
+    use crate::compile::error::{BinaryCompilationError, CompileError};
+
+    let fea = "
+@L = [a b];
+@R = [c];
+@R2 = [d];
+feature kern {
+    pos @L @R <0 0 0 0 <device 11 -1> <device NULL> <device NULL> <device NULL>>;
+    pos @L @R2 -10;
+} kern;
+";
+    let glyph_map = test_utils::make_glyph_map();
+    let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+    // reports build each subtable, without the check
+    assert_eq!(
+        crate::compile::report::Report::new(&compilation)
+            .unwrap()
+            .gpos
+            .len(),
+        1
+    );
+    assert!(matches!(
+        compilation.assemble(&glyph_map, Opts::new()),
+        Err(BinaryCompilationError::Compile(
+            CompileError::ClassPairDevice { lookup: 0 }
+        ))
+    ));
 }
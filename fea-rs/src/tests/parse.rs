@@ -4,11 +4,15 @@
 //! against saved versions.
 //!
 //! To regenerate the comparison files, pass FEA_WRITE_TEST_OUTPUT=1 as an
-//! environment variable.
+//! environment variable. Bad inputs are compared against the diagnostics
+//! listed in a file next to them; see [`test_utils::compare_bad_output`].
 
 use std::{env, path::PathBuf};
 
-use crate::util::ttx::{self as test_utils, Report, TestCase, TestResult};
+use crate::{
+    util::ttx::{self as test_utils, Report, TestCase, TestResult},
+    TreeFormat,
+};
 
 static PARSE_GOOD: &str = "./test-data/parse-tests/good";
 static PARSE_BAD: &str = "./test-data/parse-tests/bad";
@@ -49,17 +53,17 @@ fn run_good_test(path: PathBuf) -> Result<PathBuf, TestCase> {
             reason: TestResult::ParseFail(test_utils::stringify_diagnostics(&node, &errs)),
         }),
         Ok(node) => {
-            let output = node.root().simple_parse_tree();
+            let output = node.root().format(TreeFormat::Tree);
             let result =
                 test_utils::compare_to_expected_output(&output, &path, GOOD_OUTPUT_EXTENSION);
             if result.is_err() {
                 if std::env::var(crate::util::WRITE_RESULTS_VAR).is_ok() {
-                    let to_write = node.root().simple_parse_tree();
+                    let to_write = node.root().format(TreeFormat::Tree);
                     let to_path = path.with_extension(GOOD_OUTPUT_EXTENSION);
                     std::fs::write(to_path, to_write).expect("failed to write output");
                 }
                 if verbose {
-                    eprintln!("{}", node.root().simple_parse_tree());
+                    eprintln!("{}", node.root().format(TreeFormat::Tree));
                 }
             }
             result
@@ -78,12 +82,7 @@ fn run_bad_test(path: PathBuf) -> Result<PathBuf, TestCase> {
     match std::panic::catch_unwind(|| match test_utils::try_parse_file(&path, None) {
         Err((node, errs)) => {
             let msg = test_utils::stringify_diagnostics(&node, &errs);
-            let result = test_utils::compare_to_expected_output(&msg, &path, BAD_OUTPUT_EXTENSION);
-            if result.is_err() && std::env::var(crate::util::WRITE_RESULTS_VAR).is_ok() {
-                let to_path = path.with_extension(BAD_OUTPUT_EXTENSION);
-                std::fs::write(to_path, &msg).expect("failed to write output");
-            }
-            result
+            test_utils::compare_bad_output(&errs, &msg, &path, BAD_OUTPUT_EXTENSION)
         }
         Ok(_) => Err(TestCase {
             path: path.clone(),
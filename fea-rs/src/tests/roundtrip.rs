@@ -0,0 +1,811 @@
+//! round-trip tests of compiled GSUB and GPOS tables
+//!
+//! There is no decompiler, so instead of comparing source we compare what the
+//! compiled lookups do. Each table is read back and flattened into a model that
+//! does not depend on how the rules are encoded: the glyphs each lookup
+//! applies to and the result of the first subtable that applies to them. The
+//! model catches lossy transformations (such as a class being reordered or
+//! a pair being dropped when subtables are split) while ignoring differences
+//! in coverage and class def formats, value record formats or subtable layout.
+//!
+//! Device tables and feature variations are not compared.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use write_fonts::{
+    from_obj::ToOwnedTable,
+    read::{FontData, FontRead, FontRef, TableProvider},
+    tables::{
+        gpos::{self, AnchorTable, PairPos, PositionLookup, SinglePos, ValueRecord},
+        gsub::{self, SingleSubst, SubstitutionLookup},
+        layout::{
+            ChainedSequenceContext, ClassDef, CoverageTable, FeatureList, Lookup, ScriptList,
+            SequenceContext, SequenceLookupRecord,
+        },
+    },
+    types::{GlyphId, Tag},
+};
+
+use super::compile::compile_from_str;
+use crate::compile::Opts;
+
+type Glyphs = BTreeSet<u16>;
+
+/// The behaviour of the GSUB and GPOS tables of a font.
+#[derive(Debug, Default, PartialEq)]
+struct Layout {
+    gsub: Table,
+    gpos: Table,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Table {
+    /// (script, language, feature, is required) to lookup indices
+    features: BTreeMap<(Tag, Tag, Tag, bool), Vec<u16>>,
+    lookups: Vec<LookupRules>,
+}
+
+#[derive(Debug, PartialEq)]
+struct LookupRules {
+    flags: u16,
+    mark_filtering_set: Option<u16>,
+    rules: Rules,
+}
+
+#[derive(Debug, PartialEq)]
+enum Rules {
+    /// The action of the first subtable that matches each glyph sequence.
+    ///
+    /// For attachment lookups the sequence is the base glyph followed by the
+    /// mark, and for mark-to-ligature the component index is appended.
+    Glyphs(BTreeMap<Vec<u16>, Action>),
+    /// Contextual rules, in the order they are tried.
+    Contextual(Vec<ContextRule>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    Substitute(Vec<u16>),
+    Alternates(Vec<u16>),
+    /// x placement, y placement, x advance, y advance
+    Adjust([i16; 4], Option<[i16; 4]>),
+    Cursive(Option<Anchor>, Option<Anchor>),
+    Attach(Anchor, Anchor),
+}
+
+type Anchor = (i16, i16, Option<u16>);
+
+#[derive(Debug, PartialEq)]
+struct ContextRule {
+    backtrack: Vec<Glyphs>,
+    input: Vec<Glyphs>,
+    lookahead: Vec<Glyphs>,
+    lookups: Vec<(u16, u16)>,
+    /// the replacements of a reverse chaining rule
+    substitutes: Vec<u16>,
+}
+
+impl Layout {
+    fn new(data: &[u8], glyph_count: u16) -> Self {
+        let font = FontRef::new(data).unwrap();
+        let mut layout = Layout::default();
+        if let Ok(table) = font.gsub() {
+            layout.gsub = Table::from_gsub(&table.to_owned_table(), glyph_count);
+        }
+        if let Ok(table) = font.gpos() {
+            layout.gpos = Table::from_gpos(&table.to_owned_table(), glyph_count);
+        }
+        layout
+    }
+
+    /// Write the tables out and read them back again.
+    fn rewrite(data: &[u8], glyph_count: u16) -> Self {
+        let font = FontRef::new(data).unwrap();
+        let mut layout = Layout::default();
+        if let Ok(table) = font.gsub() {
+            let table: gsub::Gsub = table.to_owned_table();
+            let bytes = write_fonts::dump_table(&table).unwrap();
+            let table = write_fonts::read::tables::gsub::Gsub::read(FontData::new(&bytes));
+            layout.gsub = Table::from_gsub(&table.unwrap().to_owned_table(), glyph_count);
+        }
+        if let Ok(table) = font.gpos() {
+            let table: gpos::Gpos = table.to_owned_table();
+            let bytes = write_fonts::dump_table(&table).unwrap();
+            let table = write_fonts::read::tables::gpos::Gpos::read(FontData::new(&bytes));
+            layout.gpos = Table::from_gpos(&table.unwrap().to_owned_table(), glyph_count);
+        }
+        layout
+    }
+}
+
+impl Table {
+    fn from_gsub(table: &gsub::Gsub, glyph_count: u16) -> Self {
+        let lookups = table
+            .lookup_list
+            .lookups
+            .iter()
+            .map(|lookup| match &**lookup {
+                SubstitutionLookup::Single(lookup) => flatten(lookup, single_subst),
+                SubstitutionLookup::Multiple(lookup) => flatten(lookup, |subtable, map| {
+                    let targets = subtable
+                        .sequences
+                        .iter()
+                        .map(|seq| Action::Substitute(gids(&seq.substitute_glyph_ids)));
+                    insert_first(
+                        map,
+                        subtable.coverage.iter().map(|gid| vec![gid.to_u16()]),
+                        targets,
+                    );
+                }),
+                SubstitutionLookup::Alternate(lookup) => flatten(lookup, |subtable, map| {
+                    let targets = subtable
+                        .alternate_sets
+                        .iter()
+                        .map(|set| Action::Alternates(gids(&set.alternate_glyph_ids)));
+                    insert_first(
+                        map,
+                        subtable.coverage.iter().map(|gid| vec![gid.to_u16()]),
+                        targets,
+                    );
+                }),
+                SubstitutionLookup::Ligature(lookup) => flatten(lookup, |subtable, map| {
+                    for (first, set) in subtable.coverage.iter().zip(&subtable.ligature_sets) {
+                        for lig in &set.ligatures {
+                            let mut seq = vec![first.to_u16()];
+                            seq.extend(gids(&lig.component_glyph_ids));
+                            map.entry(seq).or_insert_with(|| {
+                                Action::Substitute(vec![lig.ligature_glyph.to_u16()])
+                            });
+                        }
+                    }
+                }),
+                SubstitutionLookup::Contextual(lookup) => {
+                    contextual(lookup, glyph_count, |subtable| &**subtable)
+                }
+                SubstitutionLookup::ChainContextual(lookup) => {
+                    chain_contextual(lookup, glyph_count, |subtable| &**subtable)
+                }
+                SubstitutionLookup::Reverse(lookup) => LookupRules {
+                    flags: lookup.lookup_flag.to_bits(),
+                    mark_filtering_set: mark_filtering_set(lookup),
+                    rules: Rules::Contextual(
+                        lookup
+                            .subtables
+                            .iter()
+                            .map(|subtable| ContextRule {
+                                backtrack: subtable
+                                    .backtrack_coverages
+                                    .iter()
+                                    .map(|cov| coverage(cov))
+                                    .collect(),
+                                input: vec![coverage(&subtable.coverage)],
+                                lookahead: subtable
+                                    .lookahead_coverages
+                                    .iter()
+                                    .map(|cov| coverage(cov))
+                                    .collect(),
+                                lookups: Vec::new(),
+                                substitutes: gids(&subtable.substitute_glyph_ids),
+                            })
+                            .collect(),
+                    ),
+                },
+                SubstitutionLookup::Extension(_) => panic!("extension lookups are not supported"),
+            })
+            .collect();
+        Table {
+            features: features(&table.script_list, &table.feature_list),
+            lookups,
+        }
+    }
+
+    fn from_gpos(table: &gpos::Gpos, glyph_count: u16) -> Self {
+        let lookups = table
+            .lookup_list
+            .lookups
+            .iter()
+            .map(|lookup| match &**lookup {
+                PositionLookup::Single(lookup) => flatten(lookup, |subtable, map| match subtable {
+                    SinglePos::Format1(subtable) => {
+                        let value = Action::Adjust(values(&subtable.value_record), None);
+                        for gid in subtable.coverage.iter() {
+                            map.entry(vec![gid.to_u16()])
+                                .or_insert_with(|| value.clone());
+                        }
+                    }
+                    SinglePos::Format2(subtable) => {
+                        let targets = subtable
+                            .value_records
+                            .iter()
+                            .map(|record| Action::Adjust(values(record), None));
+                        insert_first(
+                            map,
+                            subtable.coverage.iter().map(|gid| vec![gid.to_u16()]),
+                            targets,
+                        );
+                    }
+                }),
+                PositionLookup::Pair(lookup) => {
+                    flatten(lookup, |subtable, map| pair_pos(subtable, glyph_count, map))
+                }
+                PositionLookup::Cursive(lookup) => flatten(lookup, |subtable, map| {
+                    let targets = subtable.entry_exit_record.iter().map(|record| {
+                        Action::Cursive(
+                            record.entry_anchor.as_ref().map(anchor),
+                            record.exit_anchor.as_ref().map(anchor),
+                        )
+                    });
+                    insert_first(
+                        map,
+                        subtable.coverage.iter().map(|gid| vec![gid.to_u16()]),
+                        targets,
+                    );
+                }),
+                PositionLookup::MarkToBase(lookup) => flatten(lookup, |subtable, map| {
+                    let bases = subtable
+                        .base_coverage
+                        .iter()
+                        .zip(&subtable.base_array.base_records);
+                    for (base, record) in bases {
+                        attach(
+                            map,
+                            vec![base.to_u16()],
+                            &subtable.mark_coverage,
+                            &subtable.mark_array,
+                            &record.base_anchors,
+                        );
+                    }
+                }),
+                PositionLookup::MarkToLig(lookup) => flatten(lookup, |subtable, map| {
+                    let ligatures = subtable
+                        .ligature_coverage
+                        .iter()
+                        .zip(&subtable.ligature_array.ligature_attaches);
+                    for (lig, attach_table) in ligatures {
+                        for (i, component) in attach_table.component_records.iter().enumerate() {
+                            // the component index goes after the mark
+                            let mut component_map = BTreeMap::new();
+                            attach(
+                                &mut component_map,
+                                vec![lig.to_u16()],
+                                &subtable.mark_coverage,
+                                &subtable.mark_array,
+                                &component.ligature_anchors,
+                            );
+                            for (mut seq, action) in component_map {
+                                seq.push(i as u16);
+                                map.entry(seq).or_insert(action);
+                            }
+                        }
+                    }
+                }),
+                PositionLookup::MarkToMark(lookup) => flatten(lookup, |subtable, map| {
+                    let bases = subtable
+                        .mark2_coverage
+                        .iter()
+                        .zip(&subtable.mark2_array.mark2_records);
+                    for (base, record) in bases {
+                        attach(
+                            map,
+                            vec![base.to_u16()],
+                            &subtable.mark1_coverage,
+                            &subtable.mark1_array,
+                            &record.mark2_anchors,
+                        );
+                    }
+                }),
+                PositionLookup::Contextual(lookup) => {
+                    contextual(lookup, glyph_count, |subtable| &**subtable)
+                }
+                PositionLookup::ChainContextual(lookup) => {
+                    chain_contextual(lookup, glyph_count, |subtable| &**subtable)
+                }
+                PositionLookup::Extension(_) => panic!("extension lookups are not supported"),
+            })
+            .collect();
+        Table {
+            features: features(&table.script_list, &table.feature_list),
+            lookups,
+        }
+    }
+}
+
+fn features(
+    scripts: &ScriptList,
+    features: &FeatureList,
+) -> BTreeMap<(Tag, Tag, Tag, bool), Vec<u16>> {
+    let mut result = BTreeMap::new();
+    for script in &scripts.script_records {
+        let default = script
+            .script
+            .default_lang_sys
+            .as_ref()
+            .map(|lang_sys| (Tag::new(b"dflt"), lang_sys));
+        let languages = script
+            .script
+            .lang_sys_records
+            .iter()
+            .map(|record| (record.lang_sys_tag, &*record.lang_sys));
+        for (language, lang_sys) in default.into_iter().chain(languages) {
+            let required = Some(lang_sys.required_feature_index).filter(|idx| *idx != 0xFFFF);
+            let indices = required
+                .map(|idx| (idx, true))
+                .into_iter()
+                .chain(lang_sys.feature_indices.iter().map(|idx| (*idx, false)));
+            for (idx, is_required) in indices {
+                let record = &features.feature_records[idx as usize];
+                let key = (script.script_tag, language, record.feature_tag, is_required);
+                result.insert(key, record.feature.lookup_list_indices.clone());
+            }
+        }
+    }
+    result
+}
+
+fn mark_filtering_set<T>(lookup: &Lookup<T>) -> Option<u16> {
+    // 0x10 is UseMarkFilteringSet; otherwise the field is not meaningful
+    (lookup.lookup_flag.to_bits() & 0x10 != 0).then_some(lookup.mark_filtering_set)
+}
+
+/// Flatten a lookup whose subtables each map glyph sequences to actions.
+///
+/// The closure should only insert sequences that are not already in the map,
+/// so that each sequence keeps the action of the first matching subtable.
+fn flatten<T>(
+    lookup: &Lookup<T>,
+    mut add_subtable: impl FnMut(&T, &mut BTreeMap<Vec<u16>, Action>),
+) -> LookupRules {
+    let mut map = BTreeMap::new();
+    for subtable in &lookup.subtables {
+        add_subtable(subtable, &mut map);
+    }
+    LookupRules {
+        flags: lookup.lookup_flag.to_bits(),
+        mark_filtering_set: mark_filtering_set(lookup),
+        rules: Rules::Glyphs(map),
+    }
+}
+
+fn insert_first(
+    map: &mut BTreeMap<Vec<u16>, Action>,
+    keys: impl Iterator<Item = Vec<u16>>,
+    actions: impl Iterator<Item = Action>,
+) {
+    for (key, action) in keys.zip(actions) {
+        map.entry(key).or_insert(action);
+    }
+}
+
+fn single_subst(subtable: &SingleSubst, map: &mut BTreeMap<Vec<u16>, Action>) {
+    match subtable {
+        SingleSubst::Format1(subtable) => {
+            for gid in subtable.coverage.iter() {
+                let target = (gid.to_u16() as i32 + subtable.delta_glyph_id as i32) as u16;
+                map.entry(vec![gid.to_u16()])
+                    .or_insert_with(|| Action::Substitute(vec![target]));
+            }
+        }
+        SingleSubst::Format2(subtable) => {
+            let targets = subtable
+                .substitute_glyph_ids
+                .iter()
+                .map(|gid| Action::Substitute(vec![gid.to_u16()]));
+            insert_first(
+                map,
+                subtable.coverage.iter().map(|gid| vec![gid.to_u16()]),
+                targets,
+            );
+        }
+    }
+}
+
+/// Every pair that a pair adjustment subtable applies to.
+///
+/// A format 2 subtable matches any second glyph, with class 0 standing in for
+/// the glyphs not in the class def, so it is expanded over the whole font.
+fn pair_pos(subtable: &PairPos, glyph_count: u16, map: &mut BTreeMap<Vec<u16>, Action>) {
+    match subtable {
+        PairPos::Format1(subtable) => {
+            for (first, set) in subtable.coverage.iter().zip(&subtable.pair_sets) {
+                for record in &set.pair_value_records {
+                    let key = vec![first.to_u16(), record.second_glyph.to_u16()];
+                    map.entry(key).or_insert_with(|| {
+                        pair_action(&record.value_record1, &record.value_record2)
+                    });
+                }
+            }
+        }
+        PairPos::Format2(subtable) => {
+            for first in subtable.coverage.iter() {
+                let class1 = &subtable.class1_records[subtable.class_def1.get(first) as usize];
+                for second in 0..glyph_count {
+                    let class2 = subtable.class_def2.get(GlyphId::new(second));
+                    let record = &class1.class2_records[class2 as usize];
+                    map.entry(vec![first.to_u16(), second]).or_insert_with(|| {
+                        pair_action(&record.value_record1, &record.value_record2)
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn pair_action(first: &ValueRecord, second: &ValueRecord) -> Action {
+    Action::Adjust(values(first), Some(values(second)))
+}
+
+fn values(record: &ValueRecord) -> [i16; 4] {
+    [
+        record.x_placement,
+        record.y_placement,
+        record.x_advance,
+        record.y_advance,
+    ]
+    .map(|value| value.unwrap_or_default())
+}
+
+fn anchor(anchor: &AnchorTable) -> Anchor {
+    match anchor {
+        AnchorTable::Format1(anchor) => (anchor.x_coordinate, anchor.y_coordinate, None),
+        AnchorTable::Format2(anchor) => (
+            anchor.x_coordinate,
+            anchor.y_coordinate,
+            Some(anchor.anchor_point),
+        ),
+        AnchorTable::Format3(anchor) => (anchor.x_coordinate, anchor.y_coordinate, None),
+    }
+}
+
+/// Add the marks that can attach to this base, skipping null anchors.
+fn attach(
+    map: &mut BTreeMap<Vec<u16>, Action>,
+    base: Vec<u16>,
+    mark_coverage: &CoverageTable,
+    marks: &gpos::MarkArray,
+    base_anchors: &[write_fonts::NullableOffsetMarker<AnchorTable>],
+) {
+    for (mark, record) in mark_coverage.iter().zip(&marks.mark_records) {
+        let Some(base_anchor) = base_anchors[record.mark_class as usize].as_ref() else {
+            continue;
+        };
+        let mut key = base.clone();
+        key.push(mark.to_u16());
+        map.entry(key)
+            .or_insert_with(|| Action::Attach(anchor(base_anchor), anchor(&record.mark_anchor)));
+    }
+}
+
+fn gids(glyphs: &[GlyphId]) -> Vec<u16> {
+    glyphs.iter().map(|gid| gid.to_u16()).collect()
+}
+
+fn coverage(coverage: &CoverageTable) -> Glyphs {
+    coverage.iter().map(|gid| gid.to_u16()).collect()
+}
+
+fn class_members(class_def: &ClassDef, class: u16, glyph_count: u16) -> Glyphs {
+    (0..glyph_count)
+        .filter(|gid| class_def.get(GlyphId::new(*gid)) == class)
+        .collect()
+}
+
+fn lookup_records(records: &[SequenceLookupRecord]) -> Vec<(u16, u16)> {
+    records
+        .iter()
+        .map(|record| (record.sequence_index, record.lookup_list_index))
+        .collect()
+}
+
+fn singletons(glyphs: &[GlyphId]) -> Vec<Glyphs> {
+    glyphs.iter().map(|gid| [gid.to_u16()].into()).collect()
+}
+
+fn contextual<T>(
+    lookup: &Lookup<T>,
+    glyph_count: u16,
+    as_context: impl Fn(&T) -> &SequenceContext,
+) -> LookupRules {
+    let mut rules = Vec::new();
+    for subtable in &lookup.subtables {
+        match as_context(subtable) {
+            SequenceContext::Format1(subtable) => {
+                for (first, set) in subtable.coverage.iter().zip(&subtable.seq_rule_sets) {
+                    for rule in set.iter().flat_map(|set| &set.seq_rules) {
+                        let mut input = singletons(&[first]);
+                        input.extend(singletons(&rule.input_sequence));
+                        rules.push(ContextRule {
+                            backtrack: Vec::new(),
+                            input,
+                            lookahead: Vec::new(),
+                            lookups: lookup_records(&rule.seq_lookup_records),
+                            substitutes: Vec::new(),
+                        });
+                    }
+                }
+            }
+            SequenceContext::Format2(subtable) => {
+                let first_glyphs = coverage(&subtable.coverage);
+                for (class, set) in subtable.class_seq_rule_sets.iter().enumerate() {
+                    let first = class_members(&subtable.class_def, class as u16, glyph_count)
+                        .intersection(&first_glyphs)
+                        .copied()
+                        .collect::<Glyphs>();
+                    for rule in set.iter().flat_map(|set| &set.class_seq_rules) {
+                        let mut input = vec![first.clone()];
+                        input.extend(
+                            rule.input_sequence.iter().map(|class| {
+                                class_members(&subtable.class_def, *class, glyph_count)
+                            }),
+                        );
+                        rules.push(ContextRule {
+                            backtrack: Vec::new(),
+                            input,
+                            lookahead: Vec::new(),
+                            lookups: lookup_records(&rule.seq_lookup_records),
+                            substitutes: Vec::new(),
+                        });
+                    }
+                }
+            }
+            SequenceContext::Format3(subtable) => rules.push(ContextRule {
+                backtrack: Vec::new(),
+                input: subtable.coverages.iter().map(|cov| coverage(cov)).collect(),
+                lookahead: Vec::new(),
+                lookups: lookup_records(&subtable.seq_lookup_records),
+                substitutes: Vec::new(),
+            }),
+        }
+    }
+    LookupRules {
+        flags: lookup.lookup_flag.to_bits(),
+        mark_filtering_set: mark_filtering_set(lookup),
+        rules: Rules::Contextual(rules),
+    }
+}
+
+fn chain_contextual<T>(
+    lookup: &Lookup<T>,
+    glyph_count: u16,
+    as_context: impl Fn(&T) -> &ChainedSequenceContext,
+) -> LookupRules {
+    let mut rules = Vec::new();
+    for subtable in &lookup.subtables {
+        match as_context(subtable) {
+            ChainedSequenceContext::Format1(subtable) => {
+                for (first, set) in subtable
+                    .coverage
+                    .iter()
+                    .zip(&subtable.chained_seq_rule_sets)
+                {
+                    for rule in set.iter().flat_map(|set| &set.chained_seq_rules) {
+                        let mut input = singletons(&[first]);
+                        input.extend(singletons(&rule.input_sequence));
+                        rules.push(ContextRule {
+                            backtrack: singletons(&rule.backtrack_sequence),
+                            input,
+                            lookahead: singletons(&rule.lookahead_sequence),
+                            lookups: lookup_records(&rule.seq_lookup_records),
+                            substitutes: Vec::new(),
+                        });
+                    }
+                }
+            }
+            ChainedSequenceContext::Format2(subtable) => {
+                let first_glyphs = coverage(&subtable.coverage);
+                let members = |class_def: &ClassDef, classes: &[u16]| {
+                    classes
+                        .iter()
+                        .map(|class| class_members(class_def, *class, glyph_count))
+                        .collect::<Vec<_>>()
+                };
+                let rule_sets = subtable.chained_class_seq_rule_sets.iter().enumerate();
+                for (class, set) in rule_sets {
+                    let first = class_members(&subtable.input_class_def, class as u16, glyph_count)
+                        .intersection(&first_glyphs)
+                        .copied()
+                        .collect::<Glyphs>();
+                    for rule in set.iter().flat_map(|set| &set.chained_class_seq_rules) {
+                        let mut input = vec![first.clone()];
+                        input.extend(members(&subtable.input_class_def, &rule.input_sequence));
+                        rules.push(ContextRule {
+                            backtrack: members(
+                                &subtable.backtrack_class_def,
+                                &rule.backtrack_sequence,
+                            ),
+                            input,
+                            lookahead: members(
+                                &subtable.lookahead_class_def,
+                                &rule.lookahead_sequence,
+                            ),
+                            lookups: lookup_records(&rule.seq_lookup_records),
+                            substitutes: Vec::new(),
+                        });
+                    }
+                }
+            }
+            ChainedSequenceContext::Format3(subtable) => rules.push(ContextRule {
+                backtrack: subtable
+                    .backtrack_coverages
+                    .iter()
+                    .map(|cov| coverage(cov))
+                    .collect(),
+                input: subtable
+                    .input_coverages
+                    .iter()
+                    .map(|cov| coverage(cov))
+                    .collect(),
+                lookahead: subtable
+                    .lookahead_coverages
+                    .iter()
+                    .map(|cov| coverage(cov))
+                    .collect(),
+                lookups: lookup_records(&subtable.seq_lookup_records),
+                substitutes: Vec::new(),
+            }),
+        }
+    }
+    LookupRules {
+        flags: lookup.lookup_flag.to_bits(),
+        mark_filtering_set: mark_filtering_set(lookup),
+        rules: Rules::Contextual(rules),
+    }
+}
+
+/// Sources that exercise every lookup type we generate.
+static SAMPLES: &[&str] = &[
+    "
+languagesystem DFLT dflt;
+languagesystem latn dflt;
+languagesystem latn TRK;
+
+feature smcp {
+    sub [a b c] by [A.sc B.sc C.sc];
+    sub d by D.sc;
+} smcp;
+
+feature ccmp {
+    sub f_f_i by f f i;
+    sub [a b] by [a.alt1 b.alt];
+} ccmp;
+
+feature salt {
+    sub A from [A.alt1 A.alt2 A.alt3];
+    sub B from [B.alt1 B.alt2];
+} salt;
+
+feature liga {
+    sub f f i by f_f_i;
+    sub f f by f_f;
+    sub f i by f_i;
+    script latn;
+    language TRK;
+    sub c t by c_t;
+} liga;
+",
+    "
+lookup SWASH { sub [A B C] by [A.swash B.swash C.swash]; } SWASH;
+lookup FINAL { sub e by e.end; } FINAL;
+
+feature calt {
+    sub [A B C]' lookup SWASH [d e]' lookup FINAL;
+    ignore sub [a b] e';
+    sub e' lookup FINAL space;
+} calt;
+
+feature rclt {
+    rsub [a b] [c d]' [e f] by [c.mid d.mid];
+} rclt;
+",
+    "
+@LEFT = [A T V];
+@RIGHT = [a o e];
+@OTHER = [comma period];
+
+feature kern {
+    pos A V -80;
+    pos T a -40;
+    pos @LEFT @RIGHT -20;
+    pos @LEFT @OTHER <0 0 -10 0>;
+    enum pos [T V] comma -60;
+    pos o <10 0 20 0> period <0 0 -5 0>;
+} kern;
+
+feature cpsp {
+    pos [A B C] <5 0 10 0>;
+    pos D 12;
+} cpsp;
+",
+    "
+languagesystem arab dflt;
+
+markClass [acute grave] <anchor 150 450> @TOP;
+markClass [cedilla ogonek] <anchor 150 -10> @BOTTOM;
+markClass [dieresis] <anchor 100 500> @MARK_TOP;
+
+feature curs {
+    pos cursive noon.final <anchor 500 20> <anchor NULL>;
+    pos cursive noon.initial <anchor NULL> <anchor 20 -20>;
+} curs;
+
+feature mark {
+    pos base [a e o] <anchor 250 450> mark @TOP <anchor 250 0> mark @BOTTOM;
+    pos base [A E] <anchor 300 650> mark @TOP;
+    pos ligature f_i <anchor 150 600> mark @TOP
+        ligComponent <anchor 420 600> mark @TOP <anchor 420 0> mark @BOTTOM;
+} mark;
+
+feature mkmk {
+    lookupflag UseMarkFilteringSet [acute grave];
+    pos mark [acute grave] <anchor 150 600> mark @MARK_TOP;
+} mkmk;
+",
+    "
+lookup MOVE { pos [a b] 10; } MOVE;
+lookup LOWER { lookupflag IgnoreMarks; pos [c d] <0 -20 0 0>; } LOWER;
+
+feature kern {
+    pos a' lookup MOVE b';
+    pos [a b]' lookup MOVE [c d]' lookup LOWER e;
+    pos [A B] [c d]' lookup LOWER;
+} kern;
+",
+];
+
+fn glyph_count() -> u16 {
+    crate::util::ttx::make_glyph_map().len() as u16
+}
+
+fn compile(fea: &str, opts: Opts) -> Vec<u8> {
+    compile_from_str(fea, opts).unwrap_or_else(|e| panic!("failed to compile:\n{e}\n{fea}"))
+}
+
+#[test]
+fn rewrite_preserves_rules() {
+    let glyph_count = glyph_count();
+    for fea in SAMPLES {
+        let data = compile(fea, Opts::new());
+        let layout = Layout::new(&data, glyph_count);
+        assert!(
+            layout != Layout::default(),
+            "sample compiled to nothing:\n{fea}"
+        );
+        assert_eq!(layout, Layout::rewrite(&data, glyph_count), "{fea}");
+    }
+}
+
+#[test]
+fn encoding_options_preserve_rules() {
+    let glyph_count = glyph_count();
+    let variants = [
+        Opts::new().pair_pos_class_0(false),
+        Opts::new().compact_value_formats(true),
+        Opts::new().force_chain_pos(false),
+    ];
+    for fea in SAMPLES {
+        let expected = Layout::new(&compile(fea, Opts::new()), glyph_count);
+        for opts in &variants {
+            let layout = Layout::new(&compile(fea, opts.clone()), glyph_count);
+            assert_eq!(layout, expected, "{opts:?}\n{fea}");
+        }
+    }
+}
+
+#[test]
+fn class_order_preserves_rules() {
+    let glyph_count = glyph_count();
+    let layout = |fea: &str| Layout::new(&compile(fea, Opts::new()), glyph_count);
+
+    let kern = "feature kern { pos [A T] [a o] -20; pos V [o a] -10; } kern;";
+    let reordered = "feature kern { pos [T A] [o a] -20; pos V [a o] -10; } kern;";
+    assert_eq!(layout(kern), layout(reordered));
+
+    let sub = "feature smcp { sub [a b c] by [A.sc B.sc C.sc]; } smcp;";
+    let reordered = "feature smcp { sub [c a b] by [C.sc A.sc B.sc]; } smcp;";
+    assert_eq!(layout(sub), layout(reordered));
+
+    // and the model notices when the order is lost
+    let swapped = "feature smcp { sub [c a b] by [A.sc B.sc C.sc]; } smcp;";
+    assert_ne!(layout(sub), layout(swapped));
+    let changed = "feature kern { pos [A T] [a o] -20; pos V [o a] -12; } kern;";
+    assert_ne!(layout(kern), layout(changed));
+}
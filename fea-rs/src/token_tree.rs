@@ -1,22 +1,31 @@
-use std::fmt::Write;
-
-use std::{cell::Cell, ops::Range, sync::Arc};
+use std::{
+    cell::{Cell, OnceCell},
+    cmp::Ordering,
+    collections::HashSet,
+    hash::{Hash, Hasher},
+    ops::Range,
+    sync::Arc,
+};
 
 use smol_str::SmolStr;
 
 use crate::parse::{FileId, IncludeStatement};
 use crate::{diagnostic::Diagnostic, GlyphMap};
 
-use self::cursor::Cursor;
 use typed::AstNode as _;
 
+mod comments;
 mod cursor;
 mod edit;
+mod format;
 mod rewrite;
 mod stack;
 mod token;
 pub mod typed;
 
+pub use comments::{AttachedComments, CommentedStatement};
+pub use cursor::Cursor;
+pub use format::TreeFormat;
 use rewrite::ReparseCtx;
 pub use token::Kind;
 
@@ -27,10 +36,6 @@ pub use token::Kind;
 pub struct Node {
     /// The ``Kind` of this node.
     kind: Kind,
-    // start of this node relative to start of parent node.
-    // we can use this to more efficiently move to a given offset
-    // TODO: remove if unused
-    rel_pos: u32,
 
     // NOTE: the absolute position within the tree is not known when the node
     // is created; this is updated (and correct) only when the node has been
@@ -44,8 +49,18 @@ pub struct Node {
     //NOTE: children should not be accessed directly, but only via a cursor.
     // this ensures that their positions are updated correctly.
     children: Arc<Vec<NodeOrToken>>,
+    // the start of each child, used to find the child at a given offset.
+    child_offsets: ChildOffsets,
 }
 
+/// The start of each child of a node, relative to the start of the node.
+///
+/// This is only computed the first time a child is looked up by offset, so
+/// that nodes which are never searched do not pay for it. It is derived from
+/// the children, and so is ignored when comparing nodes.
+#[derive(Clone, Default)]
+struct ChildOffsets(OnceCell<Arc<[u32]>>);
+
 /// A token
 #[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
 pub struct Token {
@@ -68,11 +83,21 @@ pub enum NodeOrToken {
 
 #[derive(Clone, Debug, Default)]
 pub(crate) struct TreeBuilder {
-    //TODO: reuse tokens
-    //token_cache: HashMap<Arc<Token>>,
     // the kind of the parent, and the index in children of the first child.
     parents: Vec<(Kind, usize)>,
     children: Vec<NodeOrToken>,
+    cache: TreeCache,
+}
+
+/// Storage for reusing token text while building a tree.
+///
+/// Large files contain many identical tokens (long glyph names, comments),
+/// and we only need to keep a single copy of the text of each.
+///
+/// Tokens themselves are not shared, since each one stores its own position.
+#[derive(Clone, Debug, Default)]
+struct TreeCache {
+    text: HashSet<SmolStr>,
 }
 
 pub(crate) struct AstSink<'a> {
@@ -167,7 +192,8 @@ impl<'a> AstSink<'a> {
         if kind == Kind::GlyphNameOrRange {
             if let Some(map) = self.glyph_map {
                 if map.contains(text) {
-                    return Token::new(Kind::GlyphName, text.into()).into();
+                    let text = self.builder.cache.intern(text);
+                    return Token::new(Kind::GlyphName, text).into();
                 }
                 match try_split_range(text, map) {
                     Ok(node) => return node.into(),
@@ -178,7 +204,7 @@ impl<'a> AstSink<'a> {
                 }
             }
         }
-        Token::new(kind, text.into()).into()
+        Token::new(kind, self.builder.cache.intern(text)).into()
     }
 
     /// Called before finishing a node.
@@ -236,27 +262,29 @@ impl<'a> AstSink<'a> {
 }
 
 impl Node {
-    fn new(kind: Kind, mut children: Vec<NodeOrToken>, error: bool) -> Self {
-        let mut text_len = 0;
-        for child in &mut children {
-            if let NodeOrToken::Node(n) = child {
-                n.rel_pos += text_len;
-            }
-            text_len += child.text_len() as u32;
-        }
+    fn new(kind: Kind, children: Vec<NodeOrToken>, error: bool) -> Self {
+        let text_len = children.iter().map(|child| child.text_len() as u32).sum();
 
         Node {
             kind,
             text_len,
-            rel_pos: 0,
             abs_pos: Cell::new(0),
             children: children.into(),
+            child_offsets: Default::default(),
             error,
         }
     }
 
+    /// Create a new node with the given children.
+    ///
+    /// This can be used to assemble new nodes from parts of an existing tree,
+    /// for instance in a [`TreeTransform`][crate::compile::TreeTransform].
+    pub fn from_children(kind: Kind, children: impl IntoIterator<Item = NodeOrToken>) -> Self {
+        Node::new(kind, children.into_iter().collect(), false)
+    }
+
     /// Construct a new cursor for navigating the node's children
-    pub(crate) fn cursor(&self) -> Cursor {
+    pub fn cursor(&self) -> Cursor {
         Cursor::new(self)
     }
 
@@ -266,11 +294,122 @@ impl Node {
         std::iter::from_fn(move || cursor.next_token())
     }
 
+    /// Iterate over tokens along with their ranges in the source.
+    ///
+    /// Unlike [`Token::range`], these ranges are computed during iteration, so
+    /// they are correct regardless of how (or whether) the descendants of this
+    /// node have been visited by a cursor, and remain correct if the tokens
+    /// are collected.
+    ///
+    /// Ranges are absolute, and are computed from this node's own position
+    /// (the start of [`Node::range`]). This is always correct for the root of
+    /// a tree, and for any node reached through [`Node::iter_children`] or
+    /// [`Node::node_at_offset`].
+    pub fn iter_tokens_with_ranges(&self) -> impl Iterator<Item = (Range<usize>, &Token)> {
+        let mut pos = self.abs_pos.get() as usize;
+        let mut stack = vec![self.children.iter()];
+        std::iter::from_fn(move || loop {
+            match stack.last_mut()?.next() {
+                None => {
+                    stack.pop();
+                }
+                Some(NodeOrToken::Node(node)) => stack.push(node.children.iter()),
+                Some(NodeOrToken::Token(token)) => {
+                    let start = pos;
+                    pos += token.text.len();
+                    return Some((start..pos, token));
+                }
+            }
+        })
+    }
+
+    /// Return the token containing `offset`, along with its range.
+    ///
+    /// As with [`iter_tokens_with_ranges`], `offset` and the returned range are
+    /// absolute, and are computed from this node's position. Only the nodes
+    /// that contain the offset are visited, and the children of each are
+    /// binary searched.
+    ///
+    /// [`iter_tokens_with_ranges`]: Node::iter_tokens_with_ranges
+    pub fn token_at_offset(&self, offset: usize) -> Option<(Range<usize>, &Token)> {
+        let mut start = self.abs_pos.get() as usize;
+        let mut node = self;
+        loop {
+            let (child, child_start) = node.child_at_offset(offset.checked_sub(start)?)?;
+            start += child_start;
+            match child {
+                NodeOrToken::Token(token) => return Some((start..start + token.text.len(), token)),
+                NodeOrToken::Node(child) => node = child,
+            }
+        }
+    }
+
+    /// Return the nodes containing `offset`, along with their ranges.
+    ///
+    /// The first item is this node, and each item is the parent of the next;
+    /// the last is the deepest node containing the offset. The result is empty
+    /// if the offset is not in this node. Ranges are computed as in
+    /// [`token_at_offset`], and each node's position is recorded, so that
+    /// [`Node::range`] is also correct for the returned nodes.
+    ///
+    /// [`token_at_offset`]: Node::token_at_offset
+    pub fn node_at_offset(&self, offset: usize) -> Vec<(Range<usize>, &Node)> {
+        let mut start = self.abs_pos.get() as usize;
+        let mut result = Vec::new();
+        let mut node = self;
+        while offset >= start && offset < start + node.text_len() {
+            node.abs_pos.set(start as u32);
+            result.push((start..start + node.text_len(), node));
+            match node.child_at_offset(offset - start) {
+                Some((NodeOrToken::Node(child), child_start)) => {
+                    start += child_start;
+                    node = child;
+                }
+                _ => break,
+            }
+        }
+        result
+    }
+
+    /// The child containing an offset relative to the start of this node,
+    /// and the child's start.
+    fn child_at_offset(&self, offset: usize) -> Option<(&NodeOrToken, usize)> {
+        if offset >= self.text_len() {
+            return None;
+        }
+        let offsets = self.child_offsets.0.get_or_init(|| {
+            let mut text_len = 0;
+            self.children
+                .iter()
+                .map(|child| {
+                    let start = text_len;
+                    text_len += child.text_len() as u32;
+                    start
+                })
+                .collect()
+        });
+        // empty children share a start with the next child, so we want the
+        // last child that starts at or before the offset.
+        let idx = offsets
+            .partition_point(|start| *start as usize <= offset)
+            .checked_sub(1)?;
+        Some((&self.children[idx], offsets[idx] as usize))
+    }
+
     /// Iterate over this node's direct children, without descending.
     pub fn iter_children(&self) -> ChildIter {
         ChildIter(Some(self.cursor()))
     }
 
+    /// Iterate over this node's direct children whose kind is one of `kinds`.
+    pub fn children_of_kind<'a>(
+        &'a self,
+        kinds: &'a [Kind],
+    ) -> impl Iterator<Item = &'a NodeOrToken> {
+        self.iter_children()
+            .filter(|child| kinds.contains(&child.kind()))
+    }
+
     /// The `Kind` of the node
     pub fn kind(&self) -> Kind {
         self.kind
@@ -289,6 +428,37 @@ impl Node {
         start..start + (self.text_len as usize)
     }
 
+    /// A hash of the structure and non-trivia tokens of this node.
+    ///
+    /// Whitespace and comments are ignored, so two versions of a source that
+    /// differ only in formatting or comments have the same hash. This is
+    /// useful for quickly checking whether an edit changed anything that
+    /// could affect compilation.
+    ///
+    /// The hash is only stable for a given build of this crate, and should
+    /// not be persisted.
+    pub fn structural_hash(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.hash_structure(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Feed the structure and non-trivia tokens of this node to a hasher.
+    pub(crate) fn hash_structure(&self, state: &mut impl Hasher) {
+        self.kind.hash(state);
+        let children = self
+            .children
+            .iter()
+            .filter(|child| !child.kind().is_trivia());
+        children.clone().count().hash(state);
+        for child in children {
+            match child {
+                NodeOrToken::Token(token) => token.hash_structure(state),
+                NodeOrToken::Node(node) => node.hash_structure(state),
+            }
+        }
+    }
+
     /// Create a new tree, replacing the provided ranges with the provided
     /// nodes.
     ///
@@ -304,7 +474,11 @@ impl Node {
         for item in self.iter_children() {
             if let Some(node) = item.as_node() {
                 if let Some(include) = typed::Include::cast(item) {
-                    collect.push(IncludeStatement(include));
+                    // an include with no path has already been reported
+                    // by the parser; there is nothing to resolve.
+                    if include.find_token(Kind::Path).is_some() {
+                        collect.push(IncludeStatement(include));
+                    }
                     if collect.len() == num {
                         return;
                     }
@@ -336,46 +510,6 @@ impl Node {
             cursor.advance();
         }
     }
-
-    #[doc(hidden)]
-    pub fn simple_parse_tree(&self) -> String {
-        let mut result = String::new();
-        self.parse_tree_impl(0, &mut result).unwrap();
-        result
-    }
-
-    fn parse_tree_impl(&self, depth: usize, buf: &mut String) -> std::fmt::Result {
-        use crate::util::SPACES;
-        let mut pos = self.abs_pos.get();
-        writeln!(
-            buf,
-            "{}{}@[{}; {})",
-            &SPACES[..depth * 2],
-            self.kind,
-            pos,
-            pos + self.text_len
-        )?;
-        let depth = depth + 1;
-        for child in self.iter_children() {
-            match child {
-                NodeOrToken::Token(Token { kind, text, .. }) => {
-                    let spaces = &SPACES[..depth * 2];
-                    write!(buf, "{}{}@{}", spaces, kind, pos)?;
-                    if kind.is_trivia() {
-                        writeln!(buf, " \"{}\"", text.escape_debug())?;
-                    } else {
-                        writeln!(buf, " \"{}\"", text)?;
-                    }
-                    pos += text.len() as u32;
-                }
-                NodeOrToken::Node(node) => {
-                    node.parse_tree_impl(depth + 1, buf)?;
-                    pos += node.text_len;
-                }
-            }
-        }
-        Ok(())
-    }
 }
 
 impl<'a> Iterator for ChildIter<'a> {
@@ -394,8 +528,8 @@ impl TreeBuilder {
         self.parents.push((kind, len));
     }
 
-    pub(crate) fn token(&mut self, kind: Kind, text: impl Into<SmolStr>) {
-        let token = Token::new(kind, text.into());
+    pub(crate) fn token(&mut self, kind: Kind, text: impl AsRef<str>) {
+        let token = Token::new(kind, self.cache.intern(text.as_ref()));
         self.push_raw(token.into());
     }
 
@@ -425,6 +559,24 @@ impl TreeBuilder {
     }
 }
 
+impl TreeCache {
+    // strings up to this length are stored inline by SmolStr
+    const MAX_INLINE_LEN: usize = 22;
+
+    /// Return a `SmolStr` for this text, reusing an existing allocation if possible.
+    fn intern(&mut self, text: &str) -> SmolStr {
+        if text.len() <= Self::MAX_INLINE_LEN {
+            return text.into();
+        }
+        if let Some(existing) = self.text.get(text) {
+            return existing.clone();
+        }
+        let text = SmolStr::from(text);
+        self.text.insert(text.clone());
+        text
+    }
+}
+
 impl NodeOrToken {
     pub(crate) fn set_abs_pos(&self, pos: usize) {
         match self {
@@ -462,6 +614,8 @@ impl NodeOrToken {
     /// The range in the source text of this node or token.
     ///
     /// Note: this is only accurate if the token was accessed via a cursor.
+    /// See [`Node::iter_tokens_with_ranges`] and [`Node::token_at_offset`] for
+    /// ranges that are always correct.
     pub fn range(&self) -> Range<usize> {
         match self {
             NodeOrToken::Token(t) => t.range(),
@@ -523,6 +677,11 @@ impl Token {
         }
     }
 
+    pub(crate) fn hash_structure(&self, state: &mut impl Hasher) {
+        self.kind.hash(state);
+        self.as_str().hash(state);
+    }
+
     /// The raw text for this token
     pub fn as_str(&self) -> &str {
         &self.text
@@ -582,10 +741,9 @@ impl Node {
         let ws = &SPACES[..depth * 2];
         write!(
             f,
-            "\n{}{}: rel {} abs {} len {} children {}",
+            "\n{}{}: abs {} len {} children {}",
             ws,
             self.kind,
-            self.rel_pos,
             self.abs_pos.get(),
             self.text_len,
             self.children.len()
@@ -601,6 +759,26 @@ impl Node {
     }
 }
 
+impl PartialEq for ChildOffsets {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ChildOffsets {}
+
+impl PartialOrd for ChildOffsets {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ChildOffsets {
+    fn cmp(&self, _other: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
 impl std::fmt::Debug for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         self.debug_impl(f, 0)
@@ -613,6 +791,156 @@ mod tests {
     use super::*;
     static SAMPLE_FEA: &str = include_str!("../test-data/fonttools-tests/mini.fea");
 
+    #[test]
+    fn shared_token_text() {
+        let fea = "\
+        feature kern {
+            pos a_very_long_glyph_name.ss01 b <1 2 3 4>;
+            pos a_very_long_glyph_name.ss01 c <1 2 3 4>;
+        } kern;";
+        let (root, errs) = crate::parse::parse_string(fea);
+        assert!(errs.is_empty(), "{errs:?}");
+        let long_names = root
+            .iter_tokens()
+            .filter(|t| t.text.len() > TreeCache::MAX_INLINE_LEN && t.kind == Kind::GlyphName)
+            .map(|t| t.text.as_ptr())
+            .collect::<Vec<_>>();
+        assert_eq!(long_names.len(), 2);
+        assert_eq!(long_names[0], long_names[1]);
+
+        // positions are still correct when iterating
+        let reconstruct = root.iter_tokens().map(Token::as_str).collect::<String>();
+        assert_eq!(reconstruct, fea);
+        for (range, token) in root.iter_tokens_with_ranges() {
+            assert_eq!(&fea[range.clone()], token.as_str());
+            assert_eq!(root.token_at_offset(range.start), Some((range, token)));
+        }
+    }
+
+    #[test]
+    fn identical_statements_have_distinct_ranges() {
+        let fea = "feature test { sub x by y; sub x by y; } test;";
+        let (root, errs) = crate::parse::parse_string(fea);
+        assert!(errs.is_empty(), "{errs:?}");
+        let ranges = root
+            .iter_tokens()
+            .filter(|t| t.as_str() == "x")
+            .map(Token::range)
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [19..20, 31..32]);
+        // and the ranges are unchanged once the whole tree has been visited
+        let all_ranges = root.iter_tokens().map(Token::range).collect::<Vec<_>>();
+        let xs = root
+            .iter_tokens_with_ranges()
+            .zip(all_ranges)
+            .filter(|((_, t), _)| t.as_str() == "x")
+            .map(|((computed, _), stored)| {
+                assert_eq!(computed, stored);
+                stored
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(xs, [19..20, 31..32]);
+    }
+
+    #[test]
+    fn structural_hash_ignores_trivia() {
+        let hash = |fea: &str| crate::parse::parse_string(fea).0.structural_hash();
+        let base = hash("feature kern {\n    pos a b -20;\n} kern;");
+        let reformatted = hash("# kerning\nfeature kern { pos a b -20; # tight\n} kern;\n");
+        assert_eq!(base, reformatted);
+        assert_ne!(base, hash("feature kern {\n    pos a b -30;\n} kern;"));
+        assert_ne!(base, hash("feature kern {\n    pos a c -20;\n} kern;"));
+    }
+
+    #[test]
+    fn token_at_offset() {
+        let fea = "feature kern {\n    pos a b -20;\n} kern;";
+        let (root, _errs) = crate::parse::parse_string(fea);
+        let offset = fea.find("-20").unwrap();
+        for offset in offset..offset + 3 {
+            let (range, token) = root.token_at_offset(offset).unwrap();
+            assert_eq!(token.kind, Kind::Number);
+            assert_eq!(&fea[range], "-20");
+        }
+        let (range, token) = root.token_at_offset(fea.find('{').unwrap() + 1).unwrap();
+        assert_eq!(token.kind, Kind::Whitespace);
+        assert_eq!(&fea[range], "\n    ");
+        assert!(root.token_at_offset(fea.len()).is_none());
+    }
+
+    #[test]
+    fn token_at_every_offset() {
+        // many top-level children, so that the search has more than one step
+        let fea = (0..50)
+            .map(|i| format!("@class{i} = [a b];\n"))
+            .collect::<String>();
+        let (root, _errs) = crate::parse::parse_string(fea.as_str());
+        for (range, token) in root.iter_tokens_with_ranges() {
+            for offset in range.clone() {
+                let (found_range, found) = root.token_at_offset(offset).unwrap();
+                assert_eq!(found_range, range);
+                assert!(std::ptr::eq(found, token));
+            }
+        }
+    }
+
+    #[test]
+    fn node_at_offset() {
+        let fea = "languagesystem DFLT dflt;\nfeature kern {\n    pos a b -20;\n} kern;";
+        let (root, _errs) = crate::parse::parse_string(fea);
+        let offset = fea.find("-20").unwrap() + 1;
+        let nodes = root.node_at_offset(offset);
+        let kinds = nodes.iter().map(|(_, node)| node.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                root.kind,
+                Kind::FeatureNode,
+                Kind::GposType2,
+                Kind::ValueRecordNode
+            ]
+        );
+        let (range, _) = &nodes[1];
+        assert_eq!(
+            &fea[range.clone()],
+            "feature kern {\n    pos a b -20;\n} kern;"
+        );
+        let (range, _) = nodes.last().unwrap();
+        assert_eq!(&fea[range.clone()], "-20");
+
+        // whitespace between top-level items belongs only to the root
+        assert_eq!(root.node_at_offset(fea.find('\n').unwrap()).len(), 1);
+        assert!(root.node_at_offset(fea.len()).is_empty());
+    }
+
+    #[test]
+    fn nested_ranges_are_absolute() {
+        let fea = "languagesystem DFLT dflt;\nfeature kern {\n    pos a b -20;\n} kern;";
+        let (root, _errs) = crate::parse::parse_string(fea);
+        let check = |node: &Node| {
+            let mut ranges = node.iter_tokens_with_ranges().peekable();
+            assert_eq!(ranges.peek().unwrap().0.start, node.range().start);
+            for (range, token) in ranges {
+                assert_eq!(&fea[range], token.as_str());
+            }
+        };
+
+        let feature = root
+            .iter_children()
+            .find(|child| child.kind() == Kind::FeatureNode)
+            .and_then(NodeOrToken::as_node)
+            .unwrap();
+        assert_eq!(feature.range().start, fea.find("feature").unwrap());
+        check(feature);
+
+        let (_, pos) = root.node_at_offset(fea.find("-20").unwrap())[2];
+        assert_eq!(pos.kind, Kind::GposType2);
+        check(pos);
+        let (range, token) = pos.token_at_offset(fea.find("-20").unwrap()).unwrap();
+        assert_eq!(token.as_str(), "-20");
+        assert_eq!(&fea[range], "-20");
+    }
+
     #[test]
     fn token_iter() {
         let (root, _errs) = crate::parse::parse_string(SAMPLE_FEA);
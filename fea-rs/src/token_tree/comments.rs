@@ -0,0 +1,181 @@
+//! Associating comments with the statements they document.
+
+use std::ops::Range;
+
+use super::{Kind, Node, NodeOrToken, Token};
+
+/// A statement in a block, along with the comments attached to it.
+///
+/// All ranges are computed as in [`Node::iter_tokens_with_ranges`].
+#[derive(Clone, Debug)]
+pub struct CommentedStatement {
+    /// The statement node.
+    pub statement: Node,
+    /// The range of the statement, not including its comments.
+    pub range: Range<usize>,
+    /// Comments on the lines directly above the statement, in source order.
+    ///
+    /// A blank line ends a run of leading comments; comments above it are
+    /// not attached to the statement.
+    pub leading: Vec<(Range<usize>, Token)>,
+    /// A comment following the statement on the same line.
+    pub trailing: Option<(Range<usize>, Token)>,
+}
+
+/// The statements of a block, with their comments.
+///
+/// This is returned by [`Node::attached_comments`].
+#[derive(Clone, Debug, Default)]
+pub struct AttachedComments {
+    /// Each statement in the block, in source order.
+    pub statements: Vec<CommentedStatement>,
+    /// Comments that do not belong to any statement, such as a file header
+    /// followed by a blank line, or a comment at the end of a block.
+    pub detached: Vec<(Range<usize>, Token)>,
+}
+
+impl CommentedStatement {
+    /// The range of the statement, extended to include its comments.
+    ///
+    /// When moving or regenerating a statement, replacing this range keeps
+    /// its comments with it.
+    pub fn full_range(&self) -> Range<usize> {
+        let start = self
+            .leading
+            .first()
+            .map(|(range, _)| range.start)
+            .unwrap_or(self.range.start);
+        let end = self
+            .trailing
+            .as_ref()
+            .map(|(range, _)| range.end)
+            .unwrap_or(self.range.end);
+        start..end
+    }
+}
+
+impl Node {
+    /// Attach the comments in this block to the statements they document.
+    ///
+    /// The statements are the direct child nodes of this node, which should
+    /// be a block such as the root of a file or a feature or lookup block;
+    /// to handle nested blocks, call this on each of them.
+    ///
+    /// A statement's leading comments are those on the lines immediately
+    /// before it, and its trailing comment is one that starts on the same
+    /// line as its end. All other comments are detached.
+    pub fn attached_comments(&self) -> AttachedComments {
+        let mut result = AttachedComments::default();
+        let mut pending = Vec::new();
+        // what a comment would trail, if no newline has been seen since it
+        let mut trails = Trails::Nothing;
+        let mut child_start = self.abs_pos.get() as usize;
+
+        for child in self.children.iter() {
+            let range = child_start..child_start + child.text_len();
+            child_start = range.end;
+            match child {
+                NodeOrToken::Token(token) if token.kind == Kind::Whitespace => {
+                    let newlines = token.text.matches('\n').count();
+                    if newlines > 0 {
+                        trails = Trails::Nothing;
+                    }
+                    if newlines > 1 {
+                        result.detached.append(&mut pending);
+                    }
+                }
+                NodeOrToken::Token(token) if token.kind == Kind::Comment => {
+                    let comment = (range, token.clone());
+                    match (trails, result.statements.last_mut()) {
+                        (Trails::Statement, Some(prev)) => prev.trailing = Some(comment),
+                        (Trails::Token, _) => result.detached.push(comment),
+                        _ => pending.push(comment),
+                    }
+                    trails = Trails::Nothing;
+                }
+                NodeOrToken::Token(_) => {
+                    // braces, keywords and the like
+                    result.detached.append(&mut pending);
+                    trails = Trails::Token;
+                }
+                NodeOrToken::Node(node) => {
+                    let statement = node.clone();
+                    statement.abs_pos.set(range.start as u32);
+                    result.statements.push(CommentedStatement {
+                        statement,
+                        range,
+                        leading: std::mem::take(&mut pending),
+                        trailing: None,
+                    });
+                    trails = Trails::Statement;
+                }
+            }
+        }
+        result.detached.append(&mut pending);
+        result.detached.sort_by_key(|(range, _)| range.start);
+        result
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Trails {
+    Nothing,
+    Statement,
+    Token,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(comments: &[(Range<usize>, Token)]) -> Vec<&str> {
+        comments.iter().map(|(_, token)| token.as_str()).collect()
+    }
+
+    #[test]
+    fn attach_comments() {
+        let fea = "\
+# file header
+
+# the class
+# has two lines
+@class = [a b]; # trailing
+languagesystem DFLT dflt;
+feature liga { # after brace
+    # a rule
+    sub f i by f_i;
+    # dangling
+} liga;
+";
+        let (root, errs) = crate::parse::parse_string(fea);
+        assert!(errs.is_empty(), "{errs:?}");
+        let comments = root.attached_comments();
+        assert_eq!(comments.statements.len(), 3);
+        assert_eq!(texts(&comments.detached), ["# file header"]);
+
+        let class = &comments.statements[0];
+        assert_eq!(class.statement.kind(), Kind::GlyphClassDefNode);
+        assert_eq!(texts(&class.leading), ["# the class", "# has two lines"]);
+        let (trail_range, trailing) = class.trailing.as_ref().unwrap();
+        assert_eq!(trailing.as_str(), "# trailing");
+        assert_eq!(&fea[trail_range.clone()], "# trailing");
+        assert_eq!(&fea[class.range.clone()], "@class = [a b];");
+        assert_eq!(
+            &fea[class.full_range()],
+            "# the class\n# has two lines\n@class = [a b]; # trailing"
+        );
+
+        let langsys = &comments.statements[1];
+        assert!(langsys.leading.is_empty());
+        assert!(langsys.trailing.is_none());
+
+        let feature = &comments.statements[2];
+        assert_eq!(feature.statement.kind(), Kind::FeatureNode);
+        let inner = feature.statement.attached_comments();
+        assert_eq!(texts(&inner.detached), ["# after brace", "# dangling"]);
+        assert_eq!(inner.statements.len(), 1);
+        let rule = &inner.statements[0];
+        assert_eq!(texts(&rule.leading), ["# a rule"]);
+        assert_eq!(&fea[rule.range.clone()], "sub f i by f_i;");
+    }
+}
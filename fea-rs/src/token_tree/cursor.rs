@@ -2,7 +2,11 @@
 
 use super::{stack::Stack, Kind, Node, NodeOrToken, Token};
 
-pub(crate) struct Cursor<'a> {
+/// A cursor for navigating through a tree.
+///
+/// A cursor starts at the first child of the node it was created from, and
+/// can step into, over, and back out of child nodes.
+pub struct Cursor<'a> {
     pos: usize,
     // the current root. This is not directly accessible.
     current: NodeRef<'a>,
@@ -18,6 +22,7 @@ struct NodeRef<'a> {
 }
 
 impl<'a> Cursor<'a> {
+    /// Create a cursor pointing at the first child of `root`.
     pub fn new(root: &'a Node) -> Self {
         if let Some(child) = root.children.first() {
             child.set_abs_pos(root.abs_pos.get() as usize);
@@ -48,6 +53,9 @@ impl<'a> Cursor<'a> {
         self.current.node.kind
     }
 
+    /// Advance to the next token, returning it.
+    ///
+    /// This descends into child nodes.
     pub fn next_token(&mut self) -> Option<&'a Token> {
         loop {
             let current = self.current();
@@ -141,6 +149,11 @@ impl<'a> Cursor<'a> {
         self.parents.push(prev);
     }
 
+    /// Move down into the current node.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the current item is not a node.
     pub fn descend_current(&mut self) {
         let new_current = self
             .current()
@@ -149,6 +162,35 @@ impl<'a> Cursor<'a> {
         self.descend(new_current)
     }
 
+    /// Iterate over the direct children of the current node whose kind is one
+    /// of `kinds`.
+    ///
+    /// This is empty if the cursor is not pointing at a node. The cursor itself
+    /// does not move.
+    pub fn children_of_kind(&self, kinds: &'a [Kind]) -> impl Iterator<Item = &'a NodeOrToken> {
+        self.current()
+            .and_then(NodeOrToken::as_node)
+            .into_iter()
+            .flat_map(move |node| node.children_of_kind(kinds))
+    }
+
+    /// Step over siblings of the current item until one has the given kind.
+    ///
+    /// The current item itself is always skipped, and the cursor does not
+    /// leave the current parent. Returns the matching item, which is then the
+    /// current item, or `None` if no later sibling matches; in that case the
+    /// cursor is left past the parent's last child.
+    pub fn next_sibling_of_kind(&mut self, kind: Kind) -> Option<&'a NodeOrToken> {
+        self.current()?;
+        loop {
+            self.step_over();
+            let current = self.current()?;
+            if current.kind() == kind {
+                return Some(current);
+            }
+        }
+    }
+
     /// Return to a parent node.
     ///
     /// This always sets the cursor position to the start position of the
@@ -256,6 +298,35 @@ mod tests {
             .unwrap_or(false)
     }
 
+    #[test]
+    fn kind_queries() {
+        let (root, errs) =
+            crate::parse::parse_string("feature kern { pos a b -20; pos c d 10; } kern;");
+        assert!(errs.is_empty());
+        let mut cursor = root.cursor();
+        assert!(at_node(&cursor, Kind::FeatureNode));
+        let rules = cursor
+            .children_of_kind(&[Kind::GposType2, Kind::Tag])
+            .map(NodeOrToken::kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            [Kind::Tag, Kind::GposType2, Kind::GposType2, Kind::Tag]
+        );
+        // the cursor hasn't moved
+        assert!(at_node(&cursor, Kind::FeatureNode));
+
+        cursor.descend_current();
+        let brace = cursor.next_sibling_of_kind(Kind::LBrace).unwrap();
+        assert_eq!(brace.range().start, "feature kern ".len());
+        let first = cursor.next_sibling_of_kind(Kind::GposType2).unwrap();
+        assert_eq!(first.range().start, "feature kern { ".len());
+        let second = cursor.next_sibling_of_kind(Kind::GposType2).unwrap();
+        assert_eq!(second.range().start, "feature kern { pos a b -20; ".len());
+        assert!(cursor.next_sibling_of_kind(Kind::GposType2).is_none());
+        assert!(cursor.current().is_none());
+    }
+
     #[test]
     fn advance() {
         let (root, errs) = crate::parse::parse_string("feature kern { pos a b -20; }kern;");
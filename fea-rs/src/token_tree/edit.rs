@@ -79,7 +79,9 @@ fn op_for_node(node_range: Range<usize>, edit_range: Range<usize>) -> EditOp {
     assert!(edit_range.start >= node_range.start);
     if node_range == edit_range {
         EditOp::Replace
-    } else if edit_range.start > node_range.start && edit_range.end < node_range.end {
+    } else if edit_range.end <= node_range.end && edit_range.start < node_range.end {
+        // the edit may share a boundary with its parent, for instance an
+        // include statement that ends an unterminated feature block
         EditOp::Recurse
     } else {
         assert!(
@@ -155,4 +157,21 @@ feature liga {
         let result = edited.iter_tokens().map(|t| t.as_str()).collect::<String>();
         crate::assert_eq_str!(expected, result);
     }
+
+    #[test]
+    fn rewrite_at_end_of_parent() {
+        let fea = "feature liga { sub f i by f_i;";
+        let mut sink = AstSink::new(fea, FileId::CURRENT_FILE, None);
+        let mut parser = Parser::new(fea, &mut sink);
+        crate::parse::grammar::root(&mut parser);
+        let (root, _errs, _) = sink.finish();
+
+        let replace_sub = make_node("sub f l by f_l;", |p| {
+            crate::parse::grammar::parse_gsub(p, TokenSet::FEATURE_STATEMENT)
+        });
+        let edits = vec![(15..fea.len(), replace_sub)];
+        let edited = apply_edits(&root, edits, false);
+        let result = edited.iter_tokens().map(|t| t.as_str()).collect::<String>();
+        crate::assert_eq_str!("feature liga { sub f l by f_l;", result);
+    }
 }
@@ -0,0 +1,227 @@
+//! Printing a tree in a chosen format.
+
+use std::fmt::Write;
+
+use super::{Node, NodeOrToken, Token};
+
+/// A format for printing a tree, used by [`Node::format`].
+///
+/// All formats are lossless: they include every token, including whitespace
+/// and comments, along with its range in the source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TreeFormat {
+    /// An indented tree of nodes and tokens.
+    ///
+    /// This uses the short display names of kinds, such as `WS` for
+    /// whitespace; the other formats use the full names.
+    #[default]
+    Tree,
+    /// Each token on its own line, without the nodes that contain them.
+    Tokens,
+    /// A JSON object for each node and token.
+    ///
+    /// Nodes have `kind`, `range` and `children` fields, and tokens have
+    /// `kind`, `range` and `text` fields.
+    Json,
+    /// An s-expression for each node and token.
+    SExpr,
+}
+
+impl Node {
+    /// Print this node and its descendants in the given format.
+    ///
+    /// As with [`Node::iter_tokens_with_ranges`], ranges are absolute, and are
+    /// computed from this node's position.
+    pub fn format(&self, format: TreeFormat) -> String {
+        let mut buf = String::new();
+        let pos = self.abs_pos.get() as usize;
+        match format {
+            TreeFormat::Tree => write_tree(self, pos, 0, &mut buf),
+            TreeFormat::Tokens => write_tokens(self, &mut buf),
+            TreeFormat::Json => write_json(self, pos, &mut buf).and_then(|_| writeln!(buf)),
+            TreeFormat::SExpr => write_sexpr(self, pos, 0, &mut buf).and_then(|_| writeln!(buf)),
+        }
+        .unwrap();
+        buf
+    }
+}
+
+fn write_tree(node: &Node, mut pos: usize, depth: usize, buf: &mut String) -> std::fmt::Result {
+    use crate::util::SPACES;
+    writeln!(
+        buf,
+        "{}{}@[{}; {})",
+        &SPACES[..depth * 2],
+        node.kind,
+        pos,
+        pos + node.text_len()
+    )?;
+    let depth = depth + 1;
+    for child in node.iter_children() {
+        match child {
+            NodeOrToken::Token(Token { kind, text, .. }) => {
+                let spaces = &SPACES[..depth * 2];
+                write!(buf, "{}{}@{}", spaces, kind, pos)?;
+                if kind.is_trivia() {
+                    writeln!(buf, " \"{}\"", text.escape_debug())?;
+                } else {
+                    writeln!(buf, " \"{}\"", text)?;
+                }
+            }
+            NodeOrToken::Node(node) => write_tree(node, pos, depth + 1, buf)?,
+        }
+        pos += child.text_len();
+    }
+    Ok(())
+}
+
+fn write_tokens(node: &Node, buf: &mut String) -> std::fmt::Result {
+    for (range, token) in node.iter_tokens_with_ranges() {
+        writeln!(
+            buf,
+            "{:?}@{}..{} \"{}\"",
+            token.kind,
+            range.start,
+            range.end,
+            token.text.escape_debug()
+        )?;
+    }
+    Ok(())
+}
+
+fn write_json(node: &Node, mut pos: usize, buf: &mut String) -> std::fmt::Result {
+    write!(
+        buf,
+        "{{\"kind\":\"{:?}\",\"range\":[{},{}],\"children\":[",
+        node.kind,
+        pos,
+        pos + node.text_len()
+    )?;
+    for (i, child) in node.iter_children().enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        match child {
+            NodeOrToken::Token(token) => {
+                write!(
+                    buf,
+                    "{{\"kind\":\"{:?}\",\"range\":[{},{}],\"text\":\"",
+                    token.kind,
+                    pos,
+                    pos + token.text.len()
+                )?;
+                write_json_escaped(&token.text, buf)?;
+                buf.push_str("\"}");
+            }
+            NodeOrToken::Node(node) => write_json(node, pos, buf)?,
+        }
+        pos += child.text_len();
+    }
+    buf.push_str("]}");
+    Ok(())
+}
+
+fn write_json_escaped(text: &str, buf: &mut String) -> std::fmt::Result {
+    for chr in text.chars() {
+        match chr {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            chr if chr.is_control() => write!(buf, "\\u{:04x}", chr as u32)?,
+            chr => buf.push(chr),
+        }
+    }
+    Ok(())
+}
+
+fn write_sexpr(node: &Node, mut pos: usize, depth: usize, buf: &mut String) -> std::fmt::Result {
+    use crate::util::SPACES;
+    write!(buf, "({:?}@{}..{}", node.kind, pos, pos + node.text_len())?;
+    let depth = depth + 1;
+    for child in node.iter_children() {
+        write!(buf, "\n{}", &SPACES[..depth * 2])?;
+        match child {
+            NodeOrToken::Token(token) => write!(
+                buf,
+                "({:?}@{}..{} \"{}\")",
+                token.kind,
+                pos,
+                pos + token.text.len(),
+                token.text.escape_debug()
+            )?,
+            NodeOrToken::Node(node) => write_sexpr(node, pos, depth, buf)?,
+        }
+        pos += child.text_len();
+    }
+    buf.push(')');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(fea: &str) -> Node {
+        let (root, errs) = crate::parse::parse_string(fea);
+        assert!(errs.is_empty(), "{errs:?}");
+        root
+    }
+
+    #[test]
+    fn formats() {
+        let root = parse("languagesystem DFLT dflt;\n");
+        assert_eq!(
+            root.format(TreeFormat::Tree),
+            "\
+FILE@[0; 26)
+    LanguageSystemNode@[0; 25)
+      LanguagesystemKw@0 \"languagesystem\"
+      WS@14 \" \"
+      Tag@15 \"DFLT\"
+      WS@19 \" \"
+      Tag@20 \"dflt\"
+      ;@24 \";\"
+  WS@25 \"\\n\"
+"
+        );
+        assert_eq!(
+            root.format(TreeFormat::Tokens),
+            "\
+LanguagesystemKw@0..14 \"languagesystem\"
+Whitespace@14..15 \" \"
+Tag@15..19 \"DFLT\"
+Whitespace@19..20 \" \"
+Tag@20..24 \"dflt\"
+Semi@24..25 \";\"
+Whitespace@25..26 \"\\n\"
+"
+        );
+        assert_eq!(
+            root.format(TreeFormat::SExpr),
+            "\
+(SourceFile@0..26
+  (LanguageSystemNode@0..25
+    (LanguagesystemKw@0..14 \"languagesystem\")
+    (Whitespace@14..15 \" \")
+    (Tag@15..19 \"DFLT\")
+    (Whitespace@19..20 \" \")
+    (Tag@20..24 \"dflt\")
+    (Semi@24..25 \";\"))
+  (Whitespace@25..26 \"\\n\"))
+"
+        );
+    }
+
+    #[test]
+    fn json_escapes() {
+        let root = parse("# a \"quoted\" \\ comment\n");
+        assert_eq!(
+            root.format(TreeFormat::Json),
+            "{\"kind\":\"SourceFile\",\"range\":[0,23],\"children\":[\
+             {\"kind\":\"Comment\",\"range\":[0,22],\"text\":\"# a \\\"quoted\\\" \\\\ comment\"},\
+             {\"kind\":\"Whitespace\",\"range\":[22,23],\"text\":\"\\n\"}]}\n"
+        );
+    }
+}
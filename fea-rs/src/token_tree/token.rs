@@ -1,7 +1,15 @@
 /// Kinds of tokens assigned during lexing and parsing.
+///
+/// New kinds may be added in any release, so matches on this type need a
+/// wildcard arm. The category helpers, such as [`is_keyword`] and
+/// [`is_trivia`], are kept up to date as kinds are added.
+///
+/// [`is_keyword`]: Kind::is_keyword
+/// [`is_trivia`]: Kind::is_trivia
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[allow(missing_docs)]
 #[repr(u16)]
+#[non_exhaustive]
 pub enum Kind {
     Eof, // the end of the input stream
     // a name or a keyword or any other block of non-whitespace.
@@ -109,6 +117,12 @@ pub enum Kind {
     WinAscentKw,                 //OS/2 table
     WinDescentKw,                //OS/2 table
     XHeightKw,                   //OS/2 table
+    FamilyClassKw,               //OS/2 table
+    FsTypeKw,                    //OS/2 table
+    LowerOpSizeKw,               //OS/2 table
+    UpperOpSizeKw,               //OS/2 table
+    WeightClassKw,               //OS/2 table
+    WidthClassKw,                //OS/2 table
     SizemenunameKw,              //size feature
     VertTypoAscenderKw,          //vhea table
     VertTypoDescenderKw,         //vhea table
@@ -208,6 +222,7 @@ pub enum Kind {
     IncludeNode,
     MarkClassNode,
     AnchorNode,
+    VariableMetricNode,
     DeviceNode,
     AnchorDefNode,
     AnonBlockNode,
@@ -261,7 +276,39 @@ pub enum Kind {
 }
 
 impl Kind {
-    pub(crate) fn is_rule(&self) -> bool {
+    /// `true` if this is a keyword.
+    ///
+    /// This includes keywords that only have meaning in certain contexts,
+    /// such as those of specific tables, as well as `base` and `ligature`,
+    /// which are assigned during parsing.
+    pub fn is_keyword(self) -> bool {
+        (Kind::TableKw..=Kind::CharacterKw).contains(&self)
+            || matches!(self, Kind::LigatureKw | Kind::BaseKw)
+    }
+
+    /// `true` if this is a node of a substitution rule.
+    ///
+    /// This does not include `ignore sub` statements; see
+    /// [`is_ignore_rule`](Self::is_ignore_rule).
+    pub fn is_gsub_rule(self) -> bool {
+        matches!(
+            self,
+            Self::GsubType1
+                | Self::GsubType2
+                | Self::GsubType3
+                | Self::GsubType4
+                | Self::GsubType5
+                | Self::GsubType6
+                | Self::GsubType7
+                | Self::GsubType8
+        )
+    }
+
+    /// `true` if this is a node of a positioning rule.
+    ///
+    /// This does not include `ignore pos` statements; see
+    /// [`is_ignore_rule`](Self::is_ignore_rule).
+    pub fn is_gpos_rule(self) -> bool {
         matches!(
             self,
             Self::GposType1
@@ -272,18 +319,24 @@ impl Kind {
                 | Self::GposType6
                 | Self::GposType7
                 | Self::GposType8
-                | Self::GsubType1
-                | Self::GsubType2
-                | Self::GsubType3
-                | Self::GsubType4
-                | Self::GsubType5
-                | Self::GsubType6
-                | Self::GsubType7
-                | Self::GsubType8
         )
     }
 
-    pub(crate) fn is_trivia(self) -> bool {
+    /// `true` if this is a node of a substitution or positioning rule.
+    pub fn is_rule(self) -> bool {
+        self.is_gsub_rule() || self.is_gpos_rule()
+    }
+
+    /// `true` if this is a node of an `ignore sub` or `ignore pos` statement.
+    pub fn is_ignore_rule(self) -> bool {
+        matches!(self, Self::GsubIgnore | Self::GposIgnore)
+    }
+
+    /// `true` for tokens that do not affect the meaning of the source.
+    ///
+    /// These are whitespace, comments, and the backslash that can precede a
+    /// glyph name.
+    pub fn is_trivia(self) -> bool {
         matches!(self, Kind::Comment | Kind::Whitespace | Kind::Backslash)
     }
 }
@@ -396,6 +449,12 @@ impl std::fmt::Display for Kind {
             Self::WinAscentKw => write!(f, "winAscent"),
             Self::WinDescentKw => write!(f, "winDescent"),
             Self::XHeightKw => write!(f, "XHeight"),
+            Self::FamilyClassKw => write!(f, "FamilyClass"),
+            Self::FsTypeKw => write!(f, "FSType"),
+            Self::LowerOpSizeKw => write!(f, "LowerOpSize"),
+            Self::UpperOpSizeKw => write!(f, "UpperOpSize"),
+            Self::WeightClassKw => write!(f, "WeightClass"),
+            Self::WidthClassKw => write!(f, "WidthClass"),
             Self::SizemenunameKw => write!(f, "sizemenuname"),
             Self::VertTypoAscenderKw => write!(f, "VertTypoAscender"),
             Self::VertTypoDescenderKw => write!(f, "VertTypoDescender"),
@@ -465,6 +524,7 @@ impl std::fmt::Display for Kind {
             Self::MarkClassNode => write!(f, "MarkClassNode"),
             Self::AnchorDefNode => write!(f, "AnchorDefNode"),
             Self::AnchorNode => write!(f, "AnchorNode"),
+            Self::VariableMetricNode => write!(f, "VariableMetricNode"),
             Self::DeviceNode => write!(f, "DeviceNode"),
             Self::AnonBlockNode => write!(f, "AnonBlockNode"),
             Self::GlyphClassDefNode => write!(f, "GlyphClassDefNode"),
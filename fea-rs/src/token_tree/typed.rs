@@ -11,7 +11,7 @@ use std::convert::TryFrom;
 use std::ops::Range;
 
 use smol_str::SmolStr;
-use write_fonts::types::Fixed;
+use write_fonts::{read::tables::name::MacRomanMapping, types::Fixed};
 
 use crate::{Kind, Node, NodeOrToken};
 
@@ -202,7 +202,9 @@ ast_node!(GlyphRange, Kind::GlyphRange);
 ast_node!(GlyphClassDef, Kind::GlyphClassDefNode);
 ast_node!(MarkClassDef, Kind::MarkClassNode);
 ast_node!(Anchor, Kind::AnchorNode);
+ast_node!(VariableMetric, Kind::VariableMetricNode);
 ast_node!(AnchorDef, Kind::AnchorDefNode);
+ast_node!(AnonBlock, Kind::AnonBlockNode);
 ast_node!(ValueRecordDef, Kind::ValueRecordDefKw);
 ast_node!(GlyphClassLiteral, Kind::GlyphClass);
 ast_node!(LanguageSystem, Kind::LanguageSystemNode);
@@ -268,6 +270,11 @@ ast_enum!(FloatLike {
     Number(Number),
 });
 
+ast_enum!(AnchorMetric {
+    Scalar(Metric),
+    Variable(VariableMetric),
+});
+
 ast_node!(GdefClassDef, Kind::GdefClassDefNode);
 ast_node!(GdefClassDefEntry, Kind::GdefClassDefEntryNode);
 ast_node!(GdefAttach, Kind::GdefAttachNode);
@@ -418,11 +425,11 @@ impl LanguageSystem {
     }
 
     pub(crate) fn language(&self) -> Tag {
-        self.inner
-            .iter_children()
-            .skip_while(|t| t.kind() != Kind::Tag)
-            .skip(1)
-            .find_map(Tag::cast)
+        let mut cursor = self.inner.cursor();
+        cursor.next_sibling_of_kind(Kind::Tag);
+        cursor
+            .next_sibling_of_kind(Kind::Tag)
+            .and_then(Tag::cast)
             .unwrap()
     }
 }
@@ -475,7 +482,12 @@ impl GlyphClassLiteral {
 
 impl Cid {
     pub(crate) fn parse(&self) -> u16 {
-        self.inner.text.parse().expect("cid is already validated")
+        self.try_parse().expect("cid is already validated")
+    }
+
+    /// Parse this CID, returning an error message if it is out of range.
+    pub(crate) fn try_parse(&self) -> Result<u16, String> {
+        parse_u16(&self.inner.text)
     }
 }
 
@@ -533,13 +545,41 @@ impl AnchorDef {
     }
 }
 
+impl AnonBlock {
+    /// The tag following the `anon` keyword.
+    pub(crate) fn tag(&self) -> &Token {
+        self.iter()
+            .filter(|t| !t.kind().is_trivia())
+            .nth(1)
+            .and_then(NodeOrToken::as_token)
+            .expect("pre-validated")
+    }
+
+    /// The raw text between the opening and closing braces.
+    pub(crate) fn contents(&self) -> String {
+        let close_idx = self
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.kind() == Kind::RBrace)
+            .map(|(i, _)| i)
+            .last()
+            .unwrap_or(usize::MAX);
+        self.iter()
+            .take(close_idx)
+            .skip_while(|t| t.kind() != Kind::LBrace)
+            .skip(1)
+            .filter_map(NodeOrToken::token_text)
+            .collect()
+    }
+}
+
 impl Anchor {
-    pub(crate) fn coords(&self) -> Option<(Metric, Metric)> {
+    pub(crate) fn coords(&self) -> Option<(AnchorMetric, AnchorMetric)> {
         let tokens = self.iter();
         let mut first = None;
 
         for token in tokens {
-            if let Some(metric) = Metric::cast(token) {
+            if let Some(metric) = AnchorMetric::cast(token) {
                 if let Some(prev) = first.take() {
                     return Some((prev, metric));
                 } else {
@@ -569,14 +609,31 @@ impl Anchor {
     }
 }
 
+impl VariableMetric {
+    /// The text between the parentheses, listing the value at each location.
+    pub(crate) fn locations(&self) -> Option<&Token> {
+        self.find_token(Kind::Path)
+    }
+}
+
 impl Number {
     pub(crate) fn parse_signed(&self) -> i16 {
-        self.text().parse().expect("already validated")
+        self.try_parse_signed().expect("already validated")
+    }
+
+    /// Parse this number as an `i16`, returning an error message if it is out of range.
+    pub(crate) fn try_parse_signed(&self) -> Result<i16, String> {
+        parse_i16(self.text())
     }
 
     pub(crate) fn parse_unsigned(&self) -> Option<u16> {
         self.text().parse().ok()
     }
+
+    /// Parse this number as a `u16`, returning an error message if it is out of range.
+    pub(crate) fn try_parse_unsigned(&self) -> Result<u16, String> {
+        parse_u16(self.text())
+    }
 }
 
 impl Float {
@@ -597,6 +654,15 @@ impl FloatLike {
         }
     }
 
+    /// Parse this value, returning an error message if it is an integer that
+    /// is out of range.
+    pub(crate) fn try_parse(&self) -> Result<f32, String> {
+        match self {
+            FloatLike::Number(n) => n.try_parse_signed().map(f32::from),
+            FloatLike::Float(n) => Ok(n.parse()),
+        }
+    }
+
     pub(crate) fn parse_fixed(&self) -> Fixed {
         Fixed::from_f64(self.parse() as _)
     }
@@ -604,10 +670,35 @@ impl FloatLike {
 
 impl Metric {
     pub(crate) fn parse(&self) -> i16 {
-        self.text().parse().expect("already validated")
+        self.try_parse().expect("already validated")
+    }
+
+    /// Parse this metric, returning an error message if it is out of range.
+    pub(crate) fn try_parse(&self) -> Result<i16, String> {
+        parse_i16(self.text())
     }
 }
 
+pub(crate) fn parse_u16(text: &str) -> Result<u16, String> {
+    text.parse().map_err(|_| {
+        format!(
+            "value '{text}' is out of range: expected a number from {} to {}",
+            u16::MIN,
+            u16::MAX
+        )
+    })
+}
+
+fn parse_i16(text: &str) -> Result<i16, String> {
+    text.parse().map_err(|_| {
+        format!(
+            "value '{text}' is out of range: expected a number from {} to {}",
+            i16::MIN,
+            i16::MAX
+        )
+    })
+}
+
 impl Feature {
     pub(crate) fn tag(&self) -> Tag {
         self.iter().find_map(Tag::cast).unwrap()
@@ -673,7 +764,6 @@ impl Language {
     }
 
     //FIXME: I believe this is never meaningful, as it is the default behaviour?
-    #[allow(unused)]
     pub(crate) fn include_dflt(&self) -> Option<&Token> {
         self.find_token(Kind::IncludeDfltKw)
     }
@@ -719,15 +809,18 @@ impl Gsub1 {
 }
 
 impl Gsub2 {
-    pub(crate) fn target(&self) -> Glyph {
-        self.iter().find_map(Glyph::cast).unwrap()
+    pub(crate) fn target(&self) -> GlyphOrClass {
+        self.iter().find_map(GlyphOrClass::cast).unwrap()
     }
 
-    pub(crate) fn replacement(&self) -> impl Iterator<Item = Glyph> + '_ {
+    /// The replacement sequence.
+    ///
+    /// This may only contain classes if the target is a class.
+    pub(crate) fn replacement(&self) -> impl Iterator<Item = GlyphOrClass> + '_ {
         self.iter()
             .skip_while(|t| t.kind() != Kind::ByKw)
             .skip(1)
-            .filter_map(Glyph::cast)
+            .filter_map(GlyphOrClass::cast)
     }
 }
 
@@ -949,8 +1042,13 @@ impl AnchorMark {
 }
 
 impl ValueRecord {
+    /// The single number of a format A record, with or without brackets.
     pub(crate) fn advance(&self) -> Option<Number> {
-        self.iter().next().and_then(Number::cast)
+        let mut numbers = self.iter().filter_map(Number::cast);
+        match (numbers.next(), numbers.next()) {
+            (Some(number), None) => Some(number),
+            _ => None,
+        }
     }
 
     pub(crate) fn null(&self) -> Option<&Token> {
@@ -992,14 +1090,15 @@ impl ValueRecord {
 }
 
 impl Device {
-    fn null(&self) -> Option<&Token> {
+    pub(crate) fn null(&self) -> Option<&Token> {
         self.iter()
             .take(4)
             .find(|t| t.kind() == Kind::NullKw)
             .and_then(NodeOrToken::as_token)
     }
 
-    fn entries(&self) -> impl Iterator<Item = (Number, Number)> + '_ {
+    /// The (ppem size, pixel delta) pairs in this device table.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (Number, Number)> + '_ {
         let mut iter = self
             .iter()
             .filter(|i| i.kind() == Kind::Number || i.kind() == Kind::Comma);
@@ -1297,6 +1396,108 @@ impl NameSpec {
     pub(crate) fn string(&self) -> &Token {
         self.find_token(Kind::String).unwrap()
     }
+
+    /// The value of the string, with any escape sequences decoded.
+    ///
+    /// In Mac strings (platform 1) an escape is two hex digits, for a byte in
+    /// the Mac Roman encoding; in Windows strings (platform 3) it is four hex
+    /// digits, for a UTF-16 code unit. On failure, this returns the range of
+    /// the problem and a message.
+    pub(crate) fn decoded_string(&self, platform: u16) -> Result<String, (Range<usize>, String)> {
+        decode_name_string(self.string(), platform)
+    }
+}
+
+fn decode_name_string(string: &Token, platform: u16) -> Result<String, (Range<usize>, String)> {
+    const MAC_PLATFORM_ID: u16 = 1;
+
+    let is_mac = platform == MAC_PLATFORM_ID;
+    let (escape_len, length_err) = if is_mac {
+        (2, "mac escape sequences must be two hex digits long")
+    } else {
+        (4, "windows escape sequences must be four hex digits long")
+    };
+
+    let text = string.as_str();
+    debug_assert!(text.len() >= 2 && text.starts_with('"') && text.ends_with('"'));
+    let mut rest = &text[1..text.len() - 1];
+    // the position of `rest` in the source
+    let mut offset = string.range().start + 1;
+    let mut out = String::with_capacity(rest.len());
+    // a high surrogate that must be followed by a low surrogate
+    let mut high_surrogate: Option<(u16, Range<usize>)> = None;
+
+    let push_text = |out: &mut String, text: &str, offset: usize| {
+        if is_mac {
+            if let Some((pos, c)) = text
+                .char_indices()
+                .find(|(_, c)| MacRomanMapping.encode(*c).is_none())
+            {
+                return Err((
+                    offset + pos..offset + pos + c.len_utf8(),
+                    format!("'{c}' cannot be represented in the Mac Roman encoding"),
+                ));
+            }
+        }
+        out.push_str(text);
+        Ok(())
+    };
+    let unpaired = |range: Range<usize>| (range, "unpaired UTF-16 surrogate".to_string());
+
+    while !rest.is_empty() {
+        let Some(pos) = rest.find('\\') else {
+            if let Some((_, range)) = high_surrogate {
+                return Err(unpaired(range));
+            }
+            push_text(&mut out, rest, offset)?;
+            break;
+        };
+        if pos > 0 {
+            if let Some((_, range)) = high_surrogate.take() {
+                return Err(unpaired(range));
+            }
+            push_text(&mut out, &rest[..pos], offset)?;
+        }
+
+        let escape_start = offset + pos;
+        let Some(digits) = rest.get(pos + 1..pos + 1 + escape_len) else {
+            return Err((escape_start..offset + rest.len(), length_err.into()));
+        };
+        if let Some(idx) = digits.bytes().position(|b| !b.is_ascii_hexdigit()) {
+            let digit_pos = escape_start + 1 + idx;
+            return Err((
+                digit_pos..digit_pos + 1,
+                format!(
+                    "invalid escape sequence: '{}' is not a hex digit",
+                    digits.as_bytes()[idx] as char
+                ),
+            ));
+        }
+        let escape_range = escape_start..escape_start + 1 + escape_len;
+        let value = u16::from_str_radix(digits, 16).unwrap();
+
+        if is_mac {
+            out.push(MacRomanMapping.decode(value as u8));
+        } else {
+            match (value, high_surrogate.take()) {
+                (0xDC00..=0xDFFF, Some((high, _))) => {
+                    let c = char::decode_utf16([high, value]).next().unwrap().unwrap();
+                    out.push(c);
+                }
+                (_, Some((_, range))) => return Err(unpaired(range)),
+                (0xD800..=0xDBFF, None) => high_surrogate = Some((value, escape_range)),
+                (0xDC00..=0xDFFF, None) => return Err(unpaired(escape_range)),
+                (_, None) => out.push(char::from_u32(value as u32).unwrap()),
+            }
+        }
+        offset += pos + 1 + escape_len;
+        rest = &rest[pos + 1 + escape_len..];
+    }
+
+    if let Some((_, range)) = high_surrogate {
+        return Err(unpaired(range));
+    }
+    Ok(out)
 }
 
 impl DecOctHex {
@@ -1543,6 +1744,10 @@ impl Parameters {
     pub(crate) fn range_end(&self) -> Option<FloatLike> {
         self.iter().filter_map(FloatLike::cast).nth(3)
     }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = FloatLike> + '_ {
+        self.iter().filter_map(FloatLike::cast)
+    }
 }
 
 impl FeatureRef {
@@ -1,9 +1,12 @@
 //! helpers and utilties (mostly for testing/debugging?)
 
+pub mod debug;
 pub(crate) mod highlighting;
 pub mod paths;
 #[cfg(any(test, feature = "diff"))]
 pub mod pretty_diff;
+#[cfg(feature = "snippets")]
+pub(crate) mod snippets;
 #[cfg(any(test, feature = "test"))]
 pub mod ttx;
 
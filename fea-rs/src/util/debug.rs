@@ -0,0 +1,1597 @@
+//! Rendering compiled tables in a human-readable form.
+//!
+//! The main entry point is [`to_ttx_xml`], which renders the GDEF, GPOS and
+//! GSUB tables of a font in an XML format modeled on that of fontTools' `ttx`
+//! tool. This makes it easier to compare our output with the output of feaLib
+//! without needing a python installation.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Write};
+
+use write_fonts::{
+    read::{
+        tables::{
+            gdef::{CaretValue, Gdef},
+            gpos::{
+                AnchorTable, CursivePosFormat1, Gpos, MarkArray, MarkBasePosFormat1,
+                MarkLigPosFormat1, MarkMarkPosFormat1, PairPos, PositionLookup, SinglePos,
+                ValueRecord,
+            },
+            gsub::{
+                AlternateSubstFormat1, Gsub, LigatureSubstFormat1, MultipleSubstFormat1,
+                ReverseChainSingleSubstFormat1, SingleSubst, SubstitutionLookup,
+            },
+            layout::{
+                ChainedSequenceContext, ClassDef, CoverageTable, Device, FeatureList,
+                FeatureParams, LangSys, Lookup, ScriptList, SequenceContext, SequenceLookupRecord,
+            },
+        },
+        FontData, FontRead, FontRef, ReadError, TableProvider,
+    },
+    types::{BigEndian, GlyphId},
+};
+
+use crate::{GlyphIdent, GlyphMap};
+
+static XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ttFont>\n";
+static INDENT: &str = "  ";
+
+/// Render the layout tables of a font in the fontTools TTX format.
+///
+/// `font_data` is a compiled font, such as the output of
+/// [`Compilation::assemble`], built with whatever options the caller uses.
+///
+/// The output uses the elements and attributes that `ttx -t GDEF -t GPOS -t GSUB`
+/// produces for a font containing the same tables, and the `ttFont` element
+/// has no attributes (matching the normalization performed by our ttx test
+/// harness.) It is not checked against the output of `ttx`, however, and may
+/// differ in details such as the formatting of numbers and the tables that
+/// fontTools adds to every font; treat it as a guide for comparison rather
+/// than as a drop-in replacement.
+///
+/// Glyphs are named using the provided glyph map; CIDs are named in the
+/// `cid01234` form used by fontTools.
+///
+/// [`Compilation::assemble`]: crate::compile::Compilation::assemble
+pub fn to_ttx_xml(font_data: &[u8], glyph_map: &GlyphMap) -> Result<String, ReadError> {
+    let font = FontRef::new(font_data)?;
+    let mut writer = TtxWriter::new(glyph_map, &font);
+    writer.write_font(&font)?;
+    Ok(writer.out)
+}
+
+/// Names for the various tables in a GSUB or GPOS contextual lookup.
+struct ContextNames {
+    subtable: &'static str,
+    rule_set: &'static str,
+    rule: &'static str,
+    class_set: &'static str,
+    class_rule: &'static str,
+    record: &'static str,
+    record_count: &'static str,
+}
+
+impl ContextNames {
+    const SUBST: ContextNames = ContextNames {
+        subtable: "ContextSubst",
+        rule_set: "SubRuleSet",
+        rule: "SubRule",
+        class_set: "SubClassSet",
+        class_rule: "SubClassRule",
+        record: "SubstLookupRecord",
+        record_count: "SubstCount",
+    };
+
+    const CHAIN_SUBST: ContextNames = ContextNames {
+        subtable: "ChainContextSubst",
+        rule_set: "ChainSubRuleSet",
+        rule: "ChainSubRule",
+        class_set: "ChainSubClassSet",
+        class_rule: "ChainSubClassRule",
+        record: "SubstLookupRecord",
+        record_count: "SubstCount",
+    };
+
+    const POS: ContextNames = ContextNames {
+        subtable: "ContextPos",
+        rule_set: "PosRuleSet",
+        rule: "PosRule",
+        class_set: "PosClassSet",
+        class_rule: "PosClassRule",
+        record: "PosLookupRecord",
+        record_count: "PosCount",
+    };
+
+    const CHAIN_POS: ContextNames = ContextNames {
+        subtable: "ChainContextPos",
+        rule_set: "ChainPosRuleSet",
+        rule: "ChainPosRule",
+        class_set: "ChainPosClassSet",
+        class_rule: "ChainPosClassRule",
+        record: "PosLookupRecord",
+        record_count: "PosCount",
+    };
+}
+
+struct TtxWriter {
+    out: String,
+    depth: usize,
+    glyph_names: BTreeMap<GlyphId, GlyphIdent>,
+    // used to annotate name ids, as ttx does
+    names: HashMap<u16, String>,
+}
+
+impl TtxWriter {
+    fn new(glyph_map: &GlyphMap, font: &FontRef) -> Self {
+        TtxWriter {
+            out: String::new(),
+            depth: 0,
+            glyph_names: glyph_map.reverse_map(),
+            names: debug_names(font),
+        }
+    }
+
+    fn write_font(&mut self, font: &FontRef) -> Result<(), ReadError> {
+        self.out.push_str(XML_HEADER);
+        self.depth = 1;
+        if let Some(gdef) = optional_table(font.gdef())? {
+            self.out.push('\n');
+            self.write_gdef(&gdef)?;
+        }
+        if let Some(gpos) = optional_table(font.gpos())? {
+            self.out.push('\n');
+            self.write_gpos(&gpos)?;
+        }
+        if let Some(gsub) = optional_table(font.gsub())? {
+            self.out.push('\n');
+            self.write_gsub(&gsub)?;
+        }
+        self.out.push_str("\n</ttFont>\n");
+        Ok(())
+    }
+
+    // xml primitives
+
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn write_tag(&mut self, name: &str, attrs: &[(&str, String)], close: bool) {
+        self.indent();
+        self.out.push('<');
+        self.out.push_str(name);
+        for (attr, value) in attrs {
+            write!(&mut self.out, " {}=\"{}\"", attr, escape(value)).unwrap();
+        }
+        self.out.push_str(if close { "/>" } else { ">" });
+    }
+
+    fn begin(&mut self, name: &str, attrs: &[(&str, String)]) {
+        self.write_tag(name, attrs, false);
+        self.out.push('\n');
+        self.depth += 1;
+    }
+
+    fn end(&mut self, name: &str) {
+        self.depth -= 1;
+        self.indent();
+        writeln!(&mut self.out, "</{name}>").unwrap();
+    }
+
+    fn simple(&mut self, name: &str, attrs: &[(&str, String)]) {
+        self.write_tag(name, attrs, true);
+        self.out.push('\n');
+    }
+
+    fn comment(&mut self, text: impl Display) {
+        self.indent();
+        writeln!(&mut self.out, "<!-- {text} -->").unwrap();
+    }
+
+    fn count(&mut self, name: &str, count: impl Display) {
+        self.comment(format_args!("{name}={count}"));
+    }
+
+    fn value(&mut self, name: &str, value: impl Display) {
+        self.simple(name, &[("value", value.to_string())]);
+    }
+
+    fn indexed_value(&mut self, name: &str, index: usize, value: impl Display) {
+        self.simple(
+            name,
+            &[("index", index.to_string()), ("value", value.to_string())],
+        );
+    }
+
+    /// Write a name id, followed by the name it refers to, if any.
+    fn name_id(&mut self, name: &str, id: u16) {
+        self.write_tag(name, &[("value", id.to_string())], true);
+        if id != 0 {
+            let debug_name = self
+                .names
+                .get(&id)
+                .map(String::as_str)
+                .unwrap_or("missing from name table");
+            write!(&mut self.out, "  <!-- {} -->", debug_name).unwrap();
+        }
+        self.out.push('\n');
+    }
+
+    /// Begin a table that is stored in an array; `index` is the position in the array.
+    fn begin_indexed(&mut self, name: &str, index: usize) {
+        self.begin(name, &[("index", index.to_string())]);
+    }
+
+    /// Begin a format-switching table, optionally stored in an array.
+    fn begin_format(&mut self, name: &str, index: Option<usize>, format: u16) {
+        let mut attrs = Vec::new();
+        if let Some(index) = index {
+            attrs.push(("index", index.to_string()));
+        }
+        attrs.push(("Format", format.to_string()));
+        self.begin(name, &attrs);
+    }
+
+    /// A null offset in an array of offsets.
+    fn empty(&mut self, name: &str, index: usize) {
+        self.simple(name, &[("index", index.to_string()), ("empty", "1".into())]);
+    }
+
+    fn glyph_name(&self, gid: GlyphId) -> String {
+        match self.glyph_names.get(&gid) {
+            Some(GlyphIdent::Name(name)) => name.to_string(),
+            Some(GlyphIdent::Cid(cid)) => format!("cid{cid:05}"),
+            None => format!("glyph{:05}", gid.to_u16()),
+        }
+    }
+
+    fn glyph_list(&self, glyphs: &[BigEndian<GlyphId>]) -> String {
+        let names = glyphs
+            .iter()
+            .map(|gid| self.glyph_name(gid.get()))
+            .collect::<Vec<_>>();
+        names.join(",")
+    }
+
+    // shared layout tables
+
+    fn write_script_list(&mut self, list: &ScriptList) -> Result<(), ReadError> {
+        self.begin("ScriptList", &[]);
+        self.count("ScriptCount", list.script_count());
+        for (i, record) in list.script_records().iter().enumerate() {
+            self.begin_indexed("ScriptRecord", i);
+            self.value("ScriptTag", record.script_tag());
+            let script = record.script(list.offset_data())?;
+            self.begin("Script", &[]);
+            if let Some(lang_sys) = script.default_lang_sys().transpose()? {
+                self.write_lang_sys("DefaultLangSys", &lang_sys);
+            }
+            self.count("LangSysCount", script.lang_sys_count());
+            for (j, record) in script.lang_sys_records().iter().enumerate() {
+                self.begin_indexed("LangSysRecord", j);
+                self.value("LangSysTag", record.lang_sys_tag());
+                let lang_sys = record.lang_sys(script.offset_data())?;
+                self.write_lang_sys("LangSys", &lang_sys);
+                self.end("LangSysRecord");
+            }
+            self.end("Script");
+            self.end("ScriptRecord");
+        }
+        self.end("ScriptList");
+        Ok(())
+    }
+
+    fn write_lang_sys(&mut self, name: &str, lang_sys: &LangSys) {
+        self.begin(name, &[]);
+        self.value("ReqFeatureIndex", lang_sys.required_feature_index());
+        self.count("FeatureCount", lang_sys.feature_index_count());
+        for (i, idx) in lang_sys.feature_indices().iter().enumerate() {
+            self.indexed_value("FeatureIndex", i, idx.get());
+        }
+        self.end(name);
+    }
+
+    fn write_feature_list(&mut self, list: &FeatureList) -> Result<(), ReadError> {
+        self.begin("FeatureList", &[]);
+        self.count("FeatureCount", list.feature_count());
+        for (i, record) in list.feature_records().iter().enumerate() {
+            self.begin_indexed("FeatureRecord", i);
+            self.value("FeatureTag", record.feature_tag());
+            let feature = record.feature(list.offset_data())?;
+            self.begin("Feature", &[]);
+            if let Some(params) = feature.feature_params().transpose()? {
+                self.write_feature_params(&params);
+            }
+            self.count("LookupCount", feature.lookup_index_count());
+            for (j, idx) in feature.lookup_list_indices().iter().enumerate() {
+                self.indexed_value("LookupListIndex", j, idx.get());
+            }
+            self.end("Feature");
+            self.end("FeatureRecord");
+        }
+        self.end("FeatureList");
+        Ok(())
+    }
+
+    fn write_feature_params(&mut self, params: &FeatureParams) {
+        // sizes are in decipoints, and ttx prints them as floats
+        let decipoints = |val: u16| format!("{:?}", val as f64 / 10.0);
+        match params {
+            FeatureParams::Size(size) => {
+                self.begin("FeatureParamsSize", &[]);
+                self.value("DesignSize", decipoints(size.design_size()));
+                self.value("SubfamilyID", size.identifier());
+                self.name_id("SubfamilyNameID", size.name_entry());
+                self.value("RangeStart", decipoints(size.range_start()));
+                self.value("RangeEnd", decipoints(size.range_end()));
+                self.end("FeatureParamsSize");
+            }
+            FeatureParams::StylisticSet(ss) => {
+                self.begin("FeatureParamsStylisticSet", &[]);
+                self.value("Version", ss.version());
+                self.name_id("UINameID", ss.ui_name_id().to_u16());
+                self.end("FeatureParamsStylisticSet");
+            }
+            FeatureParams::CharacterVariant(cv) => {
+                self.begin("FeatureParamsCharacterVariants", &[]);
+                self.value("Format", cv.format());
+                self.name_id("FeatUILabelNameID", cv.feat_ui_label_name_id().to_u16());
+                self.name_id(
+                    "FeatUITooltipTextNameID",
+                    cv.feat_ui_tooltip_text_name_id().to_u16(),
+                );
+                self.name_id("SampleTextNameID", cv.sample_text_name_id().to_u16());
+                self.value("NumNamedParameters", cv.num_named_parameters());
+                self.name_id(
+                    "FirstParamUILabelNameID",
+                    cv.first_param_ui_label_name_id().to_u16(),
+                );
+                self.count("CharCount", cv.char_count());
+                for (i, c) in cv.character().iter().enumerate() {
+                    self.indexed_value("Character", i, c.get().to_u32());
+                }
+                self.end("FeatureParamsCharacterVariants");
+            }
+        }
+    }
+
+    /// Write the fields common to all lookups, calling `write_subtable` for each subtable.
+    fn write_lookup<'a, T: FontRead<'a>>(
+        &mut self,
+        lookup: &Lookup<'a, T>,
+        mut write_subtable: impl FnMut(&mut Self, usize, &T) -> Result<(), ReadError>,
+    ) -> Result<(), ReadError> {
+        self.value("LookupType", lookup.lookup_type());
+        let flag = lookup.lookup_flag().to_bits();
+        self.write_lookup_flag(flag);
+        self.count("SubTableCount", lookup.sub_table_count());
+        for (i, subtable) in lookup.subtables().enumerate() {
+            write_subtable(self, i, &subtable?)?;
+        }
+        if flag & 0x10 != 0 {
+            self.value("MarkFilteringSet", lookup.mark_filtering_set());
+        }
+        Ok(())
+    }
+
+    fn write_lookup_flag(&mut self, flag: u16) {
+        self.write_tag("LookupFlag", &[("value", flag.to_string())], true);
+        let mut flags = Vec::new();
+        for (bit, name) in [
+            (0x01, "rightToLeft"),
+            (0x02, "ignoreBaseGlyphs"),
+            (0x04, "ignoreLigatures"),
+            (0x08, "ignoreMarks"),
+            (0x10, "useMarkFilteringSet"),
+        ] {
+            if flag & bit != 0 {
+                flags.push(name.to_string());
+            }
+        }
+        if flag & 0xFF00 != 0 {
+            flags.push(format!("markAttachmentType[{}]", flag >> 8));
+        }
+        if !flags.is_empty() {
+            write!(&mut self.out, "<!-- {} -->", flags.join(" ")).unwrap();
+        }
+        self.out.push('\n');
+    }
+
+    fn write_coverage(&mut self, name: &str, index: Option<usize>, coverage: &CoverageTable) {
+        match index {
+            Some(index) => self.begin_indexed(name, index),
+            None => self.begin(name, &[]),
+        }
+        for gid in coverage.iter() {
+            let glyph = self.glyph_name(gid);
+            self.value("Glyph", glyph);
+        }
+        self.end(name);
+    }
+
+    fn write_class_def(&mut self, name: &str, class_def: &ClassDef) {
+        let mut classes = Vec::new();
+        match class_def {
+            ClassDef::Format1(table) => {
+                let start = table.start_glyph_id().to_u16();
+                for (i, class) in table.class_value_array().iter().enumerate() {
+                    let gid = GlyphId::new(start + i as u16);
+                    classes.push((self.glyph_name(gid), class.get()));
+                }
+            }
+            ClassDef::Format2(table) => {
+                for record in table.class_range_records() {
+                    for gid in record.start_glyph_id().to_u16()..=record.end_glyph_id().to_u16() {
+                        classes.push((self.glyph_name(GlyphId::new(gid)), record.class()));
+                    }
+                }
+            }
+        }
+        classes.sort();
+        self.begin(name, &[]);
+        for (glyph, class) in classes.into_iter().filter(|(_, class)| *class != 0) {
+            self.simple(
+                "ClassDef",
+                &[("glyph", glyph), ("class", class.to_string())],
+            );
+        }
+        self.end(name);
+    }
+
+    fn write_device(&mut self, name: &str, device: &Device) {
+        self.begin(name, &[]);
+        self.value("StartSize", device.start_size());
+        self.value("EndSize", device.end_size());
+        let format = device.delta_format() as u16;
+        self.value("DeltaFormat", format);
+        let deltas = decode_deltas(device, format);
+        self.value("DeltaValue", format_args!("{deltas:?}"));
+        self.end(name);
+    }
+
+    fn write_anchor(
+        &mut self,
+        name: &str,
+        index: Option<usize>,
+        anchor: &AnchorTable,
+    ) -> Result<(), ReadError> {
+        match anchor {
+            AnchorTable::Format1(table) => {
+                self.begin_format(name, index, 1);
+                self.value("XCoordinate", table.x_coordinate());
+                self.value("YCoordinate", table.y_coordinate());
+            }
+            AnchorTable::Format2(table) => {
+                self.begin_format(name, index, 2);
+                self.value("XCoordinate", table.x_coordinate());
+                self.value("YCoordinate", table.y_coordinate());
+                self.value("AnchorPoint", table.anchor_point());
+            }
+            AnchorTable::Format3(table) => {
+                self.begin_format(name, index, 3);
+                self.value("XCoordinate", table.x_coordinate());
+                self.value("YCoordinate", table.y_coordinate());
+                if let Some(device) = table.x_device().transpose()? {
+                    self.write_device("XDeviceTable", &device);
+                }
+                if let Some(device) = table.y_device().transpose()? {
+                    self.write_device("YDeviceTable", &device);
+                }
+            }
+        }
+        self.end(name);
+        Ok(())
+    }
+
+    /// Write an array of optional anchors, as found in base, ligature and mark2 records.
+    fn write_anchor_array<'a>(
+        &mut self,
+        name: &str,
+        anchors: impl Iterator<Item = Option<Result<AnchorTable<'a>, ReadError>>>,
+    ) -> Result<(), ReadError> {
+        for (i, anchor) in anchors.enumerate() {
+            match anchor.transpose()? {
+                Some(anchor) => self.write_anchor(name, Some(i), &anchor)?,
+                None => self.empty(name, i),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_lookup_records(&mut self, names: &ContextNames, records: &[SequenceLookupRecord]) {
+        for (i, record) in records.iter().enumerate() {
+            self.begin_indexed(names.record, i);
+            self.value("SequenceIndex", record.sequence_index());
+            self.value("LookupListIndex", record.lookup_list_index());
+            self.end(names.record);
+        }
+    }
+
+    fn write_sequence_context(
+        &mut self,
+        names: &ContextNames,
+        index: usize,
+        context: &SequenceContext,
+    ) -> Result<(), ReadError> {
+        match context {
+            SequenceContext::Format1(table) => {
+                self.begin_format(names.subtable, Some(index), 1);
+                self.write_coverage("Coverage", None, &table.coverage()?);
+                self.count(
+                    &format!("{}Count", names.rule_set),
+                    table.seq_rule_set_count(),
+                );
+                for (i, rule_set) in table.seq_rule_sets().enumerate() {
+                    let Some(rule_set) = rule_set.transpose()? else {
+                        self.empty(names.rule_set, i);
+                        continue;
+                    };
+                    self.begin_indexed(names.rule_set, i);
+                    self.count(&format!("{}Count", names.rule), rule_set.seq_rule_count());
+                    for (j, rule) in rule_set.seq_rules().enumerate() {
+                        let rule = rule?;
+                        self.begin_indexed(names.rule, j);
+                        self.count("GlyphCount", rule.glyph_count());
+                        self.count(names.record_count, rule.seq_lookup_count());
+                        for (k, gid) in rule.input_sequence().iter().enumerate() {
+                            let glyph = self.glyph_name(gid.get());
+                            self.indexed_value("Input", k, glyph);
+                        }
+                        self.write_lookup_records(names, rule.seq_lookup_records());
+                        self.end(names.rule);
+                    }
+                    self.end(names.rule_set);
+                }
+            }
+            SequenceContext::Format2(table) => {
+                self.begin_format(names.subtable, Some(index), 2);
+                self.write_coverage("Coverage", None, &table.coverage()?);
+                self.write_class_def("ClassDef", &table.class_def()?);
+                self.count(
+                    &format!("{}Count", names.class_set),
+                    table.class_seq_rule_set_count(),
+                );
+                for (i, rule_set) in table.class_seq_rule_sets().enumerate() {
+                    let Some(rule_set) = rule_set.transpose()? else {
+                        self.empty(names.class_set, i);
+                        continue;
+                    };
+                    self.begin_indexed(names.class_set, i);
+                    self.count(
+                        &format!("{}Count", names.class_rule),
+                        rule_set.class_seq_rule_count(),
+                    );
+                    for (j, rule) in rule_set.class_seq_rules().enumerate() {
+                        let rule = rule?;
+                        self.begin_indexed(names.class_rule, j);
+                        self.count("GlyphCount", rule.glyph_count());
+                        self.count(names.record_count, rule.seq_lookup_count());
+                        for (k, class) in rule.input_sequence().iter().enumerate() {
+                            self.indexed_value("Class", k, class.get());
+                        }
+                        self.write_lookup_records(names, rule.seq_lookup_records());
+                        self.end(names.class_rule);
+                    }
+                    self.end(names.class_set);
+                }
+            }
+            SequenceContext::Format3(table) => {
+                self.begin_format(names.subtable, Some(index), 3);
+                self.count("GlyphCount", table.glyph_count());
+                self.count(names.record_count, table.seq_lookup_count());
+                for (i, coverage) in table.coverages().enumerate() {
+                    self.write_coverage("Coverage", Some(i), &coverage?);
+                }
+                self.write_lookup_records(names, table.seq_lookup_records());
+            }
+        }
+        self.end(names.subtable);
+        Ok(())
+    }
+
+    fn write_chained_sequence_context(
+        &mut self,
+        names: &ContextNames,
+        index: usize,
+        context: &ChainedSequenceContext,
+    ) -> Result<(), ReadError> {
+        match context {
+            ChainedSequenceContext::Format1(table) => {
+                self.begin_format(names.subtable, Some(index), 1);
+                self.write_coverage("Coverage", None, &table.coverage()?);
+                self.count(
+                    &format!("{}Count", names.rule_set),
+                    table.chained_seq_rule_set_count(),
+                );
+                for (i, rule_set) in table.chained_seq_rule_sets().enumerate() {
+                    let Some(rule_set) = rule_set.transpose()? else {
+                        self.empty(names.rule_set, i);
+                        continue;
+                    };
+                    self.begin_indexed(names.rule_set, i);
+                    self.count(
+                        &format!("{}Count", names.rule),
+                        rule_set.chained_seq_rule_count(),
+                    );
+                    for (j, rule) in rule_set.chained_seq_rules().enumerate() {
+                        let rule = rule?;
+                        self.begin_indexed(names.rule, j);
+                        for (name, count_name, count, glyphs) in [
+                            (
+                                "Backtrack",
+                                "BacktrackGlyphCount",
+                                rule.backtrack_glyph_count(),
+                                rule.backtrack_sequence(),
+                            ),
+                            (
+                                "Input",
+                                "InputGlyphCount",
+                                rule.input_glyph_count(),
+                                rule.input_sequence(),
+                            ),
+                            (
+                                "LookAhead",
+                                "LookAheadGlyphCount",
+                                rule.lookahead_glyph_count(),
+                                rule.lookahead_sequence(),
+                            ),
+                        ] {
+                            self.count(count_name, count);
+                            for (k, gid) in glyphs.iter().enumerate() {
+                                let glyph = self.glyph_name(gid.get());
+                                self.indexed_value(name, k, glyph);
+                            }
+                        }
+                        self.count(names.record_count, rule.seq_lookup_count());
+                        self.write_lookup_records(names, rule.seq_lookup_records());
+                        self.end(names.rule);
+                    }
+                    self.end(names.rule_set);
+                }
+            }
+            ChainedSequenceContext::Format2(table) => {
+                self.begin_format(names.subtable, Some(index), 2);
+                self.write_coverage("Coverage", None, &table.coverage()?);
+                self.write_class_def("BacktrackClassDef", &table.backtrack_class_def()?);
+                self.write_class_def("InputClassDef", &table.input_class_def()?);
+                self.write_class_def("LookAheadClassDef", &table.lookahead_class_def()?);
+                self.count(
+                    &format!("{}Count", names.class_set),
+                    table.chained_class_seq_rule_set_count(),
+                );
+                for (i, rule_set) in table.chained_class_seq_rule_sets().enumerate() {
+                    let Some(rule_set) = rule_set.transpose()? else {
+                        self.empty(names.class_set, i);
+                        continue;
+                    };
+                    self.begin_indexed(names.class_set, i);
+                    self.count(
+                        &format!("{}Count", names.class_rule),
+                        rule_set.chained_class_seq_rule_count(),
+                    );
+                    for (j, rule) in rule_set.chained_class_seq_rules().enumerate() {
+                        let rule = rule?;
+                        self.begin_indexed(names.class_rule, j);
+                        for (name, count_name, count, classes) in [
+                            (
+                                "Backtrack",
+                                "BacktrackGlyphCount",
+                                rule.backtrack_glyph_count(),
+                                rule.backtrack_sequence(),
+                            ),
+                            (
+                                "Input",
+                                "InputGlyphCount",
+                                rule.input_glyph_count(),
+                                rule.input_sequence(),
+                            ),
+                            (
+                                "LookAhead",
+                                "LookAheadGlyphCount",
+                                rule.lookahead_glyph_count(),
+                                rule.lookahead_sequence(),
+                            ),
+                        ] {
+                            self.count(count_name, count);
+                            for (k, class) in classes.iter().enumerate() {
+                                self.indexed_value(name, k, class.get());
+                            }
+                        }
+                        self.count(names.record_count, rule.seq_lookup_count());
+                        self.write_lookup_records(names, rule.seq_lookup_records());
+                        self.end(names.class_rule);
+                    }
+                    self.end(names.class_set);
+                }
+            }
+            ChainedSequenceContext::Format3(table) => {
+                self.begin_format(names.subtable, Some(index), 3);
+                self.count("BacktrackGlyphCount", table.backtrack_glyph_count());
+                for (i, coverage) in table.backtrack_coverages().enumerate() {
+                    self.write_coverage("BacktrackCoverage", Some(i), &coverage?);
+                }
+                self.count("InputGlyphCount", table.input_glyph_count());
+                for (i, coverage) in table.input_coverages().enumerate() {
+                    self.write_coverage("InputCoverage", Some(i), &coverage?);
+                }
+                self.count("LookAheadGlyphCount", table.lookahead_glyph_count());
+                for (i, coverage) in table.lookahead_coverages().enumerate() {
+                    self.write_coverage("LookAheadCoverage", Some(i), &coverage?);
+                }
+                self.count(names.record_count, table.seq_lookup_count());
+                self.write_lookup_records(names, table.seq_lookup_records());
+            }
+        }
+        self.end(names.subtable);
+        Ok(())
+    }
+
+    // GDEF
+
+    fn write_gdef(&mut self, gdef: &Gdef) -> Result<(), ReadError> {
+        self.begin("GDEF", &[]);
+        let version = gdef.version();
+        self.value(
+            "Version",
+            format_args!("0x{:04x}{:04x}", version.major, version.minor),
+        );
+        if let Some(class_def) = gdef.glyph_class_def().transpose()? {
+            self.write_class_def("GlyphClassDef", &class_def);
+        }
+        if let Some(attach_list) = gdef.attach_list().transpose()? {
+            self.begin("AttachList", &[]);
+            self.write_coverage("Coverage", None, &attach_list.coverage()?);
+            self.count("GlyphCount", attach_list.glyph_count());
+            for (i, point) in attach_list.attach_points().enumerate() {
+                let point = point?;
+                self.begin_indexed("AttachPoint", i);
+                self.count("PointCount", point.point_count());
+                for (j, idx) in point.point_indices().iter().enumerate() {
+                    self.indexed_value("PointIndex", j, idx.get());
+                }
+                self.end("AttachPoint");
+            }
+            self.end("AttachList");
+        }
+        if let Some(lig_caret_list) = gdef.lig_caret_list().transpose()? {
+            self.begin("LigCaretList", &[]);
+            self.write_coverage("Coverage", None, &lig_caret_list.coverage()?);
+            self.count("LigGlyphCount", lig_caret_list.lig_glyph_count());
+            for (i, lig_glyph) in lig_caret_list.lig_glyphs().enumerate() {
+                let lig_glyph = lig_glyph?;
+                self.begin_indexed("LigGlyph", i);
+                self.count("CaretCount", lig_glyph.caret_count());
+                for (j, caret) in lig_glyph.caret_values().enumerate() {
+                    match caret? {
+                        CaretValue::Format1(caret) => {
+                            self.begin_format("CaretValue", Some(j), 1);
+                            self.value("Coordinate", caret.coordinate());
+                        }
+                        CaretValue::Format2(caret) => {
+                            self.begin_format("CaretValue", Some(j), 2);
+                            self.value("CaretValuePoint", caret.caret_value_point_index());
+                        }
+                        CaretValue::Format3(caret) => {
+                            self.begin_format("CaretValue", Some(j), 3);
+                            self.value("Coordinate", caret.coordinate());
+                            self.write_device("DeviceTable", &caret.device()?);
+                        }
+                    }
+                    self.end("CaretValue");
+                }
+                self.end("LigGlyph");
+            }
+            self.end("LigCaretList");
+        }
+        if let Some(class_def) = gdef.mark_attach_class_def().transpose()? {
+            self.write_class_def("MarkAttachClassDef", &class_def);
+        }
+        if let Some(mark_sets) = gdef.mark_glyph_sets_def().transpose()? {
+            self.begin("MarkGlyphSetsDef", &[]);
+            self.value("MarkSetTableFormat", mark_sets.format());
+            self.count("MarkSetCount", mark_sets.mark_glyph_set_count());
+            for (i, coverage) in mark_sets.coverages().enumerate() {
+                self.write_coverage("Coverage", Some(i), &coverage?);
+            }
+            self.end("MarkGlyphSetsDef");
+        }
+        self.end("GDEF");
+        Ok(())
+    }
+
+    // GSUB
+
+    fn write_gsub(&mut self, gsub: &Gsub) -> Result<(), ReadError> {
+        self.begin("GSUB", &[]);
+        let version = gsub.version();
+        self.value(
+            "Version",
+            format_args!("0x{:04x}{:04x}", version.major, version.minor),
+        );
+        self.write_script_list(&gsub.script_list()?)?;
+        self.write_feature_list(&gsub.feature_list()?)?;
+        let lookup_list = gsub.lookup_list()?;
+        self.begin("LookupList", &[]);
+        self.count("LookupCount", lookup_list.lookup_count());
+        for (i, lookup) in lookup_list.lookups().enumerate() {
+            self.begin_indexed("Lookup", i);
+            match lookup? {
+                SubstitutionLookup::Single(lookup) => {
+                    self.write_lookup(&lookup, Self::write_single_subst)?
+                }
+                SubstitutionLookup::Multiple(lookup) => {
+                    self.write_lookup(&lookup, Self::write_multiple_subst)?
+                }
+                SubstitutionLookup::Alternate(lookup) => {
+                    self.write_lookup(&lookup, Self::write_alternate_subst)?
+                }
+                SubstitutionLookup::Ligature(lookup) => {
+                    self.write_lookup(&lookup, Self::write_ligature_subst)?
+                }
+                SubstitutionLookup::Contextual(lookup) => self
+                    .write_lookup(&lookup, |this, i, sub| {
+                        this.write_sequence_context(&ContextNames::SUBST, i, sub)
+                    })?,
+                SubstitutionLookup::ChainContextual(lookup) => {
+                    self.write_lookup(&lookup, |this, i, sub| {
+                        this.write_chained_sequence_context(&ContextNames::CHAIN_SUBST, i, sub)
+                    })?
+                }
+                SubstitutionLookup::Reverse(lookup) => {
+                    self.write_lookup(&lookup, Self::write_reverse_chain_subst)?
+                }
+                // we never generate extension lookups
+                SubstitutionLookup::Extension(_) => {
+                    self.comment("extension lookups are not supported")
+                }
+            }
+            self.end("Lookup");
+        }
+        self.end("LookupList");
+        self.end("GSUB");
+        Ok(())
+    }
+
+    fn write_single_subst(
+        &mut self,
+        index: usize,
+        subtable: &SingleSubst,
+    ) -> Result<(), ReadError> {
+        let pairs: Vec<_> = match subtable {
+            SingleSubst::Format1(table) => {
+                let delta = table.delta_glyph_id();
+                table
+                    .coverage()?
+                    .iter()
+                    .map(|gid| (gid, GlyphId::new(gid.to_u16().wrapping_add(delta as u16))))
+                    .collect()
+            }
+            SingleSubst::Format2(table) => table
+                .coverage()?
+                .iter()
+                .zip(table.substitute_glyph_ids())
+                .map(|(gid, sub)| (gid, sub.get()))
+                .collect(),
+        };
+        let mut mapping = pairs
+            .into_iter()
+            .map(|(input, output)| (self.glyph_name(input), self.glyph_name(output)))
+            .collect::<Vec<_>>();
+        mapping.sort();
+        self.begin_indexed("SingleSubst", index);
+        for (input, output) in mapping {
+            self.simple("Substitution", &[("in", input), ("out", output)]);
+        }
+        self.end("SingleSubst");
+        Ok(())
+    }
+
+    fn write_multiple_subst(
+        &mut self,
+        index: usize,
+        subtable: &MultipleSubstFormat1,
+    ) -> Result<(), ReadError> {
+        let mut mapping = Vec::new();
+        for (gid, sequence) in subtable.coverage()?.iter().zip(subtable.sequences()) {
+            let output = self.glyph_list(sequence?.substitute_glyph_ids());
+            mapping.push((self.glyph_name(gid), output));
+        }
+        mapping.sort();
+        self.begin_indexed("MultipleSubst", index);
+        for (input, output) in mapping {
+            self.simple("Substitution", &[("in", input), ("out", output)]);
+        }
+        self.end("MultipleSubst");
+        Ok(())
+    }
+
+    fn write_alternate_subst(
+        &mut self,
+        index: usize,
+        subtable: &AlternateSubstFormat1,
+    ) -> Result<(), ReadError> {
+        let mut mapping = Vec::new();
+        for (gid, alt_set) in subtable.coverage()?.iter().zip(subtable.alternate_sets()) {
+            let alternates = alt_set?
+                .alternate_glyph_ids()
+                .iter()
+                .map(|alt| self.glyph_name(alt.get()))
+                .collect::<Vec<_>>();
+            mapping.push((self.glyph_name(gid), alternates));
+        }
+        mapping.sort();
+        self.begin_indexed("AlternateSubst", index);
+        for (glyph, alternates) in mapping {
+            self.begin("AlternateSet", &[("glyph", glyph)]);
+            for alt in alternates {
+                self.simple("Alternate", &[("glyph", alt)]);
+            }
+            self.end("AlternateSet");
+        }
+        self.end("AlternateSubst");
+        Ok(())
+    }
+
+    fn write_ligature_subst(
+        &mut self,
+        index: usize,
+        subtable: &LigatureSubstFormat1,
+    ) -> Result<(), ReadError> {
+        let mut mapping = Vec::new();
+        for (gid, lig_set) in subtable.coverage()?.iter().zip(subtable.ligature_sets()) {
+            let mut ligatures = Vec::new();
+            for ligature in lig_set?.ligatures() {
+                let ligature = ligature?;
+                let components = self.glyph_list(ligature.component_glyph_ids());
+                ligatures.push((components, self.glyph_name(ligature.ligature_glyph())));
+            }
+            mapping.push((self.glyph_name(gid), ligatures));
+        }
+        mapping.sort_by(|a, b| a.0.cmp(&b.0));
+        self.begin_indexed("LigatureSubst", index);
+        for (glyph, ligatures) in mapping {
+            self.begin("LigatureSet", &[("glyph", glyph)]);
+            for (components, lig_glyph) in ligatures {
+                self.simple(
+                    "Ligature",
+                    &[("components", components), ("glyph", lig_glyph)],
+                );
+            }
+            self.end("LigatureSet");
+        }
+        self.end("LigatureSubst");
+        Ok(())
+    }
+
+    fn write_reverse_chain_subst(
+        &mut self,
+        index: usize,
+        subtable: &ReverseChainSingleSubstFormat1,
+    ) -> Result<(), ReadError> {
+        self.begin_format("ReverseChainSingleSubst", Some(index), 1);
+        self.write_coverage("Coverage", None, &subtable.coverage()?);
+        self.count("BacktrackGlyphCount", subtable.backtrack_glyph_count());
+        for (i, coverage) in subtable.backtrack_coverages().enumerate() {
+            self.write_coverage("BacktrackCoverage", Some(i), &coverage?);
+        }
+        self.count("LookAheadGlyphCount", subtable.lookahead_glyph_count());
+        for (i, coverage) in subtable.lookahead_coverages().enumerate() {
+            self.write_coverage("LookAheadCoverage", Some(i), &coverage?);
+        }
+        self.count("GlyphCount", subtable.glyph_count());
+        for (i, gid) in subtable.substitute_glyph_ids().iter().enumerate() {
+            let glyph = self.glyph_name(gid.get());
+            self.indexed_value("Substitute", i, glyph);
+        }
+        self.end("ReverseChainSingleSubst");
+        Ok(())
+    }
+
+    // GPOS
+
+    fn write_gpos(&mut self, gpos: &Gpos) -> Result<(), ReadError> {
+        self.begin("GPOS", &[]);
+        let version = gpos.version();
+        self.value(
+            "Version",
+            format_args!("0x{:04x}{:04x}", version.major, version.minor),
+        );
+        self.write_script_list(&gpos.script_list()?)?;
+        self.write_feature_list(&gpos.feature_list()?)?;
+        let lookup_list = gpos.lookup_list()?;
+        self.begin("LookupList", &[]);
+        self.count("LookupCount", lookup_list.lookup_count());
+        for (i, lookup) in lookup_list.lookups().enumerate() {
+            self.begin_indexed("Lookup", i);
+            match lookup? {
+                PositionLookup::Single(lookup) => {
+                    self.write_lookup(&lookup, Self::write_single_pos)?
+                }
+                PositionLookup::Pair(lookup) => self.write_lookup(&lookup, Self::write_pair_pos)?,
+                PositionLookup::Cursive(lookup) => {
+                    self.write_lookup(&lookup, Self::write_cursive_pos)?
+                }
+                PositionLookup::MarkToBase(lookup) => {
+                    self.write_lookup(&lookup, Self::write_mark_base_pos)?
+                }
+                PositionLookup::MarkToLig(lookup) => {
+                    self.write_lookup(&lookup, Self::write_mark_lig_pos)?
+                }
+                PositionLookup::MarkToMark(lookup) => {
+                    self.write_lookup(&lookup, Self::write_mark_mark_pos)?
+                }
+                PositionLookup::Contextual(lookup) => self
+                    .write_lookup(&lookup, |this, i, sub| {
+                        this.write_sequence_context(&ContextNames::POS, i, sub)
+                    })?,
+                PositionLookup::ChainContextual(lookup) => {
+                    self.write_lookup(&lookup, |this, i, sub| {
+                        this.write_chained_sequence_context(&ContextNames::CHAIN_POS, i, sub)
+                    })?
+                }
+                // we never generate extension lookups
+                PositionLookup::Extension(_) => self.comment("extension lookups are not supported"),
+            }
+            self.end("Lookup");
+        }
+        self.end("LookupList");
+        self.end("GPOS");
+        Ok(())
+    }
+
+    /// Write a value record.
+    ///
+    /// `data` is the data of the subtable that contains the record, which is
+    /// where any device offsets are relative to.
+    fn write_value_record(
+        &mut self,
+        name: &str,
+        index: Option<usize>,
+        record: &ValueRecord,
+        data: FontData,
+    ) -> Result<(), ReadError> {
+        let mut attrs = Vec::new();
+        if let Some(index) = index {
+            attrs.push(("index", index.to_string()));
+        }
+        for (attr, value) in [
+            ("XPlacement", record.x_placement()),
+            ("YPlacement", record.y_placement()),
+            ("XAdvance", record.x_advance()),
+            ("YAdvance", record.y_advance()),
+        ] {
+            if let Some(value) = value {
+                attrs.push((attr, value.to_string()));
+            }
+        }
+
+        let mut devices = Vec::new();
+        for (device_name, offset, device) in [
+            (
+                "XPlaDevice",
+                record.x_placement_device,
+                record.x_placement_device(data),
+            ),
+            (
+                "YPlaDevice",
+                record.y_placement_device,
+                record.y_placement_device(data),
+            ),
+            (
+                "XAdvDevice",
+                record.x_advance_device,
+                record.x_advance_device(data),
+            ),
+            (
+                "YAdvDevice",
+                record.y_advance_device,
+                record.y_advance_device(data),
+            ),
+        ] {
+            // a null device offset is not the same as a missing one
+            let is_null = offset.map(|off| off.get().to_u32() == 0).unwrap_or(true);
+            if let (false, Some(device)) = (is_null, device) {
+                devices.push((device_name, device?));
+            }
+        }
+
+        if devices.is_empty() {
+            self.simple(name, &attrs);
+        } else {
+            self.begin(name, &attrs);
+            for (device_name, device) in devices {
+                self.write_device(device_name, &device);
+            }
+            self.end(name);
+        }
+        Ok(())
+    }
+
+    fn write_single_pos(&mut self, index: usize, subtable: &SinglePos) -> Result<(), ReadError> {
+        match subtable {
+            SinglePos::Format1(table) => {
+                self.begin_format("SinglePos", Some(index), 1);
+                self.write_coverage("Coverage", None, &table.coverage()?);
+                let format = table.value_format();
+                self.value("ValueFormat", format.bits());
+                if !format.is_empty() {
+                    self.write_value_record(
+                        "Value",
+                        None,
+                        &table.value_record(),
+                        table.offset_data(),
+                    )?;
+                }
+            }
+            SinglePos::Format2(table) => {
+                self.begin_format("SinglePos", Some(index), 2);
+                self.write_coverage("Coverage", None, &table.coverage()?);
+                self.value("ValueFormat", table.value_format().bits());
+                self.count("ValueCount", table.value_count());
+                for (i, record) in table.value_records().iter().enumerate() {
+                    self.write_value_record("Value", Some(i), &record?, table.offset_data())?;
+                }
+            }
+        }
+        self.end("SinglePos");
+        Ok(())
+    }
+
+    fn write_pair_pos(&mut self, index: usize, subtable: &PairPos) -> Result<(), ReadError> {
+        match subtable {
+            PairPos::Format1(table) => {
+                self.begin_format("PairPos", Some(index), 1);
+                self.write_coverage("Coverage", None, &table.coverage()?);
+                let format1 = table.value_format1();
+                let format2 = table.value_format2();
+                self.value("ValueFormat1", format1.bits());
+                self.value("ValueFormat2", format2.bits());
+                self.count("PairSetCount", table.pair_set_count());
+                for (i, pair_set) in table.pair_sets().enumerate() {
+                    let pair_set = pair_set?;
+                    self.begin_indexed("PairSet", i);
+                    self.count("PairValueCount", pair_set.pair_value_count());
+                    for (j, record) in pair_set.pair_value_records().iter().enumerate() {
+                        let record = record?;
+                        self.begin_indexed("PairValueRecord", j);
+                        let glyph = self.glyph_name(record.second_glyph());
+                        self.value("SecondGlyph", glyph);
+                        if !format1.is_empty() {
+                            self.write_value_record(
+                                "Value1",
+                                None,
+                                record.value_record1(),
+                                table.offset_data(),
+                            )?;
+                        }
+                        if !format2.is_empty() {
+                            self.write_value_record(
+                                "Value2",
+                                None,
+                                record.value_record2(),
+                                table.offset_data(),
+                            )?;
+                        }
+                        self.end("PairValueRecord");
+                    }
+                    self.end("PairSet");
+                }
+            }
+            PairPos::Format2(table) => {
+                self.begin_format("PairPos", Some(index), 2);
+                self.write_coverage("Coverage", None, &table.coverage()?);
+                let format1 = table.value_format1();
+                let format2 = table.value_format2();
+                self.value("ValueFormat1", format1.bits());
+                self.value("ValueFormat2", format2.bits());
+                self.write_class_def("ClassDef1", &table.class_def1()?);
+                self.write_class_def("ClassDef2", &table.class_def2()?);
+                self.count("Class1Count", table.class1_count());
+                self.count("Class2Count", table.class2_count());
+                for (i, class1) in table.class1_records().iter().enumerate() {
+                    let class1 = class1?;
+                    self.begin_indexed("Class1Record", i);
+                    for (j, class2) in class1.class2_records().iter().enumerate() {
+                        let class2 = class2?;
+                        self.begin_indexed("Class2Record", j);
+                        if !format1.is_empty() {
+                            self.write_value_record(
+                                "Value1",
+                                None,
+                                class2.value_record1(),
+                                table.offset_data(),
+                            )?;
+                        }
+                        if !format2.is_empty() {
+                            self.write_value_record(
+                                "Value2",
+                                None,
+                                class2.value_record2(),
+                                table.offset_data(),
+                            )?;
+                        }
+                        self.end("Class2Record");
+                    }
+                    self.end("Class1Record");
+                }
+            }
+        }
+        self.end("PairPos");
+        Ok(())
+    }
+
+    fn write_cursive_pos(
+        &mut self,
+        index: usize,
+        subtable: &CursivePosFormat1,
+    ) -> Result<(), ReadError> {
+        self.begin_format("CursivePos", Some(index), 1);
+        self.write_coverage("Coverage", None, &subtable.coverage()?);
+        self.count("EntryExitCount", subtable.entry_exit_count());
+        for (i, record) in subtable.entry_exit_record().iter().enumerate() {
+            self.begin_indexed("EntryExitRecord", i);
+            if let Some(anchor) = record.entry_anchor(subtable.offset_data()).transpose()? {
+                self.write_anchor("EntryAnchor", None, &anchor)?;
+            }
+            if let Some(anchor) = record.exit_anchor(subtable.offset_data()).transpose()? {
+                self.write_anchor("ExitAnchor", None, &anchor)?;
+            }
+            self.end("EntryExitRecord");
+        }
+        self.end("CursivePos");
+        Ok(())
+    }
+
+    fn write_mark_array(&mut self, name: &str, mark_array: &MarkArray) -> Result<(), ReadError> {
+        self.begin(name, &[]);
+        self.count("MarkCount", mark_array.mark_count());
+        for (i, record) in mark_array.mark_records().iter().enumerate() {
+            self.begin_indexed("MarkRecord", i);
+            self.value("Class", record.mark_class());
+            let anchor = record.mark_anchor(mark_array.offset_data())?;
+            self.write_anchor("MarkAnchor", None, &anchor)?;
+            self.end("MarkRecord");
+        }
+        self.end(name);
+        Ok(())
+    }
+
+    fn write_mark_base_pos(
+        &mut self,
+        index: usize,
+        subtable: &MarkBasePosFormat1,
+    ) -> Result<(), ReadError> {
+        self.begin_format("MarkBasePos", Some(index), 1);
+        self.write_coverage("MarkCoverage", None, &subtable.mark_coverage()?);
+        self.write_coverage("BaseCoverage", None, &subtable.base_coverage()?);
+        self.count("ClassCount", subtable.mark_class_count());
+        self.write_mark_array("MarkArray", &subtable.mark_array()?)?;
+        let base_array = subtable.base_array()?;
+        self.begin("BaseArray", &[]);
+        self.count("BaseCount", base_array.base_count());
+        for (i, record) in base_array.base_records().iter().enumerate() {
+            self.begin_indexed("BaseRecord", i);
+            self.write_anchor_array("BaseAnchor", record?.base_anchors(base_array.offset_data()))?;
+            self.end("BaseRecord");
+        }
+        self.end("BaseArray");
+        self.end("MarkBasePos");
+        Ok(())
+    }
+
+    fn write_mark_lig_pos(
+        &mut self,
+        index: usize,
+        subtable: &MarkLigPosFormat1,
+    ) -> Result<(), ReadError> {
+        self.begin_format("MarkLigPos", Some(index), 1);
+        self.write_coverage("MarkCoverage", None, &subtable.mark_coverage()?);
+        self.write_coverage("LigatureCoverage", None, &subtable.ligature_coverage()?);
+        self.count("ClassCount", subtable.mark_class_count());
+        self.write_mark_array("MarkArray", &subtable.mark_array()?)?;
+        let lig_array = subtable.ligature_array()?;
+        self.begin("LigatureArray", &[]);
+        self.count("LigatureCount", lig_array.ligature_count());
+        for (i, lig_attach) in lig_array.ligature_attaches().enumerate() {
+            let lig_attach = lig_attach?;
+            self.begin_indexed("LigatureAttach", i);
+            self.count("ComponentCount", lig_attach.component_count());
+            for (j, record) in lig_attach.component_records().iter().enumerate() {
+                self.begin_indexed("ComponentRecord", j);
+                self.write_anchor_array(
+                    "LigatureAnchor",
+                    record?.ligature_anchors(lig_attach.offset_data()),
+                )?;
+                self.end("ComponentRecord");
+            }
+            self.end("LigatureAttach");
+        }
+        self.end("LigatureArray");
+        self.end("MarkLigPos");
+        Ok(())
+    }
+
+    fn write_mark_mark_pos(
+        &mut self,
+        index: usize,
+        subtable: &MarkMarkPosFormat1,
+    ) -> Result<(), ReadError> {
+        self.begin_format("MarkMarkPos", Some(index), 1);
+        self.write_coverage("Mark1Coverage", None, &subtable.mark1_coverage()?);
+        self.write_coverage("Mark2Coverage", None, &subtable.mark2_coverage()?);
+        self.count("ClassCount", subtable.mark_class_count());
+        self.write_mark_array("Mark1Array", &subtable.mark1_array()?)?;
+        let mark2_array = subtable.mark2_array()?;
+        self.begin("Mark2Array", &[]);
+        self.count("Mark2Count", mark2_array.mark2_count());
+        for (i, record) in mark2_array.mark2_records().iter().enumerate() {
+            self.begin_indexed("Mark2Record", i);
+            self.write_anchor_array(
+                "Mark2Anchor",
+                record?.mark2_anchors(mark2_array.offset_data()),
+            )?;
+            self.end("Mark2Record");
+        }
+        self.end("Mark2Array");
+        self.end("MarkMarkPos");
+        Ok(())
+    }
+}
+
+/// Convert a missing table from an error into `None`.
+fn optional_table<T>(table: Result<T, ReadError>) -> Result<Option<T>, ReadError> {
+    match table {
+        Ok(table) => Ok(Some(table)),
+        Err(ReadError::TableIsMissing(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Collect the strings in the name table, preferring English names like ttx does.
+fn debug_names(font: &FontRef) -> HashMap<u16, String> {
+    let mut result = HashMap::new();
+    let Ok(name) = font.name() else {
+        return result;
+    };
+    let mut english = HashMap::new();
+    for record in name.name_record() {
+        let Ok(string) = record.string(name.string_data()) else {
+            continue;
+        };
+        let id = record.name_id().to_u16();
+        let string = string.chars().collect::<String>();
+        if matches!(
+            (record.platform_id(), record.language_id()),
+            (1, 0) | (3, 0x409)
+        ) {
+            english.entry(id).or_insert_with(|| string.clone());
+        }
+        result.insert(id, string);
+    }
+    result.extend(english);
+    result
+}
+
+/// Unpack the deltas in a device table.
+fn decode_deltas(device: &Device, format: u16) -> Vec<i16> {
+    let bits = match format {
+        1 => 2,
+        2 => 4,
+        3 => 8,
+        // variation index tables don't contain deltas
+        _ => return Vec::new(),
+    };
+    let count = (device.end_size() as usize + 1).saturating_sub(device.start_size() as usize);
+    let per_word = 16 / bits;
+    let mask = (1u16 << bits) - 1;
+    (0..count)
+        .map(|i| {
+            let word = device.delta_value()[i / per_word].get();
+            let shift = 16 - bits * (i % per_word + 1);
+            let raw = (word >> shift) & mask;
+            // sign-extend
+            ((raw << (16 - bits)) as i16) >> (16 - bits)
+        })
+        .collect()
+}
+
+fn escape(input: &str) -> std::borrow::Cow<'_, str> {
+    if !input.contains(['&', '<', '>', '"']) {
+        return input.into();
+    }
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::identity;
+
+    use super::*;
+    use crate::{compile::Opts, util::ttx as test_utils, GlyphName};
+
+    fn glyph_map() -> GlyphMap {
+        [
+            ".notdef", "a", "b", "c", "f", "i", "f_i", "acute", "grave", "a.alt",
+        ]
+        .iter()
+        .copied()
+        .map(GlyphName::new)
+        .collect()
+    }
+
+    fn dump(fea: &str) -> String {
+        let glyph_map = glyph_map();
+        let compilation = test_utils::compile_str(fea, &glyph_map, identity).unwrap();
+        let font = compilation
+            .assemble(&glyph_map, Opts::new())
+            .unwrap()
+            .build();
+        to_ttx_xml(&font, &glyph_map).unwrap()
+    }
+
+    #[test]
+    fn gsub_ligature() {
+        let ttx = dump("feature liga { sub f i by f_i; } liga;");
+        assert!(ttx.starts_with(XML_HEADER));
+        assert!(ttx.ends_with("\n  </GSUB>\n\n</ttFont>\n"));
+        assert!(!ttx.contains("<GPOS>"));
+        assert!(ttx.contains("    <Version value=\"0x00010000\"/>\n"));
+        assert!(ttx.contains("<ScriptTag value=\"DFLT\"/>"));
+        assert!(ttx.contains("<FeatureTag value=\"liga\"/>"));
+        assert!(ttx.contains("<LookupType value=\"4\"/>"));
+        assert!(ttx.contains(
+            "        <LigatureSubst index=\"0\">\n\
+            \x20         <LigatureSet glyph=\"f\">\n\
+            \x20           <Ligature components=\"i\" glyph=\"f_i\"/>\n\
+            \x20         </LigatureSet>\n\
+            \x20       </LigatureSubst>\n"
+        ));
+    }
+
+    #[test]
+    fn gpos_and_gdef() {
+        let ttx = dump(
+            "markClass [acute grave] <anchor 100 200> @TOP;
+            feature kern { pos a b -50; } kern;
+            feature mark { pos base [a b] <anchor 250 450> mark @TOP; } mark;
+            feature test { lookupflag IgnoreMarks; sub a by a.alt; } test;",
+        );
+        let gdef = ttx.find("<GDEF>").unwrap();
+        let gpos = ttx.find("<GPOS>").unwrap();
+        let gsub = ttx.find("<GSUB>").unwrap();
+        assert!(gdef < gpos && gpos < gsub);
+        assert!(ttx.contains("<ClassDef glyph=\"acute\" class=\"3\"/>"));
+        assert!(ttx.contains("<PairPos index=\"0\" Format=\"1\">"));
+        assert!(ttx.contains("<Value1 XAdvance=\"-50\"/>"));
+        assert!(ttx.contains("<!-- ClassCount=1 -->"));
+        assert!(ttx.contains("<BaseAnchor index=\"0\" Format=\"1\">"));
+        assert!(ttx.contains("<XCoordinate value=\"250\"/>"));
+        assert!(ttx.contains("<LookupFlag value=\"8\"/><!-- ignoreMarks -->\n"));
+        assert!(ttx.contains("<Substitution in=\"a\" out=\"a.alt\"/>"));
+    }
+
+    #[test]
+    fn single_sub_and_pair_pos() {
+        let ttx = dump(
+            "languagesystem DFLT dflt;
+            feature test { sub a by b; } test;
+            feature kern { pos a b -50; } kern;",
+        );
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ttFont>
+
+  <GPOS>
+    <Version value="0x00010000"/>
+    <ScriptList>
+      <!-- ScriptCount=1 -->
+      <ScriptRecord index="0">
+        <ScriptTag value="DFLT"/>
+        <Script>
+          <DefaultLangSys>
+            <ReqFeatureIndex value="65535"/>
+            <!-- FeatureCount=1 -->
+            <FeatureIndex index="0" value="0"/>
+          </DefaultLangSys>
+          <!-- LangSysCount=0 -->
+        </Script>
+      </ScriptRecord>
+    </ScriptList>
+    <FeatureList>
+      <!-- FeatureCount=1 -->
+      <FeatureRecord index="0">
+        <FeatureTag value="kern"/>
+        <Feature>
+          <!-- LookupCount=1 -->
+          <LookupListIndex index="0" value="0"/>
+        </Feature>
+      </FeatureRecord>
+    </FeatureList>
+    <LookupList>
+      <!-- LookupCount=1 -->
+      <Lookup index="0">
+        <LookupType value="2"/>
+        <LookupFlag value="0"/>
+        <!-- SubTableCount=1 -->
+        <PairPos index="0" Format="1">
+          <Coverage>
+            <Glyph value="a"/>
+          </Coverage>
+          <ValueFormat1 value="4"/>
+          <ValueFormat2 value="0"/>
+          <!-- PairSetCount=1 -->
+          <PairSet index="0">
+            <!-- PairValueCount=1 -->
+            <PairValueRecord index="0">
+              <SecondGlyph value="b"/>
+              <Value1 XAdvance="-50"/>
+            </PairValueRecord>
+          </PairSet>
+        </PairPos>
+      </Lookup>
+    </LookupList>
+  </GPOS>
+
+  <GSUB>
+    <Version value="0x00010000"/>
+    <ScriptList>
+      <!-- ScriptCount=1 -->
+      <ScriptRecord index="0">
+        <ScriptTag value="DFLT"/>
+        <Script>
+          <DefaultLangSys>
+            <ReqFeatureIndex value="65535"/>
+            <!-- FeatureCount=1 -->
+            <FeatureIndex index="0" value="0"/>
+          </DefaultLangSys>
+          <!-- LangSysCount=0 -->
+        </Script>
+      </ScriptRecord>
+    </ScriptList>
+    <FeatureList>
+      <!-- FeatureCount=1 -->
+      <FeatureRecord index="0">
+        <FeatureTag value="test"/>
+        <Feature>
+          <!-- LookupCount=1 -->
+          <LookupListIndex index="0" value="0"/>
+        </Feature>
+      </FeatureRecord>
+    </FeatureList>
+    <LookupList>
+      <!-- LookupCount=1 -->
+      <Lookup index="0">
+        <LookupType value="1"/>
+        <LookupFlag value="0"/>
+        <!-- SubTableCount=1 -->
+        <SingleSubst index="0">
+          <Substitution in="a" out="b"/>
+        </SingleSubst>
+      </Lookup>
+    </LookupList>
+  </GSUB>
+
+</ttFont>
+"#;
+        assert_eq!(ttx, expected);
+    }
+
+    #[test]
+    fn device_deltas() {
+        let mut device = write_fonts::tables::layout::Device::new(11, 14, &[-2i8, 1, 0, 1]);
+        let bytes = write_fonts::dump_table(&device).unwrap();
+        let read = Device::read(FontData::new(&bytes)).unwrap();
+        assert_eq!(decode_deltas(&read, 1), [-2, 1, 0, 1]);
+
+        device = write_fonts::tables::layout::Device::new(11, 12, &[-7, 100]);
+        let bytes = write_fonts::dump_table(&device).unwrap();
+        let read = Device::read(FontData::new(&bytes)).unwrap();
+        assert_eq!(decode_deltas(&read, 3), [-7, 100]);
+    }
+}
@@ -51,8 +51,7 @@ pub(crate) fn write_diagnostic(
     let line_width = line_width.unwrap_or(MAX_PRINT_WIDTH);
     let span = err.message.span.range();
     let (line_n, text) = source.line_containing_offset(span.start);
-    let line_start = source.offset_for_line_number(line_n);
-    let err_start = span.start - line_start;
+    let (_, err_start) = source.line_col_for_offset(span.start);
 
     // if a line is really long, we clip it
     let trim_start = if text.len() > line_width {
@@ -82,7 +81,7 @@ pub(crate) fn write_diagnostic(
     .unwrap();
     write!(writer, "{}{} |{} ", blue.prefix(), line_n, blue.suffix()).unwrap();
     writeln!(writer, "{ellipsis}{text}").unwrap();
-    let n_spaces = (span.start - line_start) - trim_start;
+    let n_spaces = err_start - trim_start;
     // use the whitespace at the front of the line first, so that
     // we don't replace tabs with spaces
     let reuse_ws = n_spaces.min(line_ws);
@@ -124,6 +123,18 @@ pub(crate) fn write_diagnostic(
         color.suffix(),
     )
     .unwrap();
+
+    if let Some(help) = err.help() {
+        writeln!(
+            writer,
+            "{}{} = {}help: {}",
+            blue.prefix(),
+            &super::SPACES[..n_digits],
+            blue.suffix(),
+            help
+        )
+        .unwrap();
+    }
 }
 
 fn write_header(writer: &mut impl Write, err: &Diagnostic, source: &Source) {
@@ -0,0 +1,96 @@
+//! Rendering diagnostics as annotated snippets.
+
+use std::path::Path;
+
+use annotate_snippets::{Level as SnippetLevel, Renderer, Snippet};
+
+use crate::{parse::Source, Diagnostic, Level};
+
+/// Render a diagnostic as an annotated snippet of its source.
+///
+/// Only the lines covered by the diagnostic's span are included. If `styled`
+/// is true the output includes ANSI colour codes; otherwise it is plain text,
+/// suitable for logs.
+pub(crate) fn render_diagnostic(err: &Diagnostic, source: &Source, styled: bool) -> String {
+    let index = source.line_index();
+    let span = err.span();
+    let first_line = index.line_col(span.start).line;
+    let last_line = index.line_col(span.end).line;
+    let start = index.line_range(first_line).unwrap().start;
+    let end = index.line_range(last_line).unwrap().end;
+    let text = source.text()[start..end].trim_end_matches('\n');
+    let text = text.trim_end_matches('\r');
+    // a span that ends at a line ending is clamped to the end of the text, and
+    // one that starts inside a byte order mark to the start of the first line
+    let local_span =
+        span.start.saturating_sub(start)..span.end.saturating_sub(start).min(text.len());
+
+    let path = Path::new(source.path()).display().to_string();
+    let level = err.level.snippet_level();
+    let mut message = level.title(err.text()).snippet(
+        Snippet::source(text)
+            .line_start(first_line)
+            .origin(&path)
+            .annotation(level.span(local_span)),
+    );
+    if let Some(help) = err.help() {
+        message = message.footer(SnippetLevel::Help.title(help));
+    }
+    let renderer = if styled {
+        Renderer::styled()
+    } else {
+        Renderer::plain()
+    };
+    let rendered = renderer.render(message);
+    rendered.to_string()
+}
+
+impl Level {
+    fn snippet_level(self) -> SnippetLevel {
+        match self {
+            Level::Error => SnippetLevel::Error,
+            Level::Warning => SnippetLevel::Warning,
+            Level::Info => SnippetLevel::Info,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::FileId;
+
+    #[test]
+    fn render_plain() {
+        let source = Source::new(
+            "test.fea",
+            "\u{feff}feature kern {\r\n    pos a b 10;\r\n} kren;\r\n".into(),
+        );
+        let offset = source.text().find("kren").unwrap();
+        let err = Diagnostic::error(FileId::CURRENT_FILE, offset..offset + 4, "bad tag")
+            .with_help("expected 'kern'");
+        assert_eq!(
+            render_diagnostic(&err, &source, false),
+            "\
+error: bad tag
+ --> test.fea:3:3
+  |
+3 | } kren;
+  |   ^^^^
+  |
+  = help: expected 'kern'"
+        );
+
+        // spans over multiple lines include every line
+        let start = source.text().find("feature").unwrap();
+        let err = Diagnostic::warning(FileId::CURRENT_FILE, start..offset, "long");
+        let rendered = render_diagnostic(&err, &source, false);
+        assert!(rendered.contains("1 | / feature kern {\n"), "{rendered}");
+        assert!(rendered.contains("3 | | } kren;\n"), "{rendered}");
+
+        // a span that starts in the byte order mark
+        let err = Diagnostic::error(FileId::CURRENT_FILE, 0..10, "bom");
+        let rendered = render_diagnostic(&err, &source, false);
+        assert!(rendered.contains("1 | feature kern {\n"), "{rendered}");
+    }
+}
@@ -7,20 +7,26 @@ use std::{
     fmt::{Debug, Display, Write},
     path::{Path, PathBuf},
     process::Command,
+    sync::Arc,
     time::SystemTime,
 };
 
 use crate::{
     compile::{
         error::{CompilerError, DiagnosticSet},
-        Compiler, Opts,
+        Compilation, Compiler, Opts,
     },
+    util::debug::to_ttx_xml,
     Diagnostic, GlyphIdent, GlyphMap, GlyphName, ParseTree,
 };
 
 use ansi_term::Color;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use write_fonts::{
+    read::{FontRef, ReadError},
+    types::Tag,
+};
 
 static IGNORED_TESTS: &[&str] = &[
     // ## tests with invalid syntax ## //
@@ -43,6 +49,46 @@ static IGNORED_TESTS: &[&str] = &[
 /// This can be set during debugging if you want to inspect the generated files.
 static TEMP_DIR_ENV: &str = "TTX_TEMP_DIR";
 
+/// The extension of files that mark a test as an expected failure.
+///
+/// A file with this extension alongside a test's input (e.g. `GPOS_1.xfail`
+/// for `GPOS_1.fea`) contains a short explanation of why we do not match the
+/// output of feaLib. These tests are reported separately, and are not counted
+/// as failures; if such a test passes, it is reported as an unexpected
+/// success, so that the annotation can be removed.
+static EXPECTED_FAILURE_EXTENSION: &str = "xfail";
+
+/// The extension of the binary recorded from feaLib for a test.
+///
+/// This is the test's input compiled by feaLib into a font generated by
+/// fea-rs, recorded with `fonttools feaLib` when the `FEA_WRITE_TEST_OUTPUT`
+/// variable is set. If a test has one and its layout tables are identical to
+/// ours, the test passes without comparing ttx.
+static FEALIB_BINARY_EXTENSION: &str = "fealib.ttf";
+
+/// The extension of files listing the diagnostics expected for a bad input.
+///
+/// See [`compare_bad_output`] for the format.
+pub static EXPECTED_DIAGNOSTICS_EXTENSION: &str = "DIAGNOSTICS";
+
+/// The tables rendered by [`to_ttx_xml`].
+///
+/// When using [`TtxSource::Builtin`], only these tables of the expected output
+/// are compared.
+static LAYOUT_TABLES: &[&str] = &["GDEF", "GPOS", "GSUB"];
+
+/// How the compiled output of a test is converted to ttx.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TtxSource {
+    /// Run the `ttx` executable from fontTools, and compare every table.
+    #[default]
+    Executable,
+    /// Render the layout tables with [`to_ttx_xml`], and compare only those.
+    ///
+    /// This does not require a python installation.
+    Builtin,
+}
+
 /// The combined results of this set of tests
 #[derive(Default, Serialize, Deserialize)]
 pub struct Report {
@@ -57,6 +103,7 @@ struct ReportSummary {
     parse: u32,
     compile: u32,
     compare: u32,
+    expected_fail: u32,
     other: u32,
     sum_compare_perc: f64,
 }
@@ -88,9 +135,15 @@ pub enum TestResult {
     CompileFail(String),
     /// Compilation succeeded, but shouldn't have
     UnexpectedSuccess,
-    /// A call to the `ttx` utility failed
+    /// The test failed, but it is annotated as an expected failure.
+    ///
+    /// This contains the reason from the annotation.
+    ExpectedFailure(String),
+    /// A call to the `ttx` or `fonttools feaLib` utility failed
     #[allow(missing_docs)]
     TtxFail { code: Option<i32>, std_err: String },
+    /// A file that is part of the test could not be read
+    ReadFail(String),
     /// The output did not match the expectation
     #[allow(missing_docs)]
     CompareFail {
@@ -143,13 +196,17 @@ impl<'a> Filter<'a> {
 ///
 /// `filter` is an optional comma-separated list of strings. If present, only
 /// tests which contain one of the strings in the list will be run.
-pub fn run_all_tests(fonttools_data_dir: impl AsRef<Path>, filter: Option<&String>) -> Report {
+pub fn run_all_tests(
+    fonttools_data_dir: impl AsRef<Path>,
+    filter: Option<&String>,
+    source: TtxSource,
+) -> Report {
     let glyph_map = make_glyph_map();
     let filter = Filter::new(filter);
 
     let result = iter_compile_tests(fonttools_data_dir.as_ref(), filter)
         .par_bridge()
-        .map(|path| run_test(path, &glyph_map))
+        .map(|path| run_test(path, &glyph_map, source))
         .collect::<Vec<_>>();
 
     finalize_results(result)
@@ -217,9 +274,54 @@ pub fn try_parse_file(
     }
 }
 
+/// Parse a string as the contents of a feature file named 'test.fea'.
+///
+/// Panics if parsing produces any diagnostics.
+pub fn parse_str(fea: &str, glyph_map: &GlyphMap) -> ParseTree {
+    let fea: Arc<str> = fea.into();
+    let (tree, errs) =
+        crate::parse::parse_root("test.fea".into(), Some(glyph_map), move |_: &OsStr| {
+            Ok(fea.clone())
+        })
+        .unwrap();
+    assert!(errs.is_empty(), "{errs:?}");
+    tree
+}
+
+/// Compile a string as the contents of a feature file named 'test.fea'.
+///
+/// The compiler is passed to `configure` before compiling, so that a test
+/// can set options or add handlers; pass [`std::convert::identity`] to use
+/// the defaults.
+pub fn compile_str<'a>(
+    fea: &str,
+    glyph_map: &'a GlyphMap,
+    configure: impl FnOnce(Compiler<'a>) -> Compiler<'a>,
+) -> Result<Compilation, CompilerError> {
+    configure(str_compiler(fea, glyph_map)).compile()
+}
+
+/// Like [`compile_str`], but returns the compiled binary.
+pub fn compile_str_to_binary<'a>(
+    fea: &str,
+    glyph_map: &'a GlyphMap,
+    configure: impl FnOnce(Compiler<'a>) -> Compiler<'a>,
+) -> Result<Vec<u8>, CompilerError> {
+    configure(str_compiler(fea, glyph_map)).compile_binary()
+}
+
+fn str_compiler<'a>(fea: &str, glyph_map: &'a GlyphMap) -> Compiler<'a> {
+    let fea: Arc<str> = fea.into();
+    Compiler::new("test.fea", glyph_map).with_resolver(move |_: &OsStr| Ok(fea.clone()))
+}
+
 /// Run the test case at the provided path.
-pub fn run_test(path: PathBuf, glyph_map: &GlyphMap) -> Result<PathBuf, TestCase> {
-    match std::panic::catch_unwind(|| {
+pub fn run_test(
+    path: PathBuf,
+    glyph_map: &GlyphMap,
+    source: TtxSource,
+) -> Result<PathBuf, TestCase> {
+    let result = match std::panic::catch_unwind(|| {
         match Compiler::new(&path, glyph_map)
             .verbose(std::env::var(super::VERBOSE).is_ok())
             .with_opts(Opts::new().make_post_table(true))
@@ -228,18 +330,37 @@ pub fn run_test(path: PathBuf, glyph_map: &GlyphMap) -> Result<PathBuf, TestCase
             // this means we have a test case that doesn't exist or something weird
             Err(CompilerError::SourceLoad(err)) => panic!("{err}"),
             Err(CompilerError::WriteFail(err)) => panic!("{err}"),
+            Err(CompilerError::TransformFail(errs)) => panic!("{errs}"),
             Err(CompilerError::ParseFail(errs)) => Err(TestResult::ParseFail(errs.to_string())),
             Err(CompilerError::ValidationFail(errs) | CompilerError::CompilationFail(errs)) => {
                 Err(TestResult::CompileFail(errs.to_string()))
             }
-            Ok(result) => compare_ttx(&result, &path),
+            Ok(result) => compare_ttx(&result, &path, glyph_map, source),
         }
     }) {
         Err(_) => Err(TestResult::Panic),
         Ok(Err(reason)) => Err(reason),
-        Ok(Ok(_)) => return Ok(path),
+        Ok(Ok(_)) => Ok(()),
+    };
+    let reason = match (result, expected_failure(&path)) {
+        (_, Err(reason)) => reason,
+        (Ok(()), Ok(None)) => return Ok(path),
+        (Ok(()), Ok(Some(_))) => TestResult::UnexpectedSuccess,
+        (Err(_), Ok(Some(reason))) => TestResult::ExpectedFailure(reason),
+        (Err(reason), Ok(None)) => reason,
+    };
+    Err(TestCase { reason, path })
+}
+
+/// The reason a test is expected to fail, if it is annotated as such.
+fn expected_failure(path: &Path) -> Result<Option<String>, TestResult> {
+    let xfail_path = path.with_extension(EXPECTED_FAILURE_EXTENSION);
+    if !xfail_path.exists() {
+        return Ok(None);
     }
-    .map_err(|reason| TestCase { reason, path })
+    std::fs::read_to_string(&xfail_path)
+        .map(|reason| Some(reason.trim().to_owned()))
+        .map_err(|err| TestResult::ReadFail(format!("{}: {err}", xfail_path.display())))
 }
 
 /// Convert diagnostics to a printable string
@@ -279,9 +400,123 @@ fn get_temp_file_name(in_file: &Path) -> PathBuf {
     Path::new(&format!("{stem}_{millis}")).with_extension("ttf")
 }
 
-fn compare_ttx(font_data: &[u8], fea_path: &Path) -> Result<(), TestResult> {
+fn compare_ttx(
+    font_data: &[u8],
+    fea_path: &Path,
+    glyph_map: &GlyphMap,
+    source: TtxSource,
+) -> Result<(), TestResult> {
     let ttx_path = fea_path.with_extension("ttx");
     let expected_diff_path = fea_path.with_extension("expected_diff");
+    let fealib_path = fea_path.with_extension(FEALIB_BINARY_EXTENSION);
+    if source == TtxSource::Executable && std::env::var(super::WRITE_RESULTS_VAR).is_ok() {
+        record_fealib_binary(font_data, fea_path, &fealib_path)?;
+    }
+    if fealib_path.exists() {
+        let expected = std::fs::read(&fealib_path)
+            .map_err(|err| TestResult::ReadFail(format!("{}: {err}", fealib_path.display())))?;
+        if compare_layout_tables(&expected, font_data)?.is_empty() {
+            return Ok(());
+        }
+    }
+
+    let result = match source {
+        TtxSource::Executable => run_ttx(font_data, fea_path)?,
+        TtxSource::Builtin => {
+            to_ttx_xml(font_data, glyph_map).map_err(|err| TestResult::TtxFail {
+                code: None,
+                std_err: err.to_string(),
+            })?
+        }
+    };
+
+    let expected = ttx_path
+        .exists()
+        .then(|| std::fs::read_to_string(&ttx_path).unwrap())
+        .unwrap_or_default();
+    let expected = rewrite_ttx(&expected);
+    let expected = match source {
+        TtxSource::Executable => expected,
+        TtxSource::Builtin => only_layout_tables(&expected),
+    };
+
+    // these are written against the output of the ttx executable, which
+    // includes all tables
+    if source == TtxSource::Executable {
+        if expected_diff_path.exists() {
+            let expected_diff = std::fs::read_to_string(&expected_diff_path).unwrap();
+            let simple_diff = plain_text_diff(&expected, &result);
+            if expected_diff == simple_diff {
+                return Ok(());
+            }
+        }
+
+        if std::env::var(super::WRITE_RESULTS_VAR).is_ok() {
+            std::fs::write(&ttx_path, &result).unwrap();
+        }
+    }
+    let diff_percent = compute_diff_percentage(&expected, &result);
+
+    if expected != result {
+        Err(TestResult::CompareFail {
+            expected,
+            result,
+            diff_percent,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Compile a test's input with feaLib, and save the result to `out_path`.
+///
+/// Our compiled font is used as the base font, so that both compilers see
+/// the same glyph order.
+fn record_fealib_binary(
+    font_data: &[u8],
+    fea_path: &Path,
+    out_path: &Path,
+) -> Result<(), TestResult> {
+    let temp_path = get_temp_dir().join(get_temp_file_name(fea_path));
+    std::fs::write(&temp_path, font_data).unwrap();
+    let status = Command::new("fonttools")
+        .arg("feaLib")
+        .arg("-o")
+        .arg(out_path)
+        .arg(fea_path)
+        .arg(&temp_path)
+        .output()
+        .unwrap_or_else(|_| panic!("failed to execute for path {}", fea_path.display()));
+    if !status.status.success() {
+        let std_err = String::from_utf8_lossy(&status.stderr).into_owned();
+        return Err(TestResult::TtxFail {
+            code: status.status.code(),
+            std_err,
+        });
+    }
+    Ok(())
+}
+
+/// Return the tags of the layout tables that differ between two fonts.
+///
+/// A table that is missing from one font but not the other is a difference.
+fn compare_layout_tables(expected: &[u8], result: &[u8]) -> Result<Vec<Tag>, TestResult> {
+    let read_fail = |err: ReadError| TestResult::ReadFail(err.to_string());
+    let expected = FontRef::new(expected).map_err(read_fail)?;
+    let result = FontRef::new(result).map_err(read_fail)?;
+    Ok(LAYOUT_TABLES
+        .iter()
+        .map(|tag| Tag::new_checked(tag.as_bytes()).unwrap())
+        .filter(|tag| {
+            let expected = expected.table_data(*tag);
+            let result = result.table_data(*tag);
+            expected.as_ref().map(AsRef::as_ref) != result.as_ref().map(AsRef::as_ref)
+        })
+        .collect())
+}
+
+/// Convert a font to ttx with the `ttx` executable.
+fn run_ttx(font_data: &[u8], fea_path: &Path) -> Result<String, TestResult> {
     let temp_path = get_temp_dir().join(get_temp_file_name(fea_path));
     std::fs::write(&temp_path, font_data).unwrap();
 
@@ -309,37 +544,7 @@ fn compare_ttx(font_data: &[u8], fea_path: &Path) -> Result<(), TestResult> {
     assert!(ttx_out_path.exists());
 
     let result = std::fs::read_to_string(ttx_out_path).unwrap();
-
-    let result = rewrite_ttx(&result);
-
-    let expected = ttx_path
-        .exists()
-        .then(|| std::fs::read_to_string(&ttx_path).unwrap())
-        .unwrap_or_default();
-    let expected = rewrite_ttx(&expected);
-
-    if expected_diff_path.exists() {
-        let expected_diff = std::fs::read_to_string(&expected_diff_path).unwrap();
-        let simple_diff = plain_text_diff(&expected, &result);
-        if expected_diff == simple_diff {
-            return Ok(());
-        }
-    }
-
-    if std::env::var(super::WRITE_RESULTS_VAR).is_ok() {
-        std::fs::write(&ttx_path, &result).unwrap();
-    }
-    let diff_percent = compute_diff_percentage(&expected, &result);
-
-    if expected != result {
-        Err(TestResult::CompareFail {
-            expected,
-            result,
-            diff_percent,
-        })
-    } else {
-        Ok(())
-    }
+    Ok(rewrite_ttx(&result))
 }
 
 /// take some output and compare it to the expected output (saved on disk)
@@ -368,6 +573,202 @@ pub fn compare_to_expected_output(
     }
     Ok(())
 }
+
+/// Compare the diagnostics for a bad input to the expected diagnostics.
+///
+/// If there is a file with the [`EXPECTED_DIAGNOSTICS_EXTENSION`] next to the
+/// input, each of its lines (other than blank lines and comments starting
+/// with '#') describes a diagnostic by its code and, optionally, its range:
+///
+/// ```text
+/// # the missing semicolon
+/// expected 10..14
+/// unused-glyph-class
+/// ```
+///
+/// The code of a diagnostic is the first line of its message without any
+/// quoted text or numbers, in kebab-case; the message
+/// `class has different length (2) than target (0)` has the code
+/// `class-has-different-length-than-target`. Each line must match one of the
+/// actual diagnostics; any other diagnostics are ignored, so adding a new
+/// diagnostic does not require updating every file.
+///
+/// If there is no such file, the `printed` diagnostics are compared to a
+/// snapshot with the extension `snapshot_ext`.
+///
+/// When test results are being written, a missing file is created with every
+/// diagnostic, which converts a test that has a snapshot; the snapshot is not
+/// removed. In an existing file, only the lines that do not match are
+/// rewritten, with the diagnostics that have the same code or range.
+pub fn compare_bad_output(
+    diagnostics: &[Diagnostic],
+    printed: &str,
+    src_path: &Path,
+    snapshot_ext: &str,
+) -> Result<(), TestCase> {
+    let expected_path = src_path.with_extension(EXPECTED_DIAGNOSTICS_EXTENSION);
+    let write = std::env::var(super::WRITE_RESULTS_VAR).is_ok();
+    if !expected_path.exists() {
+        if write {
+            std::fs::write(&expected_path, format_expected_diagnostics(diagnostics))
+                .expect("failed to write output");
+        }
+        return compare_to_expected_output(printed, src_path, snapshot_ext);
+    }
+    let expected = std::fs::read_to_string(&expected_path).expect("failed to read expected");
+    let result = compare_to_expected_diagnostics(diagnostics, &expected, src_path);
+    if result.is_err() && write {
+        std::fs::write(
+            &expected_path,
+            update_expected_diagnostics(diagnostics, &expected),
+        )
+        .expect("failed to write output");
+    }
+    result
+}
+
+fn compare_to_expected_diagnostics(
+    diagnostics: &[Diagnostic],
+    expected: &str,
+    src_path: &Path,
+) -> Result<(), TestCase> {
+    let missing = expected_diagnostic_lines(expected)
+        .filter_map(|(line, code, range)| {
+            (!diagnostics
+                .iter()
+                .any(|diag| matches_expected(diag, code, range.clone())))
+            .then_some(line)
+        })
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let expected = missing.join("\n");
+    let result = format_expected_diagnostics(diagnostics);
+    let diff_percent = compute_diff_percentage(&expected, &result);
+    Err(TestCase {
+        path: src_path.to_owned(),
+        reason: TestResult::CompareFail {
+            expected,
+            result,
+            diff_percent,
+        },
+    })
+}
+
+/// Rewrite the lines of an expected diagnostics file that do not match.
+///
+/// Each such line is replaced by the diagnostics with the same code or the
+/// same range that are not matched by another line, if any.
+fn update_expected_diagnostics(diagnostics: &[Diagnostic], expected: &str) -> String {
+    let is_match = |code, range: &Option<_>| {
+        diagnostics
+            .iter()
+            .any(|diag| matches_expected(diag, code, range.clone()))
+    };
+    let mut used = vec![false; diagnostics.len()];
+    for (_, code, range) in expected_diagnostic_lines(expected) {
+        for (diag, used) in diagnostics.iter().zip(used.iter_mut()) {
+            *used |= matches_expected(diag, code, range.clone());
+        }
+    }
+
+    let mut out = String::new();
+    for line in expected.lines() {
+        let Some((code, range)) = parse_expected_diagnostic(line.trim()) else {
+            writeln!(&mut out, "{line}").unwrap();
+            continue;
+        };
+        if is_match(code, &range) {
+            writeln!(&mut out, "{line}").unwrap();
+            continue;
+        }
+        for (diag, used) in diagnostics.iter().zip(used.iter_mut()) {
+            if !*used && (diagnostic_code(diag) == code || Some(diag.span()) == range) {
+                *used = true;
+                out.push_str(&format_expected_diagnostics(std::slice::from_ref(diag)));
+            }
+        }
+    }
+    out
+}
+
+/// The lines of an expected diagnostics file that describe a diagnostic,
+/// along with their code and range.
+fn expected_diagnostic_lines(
+    expected: &str,
+) -> impl Iterator<Item = (&str, &str, Option<std::ops::Range<usize>>)> {
+    expected
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (code, range) = parse_expected_diagnostic(line)
+                .unwrap_or_else(|| panic!("invalid expected diagnostic '{line}'"));
+            (line, code, range)
+        })
+}
+
+/// Parse a line of an expected diagnostics file into its code and range.
+fn parse_expected_diagnostic(line: &str) -> Option<(&str, Option<std::ops::Range<usize>>)> {
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let code = parts.next()?;
+    let range = match parts.next() {
+        Some(range) => {
+            let (start, end) = range.split_once("..")?;
+            Some(start.parse().ok()?..end.parse().ok()?)
+        }
+        None => None,
+    };
+    parts.next().is_none().then_some((code, range))
+}
+
+fn matches_expected(
+    diagnostic: &Diagnostic,
+    code: &str,
+    range: Option<std::ops::Range<usize>>,
+) -> bool {
+    diagnostic_code(diagnostic) == code && range.is_none_or(|range| diagnostic.span() == range)
+}
+
+/// Format diagnostics in the format of an expected diagnostics file.
+fn format_expected_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    let mut out = String::new();
+    for diag in diagnostics {
+        let range = diag.span();
+        let code = diagnostic_code(diag);
+        writeln!(&mut out, "{code} {}..{}", range.start, range.end).unwrap();
+    }
+    out
+}
+
+/// The code identifying this kind of diagnostic in expected diagnostics files.
+fn diagnostic_code(diagnostic: &Diagnostic) -> String {
+    let text = diagnostic.text().lines().next().unwrap_or_default();
+    // quoted text is the name of a glyph, tag, or similar
+    let mut unquoted = String::with_capacity(text.len());
+    let mut quote = None;
+    let mut prev = ' ';
+    for c in text.chars() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => (),
+            None if matches!(c, '\'' | '"' | '`') && !prev.is_alphanumeric() => quote = Some(c),
+            None => unquoted.push(c),
+        }
+        prev = c;
+    }
+    unquoted
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !word.chars().any(|c| c.is_ascii_digit()))
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
 // hacky way to make our ttx output match fonttools'
 fn rewrite_ttx(input: &str) -> String {
     let mut out = String::with_capacity(input.len());
@@ -383,6 +784,47 @@ fn rewrite_ttx(input: &str) -> String {
     out
 }
 
+/// Remove all but the [`LAYOUT_TABLES`] from the output of the ttx executable.
+///
+/// The result is formatted in the same way as the output of [`to_ttx_xml`].
+fn only_layout_tables(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut keep = false;
+    for line in input.lines() {
+        // tables are the only elements at the first level of indentation
+        let table_tag = line
+            .strip_prefix("  <")
+            .filter(|rest| !rest.starts_with(' '))
+            .and_then(|rest| rest.strip_suffix('>'));
+        match table_tag {
+            _ if line.starts_with("<?xml") || line == "<ttFont>" => {
+                out.push_str(line);
+                out.push('\n');
+            }
+            _ if line == "</ttFont>" => out.push_str("\n</ttFont>\n"),
+            Some(tag) if tag.starts_with('/') => {
+                if keep {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+                keep = false;
+            }
+            Some(tag) if LAYOUT_TABLES.contains(&tag) => {
+                keep = true;
+                out.push('\n');
+                out.push_str(line);
+                out.push('\n');
+            }
+            _ if keep && !line.is_empty() => {
+                out.push_str(line);
+                out.push('\n');
+            }
+            _ => (),
+        }
+    }
+    out
+}
+
 fn write_lines(f: &mut impl Write, lines: &[&str], line_num: usize, prefix: char) {
     writeln!(f, "L{}", line_num).unwrap();
     for line in lines {
@@ -503,7 +945,7 @@ static TEST_FONT_GLYPHS: &[&str] = &[
 impl Report {
     ///  Returns `true` if any tests have failed.
     pub fn has_failures(&self) -> bool {
-        self.results.iter().any(|r| !r.reason.is_success())
+        self.results.iter().any(|r| r.reason.is_failure())
     }
 
     /// Convert this type into a Result.
@@ -540,7 +982,10 @@ impl Report {
                 TestResult::Panic => summary.panic += 1,
                 TestResult::ParseFail(_) => summary.parse += 1,
                 TestResult::CompileFail(_) => summary.compile += 1,
-                TestResult::UnexpectedSuccess | TestResult::TtxFail { .. } => summary.other += 1,
+                TestResult::ExpectedFailure(_) => summary.expected_fail += 1,
+                TestResult::UnexpectedSuccess
+                | TestResult::TtxFail { .. }
+                | TestResult::ReadFail(_) => summary.other += 1,
                 TestResult::CompareFail { diff_percent, .. } => {
                     summary.compare += 1;
                     summary.sum_compare_perc += diff_percent;
@@ -559,13 +1004,15 @@ impl TestResult {
             Self::ParseFail(_) => 3,
             Self::CompileFail(_) => 4,
             Self::UnexpectedSuccess => 6,
+            Self::ExpectedFailure(_) => 8,
             Self::TtxFail { .. } => 10,
+            Self::ReadFail(_) => 12,
             Self::CompareFail { .. } => 50,
         }
     }
 
-    fn is_success(&self) -> bool {
-        matches!(self, Self::Success)
+    fn is_failure(&self) -> bool {
+        !matches!(self, Self::Success | Self::ExpectedFailure(_))
     }
 
     /// Return an (optionally verbose) type for printing the result
@@ -719,9 +1166,17 @@ impl Display for ReasonPrinter<'_> {
             TestResult::UnexpectedSuccess => {
                 write!(f, "{}", Color::Yellow.paint("unexpected success"))
             }
+            TestResult::ExpectedFailure(reason) => {
+                write!(f, "{}", Color::Cyan.paint("expected failure"))?;
+                if self.verbose {
+                    write!(f, ": {reason}")?;
+                }
+                Ok(())
+            }
             TestResult::TtxFail { code, std_err } => {
                 write!(f, "ttx failure ({:?}) stderr:\n{}", code, std_err)
             }
+            TestResult::ReadFail(err) => write!(f, "{}: {err}", Color::Red.paint("read failure")),
             TestResult::CompareFail {
                 expected,
                 result,
@@ -751,7 +1206,13 @@ impl Debug for TestResult {
 
 impl ReportSummary {
     fn total_items(&self) -> u32 {
-        self.passed + self.panic + self.parse + self.compile + self.compare + self.other
+        self.passed
+            + self.panic
+            + self.parse
+            + self.compile
+            + self.compare
+            + self.expected_fail
+            + self.other
     }
 
     fn average_diff_percent(&self) -> f64 {
@@ -768,8 +1229,155 @@ impl Display for ReportSummary {
             panic,
             parse,
             compile,
+            expected_fail,
             ..
         } = self;
-        write!(f, "passed {passed}/{total} tests: ({panic} panics {parse} unparsed {compile} compile) {perc:.2}% avg diff")
+        write!(f, "passed {passed}/{total} tests: ({panic} panics {parse} unparsed {compile} compile) {perc:.2}% avg diff")?;
+        if *expected_fail != 0 {
+            write!(f, ", {expected_fail} expected failures")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expected_diagnostics() {
+        let file = crate::parse::FileId::CURRENT_FILE;
+        let diagnostics = [
+            Diagnostic::error(file, 10..14, "Expected ';'\n  found 'sub'"),
+            Diagnostic::warning(file, 32..40, "unused glyph class '@lc'"),
+            Diagnostic::error(
+                file,
+                50..60,
+                "class has different length (2) than target (0)",
+            ),
+            Diagnostic::error(file, 70..72, "can't use 'NULL' here"),
+        ];
+        let codes = diagnostics.iter().map(diagnostic_code).collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [
+                "expected",
+                "unused-glyph-class",
+                "class-has-different-length-than-target",
+                "can-t-use-here"
+            ]
+        );
+
+        let path = Path::new("test.fea");
+        let check = |expected| compare_to_expected_diagnostics(&diagnostics, expected, path);
+        assert!(check("# partial matches are fine\nexpected 10..14\n").is_ok());
+        assert!(check("unused-glyph-class").is_ok());
+        let err = check("expected 10..14\nunused-glyph-class 30..40").unwrap_err();
+        let TestResult::CompareFail { expected, .. } = err.reason else {
+            panic!("expected compare failure");
+        };
+        assert_eq!(expected, "unused-glyph-class 30..40");
+        assert_eq!(
+            format_expected_diagnostics(&diagnostics[..2]),
+            "expected 10..14\nunused-glyph-class 32..40\n"
+        );
+
+        // only lines that don't match are rewritten
+        let expected = "\
+# comments are kept
+expected 10..14
+unused-glyph-class 30..40
+missing-semicolon 70..72
+gone
+";
+        assert_eq!(
+            update_expected_diagnostics(&diagnostics, expected),
+            "\
+# comments are kept
+expected 10..14
+unused-glyph-class 32..40
+can-t-use-here 70..72
+"
+        );
+    }
+
+    #[test]
+    fn builtin_ttx_matches_layout_tables() {
+        let full = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ttFont>
+
+  <head>
+    <unitsPerEm value="1000"/>
+  </head>
+
+  <GSUB>
+    <Version value="0x00010000"/>
+  </GSUB>
+
+  <name>
+    <namerecord nameID="1">
+      Test
+    </namerecord>
+  </name>
+
+</ttFont>
+"#;
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ttFont>
+
+  <GSUB>
+    <Version value="0x00010000"/>
+  </GSUB>
+
+</ttFont>
+"#;
+        assert_eq!(only_layout_tables(full), expected);
+
+        // our own output is already in this form
+        let glyph_map = make_glyph_map();
+        let fea = "markClass acute <anchor 0 0> @TOP;
+            feature mark { pos base a <anchor 0 0> mark @TOP; } mark;
+            feature liga { sub f i by f_i; } liga;";
+        let font = compile_str_to_binary(fea, &glyph_map, std::convert::identity).unwrap();
+        let ours = to_ttx_xml(&font, &glyph_map).unwrap();
+        assert_eq!(only_layout_tables(&ours), ours);
+    }
+
+    #[test]
+    fn compare_fealib_binary() {
+        let glyph_map = make_glyph_map();
+        let compile = |fea| compile_str_to_binary(fea, &glyph_map, std::convert::identity).unwrap();
+        let liga = compile("feature liga { sub f i by f_i; } liga;");
+        let kern =
+            compile("feature liga { sub f i by f_i; } liga; feature kern { pos a b -20; } kern;");
+        assert!(compare_layout_tables(&liga, &liga).unwrap().is_empty());
+        assert_eq!(
+            compare_layout_tables(&liga, &kern).unwrap(),
+            [Tag::new(b"GPOS")]
+        );
+        assert!(matches!(
+            compare_layout_tables(b"not a font", &liga),
+            Err(TestResult::ReadFail(_))
+        ));
+
+        // a test with an identical binary passes without an expected ttx
+        let dir = get_temp_dir().join(format!("fealib_binary_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fea_path = dir.join("liga.fea");
+        std::fs::write(fea_path.with_extension(FEALIB_BINARY_EXTENSION), &liga).unwrap();
+        assert!(compare_ttx(&liga, &fea_path, &glyph_map, TtxSource::Builtin).is_ok());
+        assert!(matches!(
+            compare_ttx(&kern, &fea_path, &glyph_map, TtxSource::Builtin),
+            Err(TestResult::CompareFail { .. })
+        ));
+
+        // an annotation that can't be read is reported, not a panic
+        assert_eq!(expected_failure(&fea_path).unwrap(), None);
+        std::fs::create_dir_all(fea_path.with_extension(EXPECTED_FAILURE_EXTENSION)).unwrap();
+        assert!(matches!(
+            expected_failure(&fea_path),
+            Err(TestResult::ReadFail(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }